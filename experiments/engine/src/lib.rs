@@ -6,6 +6,14 @@
 //! background servers—everything should be a short-lived process pipeline.
 
 use std::path::PathBuf;
+use std::time::Duration;
+
+mod persistent;
+
+pub use persistent::{OnRecord, PersistentEngine, Reply, SessionRecord};
+
+/// Context routing, the same code `nox` uses for `NOX_ROUTE`.
+pub use noxroute_core as routing;
 
 /// Basic configuration passed to a runner invocation.
 #[derive(Debug, Clone)]
@@ -15,6 +23,10 @@ pub struct EngineConfig {
     pub max_tokens: usize,
     pub ctx: usize,
     pub threads: Option<usize>,
+    /// Shut a [`PersistentEngine`]'s runner down after this long without a
+    /// request, like `NOX_IDLE_TIMEOUT_SECS` in noxrs persistent mode. The
+    /// next request starts it again.
+    pub idle_timeout: Option<Duration>,
 }
 
 impl Default for EngineConfig {
@@ -25,6 +37,7 @@ impl Default for EngineConfig {
             max_tokens: 256,
             ctx: 1024,
             threads: None,
+            idle_timeout: None,
         }
     }
 }
@@ -0,0 +1,507 @@
+//! A warm runner answering many prompts in turn, as `NOX_PERSIST=1` drives
+//! `noxlocal` in noxrs: the runner is started once with `-serve -serve-rs`
+//! and each prompt and each reply end with an ASCII record separator. With
+//! [`EngineConfig::idle_timeout`] a monitor thread closes the runner's stdin
+//! once nothing has been asked for that long, so it can save its state and
+//! exit; the next request starts it again. A host that wants an audit trail,
+//! as `NOX_SESSION_LOG` keeps one in noxrs, gets each [`SessionRecord`]
+//! through [`PersistentEngine::on_record`] and stores it as it likes.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::EngineConfig;
+
+/// Ends each prompt sent to the runner and each reply it sends back.
+pub const RECORD_SEP: u8 = 0x1e;
+/// Time a runner gets to save and exit after its stdin closes.
+const SAVE_GRACE: Duration = Duration::from_secs(10);
+/// How often the monitor thread looks at the idle timer.
+const POLL: Duration = Duration::from_millis(50);
+
+/// One answered prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    /// Until the first byte of the reply; `None` for an empty one.
+    pub ttft: Option<Duration>,
+    pub duration: Duration,
+}
+
+/// What happened in a session, one record per request or restart.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionRecord {
+    Request {
+        prompt: String,
+        /// As far as it got, when the request failed.
+        response: String,
+        ttft: Option<Duration>,
+        duration: Duration,
+        error: Option<String>,
+    },
+    /// A new runner was started in place of one that exited or was closed
+    /// for being idle.
+    Restart { reason: String },
+}
+
+/// Called with each [`SessionRecord`] as it happens.
+pub type OnRecord = Box<dyn FnMut(&SessionRecord) + Send>;
+
+/// A `-serve` runner kept warm between requests.
+pub struct PersistentEngine {
+    cfg: EngineConfig,
+    runner: Option<Runner>,
+    /// Why the next request has to start a runner, when it was not the
+    /// idle timer.
+    lost: Option<String>,
+    on_record: Option<OnRecord>,
+    shared: Arc<Shared>,
+    monitor: Option<JoinHandle<()>>,
+}
+
+/// A running child and its stdout, read on a thread of its own.
+struct Runner {
+    child: Child,
+    rx: Receiver<io::Result<Vec<u8>>>,
+    /// Bytes read past the end of the last reply.
+    pending: Vec<u8>,
+}
+
+/// What the monitor thread and the requests share.
+struct Shared {
+    /// The runner's stdin; `None` once the idle timer has closed it.
+    stdin: Mutex<Option<ChildStdin>>,
+    started: Instant,
+    /// Milliseconds after `started` that the last request ended.
+    last_activity: AtomicU64,
+    /// A request is in flight, so the runner is not idle however long it
+    /// takes.
+    busy: AtomicBool,
+    done: AtomicBool,
+}
+
+impl Shared {
+    fn touch(&self) {
+        let now = self.started.elapsed().as_millis() as u64;
+        self.last_activity.store(now, Ordering::SeqCst);
+    }
+
+    fn idle_for(&self, limit: Duration) -> bool {
+        let last = Duration::from_millis(self.last_activity.load(Ordering::SeqCst));
+        self.started.elapsed().saturating_sub(last) >= limit
+    }
+}
+
+impl PersistentEngine {
+    /// Starts the runner `cfg` names.
+    pub fn start(cfg: EngineConfig) -> io::Result<Self> {
+        let shared = Arc::new(Shared {
+            stdin: Mutex::new(None),
+            started: Instant::now(),
+            last_activity: AtomicU64::new(0),
+            busy: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        });
+        let monitor = cfg.idle_timeout.map(|limit| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || watch_idle(&shared, limit))
+        });
+        let mut engine = Self {
+            cfg,
+            runner: None,
+            lost: None,
+            on_record: None,
+            shared,
+            monitor,
+        };
+        engine.spawn()?;
+        Ok(engine)
+    }
+
+    /// Passes every request and restart to `callback` from now on.
+    pub fn on_record(&mut self, callback: impl FnMut(&SessionRecord) + Send + 'static) {
+        self.on_record = Some(Box::new(callback));
+    }
+
+    /// Whether the runner is up: not yet closed for being idle.
+    pub fn is_running(&self) -> bool {
+        self.stdin().is_some()
+    }
+
+    /// Sends `prompt` and passes the reply to `on_bytes` as it arrives,
+    /// starting the runner again first if it was closed for being idle. A
+    /// runner that exits mid-reply is an error, and the next request starts
+    /// a new one.
+    pub fn request(&mut self, prompt: &str, on_bytes: &mut dyn FnMut(&[u8])) -> io::Result<Reply> {
+        self.shared.busy.store(true, Ordering::SeqCst);
+        let mut response = Vec::new();
+        let started = Instant::now();
+        let reply = self.exchange(prompt, &mut |bytes| {
+            response.extend_from_slice(bytes);
+            on_bytes(bytes);
+        });
+        self.shared.touch();
+        self.shared.busy.store(false, Ordering::SeqCst);
+        self.record(match &reply {
+            Ok(reply) => SessionRecord::Request {
+                prompt: prompt.to_string(),
+                response: reply.text.clone(),
+                ttft: reply.ttft,
+                duration: reply.duration,
+                error: None,
+            },
+            Err(err) => SessionRecord::Request {
+                prompt: prompt.to_string(),
+                response: String::from_utf8_lossy(&response).into_owned(),
+                ttft: None,
+                duration: started.elapsed(),
+                error: Some(err.to_string()),
+            },
+        });
+        reply
+    }
+
+    /// Closes the runner's stdin and waits for it to save and exit.
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn exchange(&mut self, prompt: &str, on_bytes: &mut dyn FnMut(&[u8])) -> io::Result<Reply> {
+        if self.runner.is_none() || !self.is_running() {
+            let reason = match self.lost.take() {
+                Some(reason) => reason,
+                None => "idle timeout".to_string(),
+            };
+            self.reap();
+            self.spawn()?;
+            self.record(SessionRecord::Restart { reason });
+        }
+        let started = Instant::now();
+        let mut frame: Vec<u8> = prompt.bytes().filter(|b| *b != RECORD_SEP).collect();
+        frame.push(RECORD_SEP);
+        {
+            let mut stdin = self.stdin();
+            let stdin = stdin
+                .as_mut()
+                .ok_or_else(|| io::Error::other("runner stdin already closed"))?;
+            stdin.write_all(&frame)?;
+            stdin.flush()?;
+        }
+
+        let runner = self.runner.as_mut().expect("spawned above");
+        let mut text = Vec::new();
+        let mut ttft = None;
+        loop {
+            if !runner.pending.is_empty() {
+                let chunk = std::mem::take(&mut runner.pending);
+                ttft.get_or_insert_with(|| started.elapsed());
+                let end = chunk.iter().position(|b| *b == RECORD_SEP);
+                let body = &chunk[..end.unwrap_or(chunk.len())];
+                on_bytes(body);
+                text.extend_from_slice(body);
+                if let Some(end) = end {
+                    runner.pending = chunk[end + 1..].to_vec();
+                    return Ok(Reply {
+                        text: String::from_utf8_lossy(&text).into_owned(),
+                        ttft,
+                        duration: started.elapsed(),
+                    });
+                }
+            }
+            match runner.rx.recv() {
+                Ok(chunk) => runner.pending = chunk?,
+                Err(_) => {
+                    let why = match self.reap() {
+                        Some(status) => format!("runner exited mid-reply ({status})"),
+                        None => "runner exited mid-reply".to_string(),
+                    };
+                    self.lost = Some(why.clone());
+                    return Err(io::Error::other(why));
+                }
+            }
+        }
+    }
+
+    fn spawn(&mut self) -> io::Result<()> {
+        let mut cmd = Command::new(&self.cfg.runner_bin);
+        cmd.args(["-serve", "-serve-rs"])
+            .arg("-model")
+            .arg(&self.cfg.model)
+            .args(["-ctx", &self.cfg.ctx.to_string()])
+            .args(["-max-tokens", &self.cfg.max_tokens.to_string()])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        if let Some(threads) = self.cfg.threads {
+            cmd.env("NOX_NUM_THREADS", threads.to_string());
+        }
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take();
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::other("failed to open runner stdout"))?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.send(Ok(buf[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        break;
+                    }
+                }
+            }
+        });
+        *self.stdin() = stdin;
+        self.shared.touch();
+        self.runner = Some(Runner {
+            child,
+            rx,
+            pending: Vec::new(),
+        });
+        Ok(())
+    }
+
+    /// Closes the runner's stdin if it is still open and waits up to
+    /// [`SAVE_GRACE`] for it to exit, killing it after that.
+    fn reap(&mut self) -> Option<ExitStatus> {
+        self.stdin().take();
+        let mut runner = self.runner.take()?;
+        let deadline = Instant::now() + SAVE_GRACE;
+        loop {
+            match runner.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) if Instant::now() < deadline => thread::sleep(POLL),
+                Ok(None) => {
+                    let _ = runner.child.kill();
+                    return runner.child.wait().ok();
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+
+    fn record(&mut self, record: SessionRecord) {
+        if let Some(on_record) = self.on_record.as_mut() {
+            on_record(&record);
+        }
+    }
+
+    fn shutdown(&mut self) {
+        self.shared.done.store(true, Ordering::SeqCst);
+        self.reap();
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
+        }
+    }
+
+    fn stdin(&self) -> std::sync::MutexGuard<'_, Option<ChildStdin>> {
+        self.shared.stdin.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for PersistentEngine {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Closes the runner's stdin once no request has been in flight for
+/// `limit`. EOF is the runner's cue to run `-state-save` and exit.
+fn watch_idle(shared: &Shared, limit: Duration) {
+    while !shared.done.load(Ordering::SeqCst) {
+        {
+            let mut stdin = shared.stdin.lock().unwrap_or_else(|e| e.into_inner());
+            if stdin.is_some() && !shared.busy.load(Ordering::SeqCst) && shared.idle_for(limit) {
+                stdin.take();
+            }
+        }
+        thread::sleep(POLL);
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// A `-serve -serve-rs` runner in bash: echoes each prompt, takes its
+    /// time over ones starting `slow`, and appends to `saved` on EOF the way
+    /// `-state-save` would write.
+    fn scripted_runner(name: &str) -> (PathBuf, EngineConfig) {
+        let dir = std::env::temp_dir().join(format!("nox-engine-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("runner");
+        fs::write(
+            &script,
+            "#!/bin/bash\n\
+             while IFS= read -r -d $'\\x1e' prompt; do\n\
+             case \"$prompt\" in\n\
+             slow*) printf 'slow '; sleep 0.5; printf 'reply\\x1e' ;;\n\
+             die*) printf 'dying'; exit 3 ;;\n\
+             *) printf 'echo %s\\x1e' \"$prompt\" ;;\n\
+             esac\n\
+             done\n\
+             echo saved >> \"$(dirname \"$0\")/saved\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        let cfg = EngineConfig {
+            runner_bin: script,
+            ..EngineConfig::default()
+        };
+        (dir, cfg)
+    }
+
+    fn saves(dir: &std::path::Path) -> usize {
+        fs::read_to_string(dir.join("saved"))
+            .map(|text| text.lines().count())
+            .unwrap_or(0)
+    }
+
+    fn wait_for(what: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if what() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn replies_stream_and_end_at_the_separator() {
+        let (_dir, cfg) = scripted_runner("stream");
+        let mut engine = PersistentEngine::start(cfg).unwrap();
+        for prompt in ["one", "two"] {
+            let mut streamed = Vec::new();
+            let reply = engine
+                .request(prompt, &mut |bytes| streamed.extend_from_slice(bytes))
+                .unwrap();
+            assert_eq!(reply.text, format!("echo {prompt}"));
+            assert_eq!(streamed, reply.text.as_bytes());
+            assert!(reply.ttft.is_some());
+        }
+    }
+
+    #[test]
+    fn an_idle_runner_is_closed_and_started_again() {
+        let (dir, mut cfg) = scripted_runner("idle");
+        cfg.idle_timeout = Some(Duration::from_millis(200));
+        let mut engine = PersistentEngine::start(cfg).unwrap();
+        assert_eq!(engine.request("hi", &mut |_| {}).unwrap().text, "echo hi");
+        assert!(wait_for(|| saves(&dir) == 1));
+        assert!(!engine.is_running());
+
+        assert_eq!(
+            engine.request("again", &mut |_| {}).unwrap().text,
+            "echo again"
+        );
+        assert!(engine.is_running());
+    }
+
+    #[test]
+    fn the_idle_timer_waits_for_a_reply_in_flight() {
+        let (dir, mut cfg) = scripted_runner("busy");
+        cfg.idle_timeout = Some(Duration::from_millis(200));
+        let mut engine = PersistentEngine::start(cfg).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            engine.request("slow", &mut |_| {}).unwrap().text,
+            "slow reply"
+        );
+        assert_eq!(saves(&dir), 0);
+        assert!(engine.is_running());
+    }
+
+    fn recorded(engine: &mut PersistentEngine) -> Arc<Mutex<Vec<SessionRecord>>> {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&records);
+        engine.on_record(move |record| sink.lock().unwrap().push(record.clone()));
+        records
+    }
+
+    #[test]
+    fn every_request_is_recorded() {
+        let (_dir, cfg) = scripted_runner("records");
+        let mut engine = PersistentEngine::start(cfg).unwrap();
+        let records = recorded(&mut engine);
+        for prompt in ["one", "two", "three"] {
+            engine.request(prompt, &mut |_| {}).unwrap();
+        }
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 3);
+        for (record, prompt) in records.iter().zip(["one", "two", "three"]) {
+            let SessionRecord::Request {
+                prompt: asked,
+                response,
+                error,
+                ..
+            } = record
+            else {
+                panic!("not a request: {record:?}");
+            };
+            assert_eq!(asked, prompt);
+            assert_eq!(response, &format!("echo {prompt}"));
+            assert_eq!(error, &None);
+        }
+    }
+
+    #[test]
+    fn a_crash_is_recorded_with_the_restart_after_it() {
+        let (_dir, mut cfg) = scripted_runner("crash");
+        cfg.idle_timeout = Some(Duration::from_millis(200));
+        let mut engine = PersistentEngine::start(cfg).unwrap();
+        let records = recorded(&mut engine);
+        assert!(engine.request("die", &mut |_| {}).is_err());
+        engine.request("after", &mut |_| {}).unwrap();
+        thread::sleep(Duration::from_millis(400));
+        engine.request("woken", &mut |_| {}).unwrap();
+
+        let records = records.lock().unwrap();
+        assert_eq!(records.len(), 5, "{records:?}");
+        match &records[0] {
+            SessionRecord::Request {
+                response, error, ..
+            } => {
+                assert_eq!(response, "dying");
+                assert!(error.as_ref().unwrap().contains("exited mid-reply"));
+            }
+            other => panic!("not a request: {other:?}"),
+        }
+        match &records[1] {
+            SessionRecord::Restart { reason } => assert!(reason.contains("exit status: 3")),
+            other => panic!("not a restart: {other:?}"),
+        }
+        assert_eq!(
+            records[3],
+            SessionRecord::Restart {
+                reason: "idle timeout".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn closing_lets_the_runner_save() {
+        let (dir, cfg) = scripted_runner("close");
+        let engine = PersistentEngine::start(cfg).unwrap();
+        engine.close();
+        assert_eq!(saves(&dir), 1);
+    }
+}
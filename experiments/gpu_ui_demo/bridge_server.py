@@ -4,9 +4,14 @@
 from __future__ import annotations
 
 import asyncio
+import hmac
 import json
+import os
+import shutil
 import signal
 import sys
+import threading
+import time
 from pathlib import Path
 from typing import Any, Dict
 
@@ -21,6 +26,22 @@ from central.core import ChatClient  # type: ignore  # pylint: disable=wrong-imp
 
 DEFAULT_ADDR = "127.0.0.1"
 DEFAULT_PORT = 4510
+PROTOCOL = 1
+# How long a client gets to send its token when the bridge wants one.
+AUTH_TIMEOUT = 3.0
+# Seconds between `stats` messages, when nvidia-smi is there to ask.
+STATS_INTERVAL = 1.0
+GPU_QUERY = "utilization.gpu,memory.used,memory.total,temperature.gpu"
+
+
+class TurnCancelled(Exception):
+    """Raised from the delta callback to stop a turn the UI cancelled."""
+
+
+def offered_models() -> list[str]:
+    """The models NOX_BRIDGE_MODELS lists, comma-separated, for clients to pick."""
+    raw = os.environ.get("NOX_BRIDGE_MODELS", "")
+    return [name.strip() for name in raw.split(",") if name.strip()]
 
 
 def load_system_prompt() -> str | None:
@@ -47,18 +68,215 @@ async def send_json(writer: asyncio.StreamWriter, payload: Dict[str, Any]) -> No
         pass
 
 
+def apply_params(client: ChatClient, params: Dict[str, Any]) -> Dict[str, Any]:
+    """Set the UI's sampling values on the client; return what took effect.
+
+    ChatClient knows temperature and max_tokens. top_p, top_k, and the seed
+    the UI sends when regenerating are only set on clients that have them, so
+    they are left out of the echo otherwise.
+    """
+    applied: Dict[str, Any] = {}
+    try:
+        if "temp" in params:
+            client.temperature = float(params["temp"])
+            applied["temp"] = client.temperature
+        if "max_tokens" in params:
+            client.max_tokens = int(params["max_tokens"])
+            applied["max_tokens"] = client.max_tokens
+    except (TypeError, ValueError):
+        pass
+    for key in ("top_p", "top_k"):
+        if key in params and hasattr(client, key):
+            setattr(client, key, params[key])
+            applied[key] = params[key]
+    return applied
+
+
+def apply_seed(client: ChatClient, seed: Any, applied: Dict[str, Any]) -> None:
+    """Set a regenerated prompt's seed on clients that take one."""
+    if isinstance(seed, int) and hasattr(client, "seed"):
+        client.seed = seed
+        applied["seed"] = seed
+
+
+def apply_system(client: ChatClient, system: str | None) -> None:
+    """Lead the conversation with `system`, replacing any system message there."""
+    messages = list(client.messages)
+    if messages and messages[0].get("role") == "system":
+        messages = messages[1:]
+    if system:
+        messages.insert(0, {"role": "system", "content": system})
+    client.set_messages(messages)
+
+
+def with_context(text: str, context: Any) -> str:
+    """Put the files a prompt came with ahead of it, each under its name."""
+    if not isinstance(context, list):
+        return text
+    parts = []
+    for item in context:
+        if isinstance(item, dict) and isinstance(item.get("text"), str):
+            name = item.get("name") or "attachment"
+            parts.append(f"--- {name}\n{item['text'].rstrip()}\n")
+    if not parts:
+        return text
+    return "\n".join(parts) + "\n" + text
+
+
+def trim_history(client: ChatClient, keep: int) -> int:
+    """Drop all but the last `keep` exchanges, leaving the system message.
+
+    An exchange is a user message and whatever follows it up to the next
+    one. Returns how many were dropped.
+    """
+    messages = client.messages
+    start = 1 if messages and messages[0].get("role") == "system" else 0
+    users = [idx for idx in range(start, len(messages)) if messages[idx].get("role") == "user"]
+    if len(users) <= keep:
+        return 0
+    cut = users[len(users) - keep] if keep > 0 else len(messages)
+    del messages[start:cut]
+    return len(users) - keep
+
+
+async def gpu_stats() -> Dict[str, float] | None:
+    """Ask nvidia-smi about the first GPU; None if it can't say."""
+    try:
+        proc = await asyncio.create_subprocess_exec(
+            "nvidia-smi",
+            f"--query-gpu={GPU_QUERY}",
+            "--format=csv,noheader,nounits",
+            stdout=asyncio.subprocess.PIPE,
+            stderr=asyncio.subprocess.DEVNULL,
+        )
+        out, _ = await proc.communicate()
+    except OSError:
+        return None
+    lines = out.decode("utf-8", "replace").strip().splitlines()
+    if proc.returncode != 0 or not lines:
+        return None
+    stats: Dict[str, float] = {}
+    keys = ("gpu_util", "vram_used_mb", "vram_total_mb", "temp_c")
+    for key, field in zip(keys, lines[0].split(",")):
+        try:
+            stats[key] = float(field)
+        except ValueError:
+            continue
+    return stats or None
+
+
+async def authenticate(
+    reader: asyncio.StreamReader, writer: asyncio.StreamWriter, token: str
+) -> bool:
+    """Wait for the client's `auth` line; answer `auth_error` unless it matches."""
+    try:
+        data = await asyncio.wait_for(reader.readline(), AUTH_TIMEOUT)
+        message = json.loads(data.decode("utf-8"))
+    except (asyncio.TimeoutError, ConnectionError, json.JSONDecodeError, UnicodeDecodeError):
+        message = None
+    if not isinstance(message, dict) or message.get("type") != "auth":
+        await send_json(writer, {"type": "auth_error", "message": "token required"})
+        return False
+    if not hmac.compare_digest(str(message.get("token") or ""), token):
+        await send_json(writer, {"type": "auth_error", "message": "wrong token"})
+        return False
+    return True
+
+
 async def handle_client(reader: asyncio.StreamReader, writer: asyncio.StreamWriter) -> None:
     peer = writer.get_extra_info("peername")
     loop = asyncio.get_running_loop()
 
+    token = os.environ.get("NOX_BRIDGE_TOKEN", "").strip()
+    if token and not await authenticate(reader, writer, token):
+        writer.close()
+        return
+
     system_prompt = load_system_prompt()
     client = ChatClient(stream=True, sanitize=False)
     client.reset_messages(system=system_prompt)
 
-    await send_json(writer, {"type": "hello", "message": "Noctics bridge ready"})
+    hello: Dict[str, Any] = {
+        "type": "hello",
+        "protocol": PROTOCOL,
+        "message": "Noctics bridge ready",
+    }
+    models = offered_models()
+    if models:
+        hello["models"] = models
+        hello["active"] = client.model
+    await send_json(writer, hello)
     if peer:
         await send_json(writer, {"type": "log", "text": f"Connected: {peer}"})
 
+    # Turns run one at a time in a worker so a cancel can be read mid-turn.
+    # Every prompt ends in exactly one `done` or `error`, cancelled or not,
+    # which is how the UI knows to drop a reply it already stopped. A prompt
+    # sent with an `id` gets it back on each of those, so a UI that sends
+    # without waiting can tell the replies apart.
+    prompts: asyncio.Queue[tuple[str, Dict[str, Any], str | None, Any, Any]] = asyncio.Queue()
+    active: threading.Event | None = None
+
+    async def run_turns() -> None:
+        nonlocal active
+        while True:
+            text, params, system, prompt_id, seed = await prompts.get()
+
+            def tagged(payload: Dict[str, Any]) -> Dict[str, Any]:
+                if prompt_id is not None:
+                    payload["id"] = prompt_id
+                return payload
+
+            # Prompts without one run under the bridge's own system prompt.
+            apply_system(client, system or system_prompt)
+            applied = apply_params(client, params)
+            apply_seed(client, seed, applied)
+            cancelled = threading.Event()
+            active = cancelled
+            streamed: list[str] = []
+
+            def on_delta(piece: str) -> None:
+                if cancelled.is_set():
+                    raise TurnCancelled()
+                if not piece:
+                    return
+                streamed.append(piece)
+                asyncio.run_coroutine_threadsafe(
+                    send_json(writer, tagged({"type": "delta", "text": piece})),
+                    loop,
+                )
+
+            try:
+                assistant = await asyncio.to_thread(client.one_turn, text, on_delta=on_delta)
+            except Exception as exc:  # pragma: no cover - defensive
+                if not cancelled.is_set():
+                    await send_json(writer, tagged({"type": "error", "message": str(exc)}))
+                    continue
+                assistant = None
+            finally:
+                active = None
+            if cancelled.is_set():
+                partial = "".join(streamed)
+                await send_json(
+                    writer,
+                    tagged({"type": "done", "text": partial, "cancelled": True, "params": applied}),
+                )
+            else:
+                await send_json(
+                    writer,
+                    tagged({"type": "done", "text": assistant or "", "params": applied}),
+                )
+
+    async def send_stats() -> None:
+        while True:
+            await asyncio.sleep(STATS_INTERVAL)
+            stats = await gpu_stats()
+            if stats is not None:
+                await send_json(writer, {"type": "stats", **stats})
+
+    worker = asyncio.create_task(run_turns())
+    reporter = asyncio.create_task(send_stats()) if shutil.which("nvidia-smi") else None
+
     while True:
         try:
             data = await reader.readline()
@@ -75,30 +293,72 @@ async def handle_client(reader: asyncio.StreamReader, writer: asyncio.StreamWrit
         kind = message.get("type")
         if kind == "prompt":
             text = str(message.get("text") or "")
+            prompt_id = message.get("id")
             if not text.strip():
-                await send_json(writer, {"type": "error", "message": "empty prompt"})
+                error = {"type": "error", "message": "empty prompt"}
+                if prompt_id is not None:
+                    error["id"] = prompt_id
+                await send_json(writer, error)
                 continue
-
-            def on_delta(piece: str) -> None:
-                if not piece:
-                    return
-                asyncio.run_coroutine_threadsafe(
-                    send_json(writer, {"type": "delta", "text": piece}),
-                    loop,
+            params = message.get("params")
+            system = message.get("system")
+            prompts.put_nowait(
+                (
+                    with_context(text, message.get("context")),
+                    params if isinstance(params, dict) else {},
+                    system if isinstance(system, str) else None,
+                    prompt_id,
+                    message.get("seed"),
                 )
-
+            )
+        elif kind == "cancel":
+            # Nothing running means the turn already finished; its `done`
+            # is on the way and stands in for the cancelled one.
+            if active is not None:
+                active.set()
+        elif kind == "ping":
+            await send_json(writer, {"type": "pong", "id": message.get("id")})
+        elif kind == "auth":
+            # A token sent to a bridge that asks for none changes nothing.
+            continue
+        elif kind == "trim":
+            keep = message.get("keep")
+            if not isinstance(keep, int) or keep < 0:
+                await send_json(writer, {"type": "error", "message": "trim needs a keep count"})
+                continue
+            dropped = trim_history(client, keep)
+            await send_json(writer, {"type": "log", "text": f"Trimmed {dropped} exchange(s)"})
+        elif kind == "set_model":
+            # The console only asks between turns, so the worker picks the
+            # new client up with the next prompt.
+            name = message.get("name")
+            if not isinstance(name, str) or name not in offered_models():
+                await send_json(writer, {"type": "error", "message": f"unknown model: {name}"})
+                continue
+            started = time.monotonic()
             try:
-                assistant = await asyncio.to_thread(client.one_turn, text, on_delta=on_delta)
+                loaded = await asyncio.to_thread(
+                    ChatClient, model=name, stream=True, sanitize=False
+                )
             except Exception as exc:  # pragma: no cover - defensive
-                await send_json(writer, {"type": "error", "message": str(exc)})
+                error = {"type": "error", "message": f"could not load {name}: {exc}"}
+                await send_json(writer, error)
                 continue
-            await send_json(writer, {"type": "done", "text": assistant or ""})
+            loaded.set_messages(list(client.messages))
+            client = loaded
+            load_ms = int((time.monotonic() - started) * 1000)
+            await send_json(writer, {"type": "model_ready", "name": name, "load_ms": load_ms})
         elif kind == "reset":
             client.reset_messages(system=system_prompt)
             await send_json(writer, {"type": "status", "message": "session reset"})
         else:
             await send_json(writer, {"type": "error", "message": f"unknown command: {kind}"})
 
+    if active is not None:
+        active.set()
+    worker.cancel()
+    if reporter is not None:
+        reporter.cancel()
     try:
         writer.close()
         await writer.wait_closed()
@@ -0,0 +1,45 @@
+#!/bin/sh
+# Stands in for nox when trying the GPU console's local backend without a
+# model: answers one NOX_PERSIST_PROTO=jsonl prompt by echoing it back a word
+# at a time, then exits. Point the console's nox path at this file.
+#
+# MOCK_NOX_MODE=error answers with an error, crash exits without answering,
+# and load_failed fails the way a runner that cannot load its model does.
+# MOCK_NOX_DELAY sets the pause between words (default 0.15s).
+
+set -f
+delay=${MOCK_NOX_DELAY:-0.15}
+IFS= read -r request || exit 1
+# The prompt's text is already escaped for JSON, so it can go straight back.
+text=$(printf '%s\n' "$request" | sed -n 's/.*"text":"\(.*\)"}$/\1/p')
+
+case "${MOCK_NOX_MODE:-}" in
+load_failed)
+    printf '%s\n' '{"type":"error","reason":"load_failed","message":"runner exited with exit status: 1","stderr":["error: failed to load model"]}'
+    exit 1
+    ;;
+crash)
+    echo "mock nox: crashing on purpose" >&2
+    exit 3
+    ;;
+esac
+
+printf '{"type":"ready","model":"mock","ctx":4096,"load_ms":%d}\n' 5
+if [ "${MOCK_NOX_MODE:-}" = error ]; then
+    printf '%s\n' '{"id":1,"type":"error","message":"runner failed (exit status: 1)"}'
+    exit 0
+fi
+sleep "$delay"
+reply="(mock nox, T=${NOX_TEMP:-0} top_p=${NOX_TOP_P:-1} top_k=${NOX_TOP_K:-1} max_tokens=${NOX_MAX_TOKENS:-128}) $text"
+first=1
+for word in $reply; do
+    if [ "$first" = 1 ]; then
+        chunk=$word
+        first=0
+    else
+        chunk=" $word"
+    fi
+    printf '{"id":1,"type":"delta","text":"%s"}\n' "$chunk"
+    sleep "$delay"
+done
+printf '%s\n' '{"id":1,"type":"done","ttft_ms":150,"duration_ms":900,"truncated":false}'
@@ -0,0 +1,143 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+/// The largest file Attach takes until another cap is set, in KiB.
+pub const DEFAULT_LIMIT_KB: u64 = 256;
+
+/// What separates the prompt and each file for the local backend; noxrs
+/// routes on it, taking the first part as the query.
+pub const DELIM: &str = "---";
+
+/// A text file sent along with a prompt.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Attachment {
+    /// The file's name, without its directory.
+    pub name: String,
+    pub text: String,
+}
+
+impl Attachment {
+    pub fn size(&self) -> u64 {
+        self.text.len() as u64
+    }
+}
+
+/// Reads `path` as UTF-8 text of at most `limit_kb` KiB. Binary files, ones
+/// that are too big, and ones that can't be read give the reason instead.
+pub fn load(path: &Path, limit_kb: u64) -> Result<Attachment, String> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let len = fs::metadata(path)
+        .map_err(|err| format!("Could not read {}: {}", name, err))?
+        .len();
+    if len > limit_kb * 1024 {
+        return Err(format!(
+            "{} is {}, over the {} limit",
+            name,
+            size_label(len),
+            size_label(limit_kb * 1024)
+        ));
+    }
+    let bytes = fs::read(path).map_err(|err| format!("Could not read {}: {}", name, err))?;
+    // NUL never turns up in text, and is the surest sign of a binary file
+    // that happens to be valid UTF-8.
+    if bytes.contains(&0) {
+        return Err(format!("{} looks binary, not text", name));
+    }
+    let text = String::from_utf8(bytes).map_err(|_| format!("{} is not UTF-8 text", name))?;
+    Ok(Attachment { name, text })
+}
+
+/// The prompt as the local backend sends it: the prompt, then each file
+/// after a [`DELIM`] line.
+pub fn for_nox(prompt: &str, context: &[Attachment]) -> String {
+    let mut text = prompt.to_string();
+    for file in context {
+        text.push('\n');
+        text.push_str(DELIM);
+        text.push('\n');
+        text.push_str(&file.text);
+    }
+    text
+}
+
+/// `bytes` as `812 B`, `12.4 KB`, or `1.2 MB`.
+pub fn size_label(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1_048_575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two() -> [Attachment; 2] {
+        [
+            Attachment {
+                name: "notes.md".to_string(),
+                text: "# Notes\nfirst".to_string(),
+            },
+            Attachment {
+                name: "data.csv".to_string(),
+                text: "a,b\n1,2\n".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn nox_gets_the_prompt_then_each_file() {
+        assert_eq!(
+            for_nox("Summarise these", &two()),
+            "Summarise these\n---\n# Notes\nfirst\n---\na,b\n1,2\n"
+        );
+        assert_eq!(for_nox("just this", &[]), "just this");
+    }
+
+    #[test]
+    fn only_small_utf8_text_is_taken() {
+        let dir = std::env::temp_dir().join(format!("noctics-attach-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, bytes: &[u8]| {
+            let path = dir.join(name);
+            fs::write(&path, bytes).unwrap();
+            path
+        };
+        let text = write("ok.txt", "héllo\n".as_bytes());
+        assert_eq!(
+            load(&text, 1),
+            Ok(Attachment {
+                name: "ok.txt".to_string(),
+                text: "héllo\n".to_string(),
+            })
+        );
+        let big = write("big.txt", &[b'x'; 2049]);
+        assert_eq!(
+            load(&big, 2).unwrap_err(),
+            "big.txt is 2.0 KB, over the 2.0 KB limit"
+        );
+        let nul = write("nul.bin", b"abc\0def");
+        assert_eq!(load(&nul, 1).unwrap_err(), "nul.bin looks binary, not text");
+        let latin1 = write("latin1.txt", b"caf\xe9");
+        assert_eq!(
+            load(&latin1, 1).unwrap_err(),
+            "latin1.txt is not UTF-8 text"
+        );
+        assert!(load(&dir.join("missing.txt"), 1)
+            .unwrap_err()
+            .starts_with("Could not read missing.txt: "));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sizes_read_naturally() {
+        assert_eq!(size_label(812), "812 B");
+        assert_eq!(size_label(12_698), "12.4 KB");
+        assert_eq!(size_label(1_258_291), "1.2 MB");
+    }
+}
@@ -0,0 +1,1339 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::attach::Attachment;
+use crate::diagnostics::Level;
+use crate::handshake::{self, Handshake, Step};
+use crate::heartbeat::{Beat, Heartbeat};
+use crate::local::LocalBackend;
+use crate::shutdown;
+use crate::stats;
+use crate::telemetry;
+use crate::SamplingParams;
+
+pub type JobTx = Sender<BackendJob>;
+
+pub enum BackendEvent {
+    /// For the diagnostics drawer rather than the conversation.
+    Log(Level, String),
+    Status(String),
+    /// Part of a reply; the id is the prompt's, if the bridge tagged it.
+    Delta(Option<u64>, String),
+    Done(Option<u64>, String),
+    /// One tagged prompt failed; the others go on.
+    Failed(u64, String),
+    Error(String),
+    Link(Link),
+    Queued(usize),
+    Connected(String),
+    Applied(String),
+    Stats(stats::Reported),
+    /// The model the bridge said it runs.
+    Model(String),
+    /// The models the bridge's `hello` offered to switch to.
+    Models(Vec<String>),
+    /// The model asked for with `SetModel` is loaded; the bridge may name it.
+    ModelReady(Option<String>),
+    /// GPU and speed numbers the bridge sent on its own.
+    Telemetry(telemetry::Sample),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Online,
+    Offline,
+    Simulated,
+    /// Connecting, and waiting for the bridge's `hello`.
+    Handshaking,
+    /// The bridge turned us away; nothing is sent until a reconnect works.
+    Refused,
+}
+
+pub enum BackendJob {
+    Prompt(Prompt),
+    Disconnected { generation: u64, reason: String },
+    ReconnectNow,
+    UseSimulated,
+    Connect { addr: String, token: Option<String> },
+    SetFallback(Fallback),
+    Cancel,
+    /// Keep only this many of the latest exchanges in the bridge's history.
+    Trim(usize),
+    /// Have the bridge load another of the models it offered.
+    SetModel(String),
+    Quit,
+}
+
+/// One prompt, and what it is sent with.
+#[derive(Clone)]
+pub struct Prompt {
+    pub text: String,
+    pub params: SamplingParams,
+    /// The tab's system prompt, if it has one.
+    pub system: Option<String>,
+    /// Tags the prompt and its reply, for bridges that answer several at
+    /// once.
+    pub id: Option<u64>,
+    /// Set when regenerating, so the reply comes out different.
+    pub seed: Option<u64>,
+    /// Files attached to it, in the order they were attached.
+    pub context: Vec<Attachment>,
+}
+
+/// What a tab does when its backend can't be reached or drops.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fallback {
+    /// Says so and retries with backoff, holding prompts until it is back.
+    #[default]
+    Retry,
+    /// Answers with the simulated echo until asked to reconnect.
+    Simulated,
+}
+
+/// Where a tab's prompts go.
+#[derive(Clone, Debug)]
+pub enum Target {
+    Bridge { addr: String, token: Option<String> },
+    Local(PathBuf),
+}
+
+#[derive(Debug)]
+pub enum BackendError {
+    /// The connection or process behind the backend is gone.
+    Lost(String),
+    /// The bridge answered but won't serve us, so retrying as we are is no
+    /// use.
+    Refused(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Lost(reason) | BackendError::Refused(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// One way of answering prompts. A backend sends what it hears back as
+/// [`BackendEvent`]s itself; the [`Driver`] only hands it jobs.
+pub trait ChatBackend {
+    /// Takes a `Prompt`, `Cancel`, `Trim`, or `SetModel`. An error means the
+    /// backend is gone and the job was not taken.
+    fn submit(&mut self, job: BackendJob) -> Result<(), BackendError>;
+    /// Ends the backend and whatever it was answering.
+    fn shutdown(&mut self);
+}
+
+/// Opens the backend for a target. The number tells this attempt's
+/// disconnects apart from earlier ones.
+pub type Opener =
+    Box<dyn FnMut(&Target, u64) -> Result<Box<dyn ChatBackend>, BackendError> + Send>;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_QUEUED: usize = 32;
+const SIMULATED_WORD_DELAY: Duration = Duration::from_millis(150);
+
+/// Runs a tab's backend: owns the job channel, hands prompts and cancels to
+/// whichever backend is up, queues prompts while none is, and decides what
+/// happens when one can't be reached or drops, as its [`Fallback`] says.
+pub struct Driver {
+    target: Target,
+    fallback: Fallback,
+    open: Opener,
+    jobs: Receiver<BackendJob>,
+    tx: Sender<BackendEvent>,
+    active: Option<Box<dyn ChatBackend>>,
+    link: Link,
+    queue: VecDeque<Prompt>,
+    backoff: Duration,
+    /// When to try again while offline, and why it is.
+    retry: Option<(Instant, String)>,
+    generation: u64,
+}
+
+impl Driver {
+    pub fn spawn(
+        target: Target,
+        fallback: Fallback,
+        jobs: Receiver<BackendJob>,
+        job_tx: JobTx,
+        tx: Sender<BackendEvent>,
+    ) -> thread::JoinHandle<()> {
+        let events = tx.clone();
+        let open: Opener =
+            Box::new(move |target, generation| open(target, generation, &job_tx, &events));
+        thread::spawn(move || Self::new(target, fallback, open, jobs, tx).run())
+    }
+
+    pub fn new(
+        target: Target,
+        fallback: Fallback,
+        open: Opener,
+        jobs: Receiver<BackendJob>,
+        tx: Sender<BackendEvent>,
+    ) -> Self {
+        Self {
+            target,
+            fallback,
+            open,
+            jobs,
+            tx,
+            active: None,
+            link: Link::Offline,
+            queue: VecDeque::new(),
+            backoff: INITIAL_BACKOFF,
+            retry: None,
+            generation: 0,
+        }
+    }
+
+    pub fn run(mut self) {
+        self.connect();
+        loop {
+            let job = match &self.retry {
+                Some((at, reason)) => {
+                    let left = at.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        self.connect();
+                        continue;
+                    }
+                    let addr = match &self.target {
+                        Target::Bridge { addr, .. } => addr.as_str(),
+                        Target::Local(_) => "nox",
+                    };
+                    let _ = self.tx.send(BackendEvent::Status(format!(
+                        "Bridge offline ({}). Retrying {} in {}s…",
+                        reason.trim_end_matches('.'),
+                        addr,
+                        left.as_secs_f32().ceil() as u64
+                    )));
+                    match self.jobs.recv_timeout(left.min(Duration::from_secs(1))) {
+                        Ok(job) => job,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                None => match self.jobs.recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                },
+            };
+            if !self.handle(job) {
+                break;
+            }
+        }
+        self.close();
+    }
+
+    /// Acts on one job; false once told to quit.
+    fn handle(&mut self, job: BackendJob) -> bool {
+        match job {
+            BackendJob::Prompt(prompt) => {
+                let Some(active) = &mut self.active else {
+                    self.enqueue(prompt);
+                    return true;
+                };
+                if let Err(err) = active.submit(BackendJob::Prompt(prompt.clone())) {
+                    self.enqueue(prompt);
+                    self.lost(err);
+                }
+            }
+            // Nothing to do without a backend: a new connection starts the
+            // bridge's history afresh.
+            job @ (BackendJob::Cancel | BackendJob::Trim(_) | BackendJob::SetModel(_)) => {
+                if let Some(active) = &mut self.active {
+                    if let Err(err) = active.submit(job) {
+                        self.lost(err);
+                    }
+                }
+            }
+            BackendJob::Disconnected { generation, reason } => {
+                // A connection that dropped after being replaced is old news.
+                if generation == self.generation && self.link == Link::Online {
+                    self.lost(BackendError::Lost(reason));
+                }
+            }
+            BackendJob::ReconnectNow => {
+                if self.link != Link::Online {
+                    self.close();
+                    self.connect();
+                }
+            }
+            BackendJob::UseSimulated => {
+                if matches!(self.link, Link::Offline | Link::Refused) {
+                    self.simulate(None);
+                }
+            }
+            BackendJob::Connect { addr, token } => {
+                if self.retarget(addr, token) {
+                    self.close();
+                    self.connect();
+                }
+            }
+            BackendJob::SetFallback(fallback) => self.fallback = fallback,
+            BackendJob::Quit => return false,
+        }
+        true
+    }
+
+    fn connect(&mut self) {
+        self.retry = None;
+        self.generation += 1;
+        if let Target::Bridge { addr, .. } = &self.target {
+            self.link = Link::Handshaking;
+            let _ = self.tx.send(BackendEvent::Link(Link::Handshaking));
+            let _ = self.tx.send(BackendEvent::Status(format!(
+                "Connecting to Noctics bridge at {}…",
+                addr
+            )));
+        }
+        match (self.open)(&self.target, self.generation) {
+            Ok(backend) => {
+                self.backoff = INITIAL_BACKOFF;
+                if let Target::Bridge { addr, .. } = &self.target {
+                    let _ = self.tx.send(BackendEvent::Connected(addr.clone()));
+                }
+                self.activate(backend, Link::Online);
+            }
+            Err(BackendError::Lost(reason)) => self.fall_back(reason),
+            Err(BackendError::Refused(reason)) => self.refused(reason),
+        }
+    }
+
+    /// Stops at a bridge that won't have us: no retries and no fallback,
+    /// since neither would change its mind.
+    fn refused(&mut self, reason: String) {
+        self.link = Link::Refused;
+        let _ = self.tx.send(BackendEvent::Link(Link::Refused));
+        let _ = self.tx.send(BackendEvent::Error(reason.clone()));
+        let _ = self.tx.send(BackendEvent::Status(format!(
+            "{} (sending is off until a reconnect gets through)",
+            reason
+        )));
+    }
+
+    /// Makes `backend` the one prompts go to, and sends it the queued ones.
+    fn activate(&mut self, backend: Box<dyn ChatBackend>, link: Link) {
+        self.active = Some(backend);
+        self.link = link;
+        let _ = self.tx.send(BackendEvent::Link(link));
+        while let Some(prompt) = self.queue.pop_front() {
+            let Some(active) = &mut self.active else {
+                return;
+            };
+            if let Err(err) = active.submit(BackendJob::Prompt(prompt.clone())) {
+                self.queue.push_front(prompt);
+                self.lost(err);
+                return;
+            }
+            let _ = self.tx.send(BackendEvent::Queued(self.queue.len()));
+        }
+    }
+
+    fn lost(&mut self, err: BackendError) {
+        self.close();
+        match err {
+            BackendError::Lost(reason) => {
+                let _ = self.tx.send(BackendEvent::Error(reason.clone()));
+                self.fall_back(reason);
+            }
+            BackendError::Refused(reason) => self.refused(reason),
+        }
+    }
+
+    fn fall_back(&mut self, reason: String) {
+        match self.fallback {
+            Fallback::Simulated => self.simulate(Some(reason)),
+            Fallback::Retry => {
+                self.link = Link::Offline;
+                let _ = self.tx.send(BackendEvent::Link(Link::Offline));
+                self.retry = Some((Instant::now() + self.backoff, reason));
+                self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    /// Swaps in the simulated echo, `because` the real backend is down if
+    /// that is why.
+    fn simulate(&mut self, because: Option<String>) {
+        self.close();
+        self.retry = None;
+        let status = match because {
+            Some(reason) => format!(
+                "Bridge offline ({}). Using simulated echo; reconnect to try again.",
+                reason.trim_end_matches('.')
+            ),
+            None => "Using simulated echo. Reconnect to go back to the bridge.".to_string(),
+        };
+        let _ = self.tx.send(BackendEvent::Status(status));
+        self.activate(Box::new(simulated(self.tx.clone())), Link::Simulated);
+    }
+
+    fn close(&mut self) {
+        if let Some(mut active) = self.active.take() {
+            active.shutdown();
+        }
+    }
+
+    fn retarget(&mut self, addr: String, token: Option<String>) -> bool {
+        let resolved = addr
+            .to_socket_addrs()
+            .map_err(|err| err.to_string())
+            .and_then(|mut addrs| addrs.next().ok_or_else(|| "no addresses".to_string()));
+        if let Err(err) = resolved {
+            let _ = self.tx.send(BackendEvent::Error(format!(
+                "Invalid bridge address `{}`: {}",
+                addr, err
+            )));
+            return false;
+        }
+        self.target = Target::Bridge { addr, token };
+        self.backoff = INITIAL_BACKOFF;
+        true
+    }
+
+    fn enqueue(&mut self, prompt: Prompt) {
+        if self.queue.len() >= MAX_QUEUED {
+            let _ = self.tx.send(BackendEvent::Error(format!(
+                "Queue full ({} prompts); dropped: {}",
+                MAX_QUEUED, prompt.text
+            )));
+            return;
+        }
+        self.queue.push_back(prompt);
+        let _ = self.tx.send(BackendEvent::Queued(self.queue.len()));
+    }
+}
+
+/// The real backend for `target`.
+fn open(
+    target: &Target,
+    generation: u64,
+    job_tx: &JobTx,
+    tx: &Sender<BackendEvent>,
+) -> Result<Box<dyn ChatBackend>, BackendError> {
+    match target {
+        Target::Bridge { addr, token } => {
+            let token = token.as_deref();
+            let remote = RemoteBackend::connect(addr, token, generation, job_tx.clone(), tx.clone())?;
+            Ok(Box::new(remote))
+        }
+        Target::Local(binary) => Ok(Box::new(LocalBackend::spawn(binary.clone(), tx.clone()))),
+    }
+}
+
+/// A backend that answers on a thread of its own, fed over a channel.
+pub struct Worker {
+    jobs: Sender<BackendJob>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Worker {
+    pub fn spawn(run: impl FnOnce(Receiver<BackendJob>) + Send + 'static) -> Self {
+        let (jobs, rx) = mpsc::channel();
+        Self {
+            jobs,
+            thread: Some(thread::spawn(move || run(rx))),
+        }
+    }
+}
+
+impl ChatBackend for Worker {
+    fn submit(&mut self, job: BackendJob) -> Result<(), BackendError> {
+        self.jobs
+            .send(job)
+            .map_err(|_| BackendError::Lost("Backend thread ended.".to_string()))
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.jobs.send(BackendJob::Quit);
+        if let Some(thread) = self.thread.take() {
+            shutdown::join_by(thread, Instant::now() + shutdown::TIMEOUT);
+        }
+    }
+}
+
+/// The stand-in echo: says each prompt back a word at a time.
+fn simulated(tx: Sender<BackendEvent>) -> Worker {
+    Worker::spawn(move |jobs| {
+        let mut backlog = VecDeque::new();
+        loop {
+            let job = match backlog.pop_front() {
+                Some(job) => job,
+                None => match jobs.recv() {
+                    Ok(job) => job,
+                    Err(_) => return,
+                },
+            };
+            let more = match job {
+                BackendJob::Prompt(prompt) => simulate(&prompt.text, &jobs, &tx, &mut backlog),
+                BackendJob::Quit => false,
+                _ => true,
+            };
+            if !more {
+                return;
+            }
+        }
+    })
+}
+
+/// Echoes one prompt, keeping prompts that arrive meanwhile in `backlog`;
+/// false once told to quit.
+fn simulate(
+    text: &str,
+    jobs: &Receiver<BackendJob>,
+    tx: &Sender<BackendEvent>,
+    backlog: &mut VecDeque<BackendJob>,
+) -> bool {
+    let reply = format!("(simulated) {}", text);
+    for word in reply.split_inclusive(' ') {
+        let deadline = Instant::now() + SIMULATED_WORD_DELAY;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            match jobs.recv_timeout(left) {
+                Ok(BackendJob::Cancel) => {
+                    let _ = tx.send(BackendEvent::Done(None, String::new()));
+                    return true;
+                }
+                Ok(BackendJob::Quit) | Err(RecvTimeoutError::Disconnected) => return false,
+                Ok(job) => backlog.push_back(job),
+                Err(RecvTimeoutError::Timeout) => break,
+            }
+        }
+        let _ = tx.send(BackendEvent::Delta(None, word.to_string()));
+    }
+    let _ = tx.send(BackendEvent::Done(None, reply));
+    true
+}
+
+#[derive(Serialize)]
+struct PromptPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    text: &'a str,
+    params: SamplingParams,
+    /// Left out when empty, so bridges that predate it see what they know.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    context: &'a [Attachment],
+}
+
+impl<'a> PromptPayload<'a> {
+    fn new(prompt: &'a Prompt) -> Self {
+        Self {
+            kind: "prompt",
+            text: &prompt.text,
+            params: prompt.params,
+            system: prompt.system.as_deref(),
+            id: prompt.id,
+            seed: prompt.seed,
+            context: &prompt.context,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TrimPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    keep: usize,
+}
+
+#[derive(Serialize)]
+struct SetModelPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    name: &'a str,
+}
+
+#[derive(Serialize)]
+struct AuthPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    token: &'a str,
+}
+
+#[derive(Serialize)]
+struct PingPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    id: u64,
+}
+
+#[derive(Serialize)]
+struct ControlPayload<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+}
+
+#[derive(Deserialize)]
+struct BridgeMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+    message: Option<String>,
+    params: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Read with [`stats::Reported::parse`], which takes more than serde would.
+    stats: Option<serde_json::Value>,
+    id: Option<u64>,
+    /// The model on a `model_ready`.
+    name: Option<String>,
+    // The numbers on a `stats` message.
+    #[serde(default, deserialize_with = "telemetry::number")]
+    gpu_util: Option<f32>,
+    #[serde(default, deserialize_with = "telemetry::number")]
+    vram_used_mb: Option<f32>,
+    #[serde(default, deserialize_with = "telemetry::number")]
+    vram_total_mb: Option<f32>,
+    #[serde(default, deserialize_with = "telemetry::number")]
+    tps: Option<f32>,
+    #[serde(default, deserialize_with = "telemetry::number")]
+    temp_c: Option<f32>,
+}
+
+struct RemoteBackend {
+    writer: Arc<Mutex<TcpStream>>,
+    /// The thread reading the bridge's lines, joined on shutdown.
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+impl RemoteBackend {
+    /// Connects to the bridge, sends `token` if there is one, and waits for
+    /// its `hello` before anything else goes out.
+    fn connect(
+        addr: &str,
+        token: Option<&str>,
+        generation: u64,
+        job_tx: JobTx,
+        tx: Sender<BackendEvent>,
+    ) -> Result<Self, BackendError> {
+        let lost = |err: std::io::Error| BackendError::Lost(err.to_string());
+        let stream = TcpStream::connect(addr).map_err(lost)?;
+        stream.set_nodelay(true).map_err(lost)?;
+        let reader_stream = stream.try_clone().map_err(lost)?;
+        let mut remote = Self {
+            writer: Arc::new(Mutex::new(stream)),
+            reader: None,
+        };
+        if let Some(token) = token {
+            remote
+                .send(&AuthPayload {
+                    kind: "auth",
+                    token,
+                })
+                .map_err(BackendError::Lost)?;
+        }
+        let mut reader = BufReader::new(reader_stream);
+        let (hello, held) = match Self::handshake(&mut reader) {
+            Ok(done) => done,
+            Err(err) => {
+                remote.close();
+                return Err(err);
+            }
+        };
+        let _ = tx.send(BackendEvent::Status(format!(
+            "Connected to Noctics bridge at {}: {}",
+            addr,
+            hello.status()
+        )));
+        if let Some(model) = hello.model.clone() {
+            let _ = tx.send(BackendEvent::Model(model));
+        }
+        if !hello.models.is_empty() {
+            let _ = tx.send(BackendEvent::Models(hello.models.clone()));
+        }
+        if let Some(warning) = hello.mismatch() {
+            let _ = tx.send(BackendEvent::Log(Level::Warn, warning));
+        }
+        for line in held {
+            Self::handle_line(&line, &tx);
+        }
+        remote.reader = Some(remote.spawn_reader(reader, generation, job_tx, tx));
+        Ok(remote)
+    }
+
+    /// Reads until the bridge says `hello`, turns us away, or runs out of
+    /// time.
+    fn handshake(
+        reader: &mut BufReader<TcpStream>,
+    ) -> Result<(handshake::Hello, Vec<String>), BackendError> {
+        let mut state = Handshake::new(Instant::now());
+        loop {
+            let now = Instant::now();
+            if let Some(reason) = state.expired(now) {
+                return Err(BackendError::Refused(reason));
+            }
+            let left = state.deadline().saturating_duration_since(now);
+            reader
+                .get_ref()
+                .set_read_timeout(Some(left.max(Duration::from_millis(1))))
+                .map_err(|err| BackendError::Lost(err.to_string()))?;
+            let mut line = String::new();
+            match reader.read_line(&mut line) {
+                Ok(0) => {
+                    return Err(BackendError::Lost(
+                        "Bridge closed the connection before saying hello.".to_string(),
+                    ))
+                }
+                Ok(_) => match state.line(&line) {
+                    Step::Waiting => {}
+                    Step::Ready(hello) => {
+                        reader
+                            .get_ref()
+                            .set_read_timeout(None)
+                            .map_err(|err| BackendError::Lost(err.to_string()))?;
+                        return Ok((hello, state.held()));
+                    }
+                    Step::Refused(reason) => return Err(BackendError::Refused(reason)),
+                },
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(err) => return Err(BackendError::Lost(err.to_string())),
+            }
+        }
+    }
+
+    /// Reads the bridge's lines until it goes away, pinging it when it has
+    /// been quiet and giving up on it when the pings go unanswered.
+    fn spawn_reader(
+        &self,
+        mut reader: BufReader<TcpStream>,
+        generation: u64,
+        job_tx: JobTx,
+        tx: Sender<BackendEvent>,
+    ) -> thread::JoinHandle<()> {
+        let pinger = Self {
+            writer: Arc::clone(&self.writer),
+            reader: None,
+        };
+        thread::spawn(move || {
+            let disconnected = |reason: String| {
+                let _ = job_tx.send(BackendJob::Disconnected { generation, reason });
+            };
+            let mut heartbeat = Heartbeat::new(Instant::now());
+            // Kept across read timeouts, which can leave half a line in it.
+            let mut line = String::new();
+            loop {
+                let now = Instant::now();
+                match heartbeat.tick(now) {
+                    Beat::Idle => {}
+                    Beat::Ping(id) => {
+                        if let Err(err) = pinger.send(&PingPayload { kind: "ping", id }) {
+                            disconnected(format!("Bridge send failed: {}", err));
+                            break;
+                        }
+                    }
+                    Beat::Dead => {
+                        let _ = tx.send(BackendEvent::Status("Bridge unresponsive".to_string()));
+                        disconnected("Bridge unresponsive.".to_string());
+                        break;
+                    }
+                }
+                let wait = heartbeat.wait(now).max(Duration::from_millis(1));
+                if let Err(err) = reader.get_ref().set_read_timeout(Some(wait)) {
+                    disconnected(format!("Bridge read failed: {}", err));
+                    break;
+                }
+                match reader.read_line(&mut line) {
+                    Ok(0) => {
+                        disconnected("Bridge connection closed.".to_string());
+                        break;
+                    }
+                    Ok(_) => {
+                        heartbeat.heard(Instant::now());
+                        Self::handle_line(&line, &tx);
+                        line.clear();
+                    }
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) => {}
+                    Err(err) => {
+                        disconnected(format!("Bridge read failed: {}", err));
+                        break;
+                    }
+                }
+            }
+        })
+    }
+
+    fn handle_line(line: &str, tx: &Sender<BackendEvent>) {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return;
+        }
+        let msg = match serde_json::from_str::<BridgeMessage>(trimmed) {
+            Ok(msg) => msg,
+            Err(err) => {
+                let _ = tx.send(BackendEvent::Log(
+                    Level::Warn,
+                    format!("Bridge parse error: {} :: {}", err, trimmed),
+                ));
+                return;
+            }
+        };
+        match msg.kind.as_str() {
+            "hello" => {
+                if let Some(message) = msg.message {
+                    let _ = tx.send(BackendEvent::Status(message));
+                }
+            }
+            "delta" => {
+                if let Some(text) = msg.text {
+                    let _ = tx.send(BackendEvent::Delta(msg.id, text));
+                }
+            }
+            "done" => {
+                let mut seed = None;
+                if let Some(params) = msg.params {
+                    let applied: Vec<String> = params
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value))
+                        .collect();
+                    let _ = tx.send(BackendEvent::Applied(applied.join(", ")));
+                    seed = params.get("seed").and_then(serde_json::Value::as_u64);
+                }
+                let mut reported = msg.stats.as_ref().and_then(stats::Reported::parse);
+                // The seed the bridge says it applied, if its stats didn't.
+                if let Some(seed) = seed {
+                    reported.get_or_insert_with(Default::default).seed.get_or_insert(seed);
+                }
+                if let Some(reported) = reported {
+                    let _ = tx.send(BackendEvent::Stats(reported));
+                }
+                let text = msg.text.unwrap_or_default();
+                let _ = tx.send(BackendEvent::Done(msg.id, text));
+            }
+            "model_ready" => {
+                let name = msg.name.filter(|name| !name.trim().is_empty());
+                let _ = tx.send(BackendEvent::ModelReady(name));
+            }
+            "log" => {
+                if let Some(text) = msg.text {
+                    let _ = tx.send(BackendEvent::Log(Level::Info, text));
+                }
+            }
+            "stats" => {
+                let sample = telemetry::Sample {
+                    gpu_util: msg.gpu_util,
+                    vram_used_mb: msg.vram_used_mb,
+                    vram_total_mb: msg.vram_total_mb,
+                    tps: msg.tps,
+                    temp_c: msg.temp_c,
+                };
+                if sample.is_empty() {
+                    let _ = tx.send(BackendEvent::Log(
+                        Level::Warn,
+                        format!("Bridge stats without numbers :: {}", trimmed),
+                    ));
+                } else {
+                    let _ = tx.send(BackendEvent::Telemetry(sample));
+                }
+            }
+            // Answers our ping; the reader already counted it as heard.
+            "pong" => {}
+            "error" => {
+                let text = msg
+                    .message
+                    .or(msg.text)
+                    .unwrap_or_else(|| "Unknown bridge error".into());
+                let event = match msg.id {
+                    Some(id) => BackendEvent::Failed(id, text),
+                    None => BackendEvent::Error(text),
+                };
+                let _ = tx.send(event);
+            }
+            other => {
+                let _ = tx.send(BackendEvent::Log(Level::Info, format!("Bridge> {}", other)));
+            }
+        }
+    }
+
+    fn send_prompt(&self, prompt: &Prompt) -> Result<(), String> {
+        self.send(&PromptPayload::new(prompt))
+    }
+
+    fn send_cancel(&self) -> Result<(), String> {
+        self.send(&ControlPayload { kind: "cancel" })
+    }
+
+    fn send(&self, payload: &impl Serialize) -> Result<(), String> {
+        let line = serde_json::to_string(payload)
+            .map_err(|err| err.to_string())? + "\n";
+        let mut guard = self.writer.lock();
+        guard.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
+        guard.flush().map_err(|err| err.to_string())
+    }
+
+    fn close(&self) {
+        let _ = self.writer.lock().shutdown(Shutdown::Both);
+    }
+}
+
+impl ChatBackend for RemoteBackend {
+    fn submit(&mut self, job: BackendJob) -> Result<(), BackendError> {
+        let sent = match job {
+            BackendJob::Prompt(prompt) => self.send_prompt(&prompt),
+            BackendJob::Cancel => self.send_cancel(),
+            BackendJob::Trim(keep) => self.send(&TrimPayload { kind: "trim", keep }),
+            BackendJob::SetModel(name) => self.send(&SetModelPayload {
+                kind: "set_model",
+                name: &name,
+            }),
+            _ => Ok(()),
+        };
+        sent.map_err(|err| BackendError::Lost(format!("Bridge send failed: {}", err)))
+    }
+
+    /// Closing the socket wakes the reader from `read_line`, so it ends
+    /// straight away rather than at its next read timeout.
+    fn shutdown(&mut self) {
+        self.close();
+        if let Some(reader) = self.reader.take() {
+            shutdown::join_by(reader, Instant::now() + shutdown::TIMEOUT);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(text: &str) -> Prompt {
+        Prompt {
+            text: text.to_string(),
+            params: SamplingParams::default(),
+            system: None,
+            id: None,
+            seed: None,
+            context: Vec::new(),
+        }
+    }
+
+    fn next(events: &Receiver<BackendEvent>) -> BackendEvent {
+        events
+            .recv_timeout(Duration::from_secs(5))
+            .expect("no event from the backend")
+    }
+
+    #[test]
+    fn a_cancel_cuts_the_simulated_echo_short() {
+        let (tx, events) = mpsc::channel();
+        let mut echo = simulated(tx);
+        echo.submit(BackendJob::Prompt(prompt("one two three four")))
+            .unwrap();
+        assert!(matches!(next(&events), BackendEvent::Delta(None, word) if word == "(simulated) "));
+        echo.submit(BackendJob::Cancel).unwrap();
+        assert!(matches!(next(&events), BackendEvent::Done(None, text) if text.is_empty()));
+
+        // The echo goes on to the next prompt as usual.
+        echo.submit(BackendJob::Prompt(prompt("again"))).unwrap();
+        let mut words = String::new();
+        let full = loop {
+            match next(&events) {
+                BackendEvent::Delta(None, word) => words.push_str(&word),
+                BackendEvent::Done(None, full) => break full,
+                _ => panic!("unexpected event"),
+            }
+        };
+        assert_eq!(
+            (words.as_str(), full.as_str()),
+            ("(simulated) again", "(simulated) again")
+        );
+        echo.shutdown();
+    }
+
+    /// The events `handle_line` turns `line` into.
+    fn heard(line: &str) -> Vec<BackendEvent> {
+        let (tx, events) = mpsc::channel();
+        RemoteBackend::handle_line(line, &tx);
+        drop(tx);
+        events.iter().collect()
+    }
+
+    #[test]
+    fn the_sampling_params_go_out_with_the_prompt() {
+        let mut sent = prompt("hi");
+        sent.params = SamplingParams {
+            temp: 0.5,
+            top_p: 0.9,
+            top_k: 40,
+            max_tokens: 256,
+        };
+        let line = serde_json::to_string(&PromptPayload::new(&sent)).unwrap();
+        assert_eq!(
+            line,
+            r#"{"type":"prompt","text":"hi","params":{"temp":0.5,"top_p":0.9,"top_k":40,"max_tokens":256}}"#
+        );
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        let params: SamplingParams = serde_json::from_value(value["params"].clone()).unwrap();
+        assert_eq!(params, sent.params);
+        // Settings files from before a field was added fill in its default.
+        let params: SamplingParams = serde_json::from_str(r#"{"temp":1.5}"#).unwrap();
+        assert_eq!(
+            params,
+            SamplingParams {
+                temp: 1.5,
+                ..SamplingParams::default()
+            }
+        );
+    }
+
+    #[test]
+    fn the_params_a_bridge_applied_are_reported() {
+        let events = heard(
+            r#"{"type":"done","text":"ok","params":{"temp":0.5,"max_tokens":256,"top_k":40}}"#,
+        );
+        assert!(matches!(
+            &events[..],
+            [BackendEvent::Applied(applied), BackendEvent::Done(None, text)]
+                if applied == "max_tokens=256, temp=0.5, top_k=40" && text == "ok"
+        ));
+        // A bridge that doesn't echo them just ends the reply.
+        let events = heard(r#"{"type":"done","text":"ok"}"#);
+        assert!(matches!(&events[..], [BackendEvent::Done(None, _)]));
+    }
+
+    /// A backend that answers each prompt at once with the prompt itself,
+    /// or, when `broken`, fails every job as a dropped connection would.
+    struct Echo {
+        tx: Sender<BackendEvent>,
+        broken: bool,
+    }
+
+    impl ChatBackend for Echo {
+        fn submit(&mut self, job: BackendJob) -> Result<(), BackendError> {
+            if self.broken {
+                return Err(BackendError::Lost("Bridge send failed".to_string()));
+            }
+            if let BackendJob::Prompt(prompt) = job {
+                let _ = self
+                    .tx
+                    .send(BackendEvent::Delta(prompt.id, prompt.text.clone()));
+                let _ = self.tx.send(BackendEvent::Done(prompt.id, prompt.text));
+            }
+            Ok(())
+        }
+
+        fn shutdown(&mut self) {
+            let _ = self.tx.send(BackendEvent::Status("shut down".to_string()));
+        }
+    }
+
+    /// Runs a driver for `bridge:1` whose successive connection attempts
+    /// turn out as `opens` says: an `Echo`, broken or not, or an error.
+    fn drive(
+        fallback: Fallback,
+        opens: Vec<Result<bool, BackendError>>,
+    ) -> (JobTx, Receiver<BackendEvent>, thread::JoinHandle<()>) {
+        let (job_tx, jobs) = mpsc::channel();
+        let (tx, events) = mpsc::channel();
+        let mut opens = VecDeque::from(opens);
+        let echo_tx = tx.clone();
+        let open: Opener = Box::new(move |_, _| {
+            let broken = opens
+                .pop_front()
+                .unwrap_or_else(|| Err(BackendError::Lost("out of script".to_string())))?;
+            let echo = Echo {
+                tx: echo_tx.clone(),
+                broken,
+            };
+            Ok(Box::new(echo) as Box<dyn ChatBackend>)
+        });
+        let target = Target::Bridge {
+            addr: "bridge:1".to_string(),
+            token: None,
+        };
+        let driver = thread::spawn(move || Driver::new(target, fallback, open, jobs, tx).run());
+        (job_tx, events, driver)
+    }
+
+    fn describe(event: &BackendEvent) -> String {
+        match event {
+            BackendEvent::Log(_, text) => format!("log: {}", text),
+            BackendEvent::Status(text) => format!("status: {}", text),
+            BackendEvent::Delta(_, text) => format!("delta: {}", text),
+            BackendEvent::Done(_, text) => format!("done: {}", text),
+            BackendEvent::Error(text) => format!("error: {}", text),
+            BackendEvent::Queued(count) => format!("queued: {}", count),
+            BackendEvent::Connected(addr) => format!("connected: {}", addr),
+            BackendEvent::Link(link) => format!(
+                "link: {}",
+                match link {
+                    Link::Online => "online",
+                    Link::Offline => "offline",
+                    Link::Simulated => "simulated",
+                    Link::Handshaking => "handshaking",
+                    Link::Refused => "refused",
+                }
+            ),
+            _ => "other".to_string(),
+        }
+    }
+
+    /// The events up to and including `last`, which must come within a few
+    /// seconds.
+    fn until(events: &Receiver<BackendEvent>, last: &str) -> Vec<String> {
+        let mut seen = Vec::new();
+        while seen.last().map(String::as_str) != Some(last) {
+            seen.push(describe(&next(events)));
+        }
+        seen
+    }
+
+    fn quit(jobs: JobTx, driver: thread::JoinHandle<()>) {
+        jobs.send(BackendJob::Quit).unwrap();
+        assert!(shutdown::join_by(
+            driver,
+            Instant::now() + shutdown::TIMEOUT
+        ));
+    }
+
+    const CONNECTING: [&str; 2] = [
+        "link: handshaking",
+        "status: Connecting to Noctics bridge at bridge:1…",
+    ];
+
+    #[test]
+    fn a_prompt_goes_to_the_connected_backend() {
+        let (jobs, events, driver) = drive(Fallback::Retry, vec![Ok(false)]);
+        jobs.send(BackendJob::Prompt(prompt("hi"))).unwrap();
+        assert_eq!(
+            until(&events, "done: hi"),
+            [
+                CONNECTING[0],
+                CONNECTING[1],
+                "connected: bridge:1",
+                "link: online",
+                "delta: hi",
+                "done: hi",
+            ]
+        );
+        quit(jobs, driver);
+        assert_eq!(describe(&next(&events)), "status: shut down");
+    }
+
+    #[test]
+    fn a_lost_backend_keeps_the_prompt_and_retries() {
+        let (jobs, events, driver) = drive(Fallback::Retry, vec![Ok(true), Ok(false)]);
+        jobs.send(BackendJob::Prompt(prompt("hi"))).unwrap();
+        let seen = until(&events, "done: hi");
+        assert_eq!(
+            seen[..9],
+            [
+                CONNECTING[0],
+                CONNECTING[1],
+                "connected: bridge:1",
+                "link: online",
+                "queued: 1",
+                "status: shut down",
+                "error: Bridge send failed",
+                "link: offline",
+                "status: Bridge offline (Bridge send failed). Retrying bridge:1 in 1s…",
+            ]
+        );
+        assert_eq!(
+            seen[seen.len() - 6..],
+            [
+                CONNECTING[0],
+                CONNECTING[1],
+                "connected: bridge:1",
+                "link: online",
+                "delta: hi",
+                "done: hi",
+            ]
+        );
+        assert_eq!(describe(&next(&events)), "queued: 0");
+        quit(jobs, driver);
+    }
+
+    #[test]
+    fn an_unreachable_bridge_can_fall_back_to_the_echo() {
+        let unreachable = || Err(BackendError::Lost("Connection refused".to_string()));
+        let (jobs, events, driver) = drive(Fallback::Simulated, vec![unreachable()]);
+        jobs.send(BackendJob::Prompt(prompt("hi"))).unwrap();
+        assert_eq!(
+            until(&events, "done: (simulated) hi"),
+            [
+                CONNECTING[0],
+                CONNECTING[1],
+                "status: Bridge offline (Connection refused). Using simulated echo; \
+                 reconnect to try again.",
+                "link: simulated",
+                "delta: (simulated) ",
+                "delta: hi",
+                "done: (simulated) hi",
+            ]
+        );
+        quit(jobs, driver);
+    }
+
+    #[test]
+    fn a_refusing_bridge_holds_prompts_without_retrying() {
+        let refused = Err(BackendError::Refused("Bad token".to_string()));
+        let (jobs, events, driver) = drive(Fallback::Simulated, vec![refused]);
+        jobs.send(BackendJob::Prompt(prompt("hi"))).unwrap();
+        assert_eq!(
+            until(&events, "queued: 1"),
+            [
+                CONNECTING[0],
+                CONNECTING[1],
+                "link: refused",
+                "error: Bad token",
+                "status: Bad token (sending is off until a reconnect gets through)",
+                "queued: 1",
+            ]
+        );
+        assert!(events.recv_timeout(INITIAL_BACKOFF * 2).is_err());
+        quit(jobs, driver);
+    }
+
+    #[test]
+    fn pongs_stay_out_of_the_log() {
+        assert!(heard(r#"{"type":"pong","id":3}"#).is_empty());
+        let ping = serde_json::to_string(&PingPayload {
+            kind: "ping",
+            id: 3,
+        })
+        .unwrap();
+        assert_eq!(ping, r#"{"type":"ping","id":3}"#);
+    }
+
+    #[test]
+    fn the_system_prompt_is_sent_only_when_set() {
+        let line = |system: Option<&str>| {
+            let mut sent = prompt("hi");
+            sent.system = system.map(str::to_string);
+            serde_json::to_value(PromptPayload::new(&sent)).unwrap()
+        };
+        assert_eq!(line(Some("Be brief."))["system"], "Be brief.");
+        let bare = line(None);
+        assert!(bare.get("system").is_none());
+        assert_eq!(
+            bare.as_object().unwrap().keys().collect::<Vec<_>>(),
+            ["params", "text", "type"]
+        );
+    }
+
+    #[test]
+    fn stats_messages_become_telemetry() {
+        let events =
+            heard(r#"{"type":"stats","gpu_util":87,"vram_used_mb":3120.5,"temp_c":"hot"}"#);
+        let [BackendEvent::Telemetry(sample)] = &events[..] else {
+            panic!("no telemetry");
+        };
+        assert_eq!(
+            *sample,
+            telemetry::Sample {
+                gpu_util: Some(87.0),
+                vram_used_mb: Some(3120.5),
+                ..telemetry::Sample::default()
+            }
+        );
+        // Nothing usable in it is a warning, not a sample.
+        assert!(matches!(
+            &heard(r#"{"type":"stats","tps":null}"#)[..],
+            [BackendEvent::Log(Level::Warn, _)]
+        ));
+        // The numeric fields don't get in the way of other messages.
+        assert!(matches!(
+            &heard(r#"{"type":"delta","text":"hi","tps":"n/a"}"#)[..],
+            [BackendEvent::Delta(None, text)] if text == "hi"
+        ));
+    }
+
+    #[test]
+    fn attachments_go_out_as_context() {
+        let mut sent = prompt("Summarise these");
+        sent.context = vec![
+            Attachment {
+                name: "notes.md".to_string(),
+                text: "# Notes".to_string(),
+            },
+            Attachment {
+                name: "data.csv".to_string(),
+                text: "a,b\n1,2\n".to_string(),
+            },
+        ];
+        let line = serde_json::to_value(PromptPayload::new(&sent)).unwrap();
+        assert_eq!(
+            line["context"],
+            serde_json::json!([
+                {"name": "notes.md", "text": "# Notes"},
+                {"name": "data.csv", "text": "a,b\n1,2\n"},
+            ])
+        );
+        assert_eq!(line["text"], "Summarise these");
+        assert!(serde_json::to_value(PromptPayload::new(&prompt("hi")))
+            .unwrap()
+            .get("context")
+            .is_none());
+    }
+
+    #[test]
+    fn quitting_mid_stream_ends_both_threads_in_time() {
+        let (job_tx, jobs) = mpsc::channel();
+        let (tx, events) = mpsc::channel();
+        let (ended_tx, ended) = mpsc::channel();
+        let stream_tx = tx.clone();
+        // Streams each prompt a word every 50ms, far longer than the test
+        // waits, minding only `Quit` between words.
+        let open: Opener = Box::new(move |_, _| {
+            let tx = stream_tx.clone();
+            let ended_tx = ended_tx.clone();
+            let worker = Worker::spawn(move |jobs| {
+                while let Ok(BackendJob::Prompt(prompt)) = jobs.recv() {
+                    for _ in 0..1000 {
+                        match jobs.recv_timeout(Duration::from_millis(50)) {
+                            Ok(BackendJob::Quit) | Err(RecvTimeoutError::Disconnected) => {
+                                let _ = ended_tx.send(());
+                                return;
+                            }
+                            _ => {}
+                        }
+                        let _ = tx.send(BackendEvent::Delta(prompt.id, "word ".to_string()));
+                    }
+                }
+                let _ = ended_tx.send(());
+            });
+            Ok(Box::new(worker) as Box<dyn ChatBackend>)
+        });
+        let target = Target::Bridge {
+            addr: "bridge:1".to_string(),
+            token: None,
+        };
+        let driver =
+            thread::spawn(move || Driver::new(target, Fallback::Retry, open, jobs, tx).run());
+        job_tx.send(BackendJob::Prompt(prompt("go on"))).unwrap();
+        until(&events, "delta: word ");
+
+        let asked = Instant::now();
+        job_tx.send(BackendJob::Quit).unwrap();
+        assert!(shutdown::join_by(driver, asked + shutdown::TIMEOUT));
+        // The driver waits for its backend's thread before it ends.
+        assert_eq!(ended.try_recv(), Ok(()));
+        assert!(asked.elapsed() < shutdown::TIMEOUT);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+use crate::log::{LogEntry, LogRole};
+use crate::stats::estimate_tokens;
+
+/// The context window assumed until one is set.
+pub const DEFAULT_SIZE: usize = 4096;
+
+/// Past this share of the window the meter turns amber.
+pub const WARN: f32 = 0.75;
+
+/// Past this share it turns red.
+pub const DANGER: f32 = 0.9;
+
+/// Trimming drops exchanges until the estimate is under this share.
+pub const TRIM_TO: f32 = 0.6;
+
+/// Whether the model saw an entry as part of the conversation. Failed
+/// replies never reached it, and notes are the console's own; the system
+/// prompt is counted once, as it is now, rather than per entry.
+fn counts(entry: &LogEntry) -> bool {
+    matches!(
+        entry.role,
+        LogRole::User | LogRole::Assistant | LogRole::Stopped
+    )
+}
+
+fn tokens(entry: &LogEntry) -> usize {
+    estimate_tokens(entry.text.chars().count())
+}
+
+/// About how many tokens the conversation in `log` takes up under `system`.
+pub fn estimate(log: &VecDeque<LogEntry>, system: &str) -> usize {
+    let turns: usize = log.iter().filter(|entry| counts(entry)).map(tokens).sum();
+    estimate_tokens(system.chars().count()) + turns
+}
+
+/// Takes the oldest exchanges, each a prompt and the replies after it, out
+/// of `log` until it estimates under [`TRIM_TO`] of `size`, and puts a note
+/// where they were. System prompts and notes stay. Returns how many
+/// exchanges went.
+pub fn trim(log: &mut VecDeque<LogEntry>, system: &str, size: usize) -> usize {
+    let target = (size as f32 * TRIM_TO) as usize;
+    let mut total = estimate(log, system);
+    let mut dropped = 0;
+    let mut at = None;
+    while total >= target {
+        let Some(start) = log.iter().position(|entry| entry.role == LogRole::User) else {
+            break;
+        };
+        if let Some(prompt) = log.remove(start) {
+            total = total.saturating_sub(tokens(&prompt));
+        }
+        // Its replies, up to the next prompt; anything else in between stays.
+        let mut idx = start;
+        while let Some(entry) = log.get(idx) {
+            if entry.role == LogRole::User {
+                break;
+            }
+            if !entry.role.is_reply() {
+                idx += 1;
+            } else if let Some(reply) = log.remove(idx) {
+                if counts(&reply) {
+                    total = total.saturating_sub(tokens(&reply));
+                }
+            }
+        }
+        dropped += 1;
+        at.get_or_insert(start);
+    }
+    if let Some(at) = at {
+        let note = format!("✂ Trimmed {} oldest exchange(s) to fit the context window", dropped);
+        log.insert(at, LogEntry::from(note));
+    }
+    dropped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(role: LogRole, chars: usize) -> LogEntry {
+        LogEntry::new(role, "x".repeat(chars))
+    }
+
+    /// `n` exchanges of a 40-token prompt and a 60-token reply.
+    fn conversation(n: usize) -> VecDeque<LogEntry> {
+        (0..n)
+            .flat_map(|_| [entry(LogRole::User, 160), entry(LogRole::Assistant, 240)])
+            .collect()
+    }
+
+    #[test]
+    fn the_estimate_counts_what_the_model_saw() {
+        let mut log = conversation(2);
+        log.push_back(entry(LogRole::Failed, 400));
+        log.push_back(LogEntry::from("note".to_string()));
+        log.push_back(entry(LogRole::System { updated: false }, 400));
+        log.push_back(entry(LogRole::Stopped, 3));
+        assert_eq!(estimate(&log, ""), 201);
+        assert_eq!(estimate(&log, "Be brief, please."), 206);
+    }
+
+    #[test]
+    fn trimming_drops_the_oldest_exchanges_under_the_target() {
+        let mut log = conversation(10);
+        log.push_front(entry(LogRole::System { updated: false }, 8));
+        // 1000 tokens against a 1000-token window: down to under 600.
+        assert_eq!(trim(&mut log, "", 1000), 5);
+        assert_eq!(estimate(&log, ""), 500);
+        assert_eq!(log[0].role, LogRole::System { updated: false });
+        assert_eq!(log[1].role, LogRole::Note);
+        assert!(log[1].text.contains("Trimmed 5 oldest"));
+        assert_eq!(log[2].role, LogRole::User);
+        assert_eq!(log.len(), 12);
+    }
+
+    #[test]
+    fn an_exchange_goes_with_all_its_replies() {
+        let mut log = conversation(1);
+        log.push_back(LogEntry::from("aside".to_string()));
+        log.push_back(entry(LogRole::Failed, 40));
+        log.extend(conversation(1));
+        assert_eq!(trim(&mut log, "", 300), 1);
+        let roles: Vec<LogRole> = log.iter().map(|entry| entry.role).collect();
+        assert_eq!(
+            roles,
+            [
+                LogRole::Note,
+                LogRole::Note,
+                LogRole::User,
+                LogRole::Assistant
+            ]
+        );
+        assert_eq!(log[1].text, "aside");
+    }
+
+    #[test]
+    fn nothing_is_trimmed_under_the_target() {
+        let mut log = conversation(2);
+        assert_eq!(trim(&mut log, "", DEFAULT_SIZE), 0);
+        assert_eq!(log.len(), 4);
+    }
+}
@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::session;
+
+/// How many entries a tab keeps before dropping the oldest.
+pub const MAX: usize = 2000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub const ALL: [Level; 3] = [Level::Info, Level::Warn, Level::Error];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+pub struct Entry {
+    pub level: Level,
+    pub text: String,
+    /// Unix time in milliseconds.
+    pub ts: u64,
+}
+
+/// What a tab's backend said on the side: bridge logs, lines that didn't
+/// parse, warnings, and errors, kept out of the conversation.
+#[derive(Default)]
+pub struct Diagnostics {
+    entries: VecDeque<Entry>,
+    /// Entries since the drawer was last open.
+    pub unseen: usize,
+}
+
+/// How the drawer was left, saved in the settings file.
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct View {
+    pub open: bool,
+    /// The least severe level shown.
+    pub level: Level,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, level: Level, text: impl Into<String>) {
+        self.entries.push_back(Entry {
+            level,
+            text: text.into(),
+            ts: session::now_ms(),
+        });
+        while self.entries.len() > MAX {
+            self.entries.pop_front();
+        }
+        self.unseen += 1;
+    }
+
+    /// The entries at `level` or worse, oldest first.
+    pub fn shown(&self, level: Level) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .filter(move |entry| entry.level >= level)
+    }
+
+    /// Those entries as text, one per line, for the clipboard.
+    pub fn text(&self, level: Level) -> String {
+        self.shown(level)
+            .map(Entry::line)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+impl Entry {
+    /// `14:22:33 warn  Bridge parse error: …`
+    pub fn line(&self) -> String {
+        let stamp = session::utc_stamp(self.ts / 1000);
+        format!(
+            "{}:{}:{} {:<5} {}",
+            &stamp[9..11],
+            &stamp[11..13],
+            &stamp[13..15],
+            self.level.name(),
+            self.text
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_entries_go_past_the_cap() {
+        let mut diagnostics = Diagnostics::default();
+        for n in 0..MAX + 5 {
+            diagnostics.push(Level::Info, n.to_string());
+        }
+        let shown: Vec<&str> = diagnostics
+            .shown(Level::Info)
+            .map(|entry| entry.text.as_str())
+            .collect();
+        assert_eq!(shown.len(), MAX);
+        assert_eq!(shown[0], "5");
+        assert_eq!(diagnostics.unseen, MAX + 5);
+    }
+
+    #[test]
+    fn the_filter_shows_that_level_and_worse() {
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.push(Level::Info, "connected");
+        diagnostics.push(Level::Error, "lost");
+        diagnostics.push(Level::Warn, "parse error");
+        let texts = |level| {
+            diagnostics
+                .shown(level)
+                .map(|entry| entry.text.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(texts(Level::Info), ["connected", "lost", "parse error"]);
+        assert_eq!(texts(Level::Warn), ["lost", "parse error"]);
+        assert_eq!(texts(Level::Error), ["lost"]);
+    }
+
+    #[test]
+    fn copied_lines_have_time_level_and_text() {
+        let entry = Entry {
+            level: Level::Warn,
+            text: "Bridge parse error".to_string(),
+            ts: 1_792_160_553_000,
+        };
+        assert_eq!(entry.line(), "14:22:33 warn  Bridge parse error");
+        let view: View = serde_json::from_str(r#"{"level":"error"}"#).unwrap();
+        assert!(
+            view == View {
+                open: false,
+                level: Level::Error
+            }
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::markdown::fence;
+use crate::session::{self, Record, Role};
+use crate::stats::Stats;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Markdown,
+    Json,
+}
+
+impl Format {
+    fn ext(self) -> &'static str {
+        match self {
+            Format::Markdown => "md",
+            Format::Json => "json",
+        }
+    }
+
+    /// `records` followed by `partial`, the reply still streaming, which is
+    /// flagged as incomplete.
+    pub fn render(self, records: &[Record], partial: Option<&Record>) -> String {
+        match self {
+            Format::Markdown => markdown(records, partial),
+            Format::Json => json(records, partial),
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/noctics/exports`, next to the sessions.
+pub fn default_dir() -> Option<PathBuf> {
+    Some(session::data_dir()?.join("exports"))
+}
+
+/// One `**You:**` / `**Nox:**` block per message, `**Nox · model:**` when
+/// the reply's model is known, and a finished reply's numbers in italics
+/// after it. Text goes through as is, code fences included; a fence left
+/// open (say by a reply cut off mid-block) is closed so it doesn't swallow
+/// the blocks after it.
+pub fn markdown(records: &[Record], partial: Option<&Record>) -> String {
+    let blocks: Vec<String> = records
+        .iter()
+        .map(|record| (record, false))
+        .chain(partial.map(|record| (record, true)))
+        .map(|(record, incomplete)| {
+            let name = match (record.role, record.stopped, incomplete) {
+                (Role::User, _, _) => "You",
+                (Role::System, _, _) => "System",
+                (Role::Assistant, _, true) => "Nox (incomplete)",
+                (Role::Assistant, true, false) => "Nox (stopped)",
+                (Role::Assistant, false, false) => "Nox",
+            };
+            let label = match &record.model {
+                Some(model) => format!("**{} · {}:**", name, model),
+                None => format!("**{}:**", name),
+            };
+            let text = record.text.trim_end();
+            let mut block = if text.contains('\n') || fence(text).is_some() {
+                format!("{}\n\n{}", label, text)
+            } else {
+                format!("{} {}", label, text)
+            };
+            if let Some(open) = open_fence(text) {
+                block.push('\n');
+                block.push_str(&open);
+            }
+            if let Some(stats) = &record.stats {
+                let stats = Stats {
+                    model: record.model.clone(),
+                    ..stats.clone()
+                };
+                block.push_str(&format!("\n\n_{}_", stats.footer()));
+            }
+            block
+        })
+        .collect();
+    let mut out = blocks.join("\n\n");
+    out.push('\n');
+    out
+}
+
+#[derive(Serialize)]
+struct Entry<'a> {
+    role: Role,
+    text: &'a str,
+    ts: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stopped: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    incomplete: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    model: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stats: Option<&'a Stats>,
+}
+
+/// An array of `{role, text, ts}`, with `stopped` or `incomplete` set on
+/// replies that didn't finish, `model` on those the bridge named one for,
+/// and `stats` on finished ones.
+pub fn json(records: &[Record], partial: Option<&Record>) -> String {
+    let entries: Vec<Entry> = records
+        .iter()
+        .map(|record| (record, false))
+        .chain(partial.map(|record| (record, true)))
+        .map(|(record, incomplete)| Entry {
+            role: record.role,
+            text: &record.text,
+            ts: record.ts,
+            stopped: record.stopped,
+            incomplete,
+            model: record.model.as_deref(),
+            stats: record.stats.as_ref(),
+        })
+        .collect();
+    let mut out = serde_json::to_string_pretty(&entries).unwrap_or_else(|_| "[]".to_string());
+    out.push('\n');
+    out
+}
+
+/// Writes `contents` to `dir/noctics-<UTC stamp>.<ext>` and returns the path.
+pub fn save(dir: &Path, format: Format, contents: &str) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    let stamp = session::utc_stamp(session::now_ms() / 1000);
+    let mut path = dir.join(format!("noctics-{}.{}", stamp, format.ext()));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("noctics-{}-{}.{}", stamp, n, format.ext()));
+    }
+    fs::write(&path, contents).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(path)
+}
+
+/// The fence still open at the end of `text`, if any.
+fn open_fence(text: &str) -> Option<String> {
+    let mut open: Option<&str> = None;
+    for line in text.lines() {
+        let Some(marker) = fence(line) else {
+            continue;
+        };
+        match open {
+            None => open = Some(marker),
+            // A closing fence is a bare run of the same character, at least
+            // as long as the opening one.
+            Some(current) if marker.starts_with(current) && line.trim() == marker => open = None,
+            Some(_) => {}
+        }
+    }
+    open.map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(role: Role, text: &str, ts: u64) -> Record {
+        Record {
+            ts,
+            ..Record::new(role, text)
+        }
+    }
+
+    /// A conversation with a code fence, a long line, and some unicode, and
+    /// a reply cut off inside a fence of its own.
+    fn fixture() -> (Vec<Record>, Record) {
+        let long = "word ".repeat(60);
+        let records = vec![
+            record(Role::User, "Show me a loop — in Rust 🦀", 1),
+            record(
+                Role::Assistant,
+                "Here:\n\n```rust\nfor i in 0..3 {\n    println!(\"{i}\");\n}\n```\n\nDone ✓",
+                2,
+            ),
+            record(Role::User, long.trim_end(), 3),
+        ];
+        let partial = record(Role::Assistant, "```text\nhalf a blo", 4);
+        (records, partial)
+    }
+
+    #[test]
+    fn markdown_keeps_fences_and_closes_an_open_one() {
+        let (records, partial) = fixture();
+        let long = "word ".repeat(60);
+        let want = format!(
+            "**You:** Show me a loop — in Rust 🦀\n\n\
+             **Nox:**\n\nHere:\n\n```rust\nfor i in 0..3 {{\n    println!(\"{{i}}\");\n}}\n```\n\nDone ✓\n\n\
+             **You:** {}\n\n\
+             **Nox (incomplete):**\n\n```text\nhalf a blo\n```\n",
+            long.trim_end()
+        );
+        assert_eq!(markdown(&records, Some(&partial)), want);
+    }
+
+    #[test]
+    fn json_lists_role_text_and_time() {
+        let (records, partial) = fixture();
+        let out = json(&records, Some(&partial));
+        let entries: serde_json::Value = serde_json::from_str(&out).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(
+            entries[0],
+            serde_json::json!({"role": "user", "text": "Show me a loop — in Rust 🦀", "ts": 1})
+        );
+        assert_eq!(entries[1]["text"], records[1].text.as_str());
+        assert_eq!(entries[2]["text"].as_str().unwrap().len(), 299);
+        assert_eq!(entries[3]["incomplete"], true);
+        assert!(entries[..3]
+            .iter()
+            .all(|entry| entry.get("incomplete").is_none()));
+    }
+
+    #[test]
+    fn saves_never_overwrite() {
+        let dir = std::env::temp_dir().join(format!("noctics-export-{}", std::process::id()));
+        let first = save(&dir, Format::Markdown, "one").unwrap();
+        let second = save(&dir, Format::Markdown, "two").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(second.extension().unwrap(), "md");
+        assert_eq!(fs::read_to_string(&first).unwrap(), "one");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "two");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn exports_carry_the_reply_stats() {
+        let stats = Stats {
+            ttft: Some(std::time::Duration::from_millis(143)),
+            tokens: 212,
+            estimated: false,
+            tok_per_s: Some(58.0),
+            model: None,
+            seed: Some(42),
+            raw: None,
+        };
+        let reply = Record {
+            model: Some("nox".to_string()),
+            stats: Some(stats),
+            ..record(Role::Assistant, "Hi.", 2)
+        };
+        let records = [record(Role::User, "Hello", 1), reply];
+        assert_eq!(
+            markdown(&records, None),
+            "**You:** Hello\n\n\
+             **Nox · nox:** Hi.\n\n_⟨nox · 143 ms TTFT · 58 tok/s · 212 tokens · seed 42⟩_\n"
+        );
+        let entries: serde_json::Value = serde_json::from_str(&json(&records, None)).unwrap();
+        assert_eq!(entries[0].get("stats"), None);
+        assert_eq!(entries[1]["model"], "nox");
+        assert_eq!(
+            entries[1]["stats"],
+            serde_json::json!({"ttft_ms": 143, "tokens": 212, "tok_per_s": 58.0, "seed": 42})
+        );
+    }
+}
@@ -0,0 +1,107 @@
+/// How far above the bottom the log can be scrolled and still count as at
+/// the bottom, in points.
+pub const SLACK: f32 = 24.0;
+
+/// Whether a tab's log keeps to the newest output. Scrolling up away from
+/// the bottom pauses it and counts what arrives meanwhile; scrolling back
+/// down, or [`resume`](Self::resume), picks it up again. It only sees
+/// numbers, so it works the same without a window.
+#[derive(Default)]
+pub struct Follow {
+    paused: bool,
+    unseen: usize,
+    /// Resumed from away from the bottom, so the log has to be taken there.
+    jump: bool,
+    /// On the way back down after a jump; nothing pauses it until it gets
+    /// there.
+    settling: bool,
+}
+
+impl Follow {
+    /// Whether the log should stick to the bottom this frame.
+    pub fn sticks(&self) -> bool {
+        !self.paused
+    }
+
+    /// `count` entries or deltas came in.
+    pub fn arrived(&mut self, count: usize) {
+        if self.paused {
+            self.unseen += count;
+        }
+    }
+
+    /// Where the view ended up after a frame: `from_bottom` is how far its
+    /// bottom edge is above the end of the log.
+    pub fn scrolled(&mut self, from_bottom: f32) {
+        if self.settling {
+            self.settling = from_bottom > SLACK;
+        } else if from_bottom > SLACK {
+            self.paused = true;
+        } else if self.paused {
+            self.paused = false;
+            self.unseen = 0;
+        }
+    }
+
+    /// Sticks to the bottom again, wherever the view is.
+    pub fn resume(&mut self) {
+        self.paused = false;
+        self.unseen = 0;
+        self.jump = true;
+        self.settling = true;
+    }
+
+    /// What came in while paused, or `None` when following.
+    pub fn unseen(&self) -> Option<usize> {
+        self.paused.then_some(self.unseen)
+    }
+
+    /// Whether to scroll to the bottom this frame, once.
+    pub fn take_jump(&mut self) -> bool {
+        std::mem::take(&mut self.jump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolling_up_pauses_and_counts() {
+        let mut follow = Follow::default();
+        follow.arrived(3);
+        follow.scrolled(SLACK);
+        assert!(follow.sticks());
+        assert_eq!(follow.unseen(), None);
+
+        follow.scrolled(SLACK + 1.0);
+        assert!(!follow.sticks());
+        follow.arrived(2);
+        follow.arrived(1);
+        assert_eq!(follow.unseen(), Some(3));
+
+        // Scrolling back down picks it up again.
+        follow.scrolled(0.0);
+        assert!(follow.sticks());
+        assert_eq!(follow.unseen(), None);
+        assert!(!follow.take_jump());
+    }
+
+    #[test]
+    fn resuming_jumps_to_the_bottom_once() {
+        let mut follow = Follow::default();
+        follow.scrolled(500.0);
+        follow.arrived(4);
+        follow.resume();
+        assert!(follow.sticks());
+        assert_eq!(follow.unseen(), None);
+        assert!(follow.take_jump());
+        assert!(!follow.take_jump());
+        // Still far from the bottom on the way down: not a manual scroll.
+        follow.scrolled(300.0);
+        assert!(follow.sticks());
+        follow.scrolled(0.0);
+        follow.scrolled(100.0);
+        assert!(!follow.sticks());
+    }
+}
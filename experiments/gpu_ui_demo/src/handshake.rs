@@ -0,0 +1,232 @@
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+/// The bridge protocol versions this console speaks.
+pub const PROTOCOLS: &[u64] = &[1];
+
+/// How long the bridge has to say hello after we connect.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the opening exchange with a bridge stands: we may have sent `auth`,
+/// and nothing else goes out until the bridge answers `hello`. Lines are fed
+/// in as they arrive, and the clock is passed in, so it runs the same over a
+/// socket as over a script.
+pub struct Handshake {
+    deadline: Instant,
+    /// Lines that came before `hello`, handled as usual once it does.
+    held: Vec<String>,
+}
+
+pub enum Step {
+    /// Still waiting for `hello`.
+    Waiting,
+    Ready(Hello),
+    /// The bridge turned us away; reconnecting as we are won't help.
+    Refused(String),
+}
+
+/// What the bridge said about itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct Hello {
+    pub protocol: Option<u64>,
+    pub message: Option<String>,
+    /// The model it runs, as `active` or, from older bridges, `model`.
+    pub model: Option<String>,
+    /// The models it can switch to; empty when it didn't say.
+    pub models: Vec<String>,
+}
+
+impl Handshake {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            deadline: now + TIMEOUT,
+            held: Vec::new(),
+        }
+    }
+
+    /// Takes one line from the bridge.
+    pub fn line(&mut self, line: &str) -> Step {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return Step::Waiting;
+        }
+        let Ok(Value::Object(msg)) = serde_json::from_str::<Value>(trimmed) else {
+            // Reported by the usual handling once the bridge is up.
+            self.held.push(trimmed.to_string());
+            return Step::Waiting;
+        };
+        let text = |key: &str| msg.get(key).and_then(Value::as_str).map(str::to_string);
+        match msg.get("type").and_then(Value::as_str) {
+            Some("hello") => Step::Ready(Hello {
+                protocol: msg.get("protocol").and_then(Value::as_u64),
+                message: text("message"),
+                model: text("active")
+                    .or_else(|| text("model"))
+                    .filter(|model| !model.trim().is_empty()),
+                models: msg
+                    .get("models")
+                    .and_then(Value::as_array)
+                    .map(|models| {
+                        models
+                            .iter()
+                            .filter_map(Value::as_str)
+                            .filter(|model| !model.trim().is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            }),
+            Some("auth_error") => Step::Refused(match text("message") {
+                Some(message) => format!("Bridge rejected the token: {}", message),
+                None => "Bridge rejected the token".to_string(),
+            }),
+            _ => {
+                self.held.push(trimmed.to_string());
+                Step::Waiting
+            }
+        }
+    }
+
+    /// Why the handshake failed, once `now` is past the deadline.
+    pub fn expired(&self, now: Instant) -> Option<String> {
+        (now >= self.deadline).then(|| {
+            format!(
+                "No hello from the bridge within {}s; is it a Noctics bridge?",
+                TIMEOUT.as_secs()
+            )
+        })
+    }
+
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// The lines that arrived before `hello`.
+    pub fn held(self) -> Vec<String> {
+        self.held
+    }
+}
+
+impl Hello {
+    /// `Noctics bridge ready · model qwen2.5`, for the status bar.
+    pub fn status(&self) -> String {
+        let mut parts = vec![self
+            .message
+            .clone()
+            .unwrap_or_else(|| "Bridge ready".to_string())];
+        if let Some(model) = &self.model {
+            parts.push(format!("model {}", model));
+        }
+        parts.join(" · ")
+    }
+
+    /// A warning when the bridge speaks a protocol this console doesn't.
+    pub fn mismatch(&self) -> Option<String> {
+        let known = PROTOCOLS
+            .iter()
+            .map(u64::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self.protocol {
+            Some(protocol) if PROTOCOLS.contains(&protocol) => None,
+            Some(protocol) => Some(format!(
+                "Bridge speaks protocol {}; this console knows {}. Replies may go astray.",
+                protocol, known
+            )),
+            None => Some(format!(
+                "Bridge did not say which protocol it speaks; this console knows {}.",
+                known
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ready(step: Step) -> Hello {
+        match step {
+            Step::Ready(hello) => hello,
+            _ => panic!("no hello"),
+        }
+    }
+
+    #[test]
+    fn lines_before_hello_are_held_for_later() {
+        let mut state = Handshake::new(Instant::now());
+        assert!(matches!(
+            state.line(r#"{"type":"log","text":"warming up"}"#),
+            Step::Waiting
+        ));
+        assert!(matches!(state.line("\n"), Step::Waiting));
+        assert!(matches!(state.line("not json"), Step::Waiting));
+        assert!(matches!(state.line("[1, 2]"), Step::Waiting));
+        let hello = ready(state.line(
+            r#"{"type":"hello","protocol":1,"message":"Noctics bridge ready","model":"qwen2.5"}"#,
+        ));
+        assert_eq!(hello.status(), "Noctics bridge ready · model qwen2.5");
+        assert_eq!(hello.mismatch(), None);
+        assert_eq!(
+            state.held(),
+            [
+                r#"{"type":"log","text":"warming up"}"#,
+                "not json",
+                "[1, 2]"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_rejected_token_refuses() {
+        let mut state = Handshake::new(Instant::now());
+        assert!(matches!(
+            state.line(r#"{"type":"auth_error","message":"expired"}"#),
+            Step::Refused(reason) if reason == "Bridge rejected the token: expired"
+        ));
+        assert!(matches!(
+            state.line(r#"{"type":"auth_error"}"#),
+            Step::Refused(reason) if reason == "Bridge rejected the token"
+        ));
+    }
+
+    #[test]
+    fn a_malformed_hello_takes_what_it_can() {
+        let mut state = Handshake::new(Instant::now());
+        let hello = ready(state.line(
+            r#"{"type":"hello","protocol":"1","active":" ","model":"old","models":["a",2,"","b"]}"#,
+        ));
+        assert_eq!(
+            hello,
+            Hello {
+                protocol: None,
+                message: None,
+                model: None,
+                models: vec!["a".to_string(), "b".to_string()],
+            }
+        );
+        assert_eq!(hello.status(), "Bridge ready");
+        assert!(hello.mismatch().unwrap().starts_with("Bridge did not say"));
+        let hello = ready(state.line(r#"{"type":"hello","protocol":2,"model":"old"}"#));
+        assert_eq!(hello.model.as_deref(), Some("old"));
+        assert_eq!(
+            hello.mismatch().unwrap(),
+            "Bridge speaks protocol 2; this console knows 1. Replies may go astray."
+        );
+    }
+
+    #[test]
+    fn the_bridge_has_five_seconds() {
+        let start = Instant::now();
+        let state = Handshake::new(start);
+        assert_eq!(state.deadline(), start + TIMEOUT);
+        assert_eq!(
+            state.expired(start + TIMEOUT - Duration::from_millis(1)),
+            None
+        );
+        assert!(state
+            .expired(start + TIMEOUT)
+            .unwrap()
+            .contains("within 5s"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+/// How long the bridge may be quiet before we ping it.
+pub const INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long a ping waits for its pong.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pings in a row that go unanswered before the connection counts as dead.
+pub const MISSES: u32 = 2;
+
+/// Keeps track of whether a quiet bridge is still there. Anything the bridge
+/// sends counts as a sign of life, deltas included, so no ping goes out
+/// while a reply is streaming. Every call takes the time it is for, so it
+/// runs the same over a socket as over a script.
+pub struct Heartbeat {
+    heard: Instant,
+    /// The ping we are waiting on, and when it went out.
+    pending: Option<(u64, Instant)>,
+    next_id: u64,
+    missed: u32,
+}
+
+pub enum Beat {
+    /// Nothing to do yet.
+    Idle,
+    /// Send `{"type":"ping","id":..}` now.
+    Ping(u64),
+    /// Too many pings went unanswered.
+    Dead,
+}
+
+impl Heartbeat {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            heard: now,
+            pending: None,
+            next_id: 1,
+            missed: 0,
+        }
+    }
+
+    /// The bridge said something: a pong, a delta, anything.
+    pub fn heard(&mut self, now: Instant) {
+        self.heard = now;
+        self.pending = None;
+        self.missed = 0;
+    }
+
+    /// What to do at `now`.
+    pub fn tick(&mut self, now: Instant) -> Beat {
+        if let Some((_, sent)) = self.pending {
+            if now < sent + TIMEOUT {
+                return Beat::Idle;
+            }
+            self.pending = None;
+            self.missed += 1;
+            if self.missed >= MISSES {
+                return Beat::Dead;
+            }
+        } else if now < self.heard + INTERVAL {
+            return Beat::Idle;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending = Some((id, now));
+        Beat::Ping(id)
+    }
+
+    /// How long until [`tick`](Self::tick) could have something to do.
+    pub fn wait(&self, now: Instant) -> Duration {
+        let at = match self.pending {
+            Some((_, sent)) => sent + TIMEOUT,
+            None => self.heard + INTERVAL,
+        };
+        at.saturating_duration_since(now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn a_quiet_bridge_is_pinged_and_two_misses_are_dead() {
+        let start = Instant::now();
+        let mut beat = Heartbeat::new(start);
+        assert!(matches!(beat.tick(start + secs(9)), Beat::Idle));
+        assert_eq!(beat.wait(start + secs(9)), secs(1));
+        assert!(matches!(beat.tick(start + secs(10)), Beat::Ping(1)));
+        assert!(matches!(beat.tick(start + secs(14)), Beat::Idle));
+        assert_eq!(beat.wait(start + secs(14)), secs(1));
+        // The first miss pings again straight away; the second gives up.
+        assert!(matches!(beat.tick(start + secs(15)), Beat::Ping(2)));
+        assert!(matches!(beat.tick(start + secs(20)), Beat::Dead));
+    }
+
+    #[test]
+    fn a_pong_resets_the_count() {
+        let start = Instant::now();
+        let mut beat = Heartbeat::new(start);
+        assert!(matches!(beat.tick(start + secs(10)), Beat::Ping(1)));
+        assert!(matches!(beat.tick(start + secs(15)), Beat::Ping(2)));
+        beat.heard(start + secs(16));
+        assert!(matches!(beat.tick(start + secs(21)), Beat::Idle));
+        assert!(matches!(beat.tick(start + secs(26)), Beat::Ping(3)));
+        assert!(matches!(beat.tick(start + secs(31)), Beat::Ping(4)));
+    }
+
+    #[test]
+    fn a_streaming_reply_keeps_pings_away() {
+        let start = Instant::now();
+        let mut beat = Heartbeat::new(start);
+        for n in 1..=60 {
+            let now = start + secs(n);
+            beat.heard(now);
+            assert!(matches!(beat.tick(now), Beat::Idle));
+        }
+        assert_eq!(beat.wait(start + secs(60)), INTERVAL);
+    }
+}
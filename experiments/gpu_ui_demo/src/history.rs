@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+
+/// How many submitted prompts are kept.
+pub const MAX: usize = 100;
+
+/// The prompts submitted so far, oldest first, shared by every tab.
+#[derive(Default)]
+pub struct History {
+    entries: VecDeque<String>,
+}
+
+/// Where one tab is while stepping through the [`History`], and what it had
+/// typed before it started.
+#[derive(Default)]
+pub struct Recall {
+    /// Steps back from the newest entry; `None` is the draft.
+    at: Option<usize>,
+    draft: String,
+}
+
+impl History {
+    pub fn new(entries: Vec<String>) -> Self {
+        let mut history = Self::default();
+        for entry in entries {
+            history.push(&entry);
+        }
+        history
+    }
+
+    /// Remembers `prompt`, unless it repeats the newest entry.
+    pub fn push(&mut self, prompt: &str) {
+        let prompt = prompt.trim();
+        if prompt.is_empty() || self.entries.back().is_some_and(|last| last == prompt) {
+            return;
+        }
+        self.entries.push_back(prompt.to_string());
+        while self.entries.len() > MAX {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn entries(&self) -> Vec<String> {
+        self.entries.iter().cloned().collect()
+    }
+
+    fn back(&self, steps: usize) -> Option<&String> {
+        let idx = self.entries.len().checked_sub(steps + 1)?;
+        self.entries.get(idx)
+    }
+}
+
+impl Recall {
+    /// The entry before the one shown, keeping `input` as the draft when
+    /// leaving it; `None` at the oldest.
+    pub fn older(&mut self, history: &History, input: &str) -> Option<String> {
+        let next = self.at.map_or(0, |at| at + 1);
+        let entry = history.back(next)?.clone();
+        if self.at.is_none() {
+            self.draft = input.to_string();
+        }
+        self.at = Some(next);
+        Some(entry)
+    }
+
+    /// The entry after the one shown, or the draft past the newest; `None`
+    /// when already at the draft.
+    pub fn newer(&mut self, history: &History) -> Option<String> {
+        match self.at? {
+            0 => {
+                self.at = None;
+                Some(std::mem::take(&mut self.draft))
+            }
+            at => {
+                self.at = Some(at - 1);
+                history.back(at - 1).cloned()
+            }
+        }
+    }
+
+    /// Back to the draft, as after a submit.
+    pub fn reset(&mut self) {
+        self.at = None;
+        self.draft.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_last_hundred_distinct_prompts_are_kept() {
+        let mut history = History::new(vec!["a".to_string(), " a ".to_string(), String::new()]);
+        assert_eq!(history.entries(), ["a"]);
+        for n in 0..150 {
+            history.push(&n.to_string());
+        }
+        let entries = history.entries();
+        assert_eq!(entries.len(), MAX);
+        assert_eq!((entries[0].as_str(), entries[99].as_str()), ("50", "149"));
+    }
+
+    #[test]
+    fn stepping_back_and_forth_keeps_the_draft() {
+        let history = History::new(vec!["one".to_string(), "two".to_string()]);
+        let mut recall = Recall::default();
+        assert_eq!(recall.newer(&history), None);
+        assert_eq!(recall.older(&history, "half typed").as_deref(), Some("two"));
+        assert_eq!(recall.older(&history, "two").as_deref(), Some("one"));
+        assert_eq!(recall.older(&history, "one"), None);
+        assert_eq!(recall.newer(&history).as_deref(), Some("two"));
+        assert_eq!(recall.newer(&history).as_deref(), Some("half typed"));
+        assert_eq!(recall.newer(&history), None);
+
+        recall.older(&history, "draft");
+        recall.reset();
+        assert_eq!(recall.older(&history, "").as_deref(), Some("two"));
+        assert_eq!(recall.newer(&history).as_deref(), Some(""));
+    }
+}
@@ -0,0 +1,592 @@
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::attach;
+use crate::backend::{BackendEvent, BackendJob, Prompt, Worker};
+use crate::diagnostics::Level;
+use crate::stats::Reported;
+use crate::SamplingParams;
+
+/// Where nox is looked for when no path is configured, in order.
+pub const DEFAULT_BINARIES: [&str; 3] =
+    ["target/release/nox", "bin/nox", "../noxrs/target/release/nox"];
+
+const POLL: Duration = Duration::from_millis(20);
+/// How long nox gets to exit on its own once it has answered.
+const EXIT_GRACE: Duration = Duration::from_secs(2);
+const STDERR_LINES: usize = 8;
+
+/// The binary to run: `configured` if set, else the first of
+/// [`DEFAULT_BINARIES`] that exists.
+pub fn binary(configured: &str) -> PathBuf {
+    let configured = configured.trim();
+    if !configured.is_empty() {
+        return PathBuf::from(configured);
+    }
+    DEFAULT_BINARIES
+        .iter()
+        .map(PathBuf::from)
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_BINARIES[0]))
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    id: u64,
+    #[serde(rename = "type")]
+    kind: &'a str,
+    text: &'a str,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    #[serde(rename = "type")]
+    kind: String,
+    text: Option<String>,
+    message: Option<String>,
+    reason: Option<String>,
+    #[serde(default)]
+    truncated: bool,
+    model: Option<String>,
+    load_ms: Option<u64>,
+    #[serde(default)]
+    stderr: Vec<String>,
+}
+
+/// Turns the lines nox writes for one prompt into backend events. nox's
+/// `done` carries timings but not the text, so the deltas are kept here.
+#[derive(Default)]
+pub struct Reply {
+    text: String,
+    over: bool,
+    /// What `ready` said was loaded.
+    model: Option<String>,
+    /// The seed nox was run with.
+    seed: Option<u64>,
+}
+
+impl Reply {
+    pub fn new(seed: Option<u64>) -> Self {
+        Self {
+            seed,
+            ..Self::default()
+        }
+    }
+
+    pub fn map(&mut self, line: &str) -> Vec<BackendEvent> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Vec::new();
+        }
+        let msg = match serde_json::from_str::<Response>(line) {
+            Ok(msg) => msg,
+            Err(err) => {
+                return vec![BackendEvent::Log(
+                    Level::Warn,
+                    format!("nox parse error: {} :: {}", err, line),
+                )]
+            }
+        };
+        match msg.kind.as_str() {
+            "ready" => {
+                self.model.clone_from(&msg.model);
+                let model = msg.model.unwrap_or_else(|| "no model".to_string());
+                let status = match msg.load_ms {
+                    Some(ms) => format!("nox loaded {} in {}ms", model, ms),
+                    None => format!("nox loaded {}", model),
+                };
+                vec![BackendEvent::Status(status)]
+            }
+            "delta" => match msg.text {
+                Some(text) if !text.is_empty() => {
+                    self.text.push_str(&text);
+                    vec![BackendEvent::Delta(None, text)]
+                }
+                _ => Vec::new(),
+            },
+            "done" => {
+                self.over = true;
+                let mut events = Vec::new();
+                // The whole line, less what isn't a number about the reply.
+                let mut raw = serde_json::from_str::<Value>(line).unwrap_or_default();
+                if let Some(object) = raw.as_object_mut() {
+                    for key in ["id", "type", "text", "truncated"] {
+                        object.remove(key);
+                    }
+                }
+                if let Some(mut reported) = Reported::parse(&raw) {
+                    reported.model = reported.model.or_else(|| self.model.clone());
+                    reported.seed = reported.seed.or(self.seed);
+                    events.push(BackendEvent::Stats(reported));
+                }
+                if msg.truncated {
+                    events.push(BackendEvent::Log(
+                        Level::Warn,
+                        "nox cut the reply off at max_tokens.".to_string(),
+                    ));
+                }
+                events.push(BackendEvent::Done(None, std::mem::take(&mut self.text)));
+                events
+            }
+            "error" => {
+                self.over = true;
+                let message = msg.message.unwrap_or_else(|| "Unknown nox error".into());
+                // Only sent for a prompt that was cancelled, which has
+                // already been stopped in the UI.
+                if message == "cancelled" {
+                    return vec![BackendEvent::Done(None, std::mem::take(&mut self.text))];
+                }
+                let mut text = match msg.reason.as_deref() {
+                    Some("load_failed") => format!("nox could not load the model: {}", message),
+                    _ => format!("nox: {}", message),
+                };
+                if let Some(last) = msg.stderr.last() {
+                    text += &format!(" ({})", last.trim());
+                }
+                vec![BackendEvent::Error(text)]
+            }
+            "queue" | "pong" => Vec::new(),
+            other => vec![BackendEvent::Log(Level::Info, format!("nox> {}", other))],
+        }
+    }
+
+    /// True once the prompt has its `done` or `error`.
+    pub fn over(&self) -> bool {
+        self.over
+    }
+}
+
+/// The environment that carries `params` to nox.
+pub fn sampling_env(params: SamplingParams) -> [(&'static str, String); 4] {
+    [
+        ("NOX_TEMP", params.temp.to_string()),
+        ("NOX_TOP_P", params.top_p.to_string()),
+        ("NOX_TOP_K", params.top_k.to_string()),
+        ("NOX_MAX_TOKENS", params.max_tokens.to_string()),
+    ]
+}
+
+/// A running prompt: the child and the lines its reader has seen.
+struct Run {
+    child: Child,
+    lines: Receiver<String>,
+    stderr: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl Run {
+    fn start(binary: &Path, prompt: &Prompt) -> Result<Self, String> {
+        let mut command = Command::new(binary);
+        command
+            .env("NOX_PERSIST", "1")
+            .env("NOX_PERSIST_PROTO", "jsonl")
+            .envs(sampling_env(prompt.params));
+        if let Some(system) = &prompt.system {
+            command.env("NOX_SYSTEM_PROMPT", system);
+        }
+        if let Some(seed) = prompt.seed {
+            command.env("NOX_SEED", seed.to_string());
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| format!("Could not run {}: {}", binary.display(), err))?;
+        let request = serde_json::to_string(&Request {
+            id: 1,
+            kind: "prompt",
+            text: &attach::for_nox(&prompt.text, &prompt.context),
+        })
+        .map_err(|err| err.to_string())?;
+        // Dropping stdin after the prompt is nox's cue to exit once it has
+        // answered.
+        if let Some(mut stdin) = child.stdin.take() {
+            if let Err(err) = writeln!(stdin, "{}", request) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("Could not send the prompt to nox: {}", err));
+            }
+        }
+        let (line_tx, lines) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines() {
+                    let Ok(line) = line else { break };
+                    if line_tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        let stderr = Arc::new(Mutex::new(VecDeque::new()));
+        if let Some(pipe) = child.stderr.take() {
+            let tail = stderr.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(pipe).lines() {
+                    let Ok(line) = line else { break };
+                    let mut tail = tail.lock();
+                    tail.push_back(line);
+                    if tail.len() > STDERR_LINES {
+                        tail.pop_front();
+                    }
+                }
+            });
+        }
+        Ok(Self {
+            child,
+            lines,
+            stderr,
+        })
+    }
+
+    fn kill(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+
+    /// Waits for nox to exit after answering, killing it if it lingers.
+    fn finish(&mut self) -> Option<ExitStatus> {
+        let deadline = Instant::now() + EXIT_GRACE;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return Some(status),
+                Ok(None) => thread::sleep(POLL),
+                Err(_) => break,
+            }
+        }
+        let _ = self.child.kill();
+        self.child.wait().ok()
+    }
+
+    fn last_stderr(&self) -> Option<String> {
+        self.stderr.lock().back().cloned()
+    }
+}
+
+enum Outcome {
+    Answered,
+    Cancelled,
+    Quit,
+}
+
+/// A tab's backend when nox runs locally instead of behind the bridge: one
+/// child per prompt, in persistent mode with `NOX_PERSIST_PROTO=jsonl`. The
+/// prompt is the only request written before stdin closes, so nox answers it
+/// with `delta` lines and a `done` or `error`, then exits. Prompts run one at
+/// a time; the ones sent meanwhile wait their turn.
+pub struct LocalBackend {
+    binary: PathBuf,
+    jobs: Receiver<BackendJob>,
+    tx: Sender<BackendEvent>,
+    queue: VecDeque<Prompt>,
+}
+
+impl LocalBackend {
+    pub fn spawn(binary: PathBuf, tx: Sender<BackendEvent>) -> Worker {
+        Worker::spawn(move |jobs| {
+            Self {
+                binary,
+                jobs,
+                tx,
+                queue: VecDeque::new(),
+            }
+            .run()
+        })
+    }
+
+    fn run(mut self) {
+        let _ = self.tx.send(BackendEvent::Status(format!(
+            "Running nox locally: {}",
+            self.binary.display()
+        )));
+        loop {
+            let prompt = match self.queue.pop_front() {
+                Some(prompt) => {
+                    let _ = self.tx.send(BackendEvent::Queued(self.queue.len()));
+                    prompt
+                }
+                None => match self.jobs.recv() {
+                    Ok(BackendJob::Prompt(prompt)) => prompt,
+                    Ok(BackendJob::Quit) | Err(_) => return,
+                    Ok(_) => continue,
+                },
+            };
+            if let Outcome::Quit = self.answer(&prompt) {
+                return;
+            }
+        }
+    }
+
+    fn answer(&mut self, prompt: &Prompt) -> Outcome {
+        let params = prompt.params;
+        let mut run = match Run::start(&self.binary, prompt) {
+            Ok(run) => run,
+            Err(err) => {
+                let _ = self.tx.send(BackendEvent::Error(err));
+                return Outcome::Answered;
+            }
+        };
+        let applied: Vec<String> = sampling_env(params)
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let _ = self.tx.send(BackendEvent::Applied(applied.join(", ")));
+        let mut reply = Reply::new(prompt.seed);
+        loop {
+            match run.lines.recv_timeout(POLL) {
+                Ok(line) => {
+                    for event in reply.map(&line) {
+                        let _ = self.tx.send(event);
+                    }
+                    if reply.over() {
+                        run.finish();
+                        return Outcome::Answered;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = run.finish();
+                    let mut err = match status {
+                        Some(status) => format!("nox exited ({}) without answering", status),
+                        None => "nox exited without answering".to_string(),
+                    };
+                    if let Some(last) = run.last_stderr() {
+                        err += &format!(": {}", last.trim());
+                    }
+                    let _ = self.tx.send(BackendEvent::Error(err));
+                    return Outcome::Answered;
+                }
+            }
+            loop {
+                match self.jobs.try_recv() {
+                    Ok(BackendJob::Prompt(prompt)) => {
+                        self.queue.push_back(prompt);
+                        let _ = self.tx.send(BackendEvent::Queued(self.queue.len()));
+                    }
+                    Ok(BackendJob::Cancel) => {
+                        run.kill();
+                        // The one `done` the stopped prompt is owed.
+                        let _ = self.tx.send(BackendEvent::Done(None, String::new()));
+                        return Outcome::Cancelled;
+                    }
+                    Ok(BackendJob::Quit) | Err(TryRecvError::Disconnected) => {
+                        run.kill();
+                        return Outcome::Quit;
+                    }
+                    Ok(_) => {}
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What nox wrote for one prompt, captured from a run.
+    const ANSWERED: &str = r#"{"type":"ready","model":"qwen-0.5b","ctx":4096,"load_ms":812}
+{"id":1,"type":"delta","text":"Hello"}
+{"id":1,"type":"delta","text":""}
+{"id":1,"type":"delta","text":" there"}
+{"id":1,"type":"done","ttft_ms":143,"duration_ms":900,"tokens":52,"truncated":true}"#;
+
+    fn map_all(reply: &mut Reply, lines: &str) -> Vec<BackendEvent> {
+        lines.lines().flat_map(|line| reply.map(line)).collect()
+    }
+
+    #[test]
+    fn a_reply_maps_to_status_deltas_stats_and_done() {
+        let mut reply = Reply::new(Some(7));
+        let events = map_all(&mut reply, ANSWERED);
+        assert!(reply.over());
+        assert_eq!(events.len(), 6);
+        assert!(matches!(
+            &events[0],
+            BackendEvent::Status(status) if status == "nox loaded qwen-0.5b in 812ms"
+        ));
+        assert!(matches!(&events[1], BackendEvent::Delta(None, text) if text == "Hello"));
+        assert!(matches!(&events[2], BackendEvent::Delta(None, text) if text == " there"));
+        let BackendEvent::Stats(stats) = &events[3] else {
+            panic!("no stats");
+        };
+        assert_eq!((stats.ttft_ms, stats.tokens), (Some(143.0), Some(52)));
+        assert_eq!(stats.model.as_deref(), Some("qwen-0.5b"));
+        assert_eq!(stats.seed, Some(7));
+        let raw = stats.raw.as_ref().unwrap();
+        assert!(raw.contains_key("duration_ms") && !raw.contains_key("truncated"));
+        assert!(matches!(&events[4], BackendEvent::Log(Level::Warn, _)));
+        // nox's done has no text; the deltas make it up.
+        assert!(matches!(&events[5], BackendEvent::Done(None, text) if text == "Hello there"));
+    }
+
+    #[test]
+    fn errors_say_what_nox_said() {
+        let mut reply = Reply::default();
+        let events = reply.map(
+            r#"{"type":"error","reason":"load_failed","message":"runner exited","stderr":["a","error: no model "]}"#,
+        );
+        assert!(matches!(
+            &events[..],
+            [BackendEvent::Error(text)]
+                if text == "nox could not load the model: runner exited (error: no model)"
+        ));
+        assert!(reply.over());
+
+        // A cancelled prompt owes the UI one done with what it had.
+        let mut reply = Reply::default();
+        reply.map(r#"{"id":1,"type":"delta","text":"par"}"#);
+        let events = reply.map(r#"{"id":1,"type":"error","message":"cancelled"}"#);
+        assert!(matches!(&events[..], [BackendEvent::Done(None, text)] if text == "par"));
+    }
+
+    #[test]
+    fn other_lines_are_logged_or_ignored() {
+        let mut reply = Reply::default();
+        assert!(reply.map("  ").is_empty());
+        assert!(reply.map(r#"{"type":"queue","depth":0}"#).is_empty());
+        assert!(matches!(
+            &reply.map("not json")[..],
+            [BackendEvent::Log(Level::Warn, line)] if line.starts_with("nox parse error")
+        ));
+        assert!(matches!(
+            &reply.map(r#"{"type":"restart"}"#)[..],
+            [BackendEvent::Log(Level::Info, line)] if line == "nox> restart"
+        ));
+        assert!(!reply.over());
+    }
+
+    #[test]
+    fn sampling_goes_in_noxs_variables() {
+        let params = SamplingParams {
+            temp: 0.7,
+            top_p: 0.9,
+            top_k: 40,
+            max_tokens: 256,
+        };
+        assert_eq!(
+            sampling_env(params),
+            [
+                ("NOX_TEMP", "0.7".to_string()),
+                ("NOX_TOP_P", "0.9".to_string()),
+                ("NOX_TOP_K", "40".to_string()),
+                ("NOX_MAX_TOKENS", "256".to_string()),
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    mod mock {
+        use super::*;
+        use crate::backend::ChatBackend;
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        /// A nox that runs `mock_nox.sh` with `mode` and `delay`.
+        fn mock(name: &str, mode: &str, delay: &str) -> PathBuf {
+            let script = Path::new(env!("CARGO_MANIFEST_DIR")).join("mock_nox.sh");
+            let dir = std::env::temp_dir().join(format!("noctics-local-{}", std::process::id()));
+            fs::create_dir_all(&dir).unwrap();
+            let path = dir.join(name);
+            fs::write(
+                &path,
+                format!(
+                    "#!/bin/sh\nMOCK_NOX_MODE={} MOCK_NOX_DELAY={} exec sh '{}'\n",
+                    mode,
+                    delay,
+                    script.display()
+                ),
+            )
+            .unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        }
+
+        fn prompt(text: &str) -> Prompt {
+            Prompt {
+                text: text.to_string(),
+                params: SamplingParams::default(),
+                system: None,
+                id: None,
+                seed: None,
+                context: Vec::new(),
+            }
+        }
+
+        /// The events up to the prompt's `Done` or `Error`.
+        fn until_over(events: &Receiver<BackendEvent>) -> Vec<BackendEvent> {
+            let mut seen = Vec::new();
+            loop {
+                let event = events
+                    .recv_timeout(Duration::from_secs(10))
+                    .expect("nox never answered");
+                let over = matches!(event, BackendEvent::Done(..) | BackendEvent::Error(_));
+                seen.push(event);
+                if over {
+                    return seen;
+                }
+            }
+        }
+
+        #[test]
+        fn the_mock_is_answered_a_word_at_a_time() {
+            let (tx, events) = mpsc::channel();
+            let mut local = LocalBackend::spawn(mock("echo", "", "0"), tx);
+            local
+                .submit(BackendJob::Prompt(prompt("hi there")))
+                .unwrap();
+            let seen = until_over(&events);
+            let deltas = seen
+                .iter()
+                .filter(|event| matches!(event, BackendEvent::Delta(..)))
+                .count();
+            assert!(deltas > 2);
+            let Some(BackendEvent::Done(None, text)) = seen.last() else {
+                panic!("no done");
+            };
+            assert!(text.starts_with("(mock nox, T=0 ") && text.ends_with(") hi there"));
+            local.shutdown();
+        }
+
+        #[test]
+        fn a_crash_is_an_error() {
+            let (tx, events) = mpsc::channel();
+            let mut local = LocalBackend::spawn(mock("crash", "crash", "0"), tx);
+            local.submit(BackendJob::Prompt(prompt("hi"))).unwrap();
+            let seen = until_over(&events);
+            assert!(matches!(
+                seen.last(),
+                Some(BackendEvent::Error(err)) if err.contains("exited (exit status: 3)")
+            ));
+            local.shutdown();
+        }
+
+        #[test]
+        fn cancel_kills_the_run() {
+            let (tx, events) = mpsc::channel();
+            let mut local = LocalBackend::spawn(mock("slow", "", "30"), tx);
+            local.submit(BackendJob::Prompt(prompt("hi"))).unwrap();
+            // Running once it has said what it applied.
+            while !matches!(
+                events.recv_timeout(Duration::from_secs(10)),
+                Ok(BackendEvent::Applied(_))
+            ) {}
+            let asked = Instant::now();
+            local.submit(BackendJob::Cancel).unwrap();
+            let seen = until_over(&events);
+            assert!(matches!(seen.last(), Some(BackendEvent::Done(None, text)) if text.is_empty()));
+            assert!(asked.elapsed() < Duration::from_secs(5));
+            local.shutdown();
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::VecDeque;
+
+use crate::session::{self, Record, Role};
+use crate::stats;
+
+/// What a log entry is, which picks its tag and color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogRole {
+    User,
+    Assistant,
+    /// A reply cut short with Stop.
+    Stopped,
+    /// A prompt that got an error instead of a reply; the text is the error.
+    Failed,
+    /// The system prompt prompts went out under; `updated` when it replaced
+    /// an earlier one.
+    System { updated: bool },
+    /// The console's own notes, like where an export went.
+    Note,
+}
+
+/// One entry in a tab's log.
+#[derive(Clone)]
+pub struct LogEntry {
+    pub role: LogRole,
+    /// As sent or received, without the `You>` tag, so a copy keeps its
+    /// newlines.
+    pub text: String,
+    /// Unix time in milliseconds.
+    pub ts: u64,
+    /// How fast a finished reply came, shown after it.
+    pub stats: Option<stats::Stats>,
+}
+
+/// The last prompt in a log and what came back for it, by index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    pub prompt: usize,
+    pub reply: usize,
+}
+
+impl LogRole {
+    /// Whether an entry with this role is what came back for a prompt.
+    pub fn is_reply(self) -> bool {
+        matches!(self, LogRole::Assistant | LogRole::Stopped | LogRole::Failed)
+    }
+}
+
+impl LogEntry {
+    pub fn new(role: LogRole, text: impl Into<String>) -> Self {
+        Self {
+            role,
+            text: text.into(),
+            ts: session::now_ms(),
+            stats: None,
+        }
+    }
+
+    pub fn tag(&self) -> Option<&'static str> {
+        match self.role {
+            LogRole::User => Some("You>"),
+            LogRole::Assistant => Some("Nox>"),
+            LogRole::Stopped => Some("Nox (stopped)>"),
+            LogRole::Failed => Some("Nox (failed)>"),
+            LogRole::System { updated: false } => Some("System>"),
+            LogRole::System { updated: true } => Some("System prompt updated>"),
+            LogRole::Note => None,
+        }
+    }
+}
+
+impl From<String> for LogEntry {
+    fn from(text: String) -> Self {
+        Self::new(LogRole::Note, text)
+    }
+}
+
+impl From<&Record> for LogEntry {
+    fn from(record: &Record) -> Self {
+        let role = match (record.role, record.stopped) {
+            (Role::User, _) => LogRole::User,
+            (Role::Assistant, false) => LogRole::Assistant,
+            (Role::Assistant, true) => LogRole::Stopped,
+            (Role::System, _) => LogRole::System { updated: false },
+        };
+        let stats = record.stats.clone().map(|stats| stats::Stats {
+            model: record.model.clone(),
+            ..stats
+        });
+        Self {
+            ts: record.ts,
+            stats,
+            ..Self::new(role, record.text.clone())
+        }
+    }
+}
+
+/// The reply the log ends on, notes aside, and the prompt it answered.
+///
+/// Replies come back in the order their prompts went out, so when several
+/// prompts were sent before the first reply, the last of a run of `n`
+/// replies answers the `n`th prompt back from it. `None` when the log
+/// doesn't end on a reply, or there's no prompt to pair it with.
+pub fn last_exchange(log: &VecDeque<LogEntry>) -> Option<Exchange> {
+    let mut entries = log
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, entry)| entry.role != LogRole::Note);
+    let (reply, _) = entries.next().filter(|(_, entry)| entry.role.is_reply())?;
+    let mut replies = 1;
+    for (idx, entry) in entries {
+        match entry.role {
+            role if role.is_reply() => replies += 1,
+            LogRole::User if replies == 1 => return Some(Exchange { prompt: idx, reply }),
+            LogRole::User => replies -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(roles: &[LogRole]) -> VecDeque<LogEntry> {
+        roles
+            .iter()
+            .enumerate()
+            .map(|(idx, role)| LogEntry::new(*role, idx.to_string()))
+            .collect()
+    }
+
+    fn exchange(prompt: usize, reply: usize) -> Option<Exchange> {
+        Some(Exchange { prompt, reply })
+    }
+
+    use LogRole::{Assistant, Failed, Note, Stopped, User};
+
+    #[test]
+    fn the_last_reply_pairs_with_its_prompt() {
+        assert_eq!(last_exchange(&log(&[User, Assistant])), exchange(0, 1));
+        let system = LogRole::System { updated: false };
+        assert_eq!(
+            last_exchange(&log(&[User, Assistant, system, User, Note, Stopped, Note])),
+            exchange(3, 5)
+        );
+    }
+
+    #[test]
+    fn an_error_as_the_last_entry_is_a_reply() {
+        assert_eq!(
+            last_exchange(&log(&[User, Assistant, User, Failed])),
+            exchange(2, 3)
+        );
+    }
+
+    #[test]
+    fn a_reply_after_consecutive_prompts_answers_the_nearest() {
+        // As after a session is reopened: a prompt that failed has no
+        // recorded reply.
+        assert_eq!(
+            last_exchange(&log(&[User, User, Assistant])),
+            exchange(1, 2)
+        );
+        assert_eq!(
+            last_exchange(&log(&[User, User, Note, Failed])),
+            exchange(1, 3)
+        );
+    }
+
+    #[test]
+    fn no_exchange_without_a_reply_at_the_end() {
+        assert_eq!(last_exchange(&log(&[])), None);
+        assert_eq!(last_exchange(&log(&[User, Assistant, User])), None);
+        assert_eq!(last_exchange(&log(&[Note, Assistant])), None);
+        // More replies than prompts: the oldest can't be placed.
+        assert_eq!(last_exchange(&log(&[User, Assistant, Assistant])), None);
+    }
+}
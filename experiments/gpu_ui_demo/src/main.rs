@@ -1,375 +1,2997 @@
 use eframe::egui;
-use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::io::{BufRead, BufReader, Write};
-use std::net::TcpStream;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver, Sender};
-use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-const BRIDGE_ADDR: &str = "127.0.0.1:4510";
+mod attach;
+mod backend;
+mod context;
+mod diagnostics;
+mod export;
+mod follow;
+mod handshake;
+mod heartbeat;
+mod history;
+mod local;
+mod log;
+mod markdown;
+mod models;
+mod notify;
+mod obscure;
+mod search;
+mod session;
+mod shutdown;
+mod stats;
+mod telemetry;
+mod theme;
+
+use attach::Attachment;
+use backend::{BackendEvent, BackendJob, Driver, Fallback, JobTx, Link, Prompt, Target};
+use log::{LogEntry, LogRole};
+use session::{Record, Role};
+
+const DEFAULT_BRIDGE_ADDR: &str = "127.0.0.1:4510";
+
+/// How long the settings have to sit still before they are written.
+const SETTINGS_DELAY: Duration = Duration::from_secs(1);
+
+/// How long the status bar shows the zoom after it changes.
+const ZOOM_SHOWN: Duration = Duration::from_secs(2);
 
 type EventRx = Receiver<BackendEvent>;
-type JobTx = Sender<BackendJob>;
 
-type LogLine = String;
+/// What the hover toolbar over a log entry was asked to do.
+enum LogAction {
+    Copy(String),
+    Quote(String),
+}
 
 fn main() -> eframe::Result<()> {
+    let mut settings = Settings::load();
+    let bridge_addr = initial_bridge_addr(&settings);
+    settings.backend = initial_backend(&settings);
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
             .with_inner_size([960.0, 600.0])
             .with_min_inner_size([640.0, 480.0])
-            .with_title("Noctics GPU Console"),
+            .with_title(notify::TITLE),
         ..Default::default()
     };
 
     eframe::run_native(
-        "Noctics GPU Console",
+        notify::TITLE,
         native_options,
-        Box::new(|_cc| Box::new(DemoApp::default())),
+        Box::new(|cc| {
+            // Ctrl+= and Ctrl+- scale the text instead; see `DemoApp::zoom`.
+            cc.egui_ctx.options_mut(|o| o.zoom_with_keyboard = false);
+            Box::new(DemoApp::new(bridge_addr, settings))
+        }),
     )
 }
 
-struct DemoApp {
-    log: VecDeque<LogLine>,
+/// The value of `--name value` or `--name=value` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == name {
+            if let Some(value) = args.next() {
+                return Some(value);
+            }
+        } else if let Some(value) = arg.strip_prefix(name).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+fn initial_bridge_addr(settings: &Settings) -> String {
+    if let Some(addr) = arg_value("--bridge") {
+        return addr;
+    }
+    if let Ok(addr) = std::env::var("NOX_BRIDGE_ADDR") {
+        if !addr.trim().is_empty() {
+            return addr.trim().to_string();
+        }
+    }
+    settings
+        .bridge_addr
+        .clone()
+        .unwrap_or_else(|| DEFAULT_BRIDGE_ADDR.to_string())
+}
+
+/// The bridge token from `NOX_BRIDGE_TOKEN`, then the settings file.
+fn initial_bridge_token(settings: &Settings) -> String {
+    std::env::var("NOX_BRIDGE_TOKEN")
+        .ok()
+        .filter(|token| !token.trim().is_empty())
+        .or_else(|| settings.bridge_token.clone())
+        .unwrap_or_default()
+}
+
+fn initial_backend(settings: &Settings) -> BackendKind {
+    match arg_value("--backend").as_deref() {
+        Some("bridge") => BackendKind::Bridge,
+        Some("local") => BackendKind::Local,
+        Some(other) => {
+            eprintln!("Unknown backend `{}` (expected bridge or local)", other);
+            settings.backend
+        }
+        None => settings.backend,
+    }
+}
+
+/// What the console remembers between runs, in
+/// `$XDG_CONFIG_HOME/noctics/gpu_console.json`. Every field may be missing
+/// and unknown ones are ignored, so files from other versions still load.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+struct Settings {
+    bridge_addr: Option<String>,
+    #[serde(default)]
+    sampling: SamplingParams,
+    #[serde(default)]
+    export_dir: Option<String>,
+    #[serde(default)]
+    backend: BackendKind,
+    /// Path to the nox binary for the local backend; unset means the first
+    /// of [`local::DEFAULT_BINARIES`] that exists.
+    #[serde(default)]
+    nox_binary: Option<String>,
+    #[serde(default)]
+    fallback: Fallback,
+    /// Shared secret sent to the bridge on connect, kept [`obscure`]d.
+    #[serde(default, with = "obscure")]
+    bridge_token: Option<String>,
+    /// Submitted prompts, oldest first.
+    #[serde(default)]
+    history: Vec<String>,
+    #[serde(default)]
+    diagnostics: diagnostics::View,
+    /// Send prompts to the bridge without waiting for the reply before.
+    #[serde(default)]
+    multiplex: bool,
+    /// The model's context window in tokens; unset means
+    /// [`context::DEFAULT_SIZE`].
+    #[serde(default)]
+    context_size: Option<usize>,
+    /// Whether the log follows new lines; unset means it does.
+    #[serde(default)]
+    auto_scroll: Option<bool>,
+    /// The session open in the active tab when the settings were saved.
+    #[serde(default)]
+    last_session: Option<String>,
+    #[serde(default)]
+    theme: theme::Theme,
+    /// How much bigger than egui's own the text is; unset means 1.
+    #[serde(default)]
+    zoom: Option<f32>,
+    /// The largest file Attach takes, in KiB; unset means
+    /// [`attach::DEFAULT_LIMIT_KB`].
+    #[serde(default)]
+    attach_limit_kb: Option<u64>,
+    /// Whether replies show their numbers under them; unset means they do.
+    #[serde(default)]
+    footers: Option<bool>,
+    /// Call for attention when a slow reply ends while the window is
+    /// unfocused.
+    #[serde(default)]
+    notify: bool,
+    /// How slow, in seconds; unset means [`notify::DEFAULT_AFTER`].
+    #[serde(default)]
+    notify_after_s: Option<f32>,
+    /// Ring the terminal bell with the notification.
+    #[serde(default)]
+    notify_bell: bool,
+}
+
+/// Where prompts go: the bridge over TCP, or nox run as a child process.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BackendKind {
+    #[default]
+    Bridge,
+    Local,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct SamplingParams {
+    temp: f32,
+    top_p: f32,
+    top_k: u32,
+    max_tokens: u32,
+}
+
+impl Default for SamplingParams {
+    fn default() -> Self {
+        // Same as noxrs: NOX_TEMP, NOX_TOP_P, NOX_TOP_K, NOX_MAX_TOKENS.
+        Self {
+            temp: 0.0,
+            top_p: 1.0,
+            top_k: 1,
+            max_tokens: 128,
+        }
+    }
+}
+
+impl SamplingParams {
+    fn summary(&self) -> String {
+        format!("T={} · {} tok", self.temp, self.max_tokens)
+    }
+}
+
+impl Settings {
+    fn path() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(base.join("noctics").join("gpu_console.json"))
+    }
+
+    fn load() -> Self {
+        Self::path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("no config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&path, text + "\n").map_err(|err| err.to_string())
+    }
+}
+
+/// The open tabs, saved to `$XDG_DATA_HOME/noctics/tabs.json` on exit so the
+/// next start can offer them back.
+#[derive(Default, Serialize, Deserialize)]
+struct Tabs {
+    active: usize,
+    tabs: Vec<TabState>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TabState {
+    name: Option<String>,
+    session: Option<String>,
+    #[serde(default)]
+    addr: Option<String>,
+    #[serde(default)]
+    sampling: Option<SamplingParams>,
+    #[serde(default)]
+    input: String,
+}
+
+impl Tabs {
+    fn path() -> Option<PathBuf> {
+        Some(session::data_dir()?.join("tabs.json"))
+    }
+
+    fn load() -> Option<Self> {
+        let text = fs::read_to_string(Self::path()?).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("no data directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let text = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(&path, text + "\n").map_err(|err| err.to_string())
+    }
+}
+
+/// A reply streaming alongside the oldest one, when the bridge multiplexes.
+struct Side {
+    text: String,
+    meter: stats::Meter,
+}
+
+/// A prompt waiting to go out, and the files attached to it.
+struct Queued {
+    prompt: String,
+    context: Vec<Attachment>,
+}
+
+/// One tab: a conversation with its own backend and session file.
+struct Session {
+    name: String,
+    log: VecDeque<LogEntry>,
+    diagnostics: diagnostics::Diagnostics,
     input: String,
+    /// The system prompt sent with each of this tab's prompts.
+    system: String,
+    recall: history::Recall,
     outbound: JobTx,
     inbound: EventRx,
+    events: Sender<BackendEvent>,
+    /// The backend's thread, joined on exit so its bridge connection is
+    /// closed or its nox killed before the app goes.
+    driver: Option<thread::JoinHandle<()>>,
     status_line: String,
-    auto_scroll: bool,
     streaming_buffer: String,
     streaming_active: bool,
+    stopped: bool,
+    link: Link,
+    /// The models the bridge serves and offers, while connected.
+    models: models::Models,
+    queued: usize,
+    addr_input: String,
+    last_good_addr: Option<String>,
+    sampling: SamplingParams,
+    applied: Option<String>,
+    file: Option<session::SessionFile>,
+    writer: Option<session::Writer>,
+    /// Prompts sent and not yet answered, oldest first: each one's id and
+    /// when it went out. The streaming fields above are the oldest one's.
+    sent: VecDeque<(u64, Instant)>,
+    next_id: u64,
+    /// Prompts typed while a reply was under way, sent one at a time as
+    /// each reply ends.
+    pending: VecDeque<Queued>,
+    /// Send prompts straight away, tagged by id, for bridges that answer
+    /// several at once.
+    multiplex: bool,
+    /// Replies to prompts after the oldest, by id, while they stream.
+    side: BTreeMap<u64, Side>,
+    /// The reply Regenerate or Retry last took off the log, and its record
+    /// if it had one, for Undo.
+    undo: Option<(LogEntry, Option<Record>)>,
+    /// How far the bridge's last `prompt_tokens` was off from the estimate,
+    /// added to the context meter from then on.
+    correction: isize,
+    search: search::Search,
+    follow: follow::Follow,
+    /// The path typed into Attach, while its box is open.
+    attach_path: Option<String>,
+    /// Files to go with the next prompt sent.
+    attachments: Vec<Attachment>,
+    /// The files that went with the last prompt, for Regenerate and Retry.
+    sent_context: Vec<Attachment>,
+    /// Entries and replies that came in since the log was last drawn.
+    arrived: usize,
+    /// How long each reply that ended since the last frame took, for
+    /// notifications.
+    ended: Vec<Duration>,
+    /// What the bridge has said about its GPU lately.
+    telemetry: telemetry::Telemetry,
+    meter: Option<stats::Meter>,
+    reported: Option<stats::Reported>,
+    last_stats: Option<stats::Stats>,
+    conversation: Vec<Record>,
 }
 
-impl Default for DemoApp {
-    fn default() -> Self {
-        let (job_tx, job_rx) = mpsc::channel::<BackendJob>();
+impl Session {
+    fn new(
+        name: String,
+        bridge_addr: String,
+        sampling: SamplingParams,
+        file: Option<session::SessionFile>,
+        route: Route,
+    ) -> Self {
         let (event_tx, event_rx) = mpsc::channel::<BackendEvent>();
-        Backend::spawn(job_rx, event_tx.clone());
-        let _ = event_tx.send(BackendEvent::Status(format!(
-            "Connecting to Noctics bridge at {}…",
-            BRIDGE_ADDR
-        )));
+        let errors = event_tx.clone();
+        let writer = file.as_ref().map(|file| {
+            session::Writer::spawn(file.path.clone(), move |err| {
+                let _ = errors.send(BackendEvent::Error(format!(
+                    "Session write failed: {}",
+                    err
+                )));
+            })
+        });
+        let (job_tx, driver) = spawn_backend(&bridge_addr, route, &event_tx);
         Self {
+            name,
             log: VecDeque::with_capacity(512),
+            diagnostics: diagnostics::Diagnostics::default(),
             input: String::new(),
+            system: String::new(),
+            recall: history::Recall::default(),
             outbound: job_tx,
             inbound: event_rx,
+            events: event_tx,
+            driver: Some(driver),
             status_line: String::from("Starting up…"),
-            auto_scroll: true,
             streaming_buffer: String::new(),
             streaming_active: false,
+            stopped: false,
+            link: Link::Offline,
+            models: models::Models::default(),
+            queued: 0,
+            addr_input: bridge_addr,
+            last_good_addr: None,
+            sampling,
+            applied: None,
+            file,
+            writer,
+            sent: VecDeque::new(),
+            next_id: 1,
+            pending: VecDeque::new(),
+            multiplex: false,
+            side: BTreeMap::new(),
+            undo: None,
+            correction: 0,
+            search: search::Search::default(),
+            follow: follow::Follow::default(),
+            attach_path: None,
+            attachments: Vec::new(),
+            sent_context: Vec::new(),
+            arrived: 0,
+            ended: Vec::new(),
+            telemetry: telemetry::Telemetry::default(),
+            meter: None,
+            reported: None,
+            last_stats: None,
+            conversation: Vec::new(),
         }
     }
-}
 
-impl DemoApp {
-    fn poll_incoming(&mut self, ctx: &egui::Context) {
+    /// Drains the backend's events; true if there were any.
+    fn poll_incoming(&mut self) -> bool {
         let mut any = false;
         while let Ok(event) = self.inbound.try_recv() {
             any = true;
+            let began = match &event {
+                BackendEvent::Done(Some(id), _) | BackendEvent::Failed(id, _) => self.sent_at(*id),
+                BackendEvent::Done(None, _) | BackendEvent::Error(_) => self.oldest_sent(),
+                _ => None,
+            };
+            if let Some(began) = began {
+                self.ended.push(began.elapsed());
+            }
             match event {
-                BackendEvent::Log(line) => self.push_line(line),
+                BackendEvent::Log(level, line) => self.diagnostics.push(level, line),
                 BackendEvent::Status(msg) => self.status_line = msg,
-                BackendEvent::Delta(chunk) => {
+                BackendEvent::Delta(Some(id), chunk) if !self.is_oldest(id) => {
+                    if let Some(at) = self.sent_at(id) {
+                        let side = self.side.entry(id).or_insert_with(|| Side {
+                            text: String::new(),
+                            meter: stats::Meter::new(Some(at)),
+                        });
+                        side.meter.delta(Instant::now(), &chunk);
+                        side.text.push_str(&chunk);
+                    }
+                }
+                BackendEvent::Delta(_, _) if self.stopped => {}
+                BackendEvent::Delta(_, chunk) => {
+                    let sent = self.oldest_sent();
+                    self.meter
+                        .get_or_insert_with(|| stats::Meter::new(sent))
+                        .delta(Instant::now(), &chunk);
+                    if self.streaming_buffer.is_empty() {
+                        self.arrived += 1;
+                    }
                     self.streaming_active = true;
                     self.streaming_buffer.push_str(&chunk);
                 }
-                BackendEvent::Done(full) => {
+                BackendEvent::Done(Some(id), full) if !self.is_oldest(id) => {
+                    self.end_side(id, Some(full));
+                }
+                BackendEvent::Done(_, _) if self.stopped => {
+                    self.stopped = false;
+                    self.reported = None;
+                    self.end_oldest();
+                }
+                BackendEvent::Done(_, full) => {
                     if !full.trim().is_empty() {
-                        self.push_line(format!("Nox> {}", full));
+                        self.finish_reply(full, false);
                     }
-                    self.streaming_active = false;
-                    self.streaming_buffer.clear();
+                    self.end_oldest();
+                }
+                BackendEvent::Failed(id, msg) => {
+                    self.status_line = format!("Error: {}", msg);
+                    // One already stopped has its entry.
+                    if !(self.stopped && self.is_oldest(id)) {
+                        self.push_line(LogEntry::new(LogRole::Failed, msg.clone()));
+                    }
+                    self.diagnostics.push(diagnostics::Level::Error, msg);
+                    if self.is_oldest(id) {
+                        self.stopped = false;
+                        self.end_oldest();
+                    } else {
+                        self.end_side(id, None);
+                    }
+                }
+                // Only the switch was waiting on it; nothing else is in
+                // flight while one is under way.
+                BackendEvent::Error(msg) if self.models.switching(Instant::now()).is_some() => {
+                    if let Some(outcome) = self.models.error(&msg) {
+                        self.status_line = outcome.status();
+                    }
+                    self.diagnostics.push(diagnostics::Level::Error, msg);
                 }
                 BackendEvent::Error(msg) => {
                     self.status_line = format!("Error: {}", msg);
-                    self.push_line(format!("! {}", msg));
+                    // Each prompt that won't get its reply now says so, where
+                    // Retry can get at it.
+                    for _ in usize::from(self.stopped)..self.sent.len() {
+                        self.push_line(LogEntry::new(LogRole::Failed, msg.clone()));
+                    }
+                    self.diagnostics.push(diagnostics::Level::Error, msg);
+                    // Whatever was in flight is gone; prompts sent from here
+                    // on are timed afresh.
+                    self.stopped = false;
+                    self.meter = None;
+                    self.reported = None;
+                    self.sent.clear();
+                    self.side.clear();
                     self.streaming_active = false;
                     self.streaming_buffer.clear();
                 }
+                BackendEvent::Link(link) => {
+                    if link != Link::Online {
+                        self.stopped = false;
+                        if let Some(outcome) = self.models.lost() {
+                            self.status_line = outcome.status();
+                        }
+                    }
+                    self.link = link;
+                }
+                BackendEvent::Model(model) => self.models.set_active(model),
+                BackendEvent::Models(offered) => self.models.hello(offered),
+                BackendEvent::ModelReady(name) => {
+                    if let Some(outcome) = self.models.ready(name, Instant::now()) {
+                        self.status_line = outcome.status();
+                        self.diagnostics.push(diagnostics::Level::Info, outcome.status());
+                    }
+                }
+                BackendEvent::Queued(count) => self.queued = count,
+                BackendEvent::Connected(addr) => self.last_good_addr = Some(addr),
+                BackendEvent::Applied(summary) => self.applied = Some(summary),
+                BackendEvent::Stats(reported) => {
+                    // Counted before the reply is logged, as the bridge did.
+                    if let Some(prompt_tokens) = reported.prompt_tokens {
+                        let estimate = context::estimate(&self.log, self.system.trim());
+                        self.correction = prompt_tokens as isize - estimate as isize;
+                    }
+                    self.reported = Some(reported);
+                }
+                // A reader that outlived its connection may still have some
+                // in hand; those describe a bridge we've left.
+                BackendEvent::Telemetry(sample) if self.link == Link::Online => {
+                    self.telemetry.push(sample)
+                }
+                BackendEvent::Telemetry(_) => {}
             }
         }
         if any {
-            ctx.request_repaint();
+            self.pump();
+        }
+        any
+    }
+
+    fn is_oldest(&self, id: u64) -> bool {
+        self.sent.front().is_some_and(|(oldest, _)| *oldest == id)
+    }
+
+    fn oldest_sent(&self) -> Option<Instant> {
+        self.sent.front().map(|(_, at)| *at)
+    }
+
+    fn sent_at(&self, id: u64) -> Option<Instant> {
+        self.sent
+            .iter()
+            .find(|(sent, _)| *sent == id)
+            .map(|(_, at)| *at)
+    }
+
+    /// Done with the oldest prompt; the next one's reply, if it has been
+    /// streaming alongside, takes its place.
+    fn end_oldest(&mut self) {
+        self.meter = None;
+        self.reported = None;
+        self.sent.pop_front();
+        self.streaming_active = false;
+        self.streaming_buffer.clear();
+        let Some(&(next, _)) = self.sent.front() else {
+            return;
+        };
+        if let Some(side) = self.side.remove(&next) {
+            self.streaming_active = true;
+            self.streaming_buffer = side.text;
+            self.meter = Some(side.meter);
+        }
+    }
+
+    /// Done with a prompt after the oldest, logging its reply if it has one.
+    fn end_side(&mut self, id: u64, full: Option<String>) {
+        let Some(at) = self.sent_at(id) else {
+            return;
+        };
+        self.sent.retain(|(sent, _)| *sent != id);
+        let meter = match self.side.remove(&id) {
+            Some(side) => side.meter,
+            None => stats::Meter::new(Some(at)),
+        };
+        match full {
+            Some(full) if !full.trim().is_empty() => self.log_reply(meter, full, false),
+            _ => self.reported = None,
         }
     }
 
-    fn push_line(&mut self, line: impl Into<LogLine>) {
+    fn push_line(&mut self, line: impl Into<LogEntry>) {
         const MAX_LOG: usize = 800;
         self.log.push_back(line.into());
+        self.arrived += 1;
         while self.log.len() > MAX_LOG {
             self.log.pop_front();
         }
     }
 
-    fn submit_prompt(&mut self) {
-        let prompt = self.input.trim();
+    /// Sends what is typed, with the files attached, or lines it up behind
+    /// the reply under way, and returns it for the history.
+    fn submit_prompt(&mut self) -> Option<String> {
+        let prompt = self.input.trim().to_owned();
         if prompt.is_empty() {
-            return;
+            return None;
         }
-        let prompt_owned = prompt.to_owned();
-        self.push_line(format!("You> {}", prompt_owned));
         self.input.clear();
-        if let Err(err) = self.outbound.send(BackendJob::Prompt {
-            text: prompt_owned,
-        }) {
-            self.status_line = format!("Backend unavailable: {}", err);
-        }
+        self.recall.reset();
+        let context = std::mem::take(&mut self.attachments);
+        self.queue_prompt(prompt.clone(), context);
+        Some(prompt)
     }
-}
 
-impl eframe::App for DemoApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        self.poll_incoming(ctx);
+    fn queue_prompt(&mut self, prompt: String, context: Vec<Attachment>) {
+        self.pending.push_back(Queued { prompt, context });
+        self.pump();
+    }
 
-        egui::TopBottomPanel::top("status_panel").show(ctx, |ui| {
-            ui.horizontal(|ui| {
-                ui.label(egui::RichText::new("Status:").strong());
-                ui.label(&self.status_line);
-            });
-            if self.streaming_active {
-                ui.label(egui::RichText::new("Streaming…").italics());
+    /// Reads the file at the path typed into Attach and adds it to the next
+    /// prompt, or says why not in the status line.
+    fn attach(&mut self, limit_kb: u64) {
+        let Some(path) = self.attach_path.as_deref().map(str::trim) else {
+            return;
+        };
+        if path.is_empty() {
+            return;
+        }
+        match attach::load(Path::new(path), limit_kb) {
+            Ok(file) => {
+                self.status_line =
+                    format!("Attached {} ({})", file.name, attach::size_label(file.size()));
+                self.attachments.push(file);
+                self.attach_path = None;
             }
-        });
+            Err(err) => self.status_line = err,
+        }
+    }
 
-        egui::SidePanel::right("controls_panel").resizable(true).show(ctx, |ui| {
-            ui.heading("Options");
-            ui.separator();
-            ui.toggle_value(&mut self.auto_scroll, "Auto-scroll");
-            ui.separator();
-            ui.label("Run bridge: python experiments/gpu_ui_demo/bridge_server.py");
-            ui.small("Prompts are forwarded to the real Noctics ChatClient over TCP.");
-        });
+    /// Sends the pending prompts the backend can take: every one when the
+    /// bridge multiplexes, otherwise the next once nothing is in flight.
+    fn pump(&mut self) {
+        if self.models.switching(Instant::now()).is_some() {
+            return;
+        }
+        while self.multiplex || self.sent.is_empty() {
+            let Some(queued) = self.pending.pop_front() else {
+                return;
+            };
+            self.send_prompt(queued);
+        }
+    }
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Session Stream");
-            ui.separator();
-            egui::ScrollArea::vertical()
-                .auto_shrink([false; 2])
-                .stick_to_bottom(self.auto_scroll)
-                .show(ui, |ui| {
-                    for line in &self.log {
-                        let color = if line.starts_with("You>") {
-                            egui::Color32::from_rgb(160, 220, 255)
-                        } else if line.starts_with("Nox>") {
-                            egui::Color32::from_rgb(180, 255, 180)
-                        } else {
-                            egui::Color32::LIGHT_GRAY
-                        };
-                        ui.colored_label(color, line);
-                    }
-                    if self.streaming_active && !self.streaming_buffer.is_empty() {
-                        ui.separator();
-                        ui.colored_label(
-                            egui::Color32::from_rgb(200, 255, 200),
-                            format!("Nox (streaming)> {}", self.streaming_buffer),
-                        );
-                    }
-                });
-        });
+    fn send_prompt(&mut self, queued: Queued) {
+        let Queued { prompt, context } = queued;
+        let system = self.note_system();
+        self.push_line(LogEntry::new(LogRole::User, prompt.clone()));
+        if !context.is_empty() {
+            let files: Vec<String> = context
+                .iter()
+                .map(|file| format!("{} ({})", file.name, attach::size_label(file.size())))
+                .collect();
+            self.push_line(format!("📎 {}", files.join(", ")));
+        }
+        self.record(Record::new(Role::User, prompt.clone()));
+        // A new exchange; the reply set aside for the last one is done with.
+        self.undo = None;
+        self.sent_context = context;
+        self.dispatch(prompt, system, None);
+    }
 
-        egui::TopBottomPanel::bottom("input_panel").show(ctx, |ui| {
-            ui.separator();
-            let input_field = ui.text_edit_singleline(&mut self.input);
-            if input_field.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                self.submit_prompt();
-            }
-            ui.horizontal(|ui| {
-                if ui.button("Send").clicked() {
-                    self.submit_prompt();
-                }
-            });
-            if !input_field.has_focus() {
-                input_field.request_focus();
-            }
-        });
+    /// Logs and records the system prompt if it changed since the last one
+    /// recorded, and returns it.
+    fn note_system(&mut self) -> String {
+        let system = self.system.trim().to_string();
+        let recorded = self.recorded_system();
+        if recorded.as_deref().unwrap_or_default() != system {
+            let updated = recorded.is_some();
+            self.push_line(LogEntry::new(LogRole::System { updated }, system.clone()));
+            self.record(Record::new(Role::System, system.clone()));
+        }
+        system
     }
-}
 
-#[derive(Serialize)]
-struct PromptPayload<'a> {
-    #[serde(rename = "type")]
-    kind: &'a str,
-    text: &'a str,
-}
+    fn dispatch(&mut self, prompt: String, system: String, seed: Option<u64>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sent.push_back((id, Instant::now()));
+        self.send_job(BackendJob::Prompt(Prompt {
+            text: prompt,
+            params: self.sampling,
+            system: (!system.is_empty()).then_some(system),
+            id: self.multiplex.then_some(id),
+            seed,
+            context: self.sent_context.clone(),
+        }));
+    }
 
-#[derive(Deserialize)]
-struct BridgeMessage {
-    #[serde(rename = "type")]
-    kind: String,
-    text: Option<String>,
-    message: Option<String>,
-}
+    /// Whether anything is on its way or waiting to go, or a model is
+    /// loading, which rules out redoing the last exchange.
+    fn busy(&self) -> bool {
+        !self.sent.is_empty()
+            || !self.pending.is_empty()
+            || self.models.switching(Instant::now()).is_some()
+    }
 
-enum BackendEvent {
-    Log(String),
-    Status(String),
-    Delta(String),
-    Done(String),
-    Error(String),
-}
+    /// Has the bridge load `name`; prompts wait until it has.
+    fn switch_model(&mut self, name: String) {
+        if self.busy() || !self.models.request(&name, Instant::now()) {
+            return;
+        }
+        self.status_line = format!("Loading {}…", name);
+        self.send_job(BackendJob::SetModel(name));
+    }
 
-enum BackendJob {
-    Prompt { text: String },
-}
+    /// The role of the reply the log ends on, if it can be paired with its
+    /// prompt: Regenerate wants a finished one, Retry a failed or stopped one.
+    fn last_reply(&self) -> Option<LogRole> {
+        let exchange = log::last_exchange(&self.log)?;
+        Some(self.log[exchange.reply].role)
+    }
 
-struct Backend;
+    /// Takes the last reply off the log, keeping it for Undo, and asks for
+    /// another with the current settings and a fresh seed.
+    fn reroll(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let Some(exchange) = log::last_exchange(&self.log) else {
+            return;
+        };
+        let prompt = self.log[exchange.prompt].text.clone();
+        let Some(reply) = self.log.remove(exchange.reply) else {
+            return;
+        };
+        // Failed replies were never recorded. The session file keeps the
+        // others; only the conversation that exports read drops them.
+        let record = match reply.role {
+            LogRole::Failed => None,
+            _ => self.take_reply_record(),
+        };
+        self.undo = Some((reply, record));
+        let system = self.note_system();
+        self.dispatch(prompt, system, Some(fresh_seed()));
+    }
 
-impl Backend {
-    fn spawn(rx: Receiver<BackendJob>, tx: Sender<BackendEvent>) {
-        thread::spawn(move || {
-            match RemoteBackend::connect(BRIDGE_ADDR, tx.clone()) {
-                Ok(backend) => backend.run(rx, tx),
-                Err(err) => {
-                    let _ = tx.send(BackendEvent::Error(format!(
-                        "Bridge unavailable: {}. Falling back to simulated echo.",
-                        err
-                    )));
-                    Self::run_simulated(rx, tx);
-                }
+    /// Puts back the reply Regenerate or Retry set aside, swapping out the
+    /// one that came after it.
+    fn undo_reroll(&mut self) {
+        if self.busy() {
+            return;
+        }
+        let Some((entry, record)) = self.undo.take() else {
+            return;
+        };
+        if let Some(exchange) = log::last_exchange(&self.log) {
+            if let Some(newer) = self.log.remove(exchange.reply) {
+                let newer_record = match newer.role {
+                    LogRole::Failed => None,
+                    _ => self.take_reply_record(),
+                };
+                self.undo = Some((newer, newer_record));
             }
-        });
+        }
+        self.push_line(entry);
+        if let Some(record) = record {
+            self.conversation.push(record);
+        }
     }
 
-    fn run_simulated(rx: Receiver<BackendJob>, tx: Sender<BackendEvent>) {
-        while let Ok(job) = rx.recv() {
-            match job {
-                BackendJob::Prompt { text } => {
-                    thread::sleep(Duration::from_millis(200));
-                    let _ = tx.send(BackendEvent::Done(format!("(simulated) {}", text)));
-                }
-            }
+    /// About how many tokens of the model's context the conversation takes
+    /// up, the reply streaming in included.
+    fn context_tokens(&self) -> usize {
+        let streaming = stats::estimate_tokens(self.streaming_buffer.chars().count());
+        (context::estimate(&self.log, self.system.trim()) + streaming)
+            .saturating_add_signed(self.correction)
+    }
+
+    /// Drops the oldest exchanges, here and in the bridge's history, until
+    /// the conversation fits comfortably in `size` tokens again.
+    fn trim_context(&mut self, size: usize) {
+        if self.busy() {
+            return;
         }
+        let dropped = context::trim(&mut self.log, self.system.trim(), size);
+        if dropped == 0 {
+            self.status_line = "Nothing to trim".to_string();
+            return;
+        }
+        // The bridge's count covered what was just dropped.
+        self.correction = 0;
+        let keep = self
+            .log
+            .iter()
+            .filter(|entry| entry.role == LogRole::User)
+            .count();
+        self.send_job(BackendJob::Trim(keep));
+        self.status_line = format!("Trimmed {} oldest exchange(s)", dropped);
     }
-}
 
-struct RemoteBackend {
-    writer: Arc<Mutex<TcpStream>>,
-}
+    /// Takes the last reply out of the conversation, if it ends on one.
+    fn take_reply_record(&mut self) -> Option<Record> {
+        match self.conversation.last() {
+            Some(record) if record.role == Role::Assistant => self.conversation.pop(),
+            _ => None,
+        }
+    }
 
-impl RemoteBackend {
-    fn connect(addr: &str, tx: Sender<BackendEvent>) -> Result<Self, String> {
-        let stream = TcpStream::connect(addr).map_err(|err| err.to_string())?;
-        stream
-            .set_nodelay(true)
-            .map_err(|err| err.to_string())?;
-        let reader_stream = stream
-            .try_clone()
-            .map_err(|err| err.to_string())?;
-        Self::spawn_reader(reader_stream, tx.clone());
-        let _ = tx.send(BackendEvent::Status(format!(
-            "Connected to Noctics bridge at {}",
-            addr
-        )));
-        Ok(Self {
-            writer: Arc::new(Mutex::new(stream)),
-        })
+    /// The system prompt the conversation last recorded, if it has one.
+    fn recorded_system(&self) -> Option<String> {
+        self.conversation
+            .iter()
+            .rev()
+            .find(|record| record.role == Role::System)
+            .map(|record| record.text.clone())
     }
 
-    fn spawn_reader(stream: TcpStream, tx: Sender<BackendEvent>) {
-        thread::spawn(move || {
-            let mut reader = BufReader::new(stream);
-            loop {
-                let mut line = String::new();
-                match reader.read_line(&mut line) {
-                    Ok(0) => {
-                        let _ = tx.send(BackendEvent::Error(
-                            "Bridge connection closed.".to_string(),
-                        ));
-                        break;
-                    }
-                    Ok(_) => {
-                        let trimmed = line.trim();
-                        if trimmed.is_empty() {
-                            continue;
-                        }
-                        match serde_json::from_str::<BridgeMessage>(trimmed) {
-                            Ok(msg) => match msg.kind.as_str() {
-                                "hello" => {
-                                    if let Some(message) = msg.message {
-                                        let _ = tx.send(BackendEvent::Status(message));
-                                    }
-                                }
-                                "delta" => {
-                                    if let Some(text) = msg.text {
-                                        let _ = tx.send(BackendEvent::Delta(text));
-                                    }
-                                }
-                                "done" => {
-                                    let text = msg.text.unwrap_or_default();
-                                    let _ = tx.send(BackendEvent::Done(text));
-                                }
-                                "log" => {
-                                    if let Some(text) = msg.text {
-                                        let _ = tx.send(BackendEvent::Log(text));
-                                    }
-                                }
-                                "error" => {
-                                    let text = msg
-                                        .message
-                                        .or(msg.text)
-                                        .unwrap_or_else(|| "Unknown bridge error".into());
-                                    let _ = tx.send(BackendEvent::Error(text));
-                                }
-                                other => {
-                                    let _ = tx.send(BackendEvent::Log(format!(
-                                        "Bridge> {}",
-                                        other
-                                    )));
-                                }
-                            },
-                            Err(err) => {
-                                let _ = tx.send(BackendEvent::Log(format!(
-                                    "Bridge parse error: {} :: {}",
-                                    err, trimmed
-                                )));
-                            }
-                        }
-                    }
-                    Err(err) => {
-                        let _ = tx.send(BackendEvent::Error(format!(
-                            "Bridge read failed: {}",
-                            err
-                        )));
-                        break;
-                    }
-                }
-            }
+    /// The last prompt this tab sent, for Ctrl+R.
+    fn last_prompt(&self) -> Option<String> {
+        self.conversation
+            .iter()
+            .rev()
+            .find(|record| record.role == Role::User)
+            .map(|record| record.text.clone())
+    }
+
+    fn stop_generation(&mut self) {
+        if !self.streaming_active {
+            return;
+        }
+        let partial = std::mem::take(&mut self.streaming_buffer);
+        self.finish_reply(partial, true);
+        self.streaming_active = false;
+        self.stopped = true;
+        self.send_job(BackendJob::Cancel);
+    }
+
+    /// Logs and records the oldest prompt's reply, which just ended.
+    fn finish_reply(&mut self, text: String, stopped: bool) {
+        let meter = self
+            .meter
+            .take()
+            .unwrap_or_else(|| stats::Meter::new(self.oldest_sent()));
+        self.log_reply(meter, text, stopped);
+    }
+
+    /// Logs and records a reply that just ended, with how fast it came.
+    fn log_reply(&mut self, meter: stats::Meter, text: String, stopped: bool) {
+        let now = Instant::now();
+        let mut stats = meter.finish(self.reported.take().as_ref());
+        if stats.model.is_none() {
+            stats.model = self.models.active().map(str::to_string);
+        }
+        let role = if stopped {
+            LogRole::Stopped
+        } else {
+            LogRole::Assistant
+        };
+        self.push_line(LogEntry {
+            stats: Some(stats.clone()),
+            ..LogEntry::new(role, text.clone())
         });
+        let mut record = Record::new(Role::Assistant, text);
+        record.elapsed_ms = meter
+            .first()
+            .map(|first| now.saturating_duration_since(first).as_millis() as u64);
+        record.stopped = stopped;
+        record.model = stats.model.clone();
+        record.stats = Some(stats.clone());
+        self.record(record);
+        self.last_stats = Some(stats);
+    }
+
+    /// Puts `text` in the input box as a `> ` quote to follow up on.
+    fn quote(&mut self, text: &str) {
+        if !self.input.is_empty() && !self.input.ends_with('\n') {
+            self.input.push('\n');
+        }
+        for line in text.lines() {
+            self.input.push_str("> ");
+            self.input.push_str(line);
+            self.input.push('\n');
+        }
+    }
+
+    fn record(&mut self, record: Record) {
+        if let Some(writer) = &self.writer {
+            writer.append(record.clone());
+        }
+        self.conversation.push(record);
     }
 
-    fn run(self, rx: Receiver<BackendJob>, tx: Sender<BackendEvent>) {
-        while let Ok(job) = rx.recv() {
-            match job {
-                BackendJob::Prompt { text } => {
-                    if let Err(err) = self.send_prompt(&text) {
-                        let _ = tx.send(BackendEvent::Error(format!(
-                            "Bridge send failed: {}",
-                            err
-                        )));
-                        let _ = tx.send(BackendEvent::Status(
-                            "Bridge connection lost. Using simulated echo.".into(),
-                        ));
-                        Backend::run_simulated(rx, tx);
-                        return;
+    fn open(&mut self, file: session::SessionFile) {
+        // A restored tab may never have had anything written to it.
+        let loaded = if file.path.exists() {
+            session::load(&file.path)
+        } else {
+            Ok((Vec::new(), 0))
+        };
+        match loaded {
+            Ok((mut records, skipped)) => {
+                let drafts = session::take_drafts(&mut records);
+                self.log.clear();
+                self.undo = None;
+                self.correction = 0;
+                let mut updated = false;
+                for record in &records {
+                    let mut entry = LogEntry::from(record);
+                    if record.role == Role::System {
+                        entry.role = LogRole::System { updated };
+                        updated = true;
+                    }
+                    self.push_line(entry);
+                }
+                self.status_line =
+                    format!("Opened session {}: {} messages", file.id, records.len());
+                if skipped > 0 {
+                    self.status_line += &format!(", {} corrupt lines skipped", skipped);
+                }
+                if !drafts.is_empty() {
+                    self.status_line +=
+                        &format!(", {} unsent prompts put back in the input", drafts.len());
+                }
+                // Back in the input rather than the queue, so nothing goes
+                // out until it is sent again.
+                for draft in drafts {
+                    if !self.input.trim().is_empty() {
+                        self.input.push_str("\n\n");
                     }
+                    self.input.push_str(&draft.text);
                 }
+                self.conversation = records;
+                self.system = self.recorded_system().unwrap_or_default();
+                self.switch_file(file);
             }
+            Err(err) => self.status_line = format!("Error: could not open session: {}", err),
+        }
+    }
+
+    fn switch_file(&mut self, next: session::SessionFile) {
+        if let Some(writer) = &self.writer {
+            writer.switch(next.path.clone());
+        }
+        self.file = Some(next);
+    }
+
+    /// Drops the current backend, and whatever it was answering, for the
+    /// one `route` leads to.
+    fn switch_backend(&mut self, route: Route) {
+        let _ = self.outbound.send(BackendJob::Quit);
+        let (job_tx, handle) = spawn_backend(&self.addr(), route, &self.events);
+        self.outbound = job_tx;
+        self.driver = Some(handle);
+        if !self.sent.is_empty() {
+            self.push_line("! Switched backends; prompts in flight were dropped".to_string());
+        }
+        self.streaming_active = false;
+        self.streaming_buffer.clear();
+        self.stopped = false;
+        self.queued = 0;
+        self.models = models::Models::default();
+        self.applied = None;
+        self.meter = None;
+        self.reported = None;
+        self.sent.clear();
+        self.side.clear();
+    }
+
+    fn addr(&self) -> String {
+        self.last_good_addr
+            .clone()
+            .unwrap_or_else(|| self.addr_input.trim().to_string())
+    }
+
+    fn send_job(&mut self, job: BackendJob) {
+        if let Err(err) = self.outbound.send(job) {
+            self.status_line = format!("Backend unavailable: {}", err);
         }
     }
 
-    fn send_prompt(&self, text: &str) -> Result<(), String> {
-        let payload = PromptPayload {
-            kind: "prompt",
-            text,
+    /// Writes the prompts still waiting to go out to the session file as
+    /// drafts, so closing the app doesn't lose them.
+    fn save_drafts(&mut self) {
+        let Some(writer) = &self.writer else {
+            return;
         };
-        let line = serde_json::to_string(&payload)
-            .map_err(|err| err.to_string())? + "\n";
-        let mut guard = self.writer.lock();
-        guard.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
-        guard.flush().map_err(|err| err.to_string())
+        for queued in self.pending.drain(..) {
+            writer.append(Record::draft(queued.prompt));
+        }
+    }
+
+    /// Waits until `deadline` for the backend thread, once it has been told
+    /// to quit, and for the session file to be written out.
+    fn finish(&mut self, deadline: Instant) {
+        if let Some(handle) = self.driver.take() {
+            if !shutdown::join_by(handle, deadline) {
+                eprintln!("{}: backend still busy at exit; not waiting for it", self.name);
+            }
+        }
+        if let Some(writer) = self.writer.take() {
+            if !writer.close(deadline) {
+                eprintln!("{}: session file still being written at exit", self.name);
+            }
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Ends the backend thread and closes its bridge connection or kills
+        // its nox; the session writer's thread ends when `writer` drops.
+        let _ = self.outbound.send(BackendJob::Quit);
+    }
+}
+
+/// The Copy and Quote buttons, and the time of an entry sent at `ts`, shown
+/// in the top right corner of a log entry drawn in `rect` while the pointer
+/// is over it.
+fn log_tools(
+    ui: &mut egui::Ui,
+    rect: egui::Rect,
+    text: &str,
+    ts: Option<u64>,
+) -> Option<LogAction> {
+    const WIDTH: f32 = 170.0;
+    let row = rect.with_max_x(ui.max_rect().right());
+    let bar = egui::Rect::from_min_size(
+        egui::pos2(row.right() - WIDTH, row.top()),
+        egui::vec2(WIDTH, row.height().max(18.0)),
+    );
+    let hovered = ui
+        .input(|i| i.pointer.hover_pos())
+        .is_some_and(|pos| ui.clip_rect().contains(pos) && row.union(bar).contains(pos));
+    if !hovered {
+        return None;
+    }
+    // A child of its own, so the buttons sit over the entry rather than
+    // pushing the next one down.
+    let mut tools = ui.child_ui(bar, egui::Layout::right_to_left(egui::Align::Min));
+    if tools
+        .small_button("Quote")
+        .on_hover_text("Quote it in the input box")
+        .clicked()
+    {
+        return Some(LogAction::Quote(text.to_string()));
+    }
+    if tools.small_button("Copy").clicked() {
+        return Some(LogAction::Copy(text.to_string()));
+    }
+    if let Some(ts) = ts {
+        // `YYYYMMDD-HHMMSS` down to `HH:MM:SS`.
+        let stamp = session::utc_stamp(ts / 1000);
+        let time = format!(
+            "{}:{}:{} UTC",
+            &stamp[9..11],
+            &stamp[11..13],
+            &stamp[13..15]
+        );
+        tools.label(egui::RichText::new(time).weak().small());
+    }
+    None
+}
+
+fn search_id(tab: usize) -> egui::Id {
+    egui::Id::new(("log_search", tab))
+}
+
+/// What the search bar found in a tab's log this frame.
+struct Found {
+    filtering: bool,
+    needle: String,
+    /// Indices into the log, oldest first.
+    matches: Vec<usize>,
+    /// The log index of the match last jumped to.
+    current: Option<usize>,
+    /// Scroll to it this frame.
+    scroll: bool,
+}
+
+impl Found {
+    /// Whether a reply still streaming in is shown under the search.
+    fn shows(&self, text: &str) -> bool {
+        !self.filtering || search::is_match(text, &self.needle)
+    }
+}
+
+/// The box above the log that filters it: Enter and Shift+Enter step
+/// through the matches, Esc clears it.
+fn search_bar(ui: &mut egui::Ui, idx: usize, tab: &mut Session) -> Found {
+    let id = search_id(idx);
+    let (mut next, mut prev, mut clear) = (false, false, false);
+    if ui.memory(|m| m.has_focus(id)) {
+        (prev, next, clear) = ui.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::SHIFT, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+    }
+    if clear {
+        tab.search.clear();
+    }
+    let texts = tab.log.iter().map(|entry| entry.text.as_str());
+    let matches = tab.search.matches(texts);
+    ui.horizontal(|ui| {
+        let edited = ui
+            .add(
+                egui::TextEdit::singleline(&mut tab.search.query)
+                    .id(id)
+                    .desired_width(220.0)
+                    .hint_text("Search the log"),
+            )
+            .on_hover_text("Enter: next match · Shift+Enter: previous · Esc: clear")
+            .changed();
+        if edited {
+            tab.search.edited();
+        }
+        if !tab.search.active() {
+            return;
+        }
+        let needle = tab.search.needle();
+        let streaming = std::iter::once(&tab.streaming_buffer)
+            .filter(|_| tab.streaming_active && !tab.streaming_buffer.is_empty())
+            .chain(tab.side.values().map(|side| &side.text));
+        let hidden_streaming = streaming.filter(|text| !search::is_match(text, &needle)).count();
+        let hidden = tab.log.len() - matches.len() + hidden_streaming;
+        let at = match tab.search.current(matches.len()) {
+            Some(at) => format!("{}/", at + 1),
+            None => String::new(),
+        };
+        ui.label(format!("{}{} matches · {} hidden", at, matches.len(), hidden));
+        prev |= ui.small_button("▲").on_hover_text("Previous (Shift+Enter)").clicked();
+        next |= ui.small_button("▼").on_hover_text("Next (Enter)").clicked();
+        if ui.small_button("×").on_hover_text("Clear (Esc)").clicked() {
+            tab.search.clear();
+        }
+    });
+    if next || prev {
+        tab.search.jump(matches.len(), next);
+    }
+    let current = tab.search.current(matches.len()).map(|at| matches[at]);
+    Found {
+        filtering: tab.search.active(),
+        needle: tab.search.needle(),
+        matches,
+        current,
+        scroll: tab.search.take_scroll(),
+    }
+}
+
+/// How full the model's context is, as a bar that turns amber and then red,
+/// with **Trim oldest turns** once there is something worth trimming.
+fn context_meter(ui: &mut egui::Ui, tab: &mut Session, size: usize, palette: &theme::Palette) {
+    let used = tab.context_tokens();
+    let share = used as f32 / size.max(1) as f32;
+    let color = if share >= context::DANGER {
+        palette.error
+    } else if share >= context::WARN {
+        palette.warn
+    } else {
+        ui.visuals().selection.bg_fill
+    };
+    ui.add(
+        egui::ProgressBar::new(share.min(1.0))
+            .desired_width(140.0)
+            .fill(color)
+            .text(format!("ctx {} / {}", used, size)),
+    )
+    .on_hover_text(
+        "Estimated tokens in this conversation: the system prompt and every \
+         turn, at about 4 characters each, or the bridge's own count when it \
+         sends one. Past the context window the model no longer sees the \
+         oldest turns, and the bridge may truncate them without saying so.",
+    );
+    let worth = share >= context::TRIM_TO && !tab.busy();
+    if ui
+        .add_enabled(worth, egui::Button::new("Trim oldest turns"))
+        .on_hover_text("Drop the oldest exchanges until under 60%; the system prompt stays")
+        .clicked()
+    {
+        tab.trim_context(size);
+    }
+}
+
+/// A seed for a regenerated reply, different each time.
+fn fresh_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    // Kept to 32 bits, which every sampler takes.
+    std::collections::hash_map::RandomState::new().build_hasher().finish() >> 32
+}
+
+/// A reply's numbers in small print under it; hovering shows what the
+/// backend sent for them.
+fn stats_footer(ui: &mut egui::Ui, stats: &stats::Stats) {
+    let footer = ui.label(egui::RichText::new(stats.footer()).small().weak());
+    let raw = stats.raw_json().unwrap_or_else(|| {
+        "No raw stats: the backend sent none, or the reply came from the session file"
+            .to_string()
+    });
+    footer.on_hover_text(egui::RichText::new(raw).monospace());
+}
+
+/// The bridge's GPU numbers, newest first in each row, with a sparkline of
+/// the recent history under each.
+fn telemetry_strip(ui: &mut egui::Ui, telemetry: &telemetry::Telemetry) {
+    let shown = |value: Option<f32>, unit: &str, places: usize| match value {
+        Some(value) => format!("{:.*}{}", places, value, unit),
+        None => "—".to_string(),
+    };
+    let vram_total = telemetry.latest(|sample| sample.vram_total_mb);
+    let vram = match (telemetry.latest(|sample| sample.vram_used_mb), vram_total) {
+        (used, Some(total)) => format!("{} / {:.0} MB", shown(used, "", 0), total),
+        (used, None) => shown(used, " MB", 0),
+    };
+    let rows: [(&str, String, telemetry::Field, Option<f32>); 4] = [
+        (
+            "GPU",
+            shown(telemetry.latest(|sample| sample.gpu_util), "%", 0),
+            |sample| sample.gpu_util,
+            Some(100.0),
+        ),
+        ("VRAM", vram, |sample| sample.vram_used_mb, vram_total),
+        (
+            "Speed",
+            shown(telemetry.latest(|sample| sample.tps), " tok/s", 1),
+            |sample| sample.tps,
+            None,
+        ),
+        (
+            "Temp",
+            shown(telemetry.latest(|sample| sample.temp_c), " °C", 0),
+            |sample| sample.temp_c,
+            None,
+        ),
+    ];
+    egui::Grid::new("telemetry_grid").num_columns(2).show(ui, |ui| {
+        for (name, value, field, top) in rows {
+            ui.label(name);
+            ui.vertical(|ui| {
+                ui.label(egui::RichText::new(value).monospace());
+                sparkline(ui, &telemetry.series(field), top);
+            });
+            ui.end_row();
+        }
+    });
+}
+
+/// A small line of `values` from zero up to `top`, or up to their largest
+/// when there's no fixed top.
+fn sparkline(ui: &mut egui::Ui, values: &[f32], top: Option<f32>) {
+    let size = egui::vec2(ui.available_width().clamp(60.0, 160.0), 20.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    let values = telemetry::downsample(values, rect.width() as usize);
+    if values.len() < 2 {
+        return;
+    }
+    let top = top
+        .unwrap_or_else(|| values.iter().copied().fold(0.0, f32::max))
+        .max(f32::EPSILON);
+    let step = rect.width() / (values.len() - 1) as f32;
+    let points = values
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            let height = (value / top).clamp(0.0, 1.0) * (rect.height() - 2.0);
+            egui::pos2(rect.left() + idx as f32 * step, rect.bottom() - 1.0 - height)
+        })
+        .collect();
+    let stroke = egui::Stroke::new(1.0, ui.visuals().selection.bg_fill);
+    painter.add(egui::Shape::line(points, stroke));
+}
+
+/// Whether the prompt field's cursor is on its first line and on its last.
+fn cursor_lines(ctx: &egui::Context, id: egui::Id, text: &str) -> (bool, bool) {
+    let cursor = egui::TextEdit::load_state(ctx, id)
+        .and_then(|state| state.cursor.char_range())
+        .map(|range| range.primary.index);
+    let Some(cursor) = cursor else {
+        return (true, true);
+    };
+    let before = text.chars().take(cursor).filter(|&c| c == '\n').count();
+    (before == 0, before == text.matches('\n').count())
+}
+
+fn move_cursor_to_end(ctx: &egui::Context, id: egui::Id, text: &str) {
+    let mut state = egui::TextEdit::load_state(ctx, id).unwrap_or_default();
+    let end = egui::text::CCursor::new(text.chars().count());
+    state
+        .cursor
+        .set_char_range(Some(egui::text::CCursorRange::one(end)));
+    state.store(ctx, id);
+}
+
+/// How the tabs reach a backend, apart from each one's bridge address.
+#[derive(Clone)]
+struct Route {
+    /// The nox binary to run instead of using the bridge.
+    local: Option<PathBuf>,
+    token: Option<String>,
+    fallback: Fallback,
+}
+
+/// Starts a tab's backend: nox run locally, or the bridge at `addr`, as
+/// `route` says. Its thread handle is returned so exit can wait for it.
+fn spawn_backend(
+    addr: &str,
+    route: Route,
+    events: &Sender<BackendEvent>,
+) -> (JobTx, thread::JoinHandle<()>) {
+    let (job_tx, job_rx) = mpsc::channel::<BackendJob>();
+    let target = match route.local {
+        Some(binary) => Target::Local(binary),
+        None => Target::Bridge {
+            addr: addr.to_string(),
+            token: route.token,
+        },
+    };
+    let handle = Driver::spawn(target, route.fallback, job_rx, job_tx.clone(), events.clone());
+    (job_tx, handle)
+}
+
+struct DemoApp {
+    tabs: Vec<Session>,
+    active: usize,
+    tabs_opened: usize,
+    renaming: Option<(usize, String)>,
+    bridge_addr: String,
+    auto_scroll: bool,
+    /// As last written to the settings file.
+    settings: Settings,
+    /// Settings that differ from the file, and since when they have.
+    unsaved: Option<(Settings, Instant)>,
+    restore_offer: Option<Tabs>,
+    session_list: Option<Vec<session::SessionFile>>,
+    export_dir: String,
+    backend: BackendKind,
+    nox_binary: String,
+    fallback: Fallback,
+    bridge_token: String,
+    history: history::History,
+    diagnostics: diagnostics::View,
+    multiplex: bool,
+    context_size: usize,
+    /// The largest file Attach takes, in KiB.
+    attach_limit_kb: u64,
+    /// Show each reply's numbers under it.
+    footers: bool,
+    notify: bool,
+    /// In seconds.
+    notify_after_s: f32,
+    notify_bell: bool,
+    notifier: notify::Notifier,
+    theme: theme::Theme,
+    /// The colors for the theme in use, picked each frame.
+    palette: theme::Palette,
+    /// Text scale, from [`theme::MIN_ZOOM`] to [`theme::MAX_ZOOM`].
+    zoom: f32,
+    /// The zoom the text styles were last set for.
+    zoomed: Option<f32>,
+    /// When the zoom last changed, to show it in the status bar for a bit.
+    zoom_changed: Option<Instant>,
+}
+
+impl DemoApp {
+    fn new(bridge_addr: String, settings: Settings) -> Self {
+        let last_session = settings
+            .last_session
+            .as_deref()
+            .and_then(session::named)
+            .filter(|file| file.path.exists());
+        let restore_offer = match Tabs::load() {
+            Some(saved) => Some(saved).filter(|saved| !saved.tabs.is_empty()),
+            None => last_session
+                .or_else(|| session::list().into_iter().next())
+                .map(|latest| Tabs {
+                    active: 0,
+                    tabs: vec![TabState {
+                        session: Some(latest.id),
+                        ..Default::default()
+                    }],
+                }),
+        };
+        let export_dir = settings
+            .export_dir
+            .clone()
+            .or_else(|| export::default_dir().map(|dir| dir.display().to_string()))
+            .unwrap_or_default();
+        let backend = settings.backend;
+        let nox_binary = settings.nox_binary.clone().unwrap_or_default();
+        let fallback = settings.fallback;
+        let bridge_token = initial_bridge_token(&settings);
+        let history = history::History::new(settings.history.clone());
+        let diagnostics = settings.diagnostics;
+        let multiplex = settings.multiplex;
+        let context_size = settings.context_size.unwrap_or(context::DEFAULT_SIZE);
+        let attach_limit_kb = settings.attach_limit_kb.unwrap_or(attach::DEFAULT_LIMIT_KB);
+        let auto_scroll = settings.auto_scroll.unwrap_or(true);
+        let footers = settings.footers.unwrap_or(true);
+        let notify = settings.notify;
+        let notify_after_s = settings
+            .notify_after_s
+            .unwrap_or(notify::DEFAULT_AFTER.as_secs_f32());
+        let notify_bell = settings.notify_bell;
+        let theme = settings.theme;
+        let zoom = settings.zoom.map_or(1.0, |zoom| theme::step_zoom(zoom, 0));
+        let mut app = Self {
+            tabs: Vec::new(),
+            active: 0,
+            tabs_opened: 0,
+            renaming: None,
+            bridge_addr,
+            auto_scroll,
+            settings,
+            unsaved: None,
+            restore_offer,
+            session_list: None,
+            export_dir,
+            backend,
+            nox_binary,
+            fallback,
+            bridge_token,
+            history,
+            diagnostics,
+            multiplex,
+            context_size,
+            attach_limit_kb,
+            footers,
+            notify,
+            notify_after_s,
+            notify_bell,
+            notifier: notify::Notifier::default(),
+            theme,
+            palette: theme::Palette::new(true),
+            zoom,
+            zoomed: None,
+            zoom_changed: None,
+        };
+        app.new_tab();
+        app
+    }
+
+    /// The settings as the app has them now.
+    fn snapshot(&self) -> Settings {
+        let mut settings = self.settings.clone();
+        let tab = &self.tabs[self.active];
+        if let Some(addr) = tab
+            .last_good_addr
+            .clone()
+            .or_else(|| self.tabs.iter().find_map(|tab| tab.last_good_addr.clone()))
+        {
+            settings.bridge_addr = Some(addr);
+        }
+        settings.sampling = tab.sampling;
+        let export_dir = self.export_dir.trim();
+        let default_dir = export::default_dir().map(|dir| dir.display().to_string());
+        settings.export_dir = (!export_dir.is_empty()
+            && default_dir.as_deref() != Some(export_dir))
+        .then(|| export_dir.to_string());
+        settings.backend = self.backend;
+        settings.fallback = self.fallback;
+        // A token from the environment stays out of the settings file.
+        if std::env::var_os("NOX_BRIDGE_TOKEN").is_none() {
+            settings.bridge_token = self.token();
+        }
+        let nox_binary = self.nox_binary.trim();
+        settings.nox_binary = (!nox_binary.is_empty()).then(|| nox_binary.to_string());
+        settings.history = self.history.entries();
+        settings.diagnostics = self.diagnostics;
+        settings.multiplex = self.multiplex;
+        settings.context_size =
+            (self.context_size != context::DEFAULT_SIZE).then_some(self.context_size);
+        settings.attach_limit_kb =
+            (self.attach_limit_kb != attach::DEFAULT_LIMIT_KB).then_some(self.attach_limit_kb);
+        settings.auto_scroll = (!self.auto_scroll).then_some(false);
+        settings.footers = (!self.footers).then_some(false);
+        settings.notify = self.notify;
+        settings.notify_after_s = (self.notify_after_s != notify::DEFAULT_AFTER.as_secs_f32())
+            .then_some(self.notify_after_s);
+        settings.notify_bell = self.notify_bell;
+        settings.last_session = tab.file.as_ref().map(|file| file.id.clone());
+        settings.theme = self.theme;
+        settings.zoom = (self.zoom != 1.0).then_some(self.zoom);
+        settings
+    }
+
+    /// Puts egui in the theme's visuals, leaving them be when they already
+    /// match, and picks its palette.
+    fn apply_theme(&mut self, ctx: &egui::Context, system: Option<eframe::Theme>) {
+        let dark = self.theme.dark(system);
+        if ctx.style().visuals.dark_mode != dark {
+            ctx.set_visuals(theme::visuals(dark));
+        }
+        self.palette = theme::Palette::new(dark);
+    }
+
+    /// Scales the text by `steps` tenths, or back to 1 with `None`.
+    fn zoom(&mut self, steps: Option<i32>) {
+        self.zoom = match steps {
+            Some(steps) => theme::step_zoom(self.zoom, steps),
+            None => 1.0,
+        };
+        self.zoom_changed = Some(Instant::now());
+    }
+
+    fn apply_zoom(&mut self, ctx: &egui::Context) {
+        if self.zoomed == Some(self.zoom) {
+            return;
+        }
+        let base = egui::Style::default().text_styles;
+        ctx.style_mut(|style| style.text_styles = theme::zoomed(&base, self.zoom));
+        self.zoomed = Some(self.zoom);
+    }
+
+    /// Writes the settings once they have stopped changing for
+    /// [`SETTINGS_DELAY`], so dragging a slider doesn't write every frame.
+    fn autosave(&mut self, ctx: &egui::Context) {
+        let current = self.snapshot();
+        if current == self.settings {
+            self.unsaved = None;
+            return;
+        }
+        let now = Instant::now();
+        match &self.unsaved {
+            Some((unsaved, since)) if *unsaved == current => {
+                let waited = now.saturating_duration_since(*since);
+                if waited >= SETTINGS_DELAY {
+                    self.write_settings(current);
+                } else {
+                    ctx.request_repaint_after(SETTINGS_DELAY - waited);
+                }
+            }
+            _ => {
+                self.unsaved = Some((current, now));
+                ctx.request_repaint_after(SETTINGS_DELAY);
+            }
+        }
+    }
+
+    /// A failed write isn't retried until the settings change again.
+    fn write_settings(&mut self, settings: Settings) {
+        if let Err(err) = settings.save() {
+            eprintln!("Could not save GPU console settings: {}", err);
+        }
+        self.settings = settings;
+        self.unsaved = None;
+    }
+
+    /// Flashes the window, badges its title, and rings the bell if asked,
+    /// for each slow reply that ended while it was unfocused; focus clears
+    /// the badge. The replies' times are taken either way.
+    fn notify(&mut self, ctx: &egui::Context) {
+        let focused = ctx.input(|i| i.viewport().focused).unwrap_or(true);
+        if self.notifier.focus(focused) {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.notifier.title()));
+        }
+        let after = Duration::from_secs_f32(self.notify_after_s.max(0.0));
+        let mut alert = false;
+        for tab in &mut self.tabs {
+            for took in tab.ended.drain(..) {
+                alert |= self.notify && self.notifier.finished(took, after);
+            }
+        }
+        if !alert {
+            return;
+        }
+        ctx.send_viewport_cmd(egui::ViewportCommand::Title(self.notifier.title()));
+        ctx.send_viewport_cmd(egui::ViewportCommand::RequestUserAttention(
+            egui::UserAttentionType::Informational,
+        ));
+        if self.notify_bell {
+            eprint!("\x07");
+        }
+    }
+
+    /// Puts everything the settings file holds back as a fresh install has
+    /// it, except the prompt history.
+    fn reset_settings(&mut self) {
+        self.auto_scroll = true;
+        self.footers = true;
+        self.notify = false;
+        self.notify_after_s = notify::DEFAULT_AFTER.as_secs_f32();
+        self.notify_bell = false;
+        self.export_dir = export::default_dir()
+            .map(|dir| dir.display().to_string())
+            .unwrap_or_default();
+        self.nox_binary.clear();
+        self.bridge_token.clear();
+        self.diagnostics = diagnostics::View::default();
+        self.context_size = context::DEFAULT_SIZE;
+        self.attach_limit_kb = attach::DEFAULT_LIMIT_KB;
+        self.multiplex = false;
+        self.theme = theme::Theme::default();
+        self.zoom = 1.0;
+        self.fallback = Fallback::default();
+        let fallback = self.fallback;
+        for tab in &mut self.tabs {
+            tab.multiplex = false;
+            tab.send_job(BackendJob::SetFallback(fallback));
+        }
+        if self.backend != BackendKind::default() {
+            self.backend = BackendKind::default();
+            self.switch_backend();
+        }
+        let tab = self.tab();
+        tab.sampling = SamplingParams::default();
+        tab.addr_input = DEFAULT_BRIDGE_ADDR.to_string();
+        tab.status_line = "Settings reset to defaults".to_string();
+    }
+
+    fn tab(&mut self) -> &mut Session {
+        &mut self.tabs[self.active]
+    }
+
+    fn new_tab(&mut self) {
+        let (addr, sampling, system) = match self.tabs.get(self.active) {
+            Some(tab) => (tab.addr(), tab.sampling, tab.system.clone()),
+            None => (self.bridge_addr.clone(), self.settings.sampling, String::new()),
+        };
+        let file = session::create(&self.open_ids());
+        self.tabs_opened += 1;
+        let name = format!("Session {}", self.tabs_opened);
+        let mut tab = Session::new(name, addr, sampling, file, self.route());
+        tab.system = system;
+        tab.multiplex = self.multiplexed();
+        self.tabs.push(tab);
+        self.active = self.tabs.len() - 1;
+    }
+
+    fn close_tab(&mut self, idx: usize) {
+        if idx >= self.tabs.len() {
+            return;
+        }
+        self.tabs.remove(idx);
+        self.renaming = None;
+        if self.tabs.is_empty() {
+            self.active = 0;
+            self.new_tab();
+        } else if self.active > idx || self.active == self.tabs.len() {
+            self.active -= 1;
+        }
+    }
+
+    fn route(&self) -> Route {
+        Route {
+            local: (self.backend == BackendKind::Local).then(|| local::binary(&self.nox_binary)),
+            token: self.token(),
+            fallback: self.fallback,
+        }
+    }
+
+    /// Only the bridge tags replies by prompt; nox answers one at a time.
+    fn multiplexed(&self) -> bool {
+        self.multiplex && self.backend == BackendKind::Bridge
+    }
+
+    fn token(&self) -> Option<String> {
+        Some(self.bridge_token.trim().to_string()).filter(|token| !token.is_empty())
+    }
+
+    /// Moves every tab to the chosen backend.
+    fn switch_backend(&mut self) {
+        let route = self.route();
+        let multiplex = self.multiplexed();
+        for tab in &mut self.tabs {
+            tab.switch_backend(route.clone());
+            tab.multiplex = multiplex;
+        }
+    }
+
+    fn cycle_tab(&mut self, forward: bool) {
+        let len = self.tabs.len();
+        self.active = if forward {
+            (self.active + 1) % len
+        } else {
+            (self.active + len - 1) % len
+        };
+    }
+
+    fn open_ids(&self) -> Vec<&str> {
+        self.tabs
+            .iter()
+            .filter_map(|tab| tab.file.as_ref())
+            .map(|file| file.id.as_str())
+            .collect()
+    }
+
+    fn restore(&mut self, saved: Tabs) {
+        self.tabs.clear();
+        self.renaming = None;
+        for state in saved.tabs {
+            self.tabs_opened += 1;
+            let name = state
+                .name
+                .unwrap_or_else(|| format!("Session {}", self.tabs_opened));
+            let addr = state.addr.unwrap_or_else(|| self.bridge_addr.clone());
+            let sampling = state.sampling.unwrap_or(self.settings.sampling);
+            let file = state.session.as_deref().and_then(session::named);
+            let mut tab = Session::new(name, addr, sampling, file.clone(), self.route());
+            // Before opening, which adds the session's unsent prompts to it.
+            tab.input = state.input;
+            if let Some(file) = file {
+                tab.open(file);
+            }
+            tab.multiplex = self.multiplexed();
+            self.tabs.push(tab);
+        }
+        if self.tabs.is_empty() {
+            self.new_tab();
+        }
+        self.active = saved.active.min(self.tabs.len() - 1);
+    }
+
+    /// The tabs worth offering back next time: those with a conversation or
+    /// a draft.
+    fn saved_tabs(&self) -> Tabs {
+        let mut saved = Tabs::default();
+        for (idx, tab) in self.tabs.iter().enumerate() {
+            if tab.conversation.is_empty()
+                && tab.input.trim().is_empty()
+                && tab.pending.is_empty()
+            {
+                continue;
+            }
+            if idx == self.active {
+                saved.active = saved.tabs.len();
+            }
+            saved.tabs.push(TabState {
+                name: Some(tab.name.clone()),
+                session: tab.file.as_ref().map(|file| file.id.clone()),
+                addr: Some(tab.addr()),
+                sampling: Some(tab.sampling),
+                input: tab.input.clone(),
+            });
+        }
+        saved
+    }
+
+    fn export(&mut self, format: export::Format) {
+        let dir = self.export_dir.trim().to_string();
+        let tab = &mut self.tabs[self.active];
+        let partial = (tab.streaming_active && !tab.stopped)
+            .then(|| Record::new(Role::Assistant, tab.streaming_buffer.clone()));
+        let contents = format.render(&tab.conversation, partial.as_ref());
+        if dir.is_empty() {
+            tab.status_line = "Error: set an export directory first".to_string();
+            return;
+        }
+        match export::save(Path::new(&dir), format, &contents) {
+            Ok(path) => {
+                tab.status_line = format!("Exported to {}", path.display());
+                tab.push_line(format!("Exported to {}", path.display()));
+            }
+            Err(err) => tab.status_line = format!("Error: export failed: {}", err),
+        }
+    }
+
+    fn new_session(&mut self) {
+        let Some(fresh) = session::create(&self.open_ids()) else {
+            self.tab().status_line = "No data directory for sessions".to_string();
+            return;
+        };
+        let tab = self.tab();
+        tab.log.clear();
+        tab.undo = None;
+        tab.correction = 0;
+        tab.conversation.clear();
+        tab.status_line = format!("New session {}", fresh.id);
+        tab.switch_file(fresh);
+        self.session_list = None;
+    }
+
+    fn open_session(&mut self, chosen: session::SessionFile) {
+        self.session_list = None;
+        // Two tabs appending to one file would interleave their exchanges.
+        let open = self
+            .tabs
+            .iter()
+            .position(|tab| tab.file.as_ref().is_some_and(|file| file.id == chosen.id));
+        match open {
+            Some(idx) => self.active = idx,
+            None => self.tab().open(chosen),
+        }
+    }
+
+    /// Bridge logs, parse failures, warnings, and errors for the active tab,
+    /// behind a header that counts what came in while it was shut.
+    fn diagnostics_drawer(&mut self, ui: &mut egui::Ui) {
+        let view = &mut self.diagnostics;
+        let tab = &mut self.tabs[self.active];
+        let palette = self.palette;
+        if view.open {
+            tab.diagnostics.unseen = 0;
+        }
+        ui.horizontal(|ui| {
+            let arrow = if view.open { "▾" } else { "▸" };
+            let mut header = format!("{} Diagnostics", arrow);
+            if tab.diagnostics.unseen > 0 {
+                header += &format!(" ({})", tab.diagnostics.unseen);
+            }
+            if ui.selectable_label(view.open, header).clicked() {
+                view.open = !view.open;
+            }
+            if !view.open {
+                return;
+            }
+            ui.separator();
+            for level in diagnostics::Level::ALL {
+                ui.radio_value(&mut view.level, level, level.name());
+            }
+            if ui.button("Copy all").clicked() {
+                ui.ctx().copy_text(tab.diagnostics.text(view.level));
+            }
+        });
+        if !view.open {
+            return;
+        }
+        egui::ScrollArea::vertical()
+            .id_source(("diagnostics", self.active))
+            .auto_shrink([false, true])
+            .max_height(180.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                for entry in tab.diagnostics.shown(view.level) {
+                    let color = match entry.level {
+                        diagnostics::Level::Info => palette.info,
+                        diagnostics::Level::Warn => palette.warn,
+                        diagnostics::Level::Error => palette.error,
+                    };
+                    ui.label(egui::RichText::new(entry.line()).monospace().color(color));
+                }
+            });
+    }
+
+    fn tab_bar(&mut self, ui: &mut egui::Ui) {
+        let mut select = None;
+        let mut close = None;
+        ui.horizontal_wrapped(|ui| {
+            for (idx, tab) in self.tabs.iter().enumerate() {
+                if let Some((renaming, name)) = &mut self.renaming {
+                    if *renaming == idx {
+                        let edit = ui.add(egui::TextEdit::singleline(name).desired_width(120.0));
+                        if edit.lost_focus() {
+                            let name = name.trim().to_string();
+                            if !name.is_empty() {
+                                select = Some((idx, Some(name)));
+                            }
+                            self.renaming = None;
+                        } else if !edit.has_focus() {
+                            edit.request_focus();
+                        }
+                        continue;
+                    }
+                }
+                let mut label = tab.name.clone();
+                if idx != self.active && tab.streaming_active {
+                    label.push_str(" ●");
+                }
+                let response = ui
+                    .selectable_label(idx == self.active, label)
+                    .on_hover_text("Double-click to rename");
+                if response.double_clicked() {
+                    self.renaming = Some((idx, tab.name.clone()));
+                } else if response.clicked() {
+                    select = Some((idx, None));
+                }
+                if ui.small_button("×").on_hover_text("Close (Ctrl+W)").clicked() {
+                    close = Some(idx);
+                }
+            }
+            if ui.button("+").on_hover_text("New tab (Ctrl+T)").clicked() {
+                select = Some((usize::MAX, None));
+            }
+        });
+        match select {
+            Some((usize::MAX, _)) => self.new_tab(),
+            Some((idx, Some(name))) => self.tabs[idx].name = name,
+            Some((idx, None)) => self.active = idx,
+            None => {}
+        }
+        if let Some(idx) = close {
+            self.close_tab(idx);
+        }
+    }
+}
+
+impl eframe::App for DemoApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        let (zoom_in, zoom_out, zoom_reset) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::Equals)
+                    || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Plus),
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::Minus),
+                i.consume_key(egui::Modifiers::COMMAND, egui::Key::Num0),
+            )
+        });
+        if zoom_in || zoom_out || zoom_reset {
+            self.zoom((!zoom_reset).then_some(if zoom_in { 1 } else { -1 }));
+            ctx.request_repaint_after(ZOOM_SHOWN);
+        }
+        self.apply_zoom(ctx);
+        self.apply_theme(ctx, frame.info().system_theme);
+
+        let mut any = false;
+        for tab in &mut self.tabs {
+            any |= tab.poll_incoming();
+        }
+        if any {
+            ctx.request_repaint();
+        }
+        self.notify(ctx);
+        if self.tabs.iter().any(|tab| !tab.sent.is_empty()) {
+            // Keeps the speed readout ticking between deltas.
+            ctx.request_repaint_after(Duration::from_millis(100));
+        }
+        let (new_tab, close_tab, prev_tab, next_tab, clear_log, regenerate, resend) =
+            ctx.input_mut(|i| {
+                (
+                    i.consume_key(egui::Modifiers::COMMAND, egui::Key::T),
+                    i.consume_key(egui::Modifiers::COMMAND, egui::Key::W),
+                    i.consume_key(egui::Modifiers::CTRL | egui::Modifiers::SHIFT, egui::Key::Tab),
+                    i.consume_key(egui::Modifiers::CTRL, egui::Key::Tab),
+                    i.consume_key(egui::Modifiers::COMMAND, egui::Key::L),
+                    i.consume_key(egui::Modifiers::COMMAND | egui::Modifiers::SHIFT, egui::Key::R),
+                    i.consume_key(egui::Modifiers::COMMAND, egui::Key::R),
+                )
+            });
+        if new_tab {
+            self.new_tab();
+        }
+        if close_tab {
+            self.close_tab(self.active);
+        }
+        if prev_tab || next_tab {
+            self.cycle_tab(next_tab);
+        }
+        if clear_log {
+            self.tab().log.clear();
+            self.tab().undo = None;
+            self.tab().correction = 0;
+        }
+        if regenerate && !self.tab().busy() {
+            match self.tab().last_reply() {
+                Some(LogRole::Assistant) => self.tab().reroll(),
+                _ => self.tab().status_line = "Nothing to regenerate".to_string(),
+            }
+        }
+        if resend && !matches!(self.tab().link, Link::Handshaking | Link::Refused) {
+            match self.tab().last_prompt() {
+                Some(prompt) => self.tab().queue_prompt(prompt, Vec::new()),
+                None => self.tab().status_line = "Nothing to resend yet".to_string(),
+            }
+        }
+        // In the search box Esc clears the search instead.
+        let searching = ctx.memory(|m| m.has_focus(search_id(self.active)));
+        if self.tab().streaming_active
+            && !searching
+            && ctx.input(|i| i.key_pressed(egui::Key::Escape))
+        {
+            self.tab().stop_generation();
+        }
+
+        egui::TopBottomPanel::top("status_panel").show(ctx, |ui| {
+            let tab = &mut self.tabs[self.active];
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new("Status:").strong());
+                ui.label(&tab.status_line);
+                if tab.queued > 0 {
+                    ui.label(egui::RichText::new(format!("queued: {}", tab.queued)).strong());
+                }
+                if let Some((name, took)) = tab.models.switching(Instant::now()) {
+                    let text = format!("loading {}… {:.1}s", name, took.as_secs_f32());
+                    ui.label(egui::RichText::new(text).strong());
+                    ctx.request_repaint_after(Duration::from_millis(100));
+                } else if let Some(model) = tab.models.active() {
+                    ui.label(egui::RichText::new(model).weak());
+                }
+                let sampling = ui.label(egui::RichText::new(tab.sampling.summary()).weak());
+                if let Some(applied) = &tab.applied {
+                    sampling.on_hover_text(format!("Applied: {}", applied));
+                }
+                context_meter(ui, tab, self.context_size, &self.palette);
+                let zoom_shown = self.zoom_changed.is_some_and(|at| at.elapsed() < ZOOM_SHOWN);
+                if zoom_shown {
+                    ui.label(format!("Zoom {:.0}%", self.zoom * 100.0));
+                }
+            });
+            let now = Instant::now();
+            if tab.streaming_active {
+                ui.horizontal(|ui| {
+                    ui.label(egui::RichText::new("Streaming…").italics());
+                    if let Some(meter) = &tab.meter {
+                        ui.label(egui::RichText::new(meter.live(now)).monospace());
+                    }
+                    if ui.button("Stop").on_hover_text("Esc").clicked() {
+                        tab.stop_generation();
+                    }
+                });
+            } else if !tab.sent.is_empty()
+                && !tab.stopped
+                && !matches!(tab.link, Link::Offline | Link::Refused)
+            {
+                let waiting = stats::Meter::new(tab.oldest_sent());
+                ui.label(egui::RichText::new(waiting.live(now)).monospace());
+            } else if let Some(last) = &tab.last_stats {
+                ui.label(egui::RichText::new(format!("Last reply: {}", last.detail())).weak());
+            }
+        });
+
+        egui::SidePanel::right("controls_panel").resizable(true).show(ctx, |ui| {
+            ui.heading("Options");
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.toggle_value(&mut self.auto_scroll, "Auto-scroll");
+                ui.toggle_value(&mut self.footers, "Reply stats")
+                    .on_hover_text("Show each reply's model, speed, and seed under it");
+            });
+            ui.checkbox(&mut self.notify, "Notify when done (if unfocused)");
+            ui.add_enabled_ui(self.notify, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("for replies of");
+                    ui.add(
+                        egui::DragValue::new(&mut self.notify_after_s)
+                            .clamp_range(0.0..=600.0)
+                            .speed(0.5)
+                            .suffix(" s"),
+                    );
+                    ui.label("or more");
+                    ui.checkbox(&mut self.notify_bell, "Bell");
+                });
+            });
+            ui.horizontal(|ui| {
+                ui.label("Theme");
+                for theme in theme::Theme::ALL {
+                    ui.selectable_value(&mut self.theme, theme, theme.name());
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label(format!("Text {:.0}%", self.zoom * 100.0));
+                if ui.small_button("−").on_hover_text("Ctrl+-").clicked() {
+                    self.zoom(Some(-1));
+                }
+                if ui.small_button("+").on_hover_text("Ctrl+=").clicked() {
+                    self.zoom(Some(1));
+                }
+                if ui.small_button("Reset").on_hover_text("Ctrl+0").clicked() {
+                    self.zoom(None);
+                }
+            });
+            ui.separator();
+            match &self.tabs[self.active].file {
+                Some(active) => ui.label(format!("Session {}", active.id)),
+                None => ui.label("Sessions are not saved (no data directory)"),
+            };
+            ui.horizontal(|ui| {
+                if ui.button("New session").clicked() {
+                    self.new_session();
+                }
+                if ui.button("Open session…").clicked() {
+                    self.session_list = match self.session_list {
+                        Some(_) => None,
+                        None => Some(session::list()),
+                    };
+                }
+            });
+            let mut chosen = None;
+            if let Some(list) = &self.session_list {
+                if list.is_empty() {
+                    ui.small("No saved sessions");
+                }
+                egui::ScrollArea::vertical()
+                    .id_source("session_list")
+                    .max_height(160.0)
+                    .show(ui, |ui| {
+                        for (idx, saved) in list.iter().enumerate() {
+                            if ui.button(&saved.id).clicked() {
+                                chosen = Some(idx);
+                            }
+                        }
+                    });
+            }
+            if let Some(idx) = chosen {
+                if let Some(mut list) = self.session_list.take() {
+                    self.open_session(list.swap_remove(idx));
+                }
+            }
+            ui.separator();
+            ui.label("Export…");
+            ui.text_edit_singleline(&mut self.export_dir)
+                .on_hover_text("Directory the exports are written to");
+            ui.horizontal(|ui| {
+                if ui.button("Markdown").clicked() {
+                    self.export(export::Format::Markdown);
+                }
+                if ui.button("JSON").clicked() {
+                    self.export(export::Format::Json);
+                }
+            });
+            ui.separator();
+            ui.label("Backend");
+            let mut switch = false;
+            ui.horizontal(|ui| {
+                let backend = &mut self.backend;
+                switch |= ui.radio_value(backend, BackendKind::Bridge, "Bridge").changed();
+                switch |= ui.radio_value(backend, BackendKind::Local, "Local nox").changed();
+            });
+            if self.backend == BackendKind::Local {
+                ui.text_edit_singleline(&mut self.nox_binary).on_hover_text(format!(
+                    "Path to nox; blank tries {}",
+                    local::DEFAULT_BINARIES.join(", ")
+                ));
+                switch |= ui.button("Apply & restart").clicked();
+            }
+            if switch {
+                self.switch_backend();
+            }
+            let local = self.backend == BackendKind::Local;
+            if !local {
+                ui.label("Bridge address");
+                ui.text_edit_singleline(&mut self.tabs[self.active].addr_input);
+                ui.label("Bridge token");
+                ui.add(egui::TextEdit::singleline(&mut self.bridge_token).password(true))
+                    .on_hover_text("Sent to the bridge on connect; blank sends none");
+                if ui.button("Apply & reconnect").clicked() {
+                    let token = self.token();
+                    let tab = &mut self.tabs[self.active];
+                    let addr = tab.addr_input.trim().to_string();
+                    tab.send_job(BackendJob::Connect { addr, token });
+                }
+            }
+            let tab = &mut self.tabs[self.active];
+            // Only for bridges that said what else they can run.
+            if !local && tab.link == Link::Online && !tab.models.offered().is_empty() {
+                let mut picked = None;
+                let shown = match tab.models.switching(Instant::now()) {
+                    Some((name, _)) => format!("{} (loading)", name),
+                    None => tab.models.active().unwrap_or("—").to_string(),
+                };
+                ui.add_enabled_ui(!tab.busy(), |ui| {
+                    ui.horizontal(|ui| {
+                        ui.label("Model");
+                        egui::ComboBox::from_id_source(("model", self.active))
+                            .selected_text(shown)
+                            .show_ui(ui, |ui| {
+                                for name in tab.models.offered() {
+                                    let active = tab.models.active() == Some(name.as_str());
+                                    if ui.selectable_label(active, name).clicked() {
+                                        picked = Some(name.clone());
+                                    }
+                                }
+                            });
+                    });
+                });
+                if let Some(name) = picked {
+                    tab.switch_model(name);
+                }
+            }
+            ui.separator();
+            egui::CollapsingHeader::new("System prompt")
+                .default_open(!tab.system.is_empty())
+                .show(ui, |ui| {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut tab.system)
+                            .desired_rows(4)
+                            .hint_text("Sent with every prompt; blank sends none"),
+                    );
+                });
+            if !local {
+                egui::CollapsingHeader::new("GPU telemetry")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        if tab.telemetry.is_empty() {
+                            ui.small("The bridge hasn't sent any stats");
+                        }
+                        telemetry_strip(ui, &tab.telemetry);
+                    });
+            }
+            ui.separator();
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut self.context_size)
+                        .clamp_range(256..=1_048_576)
+                        .speed(64),
+                );
+                ui.label("context window (tokens)");
+            });
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::DragValue::new(&mut self.attach_limit_kb)
+                        .clamp_range(1..=65_536)
+                        .speed(16),
+                );
+                ui.label("largest attachment (KiB)");
+            });
+            ui.separator();
+            ui.label("Sampling");
+            ui.add(egui::Slider::new(&mut tab.sampling.temp, 0.0..=2.0).text("temp"));
+            ui.add(egui::Slider::new(&mut tab.sampling.top_p, 0.0..=1.0).text("top_p"));
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut tab.sampling.top_k).clamp_range(0..=1000));
+                ui.label("top_k");
+            });
+            ui.horizontal(|ui| {
+                ui.add(egui::DragValue::new(&mut tab.sampling.max_tokens).clamp_range(1..=32768));
+                ui.label("max_tokens");
+            });
+            if ui.button("Reset to defaults").clicked() {
+                tab.sampling = SamplingParams::default();
+            }
+            if local {
+                ui.separator();
+                ui.small("Each prompt runs nox with NOX_PERSIST_PROTO=jsonl.");
+            } else {
+                if ui
+                    .add_enabled(tab.link != Link::Online, egui::Button::new("Reconnect now"))
+                    .clicked()
+                {
+                    tab.send_job(BackendJob::ReconnectNow);
+                }
+                let stuck = matches!(tab.link, Link::Offline | Link::Refused);
+                if ui
+                    .add_enabled(stuck, egui::Button::new("Use simulated echo"))
+                    .clicked()
+                {
+                    tab.send_job(BackendJob::UseSimulated);
+                }
+                let mut simulate = self.fallback == Fallback::Simulated;
+                if ui
+                    .checkbox(&mut simulate, "Fall back to simulated echo")
+                    .on_hover_text("Otherwise an unreachable bridge is retried with backoff")
+                    .changed()
+                {
+                    self.fallback = if simulate { Fallback::Simulated } else { Fallback::Retry };
+                    for tab in &mut self.tabs {
+                        tab.send_job(BackendJob::SetFallback(self.fallback));
+                    }
+                }
+                if ui
+                    .checkbox(&mut self.multiplex, "Send prompts without waiting")
+                    .on_hover_text(
+                        "For bridges that answer several prompts at once; \
+                         otherwise each waits for the reply before it",
+                    )
+                    .changed()
+                {
+                    let multiplex = self.multiplexed();
+                    for tab in &mut self.tabs {
+                        tab.multiplex = multiplex;
+                        tab.pump();
+                    }
+                }
+                ui.separator();
+                ui.label("Run bridge: python experiments/gpu_ui_demo/bridge_server.py");
+                ui.small("Prompts are forwarded to the real Noctics ChatClient over TCP.");
+            }
+            ui.separator();
+            if ui
+                .button("Reset settings")
+                .on_hover_text("Everything in the settings file but the prompt history")
+                .clicked()
+            {
+                self.reset_settings();
+            }
+        });
+
+        egui::TopBottomPanel::bottom("diagnostics_panel")
+            .resizable(self.diagnostics.open)
+            .show(ctx, |ui| self.diagnostics_drawer(ui));
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.tab_bar(ui);
+            ui.separator();
+            ui.heading("Session Stream");
+            ui.separator();
+            if let Some(offer) = &self.restore_offer {
+                let mut restore = false;
+                let mut dismiss = false;
+                ui.horizontal(|ui| {
+                    match offer.tabs.as_slice() {
+                        [only] if only.name.is_none() => ui.label(format!(
+                            "Restore the last session ({})?",
+                            only.session.as_deref().unwrap_or_default()
+                        )),
+                        tabs => ui.label(format!("Restore {} tab(s) from last time?", tabs.len())),
+                    };
+                    restore = ui.button("Restore").clicked();
+                    dismiss = ui.button("Start fresh").clicked();
+                });
+                if restore {
+                    if let Some(offer) = self.restore_offer.take() {
+                        self.restore(offer);
+                    }
+                } else if dismiss {
+                    self.restore_offer = None;
+                }
+                ui.separator();
+            }
+            let tab = &mut self.tabs[self.active];
+            let found = search_bar(ui, self.active, tab);
+            tab.follow.arrived(std::mem::take(&mut tab.arrived));
+            // Auto-scroll off leaves the log wherever it is put; on, it
+            // follows until scrolled up.
+            let follows = self.auto_scroll && tab.follow.sticks();
+            let jump = self.auto_scroll && tab.follow.take_jump();
+            let tab = &*tab;
+            let palette = self.palette;
+            let footers = self.footers;
+            let mut action = None;
+            let scrolled = egui::ScrollArea::vertical()
+                .id_source(("session_stream", self.active))
+                .auto_shrink([false; 2])
+                .stick_to_bottom(follows)
+                .show(ui, |ui| {
+                    for (idx, entry) in tab.log.iter().enumerate() {
+                        if found.filtering && found.matches.binary_search(&idx).is_err() {
+                            continue;
+                        }
+                        let footer = entry.stats.as_ref().filter(|_| footers);
+                        let shown = ui.scope(|ui| match (entry.role, entry.tag()) {
+                            (LogRole::User, Some(tag)) => {
+                                let text = format!("{} {}", tag, entry.text);
+                                ui.colored_label(palette.user, text);
+                            }
+                            (LogRole::Assistant, Some(tag)) => {
+                                let color = palette.assistant;
+                                markdown::show(ui, tag, &entry.text, color, false);
+                                if let Some(stats) = footer {
+                                    stats_footer(ui, stats);
+                                }
+                            }
+                            (LogRole::Stopped, Some(tag)) => {
+                                let color = palette.stopped;
+                                markdown::show(ui, tag, &entry.text, color, false);
+                                if let Some(stats) = footer {
+                                    stats_footer(ui, stats);
+                                }
+                            }
+                            (LogRole::Failed, Some(tag)) => {
+                                let text = format!("{} {}", tag, entry.text);
+                                ui.colored_label(palette.failed, text);
+                            }
+                            (LogRole::System { .. }, Some(tag)) => {
+                                let text = match entry.text.as_str() {
+                                    "" => format!("{} (none)", tag),
+                                    text => format!("{} {}", tag, text),
+                                };
+                                ui.label(egui::RichText::new(text).weak().italics());
+                            }
+                            _ => {
+                                ui.colored_label(palette.note, &entry.text);
+                            }
+                        });
+                        if found.current == Some(idx) {
+                            let stroke = ui.visuals().selection.stroke;
+                            ui.painter().rect_stroke(shown.response.rect.expand(2.0), 2.0, stroke);
+                            if found.scroll {
+                                shown.response.scroll_to_me(Some(egui::Align::Center));
+                            }
+                        }
+                        if entry.role != LogRole::Note {
+                            let rect = shown.response.rect;
+                            if let Some(asked) = log_tools(ui, rect, &entry.text, Some(entry.ts)) {
+                                action = Some(asked);
+                            }
+                        }
+                    }
+                    if tab.streaming_active
+                        && !tab.streaming_buffer.is_empty()
+                        && found.shows(&tab.streaming_buffer)
+                    {
+                        ui.separator();
+                        let shown = ui.scope(|ui| {
+                            let text = &tab.streaming_buffer;
+                            markdown::show(ui, "Nox (streaming)>", text, palette.streaming, true);
+                        });
+                        let text = &tab.streaming_buffer;
+                        if let Some(asked) = log_tools(ui, shown.response.rect, text, None) {
+                            action = Some(asked);
+                        }
+                    }
+                    for (id, side) in &tab.side {
+                        if !found.shows(&side.text) {
+                            continue;
+                        }
+                        ui.separator();
+                        let tag = format!("Nox (streaming #{})>", id);
+                        let shown = ui.scope(|ui| {
+                            markdown::show(ui, &tag, &side.text, palette.streaming, true);
+                        });
+                        let text = &side.text;
+                        if let Some(asked) = log_tools(ui, shown.response.rect, text, None) {
+                            action = Some(asked);
+                        }
+                    }
+                    if jump {
+                        ui.scroll_to_cursor(Some(egui::Align::BOTTOM));
+                    }
+                });
+            if self.auto_scroll {
+                let follow = &mut self.tabs[self.active].follow;
+                let view = scrolled.inner_rect;
+                let bottom = scrolled.state.offset.y + view.height();
+                follow.scrolled(scrolled.content_size.y - bottom);
+                if let Some(unseen) = follow.unseen() {
+                    let label = match unseen {
+                        0 => "↓ Latest".to_owned(),
+                        n => format!("↓ {} new", n),
+                    };
+                    let size = egui::vec2(96.0, 24.0);
+                    let at = view.right_bottom() - size - egui::vec2(16.0, 8.0);
+                    let pill = egui::Button::new(label).rounding(12.0);
+                    if ui.put(egui::Rect::from_min_size(at, size), pill).clicked() {
+                        follow.resume();
+                    }
+                }
+            }
+            match action {
+                Some(LogAction::Copy(text)) => ctx.copy_text(text),
+                Some(LogAction::Quote(text)) => self.tab().quote(&text),
+                None => {}
+            }
+        });
+
+        egui::TopBottomPanel::bottom("input_panel").show(ctx, |ui| {
+            ui.separator();
+            // Offline prompts queue, but nothing goes out before the bridge
+            // has said hello, after it has turned us away, or while it loads
+            // another model.
+            let tab = &mut self.tabs[self.active];
+            let open = !matches!(tab.link, Link::Handshaking | Link::Refused)
+                && tab.models.switching(Instant::now()).is_none();
+            let id = egui::Id::new(("prompt_input", self.active));
+            // Enter, Up, and Down are taken before the field sees them: Enter
+            // sends (Shift+Enter is a newline), and Up on the first line or
+            // Down on the last steps through the history.
+            let mut submit = false;
+            if open && ctx.memory(|m| m.has_focus(id)) {
+                let (first, last) = cursor_lines(ctx, id, &tab.input);
+                let (enter, up, down) = ctx.input_mut(|i| {
+                    let plain = !i.modifiers.shift;
+                    (
+                        plain && i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                        first && i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                        last && i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                    )
+                });
+                submit = enter;
+                let recalled = if up {
+                    tab.recall.older(&self.history, &tab.input)
+                } else if down {
+                    tab.recall.newer(&self.history)
+                } else {
+                    None
+                };
+                if let Some(text) = recalled {
+                    tab.input = text;
+                    move_cursor_to_end(ctx, id, &tab.input);
+                }
+            }
+            // The files going with the next prompt, each removable.
+            let mut detached = None;
+            if !tab.attachments.is_empty() {
+                ui.horizontal_wrapped(|ui| {
+                    for (idx, file) in tab.attachments.iter().enumerate() {
+                        let size = attach::size_label(file.size());
+                        let chip = format!("📎 {} · {}  ×", file.name, size);
+                        if ui.button(chip).on_hover_text("Remove").clicked() {
+                            detached = Some(idx);
+                        }
+                    }
+                });
+            }
+            if let Some(idx) = detached {
+                tab.attachments.remove(idx);
+            }
+            let mut load = false;
+            let mut cancel = false;
+            if let Some(path) = &mut tab.attach_path {
+                ui.horizontal(|ui| {
+                    let field = ui.add(
+                        egui::TextEdit::singleline(path)
+                            .id(egui::Id::new(("attach_path", self.active)))
+                            .hint_text("Path to a text file"),
+                    );
+                    load = field.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    load |= ui.button("Load").clicked();
+                    cancel = ui.button("Cancel").clicked();
+                });
+            }
+            if load {
+                tab.attach(self.attach_limit_kb);
+            } else if cancel {
+                tab.attach_path = None;
+            }
+            let input_field = ui.add_enabled(
+                open,
+                egui::TextEdit::multiline(&mut tab.input)
+                    .id(id)
+                    .desired_rows(2)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("Enter sends · Shift+Enter for a newline · Up/Down for history"),
+            );
+            // Prompts waiting on the reply under way, each one droppable.
+            let mut dropped = None;
+            for (idx, queued) in tab.pending.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    if ui.small_button("×").on_hover_text("Don't send").clicked() {
+                        dropped = Some(idx);
+                    }
+                    let line = queued.prompt.lines().next().unwrap_or_default();
+                    let text = match queued.context.len() {
+                        0 => format!("waiting: {}", line),
+                        n => format!("waiting: {} (+{} file(s))", line, n),
+                    };
+                    ui.label(egui::RichText::new(text).weak());
+                });
+            }
+            if let Some(idx) = dropped {
+                tab.pending.remove(idx);
+            }
+            let mut reroll = false;
+            let mut undo = false;
+            ui.horizontal(|ui| {
+                if ui.add_enabled(open, egui::Button::new("Send")).clicked() {
+                    submit = true;
+                }
+                if ui
+                    .button("Attach file…")
+                    .on_hover_text(format!(
+                        "Send a text file of up to {} with the next prompt",
+                        attach::size_label(self.attach_limit_kb * 1024)
+                    ))
+                    .clicked()
+                {
+                    let attach_id = egui::Id::new(("attach_path", self.active));
+                    tab.attach_path.get_or_insert_with(String::new);
+                    ctx.memory_mut(|m| m.request_focus(attach_id));
+                }
+                let idle = open && !tab.busy();
+                let last = tab.last_reply();
+                reroll |= ui
+                    .add_enabled(
+                        idle && last == Some(LogRole::Assistant),
+                        egui::Button::new("Regenerate"),
+                    )
+                    .on_hover_text("Ask again for the last reply (Ctrl+Shift+R)")
+                    .clicked();
+                reroll |= ui
+                    .add_enabled(
+                        idle && matches!(last, Some(LogRole::Failed | LogRole::Stopped)),
+                        egui::Button::new("Retry"),
+                    )
+                    .on_hover_text("Send the last prompt again after an error or Stop")
+                    .clicked();
+                undo = ui
+                    .add_enabled(idle && tab.undo.is_some(), egui::Button::new("Undo"))
+                    .on_hover_text("Bring back the reply Regenerate or Retry replaced")
+                    .clicked();
+            });
+            if reroll {
+                tab.reroll();
+            } else if undo {
+                tab.undo_reroll();
+            }
+            if submit {
+                self.restore_offer = None;
+                if let Some(prompt) = self.tab().submit_prompt() {
+                    self.history.push(&prompt);
+                }
+            }
+            // Only when nothing else has focus and the pointer is idle, so
+            // the side panel's fields and sliders keep theirs.
+            let idle = ctx.memory(|m| m.focused().is_none()) && !ctx.is_using_pointer();
+            if open && idle {
+                input_field.request_focus();
+            }
+        });
+
+        self.autosave(ctx);
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        let settings = self.snapshot();
+        if settings != self.settings {
+            self.write_settings(settings);
+        }
+        // Left alone while the restore offer is still up, so dismissing the
+        // app without touching it doesn't throw the saved tabs away.
+        if self.restore_offer.is_none() {
+            if let Err(err) = self.saved_tabs().save() {
+                eprintln!("Could not save GPU console tabs: {}", err);
+            }
+        }
+        // Every backend is told first, so they all wind down at once, then
+        // each gets what is left of one timeout: a stuck thread can't keep
+        // the app from closing, and a local nox is killed before it goes.
+        for tab in &mut self.tabs {
+            tab.save_drafts();
+            tab.send_job(BackendJob::Quit);
+        }
+        let deadline = Instant::now() + shutdown::TIMEOUT;
+        for tab in &mut self.tabs {
+            tab.finish(deadline);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A tab cut off from its backend: the test plays the backend through
+    /// the returned sender and reads the jobs sent to it.
+    fn headless() -> (Session, Sender<BackendEvent>, Receiver<BackendJob>) {
+        let route = Route {
+            local: None,
+            token: None,
+            fallback: Fallback::Retry,
+        };
+        let mut tab = Session::new(
+            "test".to_string(),
+            "127.0.0.1:1".to_string(),
+            SamplingParams::default(),
+            None,
+            route,
+        );
+        let _ = tab.outbound.send(BackendJob::Quit);
+        let (events, inbound) = mpsc::channel();
+        let (outbound, jobs) = mpsc::channel();
+        tab.inbound = inbound;
+        tab.outbound = outbound;
+        tab.events = events.clone();
+        (tab, events, jobs)
+    }
+
+    fn roles(tab: &Session) -> Vec<(LogRole, &str)> {
+        tab.log
+            .iter()
+            .map(|entry| (entry.role, entry.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn a_done_after_stop_adds_nothing() {
+        let (mut tab, events, jobs) = headless();
+        tab.input = "count to ten".to_string();
+        tab.submit_prompt();
+        assert!(matches!(jobs.try_recv(), Ok(BackendJob::Prompt(p)) if p.text == "count to ten"));
+        events
+            .send(BackendEvent::Delta(None, "one ".into()))
+            .unwrap();
+        tab.poll_incoming();
+
+        tab.stop_generation();
+        assert!(matches!(jobs.try_recv(), Ok(BackendJob::Cancel)));
+        // What the backend sent before it saw the cancel.
+        events
+            .send(BackendEvent::Delta(None, "two ".into()))
+            .unwrap();
+        events
+            .send(BackendEvent::Done(None, "one two ".into()))
+            .unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            roles(&tab),
+            [(LogRole::User, "count to ten"), (LogRole::Stopped, "one ")]
+        );
+        assert!(!tab.streaming_active && tab.sent.is_empty());
+
+        // The next reply is logged as usual.
+        tab.input = "again".to_string();
+        tab.submit_prompt();
+        events.send(BackendEvent::Done(None, "ok".into())).unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            roles(&tab)[2..],
+            [(LogRole::User, "again"), (LogRole::Assistant, "ok")]
+        );
+    }
+
+    fn submit(tab: &mut Session, text: &str) {
+        tab.input = text.to_string();
+        tab.submit_prompt();
+    }
+
+    /// The prompts sent to the backend so far, with their ids.
+    fn sent(jobs: &Receiver<BackendJob>) -> Vec<(String, Option<u64>)> {
+        jobs.try_iter()
+            .filter_map(|job| match job {
+                BackendJob::Prompt(prompt) => Some((prompt.text, prompt.id)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_prompt_typed_mid_reply_waits_its_turn() {
+        let (mut tab, events, jobs) = headless();
+        submit(&mut tab, "first");
+        submit(&mut tab, "second");
+        assert_eq!(sent(&jobs), [("first".to_string(), None)]);
+        assert_eq!(tab.pending.len(), 1);
+
+        events
+            .send(BackendEvent::Delta(None, "one".into()))
+            .unwrap();
+        events.send(BackendEvent::Done(None, "one".into())).unwrap();
+        tab.poll_incoming();
+        assert_eq!(sent(&jobs), [("second".to_string(), None)]);
+        events.send(BackendEvent::Done(None, "two".into())).unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            roles(&tab),
+            [
+                (LogRole::User, "first"),
+                (LogRole::Assistant, "one"),
+                (LogRole::User, "second"),
+                (LogRole::Assistant, "two"),
+            ]
+        );
+        assert!(tab.pending.is_empty() && tab.sent.is_empty());
+    }
+
+    #[test]
+    fn multiplexed_replies_are_kept_apart_by_id() {
+        let (mut tab, events, jobs) = headless();
+        tab.multiplex = true;
+        submit(&mut tab, "a");
+        submit(&mut tab, "b");
+        assert_eq!(
+            sent(&jobs),
+            [("a".to_string(), Some(1)), ("b".to_string(), Some(2))]
+        );
+        for (id, chunk) in [(1, "A1 "), (2, "B1 "), (1, "A2")] {
+            events
+                .send(BackendEvent::Delta(Some(id), chunk.into()))
+                .unwrap();
+        }
+        tab.poll_incoming();
+        assert_eq!(tab.streaming_buffer, "A1 A2");
+        assert_eq!(tab.side[&2].text, "B1 ");
+
+        // The first reply ends, and the second takes over the stream.
+        events
+            .send(BackendEvent::Done(Some(1), "A1 A2".into()))
+            .unwrap();
+        tab.poll_incoming();
+        assert_eq!(tab.streaming_buffer, "B1 ");
+        events
+            .send(BackendEvent::Delta(Some(2), "B2".into()))
+            .unwrap();
+        events
+            .send(BackendEvent::Done(Some(2), "B1 B2".into()))
+            .unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            roles(&tab),
+            [
+                (LogRole::User, "a"),
+                (LogRole::User, "b"),
+                (LogRole::Assistant, "A1 A2"),
+                (LogRole::Assistant, "B1 B2"),
+            ]
+        );
+    }
+
+    #[test]
+    fn telemetry_counts_only_while_online() {
+        let (mut tab, events, _jobs) = headless();
+        let sample = telemetry::Sample {
+            gpu_util: Some(50.0),
+            ..telemetry::Sample::default()
+        };
+        events.send(BackendEvent::Telemetry(sample)).unwrap();
+        tab.poll_incoming();
+        assert!(tab.telemetry.is_empty());
+        events.send(BackendEvent::Link(Link::Online)).unwrap();
+        events.send(BackendEvent::Telemetry(sample)).unwrap();
+        tab.poll_incoming();
+        assert_eq!(tab.telemetry.latest(|sample| sample.gpu_util), Some(50.0));
+    }
+
+    #[test]
+    fn regenerate_resends_the_prompt_with_a_fresh_seed() {
+        let (mut tab, events, jobs) = headless();
+        submit(&mut tab, "pick a number");
+        events.send(BackendEvent::Done(None, "4".into())).unwrap();
+        tab.poll_incoming();
+        sent(&jobs);
+
+        tab.reroll();
+        let [BackendJob::Prompt(again)] = &jobs.try_iter().collect::<Vec<_>>()[..] else {
+            panic!("not resent");
+        };
+        assert_eq!(again.text, "pick a number");
+        assert!(again.seed.is_some());
+        assert_eq!(roles(&tab), [(LogRole::User, "pick a number")]);
+        // Not while the new reply is under way.
+        tab.reroll();
+        tab.undo_reroll();
+        assert!(sent(&jobs).is_empty());
+        assert_eq!(tab.log.len(), 1);
+
+        events.send(BackendEvent::Done(None, "7".into())).unwrap();
+        tab.poll_incoming();
+        tab.undo_reroll();
+        assert_eq!(
+            roles(&tab),
+            [(LogRole::User, "pick a number"), (LogRole::Assistant, "4")]
+        );
+    }
+
+    #[test]
+    fn settings_read_back_as_written() {
+        let settings = Settings {
+            bridge_addr: Some("10.0.0.2:8765".to_string()),
+            sampling: SamplingParams {
+                temp: 0.7,
+                ..SamplingParams::default()
+            },
+            backend: BackendKind::Local,
+            bridge_token: Some("s3cret".to_string()),
+            history: vec!["hi".to_string()],
+            theme: theme::Theme::Light,
+            zoom: Some(1.25),
+            notify: true,
+            ..Settings::default()
+        };
+        let text = serde_json::to_string_pretty(&settings).unwrap();
+        // The token is not in the file as typed.
+        assert!(text.contains(r#""bridge_token": "b64:czNjcmV0""#));
+        assert!(!text.contains("s3cret"));
+        assert!(serde_json::from_str::<Settings>(&text).unwrap() == settings);
+    }
+
+    #[test]
+    fn settings_from_other_versions_still_load() {
+        let settings: Settings = serde_json::from_str(
+            r#"{
+                "bridge_addr": "bridge:1",
+                "bridge_token": "plain-from-before",
+                "sampling": {"temp": 1.0, "mirostat": 2},
+                "window": {"width": 800},
+                "theme": "light"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(settings.bridge_addr.as_deref(), Some("bridge:1"));
+        assert_eq!(settings.bridge_token.as_deref(), Some("plain-from-before"));
+        assert_eq!(settings.sampling.temp, 1.0);
+        assert_eq!(
+            settings.sampling.max_tokens,
+            SamplingParams::default().max_tokens
+        );
+        assert_eq!(settings.theme, theme::Theme::Light);
+        assert_eq!(settings.backend, BackendKind::Bridge);
+        assert!(serde_json::from_str::<Settings>("{}").unwrap() == Settings::default());
+    }
+
+    /// A tab online to a bridge offering two models, running `nox`.
+    fn offering() -> (Session, Sender<BackendEvent>, Receiver<BackendJob>) {
+        let (mut tab, events, jobs) = headless();
+        for event in [
+            BackendEvent::Link(Link::Online),
+            BackendEvent::Models(vec!["mistral-7b-q4".to_string(), "nox".to_string()]),
+            BackendEvent::Model("nox".to_string()),
+        ] {
+            events.send(event).unwrap();
+        }
+        tab.poll_incoming();
+        (tab, events, jobs)
+    }
+
+    #[test]
+    fn prompts_wait_for_a_model_switch() {
+        let (mut tab, events, jobs) = offering();
+        tab.switch_model("mistral-7b-q4".to_string());
+        assert!(
+            matches!(jobs.try_recv(), Ok(BackendJob::SetModel(name)) if name == "mistral-7b-q4")
+        );
+        assert_eq!(tab.status_line, "Loading mistral-7b-q4…");
+        // Neither another switch nor a prompt goes out meanwhile.
+        tab.switch_model("nox".to_string());
+        submit(&mut tab, "hello");
+        assert!(jobs.try_recv().is_err());
+        assert_eq!(tab.pending.len(), 1);
+
+        events.send(BackendEvent::ModelReady(None)).unwrap();
+        tab.poll_incoming();
+        assert!(tab.status_line.starts_with("Switched to mistral-7b-q4 in "));
+        assert_eq!(sent(&jobs), [("hello".to_string(), None)]);
+        events.send(BackendEvent::Done(None, "hi".into())).unwrap();
+        tab.poll_incoming();
+        let reply = tab.log.back().unwrap();
+        assert_eq!(reply.role, LogRole::Assistant);
+        let model = reply
+            .stats
+            .as_ref()
+            .and_then(|stats| stats.model.as_deref());
+        assert_eq!(model, Some("mistral-7b-q4"));
+    }
+
+    #[test]
+    fn a_failed_switch_unlocks_the_input() {
+        let (mut tab, events, jobs) = offering();
+        tab.switch_model("mistral-7b-q4".to_string());
+        submit(&mut tab, "hello");
+        events
+            .send(BackendEvent::Error("out of memory".into()))
+            .unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            tab.status_line,
+            "Could not load mistral-7b-q4: out of memory"
+        );
+        // The error was the switch's, not the prompt's.
+        assert!(!tab.log.iter().any(|entry| entry.role == LogRole::Failed));
+        let after: Vec<_> = jobs.try_iter().collect();
+        assert!(
+            matches!(&after[..], [BackendJob::SetModel(_), BackendJob::Prompt(p)] if p.text == "hello")
+        );
+        assert_eq!(tab.models.active(), Some("nox"));
+    }
+
+    #[test]
+    fn a_disconnect_mid_switch_ends_it() {
+        let (mut tab, events, _jobs) = offering();
+        tab.switch_model("mistral-7b-q4".to_string());
+        events.send(BackendEvent::Link(Link::Offline)).unwrap();
+        tab.poll_incoming();
+        assert_eq!(
+            tab.status_line,
+            "Lost the bridge while loading mistral-7b-q4"
+        );
+        assert!(tab.models.switching(Instant::now()).is_none());
+        assert!(tab.models.offered().is_empty());
+    }
+
+    #[test]
+    fn prompts_still_queued_at_exit_are_saved_as_drafts() {
+        let (mut tab, _events, jobs) = headless();
+        let path =
+            std::env::temp_dir().join(format!("noctics-drafts-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        tab.writer = Some(session::Writer::spawn(path.clone(), |_| {}));
+        submit(&mut tab, "first");
+        submit(&mut tab, "second");
+        submit(&mut tab, "third");
+        assert_eq!(sent(&jobs), [("first".to_string(), None)]);
+
+        tab.save_drafts();
+        tab.send_job(BackendJob::Quit);
+        let asked = Instant::now();
+        tab.finish(asked + shutdown::TIMEOUT);
+        assert!(asked.elapsed() < shutdown::TIMEOUT);
+        assert!(tab.pending.is_empty());
+
+        let (mut records, _) = session::load(&path).unwrap();
+        let drafts = session::take_drafts(&mut records);
+        let texts: Vec<_> = drafts.iter().map(|record| record.text.as_str()).collect();
+        assert_eq!(texts, ["second", "third"]);
+        assert_eq!(
+            records.last().map(|record| record.text.as_str()),
+            Some("first")
+        );
+        let _ = std::fs::remove_file(&path);
     }
 }
@@ -0,0 +1,438 @@
+use eframe::egui::{self, text::LayoutJob, Color32, FontId, TextFormat};
+
+/// The subset of markdown replies lean on: fenced code, bullet and numbered
+/// lists, headings, and inline code, bold, and italics. Anything else is
+/// shown as the text it is.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Block {
+    Line(Vec<Span>),
+    Heading(Vec<Span>),
+    /// A list item, `depth` levels in, with its marker (`•` or `3.`).
+    Item {
+        depth: usize,
+        marker: String,
+        spans: Vec<Span>,
+    },
+    /// A fenced code block. `closed` is false when the text ran out before
+    /// the closing fence, as it does mid-stream.
+    Code {
+        fence: String,
+        lang: String,
+        text: String,
+        closed: bool,
+    },
+    Blank,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub code: bool,
+    pub strong: bool,
+    pub italic: bool,
+}
+
+pub fn parse(text: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut code: Option<(String, String, Vec<&str>)> = None;
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if let Some((open, _, lines)) = &mut code {
+            let closes = fence(line).is_some_and(|marker| {
+                marker.starts_with(open.as_str()) && line.trim() == marker
+            });
+            if !closes {
+                lines.push(line);
+                continue;
+            }
+            if let Some((fence, lang, lines)) = code.take() {
+                blocks.push(Block::Code {
+                    fence,
+                    lang,
+                    text: lines.join("\n"),
+                    closed: true,
+                });
+            }
+            continue;
+        }
+        let trimmed = line.trim_start();
+        if let Some(marker) = fence(line) {
+            let lang = trimmed[marker.len()..].trim();
+            // Backticks in the info string make it inline code instead,
+            // as in ```a``` on a line of its own.
+            if !(marker.starts_with('`') && lang.contains('`')) {
+                code = Some((marker.to_string(), lang.to_string(), Vec::new()));
+                continue;
+            }
+        }
+        if trimmed.is_empty() {
+            blocks.push(Block::Blank);
+        } else if let Some(rest) = heading(trimmed) {
+            blocks.push(Block::Heading(spans(rest)));
+        } else if let Some((marker, rest)) = item(trimmed) {
+            blocks.push(Block::Item {
+                depth: (line.len() - trimmed.len()) / 2,
+                marker,
+                spans: spans(rest),
+            });
+        } else {
+            blocks.push(Block::Line(spans(line)));
+        }
+    }
+    if let Some((fence, lang, lines)) = code {
+        blocks.push(Block::Code {
+            fence,
+            lang,
+            text: lines.join("\n"),
+            closed: false,
+        });
+    }
+    while blocks.last() == Some(&Block::Blank) {
+        blocks.pop();
+    }
+    blocks
+}
+
+/// The fence a line opens or closes: its run of three or more backticks or
+/// tildes.
+pub fn fence(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let ch = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.len() - line.trim_start_matches(ch).len();
+    (len >= 3).then(|| &line[..len])
+}
+
+fn heading(line: &str) -> Option<&str> {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[hashes..];
+    ((1..=6).contains(&hashes) && (rest.is_empty() || rest.starts_with(' ')))
+        .then(|| rest.trim())
+}
+
+fn item(line: &str) -> Option<(String, &str)> {
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(rest) = line.strip_prefix(bullet) {
+            return Some(("•".to_string(), rest));
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &line[digits..];
+    if !(1..=9).contains(&digits) {
+        return None;
+    }
+    let rest = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") "))?;
+    Some((format!("{}.", &line[..digits]), rest))
+}
+
+/// Inline code, `**`/`__` bold, and `*`/`_` italics. A marker with nothing to
+/// close it stays as text, and so does `_` inside a word, as in snake_case.
+pub fn spans(text: &str) -> Vec<Span> {
+    let mut out: Vec<Span> = Vec::new();
+    let mut current = Span::default();
+    let mut strong: Option<&str> = None;
+    let mut italic: Option<char> = None;
+    let mut prev: Option<char> = None;
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '`' {
+            let run = rest.len() - rest.trim_start_matches('`').len();
+            if let Some(end) = closing_run(&rest[run..], run) {
+                let mut code = &rest[run..run + end];
+                if code.len() > 2 && code.starts_with(' ') && code.ends_with(' ') {
+                    code = &code[1..code.len() - 1];
+                }
+                push(&mut out, &mut current);
+                out.push(Span {
+                    text: code.to_string(),
+                    code: true,
+                    ..current.clone()
+                });
+                rest = &rest[run + end + run..];
+                prev = Some('`');
+                continue;
+            }
+            current.text.push_str(&rest[..run]);
+            rest = &rest[run..];
+            prev = Some('`');
+            continue;
+        }
+        let next = |skip: usize| rest[skip..].chars().next();
+        let boundary = |c: Option<char>| !c.is_some_and(char::is_alphanumeric);
+        let double = ["**", "__"].into_iter().find(|d| rest.starts_with(d));
+        if let Some(marker) = double {
+            let word_ok = marker == "**" || boundary(prev) || strong.is_some();
+            let toggles = match strong {
+                Some(open) => open == marker,
+                None => {
+                    word_ok
+                        && !next(2).is_some_and(char::is_whitespace)
+                        && rest[2..].contains(marker)
+                }
+            };
+            if toggles {
+                push(&mut out, &mut current);
+                strong = if strong.is_some() { None } else { Some(marker) };
+                current.strong = strong.is_some();
+                rest = &rest[2..];
+                prev = marker.chars().next();
+                continue;
+            }
+            current.text.push_str(marker);
+            rest = &rest[2..];
+            prev = marker.chars().next();
+            continue;
+        }
+        if ch == '*' || ch == '_' {
+            let toggles = match italic {
+                Some(open) => open == ch && (ch == '*' || boundary(next(1))),
+                None => {
+                    (ch == '*' || boundary(prev))
+                        && next(1).is_some_and(|c| !c.is_whitespace())
+                        && rest[1..].contains(ch)
+                }
+            };
+            if toggles {
+                push(&mut out, &mut current);
+                italic = if italic.is_some() { None } else { Some(ch) };
+                current.italic = italic.is_some();
+                rest = &rest[1..];
+                prev = Some(ch);
+                continue;
+            }
+        }
+        current.text.push(ch);
+        rest = &rest[ch.len_utf8()..];
+        prev = Some(ch);
+    }
+    push(&mut out, &mut current);
+    out
+}
+
+/// Where in `text` a run of exactly `len` backticks starts.
+fn closing_run(text: &str, len: usize) -> Option<usize> {
+    let mut at = 0;
+    while let Some(found) = text[at..].find('`') {
+        let start = at + found;
+        let run = text[start..].len() - text[start..].trim_start_matches('`').len();
+        if run == len {
+            return Some(start);
+        }
+        at = start + run;
+    }
+    None
+}
+
+fn push(out: &mut Vec<Span>, current: &mut Span) {
+    if !current.text.is_empty() {
+        let next = Span {
+            text: String::new(),
+            ..current.clone()
+        };
+        out.push(std::mem::replace(current, next));
+    }
+}
+
+const INDENT: f32 = 14.0;
+
+/// Shows `text` as markdown, led by `prefix` (such as `Nox>`). While
+/// `streaming`, a code block still waiting for its closing fence is shown as
+/// plain text, and gets its frame once the fence arrives.
+pub fn show(
+    ui: &mut egui::Ui,
+    prefix: &str,
+    text: &str,
+    color: Color32,
+    streaming: bool,
+) {
+    let mut blocks = parse(text);
+    let lead = Span {
+        text: format!("{} ", prefix),
+        strong: true,
+        ..Span::default()
+    };
+    match blocks.first_mut() {
+        Some(Block::Line(spans)) | Some(Block::Heading(spans)) => spans.insert(0, lead),
+        _ => blocks.insert(0, Block::Line(vec![lead])),
+    }
+    for block in blocks {
+        match block {
+            Block::Line(spans) => {
+                ui.label(layout(ui, &spans, color));
+            }
+            Block::Heading(mut spans) => {
+                for span in &mut spans {
+                    span.strong = true;
+                }
+                ui.label(layout(ui, &spans, color));
+            }
+            Block::Item {
+                depth,
+                marker,
+                spans,
+            } => {
+                ui.horizontal_top(|ui| {
+                    ui.add_space(INDENT * (depth + 1) as f32);
+                    ui.colored_label(color, marker);
+                    ui.add(egui::Label::new(layout(ui, &spans, color)).wrap(true));
+                });
+            }
+            Block::Code {
+                fence,
+                lang,
+                text,
+                closed: false,
+            } if streaming => {
+                ui.colored_label(color, format!("{}{}\n{}", fence, lang, text));
+            }
+            Block::Code { lang, text, .. } => code_block(ui, &lang, text),
+            Block::Blank => ui.add_space(ui.spacing().item_spacing.y * 2.0),
+        }
+    }
+}
+
+fn code_block(ui: &mut egui::Ui, lang: &str, text: String) {
+    egui::Frame::group(ui.style())
+        .fill(ui.visuals().extreme_bg_color)
+        .show(ui, |ui| {
+            ui.set_width(ui.available_width());
+            ui.horizontal(|ui| {
+                ui.label(egui::RichText::new(lang).small().weak());
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui.small_button("Copy").clicked() {
+                        ui.ctx().copy_text(text.clone());
+                    }
+                });
+            });
+            ui.add(egui::Label::new(egui::RichText::new(text).monospace()).wrap(true));
+        });
+}
+
+fn layout(ui: &egui::Ui, spans: &[Span], color: Color32) -> LayoutJob {
+    let style = ui.style();
+    let body = FontId::proportional(style.text_styles[&egui::TextStyle::Body].size);
+    let mono = style.text_styles[&egui::TextStyle::Monospace].clone();
+    let mut job = LayoutJob::default();
+    for span in spans {
+        let mut format = TextFormat {
+            font_id: if span.code { mono.clone() } else { body.clone() },
+            color,
+            italics: span.italic,
+            ..Default::default()
+        };
+        if span.strong {
+            format.color = ui.visuals().strong_text_color();
+        }
+        if span.code {
+            format.background = ui.visuals().code_bg_color;
+        }
+        job.append(&span.text, 0.0, format);
+    }
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(text: &str) -> Span {
+        Span {
+            text: text.to_string(),
+            ..Span::default()
+        }
+    }
+
+    fn code(text: &str) -> Span {
+        Span {
+            code: true,
+            ..plain(text)
+        }
+    }
+
+    #[test]
+    fn an_unterminated_fence_is_an_open_code_block() {
+        assert_eq!(
+            parse("Try:\n```python\nprint(1)\n"),
+            [
+                Block::Line(vec![plain("Try:")]),
+                Block::Code {
+                    fence: "```".to_string(),
+                    lang: "python".to_string(),
+                    text: "print(1)\n".to_string(),
+                    closed: false,
+                },
+            ]
+        );
+        // A shorter run doesn't close a longer fence, and ~~~ doesn't close ```.
+        let blocks = parse("````\n```\n~~~\n````");
+        assert_eq!(
+            blocks,
+            [Block::Code {
+                fence: "````".to_string(),
+                lang: String::new(),
+                text: "```\n~~~".to_string(),
+                closed: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn nested_backticks_stay_in_their_code_span() {
+        assert_eq!(
+            spans("use `` a `b` c `` here"),
+            [plain("use "), code("a `b` c"), plain(" here")]
+        );
+        // A run with nothing to close it is text.
+        assert_eq!(spans("a ``b` c"), [plain("a ``b` c")]);
+        // Backticks in the info string make a one-line fence inline code.
+        assert_eq!(parse("```x```"), [Block::Line(vec![code("x")])]);
+    }
+
+    #[test]
+    fn windows_line_endings_parse_like_unix_ones() {
+        let unix = "# Title\n\n- one\n  - two\n```rs\nlet x = 1;\n```\n";
+        assert_eq!(parse(&unix.replace('\n', "\r\n")), parse(unix));
+        assert_eq!(
+            parse(unix)[2..4],
+            [
+                Block::Item {
+                    depth: 0,
+                    marker: "•".to_string(),
+                    spans: vec![plain("one")],
+                },
+                Block::Item {
+                    depth: 1,
+                    marker: "•".to_string(),
+                    spans: vec![plain("two")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn emphasis_needs_a_closing_marker() {
+        let strong = Span {
+            strong: true,
+            ..plain("bold")
+        };
+        let italic = Span {
+            italic: true,
+            ..plain("it")
+        };
+        assert_eq!(spans("**bold** and _it_"), [strong, plain(" and "), italic]);
+        assert_eq!(spans("snake_case_name"), [plain("snake_case_name")]);
+        assert_eq!(spans("2 * 3 = 6"), [plain("2 * 3 = 6")]);
+        assert_eq!(spans("**open"), [plain("**open")]);
+    }
+
+    #[test]
+    fn numbered_items_keep_their_number() {
+        assert!(matches!(
+            &parse("12. twelve")[..],
+            [Block::Item { marker, .. }] if marker == "12."
+        ));
+        assert_eq!(
+            parse("12.5 is a number"),
+            [Block::Line(vec![plain("12.5 is a number")])]
+        );
+    }
+}
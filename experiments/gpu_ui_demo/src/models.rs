@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+/// The models a bridge offers, the one it runs, and a switch under way.
+/// Events are fed in as they arrive, and the clock is passed in, so it runs
+/// the same without a bridge.
+#[derive(Default)]
+pub struct Models {
+    /// What the bridge's `hello` listed; empty hides the selector.
+    offered: Vec<String>,
+    active: Option<String>,
+    switch: Option<Switch>,
+}
+
+struct Switch {
+    to: String,
+    since: Instant,
+}
+
+/// How a switch ended.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The model loaded, taking this long.
+    Ready(String, Duration),
+    Failed(String, String),
+    /// The bridge went away before saying.
+    Lost(String),
+}
+
+impl Models {
+    /// The bridge said hello, offering `offered`.
+    pub fn hello(&mut self, offered: Vec<String>) {
+        self.offered = offered;
+        self.switch = None;
+    }
+
+    /// The bridge said which model it runs.
+    pub fn set_active(&mut self, name: String) {
+        self.active = Some(name);
+    }
+
+    pub fn offered(&self) -> &[String] {
+        &self.offered
+    }
+
+    pub fn active(&self) -> Option<&str> {
+        self.active.as_deref()
+    }
+
+    /// Asks for `name`. `false` when it is the one running or a switch is
+    /// already under way, and nothing should be sent.
+    pub fn request(&mut self, name: &str, now: Instant) -> bool {
+        if self.switch.is_some() || self.active() == Some(name) {
+            return false;
+        }
+        self.switch = Some(Switch {
+            to: name.to_string(),
+            since: now,
+        });
+        true
+    }
+
+    /// The model being loaded and how long it has been, while the input is
+    /// locked for it.
+    pub fn switching(&self, now: Instant) -> Option<(&str, Duration)> {
+        self.switch
+            .as_ref()
+            .map(|switch| (switch.to.as_str(), now.saturating_duration_since(switch.since)))
+    }
+
+    /// The bridge said `model_ready`, naming the model or not.
+    pub fn ready(&mut self, name: Option<String>, now: Instant) -> Option<Outcome> {
+        let switch = self.switch.take();
+        let name = name.or_else(|| switch.as_ref().map(|switch| switch.to.clone()))?;
+        self.active = Some(name.clone());
+        let took = switch.map(|switch| now.saturating_duration_since(switch.since))?;
+        Some(Outcome::Ready(name, took))
+    }
+
+    /// The bridge sent an error; it ends a switch, if one is under way.
+    pub fn error(&mut self, message: &str) -> Option<Outcome> {
+        let switch = self.switch.take()?;
+        Some(Outcome::Failed(switch.to, message.to_string()))
+    }
+
+    /// The bridge is gone; what it offered goes with it.
+    pub fn lost(&mut self) -> Option<Outcome> {
+        self.offered.clear();
+        self.active = None;
+        self.switch.take().map(|switch| Outcome::Lost(switch.to))
+    }
+}
+
+impl Outcome {
+    /// For the status bar.
+    pub fn status(&self) -> String {
+        match self {
+            Outcome::Ready(name, took) => {
+                format!("Switched to {} in {:.1}s", name, took.as_secs_f32())
+            }
+            Outcome::Failed(name, message) => format!("Could not load {}: {}", name, message),
+            Outcome::Lost(name) => format!("Lost the bridge while loading {}", name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn offering() -> Models {
+        let mut models = Models::default();
+        models.hello(vec!["mistral-7b-q4".to_string(), "nox".to_string()]);
+        models.set_active("nox".to_string());
+        models
+    }
+
+    #[test]
+    fn a_switch_runs_until_the_model_is_ready() {
+        let mut models = offering();
+        let start = Instant::now();
+        assert!(!models.request("nox", start));
+        assert!(models.request("mistral-7b-q4", start));
+        assert!(!models.request("nox", start));
+        let later = start + Duration::from_millis(2400);
+        assert_eq!(
+            models.switching(later),
+            Some(("mistral-7b-q4", Duration::from_millis(2400)))
+        );
+        let outcome = models.ready(None, later).unwrap();
+        assert_eq!(
+            outcome,
+            Outcome::Ready("mistral-7b-q4".to_string(), Duration::from_millis(2400))
+        );
+        assert_eq!(outcome.status(), "Switched to mistral-7b-q4 in 2.4s");
+        assert_eq!(models.switching(later), None);
+        assert_eq!(models.active(), Some("mistral-7b-q4"));
+    }
+
+    #[test]
+    fn an_unasked_ready_only_names_the_model() {
+        let mut models = offering();
+        assert_eq!(models.ready(None, Instant::now()), None);
+        assert_eq!(models.active(), Some("nox"));
+        assert_eq!(
+            models.ready(Some("mistral-7b-q4".to_string()), Instant::now()),
+            None
+        );
+        assert_eq!(models.active(), Some("mistral-7b-q4"));
+    }
+
+    #[test]
+    fn an_error_ends_the_switch_and_keeps_the_model() {
+        let mut models = offering();
+        assert_eq!(models.error("busy"), None);
+        let now = Instant::now();
+        models.request("mistral-7b-q4", now);
+        let outcome = models.error("out of memory").unwrap();
+        assert_eq!(
+            outcome.status(),
+            "Could not load mistral-7b-q4: out of memory"
+        );
+        assert_eq!(models.switching(now), None);
+        assert_eq!(models.active(), Some("nox"));
+        assert!(models.request("mistral-7b-q4", now));
+    }
+
+    #[test]
+    fn losing_the_bridge_mid_switch_forgets_everything() {
+        let mut models = offering();
+        let now = Instant::now();
+        models.request("mistral-7b-q4", now);
+        let outcome = models.lost().unwrap();
+        assert_eq!(outcome, Outcome::Lost("mistral-7b-q4".to_string()));
+        assert_eq!(
+            outcome.status(),
+            "Lost the bridge while loading mistral-7b-q4"
+        );
+        assert!(models.offered().is_empty());
+        assert_eq!(models.active(), None);
+        assert_eq!(models.switching(now), None);
+        assert_eq!(models.lost(), None);
+    }
+}
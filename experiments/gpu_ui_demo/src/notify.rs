@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+/// The window title, before any badge.
+pub const TITLE: &str = "Noctics GPU Console";
+
+/// How long a reply has to take before its end is worth a notification,
+/// until another threshold is set.
+pub const DEFAULT_AFTER: Duration = Duration::from_secs(5);
+
+/// Decides when a finished reply should call for attention: only while the
+/// window is unfocused, and only for ones that took a while. It counts them
+/// for the title badge until the window has focus again. Focus and timings
+/// are fed in, so it runs the same without a window.
+pub struct Notifier {
+    focused: bool,
+    unseen: usize,
+}
+
+impl Default for Notifier {
+    /// Windows open focused.
+    fn default() -> Self {
+        Self {
+            focused: true,
+            unseen: 0,
+        }
+    }
+}
+
+impl Notifier {
+    /// The window's focus, each frame. `true` when that cleared the badge,
+    /// so the title has to change back.
+    pub fn focus(&mut self, focused: bool) -> bool {
+        self.focused = focused;
+        focused && std::mem::take(&mut self.unseen) > 0
+    }
+
+    /// A reply ended, `took` after its prompt went out. `true` when that
+    /// calls for attention: the window is unfocused and `took` is at least
+    /// `after`.
+    pub fn finished(&mut self, took: Duration, after: Duration) -> bool {
+        if self.focused || took < after {
+            return false;
+        }
+        self.unseen += 1;
+        true
+    }
+
+    /// `Noctics GPU Console — 1 new`, or just the name when nothing is.
+    pub fn title(&self) -> String {
+        match self.unseen {
+            0 => TITLE.to_string(),
+            n => format!("{} — {} new", TITLE, n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(n: u64) -> Duration {
+        Duration::from_secs(n)
+    }
+
+    #[test]
+    fn only_slow_replies_while_away_call() {
+        let mut notifier = Notifier::default();
+        assert!(!notifier.finished(secs(30), DEFAULT_AFTER));
+        assert!(!notifier.focus(false));
+        assert!(!notifier.finished(secs(4), DEFAULT_AFTER));
+        assert_eq!(notifier.title(), TITLE);
+        assert!(notifier.finished(secs(5), DEFAULT_AFTER));
+        assert_eq!(notifier.title(), "Noctics GPU Console — 1 new");
+        assert!(notifier.finished(secs(2), secs(1)));
+        assert_eq!(notifier.title(), "Noctics GPU Console — 2 new");
+    }
+
+    #[test]
+    fn focus_clears_the_badge_once() {
+        let mut notifier = Notifier::default();
+        notifier.focus(false);
+        assert!(!notifier.focus(false));
+        notifier.finished(secs(9), DEFAULT_AFTER);
+        assert!(!notifier.focus(false));
+        assert!(notifier.focus(true));
+        assert_eq!(notifier.title(), TITLE);
+        assert!(!notifier.focus(true));
+        assert!(!notifier.finished(secs(9), DEFAULT_AFTER));
+    }
+}
@@ -0,0 +1,152 @@
+/// A tab's log search: what is typed, and which match was last jumped to.
+/// It only ever reads the log, so the log and the session file stay as they
+/// are whatever is typed.
+#[derive(Default)]
+pub struct Search {
+    pub query: String,
+    /// Which of the matches, counting from the oldest, was jumped to.
+    current: Option<usize>,
+    /// Set by a jump, until the log has scrolled to it.
+    scroll: bool,
+}
+
+impl Search {
+    /// Whether there is anything to filter by.
+    pub fn active(&self) -> bool {
+        !self.query.trim().is_empty()
+    }
+
+    /// What the texts are checked against: the query, trimmed and lowercase.
+    pub fn needle(&self) -> String {
+        self.query.trim().to_lowercase()
+    }
+
+    /// The indices of the texts that contain the query, ignoring case; none
+    /// when there is no query.
+    pub fn matches<'a>(&self, texts: impl IntoIterator<Item = &'a str>) -> Vec<usize> {
+        if !self.active() {
+            return Vec::new();
+        }
+        let needle = self.needle();
+        texts
+            .into_iter()
+            .enumerate()
+            .filter(|(_, text)| is_match(text, &needle))
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+
+    /// Moves to the next of `count` matches, or the previous, wrapping round.
+    /// The first jump goes to the oldest, or the newest going back.
+    pub fn jump(&mut self, count: usize, forward: bool) {
+        if count == 0 {
+            self.current = None;
+            return;
+        }
+        self.current = Some(match self.current(count) {
+            None if forward => 0,
+            None => count - 1,
+            Some(at) if forward => (at + 1) % count,
+            Some(at) => (at + count - 1) % count,
+        });
+        self.scroll = true;
+    }
+
+    /// Which of `count` matches was jumped to, if it is still one of them.
+    pub fn current(&self, count: usize) -> Option<usize> {
+        self.current.filter(|at| *at < count)
+    }
+
+    /// Whether the log should scroll to the current match, once.
+    pub fn take_scroll(&mut self) -> bool {
+        std::mem::take(&mut self.scroll)
+    }
+
+    /// The query changed; jumps start over.
+    pub fn edited(&mut self) {
+        self.current = None;
+        self.scroll = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.query.clear();
+        self.edited();
+    }
+}
+
+/// Whether `text` contains `needle`, which is already lowercase.
+pub fn is_match(text: &str, needle: &str) -> bool {
+    text.to_lowercase().contains(needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A thousand entries; every seventh mentions the GPU, in mixed case.
+    fn log() -> Vec<String> {
+        (0..1000)
+            .map(|n| match n % 7 {
+                0 => format!("{}: the Gpu is warm", n),
+                _ => format!("{}: nothing to see", n),
+            })
+            .collect()
+    }
+
+    fn search(query: &str) -> Search {
+        Search {
+            query: query.to_string(),
+            ..Search::default()
+        }
+    }
+
+    #[test]
+    fn matches_ignore_case_and_surrounding_space() {
+        let log = log();
+        let found = search("  GPU ").matches(log.iter().map(String::as_str));
+        assert_eq!(found.len(), 143);
+        assert_eq!((found[0], found[1], found[142]), (0, 7, 994));
+        assert!(search("   ")
+            .matches(log.iter().map(String::as_str))
+            .is_empty());
+        assert!(!search(" ").active());
+    }
+
+    #[test]
+    fn jumps_wrap_both_ways() {
+        let mut search = search("gpu");
+        search.jump(143, false);
+        assert_eq!(search.current(143), Some(142));
+        assert!(search.take_scroll());
+        assert!(!search.take_scroll());
+        search.jump(143, true);
+        assert_eq!(search.current(143), Some(0));
+        search.jump(143, true);
+        assert_eq!(search.current(143), Some(1));
+        search.jump(143, false);
+        search.jump(143, false);
+        assert_eq!(search.current(143), Some(142));
+    }
+
+    #[test]
+    fn new_entries_keep_the_place() {
+        let mut log = log();
+        let mut search = search("gpu");
+        let count =
+            |log: &[String], search: &Search| search.matches(log.iter().map(String::as_str)).len();
+        search.jump(count(&log, &search), true);
+        search.jump(count(&log, &search), true);
+        log.push("1000: GPU at 90%".to_string());
+        log.push("1001: idle".to_string());
+        assert_eq!(count(&log, &search), 144);
+        assert_eq!(search.current(144), Some(1));
+        // A match that no longer exists isn't current.
+        assert_eq!(search.current(1), None);
+        search.edited();
+        assert_eq!(search.current(144), None);
+        search.jump(0, true);
+        assert_eq!(search.current(144), None);
+        search.clear();
+        assert!(!search.active());
+    }
+}
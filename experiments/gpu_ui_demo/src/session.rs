@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::shutdown;
+use crate::stats::Stats;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Assistant,
+    /// The system prompt, recorded when a session starts with one and each
+    /// time it changes.
+    System,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub role: Role,
+    pub text: String,
+    /// Unix time in milliseconds.
+    pub ts: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stopped: bool,
+    /// The model that wrote a reply, when the bridge said.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// A finished reply's numbers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+    /// A prompt still waiting to go out when the app closed. It is handed
+    /// back to type over when the session is opened again, rather than being
+    /// part of the conversation.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub draft: bool,
+}
+
+impl Record {
+    pub fn new(role: Role, text: impl Into<String>) -> Self {
+        Self {
+            role,
+            text: text.into(),
+            ts: now_ms(),
+            elapsed_ms: None,
+            stopped: false,
+            model: None,
+            stats: None,
+            draft: false,
+        }
+    }
+
+    pub fn draft(text: impl Into<String>) -> Self {
+        Self {
+            draft: true,
+            ..Self::new(Role::User, text)
+        }
+    }
+}
+
+/// Splits off the drafts at the end of `records`: the ones saved since
+/// anything was last said. Drafts before that were handed back already, and
+/// are dropped.
+pub fn take_drafts(records: &mut Vec<Record>) -> Vec<Record> {
+    let sent = records.iter().rposition(|record| !record.draft).map_or(0, |at| at + 1);
+    let drafts = records.split_off(sent);
+    records.retain(|record| !record.draft);
+    drafts
+}
+
+/// A session's JSONL file.
+#[derive(Clone)]
+pub struct SessionFile {
+    pub id: String,
+    pub path: PathBuf,
+}
+
+/// `$XDG_DATA_HOME/noctics`, falling back to `~/.local/share`.
+pub fn data_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("share"))
+        })?;
+    Some(base.join("noctics"))
+}
+
+pub fn dir() -> Option<PathBuf> {
+    Some(data_dir()?.join("sessions"))
+}
+
+/// A fresh session named after the current UTC time, e.g. `20261016-142233`.
+/// Files are only written once there is something in them, so `taken` lists
+/// the ids already handed out that may not exist on disk yet.
+pub fn create(taken: &[&str]) -> Option<SessionFile> {
+    let dir = dir()?;
+    let stamp = utc_stamp(now_ms() / 1000);
+    let mut id = stamp.clone();
+    let mut n = 1;
+    while taken.contains(&id.as_str()) || dir.join(format!("{}.jsonl", id)).exists() {
+        n += 1;
+        id = format!("{}-{}", stamp, n);
+    }
+    let path = dir.join(format!("{}.jsonl", id));
+    Some(SessionFile { id, path })
+}
+
+/// The file for session `id`, whether or not it has been written yet.
+pub fn named(id: &str) -> Option<SessionFile> {
+    let path = dir()?.join(format!("{}.jsonl", id));
+    Some(SessionFile {
+        id: id.to_string(),
+        path,
+    })
+}
+
+/// Saved sessions, newest first.
+pub fn list() -> Vec<SessionFile> {
+    let Some(dir) = dir() else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+    let mut sessions: Vec<SessionFile> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .filter_map(|path| {
+            let id = path.file_stem()?.to_str()?.to_string();
+            Some(SessionFile { id, path })
+        })
+        .collect();
+    sessions.sort_by(|a, b| b.id.cmp(&a.id));
+    sessions
+}
+
+/// The records in `path`, and how many lines were skipped as corrupt.
+pub fn load(path: &Path) -> Result<(Vec<Record>, usize), String> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    read(BufReader::new(file))
+}
+
+pub fn read(reader: impl BufRead) -> Result<(Vec<Record>, usize), String> {
+    let mut records = Vec::new();
+    let mut skipped = 0;
+    for line in reader.lines() {
+        let line = line.map_err(|err| err.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<Record>(&line) {
+            Ok(record) => records.push(record),
+            Err(_) => skipped += 1,
+        }
+    }
+    Ok((records, skipped))
+}
+
+pub fn write(out: &mut impl Write, record: &Record) -> Result<(), String> {
+    let line = serde_json::to_string(record).map_err(|err| err.to_string())?;
+    writeln!(out, "{}", line).map_err(|err| err.to_string())
+}
+
+enum WriterJob {
+    Switch(PathBuf),
+    Append(Record),
+}
+
+/// Appends records to the active session file on its own thread, so the UI
+/// never waits on the disk.
+pub struct Writer {
+    jobs: Sender<WriterJob>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Writer {
+    pub fn spawn(path: PathBuf, on_error: impl Fn(String) + Send + 'static) -> Self {
+        let (jobs, rx) = mpsc::channel::<WriterJob>();
+        let thread = thread::spawn(move || {
+            let mut path = path;
+            let mut file: Option<File> = None;
+            while let Ok(job) = rx.recv() {
+                match job {
+                    WriterJob::Switch(next) => {
+                        path = next;
+                        file = None;
+                    }
+                    WriterJob::Append(record) => {
+                        if file.is_none() {
+                            match open_append(&path) {
+                                Ok(opened) => file = Some(opened),
+                                Err(err) => {
+                                    on_error(err);
+                                    continue;
+                                }
+                            }
+                        }
+                        if let Some(out) = file.as_mut() {
+                            if let Err(err) = write(out, &record) {
+                                on_error(format!("{}: {}", path.display(), err));
+                                file = None;
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        Self { jobs, thread }
+    }
+
+    pub fn switch(&self, path: PathBuf) {
+        let _ = self.jobs.send(WriterJob::Switch(path));
+    }
+
+    pub fn append(&self, record: Record) {
+        let _ = self.jobs.send(WriterJob::Append(record));
+    }
+
+    /// Writes out what is still queued, waiting for it until `deadline`.
+    /// `false` when the disk was still busy then.
+    pub fn close(self, deadline: Instant) -> bool {
+        let Self { jobs, thread } = self;
+        drop(jobs);
+        shutdown::join_by(thread, deadline)
+    }
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+pub fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC for unix time `secs`.
+pub fn utc_stamp(secs: u64) -> String {
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<Record> {
+        let mut reply = Record::new(Role::Assistant, "line one\nline two ✓");
+        reply.elapsed_ms = Some(412);
+        reply.stopped = true;
+        vec![
+            Record::new(Role::System, "Be brief."),
+            Record::new(Role::User, "say \"hi\""),
+            reply,
+        ]
+    }
+
+    #[test]
+    fn records_read_back_as_written() {
+        let records = conversation();
+        let mut out = Vec::new();
+        for record in &records {
+            write(&mut out, record).unwrap();
+        }
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(text.lines().count(), 3);
+        // Fields at their defaults are left out of the file.
+        assert!(!text.lines().nth(1).unwrap().contains("stopped"));
+        assert_eq!(read(text.as_bytes()).unwrap(), (records, 0));
+    }
+
+    #[test]
+    fn corrupt_lines_are_skipped_and_counted() {
+        let mut out = Vec::new();
+        let records = conversation();
+        write(&mut out, &records[1]).unwrap();
+        out.extend_from_slice(b"{\"role\":\"user\",\"text\":\"cut off\n");
+        out.extend_from_slice(b"\n");
+        out.extend_from_slice(b"{\"role\":\"robot\",\"text\":\"?\",\"ts\":1}\n");
+        write(&mut out, &records[2]).unwrap();
+        let (read, skipped) = read(&out[..]).unwrap();
+        assert_eq!(read, records[1..]);
+        assert_eq!(skipped, 2);
+    }
+
+    #[test]
+    fn the_writer_appends_on_its_own_thread() {
+        let dir = std::env::temp_dir().join(format!("noctics-session-{}", std::process::id()));
+        let path = dir.join("a").join("s.jsonl");
+        let (errors, failed) = mpsc::channel();
+        let writer = Writer::spawn(path.clone(), move |err| {
+            let _ = errors.send(err);
+        });
+        let records = conversation();
+        for record in &records {
+            writer.append(record.clone());
+        }
+        assert!(writer.close(Instant::now() + shutdown::TIMEOUT));
+        assert!(failed.try_recv().is_err());
+        assert_eq!(load(&path).unwrap(), (records, 0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stamps_are_utc() {
+        assert_eq!(utc_stamp(0), "19700101-000000");
+        assert_eq!(utc_stamp(951_782_400 + 3_723), "20000229-010203");
+        assert_eq!(utc_stamp(1_792_160_553), "20261016-142233");
+    }
+
+    #[test]
+    fn only_trailing_drafts_are_handed_back() {
+        let mut records = vec![
+            Record::draft("handed back last time"),
+            Record::new(Role::User, "hello"),
+            Record::new(Role::Assistant, "hi"),
+            Record::draft("next"),
+            Record::draft("after that"),
+        ];
+        let drafts = take_drafts(&mut records);
+        let texts = |records: &[Record]| -> Vec<String> {
+            records.iter().map(|record| record.text.clone()).collect()
+        };
+        assert_eq!(texts(&drafts), ["next", "after that"]);
+        assert_eq!(texts(&records), ["hello", "hi"]);
+        assert!(take_drafts(&mut records).is_empty());
+    }
+}
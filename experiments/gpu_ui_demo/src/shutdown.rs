@@ -0,0 +1,40 @@
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How long exit waits for a tab's threads, all told, before leaving them.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a thread being waited for is checked on.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Joins `handle` if its thread ends by `deadline`. `false` when it is still
+/// running then; it is left to end on its own, so a stuck thread can't hold
+/// the app open.
+pub fn join_by(handle: JoinHandle<()>, deadline: Instant) -> bool {
+    while !handle.is_finished() {
+        let now = Instant::now();
+        if now >= deadline {
+            return false;
+        }
+        thread::sleep(POLL.min(deadline - now));
+    }
+    let _ = handle.join();
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_stuck_thread_is_left_at_the_deadline() {
+        let quick = thread::spawn(|| thread::sleep(Duration::from_millis(20)));
+        assert!(join_by(quick, Instant::now() + TIMEOUT));
+
+        let start = Instant::now();
+        let stuck = thread::spawn(|| thread::sleep(Duration::from_secs(5)));
+        assert!(!join_by(stuck, start + Duration::from_millis(100)));
+        let waited = start.elapsed();
+        assert!(waited >= Duration::from_millis(100) && waited < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,458 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How far back the live tokens/sec looks.
+pub const WINDOW: Duration = Duration::from_secs(2);
+
+/// About four characters to a token, for when the bridge doesn't say.
+pub fn estimate_tokens(chars: usize) -> usize {
+    chars.div_ceil(4)
+}
+
+/// Speed of one reply as it streams: time to first token from when the
+/// prompt was sent, and characters over time. Every reading takes the time
+/// it is for, so a timeline can be replayed through it.
+pub struct Meter {
+    sent: Option<Instant>,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    chars: usize,
+    /// Characters in the first delta, which arrived before any time had
+    /// passed to produce them.
+    first_chars: usize,
+    recent: VecDeque<(Instant, usize)>,
+}
+
+/// What the backend measured itself: the `stats` object on a bridge's
+/// `done`, or nox's `done` line. Backends differ in what they send and how,
+/// so see [`Reported::parse`].
+#[derive(Clone, Debug, Default)]
+pub struct Reported {
+    pub ttft_ms: Option<f64>,
+    pub tokens: Option<u64>,
+    pub tok_per_s: Option<f64>,
+    /// What the prompt took up: the system prompt and every turn so far.
+    pub prompt_tokens: Option<u64>,
+    pub seed: Option<u64>,
+    pub model: Option<String>,
+    /// The object it was read from, as it came.
+    pub raw: Option<Map<String, Value>>,
+}
+
+impl Reported {
+    /// Reads a stats object, taking the first of each field's names that is
+    /// there. Numbers may come as integers, floats, or strings; a field that
+    /// is none of those counts as missing rather than failing the rest.
+    /// Anything but an object is `None`.
+    pub fn parse(value: &Value) -> Option<Self> {
+        let object = value.as_object()?;
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| object.get(*name).filter(|value| !value.is_null()))
+        };
+        Some(Self {
+            ttft_ms: field(&["ttft_ms"]).and_then(float),
+            tokens: field(&["tokens", "completion_tokens", "eval_count"]).and_then(count),
+            tok_per_s: field(&["tok_per_s", "tokens_per_second", "tps"]).and_then(float),
+            prompt_tokens: field(&["prompt_tokens", "prompt_eval_count"]).and_then(count),
+            seed: field(&["seed"]).and_then(count),
+            model: field(&["model"])
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|model| !model.is_empty())
+                .map(str::to_string),
+            raw: Some(object.clone()),
+        })
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str()?.trim().parse().ok())
+        .filter(|number| number.is_finite())
+}
+
+/// A whole, non-negative number; `212.0` is 212, and `212.5` rounds.
+fn count(value: &Value) -> Option<u64> {
+    value.as_u64().or_else(|| {
+        let number = float(value)?;
+        (number >= 0.0).then(|| number.round() as u64)
+    })
+}
+
+/// A finished reply's numbers, kept with its record in the session file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(rename = "ttft_ms", default, with = "millis")]
+    pub ttft: Option<Duration>,
+    pub tokens: usize,
+    /// The token count is estimated from characters.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub estimated: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tok_per_s: Option<f32>,
+    /// The model that wrote the reply, when the backend said. The record
+    /// keeps it alongside.
+    #[serde(skip)]
+    pub model: Option<String>,
+    /// The seed the backend applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// What the backend reported, as it came; only while the console runs.
+    #[serde(skip)]
+    pub raw: Option<Map<String, Value>>,
+}
+
+/// Keeps a `Duration` as whole milliseconds.
+mod millis {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<Duration>, ser: S) -> Result<S::Ok, S::Error> {
+        value.map(|ttft| ttft.as_millis() as u64).serialize(ser)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(de)?.map(Duration::from_millis))
+    }
+}
+
+impl Meter {
+    /// `sent` is when the prompt went out, if known.
+    pub fn new(sent: Option<Instant>) -> Self {
+        Self {
+            sent,
+            first: None,
+            last: None,
+            chars: 0,
+            first_chars: 0,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn delta(&mut self, at: Instant, text: &str) {
+        let chars = text.chars().count();
+        if self.first.is_none() {
+            self.first = Some(at);
+            self.first_chars = chars;
+        }
+        self.last = Some(at);
+        self.chars += chars;
+        self.recent.push_back((at, chars));
+        while self
+            .recent
+            .front()
+            .is_some_and(|(seen, _)| at.saturating_duration_since(*seen) > WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    pub fn ttft(&self) -> Option<Duration> {
+        Some(self.first?.saturating_duration_since(self.sent?))
+    }
+
+    /// When the first token arrived.
+    pub fn first(&self) -> Option<Instant> {
+        self.first
+    }
+
+    /// Estimated tokens per second over the last [`WINDOW`], or since the
+    /// first token if that is more recent, counting what arrived after the
+    /// window opened. `None` until there is time to divide by.
+    pub fn rate(&self, now: Instant) -> Option<f32> {
+        let first = self.first?;
+        let span = now.saturating_duration_since(first).min(WINDOW);
+        if span < Duration::from_millis(50) {
+            return None;
+        }
+        let chars: usize = self
+            .recent
+            .iter()
+            .filter(|(at, _)| now.saturating_duration_since(*at) < span)
+            .map(|(_, chars)| chars)
+            .sum();
+        Some(chars as f32 / 4.0 / span.as_secs_f32())
+    }
+
+    /// The reply's numbers once it is over, preferring what the bridge
+    /// `reported` over what was measured here. The rate runs from the first
+    /// delta to the last, so it needs at least two.
+    pub fn finish(&self, reported: Option<&Reported>) -> Stats {
+        let reported = reported.cloned().unwrap_or_default();
+        let measured_rate = self.first.zip(self.last).and_then(|(first, last)| {
+            let span = last.saturating_duration_since(first).as_secs_f32();
+            let chars = self.chars - self.first_chars;
+            (span > 0.0).then(|| chars as f32 / 4.0 / span)
+        });
+        Stats {
+            ttft: reported
+                .ttft_ms
+                .filter(|ms| ms.is_finite() && *ms >= 0.0)
+                .map(|ms| Duration::from_secs_f64(ms / 1000.0))
+                .or_else(|| self.ttft()),
+            tokens: reported
+                .tokens
+                .map_or_else(|| estimate_tokens(self.chars), |tokens| tokens as usize),
+            estimated: reported.tokens.is_none(),
+            tok_per_s: reported
+                .tok_per_s
+                .filter(|rate| rate.is_finite())
+                .map(|rate| rate as f32)
+                .or(measured_rate),
+            model: reported.model,
+            seed: reported.seed,
+            raw: reported.raw,
+        }
+    }
+
+    /// The status bar readout while streaming.
+    pub fn live(&self, now: Instant) -> String {
+        let Some(first) = self.first else {
+            return match self.sent {
+                Some(sent) => format!(
+                    "Waiting for first token… {:.1}s",
+                    now.saturating_duration_since(sent).as_secs_f32()
+                ),
+                None => "Waiting for first token…".to_string(),
+            };
+        };
+        let mut parts = Vec::new();
+        if let Some(ttft) = self.ttft() {
+            parts.push(format!("TTFT {}ms", ttft.as_millis()));
+        }
+        parts.push(format!(
+            "{} chars · ~{} tok",
+            self.chars,
+            estimate_tokens(self.chars)
+        ));
+        if let Some(rate) = self.rate(now) {
+            parts.push(format!("{:.0} tok/s", rate));
+        }
+        parts.push(format!(
+            "{:.1}s",
+            now.saturating_duration_since(first).as_secs_f32()
+        ));
+        parts.join(" · ")
+    }
+}
+
+impl Stats {
+    /// `TTFT 143ms · ~78 tok · 58 tok/s` for the status bar.
+    pub fn detail(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(ttft) = self.ttft {
+            parts.push(format!("TTFT {}ms", ttft.as_millis()));
+        }
+        let approx = if self.estimated { "~" } else { "" };
+        parts.push(format!("{}{} tok", approx, self.tokens));
+        if let Some(rate) = self.tok_per_s {
+            parts.push(format!("{:.0} tok/s", rate));
+        }
+        if let Some(model) = &self.model {
+            parts.push(model.clone());
+        }
+        parts.join(" · ")
+    }
+
+    /// `⟨nox · 143 ms TTFT · 58 tok/s · 212 tokens · seed 42⟩` for under the
+    /// reply, leaving out what isn't known.
+    pub fn footer(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(model) = &self.model {
+            parts.push(model.clone());
+        }
+        if let Some(ttft) = self.ttft {
+            parts.push(format!("{} ms TTFT", ttft.as_millis()));
+        }
+        if let Some(rate) = self.tok_per_s {
+            parts.push(format!("{:.0} tok/s", rate));
+        }
+        let approx = if self.estimated { "~" } else { "" };
+        parts.push(format!("{}{} tokens", approx, self.tokens));
+        if let Some(seed) = self.seed {
+            parts.push(format!("seed {}", seed));
+        }
+        format!("⟨{}⟩", parts.join(" · "))
+    }
+
+    /// What the backend reported, pretty-printed, for the footer's tooltip.
+    pub fn raw_json(&self) -> Option<String> {
+        let raw = self.raw.as_ref()?;
+        serde_json::to_string_pretty(raw).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    /// Forty characters every 100ms, the first 150ms after `sent`: ten
+    /// tokens at a time, a hundred tokens a second.
+    fn steady(sent: Instant, deltas: u64) -> Meter {
+        let mut meter = Meter::new(Some(sent));
+        for n in 0..deltas {
+            meter.delta(sent + ms(150 + 100 * n), &"x".repeat(40));
+        }
+        meter
+    }
+
+    #[test]
+    fn ttft_runs_from_the_prompt_to_the_first_delta() {
+        let sent = Instant::now();
+        let meter = steady(sent, 3);
+        assert_eq!(meter.ttft(), Some(ms(150)));
+        assert_eq!(meter.first(), Some(sent + ms(150)));
+        // Without a send time there's nothing to measure from.
+        let mut meter = Meter::new(None);
+        meter.delta(sent, "hi");
+        assert_eq!(meter.ttft(), None);
+    }
+
+    #[test]
+    fn the_live_rate_only_counts_the_last_window() {
+        let sent = Instant::now();
+        let mut meter = steady(sent, 40);
+        // A burst long ago doesn't lift the rate now.
+        let last = sent + ms(150 + 100 * 39);
+        assert!((meter.rate(last + ms(1)).unwrap() - 100.0).abs() < 6.0);
+        meter.delta(last + ms(2500), "y");
+        assert!(meter.rate(last + ms(2501)).unwrap() < 1.0);
+        // Too soon after the first delta to say.
+        assert_eq!(steady(sent, 1).rate(sent + ms(160)), None);
+    }
+
+    #[test]
+    fn finished_numbers_are_measured_unless_reported() {
+        let sent = Instant::now();
+        let meter = steady(sent, 11);
+        let stats = meter.finish(None);
+        assert_eq!(stats.ttft, Some(ms(150)));
+        assert_eq!((stats.tokens, stats.estimated), (110, true));
+        // The first delta's characters came before any time had passed.
+        assert_eq!(stats.tok_per_s, Some(100.0));
+        assert_eq!(stats.detail(), "TTFT 150ms · ~110 tok · 100 tok/s");
+
+        let reported = Reported {
+            ttft_ms: Some(42.0),
+            tokens: Some(96),
+            ..Reported::default()
+        };
+        let stats = meter.finish(Some(&reported));
+        assert_eq!(stats.ttft, Some(ms(42)));
+        assert_eq!((stats.tokens, stats.estimated), (96, false));
+        assert_eq!(stats.tok_per_s, Some(100.0));
+    }
+
+    #[test]
+    fn one_delta_has_no_rate() {
+        let sent = Instant::now();
+        let stats = steady(sent, 1).finish(None);
+        assert_eq!(stats.tok_per_s, None);
+        assert_eq!(stats.detail(), "TTFT 150ms · ~10 tok");
+    }
+
+    #[test]
+    fn the_live_readout_waits_for_the_first_token() {
+        let sent = Instant::now();
+        let meter = Meter::new(Some(sent));
+        assert_eq!(meter.live(sent + ms(1200)), "Waiting for first token… 1.2s");
+        let meter = steady(sent, 11);
+        assert_eq!(
+            meter.live(sent + ms(1151)),
+            "TTFT 150ms · 440 chars · ~110 tok · 100 tok/s · 1.0s"
+        );
+    }
+
+    fn reported(line: &str) -> Reported {
+        Reported::parse(&serde_json::from_str(line).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn reported_stats_are_read_leniently() {
+        // The bridge's own names, all there.
+        let bridge =
+            reported(r#"{"ttft_ms":143,"tokens":212,"tok_per_s":58.4,"seed":42,"model":"nox"}"#);
+        assert_eq!(bridge.ttft_ms, Some(143.0));
+        assert_eq!(bridge.tokens, Some(212));
+        assert_eq!(bridge.tok_per_s, Some(58.4));
+        assert_eq!(bridge.seed, Some(42));
+        assert_eq!(bridge.model.as_deref(), Some("nox"));
+        assert_eq!(bridge.raw.as_ref().unwrap().len(), 5);
+
+        // Counts as floats, llama.cpp-style names, and a blank model.
+        let floats = reported(
+            r#"{"ttft_ms":143.6,"eval_count":212.0,"prompt_eval_count":31,"tps":"58","model":" "}"#,
+        );
+        assert_eq!(floats.ttft_ms, Some(143.6));
+        assert_eq!(floats.tokens, Some(212));
+        assert_eq!(floats.prompt_tokens, Some(31));
+        assert_eq!(floats.tok_per_s, Some(58.0));
+        assert_eq!(floats.model, None);
+
+        // Numbers as strings, nulls, and fields of the wrong kind.
+        let odd = reported(
+            r#"{"ttft_ms":"95","completion_tokens":null,"tokens":"7","seed":-1,"tokens_per_second":[1],"model":3}"#,
+        );
+        assert_eq!(odd.ttft_ms, Some(95.0));
+        assert_eq!(odd.tokens, Some(7));
+        assert_eq!(odd.seed, None);
+        assert_eq!(odd.tok_per_s, None);
+        assert_eq!(odd.model, None);
+
+        // Nothing known at all still parses.
+        let empty = reported("{}");
+        assert!(empty.ttft_ms.is_none() && empty.tokens.is_none() && empty.seed.is_none());
+        assert!(Reported::parse(&serde_json::json!([1, 2])).is_none());
+        assert!(Reported::parse(&serde_json::json!("stats")).is_none());
+    }
+
+    #[test]
+    fn the_footer_leaves_out_what_is_unknown() {
+        let full = Stats {
+            ttft: Some(ms(143)),
+            tokens: 212,
+            estimated: false,
+            tok_per_s: Some(58.4),
+            model: Some("nox".to_string()),
+            seed: Some(42),
+            raw: None,
+        };
+        assert_eq!(
+            full.footer(),
+            "⟨nox · 143 ms TTFT · 58 tok/s · 212 tokens · seed 42⟩"
+        );
+        let bare = Stats {
+            ttft: None,
+            tokens: 9,
+            estimated: true,
+            tok_per_s: None,
+            model: None,
+            seed: None,
+            raw: None,
+        };
+        assert_eq!(bare.footer(), "⟨~9 tokens⟩");
+        assert_eq!(bare.raw_json(), None);
+    }
+
+    #[test]
+    fn reported_numbers_win_and_keep_the_raw_object() {
+        let sent = Instant::now();
+        let stats = steady(sent, 3).finish(Some(&reported(
+            r#"{"ttft_ms":"80","eval_count":30.0,"seed":7}"#,
+        )));
+        assert_eq!(stats.ttft, Some(ms(80)));
+        assert_eq!(stats.tokens, 30);
+        assert!(!stats.estimated);
+        assert_eq!(stats.tok_per_s, Some(100.0));
+        assert_eq!(stats.seed, Some(7));
+        assert!(stats.raw_json().unwrap().contains("\"eval_count\": 30.0"));
+    }
+}
@@ -0,0 +1,137 @@
+use serde::{Deserialize, Deserializer};
+use std::collections::VecDeque;
+
+/// How many `stats` messages a tab keeps for its sparklines.
+pub const HISTORY: usize = 120;
+
+/// One `stats` message from the bridge; it may leave out any field.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    /// Percent, 0 to 100.
+    pub gpu_util: Option<f32>,
+    pub vram_used_mb: Option<f32>,
+    pub vram_total_mb: Option<f32>,
+    /// Tokens per second, as the bridge measures it.
+    pub tps: Option<f32>,
+    pub temp_c: Option<f32>,
+}
+
+/// Picks one number out of a [`Sample`].
+pub type Field = fn(&Sample) -> Option<f32>;
+
+/// The last [`HISTORY`] samples, oldest first.
+#[derive(Default)]
+pub struct Telemetry {
+    samples: VecDeque<Sample>,
+}
+
+impl Sample {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl Telemetry {
+    pub fn push(&mut self, sample: Sample) {
+        self.samples.push_back(sample);
+        while self.samples.len() > HISTORY {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The newest value `field` picks out, from whichever sample last had
+    /// one, so a bridge that sends its numbers in separate messages doesn't
+    /// flicker.
+    pub fn latest(&self, field: Field) -> Option<f32> {
+        self.samples.iter().rev().find_map(field)
+    }
+
+    /// Every value `field` picks out, oldest first, skipping the samples
+    /// without one.
+    pub fn series(&self, field: Field) -> Vec<f32> {
+        self.samples.iter().filter_map(field).collect()
+    }
+}
+
+/// Squeezes `values` into at most `points` by averaging runs of neighbours,
+/// for a sparkline narrower than the history. Fewer values than that come
+/// back as they are.
+pub fn downsample(values: &[f32], points: usize) -> Vec<f32> {
+    if points == 0 {
+        return Vec::new();
+    }
+    if values.len() <= points {
+        return values.to_vec();
+    }
+    (0..points)
+        .map(|idx| {
+            let start = idx * values.len() / points;
+            let end = (idx + 1) * values.len() / points;
+            let run = &values[start..end];
+            run.iter().sum::<f32>() / run.len() as f32
+        })
+        .collect()
+}
+
+/// Reads a `stats` field as a number, and anything else (a string, null) as
+/// missing, so one odd field doesn't cost the whole message.
+pub fn number<'de, D: Deserializer<'de>>(de: D) -> Result<Option<f32>, D::Error> {
+    let value = serde_json::Value::deserialize(de)?;
+    Ok(value
+        .as_f64()
+        .filter(|number| number.is_finite())
+        .map(|number| number as f32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn util(value: f32) -> Sample {
+        Sample {
+            gpu_util: Some(value),
+            ..Sample::default()
+        }
+    }
+
+    #[test]
+    fn the_history_keeps_the_newest_samples() {
+        let mut telemetry = Telemetry::default();
+        assert!(telemetry.is_empty());
+        for n in 0..HISTORY + 30 {
+            telemetry.push(util(n as f32));
+        }
+        let series = telemetry.series(|sample| sample.gpu_util);
+        assert_eq!(series.len(), HISTORY);
+        assert_eq!((series[0], series[HISTORY - 1]), (30.0, 149.0));
+    }
+
+    #[test]
+    fn the_latest_value_outlives_samples_without_it() {
+        let mut telemetry = Telemetry::default();
+        telemetry.push(util(40.0));
+        telemetry.push(Sample {
+            temp_c: Some(61.0),
+            ..Sample::default()
+        });
+        assert_eq!(telemetry.latest(|sample| sample.gpu_util), Some(40.0));
+        assert_eq!(telemetry.latest(|sample| sample.temp_c), Some(61.0));
+        assert_eq!(telemetry.latest(|sample| sample.tps), None);
+        assert_eq!(telemetry.series(|sample| sample.temp_c), [61.0]);
+    }
+
+    #[test]
+    fn downsampling_averages_neighbours() {
+        let values: Vec<f32> = (0..120).map(|n| n as f32).collect();
+        let points = downsample(&values, 40);
+        assert_eq!(points.len(), 40);
+        assert_eq!((points[0], points[39]), (1.0, 118.0));
+        assert_eq!(downsample(&[1.0, 2.0], 40), [1.0, 2.0]);
+        assert_eq!(downsample(&[4.0, 8.0, 3.0], 2), [4.0, 5.5]);
+        assert!(downsample(&values, 0).is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use eframe::egui::{self, Color32, FontId, TextStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The text scale Ctrl+= and Ctrl+- stay within.
+pub const MIN_ZOOM: f32 = 0.8;
+pub const MAX_ZOOM: f32 = 2.0;
+pub const ZOOM_STEP: f32 = 0.1;
+
+/// How far apart in relative luminance a palette's colors must be from its
+/// background to stay readable.
+pub const MIN_CONTRAST: f32 = 0.3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    /// Whatever the OS prefers, or dark where it can't tell.
+    System,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 3] = [Theme::Dark, Theme::Light, Theme::System];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dark => "Dark",
+            Theme::Light => "Light",
+            Theme::System => "System",
+        }
+    }
+
+    /// Whether to draw dark, given what the OS said, if anything.
+    pub fn dark(self, system: Option<eframe::Theme>) -> bool {
+        match self {
+            Theme::Dark => true,
+            Theme::Light => false,
+            Theme::System => system != Some(eframe::Theme::Light),
+        }
+    }
+}
+
+/// The colors the log, the diagnostics drawer, and the context meter draw
+/// with, one set per [`Theme`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    /// What the colors below are drawn on.
+    pub background: Color32,
+    pub user: Color32,
+    pub assistant: Color32,
+    /// The reply still streaming in.
+    pub streaming: Color32,
+    pub stopped: Color32,
+    pub failed: Color32,
+    pub note: Color32,
+    pub info: Color32,
+    pub warn: Color32,
+    pub error: Color32,
+}
+
+pub const DARK: Palette = Palette {
+    background: Color32::from_rgb(27, 27, 27),
+    user: Color32::from_rgb(160, 220, 255),
+    assistant: Color32::from_rgb(180, 255, 180),
+    streaming: Color32::from_rgb(200, 255, 200),
+    stopped: Color32::LIGHT_GRAY,
+    failed: Color32::from_rgb(255, 160, 140),
+    note: Color32::LIGHT_GRAY,
+    info: Color32::LIGHT_GRAY,
+    warn: Color32::from_rgb(255, 210, 120),
+    error: Color32::from_rgb(255, 140, 140),
+};
+
+pub const LIGHT: Palette = Palette {
+    background: Color32::from_rgb(248, 248, 248),
+    user: Color32::from_rgb(20, 90, 160),
+    assistant: Color32::from_rgb(20, 110, 40),
+    streaming: Color32::from_rgb(30, 130, 60),
+    stopped: Color32::from_rgb(90, 90, 90),
+    failed: Color32::from_rgb(180, 40, 30),
+    note: Color32::from_rgb(90, 90, 90),
+    info: Color32::from_rgb(90, 90, 90),
+    warn: Color32::from_rgb(150, 95, 0),
+    error: Color32::from_rgb(180, 40, 30),
+};
+
+impl Palette {
+    pub fn new(dark: bool) -> Self {
+        let palette = if dark { DARK } else { LIGHT };
+        debug_assert!(palette.faint().is_empty(), "faint colors: {:?}", palette.faint());
+        palette
+    }
+
+    /// Every color drawn on the background, by name.
+    pub fn roles(&self) -> [(&'static str, Color32); 9] {
+        [
+            ("user", self.user),
+            ("assistant", self.assistant),
+            ("streaming", self.streaming),
+            ("stopped", self.stopped),
+            ("failed", self.failed),
+            ("note", self.note),
+            ("info", self.info),
+            ("warn", self.warn),
+            ("error", self.error),
+        ]
+    }
+
+    /// The roles closer to the background than [`MIN_CONTRAST`].
+    pub fn faint(&self) -> Vec<&'static str> {
+        let background = luminance(self.background);
+        self.roles()
+            .into_iter()
+            .filter(|(_, color)| (luminance(*color) - background).abs() < MIN_CONTRAST)
+            .map(|(name, _)| name)
+            .collect()
+    }
+}
+
+/// Relative luminance, 0 for black to 1 for white.
+pub fn luminance(color: Color32) -> f32 {
+    let channel = |value: u8| {
+        let value = value as f32 / 255.0;
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * channel(color.r()) + 0.7152 * channel(color.g()) + 0.0722 * channel(color.b())
+}
+
+/// egui's own look for the theme, with the panels in the palette's
+/// background.
+pub fn visuals(dark: bool) -> egui::Visuals {
+    let mut visuals = if dark {
+        egui::Visuals::dark()
+    } else {
+        egui::Visuals::light()
+    };
+    visuals.panel_fill = Palette::new(dark).background;
+    visuals
+}
+
+/// `base` with every text style `zoom` times the size.
+pub fn zoomed(base: &BTreeMap<TextStyle, FontId>, zoom: f32) -> BTreeMap<TextStyle, FontId> {
+    base.iter()
+        .map(|(style, font)| (style.clone(), FontId::new(font.size * zoom, font.family.clone())))
+        .collect()
+}
+
+/// `zoom` moved `steps` steps, kept in range and to a tenth.
+pub fn step_zoom(zoom: f32, steps: i32) -> f32 {
+    let zoom = (zoom + steps as f32 * ZOOM_STEP).clamp(MIN_ZOOM, MAX_ZOOM);
+    (zoom * 10.0).round() / 10.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_role_stands_out_from_its_background() {
+        for palette in [DARK, LIGHT] {
+            assert_eq!(palette.faint(), Vec::<&str>::new(), "{:?}", palette);
+            let background = luminance(palette.background);
+            for (name, color) in palette.roles() {
+                let delta = (luminance(color) - background).abs();
+                assert!(delta >= MIN_CONTRAST, "{} is {:.2} off", name, delta);
+            }
+        }
+    }
+
+    #[test]
+    fn a_faint_color_is_caught() {
+        let palette = Palette {
+            note: Color32::from_rgb(40, 40, 40),
+            ..DARK
+        };
+        assert_eq!(palette.faint(), ["note"]);
+    }
+
+    #[test]
+    fn luminance_runs_from_black_to_white() {
+        assert_eq!(luminance(Color32::BLACK), 0.0);
+        assert!((luminance(Color32::WHITE) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn zoom_steps_stay_in_range() {
+        assert_eq!(step_zoom(1.0, 1), 1.1);
+        assert_eq!(step_zoom(1.0, -3), 0.8);
+        assert_eq!(step_zoom(1.9, 5), MAX_ZOOM);
+        assert_eq!(step_zoom(0.8, -1), MIN_ZOOM);
+        let base = BTreeMap::from([(TextStyle::Body, FontId::proportional(14.0))]);
+        assert_eq!(zoomed(&base, 1.5)[&TextStyle::Body].size, 21.0);
+    }
+
+    #[test]
+    fn system_follows_the_os_or_goes_dark() {
+        assert!(Theme::System.dark(None));
+        assert!(!Theme::System.dark(Some(eframe::Theme::Light)));
+        assert!(!Theme::Light.dark(Some(eframe::Theme::Dark)));
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::mem;
+use std::sync::Arc;
+
+use crate::score::{tokens, TokenOptions};
+
+/// Words of each chunk kept between routing calls, so a context that stays
+/// the same while the query changes, as in a chat session, is tokenized
+/// once. Chunks are keyed by a hash of their text and the
+/// [`TokenOptions`], and a hit is checked against the text itself, so two
+/// texts with the same hash never share words. The least recently used
+/// are dropped once the cached words and texts take more than the cap.
+/// TF-IDF and BM25 document frequencies are kept too, for the last set of
+/// chunks seen.
+#[derive(Debug)]
+pub struct RouteCache {
+    max_bytes: usize,
+    bytes: usize,
+    tick: u64,
+    entries: HashMap<Key, Entry>,
+    /// Keys by when they were last used, oldest first.
+    by_age: BTreeMap<u64, Key>,
+    /// Document frequencies for the chunks with these words.
+    doc_freqs: Option<(Vec<Arc<[String]>>, DocFreqs)>,
+    hits: u64,
+    misses: u64,
+}
+
+/// A chunk's hash and length in bytes.
+type Key = (u64, usize);
+
+/// How many chunks have each word.
+type DocFreqs = Arc<HashMap<String, f32>>;
+
+#[derive(Debug)]
+struct Entry {
+    /// The text the words are from, to tell a hash collision from a hit.
+    text: Box<str>,
+    words: Arc<[String]>,
+    bytes: usize,
+    used: u64,
+}
+
+/// What a [`RouteCache`] has done so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CacheStats {
+    /// Chunks found in the cache.
+    pub hits: u64,
+    /// Chunks tokenized, whether or not they were then kept.
+    pub misses: u64,
+    /// Chunks held now.
+    pub entries: usize,
+    /// Roughly the memory their words and texts take.
+    pub bytes: usize,
+}
+
+impl Default for RouteCache {
+    /// A cache capped at 16 MiB.
+    fn default() -> Self {
+        Self::new(16 << 20)
+    }
+}
+
+impl RouteCache {
+    /// A cache holding at most about `max_bytes` of words and texts. A
+    /// chunk whose words and text alone are larger is tokenized every
+    /// time.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            by_age: BTreeMap::new(),
+            doc_freqs: None,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            bytes: self.bytes,
+        }
+    }
+
+    /// The words of `text`, as [`tokens`] gives them.
+    pub(crate) fn words(&mut self, text: &str, opts: &TokenOptions) -> Arc<[String]> {
+        let key = key(text, opts);
+        self.tick += 1;
+        match self.entries.get_mut(&key) {
+            Some(entry) if *entry.text == *text => {
+                self.hits += 1;
+                self.by_age.remove(&entry.used);
+                entry.used = self.tick;
+                self.by_age.insert(self.tick, key);
+                return Arc::clone(&entry.words);
+            }
+            // Another text with the same hash and length: it makes way.
+            Some(_) => {
+                if let Some(entry) = self.entries.remove(&key) {
+                    self.by_age.remove(&entry.used);
+                    self.bytes -= entry.bytes;
+                }
+            }
+            None => {}
+        }
+        self.misses += 1;
+        let words: Arc<[String]> = tokens(text, opts).into();
+        let bytes = words
+            .iter()
+            .map(|word| word.len() + mem::size_of::<String>())
+            .sum::<usize>()
+            + text.len()
+            + mem::size_of::<Entry>();
+        if bytes > self.max_bytes {
+            return words;
+        }
+        while self.bytes + bytes > self.max_bytes {
+            let Some((_, oldest)) = self.by_age.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.bytes -= entry.bytes;
+            }
+        }
+        self.bytes += bytes;
+        self.by_age.insert(self.tick, key);
+        self.entries.insert(
+            key,
+            Entry {
+                text: text.into(),
+                words: Arc::clone(&words),
+                bytes,
+                used: self.tick,
+            },
+        );
+        words
+    }
+
+    /// The words of each chunk and, for each word, how many chunks have
+    /// it.
+    pub(crate) fn corpus(
+        &mut self,
+        chunks: &[String],
+        opts: &TokenOptions,
+    ) -> (Vec<Arc<[String]>>, DocFreqs) {
+        let docs: Vec<Arc<[String]>> = chunks.iter().map(|c| self.words(c, opts)).collect();
+        // The counts depend only on the words, which usually are the very
+        // ones cached last time.
+        if let Some((seen, df)) = &self.doc_freqs {
+            let same = |(a, b): (&Arc<[String]>, &Arc<[String]>)| Arc::ptr_eq(a, b) || a == b;
+            if seen.len() == docs.len() && seen.iter().zip(&docs).all(same) {
+                return (docs, Arc::clone(df));
+            }
+        }
+        let mut df: HashMap<String, f32> = HashMap::new();
+        for doc in &docs {
+            let seen: HashSet<&str> = doc.iter().map(String::as_str).collect();
+            for term in seen {
+                *df.entry(term.to_string()).or_default() += 1.0;
+            }
+        }
+        let df = Arc::new(df);
+        self.doc_freqs = Some((docs.clone(), Arc::clone(&df)));
+        (docs, df)
+    }
+}
+
+fn key(text: &str, opts: &TokenOptions) -> Key {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    (opts.stopwords, opts.stem).hash(&mut hasher);
+    (hasher.finish(), text.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN: TokenOptions = TokenOptions {
+        stopwords: false,
+        stem: false,
+    };
+
+    fn words(cache: &mut RouteCache, text: &str) -> Vec<String> {
+        cache.words(text, &PLAIN).to_vec()
+    }
+
+    #[test]
+    fn a_text_whose_hash_collides_gets_its_own_words() {
+        let mut cache = RouteCache::default();
+        assert_eq!(words(&mut cache, "alpha beta"), ["alpha", "beta"]);
+        // File the entry under the key of another text of the same length,
+        // as a 64-bit collision would.
+        let entry = cache.entries.remove(&key("alpha beta", &PLAIN)).unwrap();
+        let other = key("gamma zeta", &PLAIN);
+        cache.by_age.insert(entry.used, other);
+        cache.entries.insert(other, entry);
+
+        assert_eq!(words(&mut cache, "gamma zeta"), ["gamma", "zeta"]);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (0, 2));
+        assert_eq!(cache.stats().entries, 1);
+        assert_eq!(words(&mut cache, "gamma zeta"), ["gamma", "zeta"]);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn the_cap_drops_the_least_recently_used() {
+        let mut cache = RouteCache::new(0);
+        words(&mut cache, "one");
+        assert_eq!(cache.stats().entries, 0, "nothing fits in no room");
+
+        let mut cache = RouteCache::default();
+        words(&mut cache, "aaa");
+        let each = cache.stats().bytes;
+        let mut cache = RouteCache::new(each * 2);
+        for text in ["aaa", "bbb", "aaa", "ccc"] {
+            words(&mut cache, text);
+        }
+        // "bbb" was the oldest when "ccc" came in.
+        assert_eq!(cache.stats().entries, 2);
+        words(&mut cache, "aaa");
+        words(&mut cache, "ccc");
+        assert_eq!((cache.stats().hits, cache.stats().misses), (3, 3));
+        words(&mut cache, "bbb");
+        assert_eq!(cache.stats().misses, 4);
+    }
+
+    #[test]
+    fn document_frequencies_are_kept_for_the_same_chunks() {
+        let mut cache = RouteCache::default();
+        let chunks =
+            |texts: &[&str]| -> Vec<String> { texts.iter().map(|t| t.to_string()).collect() };
+        let (_, first) = cache.corpus(&chunks(&["a b", "b c"]), &PLAIN);
+        assert_eq!(first["b"], 2.0);
+        let (_, again) = cache.corpus(&chunks(&["a b", "b c"]), &PLAIN);
+        assert!(Arc::ptr_eq(&first, &again));
+        let (_, other) = cache.corpus(&chunks(&["a b", "c d"]), &PLAIN);
+        assert_eq!(other["b"], 1.0);
+    }
+}
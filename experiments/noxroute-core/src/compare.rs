@@ -0,0 +1,156 @@
+use std::cmp::Ordering;
+
+use crate::neuroute::{route_values_with, RouteConfig, RouteError};
+use crate::prompt::top_k_indices;
+
+/// A way of choosing which values to keep, the router or a naive baseline
+/// to hold it against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    /// The router, as [`route_values_with`] runs it.
+    Neural,
+    /// Values at least 0.5 once scaled so the lowest is 0 and the highest
+    /// 1; all of them when they are equal.
+    Threshold,
+    /// The highest values, as many as the router kept, so the two differ
+    /// only in which they keep.
+    TopK,
+    /// Values at or above the median.
+    Median,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 4] = [
+        Strategy::Neural,
+        Strategy::Threshold,
+        Strategy::TopK,
+        Strategy::Median,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Neural => "neural",
+            Strategy::Threshold => "threshold",
+            Strategy::TopK => "top-k",
+            Strategy::Median => "median",
+        }
+    }
+}
+
+/// The mask each [`Strategy`] gives the same values, in
+/// [`Strategy::ALL`] order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub masks: Vec<(Strategy, Vec<bool>)>,
+}
+
+/// How well a mask matches labelled ones, each from 0 to 1; 0 where there
+/// is nothing to divide by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Metrics {
+    pub precision: f32,
+    pub recall: f32,
+    pub f1: f32,
+}
+
+impl Comparison {
+    /// The share of values on which the masks at `a` and `b` agree.
+    pub fn agreement(&self, a: usize, b: usize) -> f32 {
+        let (a, b) = (&self.masks[a].1, &self.masks[b].1);
+        if a.is_empty() {
+            return 1.0;
+        }
+        let same = a.iter().zip(b).filter(|(x, y)| x == y).count();
+        same as f32 / a.len() as f32
+    }
+
+    /// Each strategy's [`Metrics`] against `labels`, the values that should
+    /// have been kept.
+    pub fn metrics(&self, labels: &[bool]) -> Vec<(Strategy, Metrics)> {
+        self.masks
+            .iter()
+            .map(|(strategy, mask)| (*strategy, metrics(mask, labels)))
+            .collect()
+    }
+}
+
+/// Routes `values` under every [`Strategy`]. Fails as
+/// [`route_values_with`] does. Values that are not finite are never kept by
+/// the baselines.
+pub fn compare(values: &[f32], cfg: &RouteConfig) -> Result<Comparison, RouteError> {
+    let neural = route_values_with(values, cfg)?.mask;
+    let kept = neural.iter().filter(|m| **m).count();
+    let finite: Vec<f32> = values.iter().copied().filter(|v| v.is_finite()).collect();
+
+    let (lo, hi) = finite
+        .iter()
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), v| {
+            (lo.min(*v), hi.max(*v))
+        });
+    let threshold = values
+        .iter()
+        .map(|v| v.is_finite() && (hi == lo || (v - lo) / (hi - lo) >= 0.5))
+        .collect();
+
+    let ranked: Vec<f32> = values
+        .iter()
+        .map(|v| if v.is_finite() { *v } else { f32::NEG_INFINITY })
+        .collect();
+    let mut top_k = vec![false; values.len()];
+    for idx in top_k_indices(&ranked, kept) {
+        top_k[idx] = values[idx].is_finite();
+    }
+
+    let median = {
+        let mut sorted = finite.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let len = sorted.len();
+        (len > 0).then(|| (sorted[(len - 1) / 2] + sorted[len / 2]) / 2.0)
+    };
+    let median = values
+        .iter()
+        .map(|v| v.is_finite() && median.is_some_and(|m| *v >= m))
+        .collect();
+
+    Ok(Comparison {
+        masks: vec![
+            (Strategy::Neural, neural),
+            (Strategy::Threshold, threshold),
+            (Strategy::TopK, top_k),
+            (Strategy::Median, median),
+        ],
+    })
+}
+
+/// Precision, recall, and F1 of `mask` against `labels`, counting a kept
+/// value as positive. Entries past the shorter of the two are ignored.
+pub fn metrics(mask: &[bool], labels: &[bool]) -> Metrics {
+    let (mut tp, mut fp, mut fn_) = (0, 0, 0);
+    for (kept, wanted) in mask.iter().zip(labels) {
+        match (kept, wanted) {
+            (true, true) => tp += 1,
+            (true, false) => fp += 1,
+            (false, true) => fn_ += 1,
+            (false, false) => {}
+        }
+    }
+    let ratio = |num: usize, den: usize| {
+        if den == 0 {
+            0.0
+        } else {
+            num as f32 / den as f32
+        }
+    };
+    let precision = ratio(tp, tp + fp);
+    let recall = ratio(tp, tp + fn_);
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+    Metrics {
+        precision,
+        recall,
+        f1,
+    }
+}
@@ -0,0 +1,35 @@
+//! Context routing for Nox: picks which chunks of a long prompt are worth
+//! sending to the model. [`route_values`] runs the learned router over a
+//! list of scores; [`route_prompt`] splits a prompt into chunks, scores
+//! them against a query with a [`Scorer`], and keeps the routed ones.
+//!
+//! Shared by the `nox` and `noxroute` binaries and the engine crate, so
+//! there is one copy of the model weights. No dependencies beyond std,
+//! except `rayon` for the feature of that name.
+
+mod cache;
+mod compare;
+mod neuroute;
+mod prompt;
+mod regex;
+mod report;
+mod routing_weights;
+mod score;
+mod split;
+
+pub use cache::{CacheStats, RouteCache};
+pub use compare::{compare, metrics, Comparison, Metrics, Strategy};
+pub use neuroute::{
+    route_features, route_values, route_values_explained, route_values_with, Explanation,
+    MaskStrategy, PlattParams, RouteConfig, RouteError, RouteResult, RoutingModel, Weights,
+    WeightsError, FEATURE_NAMES,
+};
+pub use prompt::{
+    prompt_query, route_chunks, route_chunks_cached, route_chunks_scored, route_prompt,
+    route_prompt_cached, route_prompt_scored, top_k_indices, Anchors, Chunk, Duplicate, Order,
+    Pick, RouteOptions, Routed, ScoreFn,
+};
+pub use regex::{Regex, RegexError};
+pub use report::{ChunkReport, Reason, RouteReport, Timings};
+pub use score::{overlap_score, token_set, tokens, Scorer, TokenOptions};
+pub use split::{first_paragraph, merge_short, split_chunks, windows, Autochunk, Split};
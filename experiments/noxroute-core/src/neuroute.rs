@@ -0,0 +1,1468 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::routing_weights;
+
+const EPS: f32 = 1e-6;
+
+/// What the router made of a list of values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteResult {
+    /// Probability that each value should be kept.
+    pub probs: Vec<f32>,
+    /// Whether each value is kept.
+    pub mask: Vec<bool>,
+    /// A permutation of the indices: kept ones first, each group in its
+    /// original order.
+    pub perm: Vec<usize>,
+    /// Indices of values that were NaN or infinite, routed as the mean of
+    /// the finite ones instead.
+    pub patched: Vec<usize>,
+    /// The mask the router settled on before `min_keep` and `max_keep`:
+    /// [`MaskStrategy::Threshold`] or [`MaskStrategy::TopK`].
+    pub strategy: MaskStrategy,
+    /// Indices flipped from that mask to meet `min_keep` and `max_keep`, or
+    /// to keep at least one value.
+    pub clamped: Vec<usize>,
+}
+
+/// Why the router could not run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteError {
+    /// There were no values to route.
+    Empty,
+    /// [`RouteConfig::prob_temperature`] was not a positive number.
+    Temperature,
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::Empty => write!(f, "no values to route"),
+            RouteError::Temperature => {
+                write!(f, "the probability temperature must be a positive number")
+            }
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+/// Which mask the router settles on before `min_keep`/`max_keep` apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MaskStrategy {
+    /// The likelier of the threshold mask and the top-k-hat mask, where
+    /// k-hat is the sum of the probabilities rounded.
+    #[default]
+    Likelihood,
+    /// Everything at or above the threshold.
+    Threshold,
+    /// The k-hat most probable values.
+    TopK,
+}
+
+/// Tuning for [`route_values_with`]. The default is what [`route_values`]
+/// does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RouteConfig {
+    /// Values at least this probable are in the threshold mask. 1.0 puts
+    /// none in it, since a probability can round to exactly 1.
+    pub threshold: f32,
+    /// Keep at least this many, promoting the most probable of the rest.
+    /// A mask with too few or too many is corrected by flipping the
+    /// decisions the router was least sure of, not replaced.
+    pub min_keep: usize,
+    /// Keep at most this many, dropping the least probable kept ones.
+    /// Wins over `min_keep` when the two disagree, but the most probable
+    /// value is kept even when this is 0.
+    pub max_keep: Option<usize>,
+    pub prefer: MaskStrategy,
+    /// Divides each logit before the sigmoid: below 1 pushes probabilities
+    /// towards 0 and 1, above 1 towards 0.5. The order never changes.
+    pub prob_temperature: f32,
+    /// Maps each logit through a fitted [`PlattParams`], before the
+    /// temperature.
+    pub calibration: Option<PlattParams>,
+    /// The network that scores the values; see [`RoutingModel`].
+    pub weights: Weights,
+}
+
+/// The router's network weights: a set compiled in, or one fitted by
+/// `noxroute train` and read back with [`Weights::parse`].
+#[derive(Clone, Copy, PartialEq)]
+pub struct Weights {
+    /// `w1[i * hidden + h]` joins input `i` to hidden unit `h`.
+    w1: &'static [f32],
+    b1: &'static [f32],
+    w2: &'static [f32],
+    b2: f32,
+}
+
+// The compiled arrays have the sizes `routing_weights.rs` declares.
+const _: () = {
+    use routing_weights::*;
+    assert!(W1.len() == IN_DIM * HIDDEN && W2.len() == HIDDEN);
+    assert!(AGGRESSIVE_W1.len() == IN_DIM * AGGRESSIVE_HIDDEN);
+    assert!(AGGRESSIVE_W2.len() == AGGRESSIVE_HIDDEN);
+    assert!(RECALL_W1.len() == IN_DIM * RECALL_HIDDEN && RECALL_W2.len() == RECALL_HIDDEN);
+};
+
+impl Weights {
+    /// The weights in `routing_weights.rs`, as trained.
+    pub const TRAINED: Weights = Weights {
+        w1: &routing_weights::W1,
+        b1: &routing_weights::B1,
+        w2: &routing_weights::W2,
+        b2: routing_weights::B2,
+    };
+
+    /// The `aggressive` profile's network, fitted to keep only the scores
+    /// that stand clear of the rest; see `profiles/aggressive.txt`.
+    pub const AGGRESSIVE: Weights = Weights {
+        w1: &routing_weights::AGGRESSIVE_W1,
+        b1: &routing_weights::AGGRESSIVE_B1,
+        w2: &routing_weights::AGGRESSIVE_W2,
+        b2: routing_weights::AGGRESSIVE_B2,
+    };
+
+    /// The `recall` profile's network, fitted to drop only the scores that
+    /// trail well behind; see `profiles/recall.txt`.
+    pub const RECALL: Weights = Weights {
+        w1: &routing_weights::RECALL_W1,
+        b1: &routing_weights::RECALL_B1,
+        w2: &routing_weights::RECALL_W2,
+        b2: routing_weights::RECALL_B2,
+    };
+
+    /// A network of `b1.len()` hidden units. Fails unless `w1` has eight
+    /// weights for each unit and `w2` one, and every number is finite.
+    /// The arrays are leaked so the weights stay `Copy` like the compiled
+    /// ones: make them once, not once a route.
+    pub fn new(w1: Vec<f32>, b1: Vec<f32>, w2: Vec<f32>, b2: f32) -> Result<Self, WeightsError> {
+        let hidden = b1.len();
+        let whole = |message: String| WeightsError { line: 0, message };
+        if hidden == 0 {
+            return Err(whole("there are no hidden units".to_string()));
+        }
+        if w1.len() != routing_weights::IN_DIM * hidden || w2.len() != hidden {
+            return Err(whole(format!(
+                "{hidden} hidden units need {} w1 and {hidden} w2 weights, not {} and {}",
+                routing_weights::IN_DIM * hidden,
+                w1.len(),
+                w2.len()
+            )));
+        }
+        if !w1.iter().chain(&b1).chain(&w2).all(|w| w.is_finite()) || !b2.is_finite() {
+            return Err(whole("a weight is not a finite number".to_string()));
+        }
+        Ok(Weights {
+            w1: Box::leak(w1.into_boxed_slice()),
+            b1: Box::leak(b1.into_boxed_slice()),
+            w2: Box::leak(w2.into_boxed_slice()),
+            b2,
+        })
+    }
+
+    /// Weights in the format of [`Weights::to_text`]: `hidden N`, then
+    /// `w1`, `b1`, `w2`, and `b2` each followed by its numbers on one
+    /// line, in any order. Blank lines and lines starting with `#` are
+    /// skipped.
+    pub fn parse(text: &str) -> Result<Self, WeightsError> {
+        let mut hidden = None;
+        let mut arrays: [Option<Vec<f32>>; 4] = Default::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = |message: String| WeightsError {
+                line: n + 1,
+                message,
+            };
+            let mut words = line.split_whitespace();
+            let key = words.next().unwrap_or_default();
+            if key == "hidden" {
+                let count = match (words.next(), words.next()) {
+                    (Some(word), None) => word.parse::<usize>().ok(),
+                    _ => None,
+                };
+                let count =
+                    count.ok_or_else(|| bad("hidden takes one whole number".to_string()))?;
+                if hidden.replace(count).is_some() {
+                    return Err(bad("`hidden` is given twice".to_string()));
+                }
+                continue;
+            }
+            let slot = match key {
+                "w1" => 0,
+                "b1" => 1,
+                "w2" => 2,
+                "b2" => 3,
+                _ => return Err(bad(format!("unknown key `{key}`"))),
+            };
+            let numbers = words
+                .map(|word| {
+                    word.parse::<f32>()
+                        .map_err(|_| bad(format!("`{word}` is not a number")))
+                })
+                .collect::<Result<Vec<f32>, _>>()?;
+            if slot == 3 && numbers.len() != 1 {
+                return Err(bad("b2 takes one number".to_string()));
+            }
+            if arrays[slot].replace(numbers).is_some() {
+                return Err(bad(format!("`{key}` is given twice")));
+            }
+        }
+        let missing = |key: &str| WeightsError {
+            line: 0,
+            message: format!("`{key}` is missing"),
+        };
+        let hidden = hidden.ok_or_else(|| missing("hidden"))?;
+        let [w1, b1, w2, b2] = arrays;
+        let w1 = w1.ok_or_else(|| missing("w1"))?;
+        let b1 = b1.ok_or_else(|| missing("b1"))?;
+        let w2 = w2.ok_or_else(|| missing("w2"))?;
+        let b2 = b2.ok_or_else(|| missing("b2"))?[0];
+        if b1.len() != hidden {
+            return Err(WeightsError {
+                line: 0,
+                message: format!("hidden is {hidden} but b1 has {} numbers", b1.len()),
+            });
+        }
+        Self::new(w1, b1, w2, b2)
+    }
+
+    /// The weights as [`Weights::parse`] reads them. Each number is
+    /// written in full, so they read back exactly.
+    pub fn to_text(&self) -> String {
+        let line = |key: &str, values: &[f32]| {
+            let values: Vec<String> = values.iter().map(f32::to_string).collect();
+            format!("{key} {}\n", values.join(" "))
+        };
+        format!(
+            "hidden {}\n{}{}{}b2 {}\n",
+            self.hidden(),
+            line("w1", self.w1),
+            line("b1", self.b1),
+            line("w2", self.w2),
+            self.b2
+        )
+    }
+
+    /// The number of hidden units.
+    pub fn hidden(&self) -> usize {
+        self.b1.len()
+    }
+
+}
+
+/// Why [`Weights::parse`] or [`Weights::new`] refused the weights, with
+/// the 1-based line it was found on, or 0 for the weights as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightsError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for WeightsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.message),
+            line => write!(f, "line {line}: {}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for WeightsError {}
+
+impl fmt::Debug for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Weights")
+            .field("hidden", &self.hidden())
+            .field("b2", &self.b2)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A named router profile: the weights and the [`RouteConfig`] tuned
+/// with them. `default` is the main trained network; `aggressive` and
+/// `recall` have networks of their own, fitted to keep fewer or more
+/// values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RoutingModel {
+    pub name: &'static str,
+    pub config: RouteConfig,
+}
+
+impl RoutingModel {
+    /// The built-in profiles, by name.
+    pub const NAMES: [&'static str; 3] = ["default", "aggressive", "recall"];
+
+    /// The built-in profile called `name`, ignoring case.
+    pub fn builtin(name: &str) -> Option<RoutingModel> {
+        let name = Self::NAMES
+            .into_iter()
+            .find(|known| known.eq_ignore_ascii_case(name.trim()))?;
+        let default = RouteConfig::default();
+        let config = match name {
+            "aggressive" => RouteConfig {
+                threshold: 0.7,
+                weights: Weights::AGGRESSIVE,
+                ..default
+            },
+            "recall" => RouteConfig {
+                threshold: 0.3,
+                min_keep: 2,
+                weights: Weights::RECALL,
+                ..default
+            },
+            _ => default,
+        };
+        Some(RoutingModel { name, config })
+    }
+}
+
+/// Platt scaling, `sigmoid(a * logit + b)`, with `a` and `b` fitted
+/// elsewhere on labelled routes. A positive `a` keeps the order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlattParams {
+    pub a: f32,
+    pub b: f32,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            min_keep: 0,
+            max_keep: None,
+            prefer: MaskStrategy::Likelihood,
+            prob_temperature: 1.0,
+            calibration: None,
+            weights: Weights::TRAINED,
+        }
+    }
+}
+
+/// Runs the router over `values`, one score per candidate. The result has
+/// a probability and a mask entry for each value, a permutation of their
+/// indices, and keeps at least one. Fails when `values` is empty, or for
+/// [`route_values_with`] when the temperature is not positive.
+pub fn route_values(values: &[f32]) -> Result<RouteResult, RouteError> {
+    route_values_with(values, &RouteConfig::default())
+}
+
+/// Like [`route_values`], tuned by `cfg`.
+pub fn route_values_with(values: &[f32], cfg: &RouteConfig) -> Result<RouteResult, RouteError> {
+    if values.is_empty() {
+        return Err(RouteError::Empty);
+    }
+    let temperature = cfg.prob_temperature;
+    if !(temperature > 0.0 && temperature.is_finite()) {
+        return Err(RouteError::Temperature);
+    }
+    let (values, patched) = patch_non_finite(values);
+    let probs: Vec<f32> = predict_logits(&values, &cfg.weights)
+        .into_iter()
+        .map(|logit| probability(logit, cfg))
+        .collect();
+    Ok(RouteResult {
+        patched,
+        ..pick_mask(&values, probs, cfg)
+    })
+}
+
+/// The router's names for its eight inputs, in [`Explanation`] order.
+pub const FEATURE_NAMES: [&str; 8] = [
+    "value", "position", "mean", "std", "centered", "zscore", "rank", "cdf",
+];
+
+/// How the router scored one value, from [`route_values_explained`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// The router's inputs, named by [`FEATURE_NAMES`].
+    pub features: [f32; 8],
+    /// Hidden units that fired, out of all of them.
+    pub active: usize,
+    /// The largest hidden activation.
+    pub peak: f32,
+    /// Before calibration and temperature.
+    pub logit: f32,
+    pub prob: f32,
+    /// Whether the router kept the value.
+    pub kept: bool,
+    /// How much the logit falls when each input is set to 0: positive
+    /// inputs argued for keeping the value, negative ones against.
+    pub contributions: [f32; 8],
+}
+
+/// Like [`route_values_with`], saying for each value what drove its
+/// probability. Each contribution is found by zeroing one input and
+/// scoring again, so this is slow; it is meant for debugging.
+pub fn route_values_explained(
+    values: &[f32],
+    cfg: &RouteConfig,
+) -> Result<Vec<Explanation>, RouteError> {
+    let routed = route_values_with(values, cfg)?;
+    let (values, _) = patch_non_finite(values);
+    let stats = Stats::new(&values);
+    let explained = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            let features = stats.features(i, v);
+            let (logit, hidden) = forward(&cfg.weights, &features);
+            let mut contributions = [0.0_f32; 8];
+            for (at, contribution) in contributions.iter_mut().enumerate() {
+                let mut occluded = features;
+                occluded[at] = 0.0;
+                *contribution = logit - forward(&cfg.weights, &occluded).0;
+            }
+            Explanation {
+                features,
+                active: hidden.iter().filter(|h| **h > 0.0).count(),
+                peak: hidden.iter().copied().fold(0.0_f32, f32::max),
+                logit,
+                prob: routed.probs[i],
+                kept: routed.mask[i],
+                contributions,
+            }
+        })
+        .collect();
+    Ok(explained)
+}
+
+/// The router's eight inputs for each value, named by [`FEATURE_NAMES`],
+/// with non-finite values patched as [`route_values`] does. For training a
+/// replacement for the built-in weights.
+pub fn route_features(values: &[f32]) -> Vec<[f32; 8]> {
+    let (values, _) = patch_non_finite(values);
+    let stats = Stats::new(&values);
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| stats.features(i, v))
+        .collect()
+}
+
+/// `values` with each NaN or infinity replaced by the mean of the finite
+/// ones (0 when there are none), and the indices replaced.
+fn patch_non_finite(values: &[f32]) -> (Vec<f32>, Vec<usize>) {
+    let finite: Vec<f64> = values
+        .iter()
+        .filter(|v| v.is_finite())
+        .map(|&v| v as f64)
+        .collect();
+    let mean = if finite.is_empty() {
+        0.0
+    } else {
+        (finite.iter().sum::<f64>() / finite.len() as f64) as f32
+    };
+    let mut patched = Vec::new();
+    let values = values
+        .iter()
+        .enumerate()
+        .map(|(i, &v)| {
+            if v.is_finite() {
+                v
+            } else {
+                patched.push(i);
+                mean
+            }
+        })
+        .collect();
+    (values, patched)
+}
+
+/// Values scored together by [`predict_block`], one per lane.
+const BLOCK: usize = 8;
+
+/// Lists longer than this are scored across threads with `rayon`.
+#[cfg(feature = "rayon")]
+const PARALLEL_ABOVE: usize = 4096;
+
+/// What each value's features are made from: the mean, spread, and rank of
+/// every value.
+struct Stats {
+    n_f: f32,
+    pos_den: f32,
+    mean: f32,
+    std: f32,
+    ranks: Vec<usize>,
+}
+
+impl Stats {
+    fn new(values: &[f32]) -> Self {
+        let n = values.len();
+        let n_f = n as f32;
+        let pos_den = if n > 1 { (n - 1) as f32 } else { 1.0 };
+        let mean = values.iter().sum::<f32>() / n_f;
+        let mut var = 0.0_f32;
+        for v in values {
+            let d = v - mean;
+            var += d * d;
+        }
+        let std = (var / n_f).sqrt() + EPS;
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| {
+            values[a]
+                .partial_cmp(&values[b])
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.cmp(&b))
+        });
+        let mut ranks = vec![0usize; n];
+        for (rank, idx) in order.iter().enumerate() {
+            ranks[*idx] = rank;
+        }
+        Self { n_f, pos_den, mean, std, ranks }
+    }
+
+    /// The router's inputs for `v`, the value at index `i`.
+    #[inline]
+    fn features(&self, i: usize, v: f32) -> [f32; 8] {
+        let pos_norm = i as f32 / self.pos_den;
+        let centered = v - self.mean;
+        let zscore = centered / self.std;
+        let rank_norm = self.ranks[i] as f32 / self.pos_den;
+        let cdf = (self.ranks[i] as f32 + 0.5) / self.n_f;
+        [v, pos_norm, self.mean, self.std, centered, zscore, rank_norm, cdf]
+    }
+}
+
+/// The logit of each value, [`BLOCK`] values at a time. With the
+/// `rayon` feature long lists are split across threads by block; each
+/// value is scored on its own either way, so the output is the same.
+fn predict_logits(values: &[f32], weights: &Weights) -> Vec<f32> {
+    let stats = Stats::new(values);
+    let block = |(b, chunk): (usize, &[f32])| {
+        predict_block(weights, &stats, b * BLOCK, chunk)
+            .into_iter()
+            .take(chunk.len())
+    };
+    #[cfg(feature = "rayon")]
+    if values.len() > PARALLEL_ABOVE {
+        use rayon::prelude::*;
+        return values
+            .par_chunks(BLOCK)
+            .enumerate()
+            .flat_map_iter(block)
+            .collect();
+    }
+    values.chunks(BLOCK).enumerate().flat_map(block).collect()
+}
+
+/// Logits for up to [`BLOCK`] values starting at index `start`,
+/// one lane each; unused lanes are scored as zeros. A single value's sums
+/// are one long chain of dependent adds, so running the lanes side by side
+/// is what makes this fast. Within a lane every sum runs in the order it
+/// always has, which keeps the results bit for bit the same.
+#[inline]
+fn predict_block(weights: &Weights, stats: &Stats, start: usize, values: &[f32]) -> [f32; BLOCK] {
+    let mut x = [[0.0_f32; BLOCK]; routing_weights::IN_DIM];
+    for (lane, &v) in values.iter().enumerate() {
+        for (row, feat) in x.iter_mut().zip(stats.features(start + lane, v)) {
+            row[lane] = feat;
+        }
+    }
+    let units = weights.hidden();
+    let mut logit = [weights.b2; BLOCK];
+    for (h, (&b1, &w2)) in weights.b1.iter().zip(weights.w2.iter()).enumerate() {
+        let mut hidden = [b1; BLOCK];
+        for (i, row) in x.iter().enumerate() {
+            let w = weights.w1[i * units + h];
+            for (acc, x) in hidden.iter_mut().zip(row) {
+                *acc += x * w;
+            }
+        }
+        for (acc, hidden) in logit.iter_mut().zip(hidden) {
+            *acc += hidden.max(0.0) * w2;
+        }
+    }
+    logit
+}
+
+/// One value's logit and hidden activations, summed in the same order as
+/// [`predict_block`].
+fn forward(weights: &Weights, feat: &[f32; 8]) -> (f32, Vec<f32>) {
+    let units = weights.hidden();
+    let mut hidden = weights.b1.to_vec();
+    for (h, acc) in hidden.iter_mut().enumerate() {
+        for (i, x) in feat.iter().enumerate().take(routing_weights::IN_DIM) {
+            *acc += x * weights.w1[i * units + h];
+        }
+        *acc = acc.max(0.0);
+    }
+    let mut logit = weights.b2;
+    for (x, w) in hidden.iter().zip(weights.w2.iter()) {
+        logit += x * w;
+    }
+    (logit, hidden)
+}
+
+/// `logit` through the calibration and temperature of `cfg`.
+fn probability(logit: f32, cfg: &RouteConfig) -> f32 {
+    let logit = match cfg.calibration {
+        Some(PlattParams { a, b }) => a * logit + b,
+        None => logit,
+    };
+    // Exact at the default temperature of 1.
+    sigmoid(logit / cfg.prob_temperature)
+}
+
+fn sigmoid(x: f32) -> f32 {
+    // Values near f32::MAX can overflow the features into inf - inf.
+    if x.is_nan() {
+        return 0.5;
+    }
+    let x = x.clamp(-50.0, 50.0);
+    1.0 / (1.0 + (-x).exp())
+}
+
+fn mask_log_likelihood(mask: &[bool], probs: &[f32]) -> f32 {
+    let mut score = 0.0_f32;
+    for (m, p) in mask.iter().zip(probs.iter()) {
+        let p = p.clamp(1e-9, 1.0 - 1e-9);
+        if *m {
+            score += p.ln();
+        } else {
+            score += (1.0 - p).ln();
+        }
+    }
+    score
+}
+
+fn pick_mask(values: &[f32], probs: Vec<f32>, cfg: &RouteConfig) -> RouteResult {
+    let n = values.len();
+    let mask_thr: Vec<bool> = probs
+        .iter()
+        .map(|p| cfg.threshold < 1.0 && *p >= cfg.threshold)
+        .collect();
+    let mut mask_topk = vec![false; n];
+
+    // Most probable first. Probabilities often saturate at 0 or 1, so ties
+    // go to the higher value, then the earlier one.
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| {
+        probs[b]
+            .partial_cmp(&probs[a])
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| values[b].partial_cmp(&values[a]).unwrap_or(Ordering::Equal))
+            .then_with(|| a.cmp(&b))
+    });
+    let mut k_hat = probs.iter().sum::<f32>().round() as i32;
+    k_hat = k_hat.clamp(0, n as i32);
+    for idx in order.iter().take(k_hat as usize) {
+        mask_topk[*idx] = true;
+    }
+
+    let (picked, strategy) = match cfg.prefer {
+        MaskStrategy::Likelihood => {
+            let score_thr = mask_log_likelihood(&mask_thr, &probs);
+            let score_topk = mask_log_likelihood(&mask_topk, &probs);
+            if score_topk > score_thr {
+                (mask_topk, MaskStrategy::TopK)
+            } else {
+                (mask_thr, MaskStrategy::Threshold)
+            }
+        }
+        MaskStrategy::Threshold => (mask_thr, MaskStrategy::Threshold),
+        MaskStrategy::TopK => (mask_topk, MaskStrategy::TopK),
+    };
+    let mut best_mask = picked.clone();
+    clamp_kept(&mut best_mask, &order, cfg);
+    if !best_mask.contains(&true) {
+        if let Some(&idx) = order.first() {
+            best_mask[idx] = true;
+        }
+    }
+
+    let mut perm = stable_partition(&best_mask);
+    if perm.len() != n {
+        perm = canonical_partition(values, Some(&probs));
+        best_mask = partition_mask(n, &perm);
+    }
+    let clamped = (0..n).filter(|&i| best_mask[i] != picked[i]).collect();
+    RouteResult {
+        probs,
+        mask: best_mask,
+        perm,
+        patched: Vec::new(),
+        strategy,
+        clamped,
+    }
+}
+
+/// Applies `min_keep` and `max_keep` by flipping the router's least
+/// confident decisions, where its confidence is `p` in a kept value and
+/// `1 - p` in a dropped one: the smallest `|p - 0.5|` when the mask
+/// agrees with the 0.5 cut. That promotes dropped values walking `order`
+/// (most probable first) and demotes kept ones from its end.
+fn clamp_kept(mask: &mut [bool], order: &[usize], cfg: &RouteConfig) {
+    let mut kept = mask.iter().filter(|m| **m).count();
+    for idx in order {
+        if kept >= cfg.min_keep {
+            break;
+        }
+        if !mask[*idx] {
+            mask[*idx] = true;
+            kept += 1;
+        }
+    }
+    let Some(max_keep) = cfg.max_keep else {
+        return;
+    };
+    for idx in order.iter().rev() {
+        if kept <= max_keep {
+            break;
+        }
+        if mask[*idx] {
+            mask[*idx] = false;
+            kept -= 1;
+        }
+    }
+}
+
+fn stable_partition(mask: &[bool]) -> Vec<usize> {
+    let mut perm = Vec::with_capacity(mask.len());
+    for (idx, m) in mask.iter().enumerate() {
+        if *m {
+            perm.push(idx);
+        }
+    }
+    for (idx, m) in mask.iter().enumerate() {
+        if !*m {
+            perm.push(idx);
+        }
+    }
+    perm
+}
+
+fn canonical_partition(values: &[f32], probs: Option<&[f32]>) -> Vec<usize> {
+    let n = values.len();
+    let mut mask = vec![false; n];
+    if let Some(probs) = probs {
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|&a, &b| {
+            probs[b]
+                .partial_cmp(&probs[a])
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| a.cmp(&b))
+        });
+        let mut k_hat = probs.iter().sum::<f32>().round() as i32;
+        k_hat = k_hat.clamp(0, n as i32);
+        if k_hat > 0 {
+            for idx in order.iter().take(k_hat as usize) {
+                mask[*idx] = true;
+            }
+        }
+    } else if n > 0 {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let median = quantile(&sorted, 0.5);
+        for (i, v) in values.iter().enumerate() {
+            if *v >= median {
+                mask[i] = true;
+            }
+        }
+    }
+    if mask.iter().all(|m| !*m) {
+        if let Some((idx, _)) = values
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap_or(Ordering::Equal))
+        {
+            mask[idx] = true;
+        }
+    }
+    stable_partition(&mask)
+}
+
+fn partition_mask(n: usize, perm: &[usize]) -> Vec<bool> {
+    let mut seen = vec![false; n];
+    for idx in perm {
+        if *idx < n {
+            seen[*idx] = true;
+        }
+    }
+    seen
+}
+
+fn quantile(sorted: &[f32], q: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    if sorted.len() == 1 {
+        return sorted[0];
+    }
+    let q = q.clamp(0.0, 1.0);
+    let pos = q * (sorted.len() as f32 - 1.0);
+    let lo = pos.floor() as usize;
+    let hi = pos.ceil() as usize;
+    if lo == hi {
+        sorted[lo]
+    } else {
+        let frac = pos - lo as f32;
+        sorted[lo] * (1.0 - frac) + sorted[hi] * frac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOW: f32 = 1.928_75e-22;
+
+    fn assert_probs(got: &[f32], want: &[f32]) {
+        assert_eq!(got.len(), want.len());
+        for (got, want) in got.iter().zip(want) {
+            assert!((got - want).abs() <= 1e-6, "{got} != {want}");
+        }
+    }
+
+    #[test]
+    fn golden_routes() {
+        let routed = route_values(&[0.9, 0.1, 0.8, 0.2, 0.05]).unwrap();
+        assert_probs(&routed.probs, &[1.0, LOW, 1.0, LOW, LOW]);
+        assert_eq!(routed.mask, [true, false, true, false, false]);
+        assert_eq!(routed.perm, [0, 2, 1, 3, 4]);
+        assert_eq!(routed.strategy, MaskStrategy::Threshold);
+        assert!(routed.clamped.is_empty());
+
+        let routed = route_values(&[0.0, 0.0, 0.0, 0.0]).unwrap();
+        assert_probs(&routed.probs, &[LOW, LOW, 1.0, 1.0]);
+        assert_eq!(routed.mask, [false, false, true, true]);
+        assert_eq!(routed.perm, [2, 3, 0, 1]);
+    }
+
+    #[test]
+    fn golden_route_keeps_one_of_one() {
+        let routed = route_values(&[0.5]).unwrap();
+        assert_probs(&routed.probs, &[LOW]);
+        assert_eq!(routed.mask, [true]);
+        assert_eq!(routed.perm, [0]);
+        assert_eq!(routed.clamped, [0]);
+    }
+
+    #[test]
+    fn golden_route_patches_non_finite_values() {
+        let values = [1.0, f32::NAN, 0.3, 0.7, 0.2, 0.9, 0.4, 0.6];
+        let routed = route_values(&values).unwrap();
+        assert_probs(
+            &routed.probs,
+            &[1.0, LOW, LOW, 1.0, LOW, 1.0, LOW, 3.672_967e-9],
+        );
+        assert_eq!(
+            routed.mask,
+            [true, false, false, true, false, true, false, false]
+        );
+        assert_eq!(routed.perm, [0, 3, 5, 1, 2, 4, 6, 7]);
+        assert_eq!(routed.patched, [1]);
+    }
+
+    #[test]
+    fn golden_routes_with_keep_bounds() {
+        let values = [0.9, 0.1, 0.8, 0.2, 0.05];
+        let cfg = RouteConfig {
+            min_keep: 3,
+            ..RouteConfig::default()
+        };
+        let routed = route_values_with(&values, &cfg).unwrap();
+        assert_eq!(routed.mask, [true, false, true, true, false]);
+        assert_eq!(routed.perm, [0, 2, 3, 1, 4]);
+        assert_eq!(routed.clamped, [3]);
+
+        let cfg = RouteConfig {
+            max_keep: Some(1),
+            ..RouteConfig::default()
+        };
+        let routed = route_values_with(&values, &cfg).unwrap();
+        assert_eq!(routed.mask, [true, false, false, false, false]);
+        assert_eq!(routed.perm, [0, 1, 2, 3, 4]);
+        assert_eq!(routed.clamped, [2]);
+    }
+
+    #[test]
+    fn nothing_to_route_is_an_error() {
+        assert_eq!(route_values(&[]).unwrap_err(), RouteError::Empty);
+        let cfg = RouteConfig {
+            prob_temperature: 0.0,
+            ..RouteConfig::default()
+        };
+        assert_eq!(
+            route_values_with(&[0.5], &cfg).unwrap_err(),
+            RouteError::Temperature
+        );
+    }
+
+    /// xorshift64*, so the cases are the same on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn below(&mut self, n: u64) -> usize {
+            (self.next() % n) as usize
+        }
+
+        fn unit(&mut self) -> f32 {
+            (self.next() >> 40) as f32 / (1u64 << 24) as f32
+        }
+    }
+
+    fn random_values(rng: &mut Rng, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|_| match rng.below(40) {
+                0 => f32::NAN,
+                1 => f32::INFINITY,
+                2 => f32::NEG_INFINITY,
+                3 => 0.5,
+                _ => rng.unit() * 3.0 - 1.0,
+            })
+            .collect()
+    }
+
+    fn random_config(rng: &mut Rng) -> RouteConfig {
+        RouteConfig {
+            threshold: rng.unit() * 1.1,
+            min_keep: rng.below(6),
+            max_keep: (rng.below(2) == 0).then(|| rng.below(6)),
+            prefer: [
+                MaskStrategy::Likelihood,
+                MaskStrategy::Threshold,
+                MaskStrategy::TopK,
+            ][rng.below(3)],
+            prob_temperature: 0.25 + rng.unit() * 4.0,
+            ..RouteConfig::default()
+        }
+    }
+
+    fn assert_partition(values: &[f32], routed: &RouteResult) {
+        let n = values.len();
+        assert_eq!(routed.probs.len(), n);
+        assert_eq!(routed.mask.len(), n);
+        let mut seen = vec![false; n];
+        for &idx in &routed.perm {
+            assert!(idx < n && !seen[idx], "{values:?}: {:?}", routed.perm);
+            seen[idx] = true;
+        }
+        assert_eq!(routed.perm.len(), n);
+
+        let kept = routed.mask.iter().filter(|m| **m).count();
+        assert!(kept >= 1);
+        let (front, back) = routed.perm.split_at(kept);
+        assert!(front.iter().all(|&idx| routed.mask[idx]));
+        assert!(back.iter().all(|&idx| !routed.mask[idx]));
+        assert!(front.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(back.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn the_perm_is_always_a_partitioning_permutation() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..2000 {
+            let len = 1 + rng.below(40);
+            let values = random_values(&mut rng, len);
+            let cfg = random_config(&mut rng);
+            let routed = route_values_with(&values, &cfg).unwrap();
+            assert_partition(&values, &routed);
+        }
+    }
+
+    #[test]
+    fn the_perm_is_a_permutation_across_blocks() {
+        let mut rng = Rng(42);
+        for len in [BLOCK - 1, BLOCK, BLOCK + 1, 3 * BLOCK + 7, 4097, 5000] {
+            let values = random_values(&mut rng, len);
+            let cfg = random_config(&mut rng);
+            let routed = route_values_with(&values, &cfg).unwrap();
+            assert_partition(&values, &routed);
+        }
+    }
+
+    #[test]
+    fn non_finite_values_route_as_the_mean() {
+        let routed = route_values(&[f32::NAN, 0.9, f32::INFINITY, 0.1, f32::NEG_INFINITY]).unwrap();
+        let mean = route_values(&[0.5, 0.9, 0.5, 0.1, 0.5]).unwrap();
+        assert_eq!(routed.patched, [0, 2, 4]);
+        assert_eq!(routed.probs, mean.probs);
+        assert_eq!(routed.mask, mean.mask);
+        assert_eq!(routed.perm, mean.perm);
+    }
+
+    #[test]
+    fn nothing_finite_still_keeps_one() {
+        let routed = route_values(&[f32::NAN, f32::NAN, f32::INFINITY]).unwrap();
+        assert_eq!(routed.patched, [0, 1, 2]);
+        assert!(routed.probs.iter().all(|p| p.is_finite()));
+        assert!(routed.mask.contains(&true));
+    }
+
+    #[test]
+    fn random_inputs_keep_the_invariants() {
+        let mut rng = Rng(0x5eed);
+        assert_eq!(route_values(&[]).unwrap_err(), RouteError::Empty);
+        for _ in 0..2000 {
+            let len = 1 + rng.below(24);
+            let values = random_values(&mut rng, len);
+            let routed = route_values(&values).unwrap();
+            let non_finite: Vec<usize> = (0..len).filter(|&i| !values[i].is_finite()).collect();
+            assert_eq!(routed.patched, non_finite);
+            assert!(routed.probs.iter().all(|p| (0.0..=1.0).contains(p)));
+            assert_partition(&values, &routed);
+        }
+    }
+
+    /// The router as it was before it scored values in blocks: a feature
+    /// vector per value, then one scalar pass each.
+    mod reference {
+        use super::super::{routing_weights, sigmoid, EPS};
+        use std::cmp::Ordering;
+
+        pub fn build_features(values: &[f32]) -> Vec<[f32; 8]> {
+            let n = values.len();
+            let n_f = n as f32;
+            let pos_den = if n > 1 { (n - 1) as f32 } else { 1.0 };
+            let mean = values.iter().sum::<f32>() / n_f;
+            let mut var = 0.0_f32;
+            for v in values {
+                let d = v - mean;
+                var += d * d;
+            }
+            let std = (var / n_f).sqrt() + EPS;
+
+            let mut order: Vec<usize> = (0..n).collect();
+            order.sort_by(|&a, &b| {
+                values[a]
+                    .partial_cmp(&values[b])
+                    .unwrap_or(Ordering::Equal)
+                    .then_with(|| a.cmp(&b))
+            });
+            let mut ranks = vec![0usize; n];
+            for (rank, idx) in order.iter().enumerate() {
+                ranks[*idx] = rank;
+            }
+
+            let mut feats = Vec::with_capacity(n);
+            for (i, &v) in values.iter().enumerate() {
+                let pos_norm = i as f32 / pos_den;
+                let centered = v - mean;
+                let zscore = centered / std;
+                let rank_norm = ranks[i] as f32 / pos_den;
+                let cdf = (ranks[i] as f32 + 0.5) / n_f;
+                feats.push([v, pos_norm, mean, std, centered, zscore, rank_norm, cdf]);
+            }
+            feats
+        }
+
+        pub fn predict_proba(feats: &[[f32; 8]]) -> Vec<f32> {
+            let mut probs = Vec::with_capacity(feats.len());
+            for feat in feats {
+                let mut hidden = [0.0_f32; routing_weights::HIDDEN];
+                for (h, out) in hidden.iter_mut().enumerate() {
+                    let mut acc = routing_weights::B1[h];
+                    for (i, x) in feat.iter().enumerate().take(routing_weights::IN_DIM) {
+                        let w = routing_weights::W1[i * routing_weights::HIDDEN + h];
+                        acc += x * w;
+                    }
+                    *out = acc.max(0.0);
+                }
+                let mut logit = routing_weights::B2;
+                for (x, w) in hidden.iter().zip(routing_weights::W2.iter()) {
+                    logit += x * w;
+                }
+                probs.push(sigmoid(logit));
+            }
+            probs
+        }
+    }
+
+    #[test]
+    fn blocks_match_the_scalar_reference_bit_for_bit() {
+        let mut rng = Rng(0x0849);
+        let wide: Vec<f32> = (0..10_000).map(|_| rng.unit() * 200.0 - 100.0).collect();
+        let unit: Vec<f32> = (0..10_000).map(|_| rng.unit()).collect();
+        let tied: Vec<f32> = (0..10_000).map(|i| (i % 7) as f32 / 7.0).collect();
+        for values in [wide, unit, tied, vec![0.25; 9]] {
+            let want = reference::predict_proba(&reference::build_features(&values));
+            let routed = route_values(&values).unwrap();
+            assert_eq!(routed.probs.len(), want.len());
+            for (i, (got, want)) in routed.probs.iter().zip(&want).enumerate() {
+                assert_eq!(got.to_bits(), want.to_bits(), "value {i}");
+            }
+            let features = route_features(&values);
+            assert_eq!(features, reference::build_features(&values));
+        }
+    }
+
+    /// Probabilities for `values` under `cfg`.
+    fn probs_with(values: &[f32], cfg: &RouteConfig) -> Vec<f32> {
+        route_values_with(values, cfg).unwrap().probs
+    }
+
+    #[test]
+    fn calibration_and_temperature_keep_the_order() {
+        let mut rng = Rng(0x0850);
+        let values = random_values(&mut rng, 300);
+        let logits = predict_logits(&patch_non_finite(&values).0, &Weights::TRAINED);
+        let mut by_logit: Vec<usize> = (0..values.len()).collect();
+        by_logit.sort_by(|&a, &b| logits[a].total_cmp(&logits[b]));
+        let configs = [
+            (0.05, None),
+            (0.5, None),
+            (4.0, None),
+            (1.0, Some(PlattParams { a: 0.3, b: -2.0 })),
+            (2.0, Some(PlattParams { a: 5.0, b: 1.0 })),
+        ];
+        for (prob_temperature, calibration) in configs {
+            let cfg = RouteConfig {
+                prob_temperature,
+                calibration,
+                ..RouteConfig::default()
+            };
+            let probs = probs_with(&values, &cfg);
+            for pair in by_logit.windows(2) {
+                assert!(
+                    probs[pair[0]] <= probs[pair[1]],
+                    "{cfg:?}: logit {} -> {}, logit {} -> {}",
+                    logits[pair[0]],
+                    probs[pair[0]],
+                    logits[pair[1]],
+                    probs[pair[1]]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn a_temperature_near_zero_makes_hard_decisions() {
+        let logits = [-3.0, -0.5, -0.01, 0.01, 0.5, 3.0];
+        let temperatures = [1.0, 0.1, 1e-3, 1e-6];
+        for logit in logits {
+            let hard = if logit > 0.0 { 1.0 } else { 0.0 };
+            let misses: Vec<f32> = temperatures
+                .iter()
+                .map(|&prob_temperature| {
+                    let cfg = RouteConfig {
+                        prob_temperature,
+                        ..RouteConfig::default()
+                    };
+                    (probability(logit, &cfg) - hard).abs()
+                })
+                .collect();
+            assert!(
+                misses.windows(2).all(|w| w[1] <= w[0]),
+                "{logit}: {misses:?}"
+            );
+            assert!(misses[3] < 1e-6, "{logit}: {misses:?}");
+        }
+        // Far above 1 everything heads for a coin flip.
+        let cfg = RouteConfig {
+            prob_temperature: 1e6,
+            ..RouteConfig::default()
+        };
+        for logit in logits {
+            assert!((probability(logit, &cfg) - 0.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn the_default_calibration_is_bit_identical_to_none() {
+        let mut rng = Rng(0x1850);
+        let identity = RouteConfig {
+            calibration: Some(PlattParams { a: 1.0, b: 0.0 }),
+            ..RouteConfig::default()
+        };
+        for _ in 0..20 {
+            let values = random_values(&mut rng, 50);
+            let want =
+                reference::predict_proba(&reference::build_features(&patch_non_finite(&values).0));
+            for cfg in [RouteConfig::default(), identity] {
+                let got = probs_with(&values, &cfg);
+                let bits = |probs: &[f32]| probs.iter().map(|p| p.to_bits()).collect::<Vec<_>>();
+                assert_eq!(bits(&got), bits(&want), "{cfg:?}");
+            }
+        }
+    }
+
+    /// The trained weights with every hidden unit's weight on input `at`
+    /// set to 0.
+    fn without_input(at: usize) -> Weights {
+        let mut w1 = routing_weights::W1;
+        for h in 0..routing_weights::HIDDEN {
+            w1[at * routing_weights::HIDDEN + h] = 0.0;
+        }
+        Weights {
+            w1: Box::leak(Box::new(w1)),
+            ..Weights::TRAINED
+        }
+    }
+
+    #[test]
+    fn an_input_with_no_weight_contributes_nothing() {
+        let mut rng = Rng(0x0851);
+        for (at, name) in FEATURE_NAMES.iter().enumerate() {
+            let cfg = RouteConfig {
+                weights: without_input(at),
+                ..RouteConfig::default()
+            };
+            for _ in 0..5 {
+                let values = random_values(&mut rng, 40);
+                for explained in route_values_explained(&values, &cfg).unwrap() {
+                    let contribution = explained.contributions[at];
+                    assert!(
+                        contribution.abs() <= 1e-6,
+                        "{name} contributed {contribution}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn explanations_match_the_route() {
+        let values = [0.9, 0.1, 0.8, 0.2, 0.05];
+        let cfg = RouteConfig::default();
+        let routed = route_values_with(&values, &cfg).unwrap();
+        let explained = route_values_explained(&values, &cfg).unwrap();
+        assert_eq!(explained.len(), values.len());
+        for (i, explanation) in explained.iter().enumerate() {
+            assert_eq!(explanation.prob, routed.probs[i]);
+            assert_eq!(explanation.kept, routed.mask[i]);
+            assert_eq!(explanation.features, route_features(&values)[i]);
+            assert_eq!(explanation.prob, probability(explanation.logit, &cfg));
+            assert!(explanation.active <= routing_weights::HIDDEN);
+            // The trained router leans on the inputs it was given.
+            assert!(explanation.contributions.iter().any(|c| c.abs() > 1e-3));
+        }
+    }
+
+    /// `pick_mask` on probabilities chosen by hand, thresholded at 0.5.
+    fn bounded(probs: &[f32], min_keep: usize, max_keep: Option<usize>) -> RouteResult {
+        thresholded(probs, 0.5, min_keep, max_keep)
+    }
+
+    /// `pick_mask` with the threshold mask only. Each value is its index,
+    /// so ties in probability go to the later one.
+    fn thresholded(
+        probs: &[f32],
+        threshold: f32,
+        min_keep: usize,
+        max_keep: Option<usize>,
+    ) -> RouteResult {
+        let cfg = RouteConfig {
+            threshold,
+            min_keep,
+            max_keep,
+            prefer: MaskStrategy::Threshold,
+            ..RouteConfig::default()
+        };
+        let values: Vec<f32> = (0..probs.len()).map(|i| i as f32).collect();
+        pick_mask(&values, probs.to_vec(), &cfg)
+    }
+
+    #[test]
+    fn max_keep_drops_the_least_confident_of_an_all_true_mask() {
+        let routed = bounded(&[0.55, 0.95, 0.6, 0.99], 0, Some(2));
+        assert_eq!(routed.mask, [false, true, false, true]);
+        assert_eq!(routed.clamped, [0, 2]);
+        assert_eq!(routed.perm, [1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn min_keep_promotes_the_least_confident_of_an_all_false_mask() {
+        let routed = bounded(&[0.1, 0.45, 0.05, 0.3], 2, None);
+        assert_eq!(routed.mask, [false, true, false, true]);
+        assert_eq!(routed.clamped, [1, 3]);
+    }
+
+    #[test]
+    fn an_all_false_mask_keeps_the_most_probable() {
+        let routed = bounded(&[0.1, 0.45, 0.05, 0.3], 0, None);
+        assert_eq!(routed.mask, [false, true, false, false]);
+        assert_eq!(routed.clamped, [1]);
+    }
+
+    #[test]
+    fn a_mask_at_its_bounds_is_left_alone() {
+        let routed = bounded(&[0.9, 0.8, 0.1], 2, Some(2));
+        assert_eq!(routed.mask, [true, true, false]);
+        assert!(routed.clamped.is_empty());
+        let routed = bounded(&[0.9, 0.8, 0.7], 0, Some(3));
+        assert_eq!(routed.mask, [true, true, true]);
+        assert!(routed.clamped.is_empty());
+    }
+
+    #[test]
+    fn max_keep_wins_over_min_keep_but_one_is_kept() {
+        let routed = bounded(&[0.2, 0.9, 0.6], 3, Some(1));
+        assert_eq!(routed.mask, [false, true, false]);
+        let routed = bounded(&[0.2, 0.9, 0.6], 0, Some(0));
+        assert_eq!(routed.mask, [false, true, false]);
+    }
+
+    #[test]
+    fn threshold_zero_keeps_everything() {
+        let routed = thresholded(&[0.0, 1e-9, 0.5, 1.0], 0.0, 0, None);
+        assert_eq!(routed.mask, [true; 4]);
+        assert!(routed.clamped.is_empty());
+
+        let cfg = RouteConfig {
+            threshold: 0.0,
+            prefer: MaskStrategy::Threshold,
+            ..RouteConfig::default()
+        };
+        let routed = route_values_with(&[0.9, 0.1, 0.8, 0.2, 0.05], &cfg).unwrap();
+        assert_eq!(routed.mask, [true; 5]);
+        assert_eq!(routed.perm, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn threshold_one_keeps_only_min_keep() {
+        // Saturated probabilities are exactly 1 and still left out.
+        let probs = [1.0, 0.99, 1.0, 0.2];
+        let routed = thresholded(&probs, 1.0, 2, None);
+        assert_eq!(routed.mask, [true, false, true, false]);
+        assert_eq!(routed.clamped, [0, 2]);
+        let routed = thresholded(&probs, 1.0, 3, None);
+        assert_eq!(routed.mask, [true, true, true, false]);
+        // With no minimum the one-value floor still holds.
+        let routed = thresholded(&probs, 1.0, 0, None);
+        assert_eq!(routed.mask, [false, false, true, false]);
+
+        let cfg = RouteConfig {
+            threshold: 1.0,
+            min_keep: 2,
+            prefer: MaskStrategy::Threshold,
+            ..RouteConfig::default()
+        };
+        let routed = route_values_with(&[0.9, 0.1, 0.8, 0.2, 0.05], &cfg).unwrap();
+        assert_eq!(routed.mask, [true, false, true, false, false]);
+        assert_eq!(routed.clamped, [0, 2]);
+    }
+
+    #[test]
+    fn threshold_extremes_still_meet_the_keep_bounds() {
+        let probs = [0.3, 0.9, 0.1, 0.6];
+        let routed = thresholded(&probs, 0.0, 0, Some(2));
+        assert_eq!(routed.mask, [false, true, false, true]);
+        assert_eq!(routed.clamped, [0, 2]);
+        let routed = thresholded(&probs, 1.0, 4, Some(2));
+        assert_eq!(routed.mask, [false, true, false, true]);
+        let routed = thresholded(&probs, 0.0, 0, Some(0));
+        assert_eq!(routed.mask, [false, true, false, false]);
+    }
+
+    #[test]
+    fn weights_read_back_from_text_route_the_same() {
+        let read = Weights::parse(&Weights::TRAINED.to_text()).unwrap();
+        assert_eq!(read, Weights::TRAINED);
+        assert_eq!(read.hidden(), routing_weights::HIDDEN);
+        let mut rng = Rng(0x0852);
+        for _ in 0..20 {
+            let values = random_values(&mut rng, 30);
+            let cfg = RouteConfig {
+                weights: read,
+                ..RouteConfig::default()
+            };
+            assert_eq!(
+                route_values_with(&values, &cfg).unwrap(),
+                route_values(&values).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn weights_of_any_width_route() {
+        // One hidden unit that passes the value through: the larger
+        // values are kept.
+        let mut w1 = [0.0_f32; 8];
+        w1[0] = 1.0;
+        let text = format!(
+            "# a comment\n\nhidden 1\nb2 -5\nw1 {}\nb1 0\nw2 10\n",
+            w1.iter().map(f32::to_string).collect::<Vec<_>>().join(" ")
+        );
+        let weights = Weights::parse(&text).unwrap();
+        assert_eq!(weights.hidden(), 1);
+        let cfg = RouteConfig {
+            weights,
+            ..RouteConfig::default()
+        };
+        let routed = route_values_with(&[0.0, 0.9, 0.1, 0.7], &cfg).unwrap();
+        assert_eq!(routed.mask, [false, true, false, true]);
+        let explained = route_values_explained(&[0.0, 0.9], &cfg).unwrap();
+        assert_eq!(explained[1].logit, 4.0);
+        assert_eq!(explained[1].active, 1);
+    }
+
+    #[test]
+    fn bad_weights_are_refused_with_their_line() {
+        let good = "hidden 1\nw1 1 0 0 0 0 0 0 0\nb1 0\nw2 1\nb2 0\n";
+        assert!(Weights::parse(good).is_ok());
+        for (text, why) in [
+            ("hidden 1\nw3 1", "line 2: unknown key `w3`"),
+            ("hidden 1\n\nb1 x", "line 3: `x` is not a number"),
+            ("hidden two", "line 1: hidden takes one whole number"),
+            ("b2 1 2", "line 1: b2 takes one number"),
+            ("b1 0\nb1 0", "line 2: `b1` is given twice"),
+            ("hidden 1\nw1 1\nb1 0\nw2 1", "`b2` is missing"),
+            (
+                "hidden 2\nw1 1 0 0 0 0 0 0 0\nb1 0\nw2 1\nb2 0",
+                "hidden is 2 but b1 has 1 numbers",
+            ),
+            (
+                "hidden 1\nw1 1 0\nb1 0\nw2 1\nb2 0",
+                "1 hidden units need 8 w1 and 1 w2 weights, not 2 and 1",
+            ),
+            (
+                "hidden 1\nw1 1 0 0 0 0 0 0 0\nb1 0\nw2 inf\nb2 0",
+                "a weight is not a finite number",
+            ),
+        ] {
+            let err = Weights::parse(text).unwrap_err();
+            assert_eq!(err.to_string(), why, "{text:?}");
+        }
+    }
+
+    fn profile(name: &str) -> RouteConfig {
+        RoutingModel::builtin(name).unwrap().config
+    }
+
+    #[test]
+    fn the_profiles_keep_different_values() {
+        let values = [0.9, 0.5, 0.45, 0.4, 0.1, 0.05];
+        let mask = |name: &str| route_values_with(&values, &profile(name)).unwrap().mask;
+        assert_eq!(
+            mask("aggressive"),
+            [true, false, false, false, false, false]
+        );
+        assert_eq!(mask("default"), [true, true, false, false, false, false]);
+        assert_eq!(mask("recall"), [true, true, true, true, false, false]);
+
+        // Over many lists, each keeps a share of its own.
+        let mut rng = Rng(0x0855);
+        let mut kept = [0; 3];
+        for _ in 0..200 {
+            let values: Vec<f32> = (0..12).map(|_| rng.unit()).collect();
+            for (count, name) in kept.iter_mut().zip(["aggressive", "default", "recall"]) {
+                let routed = route_values_with(&values, &profile(name)).unwrap();
+                *count += routed.mask.iter().filter(|m| **m).count();
+            }
+        }
+        assert!(kept[0] < kept[1] && kept[1] < kept[2], "{kept:?}");
+    }
+
+    #[test]
+    fn profiles_are_found_by_name_ignoring_case() {
+        assert_eq!(profile("default"), RouteConfig::default());
+        assert_eq!(profile(" Recall ").weights, Weights::RECALL);
+        assert_eq!(profile("AGGRESSIVE").weights, Weights::AGGRESSIVE);
+        assert_ne!(Weights::AGGRESSIVE, Weights::RECALL);
+        assert!(RoutingModel::builtin("fast").is_none());
+    }
+}
@@ -0,0 +1,1412 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::cache::RouteCache;
+use crate::neuroute::{self, Explanation, MaskStrategy, RouteConfig};
+use crate::report::{ChunkReport, Reason, RouteReport, Timings};
+use crate::score::{trigram_scores, Scorer, TokenOptions};
+use crate::split::{self, Autochunk, Split};
+
+/// How [`route_prompt`] splits and trims a prompt.
+#[derive(Debug, Clone, Copy)]
+pub struct RouteOptions<'a> {
+    /// Separates the chunks: a literal with whitespace around it ignored
+    /// for [`Split::Delim`], a pattern for [`Split::Regex`].
+    pub delim: &'a str,
+    /// How the prompt is cut into chunks.
+    pub split: Split,
+    /// Context chunks shorter than this many characters are merged into a
+    /// neighbour (`0`: never).
+    pub min_chars: usize,
+    /// When the split finds fewer than two chunks, cut long context into
+    /// overlapping windows instead of giving up. The query is then
+    /// [`query`](Self::query), or else the prompt's first paragraph.
+    pub autochunk: Option<Autochunk>,
+    /// Scores the chunks against this. Without it the prompt's first chunk
+    /// is the query and only the rest are candidates.
+    pub query: Option<&'a str>,
+    /// Keeps at most this many chunks (`0`: no cap), as the router's
+    /// `max_keep` where that is not lower. Also how many of the
+    /// best-scoring chunks are kept when no chunk scores above 0.
+    pub keep: usize,
+    /// How chunks are scored against the query.
+    pub scorer: Scorer,
+    /// Stopwords and stemming for the scorer's words.
+    pub tokens: TokenOptions,
+    /// Tuning for the router itself.
+    pub route: RouteConfig,
+    /// Caps the kept chunks, each with the delimiter before it, at this
+    /// many characters; the query comes on top. The routed chunks are added
+    /// best first, skipping any that would overflow it. The best one is
+    /// kept even when nothing fits.
+    pub budget: Option<usize>,
+    /// 0 to 1: how much a chunk's likeness to ones already picked counts
+    /// against it, maximal marginal relevance style, so near-duplicates do
+    /// not crowd out a different chunk. Above 0 the chunks the router kept
+    /// are reordered this way before [`keep`](Self::keep) and the budget
+    /// cut them down. 0 keeps the router's own order.
+    pub diversity: f32,
+    /// Chunks kept whatever their score, in the slots the best-scoring
+    /// ones would otherwise take.
+    pub anchors: Anchors,
+    /// Drops a chunk whose word set is at least this similar (Jaccard, 0
+    /// to 1) to another's before scoring, keeping the longer of the two, or
+    /// the earlier when they are as long. `None` keeps every chunk. Windows
+    /// cut by [`autochunk`](Self::autochunk) are never deduplicated.
+    pub dedup: Option<f32>,
+    /// Fill in [`Routed::explanations`]; slow, for debugging.
+    pub explain: bool,
+    /// Fill in [`Routed::report`].
+    pub report: bool,
+    /// The order the kept chunks go back in.
+    pub order: Order,
+    /// When the scorer gives every chunk less than this, score them by
+    /// character trigrams instead, which match misspelt words. `None`
+    /// never does. Scores from a [`ScoreFn`] or brought by the chunks are
+    /// left alone.
+    pub fuzzy: Option<f32>,
+}
+
+/// How [`route_prompt`] orders the chunks it keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    /// As they were in the prompt.
+    #[default]
+    Document,
+    /// Best score first, ties in prompt order. Windows cut by
+    /// [`RouteOptions::autochunk`] stay in prompt order regardless, since
+    /// they are pieces of one text.
+    Score,
+}
+
+impl Order {
+    /// `document` or `score`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "document" => Some(Order::Document),
+            "score" => Some(Order::Score),
+            _ => None,
+        }
+    }
+
+    /// Sorts `idx`, indices into `scores`, into this order.
+    pub fn sort(self, idx: &mut [usize], scores: &[f32]) {
+        match self {
+            Order::Document => idx.sort_unstable(),
+            Order::Score => sort_by_score(idx, scores),
+        }
+    }
+}
+
+/// Context chunks routing always keeps, such as a transcript's setup and
+/// its latest turn, which rarely share words with the query.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Anchors {
+    pub first: bool,
+    pub last: bool,
+}
+
+impl Anchors {
+    /// A comma-separated list of `first` and `last`; `none` or nothing for
+    /// neither.
+    pub fn from_names(names: &str) -> Option<Self> {
+        let mut anchors = Anchors::default();
+        for name in names
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+        {
+            match name.as_str() {
+                "first" => anchors.first = true,
+                "last" => anchors.last = true,
+                "none" | "" => {}
+                _ => return None,
+            }
+        }
+        Some(anchors)
+    }
+
+    /// Indices of the anchored chunks among `len`, first before last.
+    fn indices(self, len: usize) -> Vec<usize> {
+        let mut idxs = Vec::new();
+        if self.first && len > 0 {
+            idxs.push(0);
+        }
+        if self.last && len > 0 && !idxs.contains(&(len - 1)) {
+            idxs.push(len - 1);
+        }
+        idxs
+    }
+}
+
+/// A prompt rewritten by routing, with how many context chunks survived.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Routed {
+    pub prompt: String,
+    pub candidates: usize,
+    pub selected: usize,
+    /// Characters in `prompt`.
+    pub chars: usize,
+    /// Routed chunks left out because they did not fit the budget.
+    pub skipped: usize,
+    /// The anchored chunks alone were over the budget; they are all kept
+    /// regardless, and nothing else is.
+    pub anchors_over_budget: bool,
+    /// With diversity on, the chunks in the order picked.
+    pub picks: Vec<Pick>,
+    /// Windows the context was cut into when it had no chunks of its own;
+    /// 0 when it did.
+    pub windows: usize,
+    /// Anchored chunks in `prompt`, by index among the candidates.
+    pub anchored: Vec<usize>,
+    /// Chunks dropped as near-duplicates before scoring.
+    pub duplicates: Vec<Duplicate>,
+    /// With [`RouteOptions::explain`], how the router scored each
+    /// candidate.
+    pub explanations: Vec<Explanation>,
+    /// The chunks were scored by trigrams; see [`RouteOptions::fuzzy`].
+    pub fuzzy: bool,
+    /// With [`RouteOptions::report`], why each candidate was kept or
+    /// dropped.
+    pub report: Option<RouteReport>,
+}
+
+/// A chunk dropped by the dedup pass. Indices count the chunks as split,
+/// before any were dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Duplicate {
+    pub dropped: usize,
+    pub kept: usize,
+    pub similarity: f32,
+}
+
+/// One step of the diversity pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pick {
+    /// Index among the candidate chunks.
+    pub chunk: usize,
+    pub score: f32,
+    /// `diversity` times the chunk's largest word-set Jaccard similarity
+    /// to the chunks picked before it.
+    pub penalty: f32,
+}
+
+/// Rewrites `prompt` as the query followed by the routed context chunks,
+/// each joined by the delimiter on its own line (a blank line when the
+/// split has no literal delimiter). `None` when there is nothing to route:
+/// no usable delimiter, or no candidate chunks.
+pub fn route_prompt(prompt: &str, opts: &RouteOptions) -> Option<Routed> {
+    route_prompt_cached(prompt, opts, &mut RouteCache::default())
+}
+
+/// Like [`route_prompt`], keeping the chunks' words in `cache` for the
+/// next call.
+pub fn route_prompt_cached(
+    prompt: &str,
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+) -> Option<Routed> {
+    route_prompt_scored(prompt, opts, cache, &mut |_, _| None)
+}
+
+/// Scores a query's chunks in place of [`RouteOptions::scorer`], as by
+/// embeddings; `None` leaves them to the scorer after all. Scores should run
+/// from 0 to 1, best around 1, as the router was trained on.
+pub type ScoreFn<'a> = dyn FnMut(&str, &[String]) -> Option<Vec<f32>> + 'a;
+
+/// Like [`route_prompt_cached`], scoring the chunks with `score`.
+pub fn route_prompt_scored(
+    prompt: &str,
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+    score: &mut ScoreFn,
+) -> Option<Routed> {
+    let start = Instant::now();
+    let (query, candidates, windowed) = split_prompt(prompt, opts)?;
+    let duplicates = match (&windowed, opts.dedup) {
+        (None, Some(threshold)) => near_duplicates(&candidates, threshold, &opts.tokens, cache),
+        _ => Vec::new(),
+    };
+    let candidates = without(candidates, &duplicates);
+    let tokenized = tokenize(&candidates, opts, cache);
+    let (scores, scorer) = score_chunks(&query, &candidates, opts, cache, score);
+    let scored = Instant::now();
+    let mut routed = route_scored(query, candidates, scores, windowed, opts, cache)?;
+    routed.duplicates = duplicates;
+    routed.fuzzy = scorer == "trigram";
+    finish_report(&mut routed, scorer, start, tokenized, scored);
+    Some(routed)
+}
+
+/// The query [`route_prompt`] would route `prompt`'s chunks against, as
+/// for a budget that leaves room for it; `None` when there is nothing to
+/// route.
+pub fn prompt_query(prompt: &str, opts: &RouteOptions) -> Option<String> {
+    split_prompt(prompt, opts).map(|(query, _, _)| query)
+}
+
+/// Splits `prompt` into its query and candidate chunks, cutting the
+/// context into windows when it has no chunks of its own.
+fn split_prompt(
+    prompt: &str,
+    opts: &RouteOptions,
+) -> Option<(String, Vec<String>, Option<Windowed>)> {
+    let mut chunks = split::split_chunks(prompt, opts.split, opts.delim)?;
+    if chunks.len() < 2 {
+        let (query, windows) = autochunk(chunks.pop()?, opts)?;
+        let candidates = windows
+            .spans
+            .iter()
+            .map(|&(start, end)| windows.text[start..end].trim().to_string())
+            .collect();
+        return Some((query, candidates, Some(windows)));
+    }
+    let query = match opts.query {
+        Some(query) => query.trim().to_string(),
+        None => chunks.remove(0),
+    };
+    Some((query, split::merge_short(chunks, opts.min_chars), None))
+}
+
+/// A context chunk that arrived already split, as from a retrieval step,
+/// perhaps with its own relevance score.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub text: String,
+    pub score: Option<f32>,
+}
+
+/// Like [`route_prompt`] for chunks that need no splitting. When every
+/// chunk brings a score those go to the router as they are; otherwise all
+/// are scored against `query` with the scorer. `None` when there are no
+/// chunks.
+pub fn route_chunks(query: &str, chunks: Vec<Chunk>, opts: &RouteOptions) -> Option<Routed> {
+    route_chunks_cached(query, chunks, opts, &mut RouteCache::default())
+}
+
+/// Like [`route_chunks`], keeping the chunks' words in `cache` for the
+/// next call.
+pub fn route_chunks_cached(
+    query: &str,
+    chunks: Vec<Chunk>,
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+) -> Option<Routed> {
+    route_chunks_scored(query, chunks, opts, cache, &mut |_, _| None)
+}
+
+/// Like [`route_chunks_cached`], scoring chunks that bring no scores of
+/// their own with `score`.
+pub fn route_chunks_scored(
+    query: &str,
+    chunks: Vec<Chunk>,
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+    score: &mut ScoreFn,
+) -> Option<Routed> {
+    let start = Instant::now();
+    let given: Option<Vec<f32>> = chunks.iter().map(|chunk| chunk.score).collect();
+    let candidates: Vec<String> = chunks
+        .into_iter()
+        .map(|chunk| chunk.text.trim().to_string())
+        .collect();
+    let duplicates = match opts.dedup {
+        Some(threshold) => near_duplicates(&candidates, threshold, &opts.tokens, cache),
+        None => Vec::new(),
+    };
+    let candidates = without(candidates, &duplicates);
+    let query = query.trim().to_string();
+    let tokenized = tokenize(&candidates, opts, cache);
+    let (scores, scorer) = match given {
+        Some(scores) if !scores.is_empty() => (without(scores, &duplicates), "given"),
+        _ => score_chunks(&query, &candidates, opts, cache, score),
+    };
+    let scored = Instant::now();
+    let mut routed = route_scored(query, candidates, scores, None, opts, cache)?;
+    routed.duplicates = duplicates;
+    routed.fuzzy = scorer == "trigram";
+    finish_report(&mut routed, scorer, start, tokenized, scored);
+    Some(routed)
+}
+
+/// With [`RouteOptions::report`], puts every candidate's words in `cache`
+/// first, so the scorer's time is spent scoring alone. Returns when done.
+fn tokenize(candidates: &[String], opts: &RouteOptions, cache: &mut RouteCache) -> Instant {
+    if opts.report {
+        for chunk in candidates {
+            cache.words(chunk, &opts.tokens);
+        }
+    }
+    Instant::now()
+}
+
+/// Fills in the report's scorer and timings, the phases ending at
+/// `tokenized`, `scored`, and now.
+fn finish_report(
+    routed: &mut Routed,
+    scorer: &'static str,
+    start: Instant,
+    tokenized: Instant,
+    scored: Instant,
+) {
+    if let Some(report) = &mut routed.report {
+        report.scorer = scorer;
+        report.timings = Timings {
+            tokenize: tokenized - start,
+            score: scored - tokenized,
+            route: scored.elapsed(),
+        };
+    }
+}
+
+/// Scores `candidates` with `score`, else the scorer, falling back on
+/// trigrams as [`RouteOptions::fuzzy`] says. Also what scored them, as
+/// [`RouteReport::scorer`] names it.
+fn score_chunks(
+    query: &str,
+    candidates: &[String],
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+    score: &mut ScoreFn,
+) -> (Vec<f32>, &'static str) {
+    if let Some(scores) = score(query, candidates) {
+        return (scores, "custom");
+    }
+    let scores = opts
+        .scorer
+        .score_cached(query, candidates, &opts.tokens, cache);
+    match opts.fuzzy {
+        Some(below) if !scores.is_empty() && scores.iter().all(|s| *s < below) => (
+            trigram_scores(query, candidates, &opts.tokens, cache),
+            "trigram",
+        ),
+        _ => (scores, opts.scorer.name()),
+    }
+}
+
+/// The chunks to drop as near-duplicates. Compares every pair, skipping
+/// those whose word counts alone rule out `threshold`: the Jaccard
+/// similarity of two sets is at most the smaller's size over the larger's.
+/// Words become sorted ids first, so each comparison is one merge.
+fn near_duplicates(
+    chunks: &[String],
+    threshold: f32,
+    words: &TokenOptions,
+    cache: &mut RouteCache,
+) -> Vec<Duplicate> {
+    let mut ids: HashMap<String, u32> = HashMap::new();
+    let sets: Vec<Vec<u32>> = chunks
+        .iter()
+        .map(|chunk| {
+            let mut set: Vec<u32> = cache
+                .words(chunk, words)
+                .iter()
+                .map(|word| {
+                    let next = ids.len() as u32;
+                    *ids.entry(word.clone()).or_insert(next)
+                })
+                .collect();
+            set.sort_unstable();
+            set.dedup();
+            set
+        })
+        .collect();
+    let lens: Vec<usize> = chunks.iter().map(|chunk| chunk.chars().count()).collect();
+    let mut dropped = vec![false; chunks.len()];
+    let mut duplicates = Vec::new();
+    for a in 0..chunks.len() {
+        for b in a + 1..chunks.len() {
+            if dropped[a] {
+                break;
+            }
+            if dropped[b] {
+                continue;
+            }
+            let (x, y) = (sets[a].len(), sets[b].len());
+            if x.max(y) == 0 || (x.min(y) as f32) < threshold * x.max(y) as f32 {
+                continue;
+            }
+            let common = common_sorted(&sets[a], &sets[b]);
+            let similarity = common as f32 / (x + y - common) as f32;
+            if similarity < threshold {
+                continue;
+            }
+            let (kept, gone) = if lens[b] > lens[a] { (b, a) } else { (a, b) };
+            dropped[gone] = true;
+            duplicates.push(Duplicate {
+                dropped: gone,
+                kept,
+                similarity,
+            });
+        }
+    }
+    duplicates
+}
+
+/// How many ids two sorted, repeat-free lists share.
+fn common_sorted(a: &[u32], b: &[u32]) -> usize {
+    let (mut i, mut j, mut common) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            Ordering::Less => i += 1,
+            Ordering::Greater => j += 1,
+            Ordering::Equal => {
+                common += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    common
+}
+
+/// `items` less the ones `duplicates` dropped.
+fn without<T>(items: Vec<T>, duplicates: &[Duplicate]) -> Vec<T> {
+    items
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| !duplicates.iter().any(|dup| dup.dropped == *idx))
+        .map(|(_, item)| item)
+        .collect()
+}
+
+/// Routes `candidates` on their `scores` and writes the prompt back out.
+fn route_scored(
+    query: String,
+    candidates: Vec<String>,
+    scores: Vec<f32>,
+    windowed: Option<Windowed>,
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+) -> Option<Routed> {
+    if candidates.is_empty() {
+        return None;
+    }
+
+    // With nothing scoring above 0 there is nothing for the router to go
+    // on; the cap on kept chunks is its to apply otherwise, unless the
+    // diversity pass is to choose among everything it would keep.
+    let mut route = opts.route;
+    if opts.keep > 0 && opts.diversity <= 0.0 {
+        route.max_keep = Some(route.max_keep.map_or(opts.keep, |max| max.min(opts.keep)));
+    }
+    let result = if scores.iter().all(|s| *s <= 0.0) {
+        None
+    } else {
+        Some(neuroute::route_values_with(&scores, &route).ok()?)
+    };
+    let mut reasons: Vec<Reason> = match &result {
+        Some(result) => {
+            let reason = match result.strategy {
+                MaskStrategy::TopK => Reason::TopK,
+                _ => Reason::Threshold,
+            };
+            let mut reasons = vec![reason; candidates.len()];
+            for &idx in &result.clamped {
+                reasons[idx] = Reason::Bounds;
+            }
+            reasons
+        }
+        None => vec![Reason::Fallback; candidates.len()],
+    };
+    let routed: Option<Vec<usize>> = result.as_ref().map(|result| {
+        result
+            .mask
+            .iter()
+            .enumerate()
+            .filter_map(|(i, keep)| if *keep { Some(i) } else { None })
+            .collect()
+    });
+
+    // Without a route, keep the best few. Anchors go first and take slots
+    // from the rest, unless there are more of them than slots.
+    let fallback = opts.keep.max(1);
+    let anchors = opts.anchors.indices(candidates.len());
+    let (mut selected, picks) = if opts.diversity > 0.0 {
+        let mut count = routed.as_ref().map_or(fallback, |idxs| idxs.len());
+        if opts.keep > 0 {
+            count = count.min(opts.keep);
+        }
+        // Only what the router kept is picked from; without a route,
+        // every chunk is.
+        let pool: Vec<usize> = match &routed {
+            Some(idxs) => idxs.clone(),
+            None => (0..scores.len()).collect(),
+        }
+        .into_iter()
+        .filter(|i| !anchors.contains(i))
+        .collect();
+        let mut picks = diversify(&anchors, &pool, &scores, &candidates, opts, cache);
+        picks.truncate(count.max(anchors.len()));
+        reasons.fill(Reason::Diversity);
+        (picks.iter().map(|pick| pick.chunk).collect(), picks)
+    } else {
+        let mut selected = routed.unwrap_or_else(|| top_k_indices(&scores, fallback));
+        sort_by_score(&mut selected, &scores);
+        let count = selected.len().max(anchors.len());
+        let chosen = selected.clone();
+        selected.retain(|idx| !anchors.contains(idx));
+        selected.splice(0..0, anchors.iter().copied());
+        selected.truncate(count);
+        let kept = mask(&selected, candidates.len());
+        for idx in chosen.into_iter().filter(|&idx| !kept[idx]) {
+            reasons[idx] = Reason::Anchor;
+        }
+        (selected, Vec::new())
+    };
+    for &idx in &anchors {
+        reasons[idx] = Reason::Anchor;
+    }
+
+    let explanations = if opts.explain {
+        neuroute::route_values_explained(&scores, &opts.route).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let joiner = match windowed {
+        Some(_) => "\n\n".to_string(),
+        None => opts.split.joiner(opts.delim),
+    };
+    let mut skipped = 0;
+    let mut anchors_over_budget = false;
+    if let Some(budget) = opts.budget {
+        let routed = selected.clone();
+        (selected, anchors_over_budget) =
+            fit_budget(&selected, &anchors, &candidates, &joiner, budget);
+        skipped = routed.len() - selected.len();
+        let kept = mask(&selected, candidates.len());
+        for idx in routed.into_iter().filter(|&idx| !kept[idx]) {
+            reasons[idx] = Reason::Budget;
+        }
+    }
+    if windowed.is_some() {
+        selected.sort_unstable();
+    } else {
+        opts.order.sort(&mut selected, &scores);
+    }
+
+    let report = opts.report.then(|| {
+        let kept = mask(&selected, candidates.len());
+        RouteReport {
+            query: query.clone(),
+            chunks: candidates
+                .iter()
+                .enumerate()
+                .map(|(index, text)| ChunkReport {
+                    index,
+                    preview: text.chars().take(80).collect(),
+                    score: scores[index],
+                    prob: result.as_ref().map(|result| result.probs[index]),
+                    selected: kept[index],
+                    reason: reasons[index],
+                })
+                .collect(),
+            scorer: opts.scorer.name(),
+            threshold: route.threshold,
+            min_keep: route.min_keep,
+            max_keep: route.max_keep,
+            budget: opts.budget,
+            timings: Timings::default(),
+        }
+    });
+
+    let context = match &windowed {
+        Some(windows) => windows.join(&selected, &joiner),
+        None => selected
+            .iter()
+            .map(|idx| candidates[*idx].clone())
+            .collect::<Vec<_>>()
+            .join(&joiner),
+    };
+
+    let prompt = if context.is_empty() {
+        query
+    } else {
+        format!("{query}{joiner}{context}")
+    };
+    Some(Routed {
+        chars: prompt.chars().count(),
+        prompt,
+        candidates: candidates.len(),
+        selected: selected.len(),
+        skipped,
+        anchors_over_budget,
+        picks,
+        windows: windowed.map_or(0, |windows| windows.spans.len()),
+        anchored: anchors
+            .into_iter()
+            .filter(|idx| selected.contains(idx))
+            .collect(),
+        duplicates: Vec::new(),
+        explanations,
+        fuzzy: false,
+        report,
+    })
+}
+
+/// Which of `len` indices are in `idx`.
+fn mask(idx: &[usize], len: usize) -> Vec<bool> {
+    let mut mask = vec![false; len];
+    for &i in idx {
+        mask[i] = true;
+    }
+    mask
+}
+
+/// Context cut into windows, kept whole so overlapping ones can be put
+/// back as one.
+struct Windowed {
+    text: String,
+    /// Byte range of each window in `text`.
+    spans: Vec<(usize, usize)>,
+}
+
+impl Windowed {
+    /// The `selected` windows in order, runs of overlapping or touching ones
+    /// merged so no text repeats, the rest joined by `joiner`.
+    fn join(&self, selected: &[usize], joiner: &str) -> String {
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for &(start, end) in selected.iter().map(|&idx| &self.spans[idx]) {
+            match runs.last_mut() {
+                Some(run) if start <= run.1 => run.1 = run.1.max(end),
+                _ => runs.push((start, end)),
+            }
+        }
+        runs.iter()
+            .map(|&(start, end)| self.text[start..end].trim())
+            .collect::<Vec<_>>()
+            .join(joiner)
+    }
+}
+
+/// The query and the windowed context, for a prompt the split left whole.
+/// `None` when autochunking is off, there is no query to take, or the
+/// context is short enough to send as it is.
+fn autochunk(text: String, opts: &RouteOptions) -> Option<(String, Windowed)> {
+    let auto = opts.autochunk?;
+    let (query, context) = match opts.query {
+        Some(query) => (query.trim().to_string(), text),
+        None => {
+            let (query, context) = split::first_paragraph(&text)?;
+            (query.to_string(), context.to_string())
+        }
+    };
+    if context.chars().count() <= auto.above {
+        return None;
+    }
+    let spans = split::windows(&context, auto.window, auto.overlap);
+    (spans.len() >= 2).then_some((
+        query,
+        Windowed {
+            text: context,
+            spans,
+        },
+    ))
+}
+
+/// Orders `selected` by maximal marginal relevance: each step takes the
+/// chunk with the best `(1 - diversity) * score - diversity * similarity`,
+/// where similarity is the largest Jaccard overlap of its words with the
+/// chunks already taken. Ties go to the higher score, then the lower index.
+/// The `anchors` are taken first, unpenalised.
+fn diversify(
+    anchors: &[usize],
+    selected: &[usize],
+    scores: &[f32],
+    chunks: &[String],
+    opts: &RouteOptions,
+    cache: &mut RouteCache,
+) -> Vec<Pick> {
+    let mut left = selected.to_vec();
+    sort_by_score(&mut left, scores);
+    let tokens: Vec<Arc<[String]>> = chunks
+        .iter()
+        .map(|chunk| cache.words(chunk, &opts.tokens))
+        .collect();
+    let words: Vec<HashSet<&str>> = tokens
+        .iter()
+        .map(|words| words.iter().map(String::as_str).collect())
+        .collect();
+    let weight = opts.diversity.clamp(0.0, 1.0);
+    let mut picks: Vec<Pick> = anchors
+        .iter()
+        .map(|&chunk| Pick {
+            chunk,
+            score: scores[chunk],
+            penalty: 0.0,
+        })
+        .collect();
+    while !left.is_empty() {
+        let mut best: Option<(usize, f32, f32)> = None;
+        for (at, &idx) in left.iter().enumerate() {
+            let similarity = picks
+                .iter()
+                .map(|pick| jaccard(&words[idx], &words[pick.chunk]))
+                .fold(0.0_f32, f32::max);
+            let penalty = weight * similarity;
+            let value = (1.0 - weight) * scores[idx] - penalty;
+            if best.is_none_or(|(_, best, _)| value > best) {
+                best = Some((at, value, penalty));
+            }
+        }
+        let Some((at, _, penalty)) = best else { break };
+        let chunk = left.remove(at);
+        picks.push(Pick {
+            chunk,
+            score: scores[chunk],
+            penalty,
+        });
+    }
+    picks
+}
+
+fn jaccard(a: &HashSet<&str>, b: &HashSet<&str>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+/// The chunks of `ranked` that fit in `budget` characters, each costing its
+/// length plus a joiner. The `anchors` among them are counted first and
+/// always kept; the rest are taken in order while they fit in what is
+/// left. Falls back to the first chunk alone when none
+/// fits. Also whether the anchors alone were over the budget.
+fn fit_budget(
+    ranked: &[usize],
+    anchors: &[usize],
+    chunks: &[String],
+    joiner: &str,
+    budget: usize,
+) -> (Vec<usize>, bool) {
+    let joiner_chars = joiner.chars().count();
+    let cost = |idx: usize| joiner_chars + chunks[idx].chars().count();
+    let mut used = 0;
+    let mut keep = vec![false; chunks.len()];
+    for &idx in ranked.iter().filter(|idx| anchors.contains(idx)) {
+        used += cost(idx);
+        keep[idx] = true;
+    }
+    let over = used > budget;
+    for &idx in ranked.iter().filter(|idx| !anchors.contains(idx)) {
+        if used + cost(idx) <= budget {
+            used += cost(idx);
+            keep[idx] = true;
+        }
+    }
+    let mut fitted: Vec<usize> = ranked.iter().copied().filter(|&idx| keep[idx]).collect();
+    if fitted.is_empty() {
+        fitted.extend(ranked.first());
+    }
+    (fitted, over)
+}
+
+/// Indices of the `k` highest scores, best first (ties by index); all of
+/// them when `k` is 0 or covers every score.
+pub fn top_k_indices(scores: &[f32], k: usize) -> Vec<usize> {
+    if scores.is_empty() {
+        return Vec::new();
+    }
+    if k == 0 || k >= scores.len() {
+        return (0..scores.len()).collect();
+    }
+    let mut idx: Vec<usize> = (0..scores.len()).collect();
+    sort_by_score(&mut idx, scores);
+    idx.truncate(k);
+    idx
+}
+
+/// Best score first, ties by index.
+fn sort_by_score(idx: &mut [usize], scores: &[f32]) {
+    idx.sort_by(|&a, &b| {
+        scores[b]
+            .partial_cmp(&scores[a])
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.cmp(&b))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> RouteOptions<'static> {
+        RouteOptions {
+            delim: "---",
+            split: Split::Delim,
+            min_chars: 0,
+            autochunk: None,
+            query: None,
+            keep: 0,
+            scorer: Scorer::Overlap,
+            tokens: TokenOptions::default(),
+            route: RouteConfig::default(),
+            budget: None,
+            diversity: 0.0,
+            anchors: Anchors::default(),
+            dedup: None,
+            explain: false,
+            report: false,
+            order: Order::Document,
+            fuzzy: None,
+        }
+    }
+
+    /// Options whose router keeps every chunk, leaving the cut to the
+    /// budget.
+    fn keep_all(budget: usize) -> RouteOptions<'static> {
+        let mut opts = opts();
+        opts.route.threshold = 0.0;
+        opts.route.prefer = MaskStrategy::Threshold;
+        opts.budget = Some(budget);
+        opts
+    }
+
+    fn scored(chunks: &[(&str, f32)]) -> Vec<Chunk> {
+        chunks
+            .iter()
+            .map(|&(text, score)| Chunk {
+                text: text.to_string(),
+                score: Some(score),
+            })
+            .collect()
+    }
+
+    fn kept(routed: &Routed, chunks: &[Chunk]) -> Vec<usize> {
+        (0..chunks.len())
+            .filter(|&idx| routed.prompt.contains(&chunks[idx].text))
+            .collect()
+    }
+
+    #[test]
+    fn diversity_keeps_the_distinct_chunk() {
+        let chunks = scored(&[
+            ("the cache is flushed every minute to disk", 0.95),
+            ("the cache is flushed every minute to the disk", 0.94),
+            ("the cache gets flushed every minute to disk", 0.93),
+            ("requests over the quota are rejected with 429", 0.6),
+        ]);
+        let mut opts = opts();
+        opts.keep = 3;
+        opts.route.min_keep = 4;
+        let plain = route_chunks("q", chunks.clone(), &opts).unwrap();
+        assert_eq!(kept(&plain, &chunks), [0, 1, 2]);
+
+        opts.diversity = 0.5;
+        let diverse = route_chunks("q", chunks.clone(), &opts).unwrap();
+        assert!(kept(&diverse, &chunks).contains(&3));
+        assert_eq!(diverse.selected, 3);
+        assert!(diverse.picks.iter().any(|pick| pick.penalty > 0.0));
+    }
+
+    #[test]
+    fn diversity_only_picks_what_the_router_kept() {
+        let chunks = scored(&[
+            ("alpha beta gamma", 0.9),
+            ("alpha beta gamma delta", 0.85),
+            ("alpha beta gamma epsilon", 0.8),
+            ("unrelated words entirely here", 0.3),
+            ("something else again", 0.05),
+        ]);
+        let mut opts = opts();
+        opts.route.max_keep = Some(2);
+        let plain = route_chunks("q", chunks.clone(), &opts).unwrap();
+        let routed = kept(&plain, &chunks);
+
+        for diversity in [0.3, 0.7, 1.0] {
+            opts.diversity = diversity;
+            let diverse = route_chunks("q", chunks.clone(), &opts).unwrap();
+            for pick in &diverse.picks {
+                assert!(routed.contains(&pick.chunk), "{diversity}: {pick:?}");
+            }
+            assert_eq!(diverse.selected, routed.len());
+        }
+    }
+
+    #[test]
+    fn an_anchored_last_chunk_evicts_the_worst_pick() {
+        let prompt = "how is the cache flushed\n---\nthe cache is flushed by a timer\n---\n\
+                      the cache is flushed on shutdown\n---\nwhat is for lunch";
+        let mut opts = opts();
+        opts.keep = 2;
+        opts.route.min_keep = 2;
+        let plain = route_prompt(prompt, &opts).unwrap();
+        assert!(!plain.prompt.contains("lunch"));
+
+        opts.anchors = Anchors {
+            first: false,
+            last: true,
+        };
+        let anchored = route_prompt(prompt, &opts).unwrap();
+        assert_eq!(anchored.selected, 2);
+        assert_eq!(anchored.anchored, [2]);
+        assert!(anchored.prompt.contains("lunch"));
+        assert!(anchored.prompt.contains("by a timer"));
+        assert!(!anchored.prompt.contains("on shutdown"));
+    }
+
+    #[test]
+    fn the_budget_goes_to_anchors_first() {
+        let chunks = ["best".repeat(5), "good".repeat(5), "last".repeat(5)];
+        // Room for two chunks, each 20 characters and a two-character
+        // joiner.
+        let budget = 2 * 22;
+        let (fitted, over) = fit_budget(&[0, 1, 2], &[2], &chunks, "\n\n", budget);
+        assert_eq!(fitted, [0, 2]);
+        assert!(!over);
+
+        let (fitted, over) = fit_budget(&[0, 1, 2], &[], &chunks, "\n\n", budget);
+        assert_eq!(fitted, [0, 1]);
+        assert!(!over);
+    }
+
+    #[test]
+    fn the_budget_skips_huge_chunks_and_fills_with_tiny_ones() {
+        let chunks = [
+            "huge ".repeat(200),
+            "tiny".to_string(),
+            "huge ".repeat(100),
+            "wee".to_string(),
+        ];
+        // The best chunk alone is over 100 characters, so the two small
+        // ones behind it fill the budget instead.
+        let (fitted, over) = fit_budget(&[0, 2, 1, 3], &[], &chunks, "\n\n", 100);
+        assert_eq!(fitted, [1, 3]);
+        assert!(!over);
+
+        // Room for the best tiny chunk and not the next.
+        let (fitted, _) = fit_budget(&[0, 2, 1, 3], &[], &chunks, "\n\n", 6);
+        assert_eq!(fitted, [1]);
+    }
+
+    #[test]
+    fn the_budget_keeps_the_best_chunk_when_nothing_fits() {
+        let chunks = ["huge ".repeat(200), "huge ".repeat(100)];
+        for budget in [0, 1, 50] {
+            let (fitted, over) = fit_budget(&[1, 0], &[], &chunks, "\n\n", budget);
+            assert_eq!(fitted, [1], "budget {budget}");
+            assert!(!over);
+        }
+
+        let prompt = format!(
+            "huge\n---\n{}\n---\n{}",
+            "huge ".repeat(200),
+            "huge ".repeat(100)
+        );
+        let routed = route_prompt(&prompt, &keep_all(1)).unwrap();
+        assert_eq!(routed.candidates, 2);
+        assert_eq!(routed.selected, 1);
+        assert_eq!(routed.skipped, routed.candidates - 1);
+    }
+
+    #[test]
+    fn the_budget_counts_the_chunks_and_not_the_query() {
+        let prompt = format!("{}\n---\nalpha one\n---\nalpha two", "alpha ".repeat(50));
+        // Both chunks and their delimiters, however long the query.
+        let opts = keep_all(2 * ("alpha one".len() + "\n---\n".len()));
+        let routed = route_prompt(&prompt, &opts).unwrap();
+        assert_eq!(routed.selected, 2);
+        assert_eq!(routed.skipped, 0);
+        assert_eq!(
+            prompt_query(&prompt, &opts).unwrap(),
+            "alpha ".repeat(50).trim()
+        );
+    }
+
+    #[test]
+    fn markdown_routing_keeps_a_section_with_its_heading() {
+        let prompt = "where does the config file live?\n\
+                      # Install\nRun the installer.\n\
+                      ## Config file\nThe config file lives in ~/.config/nox.\n\
+                      ## Uninstall\nDelete the binary.\n";
+        let mut opts = opts();
+        opts.split = Split::Markdown;
+        opts.keep = 1;
+        let routed = route_prompt(prompt, &opts).unwrap();
+        assert_eq!(routed.candidates, 3);
+        assert_eq!(
+            routed.prompt,
+            "where does the config file live?\n\n\
+             ## Config file\nThe config file lives in ~/.config/nox."
+        );
+
+        opts.split = Split::Blank;
+        let prompt = "where does the config file live?\n\n\
+                      Run the installer.\n\n\
+                      The config file lives in ~/.config/nox.\n\n\
+                      Delete the binary.";
+        let routed = route_prompt(prompt, &opts).unwrap();
+        assert_eq!(routed.candidates, 3);
+        assert!(routed
+            .prompt
+            .ends_with("\n\nThe config file lives in ~/.config/nox."));
+    }
+
+    /// A 10KB document with no delimiters and one sentence that answers
+    /// [`BURIED_QUERY`] near its middle.
+    fn buried_document() -> String {
+        let filler = |from: usize, to: usize| {
+            (from..to)
+                .map(|n| format!("Paragraph {n} talks about weather, rivers, and birds."))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        format!(
+            "{}\n\nThe secret launch code is tangerine.\n\n{}",
+            filler(0, 95),
+            filler(95, 190)
+        )
+    }
+
+    const BURIED_QUERY: &str = "what is the secret launch code?";
+
+    #[test]
+    fn autochunking_finds_the_answer_in_a_long_document() {
+        let document = buried_document();
+        assert!(document.len() > 10_000);
+        let prompt = format!("{BURIED_QUERY}\n\n{document}");
+        let mut opts = opts();
+        opts.keep = 1;
+        // Without autochunking a prompt with no delimiter is left alone.
+        assert_eq!(route_prompt(&prompt, &opts), None);
+
+        let auto = Autochunk::default();
+        opts.autochunk = Some(auto);
+        let routed = route_prompt(&prompt, &opts).unwrap();
+        let windows = split::windows(&document, auto.window, auto.overlap);
+        assert!(windows.len() >= 10, "{} windows", windows.len());
+        assert_eq!(routed.windows, windows.len());
+        assert_eq!(routed.candidates, windows.len());
+        assert_eq!(routed.selected, 1);
+        assert!(routed.prompt.starts_with(BURIED_QUERY));
+        assert!(routed
+            .prompt
+            .contains("The secret launch code is tangerine."));
+        assert!(
+            routed.chars * 5 < prompt.len(),
+            "{} chars kept",
+            routed.chars
+        );
+    }
+
+    #[test]
+    fn autochunking_leaves_short_contexts_alone() {
+        let mut opts = opts();
+        opts.autochunk = Some(Autochunk::default());
+        let prompt = format!("{BURIED_QUERY}\n\nThe secret launch code is tangerine.");
+        assert_eq!(route_prompt(&prompt, &opts), None);
+        // Nor can it take a query from a context with no blank line.
+        let document = buried_document().replace("\n\n", " ");
+        assert_eq!(route_prompt(&document, &opts), None);
+    }
+
+    #[test]
+    fn adjacent_windows_are_joined_without_their_overlap() {
+        let document = buried_document();
+        let auto = Autochunk::default();
+        let spans = split::windows(&document, auto.window, auto.overlap);
+        assert!(spans.windows(2).all(|w| w[1].0 < w[0].1), "windows overlap");
+        let windowed = Windowed {
+            text: document.clone(),
+            spans: spans.clone(),
+        };
+        assert_eq!(
+            windowed.join(&[0, 1], "\n\n"),
+            document[spans[0].0..spans[1].1].trim()
+        );
+        let window = |idx: usize| document[spans[idx].0..spans[idx].1].trim();
+        assert_eq!(
+            windowed.join(&[0, 2], "\n\n"),
+            format!("{}\n\n{}", window(0), window(2))
+        );
+    }
+
+    #[test]
+    fn given_scores_decide_in_place_of_the_scorer() {
+        // The query's words are all in the chunk scored lowest.
+        let chunks = scored(&[
+            ("restart the runner with nox", 0.0),
+            ("unrelated but scored high", 1.0),
+            ("also unrelated", 0.1),
+        ]);
+        let mut opts = opts();
+        opts.keep = 1;
+        let routed = route_chunks("restart the runner", chunks.clone(), &opts).unwrap();
+        assert_eq!(kept(&routed, &chunks), [1]);
+
+        // Without scores of their own the chunks are scored as usual.
+        let plain: Vec<Chunk> = chunks
+            .iter()
+            .map(|chunk| Chunk {
+                score: None,
+                ..chunk.clone()
+            })
+            .collect();
+        let routed = route_chunks("restart the runner", plain.clone(), &opts).unwrap();
+        assert_eq!(kept(&routed, &plain), [0]);
+    }
+
+    #[test]
+    fn chunks_may_hold_the_delimiter() {
+        let chunks = vec![
+            Chunk {
+                text: "first --- part of one chunk".to_string(),
+                score: None,
+            },
+            Chunk {
+                text: "---".to_string(),
+                score: None,
+            },
+        ];
+        let mut opts = opts();
+        opts.keep = 1;
+        let routed = route_chunks("first part", chunks, &opts).unwrap();
+        assert_eq!(routed.candidates, 2);
+        assert_eq!(
+            routed.prompt,
+            "first part\n---\nfirst --- part of one chunk"
+        );
+    }
+
+    /// Forty distinct words, `w0` to `w39`, with `swap` in place of the
+    /// last when given.
+    fn forty_words(swap: Option<&str>) -> String {
+        let mut words: Vec<String> = (0..40).map(|n| format!("w{n}")).collect();
+        if let Some(swap) = swap {
+            words[39] = swap.to_string();
+        }
+        words.join(" ")
+    }
+
+    #[test]
+    fn dedup_collapses_only_the_near_duplicates() {
+        let chunks = vec![
+            forty_words(None),
+            "the runner restarts after a crash".to_string(),
+            // Exactly the first.
+            forty_words(None),
+            // One word of forty changed: Jaccard 39/41, about 0.95.
+            forty_words(Some("other")),
+            "models load from the assets directory".to_string(),
+        ];
+        let dups = near_duplicates(
+            &chunks,
+            0.9,
+            &TokenOptions::default(),
+            &mut RouteCache::default(),
+        );
+        let pairs: Vec<(usize, usize)> = dups.iter().map(|d| (d.dropped, d.kept)).collect();
+        // The exact copy goes to the earlier one; of the 95% pair the
+        // longer is kept. The unrelated chunks stay.
+        assert_eq!(pairs, [(2, 0), (0, 3)]);
+        assert_eq!(dups[0].similarity, 1.0);
+        assert!((dups[1].similarity - 39.0 / 41.0).abs() < 1e-6, "{dups:?}");
+
+        // A stricter threshold keeps the 95% pair apart and still drops
+        // the exact copy.
+        let dups = near_duplicates(
+            &chunks,
+            0.99,
+            &TokenOptions::default(),
+            &mut RouteCache::default(),
+        );
+        let pairs: Vec<(usize, usize)> = dups.iter().map(|d| (d.dropped, d.kept)).collect();
+        assert_eq!(pairs, [(2, 0)]);
+        assert_eq!(dups[0].similarity, 1.0);
+    }
+
+    #[test]
+    fn dedup_runs_before_scoring() {
+        let prompt = format!(
+            "w1 w2\n---\n{}\n---\n{}\n---\nsomething else entirely",
+            forty_words(None),
+            forty_words(None)
+        );
+        let mut opts = opts();
+        let routed = route_prompt(&prompt, &opts).unwrap();
+        assert_eq!(routed.candidates, 3);
+        assert!(routed.duplicates.is_empty());
+
+        opts.dedup = Some(0.9);
+        opts.report = true;
+        let routed = route_prompt(&prompt, &opts).unwrap();
+        assert_eq!(routed.candidates, 2);
+        assert_eq!(
+            routed.duplicates,
+            [Duplicate {
+                dropped: 1,
+                kept: 0,
+                similarity: 1.0
+            }]
+        );
+        assert_eq!(routed.prompt.matches("w39").count(), 1);
+        assert_eq!(routed.report.unwrap().chunks.len(), 2);
+    }
+
+    #[test]
+    fn kept_chunks_come_back_in_document_or_score_order() {
+        let chunks = scored(&[("d", 0.5), ("a", 0.9), ("c", 0.7), ("b", 0.9), ("e", 0.3)]);
+        let mut opts = keep_all(usize::MAX);
+        opts.budget = None;
+        let routed = route_chunks("q", chunks.clone(), &opts).unwrap();
+        assert_eq!(routed.prompt, "q\n---\nd\n---\na\n---\nc\n---\nb\n---\ne");
+
+        // Best first; the two at 0.9 stay in prompt order.
+        opts.order = Order::Score;
+        let routed = route_chunks("q", chunks, &opts).unwrap();
+        assert_eq!(routed.prompt, "q\n---\na\n---\nb\n---\nc\n---\nd\n---\ne");
+
+        let mut idx = vec![4, 3, 2, 1, 0];
+        let scores = [0.5, 0.9, 0.7, 0.9, 0.3];
+        Order::Score.sort(&mut idx, &scores);
+        assert_eq!(idx, [1, 3, 2, 0, 4]);
+        Order::Document.sort(&mut idx, &scores);
+        assert_eq!(idx, [0, 1, 2, 3, 4]);
+    }
+
+    const TYPO_CHUNKS: [&str; 3] = [
+        "configure the runner with NOX_LOCAL_RUNNER",
+        "download a model into assets/models",
+        "restart the session after a crash",
+    ];
+
+    /// The chunk kept for `query` with only one kept, and whether the
+    /// trigram fallback scored it.
+    fn fuzzy_route(query: &str, scorer: Scorer) -> (usize, bool) {
+        let chunks: Vec<Chunk> = TYPO_CHUNKS
+            .iter()
+            .map(|text| Chunk {
+                text: text.to_string(),
+                score: None,
+            })
+            .collect();
+        let mut opts = opts();
+        opts.keep = 1;
+        opts.scorer = scorer;
+        opts.tokens = TokenOptions::for_scorer(scorer);
+        opts.fuzzy = Some(0.01);
+        let routed = route_chunks(query, chunks.clone(), &opts).unwrap();
+        let kept = kept(&routed, &chunks);
+        assert_eq!(kept.len(), 1, "{query}: {}", routed.prompt);
+        (kept[0], routed.fuzzy)
+    }
+
+    #[test]
+    fn misspelt_queries_fall_back_to_trigrams() {
+        for scorer in [Scorer::Overlap, Scorer::TfIdf, Scorer::BM25] {
+            for (query, want) in [
+                ("confgiure runer", 0),
+                ("dowload modle", 1),
+                ("restrat sesion crsh", 2),
+            ] {
+                assert_eq!(
+                    fuzzy_route(query, scorer),
+                    (want, true),
+                    "{query} with {scorer:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn well_spelt_queries_never_fall_back() {
+        for scorer in [Scorer::Overlap, Scorer::TfIdf, Scorer::BM25] {
+            for (query, want) in [
+                ("configure runner", 0),
+                ("download model", 1),
+                ("restart crash", 2),
+            ] {
+                assert_eq!(
+                    fuzzy_route(query, scorer),
+                    (want, false),
+                    "{query} with {scorer:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn anchors_over_the_budget_are_kept_alone() {
+        let chunks = ["best".repeat(5), "good".repeat(5), "last".repeat(5)];
+        let (fitted, over) = fit_budget(&[0, 1, 2], &[1, 2], &chunks, "\n\n", 30);
+        assert_eq!(fitted, [1, 2]);
+        assert!(over);
+    }
+
+    #[test]
+    fn the_scorer_decides_what_routing_keeps() {
+        let prompt = "how does photosynthesis work in the leaf\n---\n\
+                      how does the work get done: the team does the work in the office, \
+                      and how the work is split is how it works\n---\n\
+                      photosynthesis happens in the leaf\n---\n\
+                      how does the team meet? in the morning\n---\n\
+                      the office does the work";
+        let mut opts = opts();
+        opts.keep = 1;
+        let routed = route_prompt(prompt, &opts).unwrap();
+        assert!(
+            routed.prompt.contains("the team does the work"),
+            "{}",
+            routed.prompt
+        );
+        for scorer in [Scorer::TfIdf, Scorer::BM25] {
+            opts.scorer = scorer;
+            let routed = route_prompt(prompt, &opts).unwrap();
+            assert_eq!(routed.selected, 1);
+            assert!(
+                routed
+                    .prompt
+                    .ends_with("\n---\nphotosynthesis happens in the leaf"),
+                "{scorer:?}: {}",
+                routed.prompt
+            );
+        }
+    }
+
+    #[test]
+    fn a_second_query_over_the_same_chunks_tokenizes_none_of_them() {
+        use crate::score::TOKENIZED;
+
+        let chunks = [
+            "The runner reads prompts from stdin, one per line.",
+            "Models are searched for under assets/models.",
+            "Persistent mode keeps the runner alive between prompts.",
+        ];
+        let given = || -> Vec<Chunk> {
+            chunks
+                .iter()
+                .map(|text| Chunk {
+                    text: text.to_string(),
+                    score: None,
+                })
+                .collect()
+        };
+        for scorer in [Scorer::Overlap, Scorer::TfIdf, Scorer::BM25] {
+            let opts = RouteOptions {
+                scorer,
+                tokens: TokenOptions::for_scorer(scorer),
+                ..opts()
+            };
+            let mut cache = RouteCache::default();
+            let first = route_chunks_cached("where are models found?", given(), &opts, &mut cache);
+            assert!(first.is_some());
+            let before = cache.stats();
+            TOKENIZED.with(|seen| seen.borrow_mut().clear());
+
+            let second =
+                route_chunks_cached("how does the runner stay up?", given(), &opts, &mut cache);
+            let seen = TOKENIZED.with(|seen| seen.take());
+            assert!(second.is_some());
+            assert!(
+                seen.iter().all(|text| !chunks.contains(&text.as_str())),
+                "{scorer:?} tokenized {seen:?}"
+            );
+            let after = cache.stats();
+            assert_eq!(after.misses, before.misses, "{scorer:?}");
+            assert_eq!(after.hits - before.hits, chunks.len() as u64, "{scorer:?}");
+        }
+    }
+}
@@ -0,0 +1,521 @@
+//! A small regular expression matcher for `Split::Regex`, so the crate
+//! keeps to std. Supports literals, `.`, classes (`[a-z]`, `[^...]`, `\d`,
+//! `\w`, `\s` and their negations), groups, `|`, the quantifiers `*`, `+`,
+//! `?`, `{n}`, `{n,}`, `{n,m}`, and `^`/`$` at line starts and ends. The
+//! pattern compiles to an NFA that is run over the text once, finding the
+//! leftmost-longest non-empty match.
+
+use std::fmt;
+
+/// Caps `{n,m}`, and the compiled program, so nested repeats cannot blow
+/// up.
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    prog: Vec<Inst>,
+}
+
+/// Why a pattern did not compile, with the character offset it was found at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError {
+    pub at: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RegexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at character {}", self.message, self.at)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            at: 0,
+        };
+        let node = parser.alternation()?;
+        if parser.at < parser.chars.len() {
+            return Err(parser.error("unmatched `)`"));
+        }
+        let mut prog = Vec::new();
+        compile(&node, &mut prog);
+        if prog.len() > MAX_PROGRAM {
+            return Err(RegexError {
+                at: 0,
+                message: "pattern too large".to_string(),
+            });
+        }
+        prog.push(Inst::Match);
+        Ok(Self { prog })
+    }
+
+    /// The byte range of the leftmost-longest non-empty match starting at
+    /// or after byte `from`.
+    pub fn find_at(&self, text: &str, from: usize) -> Option<(usize, usize)> {
+        let mut current = Threads::new(self.prog.len());
+        let mut next = Threads::new(self.prog.len());
+        let mut best: Option<(usize, usize)> = None;
+        let mut pos = from;
+        loop {
+            // Threads already running started earlier, so they win a tie.
+            if best.is_none() {
+                self.add(&mut current, 0, pos, pos, text, &mut best);
+            }
+            let Some(ch) = text[pos..].chars().next() else {
+                break;
+            };
+            if current.list.is_empty() && best.is_some() {
+                break;
+            }
+            let after = pos + ch.len_utf8();
+            next.clear();
+            for i in 0..current.list.len() {
+                let (pc, start) = current.list[i];
+                if best.is_some_and(|(first, _)| start > first) {
+                    continue;
+                }
+                if let Inst::Char(class) = &self.prog[pc] {
+                    if class.matches(ch) {
+                        self.add(&mut next, pc + 1, start, after, text, &mut best);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            pos = after;
+        }
+        best
+    }
+
+    /// Every non-empty match, left to right and not overlapping.
+    pub fn find_iter<'a>(&'a self, text: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut from = 0;
+        std::iter::from_fn(move || {
+            let (start, end) = self.find_at(text, from)?;
+            from = end;
+            Some((start, end))
+        })
+    }
+
+    /// Follows jumps and assertions from `pc`, adding the threads that
+    /// wait on a character and noting any match.
+    fn add(
+        &self,
+        threads: &mut Threads,
+        pc: usize,
+        start: usize,
+        pos: usize,
+        text: &str,
+        best: &mut Option<(usize, usize)>,
+    ) {
+        if threads.seen[pc] {
+            return;
+        }
+        threads.seen[pc] = true;
+        match self.prog[pc] {
+            Inst::Char(_) => threads.list.push((pc, start)),
+            Inst::Split(a, b) => {
+                self.add(threads, a, start, pos, text, best);
+                self.add(threads, b, start, pos, text, best);
+            }
+            Inst::Jump(to) => self.add(threads, to, start, pos, text, best),
+            Inst::LineStart => {
+                if pos == 0 || text[..pos].ends_with('\n') {
+                    self.add(threads, pc + 1, start, pos, text, best);
+                }
+            }
+            Inst::LineEnd => {
+                let rest = &text[pos..];
+                if rest.is_empty() || rest.starts_with('\n') || rest.starts_with("\r\n") {
+                    self.add(threads, pc + 1, start, pos, text, best);
+                }
+            }
+            Inst::Match => {
+                let better = match *best {
+                    None => true,
+                    Some((first, end)) => start < first || (start == first && pos > end),
+                };
+                if pos > start && better {
+                    *best = Some((start, pos));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Char(Class),
+    /// Try both; the order does not matter for leftmost-longest.
+    Split(usize, usize),
+    Jump(usize),
+    LineStart,
+    LineEnd,
+    Match,
+}
+
+/// A thread list for one position, without duplicate states.
+struct Threads {
+    list: Vec<(usize, usize)>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(states: usize) -> Self {
+        Self {
+            list: Vec::new(),
+            seen: vec![false; states],
+        }
+    }
+
+    fn clear(&mut self) {
+        self.list.clear();
+        self.seen.iter_mut().for_each(|s| *s = false);
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Class(Class),
+    LineStart,
+    LineEnd,
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    if prog.len() > MAX_PROGRAM {
+        return;
+    }
+    match node {
+        Node::Empty => {}
+        Node::Class(class) => prog.push(Inst::Char(class.clone())),
+        Node::LineStart => prog.push(Inst::LineStart),
+        Node::LineEnd => prog.push(Inst::LineEnd),
+        Node::Concat(nodes) => nodes.iter().for_each(|n| compile(n, prog)),
+        Node::Alt(nodes) => {
+            let mut jumps = Vec::new();
+            for (i, n) in nodes.iter().enumerate() {
+                if i + 1 == nodes.len() {
+                    compile(n, prog);
+                    break;
+                }
+                let split = prog.len();
+                prog.push(Inst::Split(split + 1, 0));
+                compile(n, prog);
+                jumps.push(prog.len());
+                prog.push(Inst::Jump(0));
+                let other = prog.len();
+                prog[split] = Inst::Split(split + 1, other);
+            }
+            let end = prog.len();
+            for jump in jumps {
+                prog[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, prog);
+            }
+            match max {
+                None => {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(node, prog);
+                    prog.push(Inst::Jump(split));
+                    let end = prog.len();
+                    prog[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Split(prog.len() + 1, 0));
+                        compile(node, prog);
+                    }
+                    let end = prog.len();
+                    for split in splits {
+                        prog[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One character's worth of pattern.
+#[derive(Debug, Clone)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+    named: Vec<(Named, bool)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Named {
+    Digit,
+    Word,
+    Space,
+    /// `.`: anything but a newline.
+    Any,
+}
+
+impl Class {
+    fn new() -> Self {
+        Self {
+            negated: false,
+            ranges: Vec::new(),
+            named: Vec::new(),
+        }
+    }
+
+    fn literal(ch: char) -> Self {
+        let mut class = Self::new();
+        class.ranges.push((ch, ch));
+        class
+    }
+
+    fn named(named: Named, negated: bool) -> Self {
+        let mut class = Self::new();
+        class.named.push((named, negated));
+        class
+    }
+
+    fn matches(&self, ch: char) -> bool {
+        let hit = self.ranges.iter().any(|&(lo, hi)| lo <= ch && ch <= hi)
+            || self.named.iter().any(|&(named, negated)| {
+                let hit = match named {
+                    Named::Digit => ch.is_ascii_digit(),
+                    Named::Word => ch.is_alphanumeric() || ch == '_',
+                    Named::Space => ch.is_whitespace(),
+                    Named::Any => ch != '\n',
+                };
+                hit != negated
+            });
+        hit != self.negated
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn error(&self, message: &str) -> RegexError {
+        RegexError {
+            at: self.at,
+            message: message.to_string(),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        if self.peek() == Some(ch) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn alternation(&mut self) -> Result<Node, RegexError> {
+        let mut branches = vec![self.concat()?];
+        while self.eat('|') {
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 {
+            branches.remove(0)
+        } else {
+            Node::Alt(branches)
+        })
+    }
+
+    fn concat(&mut self) -> Result<Node, RegexError> {
+        let mut nodes = Vec::new();
+        while let Some(ch) = self.peek() {
+            if ch == '|' || ch == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.remove(0),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node, RegexError> {
+        let Some(ch) = self.peek() else {
+            return Err(self.error("unexpected end of pattern"));
+        };
+        self.at += 1;
+        Ok(match ch {
+            '(' => {
+                if self.chars[self.at..].starts_with(&['?', ':']) {
+                    self.at += 2;
+                }
+                let inner = self.alternation()?;
+                if !self.eat(')') {
+                    return Err(self.error("unclosed `(`"));
+                }
+                inner
+            }
+            '[' => Node::Class(self.class()?),
+            '.' => Node::Class(Class::named(Named::Any, false)),
+            '^' => Node::LineStart,
+            '$' => Node::LineEnd,
+            '\\' => Node::Class(self.escape()?),
+            '*' | '+' | '?' => {
+                self.at -= 1;
+                return Err(self.error("nothing to repeat"));
+            }
+            ch => Node::Class(Class::literal(ch)),
+        })
+    }
+
+    /// After a `\`, outside or inside a class.
+    fn escape(&mut self) -> Result<Class, RegexError> {
+        let Some(ch) = self.peek() else {
+            return Err(self.error("trailing `\\`"));
+        };
+        self.at += 1;
+        Ok(match ch {
+            'd' => Class::named(Named::Digit, false),
+            'D' => Class::named(Named::Digit, true),
+            'w' => Class::named(Named::Word, false),
+            'W' => Class::named(Named::Word, true),
+            's' => Class::named(Named::Space, false),
+            'S' => Class::named(Named::Space, true),
+            'n' => Class::literal('\n'),
+            't' => Class::literal('\t'),
+            'r' => Class::literal('\r'),
+            ch if ch.is_alphanumeric() => {
+                self.at -= 1;
+                return Err(self.error(&format!("unknown escape `\\{ch}`")));
+            }
+            ch => Class::literal(ch),
+        })
+    }
+
+    /// After a `[`, up to and including the `]`.
+    fn class(&mut self) -> Result<Class, RegexError> {
+        let mut class = Class::new();
+        class.negated = self.eat('^');
+        let mut first = true;
+        loop {
+            let Some(ch) = self.peek() else {
+                return Err(self.error("unclosed `[`"));
+            };
+            self.at += 1;
+            if ch == ']' && !first {
+                return Ok(class);
+            }
+            first = false;
+            let lo = if ch == '\\' {
+                let escaped = self.escape()?;
+                match escaped.ranges.first() {
+                    Some(&(lo, _)) if escaped.named.is_empty() => lo,
+                    _ => {
+                        class.named.extend(escaped.named);
+                        continue;
+                    }
+                }
+            } else {
+                ch
+            };
+            let ranged =
+                self.peek() == Some('-') && self.chars.get(self.at + 1).is_some_and(|&c| c != ']');
+            if !ranged {
+                class.ranges.push((lo, lo));
+                continue;
+            }
+            self.at += 1;
+            let mut hi = self.chars[self.at];
+            self.at += 1;
+            if hi == '\\' {
+                let escaped = self.escape()?;
+                match escaped.ranges.first() {
+                    Some(&(c, _)) if escaped.named.is_empty() => hi = c,
+                    _ => return Err(self.error("bad range in `[...]`")),
+                }
+            }
+            if hi < lo {
+                return Err(self.error("range out of order in `[...]`"));
+            }
+            class.ranges.push((lo, hi));
+        }
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node, RegexError> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{')
+                    if self
+                        .chars
+                        .get(self.at + 1)
+                        .is_some_and(char::is_ascii_digit) =>
+                {
+                    self.at += 1;
+                    let min = self.number()?;
+                    let max = if self.eat(',') {
+                        if self.peek() == Some('}') {
+                            None
+                        } else {
+                            Some(self.number()?)
+                        }
+                    } else {
+                        Some(min)
+                    };
+                    if self.peek() != Some('}') {
+                        return Err(self.error("unclosed `{`"));
+                    }
+                    if max.is_some_and(|max| max < min) {
+                        return Err(self.error("`{n,m}` with m below n"));
+                    }
+                    (min, max)
+                }
+                _ => return Ok(node),
+            };
+            self.at += 1;
+            if matches!(node, Node::Empty | Node::LineStart | Node::LineEnd) {
+                return Err(self.error("nothing to repeat"));
+            }
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    fn number(&mut self) -> Result<u32, RegexError> {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.at += 1;
+        }
+        let digits: String = self.chars[start..self.at].iter().collect();
+        match digits.parse::<u32>() {
+            Ok(n) if n <= MAX_REPEAT => Ok(n),
+            _ => Err(self.error(&format!("repeat count above {MAX_REPEAT}"))),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+/// What routing did with each chunk and why, with the settings it ran
+/// under and how long each phase took: filled in by
+/// [`route_prompt`](crate::route_prompt) with
+/// [`RouteOptions::report`](crate::RouteOptions::report), for tuning.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RouteReport {
+    pub query: String,
+    /// One per candidate chunk, in prompt order.
+    pub chunks: Vec<ChunkReport>,
+    /// What scored the chunks: a [`Scorer`](crate::Scorer) name,
+    /// `trigram` when the fuzzy fallback did, `given` for chunks that
+    /// brought their own scores, or `custom` for a
+    /// [`ScoreFn`](crate::ScoreFn).
+    pub scorer: &'static str,
+    pub threshold: f32,
+    pub min_keep: usize,
+    /// The router's cap on kept chunks, lowered to
+    /// [`RouteOptions::keep`](crate::RouteOptions::keep) where that is
+    /// lower.
+    pub max_keep: Option<usize>,
+    pub budget: Option<usize>,
+    pub timings: Timings,
+}
+
+/// One candidate chunk in a [`RouteReport`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkReport {
+    /// Index among the candidates, as in [`Routed::anchored`](crate::Routed::anchored).
+    pub index: usize,
+    /// The chunk's first 80 characters.
+    pub preview: String,
+    pub score: f32,
+    /// The router's probability of keeping the chunk; `None` when no chunk
+    /// scored above 0 and the router did not run.
+    pub prob: Option<f32>,
+    /// Whether the chunk is in the rewritten prompt.
+    pub selected: bool,
+    /// The last step that decided `selected`.
+    pub reason: Reason,
+}
+
+/// Which step of routing kept or dropped a chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    /// The router's threshold mask.
+    Threshold,
+    /// The router's top-k-hat mask.
+    TopK,
+    /// Flipped from the router's mask to keep between `min_keep` and
+    /// `max_keep` chunks.
+    Bounds,
+    /// No chunk scored above 0, so the best-scoring were kept.
+    Fallback,
+    /// Picked, or not, by the diversity pass.
+    Diversity,
+    /// An anchor, or a chunk whose slot one took.
+    Anchor,
+    /// Routed, but left out to fit the budget.
+    Budget,
+}
+
+impl Reason {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reason::Threshold => "threshold",
+            Reason::TopK => "topk",
+            Reason::Bounds => "bounds",
+            Reason::Fallback => "fallback",
+            Reason::Diversity => "diversity",
+            Reason::Anchor => "anchor",
+            Reason::Budget => "budget",
+        }
+    }
+}
+
+/// Wall-clock time spent in each phase of routing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timings {
+    /// Splitting the prompt, dropping near-duplicates, and tokenizing the
+    /// chunks.
+    pub tokenize: Duration,
+    pub score: Duration,
+    /// The router, and picking and joining the chunks it kept.
+    pub route: Duration,
+}
@@ -1,4 +1,6 @@
 // Generated from routing_model_weights_best.npz
+#![allow(clippy::excessive_precision)]
+
 pub const IN_DIM: usize = 8;
 pub const HIDDEN: usize = 128;
 
@@ -172,3 +174,71 @@ pub const W2: [f32; 128] = [
 ];
 
 pub const B2: f32 = -1.50082231e+00f32;
+
+// Generated by `noxroute train` on profiles/aggressive.txt (seed 1, 40 epochs, lr 0.01)
+pub const AGGRESSIVE_HIDDEN: usize = 16;
+
+pub const AGGRESSIVE_W1: [f32; 128] = [
+    5.92081130e-01f32, -3.39274347e-01f32, -6.53597355e-01f32, 5.31661630e-01f32, 6.57134295e-01f32, -9.30499732e-02f32, -5.59284270e-01f32, -1.57996923e-01f32,
+    2.52741247e-01f32, 8.31196010e-01f32, 2.64936611e-02f32, -3.59259635e-01f32, -6.00199580e-01f32, -3.07629973e-01f32, -7.55626634e-02f32, 2.95158654e-01f32,
+    -2.70093411e-01f32, -3.47877815e-02f32, -4.80927616e-01f32, 7.23464310e-01f32, -5.04608154e-01f32, 7.67005920e-01f32, 6.96990788e-01f32, -2.20982820e-01f32,
+    -3.97414528e-02f32, 2.36383706e-01f32, -1.76583584e-02f32, 2.12156460e-01f32, 1.09101675e-01f32, 3.43957156e-01f32, 6.31762028e-01f32, 3.65496993e-01f32,
+    2.09186837e-01f32, -5.92809677e-01f32, -2.07026854e-01f32, -2.89300740e-01f32, -4.50948447e-01f32, 5.64063549e-01f32, -5.39680362e-01f32, 4.34637815e-01f32,
+    -6.04246080e-01f32, -6.40893877e-01f32, -9.07555372e-02f32, 6.03049934e-01f32, -7.81670749e-01f32, 6.06903195e-01f32, -2.56165922e-01f32, -5.34938395e-01f32,
+    4.27382946e-01f32, 1.22233704e-01f32, 7.22851038e-01f32, -1.29758751e+00f32, -1.56865656e-01f32, -3.12739789e-01f32, 3.09875280e-01f32, 1.35841620e+00f32,
+    -7.50741541e-01f32, -5.01802623e-01f32, 1.70336589e-01f32, -2.59562973e-02f32, -1.67411014e-01f32, 1.35641778e+00f32, -4.59289670e-01f32, -6.78780138e-01f32,
+    7.04662681e-01f32, 4.11109418e-01f32, 4.18023586e-01f32, -1.56570166e-01f32, -5.55392429e-02f32, -1.30974963e-01f32, -1.09444529e-01f32, 2.26738200e-01f32,
+    8.76157701e-01f32, 9.25127506e-01f32, -2.91133255e-01f32, 1.41119566e-02f32, 1.59183234e-01f32, -5.58613300e-01f32, -3.66399080e-01f32, -2.29373842e-01f32,
+    -7.54753530e-01f32, -4.61346135e-02f32, 7.99872041e-01f32, 1.94666481e+00f32, 2.02346253e+00f32, -9.93623853e-01f32, -5.85088171e-02f32, -9.25660849e-01f32,
+    1.53311384e+00f32, 1.97824955e+00f32, -6.26510561e-01f32, 2.04220459e-01f32, -5.28548181e-01f32, -2.64796710e+00f32, -1.28715086e+00f32, -3.83590460e-01f32,
+    -4.63008344e-01f32, -6.39375210e-01f32, 8.37616801e-01f32, 6.92167401e-01f32, 1.05373055e-01f32, 5.53508043e-01f32, -2.52500419e-02f32, -2.96593785e-01f32,
+    5.82891963e-02f32, -2.04360247e-01f32, -4.12827991e-02f32, 4.99561697e-01f32, -6.70119882e-01f32, -3.05801392e-01f32, 5.49752474e-01f32, 5.96209824e-01f32,
+    -7.05729008e-01f32, 1.18337013e-01f32, -6.25943482e-01f32, -3.32530856e-01f32, -3.62029582e-01f32, -3.69963348e-01f32, 4.16798383e-01f32, 5.94144985e-02f32,
+    3.40295196e-01f32, 1.83903873e-01f32, 4.14262533e-01f32, 2.95544535e-01f32, 1.74402535e-01f32, 3.55726600e-01f32, -6.68666959e-02f32, -8.31829607e-01f32,
+];
+
+pub const AGGRESSIVE_B1: [f32; 16] = [
+    -1.49516776e-01f32, -8.23210739e-03f32, -5.79805821e-02f32, -1.30031562e+00f32, -9.12270546e-01f32, 1.81269914e-01f32, 1.23306001e-02f32, 1.51290917e+00f32,
+    -7.82839894e-01f32, -1.42281079e+00f32, 9.44850326e-01f32, 1.96954429e-01f32, 7.19298869e-02f32, 3.06737804e+00f32, 1.72887051e+00f32, -1.55103326e-01f32,
+];
+
+pub const AGGRESSIVE_W2: [f32; 16] = [
+    -3.94365400e-01f32, 4.59155254e-02f32, 2.03102738e-01f32, 2.21360111e+00f32, 1.75072324e+00f32, -6.03492618e-01f32, 2.94028103e-01f32, -1.87188649e+00f32,
+    1.57373500e+00f32, 2.27546263e+00f32, -1.11240637e+00f32, -5.17692156e-02f32, -3.04212451e-01f32, -3.77649570e+00f32, -1.95514905e+00f32, 4.93989468e-01f32,
+];
+
+pub const AGGRESSIVE_B2: f32 = -1.50487030e+00f32;
+
+// Generated by `noxroute train` on profiles/recall.txt (seed 1, 40 epochs, lr 0.01)
+pub const RECALL_HIDDEN: usize = 16;
+
+pub const RECALL_W1: [f32; 128] = [
+    7.76837587e-01f32, -3.46968025e-01f32, -6.59655809e-01f32, 4.53364253e-01f32, 4.77037072e-01f32, 1.69410303e-01f32, -4.94878829e-01f32, -1.21892311e-01f32,
+    -1.16695933e-01f32, 1.12423515e+00f32, -1.67417005e-02f32, -2.97358274e-01f32, -6.21770263e-01f32, -4.80666280e-01f32, 1.19619906e-01f32, 2.18424499e-01f32,
+    -2.53706366e-01f32, -3.65655986e-03f32, -5.34059167e-01f32, 7.59345651e-01f32, -7.75470436e-01f32, 7.56612062e-01f32, 6.51361287e-01f32, -1.38605639e-01f32,
+    2.27418125e-01f32, 3.02242965e-01f32, -1.27463311e-01f32, 2.30404526e-01f32, -3.59866880e-02f32, 4.13386762e-01f32, 6.48913264e-01f32, 4.87446785e-01f32,
+    3.23152959e-01f32, -6.02035284e-01f32, -2.02161089e-01f32, -3.90714467e-01f32, -4.40544605e-01f32, 6.14812791e-01f32, -4.96070266e-01f32, 4.29584175e-01f32,
+    -7.98460126e-01f32, -6.21249139e-01f32, -2.40987092e-01f32, 4.55580026e-01f32, -7.36576736e-01f32, 7.38008082e-01f32, 1.92685090e-02f32, -6.46684587e-01f32,
+    3.92898381e-01f32, 1.22756720e-01f32, 7.08181083e-01f32, -7.33727157e-01f32, 3.30791980e-01f32, -3.37896913e-01f32, 3.86464119e-01f32, 6.52121007e-01f32,
+    -4.29146469e-01f32, 1.05470657e+00f32, -7.84522742e-02f32, 1.40864640e-01f32, -1.27332523e-01f32, -4.66999114e-01f32, -8.68004203e-01f32, -6.94795549e-01f32,
+    7.75458455e-01f32, 4.12640333e-01f32, 4.07098353e-01f32, -1.33467272e-01f32, -2.46043921e-01f32, 8.07519928e-02f32, -8.86518881e-02f32, 2.67914504e-01f32,
+    7.00916708e-01f32, 1.19854701e+00f32, -1.84131742e-01f32, 2.23483533e-01f32, 9.25146118e-02f32, -8.62723410e-01f32, -4.46622789e-01f32, -1.94361612e-01f32,
+    -5.46117127e-01f32, -3.57045829e-02f32, 7.86625445e-01f32, 1.70640182e+00f32, 7.65503824e-01f32, -3.17022353e-01f32, 2.61231601e-01f32, -6.94607675e-01f32,
+    7.17159629e-01f32, 3.07360339e+00f32, 1.20225586e-02f32, 1.28419316e+00f32, -8.58702064e-01f32, -3.89133596e+00f32, -2.27822042e+00f32, -1.90434068e-01f32,
+    -3.42096478e-01f32, -6.38108909e-01f32, 7.80462325e-01f32, 5.45443296e-01f32, -2.96904832e-01f32, 8.62342358e-01f32, 3.29926610e-02f32, -3.18074375e-01f32,
+    -7.92205036e-02f32, -1.27052993e-01f32, -1.05807051e-01f32, 5.52187979e-01f32, -6.47902250e-01f32, -3.55723917e-01f32, 4.37382102e-01f32, 6.53539240e-01f32,
+    -5.99657118e-01f32, 1.20922409e-01f32, -6.74705386e-01f32, -2.52889097e-01f32, -6.23581886e-01f32, -1.15368910e-01f32, 4.85148609e-01f32, -9.83142927e-02f32,
+    3.32234979e-01f32, 5.49486160e-01f32, 2.70878613e-01f32, 3.76057476e-01f32, 1.80577099e-01f32, -6.11645766e-02f32, -3.68406296e-01f32, -7.70039439e-01f32,
+];
+
+pub const RECALL_B1: [f32; 16] = [
+    -1.48409337e-01f32, 7.95427803e-03f32, -9.37435776e-02f32, 1.04588330e+00f32, 4.66932692e-02f32, 5.40418290e-02f32, 1.13322496e-01f32, -8.25975686e-02f32,
+    4.75594938e-01f32, 2.09862638e+00f32, -1.51017919e-01f32, 5.84664941e-01f32, 5.76389581e-02f32, -1.18445206e+00f32, -5.95798254e-01f32, -5.33905476e-02f32,
+];
+
+pub const RECALL_W2: [f32; 16] = [
+    -8.34016502e-02f32, 1.29940644e-01f32, -9.17110741e-02f32, 1.76741874e+00f32, 5.25189459e-01f32, -1.69709519e-01f32, 3.21412623e-01f32, -6.01629198e-01f32,
+    7.05719054e-01f32, 3.87899065e+00f32, -4.60273504e-01f32, 1.25427902e+00f32, -6.55134976e-01f32, -3.96831036e+00f32, -2.31853175e+00f32, 5.27728260e-01f32,
+];
+
+pub const RECALL_B2: f32 = 9.34581995e-01f32;
@@ -0,0 +1,531 @@
+use std::collections::HashSet;
+
+use crate::cache::RouteCache;
+
+/// How context chunks are scored against the query before routing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Scorer {
+    /// The share of the query's words found in the chunk.
+    #[default]
+    Overlap,
+    /// Query words found in the chunk, each weighted by its inverse
+    /// document frequency across the chunks and its count divided by the
+    /// chunk's length. Words in every chunk count for nothing.
+    TfIdf,
+    /// Okapi BM25 over the chunks: `k1` sets how fast repeats of a word stop
+    /// adding, `b` how much long chunks are penalised.
+    Bm25 { k1: f32, b: f32 },
+}
+
+impl Scorer {
+    /// BM25 with the usual `k1 = 1.2`, `b = 0.75`.
+    pub const BM25: Scorer = Scorer::Bm25 { k1: 1.2, b: 0.75 };
+
+    /// `overlap`, `tfidf` (or `tf-idf`), or `bm25` with default parameters.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "overlap" => Some(Scorer::Overlap),
+            "tfidf" | "tf-idf" => Some(Scorer::TfIdf),
+            "bm25" => Some(Scorer::BM25),
+            _ => None,
+        }
+    }
+
+    /// The name [`from_name`](Self::from_name) takes for this kind of
+    /// scorer.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scorer::Overlap => "overlap",
+            Scorer::TfIdf => "tfidf",
+            Scorer::Bm25 { .. } => "bm25",
+        }
+    }
+
+    /// Scores each chunk against `query`, with words as `words` makes them.
+    /// TF-IDF and BM25 scores are scaled so the best chunk gets 1, the range
+    /// the router was trained on.
+    pub fn score(&self, query: &str, chunks: &[String], words: &TokenOptions) -> Vec<f32> {
+        self.score_cached(query, chunks, words, &mut RouteCache::default())
+    }
+
+    /// Like [`score`](Self::score), taking the chunks' words from `cache`
+    /// when it has them.
+    pub fn score_cached(
+        &self,
+        query: &str,
+        chunks: &[String],
+        words: &TokenOptions,
+        cache: &mut RouteCache,
+    ) -> Vec<f32> {
+        let terms = token_set(query, words);
+        let (k1, b) = match *self {
+            Scorer::Overlap => {
+                return chunks
+                    .iter()
+                    .map(|c| {
+                        let c = cache.words(c, words);
+                        let c: HashSet<&str> = c.iter().map(String::as_str).collect();
+                        overlap(&terms, &c)
+                    })
+                    .collect();
+            }
+            Scorer::TfIdf => (None, 0.0),
+            Scorer::Bm25 { k1, b } => (Some(k1), b),
+        };
+        let (docs, df) = cache.corpus(chunks, words);
+        let n = docs.len() as f32;
+        let avg_len = docs.iter().map(|doc| doc.len()).sum::<usize>() as f32 / n.max(1.0);
+
+        let mut scores: Vec<f32> = docs
+            .iter()
+            .map(|doc| {
+                let len = doc.len() as f32;
+                let mut score = 0.0_f32;
+                for term in &terms {
+                    let tf = doc.iter().filter(|t| *t == term).count() as f32;
+                    let Some(&df) = df.get(term.as_str()).filter(|_| tf > 0.0) else {
+                        continue;
+                    };
+                    score += match k1 {
+                        None => (n / df).ln() * tf / len,
+                        Some(k1) => {
+                            let idf = (1.0 + (n - df + 0.5) / (df + 0.5)).ln();
+                            let norm = 1.0 - b + b * len / avg_len.max(f32::EPSILON);
+                            idf * tf * (k1 + 1.0) / (tf + k1 * norm)
+                        }
+                    };
+                }
+                score
+            })
+            .collect();
+        let best = scores.iter().copied().fold(0.0_f32, f32::max);
+        if best > 0.0 {
+            for score in &mut scores {
+                *score /= best;
+            }
+        }
+        scores
+    }
+}
+
+/// Which words [`tokens`] keeps and how it spells them. Both can hurt when
+/// the chunks are code, so each is separate.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TokenOptions {
+    /// Drops common English words such as "the" and "is", unless that
+    /// would leave nothing.
+    pub stopwords: bool,
+    /// Strips a trailing "ing", "ed", "es", "s", or "e" when at least three
+    /// letters remain (four for "e"), so "configure" and "configuring"
+    /// match.
+    pub stem: bool,
+}
+
+impl TokenOptions {
+    /// What suits `scorer`: stopwords are dropped for overlap, which has no
+    /// other way to tell them from the words that matter.
+    pub fn for_scorer(scorer: Scorer) -> Self {
+        Self {
+            stopwords: scorer == Scorer::Overlap,
+            stem: false,
+        }
+    }
+}
+
+/// The share of `query`'s words that also appear in `chunk`, from 0 to 1.
+pub fn overlap_score(query: &str, chunk: &str, words: &TokenOptions) -> f32 {
+    let chunk = token_set(chunk, words);
+    overlap(
+        &token_set(query, words),
+        &chunk.iter().map(String::as_str).collect(),
+    )
+}
+
+fn overlap(query: &HashSet<String>, chunk: &HashSet<&str>) -> f32 {
+    if query.is_empty() || chunk.is_empty() {
+        return 0.0;
+    }
+    let common = query
+        .iter()
+        .filter(|tok| chunk.contains(tok.as_str()))
+        .count();
+    common as f32 / query.len() as f32
+}
+
+/// Each chunk's Jaccard similarity to `query` over the character
+/// trigrams of their words, each word padded with a space either side,
+/// scaled so the best chunk gets 1. A misspelt word still shares most of
+/// its trigrams with the right one, so this finds chunks that no scorer
+/// matching whole words can.
+pub(crate) fn trigram_scores(
+    query: &str,
+    chunks: &[String],
+    words: &TokenOptions,
+    cache: &mut RouteCache,
+) -> Vec<f32> {
+    let query = trigrams(&tokens(query, words));
+    let mut scores: Vec<f32> = chunks
+        .iter()
+        .map(|chunk| {
+            let chunk = trigrams(&cache.words(chunk, words));
+            let union = query.union(&chunk).count();
+            if union == 0 {
+                return 0.0;
+            }
+            query.intersection(&chunk).count() as f32 / union as f32
+        })
+        .collect();
+    let best = scores.iter().copied().fold(0.0_f32, f32::max);
+    if best > 0.0 {
+        for score in &mut scores {
+            *score /= best;
+        }
+    }
+    scores
+}
+
+fn trigrams(words: &[String]) -> HashSet<[char; 3]> {
+    let mut set = HashSet::new();
+    for word in words {
+        let padded: Vec<char> = std::iter::once(' ')
+            .chain(word.chars())
+            .chain(std::iter::once(' '))
+            .collect();
+        set.extend(padded.windows(3).map(|w| [w[0], w[1], w[2]]));
+    }
+    set
+}
+
+/// The lowercased words of `text`: runs of Unicode letters and digits,
+/// with Chinese and Japanese runs split into overlapping character pairs
+/// since they are written without spaces. `words` drops stopwords and
+/// stems.
+pub fn token_set(text: &str, words: &TokenOptions) -> HashSet<String> {
+    tokens(text, words).into_iter().collect()
+}
+
+/// Like [`token_set`], in order and with repeats.
+pub fn tokens(text: &str, words: &TokenOptions) -> Vec<String> {
+    #[cfg(test)]
+    TOKENIZED.with(|seen| seen.borrow_mut().push(text.to_string()));
+    let mut out = split(text);
+    if words.stopwords && out.iter().any(|t| !is_stopword(t)) {
+        out.retain(|t| !is_stopword(t));
+    }
+    if words.stem {
+        for token in &mut out {
+            stem(token);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Every text [`tokens`] has split on this thread, for tests of what
+    /// the cache saves.
+    pub(crate) static TOKENIZED: std::cell::RefCell<Vec<String>> = const {
+        std::cell::RefCell::new(Vec::new())
+    };
+}
+
+fn split(text: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut word = String::new();
+    let mut cjk: Vec<char> = Vec::new();
+    for ch in text.chars() {
+        if is_cjk(ch) {
+            flush_word(&mut word, &mut out);
+            cjk.push(ch);
+        } else if ch.is_alphanumeric() || (is_combining(ch) && !word.is_empty()) {
+            flush_cjk(&mut cjk, &mut out);
+            word.extend(ch.to_lowercase());
+        } else {
+            flush_word(&mut word, &mut out);
+            flush_cjk(&mut cjk, &mut out);
+        }
+    }
+    flush_word(&mut word, &mut out);
+    flush_cjk(&mut cjk, &mut out);
+    out
+}
+
+fn flush_word(word: &mut String, out: &mut Vec<String>) {
+    if !word.is_empty() {
+        out.push(std::mem::take(word));
+    }
+}
+
+/// Emits a CJK run as character bigrams, or one character on its own.
+fn flush_cjk(run: &mut Vec<char>, out: &mut Vec<String>) {
+    match run.len() {
+        0 => {}
+        1 => out.push(run[0].to_string()),
+        _ => out.extend(run.windows(2).map(|pair| pair.iter().collect::<String>())),
+    }
+    run.clear();
+}
+
+/// Han ideographs and kana. CJK punctuation is not, so it still ends a run.
+fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{3040}'..='\u{30FF}'     // hiragana, katakana
+        | '\u{31F0}'..='\u{31FF}'   // katakana phonetic extensions
+        | '\u{3400}'..='\u{4DBF}'   // Han extension A
+        | '\u{4E00}'..='\u{9FFF}'   // Han
+        | '\u{F900}'..='\u{FAFF}'   // Han compatibility
+        | '\u{FF66}'..='\u{FF9D}'   // half-width katakana
+        | '\u{20000}'..='\u{3134F}' // Han extensions B-G
+    ) && ch != '\u{30FB}' // katakana middle dot
+}
+
+/// Combining diacritics, so a decomposed `e` + acute stays one word.
+fn is_combining(ch: char) -> bool {
+    matches!(ch,
+        '\u{0300}'..='\u{036F}'
+        | '\u{1AB0}'..='\u{1AFF}'
+        | '\u{1DC0}'..='\u{1DFF}'
+        | '\u{20D0}'..='\u{20FF}'
+    )
+}
+
+fn is_stopword(token: &str) -> bool {
+    STOPWORDS.binary_search(&token).is_ok()
+}
+
+/// Strips the first matching suffix that leaves a stem of three or more
+/// characters, four for a lone "e" so that short words such as "note" and
+/// "care" are not folded into "not" and "car". "ss" keeps its "s", so
+/// "class" and "classes" agree.
+fn stem(token: &mut String) {
+    for suffix in ["ing", "ed", "es", "s", "e"] {
+        let Some(rest) = token.strip_suffix(suffix) else {
+            continue;
+        };
+        let shortest = if suffix == "e" { 4 } else { 3 };
+        if rest.chars().count() < shortest || (suffix == "s" && rest.ends_with('s')) {
+            continue;
+        }
+        token.truncate(rest.len());
+        return;
+    }
+}
+
+/// Common English words, sorted for binary search.
+#[rustfmt::skip]
+const STOPWORDS: &[&str] = &[
+    "a", "about", "above", "after", "again", "against", "all", "also", "am",
+    "an", "and", "any", "are", "as", "at", "be", "because", "been", "before",
+    "being", "below", "between", "both", "but", "by", "can", "could", "did",
+    "do", "does", "doing", "down", "during", "each", "few", "for", "from",
+    "further", "had", "has", "have", "having", "he", "her", "here", "hers",
+    "herself", "him", "himself", "his", "how", "i", "if", "in", "into", "is",
+    "it", "its", "itself", "just", "me", "more", "most", "my", "myself", "no",
+    "nor", "not", "now", "of", "off", "on", "once", "only", "or", "other",
+    "our", "ours", "ourselves", "out", "over", "own", "same", "she", "should",
+    "so", "some", "such", "than", "that", "the", "their", "theirs", "them",
+    "themselves", "then", "there", "these", "they", "this", "those", "through",
+    "to", "too", "under", "until", "up", "very", "was", "we", "were", "what",
+    "when", "where", "which", "while", "who", "whom", "why", "will", "with",
+    "would", "you", "your", "yours", "yourself", "yourselves",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAIN: TokenOptions = TokenOptions {
+        stopwords: false,
+        stem: false,
+    };
+
+    fn strings(chunks: &[&str]) -> Vec<String> {
+        chunks.iter().map(|c| c.to_string()).collect()
+    }
+
+    /// Index of the best score, the earliest on a tie.
+    fn best(scores: &[f32]) -> usize {
+        (0..scores.len())
+            .reduce(|a, b| if scores[b] > scores[a] { b } else { a })
+            .unwrap()
+    }
+
+    /// The first chunk shares the most query words, all of them common;
+    /// only the second is about the question.
+    const QUERY: &str = "how does photosynthesis work in the leaf";
+    const CHUNKS: [&str; 4] = [
+        "how does the work get done: the team does the work in the office, \
+         and how the work is split is how it works",
+        "photosynthesis happens in the leaf",
+        "how does the team meet? in the morning",
+        "the office does the work",
+    ];
+
+    #[test]
+    fn overlap_picks_the_wordy_chunk_and_idf_the_relevant_one() {
+        let chunks = strings(&CHUNKS);
+        assert_eq!(best(&Scorer::Overlap.score(QUERY, &chunks, &PLAIN)), 0);
+        for scorer in [Scorer::TfIdf, Scorer::BM25] {
+            let scores = scorer.score(QUERY, &chunks, &PLAIN);
+            assert_eq!(best(&scores), 1, "{scorer:?}: {scores:?}");
+            assert_eq!(scores[1], 1.0, "{scorer:?}: the best is scaled to 1");
+            assert!(scores.iter().all(|s| (0.0..=1.0).contains(s)));
+        }
+    }
+
+    #[test]
+    fn a_word_in_every_chunk_counts_for_nothing_in_tfidf() {
+        let chunks = strings(&CHUNKS);
+        assert_eq!(Scorer::TfIdf.score("the", &chunks, &PLAIN), [0.0; 4]);
+        let overlap = Scorer::Overlap.score("the", &chunks, &PLAIN);
+        assert_eq!(overlap, [1.0; 4]);
+    }
+
+    #[test]
+    fn bm25_b_sets_the_length_penalty() {
+        let chunks = strings(&["leaf", "leaf and a great many other words besides"]);
+        let scores = Scorer::BM25.score("leaf", &chunks, &PLAIN);
+        assert!(scores[0] > scores[1], "{scores:?}");
+        let flat = Scorer::Bm25 { k1: 1.2, b: 0.0 };
+        assert_eq!(flat.score("leaf", &chunks, &PLAIN), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn bm25_k1_sets_how_fast_repeats_stop_adding() {
+        let chunks = strings(&["leaf leaf leaf leaf", "leaf stem root bark"]);
+        let saturated = Scorer::Bm25 { k1: 0.01, b: 0.0 }.score("leaf", &chunks, &PLAIN);
+        assert!(saturated[1] > 0.99, "{saturated:?}");
+        let linear = Scorer::Bm25 { k1: 100.0, b: 0.0 }.score("leaf", &chunks, &PLAIN);
+        assert!(linear[1] < 0.3, "{linear:?}");
+    }
+
+    #[test]
+    fn scorer_names_round_trip() {
+        for scorer in [Scorer::Overlap, Scorer::TfIdf, Scorer::BM25] {
+            assert_eq!(Scorer::from_name(scorer.name()), Some(scorer));
+        }
+        assert_eq!(Scorer::from_name(" TF-IDF "), Some(Scorer::TfIdf));
+        assert_eq!(Scorer::from_name("cosine"), None);
+    }
+
+    fn words(text: &str) -> Vec<String> {
+        tokens(text, &PLAIN)
+    }
+
+    #[test]
+    fn accented_words_stay_whole_and_lowercase() {
+        assert_eq!(words("Élève, ÉTÉ à Noël!"), ["élève", "été", "à", "noël"]);
+        // A decomposed e + combining acute is one word, not two.
+        assert_eq!(words("cafe\u{301} noir"), ["cafe\u{301}", "noir"]);
+        let score = overlap_score(
+            "où est la bibliothèque",
+            "La bibliothèque est fermée",
+            &PLAIN,
+        );
+        assert_eq!(score, 0.75);
+    }
+
+    #[test]
+    fn cjk_runs_become_bigrams() {
+        assert_eq!(words("机器学习"), ["机器", "器学", "学习"]);
+        assert_eq!(words("猫"), ["猫"]);
+        // CJK punctuation ends a run.
+        assert_eq!(words("学习。模型"), ["学习", "模型"]);
+        assert_eq!(
+            words("カタカナ・テスト"),
+            ["カタ", "タカ", "カナ", "テス", "スト"]
+        );
+        let query = "什么是机器学习";
+        // Three of the query's six bigrams.
+        assert_eq!(
+            overlap_score(query, "机器学习是人工智能的一个分支", &PLAIN),
+            0.5
+        );
+        assert_eq!(overlap_score(query, "今天天气很好", &PLAIN), 0.0);
+    }
+
+    #[test]
+    fn mixed_script_text_splits_at_each_change() {
+        assert_eq!(
+            words("GPU加速 on Москва-2024"),
+            ["gpu", "加速", "on", "москва", "2024"]
+        );
+        let score = overlap_score("Москва GPU", "в Москве нет GPU, только москва", &PLAIN);
+        assert_eq!(score, 1.0);
+        assert_eq!(overlap_score("日本語", "English only", &PLAIN), 0.0);
+    }
+
+    const STEM: TokenOptions = TokenOptions {
+        stopwords: false,
+        stem: true,
+    };
+
+    #[test]
+    fn stemming_folds_inflections_together() {
+        let stems = tokens("configure configuring configured configures", &STEM);
+        assert_eq!(stems, ["configur"; 4]);
+        let stems = tokens("tests testing tested test", &STEM);
+        assert_eq!(stems, ["test"; 4]);
+        assert_eq!(tokens("class classes", &STEM), ["class"; 2]);
+    }
+
+    #[test]
+    fn stemming_leaves_short_words_apart() {
+        // A stem needs three letters, four when only an "e" comes off, so
+        // none of these meet another word.
+        let pairs = [
+            ("the", "the"),
+            ("there", "ther"),
+            ("these", "thes"),
+            ("note", "note"),
+            ("care", "care"),
+            ("uses", "use"),
+            ("is", "is"),
+            ("sing", "sing"),
+            ("red", "red"),
+        ];
+        for (word, stemmed) in pairs {
+            assert_eq!(tokens(word, &STEM), [stemmed], "{word}");
+        }
+        assert_eq!(overlap_score("the note", "there is not a car", &STEM), 0.0);
+    }
+
+    #[test]
+    fn stopwords_and_stemming_toggle_separately() {
+        let text = "The runners are configured";
+        assert_eq!(
+            tokens(text, &PLAIN),
+            ["the", "runners", "are", "configured"]
+        );
+        assert_eq!(tokens(text, &STEM), ["the", "runner", "are", "configur"]);
+        let stop = TokenOptions {
+            stopwords: true,
+            stem: false,
+        };
+        assert_eq!(tokens(text, &stop), ["runners", "configured"]);
+        let both = TokenOptions {
+            stopwords: true,
+            stem: true,
+        };
+        assert_eq!(tokens(text, &both), ["runner", "configur"]);
+        // Nothing but stopwords is kept rather than emptied.
+        assert_eq!(tokens("what is it", &both), ["what", "is", "it"]);
+    }
+
+    #[test]
+    fn the_question_routes_to_the_configuration_chunk() {
+        let query = "what is the best way to configure the runner";
+        let chunks = strings(&[
+            "what is it? it is the way it is, and that is what it is to be",
+            "configuring the runner: set NOX_LOCAL_RUNNER to its path",
+            "the model is loaded once and kept warm",
+        ]);
+        assert_eq!(best(&Scorer::Overlap.score(query, &chunks, &PLAIN)), 0);
+        let words = TokenOptions {
+            stem: true,
+            ..TokenOptions::for_scorer(Scorer::Overlap)
+        };
+        let scores = Scorer::Overlap.score(query, &chunks, &words);
+        assert_eq!(best(&scores), 1, "{scores:?}");
+    }
+}
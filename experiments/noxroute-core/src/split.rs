@@ -0,0 +1,346 @@
+use crate::regex::Regex;
+
+/// How [`route_prompt`](crate::route_prompt) cuts a prompt into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Split {
+    /// At each occurrence of the delimiter, whitespace around it ignored.
+    #[default]
+    Delim,
+    /// At each match of the delimiter read as a [`Regex`].
+    Regex,
+    /// Before each `#` or `##` heading outside code fences, so a heading
+    /// stays with its section.
+    Markdown,
+    /// At blank lines.
+    Blank,
+}
+
+impl Split {
+    /// `delim`, `regex`, `markdown`, or `blank`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "delim" => Some(Split::Delim),
+            "regex" => Some(Split::Regex),
+            "markdown" | "md" => Some(Split::Markdown),
+            "blank" => Some(Split::Blank),
+            _ => None,
+        }
+    }
+
+    /// What goes between chunks when the prompt is put back together: the
+    /// delimiter on its own line, or a blank line when there is no literal
+    /// delimiter to reuse.
+    pub fn joiner(self, delim: &str) -> String {
+        match self {
+            Split::Delim => format!("\n{}\n", delim.trim()),
+            Split::Regex | Split::Markdown | Split::Blank => "\n\n".to_string(),
+        }
+    }
+}
+
+/// The trimmed, non-empty chunks of `text`. `None` when the delimiter is
+/// needed and empty, or not a valid pattern.
+pub fn split_chunks(text: &str, split: Split, delim: &str) -> Option<Vec<String>> {
+    let pieces: Vec<&str> = match split {
+        Split::Delim => {
+            let delim = delim.trim();
+            if delim.is_empty() {
+                return None;
+            }
+            text.split(delim).collect()
+        }
+        Split::Regex => {
+            if delim.is_empty() {
+                return None;
+            }
+            let regex = Regex::new(delim).ok()?;
+            let mut pieces = Vec::new();
+            let mut last = 0;
+            for (start, end) in regex.find_iter(text) {
+                pieces.push(&text[last..start]);
+                last = end;
+            }
+            pieces.push(&text[last..]);
+            pieces
+        }
+        Split::Markdown => split_before_headings(text),
+        Split::Blank => split_at_blank_lines(text),
+    };
+    Some(
+        pieces
+            .into_iter()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// Folds each chunk shorter than `min_chars` into the one after it, or the
+/// one before when it is last, joined by a blank line.
+pub fn merge_short(chunks: Vec<String>, min_chars: usize) -> Vec<String> {
+    if min_chars == 0 {
+        return chunks;
+    }
+    let mut merged: Vec<String> = Vec::with_capacity(chunks.len());
+    let mut carry: Option<String> = None;
+    for chunk in chunks {
+        let chunk = match carry.take() {
+            Some(short) => format!("{short}\n\n{chunk}"),
+            None => chunk,
+        };
+        if chunk.chars().count() < min_chars {
+            carry = Some(chunk);
+        } else {
+            merged.push(chunk);
+        }
+    }
+    if let Some(short) = carry {
+        match merged.last_mut() {
+            Some(last) => {
+                last.push_str("\n\n");
+                last.push_str(&short);
+            }
+            None => merged.push(short),
+        }
+    }
+    merged
+}
+
+fn split_before_headings(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    let mut fence: Option<&str> = None;
+    for line in text.split_inclusive('\n') {
+        let bare = indented(line).unwrap_or("");
+        match fence {
+            Some(open) if bare.starts_with(open) => fence = None,
+            Some(_) => {}
+            None if bare.starts_with("```") => fence = Some("```"),
+            None if bare.starts_with("~~~") => fence = Some("~~~"),
+            None if is_heading(bare) => {
+                pieces.push(&text[start..at]);
+                start = at;
+            }
+            None => {}
+        }
+        at += line.len();
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+/// `line` without up to three leading spaces; `None` for deeper indents,
+/// which Markdown reads as code.
+fn indented(line: &str) -> Option<&str> {
+    let bare = line.trim_start_matches(' ');
+    (line.len() - bare.len() <= 3).then_some(bare)
+}
+
+/// `# Title` or `## Title`; `###` and deeper stay inside their section.
+fn is_heading(line: &str) -> bool {
+    let hashes = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[hashes..];
+    (1..=2).contains(&hashes) && (rest.trim().is_empty() || rest.starts_with([' ', '\t']))
+}
+
+/// `text` cut at its first blank line, both halves trimmed. `None` when it
+/// has no blank line or either half is empty.
+pub fn first_paragraph(text: &str) -> Option<(&str, &str)> {
+    let mut at = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            let (first, rest) = (text[..at].trim(), text[at..].trim());
+            return (!first.is_empty() && !rest.is_empty()).then_some((first, rest));
+        }
+        at += line.len();
+    }
+    None
+}
+
+fn split_at_blank_lines(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            pieces.push(&text[start..at]);
+            start = at + line.len();
+        }
+        at += line.len();
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+/// Windows for text that has no chunks of its own: see [`windows`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Autochunk {
+    /// Only text longer than this many characters is cut into windows.
+    pub above: usize,
+    /// Characters per window, at most.
+    pub window: usize,
+    /// Characters each window repeats from the end of the one before.
+    pub overlap: usize,
+}
+
+impl Default for Autochunk {
+    fn default() -> Self {
+        Self {
+            above: 2000,
+            window: 1000,
+            overlap: 200,
+        }
+    }
+}
+
+/// Cuts `text` into overlapping windows of at most `window` characters,
+/// as byte ranges. Each window ends at the last paragraph break in its
+/// second half, else the last sentence end, else the last space, else
+/// mid-word; the next starts at the first sentence within `overlap`
+/// characters before that end.
+pub fn windows(text: &str, window: usize, overlap: usize) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut bytes: Vec<usize> = text.char_indices().map(|(at, _)| at).collect();
+    bytes.push(text.len());
+    let window = window.max(1);
+    let overlap = overlap.min(window / 2);
+    let n = chars.len();
+
+    let mut spans = Vec::new();
+    let mut start = 0;
+    loop {
+        while start < n && chars[start].is_whitespace() {
+            start += 1;
+        }
+        if start >= n {
+            break;
+        }
+        let hard = (start + window).min(n);
+        let end = if hard == n {
+            n
+        } else {
+            last_boundary(&chars, start + window / 2 + 1, hard).unwrap_or(hard)
+        };
+        spans.push((bytes[start], bytes[end]));
+        if end >= n {
+            break;
+        }
+        let back = end.saturating_sub(overlap).max(start + 1);
+        start = first_boundary(&chars, back, end).unwrap_or(back);
+    }
+    spans
+}
+
+/// How good a place to cut the gap before `chars[at]` is: 3 after a blank
+/// line, 2 after a sentence or line ends, 1 after a space, else 0.
+fn boundary(chars: &[char], at: usize) -> u8 {
+    let before = |back: usize| at.checked_sub(back).map(|i| chars[i]);
+    match (before(2), before(1)) {
+        (Some('\n'), Some('\n')) => 3,
+        (_, Some('\n' | '。' | '！' | '？')) => 2,
+        (Some('.' | '!' | '?' | ':' | ';'), Some(c)) if c.is_whitespace() => 2,
+        (_, Some(c)) if c.is_whitespace() => 1,
+        _ => 0,
+    }
+}
+
+/// The best cut in `lo..=hi`, latest first among equals.
+fn last_boundary(chars: &[char], lo: usize, hi: usize) -> Option<usize> {
+    (lo..=hi)
+        .rev()
+        .map(|at| (boundary(chars, at), at))
+        .filter(|&(strength, _)| strength > 0)
+        .max_by_key(|&(strength, at)| (strength, at))
+        .map(|(_, at)| at)
+}
+
+/// The earliest sentence start in `lo..hi`, else the earliest word start.
+fn first_boundary(chars: &[char], lo: usize, hi: usize) -> Option<usize> {
+    let find = |min: u8| (lo..hi).find(|&at| boundary(chars, at) >= min);
+    find(2).or_else(|| find(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKDOWN: &str = "\
+Intro before any heading.
+
+# Install
+
+Run the installer.
+### Options
+Pass `--prefix` to choose where.
+
+```sh
+# not a heading, inside a fence
+make install
+```
+
+## Configure
+Edit `nox.toml`.
+    # indented four spaces: code, not a heading
+#hashtag is not a heading either
+";
+
+    #[test]
+    fn markdown_splits_before_top_headings_and_keeps_them_attached() {
+        let chunks = split_chunks(MARKDOWN, Split::Markdown, "").unwrap();
+        assert_eq!(
+            chunks,
+            [
+                "Intro before any heading.",
+                "# Install\n\nRun the installer.\n### Options\nPass `--prefix` to choose where.\n\n\
+                 ```sh\n# not a heading, inside a fence\nmake install\n```",
+                "## Configure\nEdit `nox.toml`.\n    \
+                 # indented four spaces: code, not a heading\n#hashtag is not a heading either",
+            ]
+        );
+    }
+
+    #[test]
+    fn blank_lines_split_paragraphs() {
+        let text = "first line\nstill first\n\nsecond\n   \n\n\nthird\n\t\n";
+        assert_eq!(
+            split_chunks(text, Split::Blank, "").unwrap(),
+            ["first line\nstill first", "second", "third"]
+        );
+        // No blank line, no split.
+        assert_eq!(
+            split_chunks("one\ntwo", Split::Blank, "").unwrap(),
+            ["one\ntwo"]
+        );
+    }
+
+    #[test]
+    fn the_delimiter_splits_literally_or_as_a_pattern() {
+        let text = "q\n---\na\n----\nb\n===\nc";
+        assert_eq!(
+            split_chunks(text, Split::Delim, "---").unwrap(),
+            ["q", "a", "-\nb\n===\nc"]
+        );
+        assert_eq!(
+            split_chunks(text, Split::Regex, "\n(-|=)+\n").unwrap(),
+            ["q", "a", "b", "c"]
+        );
+        assert_eq!(split_chunks(text, Split::Delim, "  "), None);
+        assert_eq!(split_chunks(text, Split::Regex, "("), None);
+    }
+
+    #[test]
+    fn short_chunks_merge_into_a_neighbour() {
+        let chunks = |texts: &[&str]| texts.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let merged = merge_short(chunks(&["tiny", "a longer chunk", "also long enough"]), 10);
+        assert_eq!(merged, ["tiny\n\na longer chunk", "also long enough"]);
+        // The last one has nothing after it, so it joins the one before.
+        let merged = merge_short(chunks(&["a longer chunk", "end"]), 10);
+        assert_eq!(merged, ["a longer chunk\n\nend"]);
+        // Runs of short chunks fold together until they are long enough.
+        let merged = merge_short(chunks(&["a", "b", "c", "long enough"]), 10);
+        assert_eq!(merged, ["a\n\nb\n\nc\n\nlong enough"]);
+        assert_eq!(merge_short(chunks(&["a", "b"]), 0), ["a", "b"]);
+    }
+}
@@ -0,0 +1,543 @@
+//! `--batch FILE`: runs every prompt in a file and writes one JSONL result
+//! per prompt. A warm `noxlocal -serve` child is reused when the runner style
+//! allows it; other styles (and the simulator) run one process per prompt.
+//! With `--jobs N` that many workers share the queue, each with its own
+//! runner, and records are still written in input order. Lines carrying a
+//! `"messages"` history are chat items: the conversation is trimmed to the
+//! context window and the record reports how many turns were dropped.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use noxroute_core::RouteCache;
+
+use crate::constrain;
+use crate::context::{ContextBudget, Conversation, Role, Turn};
+use crate::error::NoxError;
+use crate::json::{self, Json};
+use crate::models;
+use crate::serve::ServeChild;
+use crate::signals;
+use crate::transcript::Transcript;
+use crate::{generate, route_prompt, serve_command, Config, RunStats, RunnerStyle};
+
+pub struct BatchOptions {
+    pub input: PathBuf,
+    pub out: Option<PathBuf>,
+    pub fail_fast: bool,
+    pub jobs: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchItem {
+    pub id: Json,
+    /// Empty for chat items, which are rendered once the budget is known.
+    pub prompt: String,
+    pub chat: Option<Conversation>,
+}
+
+/// Parses one input line: a `{"id":..,"prompt":..}` object, a
+/// `{"id":..,"system":..,"messages":[{"role":..,"content":..}]}` chat
+/// object, or plain text. Blank lines yield `None`; items without an id get
+/// their line number.
+pub fn parse_line(line: &str, lineno: usize) -> Result<Option<BatchItem>, String> {
+    let trimmed = line.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    if !trimmed.starts_with('{') {
+        return Ok(Some(BatchItem {
+            id: Json::from(lineno),
+            prompt: trimmed.to_string(),
+            chat: None,
+        }));
+    }
+    let value = Json::parse(trimmed).map_err(|err| format!("line {lineno}: {err}"))?;
+    let id = value
+        .get("id")
+        .filter(|id| !id.is_null())
+        .cloned()
+        .unwrap_or_else(|| Json::from(lineno));
+    if let Some(messages) = value.get("messages") {
+        let chat = parse_chat(&value, messages).map_err(|err| format!("line {lineno}: {err}"))?;
+        return Ok(Some(BatchItem {
+            id,
+            prompt: String::new(),
+            chat: Some(chat),
+        }));
+    }
+    let prompt = value
+        .get("prompt")
+        .and_then(Json::as_str)
+        .ok_or_else(|| format!("line {lineno}: missing string field \"prompt\""))?;
+    Ok(Some(BatchItem {
+        id,
+        prompt: prompt.to_string(),
+        chat: None,
+    }))
+}
+
+/// A `"system"` string or leading `system` message, then user/assistant
+/// turns ending in a user message.
+fn parse_chat(value: &Json, messages: &Json) -> Result<Conversation, String> {
+    let messages = messages.as_array().ok_or("\"messages\" must be an array")?;
+    let mut convo = Conversation {
+        system: value
+            .get("system")
+            .and_then(Json::as_str)
+            .map(str::to_string),
+        turns: Vec::new(),
+    };
+    for (i, message) in messages.iter().enumerate() {
+        let role = message.get("role").and_then(Json::as_str).unwrap_or("");
+        let content = message
+            .get("content")
+            .and_then(Json::as_str)
+            .ok_or_else(|| format!("message {i}: missing string field \"content\""))?;
+        if role == "system" && i == 0 && convo.system.is_none() {
+            convo.system = Some(content.to_string());
+            continue;
+        }
+        let role = Role::parse(role).ok_or_else(|| {
+            format!("message {i}: role must be \"user\" or \"assistant\", got `{role}`")
+        })?;
+        convo.turns.push(Turn::new(role, content));
+    }
+    match convo.turns.last() {
+        Some(turn) if turn.role == Role::User => Ok(convo),
+        _ => Err("\"messages\" must end with a user message".to_string()),
+    }
+}
+
+/// Runs prompts through one warm serve child, or a fresh process each.
+/// Serve mode needs `cfg.persist_rs` so responses are framed.
+pub enum Executor {
+    Serve(Option<ServeChild>),
+    OneShot,
+}
+
+impl Executor {
+    pub fn for_config(cfg: &Config) -> Self {
+        if matches!(cfg.runner_style, RunnerStyle::NoxLocal) && !cfg.emulate_a1000 {
+            Executor::Serve(None)
+        } else {
+            Executor::OneShot
+        }
+    }
+
+    fn execute(&mut self, cfg: &Config, prompt: &str) -> (Vec<u8>, Result<RunStats, NoxError>) {
+        let mut output = Vec::new();
+        let result = self.stream(cfg, prompt, &mut output);
+        (output, result)
+    }
+
+    /// Runs `prompt`, streaming the response into `out`.
+    pub fn stream(
+        &mut self,
+        cfg: &Config,
+        prompt: &str,
+        out: &mut dyn Write,
+    ) -> Result<RunStats, NoxError> {
+        match self {
+            Executor::OneShot => generate(cfg, prompt, out),
+            Executor::Serve(slot) => {
+                let result = Self::serve_one(slot, cfg, prompt, out);
+                if result.is_err() {
+                    // A failed exchange leaves the child in an unknown state;
+                    // the next prompt gets a fresh one.
+                    if let Some(child) = slot.take() {
+                        child.kill();
+                    }
+                }
+                result
+            }
+        }
+    }
+
+    fn serve_one(
+        slot: &mut Option<ServeChild>,
+        cfg: &Config,
+        prompt: &str,
+        out: &mut dyn Write,
+    ) -> Result<RunStats, NoxError> {
+        if slot.is_none() {
+            let runner = cfg.resolve_runner()?;
+            let model = cfg.resolve_model()?;
+            let cmd = serve_command(cfg, &runner, model.as_deref());
+            let child = ServeChild::spawn(cmd).map_err(|source| {
+                cfg.resource_limits
+                    .explain(NoxError::Spawn { runner, source })
+            })?;
+            *slot = Some(child);
+        }
+        let child = slot.as_mut().expect("serve child spawned above");
+        let exchange = child
+            .request(prompt, out, cfg.limits().stall)
+            .map_err(|err| cfg.resource_limits.explain(err))?;
+        Ok(RunStats {
+            ttft: exchange.ttft,
+            duration: exchange.duration,
+            tokens: None,
+            prefill: None,
+        })
+    }
+
+    pub fn finish(self) {
+        if let Executor::Serve(Some(child)) = self {
+            child.close(Duration::from_secs(10));
+        }
+    }
+}
+
+pub fn run(cfg: &Config, opts: &BatchOptions) -> Result<(), NoxError> {
+    let text = fs::read_to_string(&opts.input).map_err(|err| {
+        NoxError::Usage(format!(
+            "cannot read batch file {}: {err}",
+            opts.input.display()
+        ))
+    })?;
+    let mut items = Vec::new();
+    for (idx, line) in text.lines().enumerate() {
+        if let Some(item) = parse_line(line, idx + 1).map_err(NoxError::Usage)? {
+            items.push(item);
+        }
+    }
+
+    let mut sink: Box<dyn Write> = match &opts.out {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    // Batch output is captured verbatim, so never let the runner add prefixes.
+    let mut cfg = cfg.clone();
+    cfg.raw = true;
+    // Serve mode frames each response with RECORD_SEP.
+    cfg.persist_rs = true;
+    let jobs = opts.jobs.clamp(1, items.len().max(1));
+    if jobs > 1 && cfg.threads.is_none() {
+        // Concurrent runners split the cores instead of each claiming all.
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        cfg.threads = Some((cores / jobs).max(1) as u32);
+    }
+    signals::install();
+
+    let total = items.len();
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let mut writer = Writer {
+        sink: &mut sink,
+        log: Transcript::open(cfg.log_file.as_deref()),
+        items: &items,
+        failed: 0,
+        first_error: None,
+    };
+    let (tx, rx) = mpsc::channel::<Done>();
+    thread::scope(|scope| -> Result<(), NoxError> {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (cfg, items, next, stop) = (&cfg, &items, &next, &stop);
+            scope.spawn(move || worker(cfg, items, next, stop, opts.fail_fast, tx));
+        }
+        drop(tx);
+        // Completions arrive in any order; records go out in input order.
+        let mut pending = BTreeMap::new();
+        let mut written = 0;
+        for done in rx {
+            pending.insert(done.idx, done);
+            while let Some(done) = pending.remove(&written) {
+                writer.write(done, opts.fail_fast)?;
+                written += 1;
+            }
+        }
+        // A stopped batch can leave gaps; keep whatever did finish.
+        for done in pending.into_values() {
+            writer.write(done, opts.fail_fast)?;
+        }
+        Ok(())
+    })?;
+    if let Some(err) = writer.first_error {
+        return Err(err);
+    }
+    let failed = writer.failed;
+    eprintln!("nox: batch done: {} ok, {} failed", total - failed, failed);
+    Ok(())
+}
+
+/// One finished prompt, sent from a worker to the writer.
+struct Done {
+    idx: usize,
+    prompt: String,
+    output: Vec<u8>,
+    result: Result<RunStats, NoxError>,
+    valid_json: Option<bool>,
+    dropped_turns: Option<usize>,
+}
+
+/// Pulls prompts off the shared queue with its own runner until the queue
+/// is empty or the batch is stopped.
+fn worker(
+    cfg: &Config,
+    items: &[BatchItem],
+    next: &AtomicUsize,
+    stop: &AtomicBool,
+    fail_fast: bool,
+    tx: mpsc::Sender<Done>,
+) {
+    let mut executor = Executor::for_config(cfg);
+    // Batch prompts often share their context; keep its words across them.
+    let mut cache = RouteCache::default();
+    while !stop.load(Ordering::SeqCst) && signals::interrupts() == 0 {
+        let idx = next.fetch_add(1, Ordering::SeqCst);
+        let Some(item) = items.get(idx) else { break };
+        eprintln!("nox: {}/{}", idx + 1, items.len());
+        let done = run_item(&mut executor, &mut cache, cfg, idx, item);
+        if let Err(err) = &done.result {
+            if fail_fast || matches!(err, NoxError::Interrupted) {
+                stop.store(true, Ordering::SeqCst);
+            }
+        }
+        if tx.send(done).is_err() {
+            break;
+        }
+    }
+    executor.finish();
+}
+
+fn run_item(
+    executor: &mut Executor,
+    cache: &mut RouteCache,
+    cfg: &Config,
+    idx: usize,
+    item: &BatchItem,
+) -> Done {
+    let mut prompt = item.prompt.clone();
+    let mut dropped_turns = None;
+    if let Some(chat) = &item.chat {
+        let fitted = ContextBudget::new(cfg).fit(chat);
+        prompt = fitted.render();
+        dropped_turns = Some(fitted.dropped);
+    } else if cfg.route_enabled {
+        if let Some(routed) = route_prompt(cfg, &prompt, cache) {
+            prompt = routed.prompt;
+        }
+    }
+    let (output, result, valid_json) = if cfg.json_schema_file.is_some() {
+        let mut output = Vec::new();
+        let checked = constrain::generate_json(cfg, &prompt, &mut output, &mut |p, out| {
+            let (bytes, result) = executor.execute(cfg, p);
+            out.write_all(&bytes)?;
+            result
+        });
+        match checked {
+            Ok((stats, valid)) => (output, Ok(stats), Some(valid)),
+            Err(err) => (output, Err(err), Some(false)),
+        }
+    } else {
+        let (output, result) = executor.execute(cfg, &prompt);
+        (output, result, None)
+    };
+    Done {
+        idx,
+        prompt,
+        output,
+        result,
+        valid_json,
+        dropped_turns,
+    }
+}
+
+/// Writes result records and the transcript, tracking failures.
+struct Writer<'a> {
+    sink: &'a mut dyn Write,
+    log: Transcript,
+    items: &'a [BatchItem],
+    failed: usize,
+    first_error: Option<NoxError>,
+}
+
+impl Writer<'_> {
+    fn write(&mut self, done: Done, fail_fast: bool) -> Result<(), NoxError> {
+        let item = &self.items[done.idx];
+        self.log.begin(&done.prompt);
+        self.log.output(&done.output);
+        self.log.finish(&done.result);
+        let output = String::from_utf8_lossy(&done.output).into_owned();
+        let mut record = match &done.result {
+            Ok(stats) => json::obj([
+                ("id", item.id.clone()),
+                ("output", Json::from(output)),
+                (
+                    "ttft_ms",
+                    Json::from(stats.ttft.map(|d| d.as_millis() as u64)),
+                ),
+                ("duration_ms", Json::from(stats.duration.as_millis() as u64)),
+                ("error", Json::Null),
+            ]),
+            Err(err) => json::obj([
+                ("id", item.id.clone()),
+                ("output", Json::from(output)),
+                ("ttft_ms", Json::Null),
+                ("duration_ms", Json::Null),
+                ("error", Json::from(err.to_string())),
+            ]),
+        };
+        if let (Some(tps), Json::Obj(fields)) = (
+            done.result.as_ref().ok().and_then(RunStats::tps),
+            &mut record,
+        ) {
+            fields.push(("tokens_per_sec".to_string(), Json::from(tps)));
+        }
+        if let (Some(valid), Json::Obj(fields)) = (done.valid_json, &mut record) {
+            fields.push(("valid_json".to_string(), Json::from(valid)));
+        }
+        if let (Some(dropped), Json::Obj(fields)) = (done.dropped_turns, &mut record) {
+            fields.push(("dropped_turns".to_string(), Json::from(dropped)));
+        }
+        if let (Some(checked), Json::Obj(fields)) = (
+            done.result
+                .as_ref()
+                .err()
+                .and_then(NoxError::model_candidates),
+            &mut record,
+        ) {
+            fields.push((
+                "model_candidates".to_string(),
+                models::checked_json(checked),
+            ));
+        }
+        writeln!(self.sink, "{record}")?;
+        self.sink.flush()?;
+        if let Err(err) = done.result {
+            if matches!(err, NoxError::Interrupted) || fail_fast {
+                self.first_error.get_or_insert(err);
+            } else {
+                self.failed += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPTS: &str = "plain text prompt\n\
+                           {\"id\":\"b\",\"prompt\":\"an object prompt\"}\n\
+                           \n\
+                           {\"id\":7,\"prompt\":\"a numbered prompt\"}\n";
+
+    /// Runs `PROMPTS` through the simulator, returning whether the batch
+    /// succeeded and the records it wrote.
+    fn run_batch(name: &str, extra: &[(&str, &str)], fail_fast: bool) -> (bool, Vec<Json>) {
+        let dir = std::env::temp_dir().join(format!("nox-batch-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("prompts.jsonl");
+        let out = dir.join("results.jsonl");
+        fs::write(&input, PROMPTS).unwrap();
+        let mut values = vec![
+            ("NOX_EMULATE_A1000", "1"),
+            ("NOX_SIM_TTFT_MS", "0"),
+            ("NOX_SIM_TPS", "100000"),
+        ];
+        values.extend_from_slice(extra);
+        let cfg = crate::tests::config(&values);
+        let opts = BatchOptions {
+            input,
+            out: Some(out.clone()),
+            fail_fast,
+            jobs: 1,
+        };
+        let ok = run(&cfg, &opts).is_ok();
+        let records = fs::read_to_string(&out)
+            .unwrap()
+            .lines()
+            .map(|line| Json::parse(line).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        (ok, records)
+    }
+
+    fn keys(record: &Json) -> Vec<&str> {
+        match record {
+            Json::Obj(fields) => fields.iter().map(|(key, _)| key.as_str()).collect(),
+            other => panic!("not an object: {other}"),
+        }
+    }
+
+    #[test]
+    fn lines_parse_as_text_or_objects() {
+        let text = parse_line("  hello  ", 3).unwrap().unwrap();
+        assert_eq!(
+            (text.id, text.prompt.as_str()),
+            (Json::from(3usize), "hello")
+        );
+        let object = parse_line("{\"id\":\"x\",\"prompt\":\"hi\"}", 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!((object.id, object.prompt.as_str()), (Json::from("x"), "hi"));
+        let unnamed = parse_line("{\"prompt\":\"hi\"}", 4).unwrap().unwrap();
+        assert_eq!(unnamed.id, Json::from(4usize));
+        assert_eq!(parse_line("   ", 2).unwrap(), None);
+        assert!(parse_line("{\"id\":1}", 5).unwrap_err().contains("line 5"));
+    }
+
+    #[test]
+    fn three_prompts_give_three_records_in_order() {
+        let (ok, records) = run_batch("schema", &[], false);
+        assert!(ok);
+        assert_eq!(records.len(), 3);
+        let ids: Vec<Json> = records
+            .iter()
+            .map(|r| r.get("id").unwrap().clone())
+            .collect();
+        assert_eq!(
+            ids,
+            [Json::from(1usize), Json::from("b"), Json::from(7usize)]
+        );
+        for record in &records {
+            assert_eq!(
+                keys(record)[..5],
+                ["id", "output", "ttft_ms", "duration_ms", "error"]
+            );
+            assert!(!record
+                .get("output")
+                .and_then(Json::as_str)
+                .unwrap()
+                .is_empty());
+            assert!(record.get("ttft_ms").and_then(Json::as_f64).is_some());
+            assert!(record.get("duration_ms").and_then(Json::as_f64).is_some());
+            assert!(record.get("error").unwrap().is_null());
+        }
+    }
+
+    #[test]
+    fn a_failed_prompt_is_recorded_and_the_batch_goes_on() {
+        let (ok, records) = run_batch("failing", &[("NOX_SIM_FAIL", "exit:3")], false);
+        assert!(ok);
+        assert_eq!(records.len(), 3);
+        for record in &records {
+            assert_eq!(
+                keys(record),
+                ["id", "output", "ttft_ms", "duration_ms", "error"]
+            );
+            assert!(record.get("ttft_ms").unwrap().is_null());
+            assert!(record.get("duration_ms").unwrap().is_null());
+            assert!(record.get("error").and_then(Json::as_str).is_some());
+        }
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_failure() {
+        let (ok, records) = run_batch("fail-fast", &[("NOX_SIM_FAIL", "exit:3")], true);
+        assert!(!ok);
+        assert_eq!(records.len(), 1);
+    }
+}
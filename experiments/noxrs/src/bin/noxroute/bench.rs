@@ -0,0 +1,167 @@
+//! `noxroute --bench N [--reps R] [--bench-explained] [--json]`: times the
+//! router over N pseudo-random values, R times (default 5). The values
+//! come from a fixed seed, so runs compare across machines and commits.
+//! Reports the min, median, and mean wall time, values per second at the
+//! median, and on Linux the peak resident set size. `--bench-explained`
+//! times [`route_values_explained`] the same way.
+
+use std::fs;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use noxroute_core::{route_values_explained, route_values_with, RouteConfig};
+
+use crate::USAGE;
+
+const BENCH_USAGE: &str = "usage: noxroute [--profile NAME] [--prob-temp T] [--threshold F] \
+                           [--min-keep N] [--max-keep N] --bench N [--reps R] \
+                           [--bench-explained] [--json]";
+
+pub fn run(args: &[String], cfg: &RouteConfig, json: bool) -> ExitCode {
+    let (n, reps, explained) = match parse_args(args) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            eprintln!("noxroute: {err}\n{BENCH_USAGE}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    let values = values(n);
+    let mut kept = 0;
+    let route = time(reps, || {
+        let result = route_values_with(&values, cfg).expect("bench values are not empty");
+        kept = result.mask.iter().filter(|m| **m).count();
+    });
+    let explain = explained.then(|| {
+        time(reps, || {
+            route_values_explained(&values, cfg).expect("bench values are not empty");
+        })
+    });
+    let rss = peak_rss_kib();
+
+    if json {
+        let explain = explain.map_or("null".to_string(), |t| t.json(n));
+        let rss = rss.map_or("null".to_string(), |kib| kib.to_string());
+        println!(
+            "{{\"n\":{n},\"reps\":{reps},\"kept\":{kept},\"route\":{},\"explained\":{explain},\"peak_rss_kib\":{rss}}}",
+            route.json(n)
+        );
+        return ExitCode::SUCCESS;
+    }
+    println!(
+        "routed {n} values ({kept} kept) over {reps} runs: {}",
+        route.human(n)
+    );
+    if let Some(explain) = explain {
+        println!("explained: {}", explain.human(n));
+    }
+    if let Some(kib) = rss {
+        println!("peak RSS: {:.1} MiB", kib as f64 / 1024.0);
+    }
+    ExitCode::SUCCESS
+}
+
+fn parse_args(args: &[String]) -> Result<(usize, usize, bool), String> {
+    let (mut n, mut reps, mut explained) = (None, 5, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut count = |name: &str| {
+            args.next()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .ok_or_else(|| format!("{name} needs a positive whole number"))
+        };
+        match arg.as_str() {
+            "--bench" => n = Some(count("--bench")?),
+            "--reps" => reps = count("--reps")?,
+            "--bench-explained" => explained = true,
+            other => return Err(format!("{other} does not go with --bench")),
+        }
+    }
+    let n = n.ok_or("--bench-explained needs --bench N")?;
+    Ok((n, reps, explained))
+}
+
+/// `n` values in [0, 1) from xorshift64 with a fixed seed.
+fn values(n: usize) -> Vec<f32> {
+    let mut state = 0x9E37_79B9_7F4A_7C15_u64;
+    (0..n)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        })
+        .collect()
+}
+
+/// Wall times of repeated runs, sorted.
+struct Times(Vec<Duration>);
+
+fn time(reps: usize, mut run: impl FnMut()) -> Times {
+    let mut times: Vec<Duration> = (0..reps)
+        .map(|_| {
+            let start = Instant::now();
+            run();
+            start.elapsed()
+        })
+        .collect();
+    times.sort_unstable();
+    Times(times)
+}
+
+impl Times {
+    fn min(&self) -> Duration {
+        self.0[0]
+    }
+
+    /// The middle time, or the mean of the middle two.
+    fn median(&self) -> Duration {
+        let len = self.0.len();
+        (self.0[(len - 1) / 2] + self.0[len / 2]) / 2
+    }
+
+    fn mean(&self) -> Duration {
+        self.0.iter().sum::<Duration>() / self.0.len() as u32
+    }
+
+    /// Values per second at the median time.
+    fn rate(&self, n: usize) -> f64 {
+        n as f64 / self.median().as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    fn human(&self, n: usize) -> String {
+        let ms = |t: Duration| t.as_secs_f64() * 1e3;
+        format!(
+            "min {:.3} ms, median {:.3} ms, mean {:.3} ms, {:.0} values/s",
+            ms(self.min()),
+            ms(self.median()),
+            ms(self.mean()),
+            self.rate(n)
+        )
+    }
+
+    fn json(&self, n: usize) -> String {
+        let us = |t: Duration| t.as_secs_f64() * 1e6;
+        format!(
+            "{{\"min_us\":{:.3},\"median_us\":{:.3},\"mean_us\":{:.3},\"values_per_sec\":{:.0}}}",
+            us(self.min()),
+            us(self.median()),
+            us(self.mean()),
+            self.rate(n)
+        )
+    }
+}
+
+/// The process's peak resident set size, from `VmHWM` in
+/// `/proc/self/status`; `None` where there is no such file.
+fn peak_rss_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
@@ -0,0 +1,118 @@
+//! `noxroute --compare [--labels FILE]`: the router's selection next to
+//! naive baselines (a 0.5 cut on the min-max scaled values, the top k by
+//! value with k what the router kept, and a median split), with how often
+//! each pair agrees. `--labels` names a file of 0/1 entries, one per value
+//! in order, separated by commas, whitespace, or newlines; with it each
+//! strategy also gets precision, recall, and F1. With `--text` the values
+//! are the chunks' scores, after near-duplicates are dropped, and the
+//! labels count those chunks.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use noxroute_core::{compare, RouteConfig};
+
+use crate::{input, NO_VALUES};
+
+/// `--compare`, and the `--labels` file if there was one.
+pub struct Compare {
+    pub labels: Option<PathBuf>,
+}
+
+/// Prints what each strategy keeps of `values`, how often they agree, and
+/// with labels how well each matches them.
+pub fn run(values: &[f32], cfg: &RouteConfig, flags: &Compare) -> ExitCode {
+    let labels = flags.labels.as_deref();
+    let labels = match labels.map(|path| read_labels(path, values.len())) {
+        Some(Ok(labels)) => Some(labels),
+        Some(Err(err)) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(NO_VALUES);
+        }
+        None => None,
+    };
+    let comparison = match compare(values, cfg) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(NO_VALUES);
+        }
+    };
+
+    println!("{:<10} {:>5}  indices", "strategy", "kept");
+    for (strategy, mask) in &comparison.masks {
+        let kept: Vec<String> = (0..mask.len())
+            .filter(|&idx| mask[idx])
+            .map(|idx| idx.to_string())
+            .collect();
+        println!(
+            "{:<10} {:>5}  {}",
+            strategy.name(),
+            kept.len(),
+            kept.join(" ")
+        );
+    }
+
+    println!();
+    let names: String = comparison
+        .masks
+        .iter()
+        .map(|(strategy, _)| format!(" {:>9}", strategy.name()))
+        .collect();
+    println!("{:<10}{names}", "agreement");
+    for (a, (strategy, _)) in comparison.masks.iter().enumerate() {
+        let cells: String = (0..comparison.masks.len())
+            .map(|b| format!(" {:>8.1}%", comparison.agreement(a, b) * 100.0))
+            .collect();
+        println!("{:<10}{cells}", strategy.name());
+    }
+
+    if let Some(labels) = labels {
+        println!();
+        println!(
+            "{:<10} {:>9} {:>9} {:>9}",
+            "labels", "precision", "recall", "f1"
+        );
+        for (strategy, m) in comparison.metrics(&labels) {
+            println!(
+                "{:<10} {:>9.3} {:>9.3} {:>9.3}",
+                strategy.name(),
+                m.precision,
+                m.recall,
+                m.f1
+            );
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// The 0/1 entries of the file at `path`, which must number `len`.
+fn read_labels(path: &Path, len: usize) -> Result<Vec<bool>, String> {
+    let text = input::read_file(path)?;
+    let mut labels = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let values = input::parse_line(line)
+            .map_err(|why| format!("{}:{}: {why}", path.display(), n + 1))?;
+        for value in values {
+            match value {
+                0.0 => labels.push(false),
+                1.0 => labels.push(true),
+                _ => {
+                    return Err(format!(
+                        "{}:{}: labels must be 0 or 1, got {value}",
+                        path.display(),
+                        n + 1
+                    ))
+                }
+            }
+        }
+    }
+    if labels.len() != len {
+        return Err(format!(
+            "{}: {} labels for {len} values",
+            path.display(),
+            labels.len()
+        ));
+    }
+    Ok(labels)
+}
@@ -0,0 +1,161 @@
+//! Where `noxroute` reads its values from besides argv and stdin:
+//! `--file PATH`, read whole and parsed like stdin; `--csv PATH --column
+//! NAME|IDX`, one numeric column of a CSV file; and the strict per-line
+//! parsing behind `--lines` and `--stream`, where each line is its own list
+//! of values.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// The whole of `path`.
+pub fn read_file(path: &Path) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// The values in one column of the CSV file at `path`, picked by header
+/// name or by index from 0. Cells are split on commas, or on semicolons
+/// when the first line has more of those (and then `0,5` is 0.5), and may
+/// be double-quoted. The first line is a header when `column` names one of
+/// its cells, or when the cell under an index is not a number. Non-numeric
+/// cells further down are skipped, with a count on stderr. The file is read
+/// a line at a time.
+pub fn csv_column(path: &Path, column: &str) -> Result<Vec<f32>, String> {
+    let at = |line: usize, why: String| format!("{}:{line}: {why}", path.display());
+    let file = File::open(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    let mut values = Vec::new();
+    // How many cells were skipped, and where the first was.
+    let mut skipped = (0, None);
+    let mut sep = None;
+    let mut header_done = false;
+    let mut index = column.trim().parse::<usize>().ok();
+
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line_no = n + 1;
+        let line = line.map_err(|err| at(line_no, err.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let sep = *sep.get_or_insert_with(|| {
+            if line.matches(';').count() > line.matches(',').count() {
+                ';'
+            } else {
+                ','
+            }
+        });
+        let cells = split_row(&line, sep).map_err(|why| at(line_no, why))?;
+        let first = !header_done;
+        header_done = true;
+        let idx = match index {
+            Some(idx) => idx,
+            None => {
+                // A name: this first line has to be the header.
+                let found = cells.iter().position(|cell| cell.trim() == column.trim());
+                let idx = found.ok_or_else(|| {
+                    at(
+                        line_no,
+                        format!("no column named `{}` in the header", column.trim()),
+                    )
+                })?;
+                index = Some(idx);
+                continue;
+            }
+        };
+        let Some(cell) = cells.get(idx) else {
+            return Err(at(
+                line_no,
+                format!(
+                    "the row has no column {idx} (counting from 0), only {}",
+                    cells.len()
+                ),
+            ));
+        };
+        // Semicolon files tend to come from locales with decimal commas.
+        let cell = match sep {
+            ';' => cell.trim().replace(',', "."),
+            _ => cell.trim().to_string(),
+        };
+        match cell.parse::<f32>() {
+            Ok(value) => values.push(value),
+            // A header over an index.
+            Err(_) if first => {}
+            Err(_) => {
+                skipped.0 += 1;
+                skipped.1.get_or_insert((line_no, idx + 1));
+            }
+        }
+    }
+    if let (count, Some((line, col))) = skipped {
+        eprintln!(
+            "noxroute: {}: skipped {count} non-numeric cells, the first at line {line}, column {col}",
+            path.display()
+        );
+    }
+    Ok(values)
+}
+
+/// The cells of one CSV row. A quoted cell may hold `sep`, and `""` for a
+/// quote.
+fn split_row(line: &str, sep: char) -> Result<Vec<String>, String> {
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().enumerate().peekable();
+    let mut opened = 0;
+    while let Some((col, ch)) = chars.next() {
+        match ch {
+            '"' if quoted => {
+                if chars.peek().is_some_and(|&(_, next)| next == '"') {
+                    cell.push('"');
+                    chars.next();
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if cell.trim().is_empty() => {
+                quoted = true;
+                opened = col + 1;
+            }
+            ch if ch == sep && !quoted => cells.push(std::mem::take(&mut cell)),
+            ch => cell.push(ch),
+        }
+    }
+    if quoted {
+        return Err(format!("column {opened}: the quote is never closed"));
+    }
+    cells.push(cell);
+    Ok(cells)
+}
+
+/// The values on `line`, separated by commas or whitespace, for
+/// `--lines`. Unlike argv and stdin, anything that is not a number is an
+/// error, reported at its 1-based column.
+pub fn parse_line(line: &str) -> Result<Vec<f32>, String> {
+    let mut values = Vec::new();
+    parse_line_into(line, &mut values)?;
+    Ok(values)
+}
+
+/// [`parse_line`] into `values`, which is cleared first, so a caller
+/// reading many lines can keep one buffer.
+pub fn parse_line_into(line: &str, values: &mut Vec<f32>) -> Result<(), String> {
+    values.clear();
+    let mut start = None;
+    for (col, ch) in line.char_indices().chain([(line.len(), ' ')]) {
+        let gap = ch == ',' || ch.is_whitespace();
+        match (start, gap) {
+            (None, false) => start = Some(col),
+            (Some(from), true) => {
+                let token = &line[from..col];
+                let value = token.parse::<f32>().map_err(|_| {
+                    let column = line[..from].chars().count() + 1;
+                    format!("column {column}: `{token}` is not a number")
+                })?;
+                values.push(value);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
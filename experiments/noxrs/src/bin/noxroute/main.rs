@@ -0,0 +1,792 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use compare::Compare;
+use noxroute_core::{
+    route_values_explained, route_values_with, MaskStrategy, Order, RouteConfig, RouteError,
+    RouteResult, RoutingModel, Weights, FEATURE_NAMES,
+};
+
+mod bench;
+mod compare;
+mod input;
+mod stream;
+mod text;
+mod train;
+
+/// Exit status when no values could be parsed or routed.
+const NO_VALUES: u8 = 1;
+/// Exit status for a bad flag or missing input.
+const USAGE: u8 = 2;
+
+/// How the routed values are printed.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Output {
+    /// The labelled dump, for people.
+    Dump,
+    /// One JSON object on one line.
+    Json,
+    /// Only the selected values, comma separated.
+    Quiet,
+}
+
+/// Where the values come from.
+enum Source {
+    /// Argv, or stdin when there are no arguments.
+    Args,
+    /// `--file PATH`.
+    File(PathBuf),
+    /// `--csv PATH --column NAME|IDX`.
+    Csv(PathBuf, String),
+}
+
+/// Exits 0 when a value was selected, [`NO_VALUES`] when none parsed, and
+/// [`USAGE`] for a usage error.
+fn main() -> ExitCode {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("train") {
+        if let Err(err) = train::run(&args[1..]) {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(NO_VALUES);
+        }
+        return ExitCode::SUCCESS;
+    }
+    let mut cfg = RouteConfig::default();
+    if let Some(at) = args.iter().position(|arg| arg == "--profile") {
+        let name = args.get(at + 1).map_or("", String::as_str);
+        match RoutingModel::builtin(name) {
+            Some(model) => cfg = model.config,
+            None => {
+                eprintln!(
+                    "noxroute: --profile must be one of {}, got `{name}`",
+                    RoutingModel::NAMES.join(", ")
+                );
+                return ExitCode::from(USAGE);
+            }
+        }
+        args.drain(at..(at + 2).min(args.len()));
+    }
+    // After the profile, so the file's weights replace the profile's.
+    if let Some(at) = args.iter().position(|arg| arg == "--weights") {
+        let Some(path) = args.get(at + 1) else {
+            eprintln!("noxroute: --weights needs a file from `noxroute train`");
+            return ExitCode::from(USAGE);
+        };
+        let weights = input::read_file(path.as_ref())
+            .and_then(|text| Weights::parse(&text).map_err(|err| format!("{path}: {err}")));
+        match weights {
+            Ok(weights) => cfg.weights = weights,
+            Err(err) => {
+                eprintln!("noxroute: --weights {err}");
+                return ExitCode::from(USAGE);
+            }
+        }
+        args.drain(at..at + 2);
+    }
+    if let Some(at) = args.iter().position(|arg| arg == "--prob-temp") {
+        match args.get(at + 1).and_then(|t| t.parse::<f32>().ok()) {
+            Some(temp) if temp > 0.0 && temp.is_finite() => cfg.prob_temperature = temp,
+            _ => {
+                eprintln!("noxroute: --prob-temp needs a positive number");
+                return ExitCode::from(USAGE);
+            }
+        }
+        args.drain(at..at + 2);
+    }
+    let top_k = match take_limits(&mut args, &mut cfg) {
+        Ok(top_k) => top_k,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    let mut order = Order::Document;
+    if let Some(at) = args.iter().position(|arg| arg == "--order") {
+        match args.get(at + 1).and_then(|name| Order::from_name(name)) {
+            Some(chosen) => order = chosen,
+            None => {
+                eprintln!("noxroute: --order must be `document` or `score`");
+                return ExitCode::from(USAGE);
+            }
+        }
+        args.drain(at..at + 2);
+    }
+    let explain = take_flag(&mut args, "--explain");
+    let json = take_flag(&mut args, "--json");
+    let quiet = take_flag(&mut args, "--quiet");
+    let output = match (json, quiet) {
+        (true, true) => {
+            eprintln!("noxroute: --json and --quiet do not go together");
+            return ExitCode::from(USAGE);
+        }
+        (true, false) => Output::Json,
+        (false, true) => Output::Quiet,
+        (false, false) => Output::Dump,
+    };
+    if explain && output != Output::Dump {
+        eprintln!("noxroute: --explain only goes with the default output");
+        return ExitCode::from(USAGE);
+    }
+    let compare = match take_compare(&mut args) {
+        Ok(compare) => compare,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    if compare.is_some() && (explain || top_k.is_some() || output != Output::Dump) {
+        eprintln!("noxroute: --explain, --top-k, --json, and --quiet do not go with --compare");
+        return ExitCode::from(USAGE);
+    }
+    let stream = take_flag(&mut args, "--stream");
+    let text_lines = take_flag(&mut args, "--text-lines");
+    if text_lines && !stream {
+        eprintln!("noxroute: --text-lines goes with --stream");
+        return ExitCode::from(USAGE);
+    }
+    if stream {
+        if explain || output == Output::Quiet || compare.is_some() {
+            eprintln!("noxroute: --explain, --quiet, and --compare do not go with --stream");
+            return ExitCode::from(USAGE);
+        }
+        let conflict = ["--text", "--lines", "--bench", "--file", "--csv"]
+            .into_iter()
+            .find(|flag| args.iter().any(|arg| arg == flag));
+        if let Some(flag) = conflict {
+            eprintln!("noxroute: {flag} does not go with --stream, which reads lines of stdin");
+            return ExitCode::from(USAGE);
+        }
+        return stream::run(&args, &cfg, top_k, order, text_lines);
+    }
+    if take_flag(&mut args, "--text") {
+        if explain || top_k.is_some() {
+            eprintln!("noxroute: --explain and --top-k do not go with --text");
+            return ExitCode::from(USAGE);
+        }
+        return text::run(&args, &cfg, order, output == Output::Json, compare.as_ref());
+    }
+    if args
+        .iter()
+        .any(|arg| arg == "--bench" || arg == "--bench-explained")
+    {
+        if explain || top_k.is_some() || output == Output::Quiet || compare.is_some() {
+            eprintln!(
+                "noxroute: --explain, --top-k, --quiet, and --compare do not go with --bench"
+            );
+            return ExitCode::from(USAGE);
+        }
+        return bench::run(&args, &cfg, output == Output::Json);
+    }
+    let lines = take_flag(&mut args, "--lines");
+    if lines && compare.is_some() {
+        eprintln!("noxroute: --compare does not go with --lines");
+        return ExitCode::from(USAGE);
+    }
+    let source = match take_source(&mut args) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    if lines {
+        let reader: Box<dyn BufRead> = match &source {
+            Source::Args if args.is_empty() => Box::new(io::stdin().lock()),
+            Source::File(path) => match File::open(path) {
+                Ok(file) => Box::new(BufReader::new(file)),
+                Err(err) => {
+                    eprintln!("noxroute: {}: {err}", path.display());
+                    return ExitCode::from(NO_VALUES);
+                }
+            },
+            _ => {
+                eprintln!("noxroute: --lines reads stdin or --file");
+                return ExitCode::from(USAGE);
+            }
+        };
+        if explain {
+            eprintln!("noxroute: --explain does not go with --lines");
+            return ExitCode::from(USAGE);
+        }
+        let name = match &source {
+            Source::File(path) => path.display().to_string(),
+            _ => "stdin".to_string(),
+        };
+        return route_lines(reader, &name, &cfg, top_k, order, output);
+    }
+    let help = args.iter().any(|v| v == "-h" || v == "--help");
+    let input = match &source {
+        Source::Args => read_input(&args).map_err(|err| format!("reading stdin: {err}")),
+        Source::File(path) => input::read_file(path),
+        Source::Csv(..) => Ok(String::new()),
+    };
+    let input = match input {
+        Ok(input) => input,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return ExitCode::from(NO_VALUES);
+        }
+    };
+    if help || (matches!(source, Source::Args) && input.trim().is_empty()) {
+        eprintln!(
+            "usage: noxroute [--profile NAME] [--weights FILE] [--prob-temp T] [--threshold F | --top-k N] [--min-keep N] [--max-keep N]"
+        );
+        eprintln!(
+            "                [--order document|score] [--explain | --json | --quiet] \"1.2,0.5,3.4,-1.0\""
+        );
+        eprintln!("       noxroute [...] --file PATH | --csv PATH --column NAME|IDX");
+        eprintln!("       noxroute [...] --lines [--file PATH]  (one list, one result per line)");
+        eprintln!(
+            "       noxroute [...] --stream [--text-lines [--scorer NAME] [--keep N] [--budget CHARS]] < lines"
+        );
+        eprintln!("           routes each non-blank line as it arrives and prints one JSON line for");
+        eprintln!("           it until EOF: the --json object for a list of values, or with");
+        eprintln!("           --text-lines the --text --json object for QUERY<TAB>CHUNK<TAB>CHUNK...;");
+        eprintln!("           a bad line gets {{\"line\":N,\"error\":\"...\"}} and the stream goes on,");
+        eprintln!("           exiting 1 at the end");
+        eprintln!("       noxroute [...] --compare [--labels FILE] [--text ...]");
+        eprintln!("           the router's selection next to a 0.5 cut on the min-max scaled values,");
+        eprintln!("           the top k (k what the router kept), and a median split, then how often");
+        eprintln!("           each pair agrees; FILE holds one 0/1 label per value, separated by");
+        eprintln!("           commas, spaces, or newlines, and adds precision, recall, and F1");
+        eprintln!("       noxroute [...] --bench N [--reps R] [--bench-explained] [--json]");
+        eprintln!(
+            "       noxroute --text --query QUERY [--delim ---] [--scorer overlap|tfidf|bm25] [--keep N]"
+        );
+        eprintln!("                [--budget CHARS] [--order document|score] [--json] < chunks");
+        eprintln!("           routes the chunks of stdin, split on the delimiter, as nox does with");
+        eprintln!("           NOX_ROUTE=1 (keep 4 and no budget by default) and prints the kept ones");
+        eprintln!("           joined by it; --json prints one line with the query, each chunk's");
+        eprintln!("           score, prob, and reason, and the kept indices in output order");
+        eprintln!(
+            "       noxroute train DATA [--weights FILE] [--out FILE.rs] [--profile NAME] [--seed N]"
+        );
+        eprintln!("                [--epochs N] [--lr X] [--hidden N] [--val F]");
+        eprintln!("           fits the weights to DATA, one route per line as values;mask");
+        eprintln!("           (e.g. 0.9,0.1,0.4;1,0,0), and writes a weights file for --weights");
+        eprintln!("           and NOX_ROUTE_WEIGHTS and/or a routing_weights.rs; with --profile,");
+        eprintln!("           only the NAME_ constants of a built-in profile");
+        eprintln!(
+            "exit status: 0 when a value was selected, 1 when no values parsed, 2 for bad usage"
+        );
+        return if help {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::from(USAGE)
+        };
+    }
+
+    let values = match &source {
+        Source::Csv(path, column) => match input::csv_column(path, column) {
+            Ok(values) => values,
+            Err(err) => {
+                eprintln!("noxroute: {err}");
+                return ExitCode::from(NO_VALUES);
+            }
+        },
+        _ => parse_values(&input),
+    };
+    if let Some(compare) = &compare {
+        return compare::run(&values, &cfg, compare);
+    }
+    if route_and_print(&values, &cfg, top_k, order, output, explain) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(NO_VALUES)
+    }
+}
+
+/// Routes `values` and prints the result as `output` says; with `top_k`,
+/// the mask is the `top_k` most probable values instead of the router's.
+/// False when nothing was selected, with the reason on stderr.
+fn route_and_print(
+    values: &[f32],
+    cfg: &RouteConfig,
+    top_k: Option<usize>,
+    order: Order,
+    output: Output,
+    explain: bool,
+) -> bool {
+    let selection = match select(values, cfg, top_k, order) {
+        Ok(selection) => selection,
+        Err(err) => {
+            eprintln!("noxroute: {err}");
+            return false;
+        }
+    };
+    let status = !selection.selected.is_empty();
+    if output == Output::Json {
+        println!("{}", selection.json(values));
+        return status;
+    }
+    let Selection {
+        result,
+        selected,
+        rest,
+    } = selection;
+    let pick = |idxs: &[usize]| idxs.iter().map(|&idx| values[idx]).collect::<Vec<f32>>();
+
+    if output == Output::Quiet {
+        let kept: Vec<String> = pick(&selected).iter().map(f32::to_string).collect();
+        println!("{}", kept.join(","));
+        return status;
+    }
+
+    println!("config: {}", describe(cfg, top_k));
+    println!("input: {:?}", values);
+    println!(
+        "probs: [{}]",
+        result
+            .probs
+            .iter()
+            .map(|v| format!("{v:.3}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!(
+        "mask:  [{}]",
+        result
+            .mask
+            .iter()
+            .map(|v| if *v { "1" } else { "0" })
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    println!("perm:  {:?}", result.perm);
+    if !result.patched.is_empty() {
+        println!("patched: {:?}", result.patched);
+    }
+    let arranged: Vec<f32> = selected
+        .iter()
+        .chain(&rest)
+        .map(|&idx| values[idx])
+        .collect();
+    println!("selected+rest: {:?}", arranged);
+    if explain {
+        print_explained(values, cfg);
+    }
+
+    status
+}
+
+/// The router's result for some values, and their indices split into the
+/// selected and the rest, each in the order asked for.
+struct Selection {
+    result: RouteResult,
+    selected: Vec<usize>,
+    rest: Vec<usize>,
+}
+
+impl Selection {
+    /// The `--json` object for `values`, on one line.
+    fn json(&self, values: &[f32]) -> String {
+        let pick = |idxs: &[usize]| idxs.iter().map(|&idx| values[idx]).collect::<Vec<f32>>();
+        let mask: Vec<String> = self.result.mask.iter().map(bool::to_string).collect();
+        let perm: Vec<String> = self.result.perm.iter().map(usize::to_string).collect();
+        format!(
+            "{{\"input\":[{}],\"probs\":[{}],\"mask\":[{}],\"perm\":[{}],\"selected\":[{}],\"rest\":[{}]}}",
+            json_numbers(values),
+            json_numbers(&self.result.probs),
+            mask.join(","),
+            perm.join(","),
+            json_numbers(&pick(&self.selected)),
+            json_numbers(&pick(&self.rest))
+        )
+    }
+}
+
+/// Routes `values`; with `top_k`, the mask is the `top_k` most probable
+/// values instead of the router's.
+fn select(
+    values: &[f32],
+    cfg: &RouteConfig,
+    top_k: Option<usize>,
+    order: Order,
+) -> Result<Selection, RouteError> {
+    let mut result = route_values_with(values, cfg)?;
+    if let Some(k) = top_k {
+        keep_most_probable(&mut result, values, k);
+    }
+    let (mut selected, mut rest): (Vec<usize>, Vec<usize>) =
+        (0..values.len()).partition(|&idx| result.mask[idx]);
+    order.sort(&mut selected, values);
+    order.sort(&mut rest, values);
+    Ok(Selection {
+        result,
+        selected,
+        rest,
+    })
+}
+
+/// `--lines`: routes each line of `reader` as its own list of values and
+/// prints one line for each, the selected values or with `--json` the
+/// object. A blank line, or one that does not parse, gets a blank line, so
+/// the output lines up with the input. Fails when any line did.
+fn route_lines(
+    reader: Box<dyn BufRead>,
+    name: &str,
+    cfg: &RouteConfig,
+    top_k: Option<usize>,
+    order: Order,
+    output: Output,
+) -> ExitCode {
+    let output = match output {
+        Output::Json => Output::Json,
+        Output::Dump | Output::Quiet => Output::Quiet,
+    };
+    let mut ok = true;
+    for (n, line) in reader.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                eprintln!("noxroute: {name}:{}: {err}", n + 1);
+                return ExitCode::from(NO_VALUES);
+            }
+        };
+        if line.trim().is_empty() {
+            println!();
+            continue;
+        }
+        match input::parse_line(&line) {
+            Ok(values) if route_and_print(&values, cfg, top_k, order, output, false) => {}
+            Ok(_) => {
+                eprintln!("noxroute: {name}:{}: nothing selected", n + 1);
+                println!();
+                ok = false;
+            }
+            Err(err) => {
+                eprintln!("noxroute: {name}:{}: {err}", n + 1);
+                println!();
+                ok = false;
+            }
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(NO_VALUES)
+    }
+}
+
+/// Takes `--threshold`, `--min-keep`, and `--max-keep` out of `args` into
+/// `cfg`, and returns `--top-k`, which stands in for all three.
+fn take_limits(args: &mut Vec<String>, cfg: &mut RouteConfig) -> Result<Option<usize>, String> {
+    let count = |flag: &str, value: Option<String>| {
+        value
+            .map(|v| v.trim().parse::<usize>())
+            .transpose()
+            .map_err(|_| format!("{flag} needs a whole number"))
+    };
+    let threshold = match take_value(args, "--threshold")? {
+        Some(v) => match v.trim().parse::<f32>() {
+            Ok(t) if (0.0..=1.0).contains(&t) => Some(t),
+            _ => return Err("--threshold needs a number from 0 to 1".to_string()),
+        },
+        None => None,
+    };
+    let top_k = count("--top-k", take_value(args, "--top-k")?)?;
+    let min_keep = count("--min-keep", take_value(args, "--min-keep")?)?;
+    let max_keep = count("--max-keep", take_value(args, "--max-keep")?)?;
+    if let Some(k) = top_k {
+        if k == 0 {
+            return Err("--top-k needs at least 1".to_string());
+        }
+        if threshold.is_some() || min_keep.is_some() || max_keep.is_some() {
+            return Err(
+                "--top-k sets how many to keep by itself, so it does not go with --threshold, --min-keep, or --max-keep"
+                    .to_string(),
+            );
+        }
+        return Ok(top_k);
+    }
+    if let Some(threshold) = threshold {
+        cfg.threshold = threshold;
+        cfg.prefer = MaskStrategy::Threshold;
+    }
+    cfg.min_keep = min_keep.unwrap_or(cfg.min_keep);
+    cfg.max_keep = max_keep.or(cfg.max_keep);
+    if let Some(max) = cfg.max_keep.filter(|max| cfg.min_keep > *max) {
+        return Err(format!(
+            "--min-keep ({}) is more than --max-keep ({max})",
+            cfg.min_keep
+        ));
+    }
+    Ok(None)
+}
+
+/// Makes the mask the `k` most probable values, ties to the higher value
+/// and then the earlier, as the router breaks them.
+fn keep_most_probable(result: &mut RouteResult, values: &[f32], k: usize) {
+    let mut ranked: Vec<usize> = (0..values.len()).collect();
+    ranked.sort_by(|&a, &b| {
+        result.probs[b]
+            .total_cmp(&result.probs[a])
+            .then_with(|| values[b].total_cmp(&values[a]))
+            .then_with(|| a.cmp(&b))
+    });
+    result.mask = vec![false; values.len()];
+    for &idx in ranked.iter().take(k) {
+        result.mask[idx] = true;
+    }
+    let (kept, dropped): (Vec<usize>, Vec<usize>) =
+        (0..values.len()).partition(|&idx| result.mask[idx]);
+    result.perm = kept.into_iter().chain(dropped).collect();
+    result.clamped.clear();
+}
+
+/// The routing settings in effect, for the header of the dump.
+fn describe(cfg: &RouteConfig, top_k: Option<usize>) -> String {
+    let temp = cfg.prob_temperature;
+    if let Some(k) = top_k {
+        return format!("top-k {k} by probability, prob_temp {temp}");
+    }
+    let prefer = match cfg.prefer {
+        MaskStrategy::Likelihood => "likelihood",
+        MaskStrategy::Threshold => "threshold",
+        MaskStrategy::TopK => "top-k-hat",
+    };
+    let max_keep = cfg
+        .max_keep
+        .map_or("none".to_string(), |max| max.to_string());
+    format!(
+        "threshold {}, prefer {prefer}, min_keep {}, max_keep {max_keep}, prob_temp {temp}",
+        cfg.threshold, cfg.min_keep
+    )
+}
+
+/// Takes `--compare`, and `--labels` which needs it, out of `args`.
+fn take_compare(args: &mut Vec<String>) -> Result<Option<Compare>, String> {
+    let compare = take_flag(args, "--compare");
+    let labels = take_value(args, "--labels")?.map(PathBuf::from);
+    match (compare, labels) {
+        (false, None) => Ok(None),
+        (false, Some(_)) => Err("--labels goes with --compare".to_string()),
+        (true, labels) => Ok(Some(Compare { labels })),
+    }
+}
+
+/// Takes `--file`, or `--csv` with `--column`, out of `args`.
+fn take_source(args: &mut Vec<String>) -> Result<Source, String> {
+    let file = take_value(args, "--file")?;
+    let csv = take_value(args, "--csv")?;
+    let column = take_value(args, "--column")?;
+    let source = match (file, csv, column) {
+        (None, None, None) => return Ok(Source::Args),
+        (Some(path), None, None) => Source::File(PathBuf::from(path)),
+        (None, Some(path), Some(column)) => Source::Csv(PathBuf::from(path), column),
+        (None, Some(_), None) => return Err("--csv needs --column NAME|IDX".to_string()),
+        (None, None, Some(_)) => return Err("--column goes with --csv".to_string()),
+        _ => return Err("--file and --csv do not go together".to_string()),
+    };
+    if let Some(extra) = args.iter().find(|arg| !arg.starts_with('-')) {
+        return Err(format!(
+            "unexpected argument {extra}; the values come from the file"
+        ));
+    }
+    Ok(source)
+}
+
+/// Removes `flag` and the value after it from `args`.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let Some(at) = args.iter().position(|arg| arg == flag) else {
+        return Ok(None);
+    };
+    if at + 1 >= args.len() {
+        return Err(format!("{flag} needs a value"));
+    }
+    let value = args.remove(at + 1);
+    args.remove(at);
+    Ok(Some(value))
+}
+
+/// Removes `flag` from `args`, saying whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != len
+}
+
+/// `values` as a JSON array's contents; JSON has no NaN or infinity, so
+/// those are `null`.
+fn json_numbers(values: &[f32]) -> String {
+    values
+        .iter()
+        .map(|v| {
+            if v.is_finite() {
+                v.to_string()
+            } else {
+                "null".to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// `s` as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The router's inputs for each value, then how much each one moved the
+/// logit: the drop when that input alone is set to 0.
+fn print_explained(values: &[f32], cfg: &RouteConfig) {
+    let Ok(explained) = route_values_explained(values, cfg) else {
+        return;
+    };
+    let header: String = FEATURE_NAMES
+        .iter()
+        .map(|name| format!(" {name:>9}"))
+        .collect();
+    println!();
+    println!("features:");
+    println!("{:>5}{header}", "idx");
+    for (idx, ex) in explained.iter().enumerate() {
+        let cells: String = ex.features.iter().map(|f| format!(" {f:>9.3}")).collect();
+        println!("{idx:>5}{cells}");
+    }
+    println!();
+    println!("contributions (logit drop when the input is zeroed):");
+    println!(
+        "{:>5} {:>4} {:>8} {:>6} {:>7}{header}",
+        "idx", "keep", "logit", "prob", "active"
+    );
+    for (idx, ex) in explained.iter().enumerate() {
+        let cells: String = ex
+            .contributions
+            .iter()
+            .map(|c| format!(" {c:>+9.3}"))
+            .collect();
+        println!(
+            "{idx:>5} {:>4} {:>8.3} {:>6.3} {:>7}{cells}",
+            if ex.kept { "1" } else { "0" },
+            ex.logit,
+            ex.prob,
+            ex.active
+        );
+    }
+}
+
+fn read_input(args: &[String]) -> io::Result<String> {
+    if args.iter().any(|v| v == "-h" || v == "--help") {
+        return Ok(String::new());
+    }
+    if !args.is_empty() {
+        return Ok(args.join(" "));
+    }
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf)?;
+    Ok(buf)
+}
+
+fn parse_values(input: &str) -> Vec<f32> {
+    input
+        .replace(',', " ")
+        .split_whitespace()
+        .filter_map(|v| v.parse::<f32>().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [f32; 9] = [1.2, 0.5, 3.4, -1.0, 2.2, 0.1, 4.8, -0.3, 1.9];
+
+    fn args(flags: &[&str]) -> Vec<String> {
+        flags.iter().map(|flag| flag.to_string()).collect()
+    }
+
+    fn kept(flags: &[&str]) -> usize {
+        let mut cfg = RouteConfig::default();
+        let top_k = take_limits(&mut args(flags), &mut cfg).unwrap();
+        select(&VALUES, &cfg, top_k, Order::Document)
+            .unwrap()
+            .selected
+            .len()
+    }
+
+    #[test]
+    fn raising_the_threshold_never_keeps_more() {
+        let mut last = usize::MAX;
+        for step in 0..=20 {
+            let threshold = format!("{}", step as f32 / 20.0);
+            let count = kept(&["--threshold", &threshold, "--min-keep", "0"]);
+            assert!(
+                count <= last,
+                "threshold {threshold} kept {count}, up from {last}"
+            );
+            last = count;
+        }
+    }
+
+    #[test]
+    fn top_k_keeps_exactly_k() {
+        for k in 1..=VALUES.len() {
+            assert_eq!(kept(&["--top-k", &k.to_string()]), k);
+        }
+    }
+
+    #[test]
+    fn keep_bounds_hold() {
+        assert!(kept(&["--threshold", "1", "--min-keep", "3"]) >= 3);
+        assert!(kept(&["--threshold", "0", "--max-keep", "2"]) <= 2);
+    }
+
+    #[test]
+    fn conflicting_and_out_of_range_flags_are_refused() {
+        for flags in [
+            &["--top-k", "2", "--threshold", "0.5"][..],
+            &["--top-k", "2", "--min-keep", "1"],
+            &["--top-k", "0"],
+            &["--threshold", "1.5"],
+            &["--threshold", "x"],
+            &["--min-keep", "4", "--max-keep", "2"],
+        ] {
+            let mut cfg = RouteConfig::default();
+            assert!(
+                take_limits(&mut args(flags), &mut cfg).is_err(),
+                "{flags:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn selected_and_rest_follow_the_order() {
+        let values = [0.2, 0.9, 0.1, 0.9, 0.1, 0.4];
+        let mut cfg = RouteConfig::default();
+        let top_k = take_limits(&mut args(&["--top-k", "2"]), &mut cfg).unwrap();
+        let document = select(&values, &cfg, top_k, Order::Document).unwrap();
+        assert_eq!(document.selected, [1, 3]);
+        assert_eq!(document.rest, [0, 2, 4, 5]);
+        // Ties, at 0.9 and at 0.1, stay in input order.
+        let score = select(&values, &cfg, top_k, Order::Score).unwrap();
+        assert_eq!(score.selected, [1, 3]);
+        assert_eq!(score.rest, [5, 0, 2, 4]);
+    }
+
+    #[test]
+    fn limits_are_taken_out_of_the_arguments() {
+        let mut cfg = RouteConfig::default();
+        let mut rest = args(&["--threshold", "0.4", "1,2,3", "--max-keep", "2"]);
+        take_limits(&mut rest, &mut cfg).unwrap();
+        assert_eq!(rest, args(&["1,2,3"]));
+        assert_eq!(cfg.threshold, 0.4);
+        assert_eq!(cfg.max_keep, Some(2));
+    }
+}
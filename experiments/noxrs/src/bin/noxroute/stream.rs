@@ -0,0 +1,114 @@
+//! `noxroute --stream`: a long-lived filter. Reads stdin a line at a time
+//! and routes each non-blank line on its own, writing one line of JSON for
+//! it and flushing before reading the next, until EOF. A line is a list of
+//! values, answered with the `--json` object, or with `--text-lines` a
+//! query and its chunks separated by tabs, answered with the `--text
+//! --json` object (`--scorer`, `--keep`, and `--budget` apply as there). A
+//! line that does not parse or route gets `{"line":N,"error":"..."}`, with
+//! N counting every line from 1, and the stream goes on; the exit status is
+//! then 1. The line and value buffers are reused from one line to the next.
+
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use noxroute_core::{Order, RouteConfig};
+
+use crate::text::{self, Options};
+use crate::{input, json_string, select, NO_VALUES, USAGE};
+
+const STREAM_USAGE: &str = "usage: noxroute [--profile NAME] [--prob-temp T] [--threshold F] \
+                            [--min-keep N] [--max-keep N | --top-k K] [--order document|score] \
+                            --stream [--text-lines [--scorer NAME] [--keep N] [--budget CHARS]] \
+                            < lines";
+
+pub fn run(
+    args: &[String],
+    cfg: &RouteConfig,
+    top_k: Option<usize>,
+    order: Order,
+    text_lines: bool,
+) -> ExitCode {
+    let parsed = match (text_lines, args.first()) {
+        (true, _) if top_k.is_some() => Err("--top-k does not go with --text-lines".to_string()),
+        (true, _) => text::parse_args(args, true).map(Some),
+        (false, Some(extra)) if extra.starts_with("--") => Err(format!("unknown flag {extra}")),
+        (false, Some(extra)) => Err(format!(
+            "unexpected argument {extra}; --stream reads the values from stdin"
+        )),
+        (false, None) => Ok(None),
+    };
+    let text_opts = match parsed {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("noxroute: {err}\n{STREAM_USAGE}");
+            return ExitCode::from(USAGE);
+        }
+    };
+
+    let mut reader = io::stdin().lock();
+    let mut out = io::stdout().lock();
+    let mut line = String::new();
+    let mut values = Vec::new();
+    let mut ok = true;
+    for n in 1.. {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(err) => {
+                eprintln!("noxroute: stdin:{n}: {err}");
+                return ExitCode::from(NO_VALUES);
+            }
+        }
+        let record = line.trim_end_matches(['\n', '\r']);
+        if record.trim().is_empty() {
+            continue;
+        }
+        let reply = match &text_opts {
+            Some(opts) => route_text(record, opts, cfg, order),
+            None => input::parse_line_into(record, &mut values).and_then(|()| {
+                let selection =
+                    select(&values, cfg, top_k, order).map_err(|err| err.to_string())?;
+                Ok(selection.json(&values))
+            }),
+        };
+        let reply = reply.unwrap_or_else(|err| {
+            ok = false;
+            format!("{{\"line\":{n},\"error\":{}}}", json_string(&err))
+        });
+        // A reader that went away, as `head` does, ends the stream.
+        if writeln!(out, "{reply}").and_then(|()| out.flush()).is_err() {
+            break;
+        }
+    }
+    if ok {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(NO_VALUES)
+    }
+}
+
+/// Routes a `--text-lines` record: the query, a tab, and the chunks
+/// separated by tabs.
+fn route_text(
+    record: &str,
+    opts: &Options,
+    cfg: &RouteConfig,
+    order: Order,
+) -> Result<String, String> {
+    let (query, chunks) = record
+        .split_once('\t')
+        .ok_or("expected the query, a tab, and the chunks separated by tabs")?;
+    let query = query.trim();
+    if query.is_empty() {
+        return Err("the query is blank".to_string());
+    }
+    let chunks: Vec<String> = chunks
+        .split('\t')
+        .map(str::trim)
+        .filter(|chunk| !chunk.is_empty())
+        .map(str::to_string)
+        .collect();
+    let routed = text::route_texts(query, chunks, opts, cfg, order).ok_or("no chunks")?;
+    Ok(routed.json())
+}
@@ -0,0 +1,245 @@
+//! `noxroute --text`: routes text chunks read from stdin against a query,
+//! through the same scoring and router as `nox` with `NOX_ROUTE=1`, so
+//! routing can be tuned without a model. The chunks are split on
+//! `--delim` (default `---`), and the flags mirror nox's settings:
+//! `--scorer` for `NOX_ROUTE_SCORER` (`overlap`, `tfidf`, or `bm25`),
+//! `--keep` for `NOX_ROUTE_KEEP` (default 4), `--budget` for
+//! `NOX_ROUTE_BUDGET_CHARS` (default none; nox's own default comes from
+//! `NOX_CTX`), and `--order` and `--profile` as for the values. The other
+//! settings are nox's defaults: near-duplicates are dropped at a Jaccard
+//! similarity of 0.9 and the trigram fallback is at 0.01.
+//!
+//! Prints the kept chunks joined by the delimiter, or with `--json` one
+//! line with the query, each chunk's score, probability, and what decided
+//! it, and the kept chunks' indices in the order they would go back. With
+//! `--compare`, prints the comparison of the chunks' scores instead.
+
+use std::io::{self, Read};
+use std::process::ExitCode;
+
+use noxroute_core::{
+    route_chunks, split_chunks, Anchors, Chunk, Order, RouteConfig, RouteOptions, RouteReport,
+    Scorer, Split, TokenOptions,
+};
+
+use crate::compare::{self, Compare};
+use crate::{json_numbers, json_string, NO_VALUES, USAGE};
+
+const TEXT_USAGE: &str = "usage: noxroute --text --query QUERY [--delim ---] [--scorer NAME] \
+                          [--keep N] [--budget CHARS] [--order document|score] \
+                          [--profile NAME] [--json] < chunks";
+
+pub fn run(
+    args: &[String],
+    route: &RouteConfig,
+    order: Order,
+    json: bool,
+    compare: Option<&Compare>,
+) -> ExitCode {
+    let opts = match parse_args(args, false) {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("noxroute: {err}\n{TEXT_USAGE}");
+            return ExitCode::from(USAGE);
+        }
+    };
+    let mut input = String::new();
+    if let Err(err) = io::stdin().read_to_string(&mut input) {
+        eprintln!("noxroute: reading stdin: {err}");
+        return ExitCode::from(NO_VALUES);
+    }
+    let texts = split_chunks(&input, Split::Delim, &opts.delim).unwrap_or_default();
+    let Some(routed) = route_texts(&opts.query, texts, &opts, route, order) else {
+        eprintln!("noxroute: no chunks on stdin");
+        return ExitCode::from(NO_VALUES);
+    };
+
+    if let Some(compare) = compare {
+        let scores: Vec<f32> = routed.report.chunks.iter().map(|c| c.score).collect();
+        return compare::run(&scores, &routed.route, compare);
+    }
+    if json {
+        println!("{}", routed.json());
+    } else {
+        let joiner = Split::Delim.joiner(&opts.delim);
+        let kept: Vec<&str> = routed
+            .kept
+            .iter()
+            .map(|&idx| routed.texts[idx].as_str())
+            .collect();
+        println!("{}", kept.join(&joiner));
+    }
+    ExitCode::SUCCESS
+}
+
+/// The settings `--text` takes from its flags.
+pub struct Options {
+    query: String,
+    delim: String,
+    scorer: Scorer,
+    keep: usize,
+    budget: Option<usize>,
+}
+
+/// Chunks routed against a query, as `--text` reports them.
+pub struct Routed {
+    /// The chunks left after near-duplicates were dropped, which is what
+    /// the report's indices count.
+    texts: Vec<String>,
+    report: RouteReport,
+    /// The kept chunks' indices, in the order they would go back.
+    kept: Vec<usize>,
+    /// The router's settings, with `--keep` applied.
+    route: RouteConfig,
+}
+
+impl Routed {
+    /// One line of JSON: the query, each chunk and what decided it, and the
+    /// kept indices.
+    pub fn json(&self) -> String {
+        let chunks: Vec<String> = self
+            .report
+            .chunks
+            .iter()
+            .map(|chunk| {
+                format!(
+                    "{{\"index\":{},\"text\":{},\"score\":{},\"prob\":{},\"selected\":{},\"reason\":\"{}\"}}",
+                    chunk.index,
+                    json_string(&self.texts[chunk.index]),
+                    json_numbers(&[chunk.score]),
+                    chunk.prob.map_or("null".to_string(), |p| json_numbers(&[p])),
+                    chunk.selected,
+                    chunk.reason.name()
+                )
+            })
+            .collect();
+        let kept: Vec<String> = self.kept.iter().map(usize::to_string).collect();
+        format!(
+            "{{\"query\":{},\"scorer\":\"{}\",\"chunks\":[{}],\"selected\":[{}]}}",
+            json_string(&self.report.query),
+            self.report.scorer,
+            chunks.join(","),
+            kept.join(",")
+        )
+    }
+}
+
+/// Routes `texts` against `query` with the settings in `opts`; `None` when
+/// there are no chunks.
+pub fn route_texts(
+    query: &str,
+    texts: Vec<String>,
+    opts: &Options,
+    route: &RouteConfig,
+    order: Order,
+) -> Option<Routed> {
+    let chunks: Vec<Chunk> = texts
+        .iter()
+        .map(|text| Chunk {
+            text: text.clone(),
+            score: None,
+        })
+        .collect();
+    let route_opts = RouteOptions {
+        delim: &opts.delim,
+        split: Split::Delim,
+        min_chars: 0,
+        autochunk: None,
+        query: Some(query),
+        keep: opts.keep,
+        scorer: opts.scorer,
+        tokens: TokenOptions::for_scorer(opts.scorer),
+        route: RouteConfig {
+            max_keep: (opts.keep > 0).then_some(opts.keep),
+            ..*route
+        },
+        budget: opts.budget,
+        diversity: 0.0,
+        anchors: Anchors::default(),
+        dedup: Some(0.9),
+        explain: false,
+        report: true,
+        order,
+        fuzzy: Some(0.01),
+    };
+    let routed = route_chunks(query, chunks, &route_opts)?;
+    let report = routed.report.expect("the report was asked for");
+    let texts: Vec<String> = texts
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| !routed.duplicates.iter().any(|dup| dup.dropped == *idx))
+        .map(|(_, text)| text)
+        .collect();
+    let scores: Vec<f32> = report.chunks.iter().map(|chunk| chunk.score).collect();
+    let mut kept: Vec<usize> = report
+        .chunks
+        .iter()
+        .filter(|chunk| chunk.selected)
+        .map(|chunk| chunk.index)
+        .collect();
+    order.sort(&mut kept, &scores);
+    Some(Routed {
+        texts,
+        report,
+        kept,
+        route: route_opts.route,
+    })
+}
+
+/// Parses the `--text` flags. With `per_line`, for `--stream
+/// --text-lines`, each line brings its own query and chunks, so `--query`
+/// and `--delim` are errors.
+pub fn parse_args(args: &[String], per_line: bool) -> Result<Options, String> {
+    let mut opts = Options {
+        query: String::new(),
+        delim: "---".to_string(),
+        scorer: Scorer::Overlap,
+        keep: 4,
+        budget: None,
+    };
+    let mut query = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .map(String::as_str)
+                .ok_or_else(|| format!("{name} needs a value"))
+        };
+        match arg.as_str() {
+            "--query" | "--delim" if per_line => {
+                return Err(format!("{arg} does not go with --text-lines"))
+            }
+            "--query" => query = Some(value("--query")?.trim().to_string()),
+            "--delim" => opts.delim = value("--delim")?.to_string(),
+            "--scorer" => {
+                let name = value("--scorer")?;
+                opts.scorer = Scorer::from_name(name).ok_or_else(|| {
+                    format!("--scorer must be `overlap`, `tfidf`, or `bm25`, got `{name}`")
+                })?;
+            }
+            "--keep" => {
+                opts.keep = value("--keep")?
+                    .parse()
+                    .map_err(|_| "--keep needs a whole number".to_string())?;
+            }
+            "--budget" => {
+                let chars: usize = value("--budget")?
+                    .parse()
+                    .map_err(|_| "--budget needs a whole number of characters".to_string())?;
+                opts.budget = (chars > 0).then_some(chars);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}")),
+            extra => return Err(format!("unexpected argument {extra}")),
+        }
+    }
+    if per_line {
+        return Ok(opts);
+    }
+    opts.query = query
+        .filter(|query| !query.is_empty())
+        .ok_or_else(|| "--text needs --query".to_string())?;
+    if opts.delim.trim().is_empty() {
+        return Err("--delim must not be blank".to_string());
+    }
+    Ok(opts)
+}
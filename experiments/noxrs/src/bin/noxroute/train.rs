@@ -0,0 +1,610 @@
+//! `noxroute train`: fits the router's weights to labelled routes and
+//! writes them as a weights file that `noxroute --weights` and
+//! `NOX_ROUTE_WEIGHTS` load as they start, and as a `routing_weights.rs` to
+//! replace the one compiled into noxroute-core. With `--profile NAME`
+//! the source holds only the constants, prefixed `NAME_`, to append to
+//! that file as the weights of a built-in profile.
+//!
+//! Each line of the data file is one route, `values;mask`: the chunk
+//! scores and which chunks should be kept, e.g. `0.9,0.1,0.4;1,0,0`.
+//! Blank lines and lines starting with `#` are skipped. The inputs are the
+//! runtime's own ([`route_features`]), and the model is the same
+//! 8 -> hidden -> 1 ReLU network, fitted by plain SGD on cross-entropy.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+use noxroute_core::{route_features, Weights};
+
+const USAGE: &str = "usage: noxroute train DATA [--weights FILE] [--out FILE.rs] \
+                     [--profile NAME] [--seed N] [--epochs N] [--lr X] [--hidden N] [--val F]";
+
+struct Options {
+    data: PathBuf,
+    /// The weights file, if wanted.
+    weights: Option<PathBuf>,
+    /// The `routing_weights.rs`, if wanted.
+    out: Option<PathBuf>,
+    /// The profile the source is for; `None` for the main weights.
+    profile: Option<String>,
+    seed: u64,
+    epochs: usize,
+    lr: f32,
+    hidden: usize,
+    /// Share of the routes held out to measure accuracy on.
+    val: f32,
+}
+
+/// One chunk: its eight inputs and whether it should be kept.
+type Sample = ([f32; 8], f32);
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let opts = parse_args(args)?;
+    let text =
+        fs::read_to_string(&opts.data).map_err(|err| format!("{}: {err}", opts.data.display()))?;
+    let routes = parse_routes(&text)?;
+    if routes.is_empty() {
+        return Err(format!("{}: no routes", opts.data.display()));
+    }
+    let model = fit(routes, &opts, true)?;
+
+    let note = format!(
+        "`noxroute train` on {} (seed {}, {} epochs, lr {})",
+        opts.data.display(),
+        opts.seed,
+        opts.epochs,
+        opts.lr
+    );
+    let write = |path: &PathBuf, contents: String| {
+        fs::write(path, contents).map_err(|err| format!("{}: {err}", path.display()))?;
+        eprintln!("noxroute: wrote {}", path.display());
+        Ok::<_, String>(())
+    };
+    if let Some(path) = &opts.weights {
+        write(path, model.weights_file(&note)?)?;
+    }
+    if let Some(path) = &opts.out {
+        write(path, model.source(&note, opts.profile.as_deref()))?;
+    }
+    Ok(())
+}
+
+/// Holds out `opts.val` of `routes` at random and fits a model to the
+/// rest, reporting each epoch on stderr when `verbose`.
+fn fit(mut routes: Vec<Vec<Sample>>, opts: &Options, verbose: bool) -> Result<Model, String> {
+    let mut rng = Rng::new(opts.seed);
+    rng.shuffle(&mut routes);
+    let held = ((routes.len() as f32 * opts.val).round() as usize).min(routes.len() - 1);
+    let samples = |routes: &[Vec<Sample>]| routes.concat();
+    let (val, train) = (samples(&routes[..held]), samples(&routes[held..]));
+    if verbose {
+        eprintln!(
+            "noxroute: {} routes: {} chunks to train on, {} to validate",
+            routes.len(),
+            train.len(),
+            val.len()
+        );
+    }
+
+    let mut model = Model::new(opts.hidden, &mut rng);
+    let mut order: Vec<usize> = (0..train.len()).collect();
+    for epoch in 1..=opts.epochs {
+        rng.shuffle(&mut order);
+        let mut loss = 0.0_f32;
+        for &idx in &order {
+            loss += model.step(&train[idx], opts.lr);
+        }
+        loss /= train.len() as f32;
+        if !loss.is_finite() {
+            return Err(format!(
+                "training diverged in epoch {epoch}; try a lower --lr"
+            ));
+        }
+        if verbose {
+            eprintln!(
+                "noxroute: epoch {epoch}/{}: loss {loss:.4}, train {}, val {}",
+                opts.epochs,
+                percent(model.accuracy(&train)),
+                percent(model.accuracy(&val))
+            );
+        }
+    }
+    if verbose {
+        let baseline = majority(if val.is_empty() { &train } else { &val });
+        eprintln!(
+            "noxroute: always guessing the commoner label scores {}",
+            percent(Some(baseline))
+        );
+    }
+    Ok(model)
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut opts = Options {
+        data: PathBuf::new(),
+        weights: None,
+        out: None,
+        profile: None,
+        seed: 0,
+        epochs: 20,
+        lr: 0.01,
+        hidden: 128,
+        val: 0.2,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("{name} needs a value\n{USAGE}"))
+        };
+        let bad = |name: &str| format!("bad value for {name}\n{USAGE}");
+        match arg.as_str() {
+            "--weights" => opts.weights = Some(value("--weights")?.into()),
+            "--out" => opts.out = Some(value("--out")?.into()),
+            "--profile" => opts.profile = Some(value("--profile")?),
+            "--seed" => opts.seed = value("--seed")?.parse().map_err(|_| bad("--seed"))?,
+            "--epochs" => opts.epochs = value("--epochs")?.parse().map_err(|_| bad("--epochs"))?,
+            "--lr" => opts.lr = value("--lr")?.parse().map_err(|_| bad("--lr"))?,
+            "--hidden" => opts.hidden = value("--hidden")?.parse().map_err(|_| bad("--hidden"))?,
+            "--val" => opts.val = value("--val")?.parse().map_err(|_| bad("--val"))?,
+            flag if flag.starts_with("--") => return Err(format!("unknown flag {flag}\n{USAGE}")),
+            data if opts.data.as_os_str().is_empty() => opts.data = data.into(),
+            extra => return Err(format!("unexpected argument {extra}\n{USAGE}")),
+        }
+    }
+    if opts.data.as_os_str().is_empty() || (opts.weights.is_none() && opts.out.is_none()) {
+        return Err(format!(
+            "give DATA, and --weights, --out, or both for the output\n{USAGE}"
+        ));
+    }
+    if let Some(name) = &opts.profile {
+        let ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        if name.is_empty()
+            || !name.chars().all(ident)
+            || name.starts_with(|c: char| c.is_ascii_digit())
+        {
+            return Err(format!(
+                "--profile needs a name of letters, digits, and `_`\n{USAGE}"
+            ));
+        }
+    }
+    let lr_ok = opts.lr > 0.0 && opts.lr.is_finite();
+    if opts.epochs == 0 || opts.hidden == 0 || !lr_ok || !(0.0..1.0).contains(&opts.val) {
+        return Err(format!(
+            "--epochs and --hidden must be at least 1, --lr above 0, and --val from 0 to below 1\n{USAGE}"
+        ));
+    }
+    Ok(opts)
+}
+
+/// Each route's chunks as samples, checking every line as it goes.
+fn parse_routes(text: &str) -> Result<Vec<Vec<Sample>>, String> {
+    let mut routes = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let bad = |why: &str| format!("line {}: {why}", n + 1);
+        let (values, mask) = line
+            .split_once(';')
+            .ok_or_else(|| bad("expected `values;mask`"))?;
+        let values: Vec<f32> = list(values)
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|_| bad(&format!("`{v}` is not a number")))
+            })
+            .collect::<Result<_, _>>()?;
+        let mask: Vec<f32> = list(mask)
+            .map(|m| match m {
+                "1" => Ok(1.0),
+                "0" => Ok(0.0),
+                _ => Err(bad(&format!("mask entries must be 0 or 1, got `{m}`"))),
+            })
+            .collect::<Result<_, _>>()?;
+        if values.is_empty() || values.len() != mask.len() {
+            return Err(bad(&format!(
+                "{} values but {} mask entries",
+                values.len(),
+                mask.len()
+            )));
+        }
+        routes.push(route_features(&values).into_iter().zip(mask).collect());
+    }
+    Ok(routes)
+}
+
+fn list(text: &str) -> impl Iterator<Item = &str> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// The 8 -> hidden -> 1 network, laid out as in `routing_weights.rs`:
+/// `w1[i * hidden + h]` joins input `i` to hidden unit `h`.
+struct Model {
+    hidden: usize,
+    w1: Vec<f32>,
+    b1: Vec<f32>,
+    w2: Vec<f32>,
+    b2: f32,
+}
+
+impl Model {
+    /// He-uniform weights for the ReLU layer, Glorot for the output, zero
+    /// biases.
+    fn new(hidden: usize, rng: &mut Rng) -> Self {
+        let r1 = (6.0 / 8.0_f32).sqrt();
+        let r2 = (6.0 / (hidden as f32 + 1.0)).sqrt();
+        Self {
+            hidden,
+            w1: (0..8 * hidden).map(|_| rng.uniform(r1)).collect(),
+            b1: vec![0.0; hidden],
+            w2: (0..hidden).map(|_| rng.uniform(r2)).collect(),
+            b2: 0.0,
+        }
+    }
+
+    /// The hidden activations and the probability of keeping `x`.
+    fn forward(&self, x: &[f32; 8]) -> (Vec<f32>, f32) {
+        let mut act = self.b1.clone();
+        for (h, acc) in act.iter_mut().enumerate() {
+            for (i, xi) in x.iter().enumerate() {
+                *acc += xi * self.w1[i * self.hidden + h];
+            }
+            *acc = acc.max(0.0);
+        }
+        let mut logit = self.b2;
+        for (a, w) in act.iter().zip(&self.w2) {
+            logit += a * w;
+        }
+        (act, sigmoid(logit))
+    }
+
+    /// One SGD step on `(x, label)`; returns its cross-entropy before the
+    /// step.
+    fn step(&mut self, (x, label): &Sample, lr: f32) -> f32 {
+        let (act, p) = self.forward(x);
+        let dz = p - label;
+        self.b2 -= lr * dz;
+        for (h, &a) in act.iter().enumerate() {
+            let da = dz * self.w2[h];
+            self.w2[h] -= lr * dz * a;
+            if a > 0.0 {
+                for (i, xi) in x.iter().enumerate() {
+                    self.w1[i * self.hidden + h] -= lr * da * xi;
+                }
+                self.b1[h] -= lr * da;
+            }
+        }
+        let p = p.clamp(1e-7, 1.0 - 1e-7);
+        -(label * p.ln() + (1.0 - label) * (1.0 - p).ln())
+    }
+
+    /// The share of `samples` on the right side of 0.5; `None` for none.
+    fn accuracy(&self, samples: &[Sample]) -> Option<f32> {
+        if samples.is_empty() {
+            return None;
+        }
+        let right = samples
+            .iter()
+            .filter(|(x, label)| (self.forward(x).1 >= 0.5) == (*label > 0.5))
+            .count();
+        Some(right as f32 / samples.len() as f32)
+    }
+
+    /// The weights as a file for `noxroute --weights` and
+    /// `NOX_ROUTE_WEIGHTS`, headed by `note`.
+    fn weights_file(&self, note: &str) -> Result<String, String> {
+        let weights = Weights::new(self.w1.clone(), self.b1.clone(), self.w2.clone(), self.b2)
+            .map_err(|err| format!("the trained weights are unusable: {err}"))?;
+        Ok(format!("# Fitted by {note}\n{}", weights.to_text()))
+    }
+
+    /// The weights as a `routing_weights.rs`, laid out like the one the
+    /// runtime ships; for `profile`, only its constants, to append to it.
+    fn source(&self, note: &str, profile: Option<&str>) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "// Generated by {note}");
+        let prefix = match profile {
+            Some(name) => format!("{}_", name.to_ascii_uppercase()),
+            None => {
+                out.push_str("#![allow(clippy::excessive_precision)]\n\n");
+                out.push_str("pub const IN_DIM: usize = 8;\n");
+                String::new()
+            }
+        };
+        let _ = writeln!(out, "pub const {prefix}HIDDEN: usize = {};\n", self.hidden);
+        array(&mut out, &format!("{prefix}W1"), &self.w1);
+        array(&mut out, &format!("{prefix}B1"), &self.b1);
+        array(&mut out, &format!("{prefix}W2"), &self.w2);
+        let _ = writeln!(out, "pub const {prefix}B2: f32 = {};", literal(self.b2));
+        out
+    }
+}
+
+fn array(out: &mut String, name: &str, values: &[f32]) {
+    let _ = writeln!(out, "pub const {name}: [f32; {}] = [", values.len());
+    for row in values.chunks(8) {
+        let row: String = row.iter().map(|v| format!(" {},", literal(*v))).collect();
+        let _ = writeln!(out, "   {row}");
+    }
+    out.push_str("];\n\n");
+}
+
+/// `v` as numpy's `%.8e` spells it, typed: `-1.50082231e+00f32`.
+fn literal(v: f32) -> String {
+    let text = format!("{v:.8e}");
+    let (mantissa, exp) = text.split_once('e').unwrap_or((&text, "0"));
+    let exp: i32 = exp.parse().unwrap_or(0);
+    format!("{mantissa}e{exp:+03}f32")
+}
+
+fn sigmoid(x: f32) -> f32 {
+    1.0 / (1.0 + (-x.clamp(-50.0, 50.0)).exp())
+}
+
+/// The share of the commoner label, what a constant guess would score.
+fn majority(samples: &[Sample]) -> f32 {
+    let kept = samples.iter().filter(|(_, label)| *label > 0.5).count();
+    kept.max(samples.len() - kept) as f32 / samples.len().max(1) as f32
+}
+
+fn percent(share: Option<f32>) -> String {
+    match share {
+        Some(share) => format!("{:.1}%", share * 100.0),
+        None => "n/a".to_string(),
+    }
+}
+
+/// xorshift64*, seeded through splitmix64 so any seed, 0 included, works.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `[-range, range)`.
+    fn uniform(&mut self, range: f32) -> f32 {
+        let unit = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        (unit * 2.0 - 1.0) * range
+    }
+
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use noxroute_core::{route_values_with, RouteConfig};
+
+    use super::*;
+
+    /// Routes of six random scores where only those of 0.6 or more are
+    /// kept, so most labels are "drop".
+    fn synthetic(routes: usize, rng: &mut Rng) -> String {
+        let mut text = String::from("# value >= 0.6\n");
+        for _ in 0..routes {
+            let values: Vec<f32> = (0..6).map(|_| rng.uniform(0.5) + 0.5).collect();
+            let mask: Vec<&str> = values
+                .iter()
+                .map(|&v| if v >= 0.6 { "1" } else { "0" })
+                .collect();
+            let values: Vec<String> = values.iter().map(|v| format!("{v:.4}")).collect();
+            let _ = writeln!(text, "{};{}", values.join(","), mask.join(","));
+        }
+        text
+    }
+
+    fn train(samples: &[Sample], seed: u64) -> Model {
+        let mut rng = Rng::new(seed);
+        let mut model = Model::new(16, &mut rng);
+        let mut order: Vec<usize> = (0..samples.len()).collect();
+        for _ in 0..30 {
+            rng.shuffle(&mut order);
+            for &idx in &order {
+                model.step(&samples[idx], 0.01);
+            }
+        }
+        model
+    }
+
+    #[test]
+    fn the_trained_model_beats_the_majority_guess() {
+        let mut rng = Rng::new(7);
+        let routes = parse_routes(&synthetic(300, &mut rng)).unwrap();
+        assert_eq!(routes.len(), 300);
+        let (val, fit) = routes.split_at(60);
+        let (val, fit) = (val.concat(), fit.concat());
+        let model = train(&fit, 7);
+        let accuracy = model.accuracy(&val).unwrap();
+        let baseline = majority(&val);
+        assert!(baseline > 0.5 && baseline < 0.8, "baseline {baseline}");
+        assert!(accuracy > baseline + 0.1, "{accuracy} vs {baseline}");
+    }
+
+    #[test]
+    fn a_seed_gives_the_same_weights() {
+        let routes = parse_routes(&synthetic(20, &mut Rng::new(1)))
+            .unwrap()
+            .concat();
+        let (a, b) = (train(&routes, 3), train(&routes, 3));
+        assert_eq!((a.w1, a.b1, a.w2, a.b2), (b.w1, b.b1, b.w2, b.b2));
+    }
+
+    #[test]
+    fn the_weights_file_routes_as_the_trained_model_scores() {
+        let mut rng = Rng::new(5);
+        let routes = parse_routes(&synthetic(40, &mut rng)).unwrap().concat();
+        let model = train(&routes, 5);
+        let file = model.weights_file("a test").unwrap();
+        assert!(
+            file.starts_with("# Fitted by a test\nhidden 16\nw1 "),
+            "{file}"
+        );
+        let cfg = RouteConfig {
+            weights: Weights::parse(&file).unwrap(),
+            ..RouteConfig::default()
+        };
+        for _ in 0..10 {
+            let values: Vec<f32> = (0..6).map(|_| rng.uniform(0.5) + 0.5).collect();
+            let routed = route_values_with(&values, &cfg).unwrap();
+            let scored: Vec<f32> = route_features(&values)
+                .iter()
+                .map(|x| model.forward(x).1)
+                .collect();
+            assert_eq!(routed.probs, scored);
+        }
+    }
+
+    #[test]
+    fn the_source_is_laid_out_like_routing_weights() {
+        let model = Model {
+            hidden: 2,
+            w1: (0..16).map(|i| i as f32 / 8.0 - 1.0).collect(),
+            b1: vec![0.5, -0.25],
+            w2: vec![1.5, -0.75],
+            b2: 0.0625,
+        };
+        let want = "\
+// Generated by a test
+#![allow(clippy::excessive_precision)]
+
+pub const IN_DIM: usize = 8;
+pub const HIDDEN: usize = 2;
+
+pub const W1: [f32; 16] = [
+    -1.00000000e+00f32, -8.75000000e-01f32, -7.50000000e-01f32, -6.25000000e-01f32, -5.00000000e-01f32, -3.75000000e-01f32, -2.50000000e-01f32, -1.25000000e-01f32,
+    0.00000000e+00f32, 1.25000000e-01f32, 2.50000000e-01f32, 3.75000000e-01f32, 5.00000000e-01f32, 6.25000000e-01f32, 7.50000000e-01f32, 8.75000000e-01f32,
+];
+
+pub const B1: [f32; 2] = [
+    5.00000000e-01f32, -2.50000000e-01f32,
+];
+
+pub const W2: [f32; 2] = [
+    1.50000000e+00f32, -7.50000000e-01f32,
+];
+
+pub const B2: f32 = 6.25000000e-02f32;
+";
+        assert_eq!(model.source("a test", None), want);
+    }
+
+    #[test]
+    fn the_shipped_weights_have_the_same_layout() {
+        let shipped = include_str!("../../../../noxroute-core/src/routing_weights.rs");
+        let declarations: Vec<&str> = shipped
+            .lines()
+            .filter(|line| line.starts_with("pub const") || line.starts_with("#!"))
+            .collect();
+        assert_eq!(
+            declarations,
+            [
+                "#![allow(clippy::excessive_precision)]",
+                "pub const IN_DIM: usize = 8;",
+                "pub const HIDDEN: usize = 128;",
+                "pub const W1: [f32; 1024] = [",
+                "pub const B1: [f32; 128] = [",
+                "pub const W2: [f32; 128] = [",
+                "pub const B2: f32 = -1.50082231e+00f32;",
+                "pub const AGGRESSIVE_HIDDEN: usize = 16;",
+                "pub const AGGRESSIVE_W1: [f32; 128] = [",
+                "pub const AGGRESSIVE_B1: [f32; 16] = [",
+                "pub const AGGRESSIVE_W2: [f32; 16] = [",
+                "pub const AGGRESSIVE_B2: f32 = -1.50487030e+00f32;",
+                "pub const RECALL_HIDDEN: usize = 16;",
+                "pub const RECALL_W1: [f32; 128] = [",
+                "pub const RECALL_B1: [f32; 16] = [",
+                "pub const RECALL_W2: [f32; 16] = [",
+                "pub const RECALL_B2: f32 = 9.34581995e-01f32;",
+            ]
+        );
+    }
+
+    #[test]
+    fn the_shipped_profiles_are_what_their_data_trains() {
+        let shipped = include_str!("../../../../noxroute-core/src/routing_weights.rs");
+        for name in ["aggressive", "recall"] {
+            let path = format!("../noxroute-core/profiles/{name}.txt");
+            let args: Vec<String> = [&path, "--profile", name, "--hidden", "16"]
+                .iter()
+                .chain(&["--epochs", "40", "--seed", "1", "--out", "x.rs"])
+                .map(|arg| arg.to_string())
+                .collect();
+            let opts = parse_args(&args).unwrap();
+            let text = fs::read_to_string(&opts.data).unwrap();
+            let model = fit(parse_routes(&text).unwrap(), &opts, false).unwrap();
+            let note =
+                format!("`noxroute train` on profiles/{name}.txt (seed 1, 40 epochs, lr 0.01)");
+            let source = model.source(&note, Some(name));
+            assert!(shipped.contains(&source), "{name} is stale");
+        }
+    }
+
+    #[test]
+    fn a_profile_source_is_prefixed_constants_only() {
+        let model = Model {
+            hidden: 1,
+            w1: vec![0.0; 8],
+            b1: vec![0.5],
+            w2: vec![1.0],
+            b2: -1.0,
+        };
+        let source = model.source("a test", Some("fast_1"));
+        let declarations: Vec<&str> = source
+            .lines()
+            .filter(|line| !line.starts_with(' ') && !line.is_empty() && *line != "];")
+            .collect();
+        assert_eq!(
+            declarations,
+            [
+                "// Generated by a test",
+                "pub const FAST_1_HIDDEN: usize = 1;",
+                "pub const FAST_1_W1: [f32; 8] = [",
+                "pub const FAST_1_B1: [f32; 1] = [",
+                "pub const FAST_1_W2: [f32; 1] = [",
+                "pub const FAST_1_B2: f32 = -1.00000000e+00f32;",
+            ]
+        );
+        for bad in ["", "1st", "a-b"] {
+            let args: Vec<String> = ["d", "--out", "x.rs", "--profile", bad]
+                .iter()
+                .map(|arg| arg.to_string())
+                .collect();
+            assert!(parse_args(&args).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn bad_lines_are_refused_with_their_number() {
+        for (text, why) in [
+            ("0.1,0.2", "line 1: expected `values;mask`"),
+            ("\n0.1,x;1,0", "line 2: `x` is not a number"),
+            (
+                "0.1,0.2;1,2",
+                "line 1: mask entries must be 0 or 1, got `2`",
+            ),
+            ("0.1,0.2;1", "line 1: 2 values but 1 mask entries"),
+        ] {
+            assert_eq!(parse_routes(text).unwrap_err(), why);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Runner capability probe: asks a runner for its `-h` text and records which
+//! flags it advertises, so optional flags are only passed to runners that
+//! accept them. Results are cached per runner path for the process lifetime.
+
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sim_serve;
+
+/// A runner that has not printed its help within this long is treated as
+/// advertising nothing.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    flags: HashSet<String>,
+}
+
+impl Capabilities {
+    /// Whether the help text mentions `flag` (e.g. `-seed`).
+    pub fn supports(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+}
+
+pub fn probe(runner: &Path) -> Capabilities {
+    static CACHE: OnceLock<Mutex<HashMap<PathBuf, Capabilities>>> = OnceLock::new();
+    let cache = CACHE.get_or_init(Default::default);
+    let mut cache = cache.lock().unwrap_or_else(|e| e.into_inner());
+    cache
+        .entry(runner.to_path_buf())
+        .or_insert_with(|| Capabilities {
+            flags: parse_flags(&help_text(runner)),
+        })
+        .clone()
+}
+
+/// Collects every `-flag` / `--flag` token that starts a word in `help`.
+pub fn parse_flags(help: &str) -> HashSet<String> {
+    help.split(|c: char| c.is_whitespace() || c == ',' || c == '=')
+        .filter(|word| word.starts_with('-') && word.len() > 1)
+        .map(|word| {
+            word.trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+                .to_string()
+        })
+        .filter(|word| {
+            word.chars()
+                .nth(1)
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == '-')
+        })
+        .collect()
+}
+
+fn help_text(runner: &Path) -> String {
+    let mut cmd = Command::new(runner);
+    // Standing in for a runner, nox only answers as one after this flag.
+    if sim_serve::is_self(runner) {
+        cmd.arg(sim_serve::FLAG);
+    }
+    let child = cmd
+        .arg("-h")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let Ok(mut child) = child else {
+        return String::new();
+    };
+    // Go's flag package prints usage on stderr; other runners use stdout.
+    let readers: Vec<_> = [
+        child
+            .stdout
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>),
+        child
+            .stderr
+            .take()
+            .map(|s| Box::new(s) as Box<dyn Read + Send>),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|mut src| {
+        thread::spawn(move || {
+            let mut text = String::new();
+            let _ = src.read_to_string(&mut text);
+            text
+        })
+    })
+    .collect();
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(Some(_)) => break,
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                break;
+            }
+        }
+    }
+    readers
+        .into_iter()
+        .filter_map(|reader| reader.join().ok())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
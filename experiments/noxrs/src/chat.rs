@@ -0,0 +1,134 @@
+//! `--chat`: a line-per-message REPL. Every turn sends the whole
+//! conversation (system prompt from `NOX_SYSTEM_PROMPT`, then the history),
+//! trimmed by [`ContextBudget`] so it fits the context window. A warm
+//! `noxlocal -serve` child answers when the style allows it, as in batch
+//! mode. After each reply a stats line goes to stderr.
+
+use std::io::{self, BufRead, IsTerminal, Write};
+
+use crate::batch::Executor;
+use crate::context::{ContextBudget, Conversation, Role, Turn};
+use crate::error::NoxError;
+use crate::signals;
+use crate::transcript::{Tee, Transcript};
+use crate::{Config, RunStats};
+
+pub fn run(cfg: &Config) -> Result<(), NoxError> {
+    let mut cfg = cfg.clone();
+    // Replies go back into the history, so no runner prefixes.
+    cfg.raw = true;
+    cfg.persist_rs = true;
+    let budget = ContextBudget::new(&cfg);
+    let mut convo = Conversation {
+        system: cfg.system_prompt.clone(),
+        turns: Vec::new(),
+    };
+    let mut executor = Executor::for_config(&cfg);
+    let mut log = Transcript::open(cfg.log_file.as_deref());
+    let interactive = io::stdin().is_terminal();
+    signals::install();
+
+    let mut lines = io::stdin().lock().lines();
+    let result = loop {
+        if interactive {
+            eprint!("> ");
+        }
+        let line = match lines.next() {
+            None => break Ok(()),
+            Some(Ok(line)) => line,
+            Some(Err(err)) => break Err(err.into()),
+        };
+        if signals::interrupts() > 0 {
+            break Err(NoxError::Interrupted);
+        }
+        let message = line.trim();
+        if message.is_empty() {
+            continue;
+        }
+        convo.turns.push(Turn::new(Role::User, message));
+        let fitted = budget.fit(&convo);
+        if !fitted.fits {
+            eprintln!(
+                "nox: warning: the system prompt and latest message alone exceed ctx - max_tokens ({} tokens)",
+                budget.limit
+            );
+        }
+        let dropped = fitted.dropped;
+        let prompt = fitted.render();
+
+        log.begin(message);
+        let mut reply = Vec::new();
+        let result = {
+            let mut stdout = io::stdout();
+            let mut tee = Tee {
+                out: &mut stdout,
+                log: &mut log,
+            };
+            let mut out = Reply {
+                out: &mut tee,
+                seen: &mut reply,
+            };
+            executor.stream(&cfg, &prompt, &mut out)
+        };
+        log.finish(&result);
+        let mut stdout = io::stdout();
+        if !reply.is_empty() && !reply.ends_with(b"\n") {
+            writeln!(stdout)?;
+        }
+        stdout.flush()?;
+        match result {
+            Ok(stats) => {
+                eprintln!("nox: {}", stats_line(&stats, dropped));
+                let text = String::from_utf8_lossy(&reply);
+                convo.turns.push(Turn::new(Role::Assistant, text.trim()));
+            }
+            // A runner that died or stalled on one message may manage the
+            // next; drop the unanswered message so the history stays
+            // alternating. Setup errors would only repeat, so they end the chat.
+            Err(
+                err @ (NoxError::RunnerFailed(_)
+                | NoxError::LimitExceeded(_)
+                | NoxError::Timeout(_)
+                | NoxError::BadOutput(_)),
+            ) => {
+                eprintln!("nox: {err}");
+                convo.turns.pop();
+            }
+            Err(err) => break Err(err),
+        }
+    };
+    executor.finish();
+    result
+}
+
+/// `ttft 120 ms, total 840 ms, dropped 2 turns`, with the rate reached
+/// (`41.2 tok/s, `) before the total when the tokens were counted.
+fn stats_line(stats: &RunStats, dropped: usize) -> String {
+    let ttft = stats.ttft_label();
+    let tps = stats
+        .tps()
+        .map_or(String::new(), |tps| format!("{tps:.1} tok/s, "));
+    format!(
+        "ttft {ttft}, {tps}total {} ms, dropped {dropped} turn{}",
+        stats.duration.as_millis(),
+        if dropped == 1 { "" } else { "s" }
+    )
+}
+
+/// Passes the reply through while keeping a copy for the history.
+struct Reply<'a> {
+    out: &'a mut dyn Write,
+    seen: &'a mut Vec<u8>,
+}
+
+impl Write for Reply<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.seen.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
@@ -0,0 +1,158 @@
+//! Command-line flags. Anything that is not a recognised `--flag` is part of
+//! the prompt; `--` ends flag parsing so prompts may start with dashes.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::config::Layer;
+use crate::error::NoxError;
+use crate::sampling::Sampling;
+
+pub const USAGE: &str = "\
+usage: nox [flags] [prompt...]
+       echo prompt | nox [flags]
+
+flags:
+  --batch FILE           run each line of FILE (text or {\"id\",\"prompt\"} JSON) and emit JSONL results
+  --out FILE             write batch results to FILE instead of stdout
+  --fail-fast            stop a batch at the first failed prompt
+  -j, --jobs N           run up to N batch prompts concurrently (default 1)
+  --chat                 chat on stdin, one message per line, trimming history to fit the ctx
+  --seed N               sampling seed (NOX_SEED)
+  --repeat-penalty X     repetition penalty, >= 0 (NOX_REPEAT_PENALTY)
+  --repeat-last-n N      repetition window, -1 = whole context (NOX_REPEAT_LAST_N)
+  --min-p X              min-p cutoff in [0, 1] (NOX_MIN_P)
+  --presence-penalty X   presence penalty, >= 0 (NOX_PRESENCE_PENALTY)
+  --extra-arg ARG        pass ARG to the runner before the prompt (repeatable; NOX_EXTRA_ARGS)
+  --log FILE             append prompts and responses to FILE (JSONL if it ends in .jsonl)
+  --dry-run              print the resolved runner command instead of running it
+  --count-tokens         estimate the final prompt's token count and whether it fits the ctx
+  --json                 print embeddings or the token count as JSON
+  --print-config         show the effective settings and where each came from
+  -h, --help             show this help
+
+Runner, model, and sampling settings come from NOX_* environment variables,
+./nox.toml, or ~/.config/nox/config.toml (see README); flags win over all of them.";
+
+#[derive(Debug, Default)]
+pub struct Cli {
+    pub prompt: Vec<String>,
+    pub help: bool,
+    pub batch: Option<PathBuf>,
+    pub out: Option<PathBuf>,
+    pub fail_fast: bool,
+    pub jobs: Option<usize>,
+    pub chat: bool,
+    pub json: bool,
+    pub dry_run: bool,
+    pub log: Option<PathBuf>,
+    pub sampling: Sampling,
+    pub print_config: bool,
+    pub count_tokens: bool,
+    pub extra_args: Vec<String>,
+}
+
+impl Cli {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, NoxError> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                cli.prompt.extend(args.by_ref());
+                break;
+            }
+            if !arg.starts_with("--") {
+                if arg == "-h" {
+                    cli.help = true;
+                } else if arg == "-j" {
+                    let value = args
+                        .next()
+                        .ok_or_else(|| NoxError::Usage("-j needs a value".to_string()))?;
+                    cli.jobs = Some(jobs(&arg, value)?);
+                } else {
+                    cli.prompt.push(arg);
+                }
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            let mut value = |name: &str| -> Result<String, NoxError> {
+                inline
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| NoxError::Usage(format!("{name} needs a value")))
+            };
+            match name.as_str() {
+                "--help" => cli.help = true,
+                "--batch" => cli.batch = Some(PathBuf::from(value(&name)?)),
+                "--out" => cli.out = Some(PathBuf::from(value(&name)?)),
+                "--fail-fast" => cli.fail_fast = true,
+                "--jobs" => cli.jobs = Some(jobs(&name, value(&name)?)?),
+                "--chat" => cli.chat = true,
+                "--json" => cli.json = true,
+                "--dry-run" => cli.dry_run = true,
+                "--print-config" => cli.print_config = true,
+                "--count-tokens" => cli.count_tokens = true,
+                "--seed" => cli.sampling.seed = Some(number(&name, value(&name)?)?),
+                "--repeat-penalty" => {
+                    cli.sampling.repeat_penalty = Some(number(&name, value(&name)?)?)
+                }
+                "--repeat-last-n" => {
+                    cli.sampling.repeat_last_n = Some(number(&name, value(&name)?)?)
+                }
+                "--min-p" => cli.sampling.min_p = Some(number(&name, value(&name)?)?),
+                "--presence-penalty" => {
+                    cli.sampling.presence_penalty = Some(number(&name, value(&name)?)?)
+                }
+                "--log" => cli.log = Some(PathBuf::from(value(&name)?)),
+                "--extra-arg" => cli.extra_args.push(value(&name)?),
+                _ => return Err(NoxError::Usage(format!("unknown flag {name} (see --help)"))),
+            }
+        }
+        Ok(cli)
+    }
+
+    /// The flags that shadow `NOX_*` settings, as the top config layer.
+    pub fn layer(&self) -> Layer {
+        let s = &self.sampling;
+        let values: HashMap<String, String> = [
+            ("NOX_DRY_RUN", self.dry_run.then(|| "1".to_string())),
+            (
+                "NOX_LOG_FILE",
+                self.log.as_ref().map(|p| p.to_string_lossy().into_owned()),
+            ),
+            ("NOX_SEED", s.seed.map(|v| v.to_string())),
+            (
+                "NOX_REPEAT_PENALTY",
+                s.repeat_penalty.map(|v| v.to_string()),
+            ),
+            ("NOX_REPEAT_LAST_N", s.repeat_last_n.map(|v| v.to_string())),
+            ("NOX_MIN_P", s.min_p.map(|v| v.to_string())),
+            (
+                "NOX_PRESENCE_PENALTY",
+                s.presence_penalty.map(|v| v.to_string()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.map(|v| (k.to_string(), v)))
+        .collect();
+        Layer::new("flag", values)
+    }
+}
+
+fn number<T: FromStr>(name: &str, value: String) -> Result<T, NoxError> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| NoxError::Usage(format!("{name}: `{value}` is not a valid number")))
+}
+
+fn jobs(name: &str, value: String) -> Result<usize, NoxError> {
+    match number(name, value)? {
+        0 => Err(NoxError::Usage(format!("{name} must be at least 1"))),
+        n => Ok(n),
+    }
+}
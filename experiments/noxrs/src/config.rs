@@ -0,0 +1,372 @@
+//! Layered settings. Every knob is named by its `NOX_*` variable and looked
+//! up through [`Sources`], highest precedence first: command-line flags,
+//! environment, `./nox.toml`, `$XDG_CONFIG_HOME/nox/config.toml`, then the
+//! built-in default at the call site. Config files use a flat TOML subset
+//! (`key = value` with strings, ints, floats, and bools) whose keys are the
+//! variable names without the prefix, lowercased: `ctx = 2048` sets `NOX_CTX`.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::NoxError;
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    /// Where the values came from, for `--print-config`.
+    pub label: String,
+    values: HashMap<String, String>,
+}
+
+impl Layer {
+    pub fn new(label: impl Into<String>, values: HashMap<String, String>) -> Self {
+        Self {
+            label: label.into(),
+            values,
+        }
+    }
+
+    /// Every `NOX_*` variable in the process environment.
+    pub fn from_env() -> Self {
+        let values = env::vars().filter(|(k, _)| k.starts_with("NOX_")).collect();
+        Self::new("env", values)
+    }
+
+    /// Parses a config file; a missing file is an empty layer.
+    pub fn from_file(path: &Path) -> Result<Option<Self>, NoxError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(NoxError::Usage(format!(
+                    "cannot read {}: {err}",
+                    path.display()
+                )))
+            }
+        };
+        let values = parse_toml(&text)
+            .map_err(|err| NoxError::Usage(format!("{}: {err}", path.display())))?;
+        Ok(Some(Self::new(path.display().to_string(), values)))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Sources {
+    layers: Vec<Layer>,
+    /// Keys looked up so far, in first-use order.
+    seen: RefCell<Vec<String>>,
+}
+
+impl Sources {
+    /// `layers` is ordered from highest to lowest precedence.
+    pub fn new(layers: Vec<Layer>) -> Self {
+        Self {
+            layers,
+            seen: RefCell::default(),
+        }
+    }
+
+    /// The standard stack: `flags` > env > `./nox.toml` > user config.
+    pub fn load(flags: Layer) -> Result<Self, NoxError> {
+        let mut layers = vec![flags, Layer::from_env()];
+        let mut files = vec![PathBuf::from("nox.toml")];
+        files.extend(user_config_path());
+        for path in files {
+            layers.extend(Layer::from_file(&path)?);
+        }
+        Ok(Self::new(layers))
+    }
+
+    fn lookup(&self, key: &str) -> Option<(&str, &Layer)> {
+        {
+            let mut seen = self.seen.borrow_mut();
+            if !seen.iter().any(|k| k == key) {
+                seen.push(key.to_string());
+            }
+        }
+        self.layers
+            .iter()
+            .find_map(|layer| layer.values.get(key).map(|v| (v.as_str(), layer)))
+    }
+
+    pub fn var(&self, key: &str) -> Option<String> {
+        self.lookup(key).map(|(v, _)| v.to_string())
+    }
+
+    fn parse<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.lookup(key).and_then(|(v, _)| v.trim().parse().ok())
+    }
+
+    pub fn u32(&self, key: &str) -> Option<u32> {
+        self.parse(key)
+    }
+
+    pub fn i32(&self, key: &str) -> Option<i32> {
+        self.parse(key)
+    }
+
+    pub fn u64(&self, key: &str) -> Option<u64> {
+        self.parse(key)
+    }
+
+    pub fn f32(&self, key: &str) -> Option<f32> {
+        self.parse(key)
+    }
+
+    pub fn bool(&self, key: &str) -> Option<bool> {
+        self.lookup(key).map(|(v, _)| {
+            let v = v.trim();
+            v == "1" || v.eq_ignore_ascii_case("true") || v.eq_ignore_ascii_case("yes")
+        })
+    }
+
+    pub fn path(&self, key: &str) -> Option<PathBuf> {
+        self.lookup(key).and_then(|(v, _)| {
+            let v = v.trim();
+            (!v.is_empty()).then(|| PathBuf::from(v))
+        })
+    }
+
+    /// Writes every key consulted so far with its value and origin.
+    pub fn print(&self, out: &mut dyn Write) -> io::Result<()> {
+        let seen = self.seen.borrow().clone();
+        let width = seen.iter().map(String::len).max().unwrap_or(0);
+        for key in &seen {
+            let found = self
+                .layers
+                .iter()
+                .find_map(|layer| layer.values.get(key).map(|v| (v, layer)));
+            match found {
+                Some((value, layer)) => {
+                    writeln!(out, "{key:width$} = {value:?}  # {}", layer.label)?
+                }
+                None => writeln!(out, "{key:width$}   (default)")?,
+            }
+        }
+        out.flush()
+    }
+}
+
+/// `$XDG_CONFIG_HOME/nox/config.toml`, falling back to `~/.config`.
+fn user_config_path() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(base.join("nox").join("config.toml"))
+}
+
+/// Parses the flat TOML subset into `NOX_*` keys.
+pub fn parse_toml(text: &str) -> Result<HashMap<String, String>, String> {
+    let mut values = HashMap::new();
+    for (idx, raw) in text.lines().enumerate() {
+        let lineno = idx + 1;
+        let line = raw.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            return Err(format!("line {lineno}: tables are not supported"));
+        }
+        let (key, rest) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {lineno}: expected `key = value`"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("line {lineno}: invalid key `{key}`"));
+        }
+        let value = parse_value(rest.trim()).map_err(|err| format!("line {lineno}: {err}"))?;
+        let name = format!("NOX_{}", key.replace('-', "_").to_ascii_uppercase());
+        if values.insert(name, value).is_some() {
+            return Err(format!("line {lineno}: duplicate key `{key}`"));
+        }
+    }
+    Ok(values)
+}
+
+fn parse_value(text: &str) -> Result<String, String> {
+    if let Some(body) = text.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return trailing_comment(chars.as_str()).map(|_| out),
+                '\\' => match chars.next() {
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    Some('r') => out.push('\r'),
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    other => {
+                        return Err(format!("unsupported escape `\\{}`", other.unwrap_or(' ')))
+                    }
+                },
+                c => out.push(c),
+            }
+        }
+        return Err("unterminated string".to_string());
+    }
+    if let Some(body) = text.strip_prefix('\'') {
+        let end = body
+            .find('\'')
+            .ok_or_else(|| "unterminated string".to_string())?;
+        trailing_comment(&body[end + 1..])?;
+        return Ok(body[..end].to_string());
+    }
+    let bare = text.split('#').next().unwrap_or_default().trim();
+    match bare {
+        "true" | "false" => Ok(bare.to_string()),
+        _ => {
+            let digits = bare.replace('_', "");
+            if digits.parse::<i64>().is_ok() || digits.parse::<f64>().is_ok() {
+                Ok(digits)
+            } else {
+                Err(format!("unsupported value `{bare}` (quote strings)"))
+            }
+        }
+    }
+}
+
+fn trailing_comment(rest: &str) -> Result<(), String> {
+    let rest = rest.trim();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("unexpected `{rest}` after value"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Config;
+
+    fn layer(label: &str, values: &[(&str, &str)]) -> Layer {
+        let values = values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        Layer::new(label, values)
+    }
+
+    /// The four layers, lowest precedence first: the user config and
+    /// `./nox.toml` read from real files, then env, then flags.
+    fn four_layers(dir: &Path) -> Vec<Layer> {
+        let user = dir.join("config.toml");
+        fs::write(&user, "ctx = 512\nmodel = \"user\"\ntemp = 0.1\n").unwrap();
+        let project = dir.join("nox.toml");
+        fs::write(
+            &project,
+            "# project\nctx = 1_536\nmodel = 'project'\ntemp = 0.2\n",
+        )
+        .unwrap();
+        vec![
+            Layer::from_file(&user).unwrap().unwrap(),
+            Layer::from_file(&project).unwrap().unwrap(),
+            layer(
+                "env",
+                &[
+                    ("NOX_CTX", "3072"),
+                    ("NOX_MODEL", "env"),
+                    ("NOX_TEMP", "0.3"),
+                ],
+            ),
+            layer(
+                "flags",
+                &[
+                    ("NOX_CTX", "4096"),
+                    ("NOX_MODEL", "flag"),
+                    ("NOX_TEMP", "0.4"),
+                ],
+            ),
+        ]
+    }
+
+    fn resolve(mut lowest_first: Vec<Layer>) -> (u32, Option<String>, f32) {
+        lowest_first.reverse();
+        let cfg = Config::resolve(&Sources::new(lowest_first));
+        (cfg.ctx, cfg.model_name, cfg.temp)
+    }
+
+    #[test]
+    fn flags_beat_env_beat_project_beat_user_beat_defaults() {
+        let dir = env::temp_dir().join(format!("nox-layers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let layers = four_layers(&dir);
+        let expected = [
+            (1024, None, 0.0),
+            (512, Some("user"), 0.1),
+            (1536, Some("project"), 0.2),
+            (3072, Some("env"), 0.3),
+            (4096, Some("flag"), 0.4),
+        ];
+        for (present, (ctx, model, temp)) in expected.into_iter().enumerate() {
+            let got = resolve(layers[..present].to_vec());
+            assert_eq!(
+                got,
+                (ctx, model.map(str::to_string), temp),
+                "{present} layers"
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn each_key_falls_through_to_the_highest_layer_that_sets_it() {
+        let (ctx, model, temp) = resolve(vec![
+            layer("user", &[("NOX_TEMP", "0.7")]),
+            layer("project", &[("NOX_MODEL", "project")]),
+            layer("env", &[]),
+            layer("flags", &[("NOX_CTX", "2048")]),
+        ]);
+        assert_eq!((ctx, model.as_deref(), temp), (2048, Some("project"), 0.7));
+    }
+
+    #[test]
+    fn print_config_names_where_each_value_came_from() {
+        let sources = Sources::new(vec![
+            layer("flags", &[("NOX_CTX", "2048")]),
+            layer("./nox.toml", &[("NOX_CTX", "512"), ("NOX_TEMP", "0.5")]),
+        ]);
+        sources.u32("NOX_CTX");
+        sources.f32("NOX_TEMP");
+        sources.var("NOX_MODEL");
+        let mut out = Vec::new();
+        sources.print(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "NOX_CTX   = \"2048\"  # flags\n\
+             NOX_TEMP  = \"0.5\"  # ./nox.toml\n\
+             NOX_MODEL   (default)\n"
+        );
+    }
+
+    #[test]
+    fn the_toml_subset_rejects_what_it_cannot_read() {
+        let values =
+            parse_toml("stall-secs = 30 # comment\nraw = true\nstop = \"a\\nb\"\n").unwrap();
+        assert_eq!(values["NOX_STALL_SECS"], "30");
+        assert_eq!(values["NOX_RAW"], "true");
+        assert_eq!(values["NOX_STOP"], "a\nb");
+        for (text, err) in [
+            ("[nox]\n", "line 1: tables are not supported"),
+            ("ctx 1\n", "line 1: expected `key = value`"),
+            ("ctx = 1\nctx = 2\n", "line 2: duplicate key `ctx`"),
+            (
+                "model = tiny\n",
+                "line 1: unsupported value `tiny` (quote strings)",
+            ),
+            ("model = \"tiny\n", "line 1: unterminated string"),
+        ] {
+            assert_eq!(parse_toml(text).unwrap_err(), err, "{text:?}");
+        }
+    }
+}
@@ -0,0 +1,132 @@
+//! Constrained output. `NOX_GRAMMAR_FILE` / `NOX_JSON_SCHEMA_FILE` are
+//! forwarded to llama-completion, which enforces them while sampling. Other
+//! styles cannot, so with a JSON schema the response is buffered, checked to
+//! be valid JSON, and the prompt re-run with a corrective suffix up to
+//! `NOX_JSON_RETRIES` times.
+
+use std::fs;
+use std::io::{self, Write};
+
+use crate::error::NoxError;
+use crate::json::Json;
+use crate::{Config, RunStats, RunnerStyle};
+
+const CORRECTIVE_SUFFIX: &str = "\n\nYour previous answer was not valid JSON. \
+Reply with a single JSON value only: no prose, no code fences.";
+
+/// Rejects contradictory or unusable constraint settings before anything runs.
+pub fn validate(cfg: &Config) -> Result<(), NoxError> {
+    if cfg.grammar_file.is_some() && cfg.json_schema_file.is_some() {
+        return Err(NoxError::Usage(
+            "NOX_GRAMMAR_FILE and NOX_JSON_SCHEMA_FILE are mutually exclusive".to_string(),
+        ));
+    }
+    if cfg.grammar_file.is_some() && !enforced(cfg) {
+        return Err(NoxError::Usage(format!(
+            "NOX_GRAMMAR_FILE needs NOX_RUNNER_STYLE=llama (the {} runner cannot enforce a grammar)",
+            cfg.runner_style.name()
+        )));
+    }
+    for (var, path) in [
+        ("NOX_GRAMMAR_FILE", &cfg.grammar_file),
+        ("NOX_JSON_SCHEMA_FILE", &cfg.json_schema_file),
+    ] {
+        if let Some(path) = path {
+            if !path.is_file() {
+                return Err(NoxError::Usage(format!(
+                    "{var}: {} does not exist",
+                    path.display()
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether the runner applies the constraint itself while sampling.
+fn enforced(cfg: &Config) -> bool {
+    matches!(cfg.runner_style, RunnerStyle::LlamaCompletion) && !cfg.emulate_a1000
+}
+
+/// Runs `prompt` through `attempt` in JSON mode and reports whether the final
+/// output parsed. Enforced styles stream straight to `out`; the rest are
+/// buffered so an invalid answer can be retried before anything is shown.
+pub fn generate_json(
+    cfg: &Config,
+    prompt: &str,
+    out: &mut dyn Write,
+    attempt: &mut dyn FnMut(&str, &mut dyn Write) -> Result<RunStats, NoxError>,
+) -> Result<(RunStats, bool), NoxError> {
+    if enforced(cfg) {
+        let mut copy = Capture {
+            out,
+            seen: Vec::new(),
+        };
+        let stats = attempt(prompt, &mut copy)?;
+        let valid = check(&copy.seen).is_ok();
+        return Ok((stats, valid));
+    }
+
+    let mut current = prompt.to_string();
+    let attempts = cfg.json_retries + 1;
+    for n in 1..=attempts {
+        let mut buf = Vec::new();
+        let stats = attempt(&current, &mut buf)?;
+        match check(&buf) {
+            Ok(()) => {
+                out.write_all(&buf)?;
+                out.flush()?;
+                return Ok((stats, true));
+            }
+            Err(err) if n < attempts => {
+                eprintln!(
+                    "nox: output is not valid JSON ({err}); retry {n}/{}",
+                    cfg.json_retries
+                );
+                current = corrective_prompt(cfg, prompt);
+            }
+            Err(err) => {
+                eprintln!(
+                    "nox: output is not valid JSON ({err}); giving up after {attempts} attempt(s)"
+                );
+                out.write_all(&buf)?;
+                out.flush()?;
+                return Ok((stats, false));
+            }
+        }
+    }
+    unreachable!("the last attempt always returns")
+}
+
+fn corrective_prompt(cfg: &Config, prompt: &str) -> String {
+    let schema = cfg
+        .json_schema_file
+        .as_ref()
+        .and_then(|p| fs::read_to_string(p).ok())
+        .map(|s| format!(" It must match this JSON schema:\n{}", s.trim()))
+        .unwrap_or_default();
+    format!("{prompt}{CORRECTIVE_SUFFIX}{schema}")
+}
+
+fn check(output: &[u8]) -> Result<(), String> {
+    let text = std::str::from_utf8(output).map_err(|_| "not utf-8".to_string())?;
+    Json::parse(text.trim()).map(|_| ())
+}
+
+/// Passes writes through to `out` while keeping a copy for validation.
+struct Capture<'a> {
+    out: &'a mut dyn Write,
+    seen: Vec<u8>,
+}
+
+impl Write for Capture<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.seen.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
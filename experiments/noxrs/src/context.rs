@@ -0,0 +1,282 @@
+//! Fits a multi-turn conversation into the context window. [`ContextBudget`]
+//! drops the oldest turns until the rendered prompt's estimate leaves room
+//! for `NOX_MAX_TOKENS` within `NOX_CTX`. The system prompt and the latest
+//! turn are always kept, even when they alone do not fit.
+
+use crate::tokens;
+use crate::Config;
+
+/// Stands in for dropped turns when `NOX_OMIT_MARKER` is on (the default).
+pub const OMITTED: &str = "[...earlier conversation omitted...]";
+
+/// Ends every rendered prompt so the runner continues as the assistant.
+const ANSWER_CUE: &str = "Assistant:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    User,
+    Assistant,
+}
+
+impl Role {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "user" => Some(Role::User),
+            "assistant" => Some(Role::Assistant),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Role::User => "User",
+            Role::Assistant => "Assistant",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Turn {
+    pub role: Role,
+    pub text: String,
+}
+
+impl Turn {
+    pub fn new(role: Role, text: impl Into<String>) -> Self {
+        Self {
+            role,
+            text: text.into(),
+        }
+    }
+
+    /// `User: text` plus the newline ending it.
+    fn line(&self) -> String {
+        format!("{}: {}\n", self.role.label(), self.text.trim())
+    }
+}
+
+/// A system prompt followed by the turns so far, the last one being the
+/// message to answer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conversation {
+    pub system: Option<String>,
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ContextBudget {
+    /// Tokens the prompt may use: `ctx - max_tokens`.
+    pub limit: usize,
+    pub chars_per_token: f32,
+    pub marker: bool,
+}
+
+/// The turns that survived, ready to render.
+#[derive(Debug)]
+pub struct Fitted<'a> {
+    pub system: Option<&'a str>,
+    pub turns: &'a [Turn],
+    pub dropped: usize,
+    /// Whether the result is within the budget; false only when the system
+    /// prompt and latest turn alone are too long.
+    pub fits: bool,
+    marker: bool,
+}
+
+impl ContextBudget {
+    pub fn new(cfg: &Config) -> Self {
+        Self {
+            limit: cfg.ctx.saturating_sub(cfg.max_tokens) as usize,
+            chars_per_token: cfg.chars_per_token,
+            marker: cfg.omit_marker,
+        }
+    }
+
+    /// Drops turns from the front of `convo` until the estimate fits.
+    pub fn fit<'a>(&self, convo: &'a Conversation) -> Fitted<'a> {
+        let system = convo
+            .system
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty());
+        let turns = &convo.turns;
+        // Rendering is a concatenation, so the cost of each candidate start
+        // is a running sum of per-turn lengths.
+        let fixed = system.map_or(0, |s| s.chars().count() + 2) + ANSWER_CUE.len();
+        let marker_len = OMITTED.len() + 1;
+        let lens: Vec<usize> = turns.iter().map(|t| t.line().chars().count()).collect();
+        let mut tail: usize = lens.iter().sum();
+        let keep_from = turns.len().saturating_sub(1);
+        let mut start = 0;
+        loop {
+            let chars = fixed
+                + tail
+                + if start > 0 && self.marker {
+                    marker_len
+                } else {
+                    0
+                };
+            let fits = tokens::estimate_chars(chars, self.chars_per_token) <= self.limit;
+            if fits || start >= keep_from {
+                return Fitted {
+                    system,
+                    turns: &turns[start..],
+                    dropped: start,
+                    fits,
+                    marker: self.marker,
+                };
+            }
+            tail -= lens[start];
+            start += 1;
+        }
+    }
+}
+
+impl Fitted<'_> {
+    /// The system prompt, a blank line, one `Role: text` line per turn, and
+    /// a trailing `Assistant:` cue. The blank line keeps the system prompt
+    /// as the prefix `NOX_STATE_AUTO` caches.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        if let Some(system) = self.system {
+            out.push_str(system);
+            out.push_str("\n\n");
+        }
+        if self.dropped > 0 && self.marker {
+            out.push_str(OMITTED);
+            out.push('\n');
+        }
+        for turn in self.turns {
+            out.push_str(&turn.line());
+        }
+        out.push_str(ANSWER_CUE);
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+
+    const SYSTEM: &str = "You are a careful assistant.";
+
+    /// `n` alternating turns of uneven length, ending on a user message.
+    fn history(n: usize) -> Conversation {
+        let turns = (0..n)
+            .map(|i| {
+                let role = if (n - 1 - i).is_multiple_of(2) {
+                    Role::User
+                } else {
+                    Role::Assistant
+                };
+                Turn::new(role, format!("turn {i} ") + &"word ".repeat(i * 7 % 40))
+            })
+            .collect();
+        Conversation {
+            system: Some(SYSTEM.to_string()),
+            turns,
+        }
+    }
+
+    fn budget(limit: usize, marker: bool) -> ContextBudget {
+        ContextBudget {
+            limit,
+            chars_per_token: 4.0,
+            marker,
+        }
+    }
+
+    fn assert_invariants(budget: &ContextBudget, convo: &Conversation) -> usize {
+        let fitted = budget.fit(convo);
+        let prompt = fitted.render();
+        let latest = convo.turns.last().unwrap();
+        assert!(prompt.starts_with(&format!("{SYSTEM}\n\n")), "{prompt}");
+        assert_eq!(fitted.turns.last(), Some(latest));
+        assert!(prompt.ends_with(&format!("{}Assistant:", latest.line())));
+        assert_eq!(fitted.dropped + fitted.turns.len(), convo.turns.len());
+        assert_eq!(convo.turns[fitted.dropped..], *fitted.turns);
+        assert_eq!(
+            prompt.contains(OMITTED),
+            budget.marker && fitted.dropped > 0
+        );
+        let used = tokens::estimate(&prompt, budget.chars_per_token);
+        if fitted.fits {
+            assert!(used <= budget.limit, "{used} > {}", budget.limit);
+        } else {
+            assert_eq!(fitted.turns.len(), 1);
+        }
+        // Keeping one more turn would not have fit.
+        if fitted.dropped > 0 {
+            let wider = Fitted {
+                turns: &convo.turns[fitted.dropped - 1..],
+                dropped: fitted.dropped - 1,
+                ..fitted
+            };
+            let wider_used = tokens::estimate(&wider.render(), budget.chars_per_token);
+            assert!(wider_used > budget.limit, "dropped one turn too many");
+        }
+        fitted.dropped
+    }
+
+    #[test]
+    fn long_histories_keep_system_and_latest_within_budget() {
+        for n in [1, 2, 7, 60, 301] {
+            let convo = history(n);
+            for limit in [0, 10, 40, 100, 333, 1000, 100_000] {
+                for marker in [true, false] {
+                    assert_invariants(&budget(limit, marker), &convo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn a_tighter_budget_never_drops_fewer_turns() {
+        let convo = history(120);
+        let dropped: Vec<usize> = (0..60)
+            .rev()
+            .map(|i| assert_invariants(&budget(i * 100, true), &convo))
+            .collect();
+        assert!(dropped.windows(2).all(|w| w[0] <= w[1]), "{dropped:?}");
+        assert_eq!(*dropped.first().unwrap(), 0);
+        assert_eq!(*dropped.last().unwrap(), 119);
+    }
+
+    #[test]
+    fn an_oversized_latest_turn_is_kept_but_does_not_fit() {
+        let mut convo = history(10);
+        convo.turns.push(Turn::new(Role::User, "x".repeat(10_000)));
+        let fitted = budget(500, true).fit(&convo);
+        assert!(!fitted.fits);
+        assert_eq!(fitted.dropped, 10);
+        assert!(fitted
+            .render()
+            .starts_with(&format!("{SYSTEM}\n\n{OMITTED}\nUser: xxx")));
+    }
+
+    #[test]
+    fn the_budget_leaves_room_for_the_answer() {
+        let cfg = config(&[
+            ("NOX_CTX", "2048"),
+            ("NOX_MAX_TOKENS", "512"),
+            ("NOX_OMIT_MARKER", "0"),
+        ]);
+        let budget = ContextBudget::new(&cfg);
+        assert_eq!(budget.limit, 1536);
+        assert!(!budget.marker);
+        let tiny = config(&[("NOX_CTX", "256"), ("NOX_MAX_TOKENS", "512")]);
+        assert_eq!(ContextBudget::new(&tiny).limit, 0);
+    }
+
+    #[test]
+    fn a_blank_system_prompt_is_left_out() {
+        let convo = Conversation {
+            system: Some(" \n".to_string()),
+            turns: vec![Turn::new(Role::User, "  hi  ")],
+        };
+        let fitted = budget(100, true).fit(&convo);
+        assert_eq!(fitted.system, None);
+        assert_eq!(fitted.render(), "User: hi\nAssistant:");
+    }
+}
@@ -0,0 +1,47 @@
+//! `--dry-run` / `NOX_DRY_RUN=1`: prints the runner invocation that would be
+//! spawned, quoted so it can be pasted straight into bash.
+
+use std::io::{self, Write};
+use std::process::Command;
+
+/// Quotes `arg` for a POSIX shell. Plain words stay bare; everything else is
+/// single-quoted, which also keeps newlines intact.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Renders `cmd` as `ENV=.. program args..`, piping `stdin` in when the
+/// prompt travels that way.
+pub fn command_line(cmd: &Command, stdin: Option<&str>) -> String {
+    let mut words = Vec::new();
+    for (key, value) in cmd.get_envs() {
+        if let Some(value) = value {
+            words.push(format!(
+                "{}={}",
+                key.to_string_lossy(),
+                shell_quote(&value.to_string_lossy())
+            ));
+        }
+    }
+    words.push(shell_quote(&cmd.get_program().to_string_lossy()));
+    words.extend(cmd.get_args().map(|a| shell_quote(&a.to_string_lossy())));
+    let line = words.join(" ");
+    match stdin {
+        Some(prompt) => format!("printf '%s' {} | {line}", shell_quote(prompt)),
+        None => line,
+    }
+}
+
+/// Writes the report: `#` comment lines for context, then the command.
+pub fn print(notes: &[String], cmd: &Command, stdin: Option<&str>) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    for note in notes {
+        writeln!(out, "# {note}")?;
+    }
+    writeln!(out, "{}", command_line(cmd, stdin))?;
+    out.flush()
+}
@@ -0,0 +1,183 @@
+//! Prompt echo suppression. llama-simple prints the prompt back before the
+//! completion; [`EchoStrip`] holds back the start of the output until it can
+//! tell whether it is that echo and drops it if so. Whitespace is compared
+//! collapsed because the tokenizer round-trip normalizes it. At most
+//! `prompt + HOLD_SLACK` bytes are held, so long generations still stream.
+
+use std::io::{self, Write};
+
+/// Extra bytes beyond the prompt length that may be held while matching.
+const HOLD_SLACK: usize = 64;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Match {
+    /// The whole prompt was echoed in the first `n` bytes.
+    Echo(usize),
+    /// Everything so far matches, but the prompt is not finished yet.
+    Partial,
+    Mismatch,
+}
+
+/// Compares `output` against `prompt`, treating every whitespace run as one
+/// separator and ignoring leading whitespace in the output.
+fn match_echo(prompt: &[u8], output: &[u8]) -> Match {
+    let mut i = 0;
+    let mut j = output
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(output.len());
+    while i < prompt.len() {
+        if j == output.len() {
+            return Match::Partial;
+        }
+        if prompt[i].is_ascii_whitespace() {
+            if !output[j].is_ascii_whitespace() {
+                return Match::Mismatch;
+            }
+            while i < prompt.len() && prompt[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            while j < output.len() && output[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            continue;
+        }
+        if prompt[i] != output[j] {
+            return Match::Mismatch;
+        }
+        i += 1;
+        j += 1;
+    }
+    Match::Echo(j)
+}
+
+/// A writer that drops a leading echo of `prompt` and passes everything
+/// else through unchanged. Call [`EchoStrip::finish`] once the output ends
+/// so bytes still being held are written.
+pub struct EchoStrip<'a> {
+    out: &'a mut dyn Write,
+    /// `None` once the echo has been dropped or ruled out.
+    prompt: Option<String>,
+    held: Vec<u8>,
+}
+
+impl<'a> EchoStrip<'a> {
+    /// With `prompt` unset the writer is a plain pass-through.
+    pub fn new(out: &'a mut dyn Write, prompt: Option<&str>) -> Self {
+        Self {
+            out,
+            prompt: prompt
+                .map(|p| p.trim().to_string())
+                .filter(|p| !p.is_empty()),
+            held: Vec::new(),
+        }
+    }
+
+    /// Writes out anything still held back.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.prompt.take().is_some() {
+            self.out.write_all(&self.held)?;
+            self.held.clear();
+        }
+        self.out.flush()
+    }
+}
+
+impl Write for EchoStrip<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(prompt) = &self.prompt else {
+            self.out.write_all(buf)?;
+            return Ok(buf.len());
+        };
+        self.held.extend_from_slice(buf);
+        let keep_from = match match_echo(prompt.as_bytes(), &self.held) {
+            Match::Echo(n) => n,
+            Match::Mismatch => 0,
+            Match::Partial if self.held.len() > prompt.len() + HOLD_SLACK => 0,
+            Match::Partial => return Ok(buf.len()),
+        };
+        self.prompt = None;
+        let held = std::mem::take(&mut self.held);
+        self.out.write_all(&held[keep_from..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.prompt.is_some() {
+            return Ok(());
+        }
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROMPT: &str = "Write a haiku about\nthe   sea.";
+
+    /// llama-simple's stdout for `PROMPT`: the prompt as the tokenizer gave
+    /// it back, then the completion.
+    const TRANSCRIPT: &str = " Write a haiku about\nthe sea. Waves fold into foam,\n\
+                              salt wind combs the grey water,\nthe tide keeps its time.\n";
+
+    /// Feeds `output` in `chunk`-byte writes, as a pipe would deliver it.
+    fn strip(prompt: Option<&str>, output: &str, chunk: usize) -> String {
+        let mut out = Vec::new();
+        let mut strip = EchoStrip::new(&mut out, prompt);
+        for piece in output.as_bytes().chunks(chunk) {
+            strip.write_all(piece).unwrap();
+        }
+        strip.finish().unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn the_echoed_prompt_is_dropped_however_the_output_is_cut() {
+        for chunk in [1, 3, 7, 64, TRANSCRIPT.len()] {
+            assert_eq!(
+                strip(Some(PROMPT), TRANSCRIPT, chunk),
+                " Waves fold into foam,\nsalt wind combs the grey water,\nthe tide keeps its time.\n",
+                "{chunk}-byte writes"
+            );
+        }
+    }
+
+    #[test]
+    fn a_completion_starting_with_the_prompts_first_word_is_untouched() {
+        let output = "Write it yourself, I am busy.";
+        assert_eq!(strip(Some(PROMPT), output, 4), output);
+        let output = "Write a haiku about the moon instead.";
+        assert_eq!(strip(Some(PROMPT), output, 4), output);
+    }
+
+    #[test]
+    fn output_that_ends_mid_echo_is_written_on_finish() {
+        assert_eq!(strip(Some(PROMPT), "Write a haiku", 2), "Write a haiku");
+    }
+
+    #[test]
+    fn without_a_prompt_everything_passes_through() {
+        assert_eq!(strip(None, TRANSCRIPT, 5), TRANSCRIPT);
+        assert_eq!(strip(Some("  \n"), TRANSCRIPT, 5), TRANSCRIPT);
+    }
+
+    #[test]
+    fn no_more_than_the_prompt_plus_slack_is_held() {
+        // Whitespace keeps matching the prompt's first gap without ending it.
+        let prompt = "a b";
+        let output = format!("a{}b!", " ".repeat(200));
+        let mut out = Vec::new();
+        let mut strip = EchoStrip::new(&mut out, Some(prompt));
+        let mut released_at = None;
+        for (n, byte) in output.bytes().enumerate() {
+            strip.write_all(&[byte]).unwrap();
+            if released_at.is_none() && strip.held.is_empty() {
+                released_at = Some(n + 1);
+            }
+        }
+        strip.finish().unwrap();
+        assert_eq!(released_at, Some(prompt.len() + HOLD_SLACK + 1));
+        assert_eq!(String::from_utf8(out).unwrap(), output);
+    }
+}
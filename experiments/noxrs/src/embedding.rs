@@ -0,0 +1,214 @@
+//! `NOX_RUNNER_STYLE=embedding`: runs `llama-embedding` and turns its JSON
+//! output into one vector per prompt. Prompts split on the route delimiter
+//! go to a single runner invocation via `--embd-separator`.
+
+use std::io::Write;
+
+use crate::error::NoxError;
+use crate::json::Json;
+use crate::{generate, Config};
+
+/// How much raw runner output a parse error quotes.
+const RAW_PREVIEW: usize = 200;
+
+/// Separator handed to `--embd-separator`; the route delimiter when set,
+/// otherwise one llama-embedding will not find inside ordinary text.
+pub fn separator(cfg: &Config) -> &str {
+    let delim = cfg.route_delim.trim();
+    if delim.is_empty() {
+        "\u{1e}"
+    } else {
+        delim
+    }
+}
+
+/// Splits `prompt` into the individual texts to embed.
+pub fn split_prompts<'a>(cfg: &Config, prompt: &'a str) -> Vec<&'a str> {
+    prompt
+        .split(separator(cfg))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Embeds every prompt in `prompt` and returns the vectors in input order.
+pub fn embed(cfg: &Config, prompt: &str) -> Result<Vec<Vec<f32>>, NoxError> {
+    if cfg.emulate_a1000 {
+        return Err(NoxError::Usage(
+            "the simulator does not produce embeddings; unset NOX_EMULATE_A1000".to_string(),
+        ));
+    }
+    let (joined, count) = join_prompts(cfg, prompt)?;
+    let mut raw = Vec::new();
+    generate(cfg, &joined, &mut raw)?;
+    let vectors = parse_vectors(&raw)?;
+    if vectors.len() != count {
+        return Err(bad_output(
+            &format!(
+                "expected {count} embeddings, runner returned {}",
+                vectors.len()
+            ),
+            &raw,
+        ));
+    }
+    Ok(vectors)
+}
+
+/// The runner prompt for `prompt` and how many vectors it should yield.
+pub fn join_prompts(cfg: &Config, prompt: &str) -> Result<(String, usize), NoxError> {
+    let prompts = split_prompts(cfg, prompt);
+    if prompts.is_empty() {
+        return Err(NoxError::Usage("empty prompt".to_string()));
+    }
+    Ok((prompts.join(separator(cfg)), prompts.len()))
+}
+
+/// Parses `--embd-output-format json` output (`{"data":[{"embedding":[..]}]}`).
+/// A bare array or array of arrays is accepted as well.
+pub fn parse_vectors(raw: &[u8]) -> Result<Vec<Vec<f32>>, NoxError> {
+    let text = String::from_utf8_lossy(raw);
+    // llama.cpp may print log lines before the document; start at the first brace.
+    let start = text.find(['{', '[']).unwrap_or(0);
+    let doc = Json::parse(text[start..].trim())
+        .map_err(|err| bad_output(&format!("invalid embedding JSON: {err}"), raw))?;
+
+    let rows: Vec<&Json> = match &doc {
+        Json::Obj(_) => {
+            let data = doc
+                .get("data")
+                .and_then(Json::as_array)
+                .ok_or_else(|| bad_output("embedding JSON has no \"data\" array", raw))?;
+            data.iter()
+                .map(|item| {
+                    item.get("embedding")
+                        .ok_or_else(|| bad_output("data entry has no \"embedding\" field", raw))
+                })
+                .collect::<Result<_, _>>()?
+        }
+        Json::Arr(items) if items.iter().all(|v| matches!(v, Json::Arr(_))) => {
+            items.iter().collect()
+        }
+        Json::Arr(_) => vec![&doc],
+        _ => return Err(bad_output("unexpected embedding JSON", raw)),
+    };
+
+    rows.into_iter()
+        .map(|row| {
+            row.as_array()
+                .ok_or_else(|| bad_output("embedding is not an array", raw))?
+                .iter()
+                .map(|v| {
+                    v.as_f64()
+                        .map(|n| n as f32)
+                        .ok_or_else(|| bad_output("embedding holds a non-number", raw))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Writes one vector per line, as a JSON array or space-separated floats.
+pub fn write_vectors(out: &mut dyn Write, vectors: &[Vec<f32>], json: bool) -> std::io::Result<()> {
+    for vector in vectors {
+        let values: Vec<String> = vector.iter().map(|v| v.to_string()).collect();
+        if json {
+            writeln!(out, "[{}]", values.join(","))?;
+        } else {
+            writeln!(out, "{}", values.join(" "))?;
+        }
+    }
+    out.flush()
+}
+
+fn bad_output(what: &str, raw: &[u8]) -> NoxError {
+    let preview = String::from_utf8_lossy(&raw[..raw.len().min(RAW_PREVIEW)]);
+    NoxError::BadOutput(format!("{what}; raw output starts: {preview:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+
+    /// `llama-embedding --embd-output-format json` for two prompts, with the
+    /// log lines it leaves on stdout when stderr is redirected.
+    const FIXTURE: &str = r#"llama_model_loader: loaded meta data with 23 key-value pairs
+system_info: n_threads = 4 / 8 | AVX = 1 | AVX2 = 1 |
+{
+  "object": "list",
+  "data": [
+    {
+      "object": "embedding",
+      "index": 0,
+      "embedding": [0.0123, -0.5, 1e-3, 2]
+    },
+    {
+      "object": "embedding",
+      "index": 1,
+      "embedding": [-0.25, 0.75, 0, -1.5E2]
+    }
+  ],
+  "cosineSimilarity": [[1.00, 0.12], [0.12, 1.00]]
+}
+"#;
+
+    #[test]
+    fn the_llama_embedding_fixture_yields_one_vector_per_prompt() {
+        let vectors = parse_vectors(FIXTURE.as_bytes()).unwrap();
+        assert_eq!(
+            vectors,
+            [
+                vec![0.0123, -0.5, 0.001, 2.0],
+                vec![-0.25, 0.75, 0.0, -150.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn bare_arrays_parse_too() {
+        assert_eq!(parse_vectors(b"[1, 2]").unwrap(), [vec![1.0, 2.0]]);
+        assert_eq!(
+            parse_vectors(b"[[1], [2]]").unwrap(),
+            [vec![1.0], vec![2.0]]
+        );
+    }
+
+    #[test]
+    fn parse_errors_quote_the_first_200_bytes() {
+        let head = "error: model has no pooling layer\n";
+        let raw = format!("{head}{}", "z".repeat(500));
+        let err = parse_vectors(raw.as_bytes()).unwrap_err();
+        assert_eq!(err.exit_code(), 8);
+        let msg = err.to_string();
+        assert!(msg.contains("invalid embedding JSON"), "{msg}");
+        let quoted = format!("{:?}", &raw[..200]);
+        assert!(msg.ends_with(&quoted), "{msg}");
+
+        let err = parse_vectors(br#"{"data": [{"vector": []}]}"#).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("data entry has no \"embedding\" field"));
+        let err = parse_vectors(br#"[["x"]]"#).unwrap_err();
+        assert!(err.to_string().contains("non-number"), "{err}");
+    }
+
+    #[test]
+    fn prompts_split_on_the_route_delimiter() {
+        let cfg = config(&[("NOX_ROUTE_DELIM", "---")]);
+        let (joined, count) = join_prompts(&cfg, " cats ---\n dogs --- --- ").unwrap();
+        assert_eq!((joined.as_str(), count), ("cats---dogs", 2));
+        assert_eq!(join_prompts(&cfg, " --- ").unwrap_err().exit_code(), 2);
+    }
+
+    #[test]
+    fn vectors_print_as_json_or_floats() {
+        let vectors = [vec![0.5, -1.0], vec![2.0, 0.25]];
+        let mut out = Vec::new();
+        write_vectors(&mut out, &vectors, true).unwrap();
+        write_vectors(&mut out, &vectors, false).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "[0.5,-1]\n[2,0.25]\n0.5 -1\n2 0.25\n"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Failure classes and the exit-code contract scripts can rely on:
+//! 0 ok, 2 usage, 3 runner missing, 4 model missing, 5 spawn failed,
+//! 6 runner exited non-zero, 7 timeout/stall, 8 unparseable runner output,
+//! 130 interrupted.
+
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+
+use crate::models::Checked;
+
+#[derive(Debug)]
+pub enum NoxError {
+    Usage(String),
+    RunnerNotFound {
+        style: &'static str,
+        checked: Vec<PathBuf>,
+    },
+    ModelNotFound {
+        /// The setting that asked for a model, e.g. `NOX_MODEL_PATH=/x.gguf`.
+        setting: Option<String>,
+        checked: Vec<Checked>,
+    },
+    Spawn {
+        runner: PathBuf,
+        source: io::Error,
+    },
+    RunnerFailed(ExitStatus),
+    LimitExceeded(String),
+    Timeout(String),
+    BadOutput(String),
+    Interrupted,
+    Io(io::Error),
+}
+
+impl NoxError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            NoxError::Io(_) => 1,
+            NoxError::Usage(_) => 2,
+            NoxError::RunnerNotFound { .. } => 3,
+            NoxError::ModelNotFound { .. } => 4,
+            NoxError::Spawn { .. } => 5,
+            NoxError::RunnerFailed(_) | NoxError::LimitExceeded(_) => 6,
+            NoxError::Timeout(_) => 7,
+            NoxError::BadOutput(_) => 8,
+            NoxError::Interrupted => 130,
+        }
+    }
+}
+
+impl fmt::Display for NoxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NoxError::Usage(msg) => write!(f, "{msg}"),
+            NoxError::RunnerNotFound { style, checked } => write!(
+                f,
+                "no {style} runner binary found (checked: {})",
+                join_paths(checked)
+            ),
+            NoxError::ModelNotFound { setting, checked } => {
+                write!(f, "model not found")?;
+                if let Some(setting) = setting {
+                    write!(f, " ({setting})")?;
+                }
+                match checked.len() {
+                    0 => write!(f, "; nothing to check"),
+                    1 => write!(f, "; 1 path rejected"),
+                    n => write!(f, "; {n} paths rejected"),
+                }
+            }
+            NoxError::Spawn { runner, source } => {
+                write!(f, "failed to spawn {}: {source}", runner.display())
+            }
+            NoxError::RunnerFailed(status) => write!(f, "runner exited with {status}"),
+            NoxError::LimitExceeded(msg) => write!(f, "{msg}"),
+            NoxError::Timeout(msg) => write!(f, "{msg}"),
+            NoxError::BadOutput(msg) => write!(f, "{msg}"),
+            NoxError::Interrupted => write!(f, "interrupted"),
+            NoxError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl NoxError {
+    /// Lines to print under the one-line reason: every rejected model path,
+    /// then a hint.
+    pub fn details(&self) -> Vec<String> {
+        match self {
+            NoxError::ModelNotFound { checked, .. } => checked
+                .iter()
+                .map(|c| format!("  {}: {}", c.path.display(), c.reason.as_str()))
+                .chain([MODEL_HINT.to_string()])
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The rejected model paths behind a [`NoxError::ModelNotFound`].
+    pub fn model_candidates(&self) -> Option<&[Checked]> {
+        match self {
+            NoxError::ModelNotFound { checked, .. } => Some(checked),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NoxError {
+    fn from(err: io::Error) -> Self {
+        NoxError::Io(err)
+    }
+}
+
+const MODEL_HINT: &str = "hint: set NOX_MODEL_PATH to a .gguf file, or put one in \
+                          assets/models/ (NOX_MODELS_DIR adds a directory)";
+
+fn join_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "nothing".to_string();
+    }
+    paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_follow_the_contract() {
+        let cases = [
+            (NoxError::Io(io::Error::other("disk")), 1),
+            (NoxError::Usage("empty prompt".to_string()), 2),
+            (
+                NoxError::RunnerNotFound {
+                    style: "noxlocal",
+                    checked: Vec::new(),
+                },
+                3,
+            ),
+            (
+                NoxError::ModelNotFound {
+                    setting: None,
+                    checked: Vec::new(),
+                },
+                4,
+            ),
+            (
+                NoxError::Spawn {
+                    runner: PathBuf::from("bin/noxlocal"),
+                    source: io::Error::from(io::ErrorKind::PermissionDenied),
+                },
+                5,
+            ),
+            (NoxError::LimitExceeded("out of memory".to_string()), 6),
+            (NoxError::Timeout("runner stalled".to_string()), 7),
+            (NoxError::BadOutput("no vectors".to_string()), 8),
+            (NoxError::Interrupted, 130),
+        ];
+        for (err, code) in cases {
+            assert_eq!(err.exit_code(), code, "{err}");
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_failed_runner_exits_6() {
+        use std::os::unix::process::ExitStatusExt;
+        let err = NoxError::RunnerFailed(ExitStatus::from_raw(3 << 8));
+        assert_eq!(err.exit_code(), 6);
+        assert_eq!(err.to_string(), "runner exited with exit status: 3");
+    }
+
+    #[test]
+    fn a_missing_runner_lists_every_path_checked() {
+        let err = NoxError::RunnerNotFound {
+            style: "noxlocal",
+            checked: vec![
+                PathBuf::from("bin/noxlocal"),
+                PathBuf::from("/opt/noxlocal"),
+            ],
+        };
+        assert_eq!(
+            err.to_string(),
+            "no noxlocal runner binary found (checked: bin/noxlocal, /opt/noxlocal)"
+        );
+    }
+
+    #[test]
+    fn a_missing_model_is_one_line_with_the_paths_in_details() {
+        use crate::models::Reason;
+        let err = NoxError::ModelNotFound {
+            setting: Some("NOX_MODEL=tiny".to_string()),
+            checked: vec![
+                Checked {
+                    path: PathBuf::from("assets/models"),
+                    reason: Reason::NotADirectory,
+                },
+                Checked {
+                    path: PathBuf::from("/srv/big.gguf"),
+                    reason: Reason::NoNameMatch,
+                },
+            ],
+        };
+        assert_eq!(
+            err.to_string(),
+            "model not found (NOX_MODEL=tiny); 2 paths rejected"
+        );
+        let details = err.details();
+        assert_eq!(details[0], "  assets/models: not a readable directory");
+        assert_eq!(details[1], "  /srv/big.gguf: does not match NOX_MODEL");
+        assert!(details[2].starts_with("hint: set NOX_MODEL_PATH"));
+        assert!(NoxError::Interrupted.details().is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+//! `NOX_EXTRA_ARGS` / `--extra-arg`: extra runner arguments appended after
+//! the generated flags and before the prompt. When the user passes a flag
+//! nox already generates (say a second `-ctx`), the generated one and its
+//! value are dropped so the user's wins.
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+use crate::config::Sources;
+use crate::error::NoxError;
+use crate::template;
+use crate::Config;
+
+/// Words from `NOX_EXTRA_ARGS` (split like a shell would), then each
+/// `--extra-arg` value as-is.
+pub fn resolve(src: &Sources, flags: &[String]) -> Result<Vec<String>, NoxError> {
+    let mut args = match src.var("NOX_EXTRA_ARGS") {
+        Some(text) => template::shell_words(&text)
+            .map_err(|err| NoxError::Usage(format!("NOX_EXTRA_ARGS: {err}")))?,
+        None => Vec::new(),
+    };
+    args.extend(flags.iter().cloned());
+    Ok(args)
+}
+
+/// Rebuilds `cmd` with `cfg.extra_args` inserted before its last `tail`
+/// arguments (the prompt), dropping generated flags the extras override.
+pub fn merge(cfg: &Config, cmd: Command, tail: usize) -> Command {
+    if cfg.extra_args.is_empty() {
+        return cmd;
+    }
+    let args: Vec<OsString> = cmd.get_args().map(OsStr::to_os_string).collect();
+    let (generated, prompt) = args.split_at(args.len().saturating_sub(tail));
+    let overridden: HashSet<String> = cfg.extra_args.iter().filter_map(|a| flag_name(a)).collect();
+
+    let mut kept: Vec<&OsString> = Vec::new();
+    let mut i = 0;
+    while i < generated.len() {
+        let arg = generated[i].to_string_lossy();
+        let Some(name) = flag_name(&arg) else {
+            kept.push(&generated[i]);
+            i += 1;
+            continue;
+        };
+        let takes_value = !arg.contains('=')
+            && generated
+                .get(i + 1)
+                .is_some_and(|next| flag_name(&next.to_string_lossy()).is_none());
+        let end = if takes_value { i + 2 } else { i + 1 };
+        if overridden.contains(&name) {
+            let dropped: Vec<_> = generated[i..end]
+                .iter()
+                .map(|a| a.to_string_lossy())
+                .collect();
+            cfg.debug(format!(
+                "extra args: dropping generated `{}`, overridden by an extra argument",
+                dropped.join(" ")
+            ));
+        } else {
+            kept.extend(&generated[i..end]);
+        }
+        i = end;
+    }
+
+    let mut out = Command::new(cmd.get_program());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => out.env(key, value),
+            None => out.env_remove(key),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        out.current_dir(dir);
+    }
+    out.args(kept).args(&cfg.extra_args).args(prompt);
+    out
+}
+
+/// `-ctx`, `--ctx`, and `--ctx=4096` all name `ctx`; negative numbers and
+/// plain words are values, not flags.
+fn flag_name(arg: &str) -> Option<String> {
+    let body = arg.strip_prefix("--").or_else(|| arg.strip_prefix('-'))?;
+    if !body.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some(body.split('=').next().unwrap_or(body).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Layer;
+    use crate::tests::config;
+
+    fn sources(extra: &str) -> Sources {
+        let values = [("NOX_EXTRA_ARGS".to_string(), extra.to_string())].into();
+        Sources::new(vec![Layer::new("test", values)])
+    }
+
+    /// A config whose extra args are `extra`, resolved the way `main` does.
+    fn with_extras(extra: &str) -> Config {
+        let mut cfg = config(&[]);
+        cfg.extra_args = resolve(&sources(extra), &[]).unwrap();
+        cfg
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn quoted_words_keep_their_spaces() {
+        let flags = ["--log-file".to_string(), "my log.txt".to_string()];
+        assert_eq!(
+            resolve(
+                &sources(r#"--stop "END  here" --prefix 'You: ' a\ b"#),
+                &flags
+            )
+            .unwrap(),
+            [
+                "--stop",
+                "END  here",
+                "--prefix",
+                "You: ",
+                "a b",
+                "--log-file",
+                "my log.txt"
+            ]
+        );
+        assert_eq!(
+            resolve(&sources("--stop 'open"), &[])
+                .unwrap_err()
+                .to_string(),
+            "NOX_EXTRA_ARGS: unterminated single quote"
+        );
+    }
+
+    #[test]
+    fn extras_go_after_the_generated_flags_and_before_the_prompt() {
+        let mut cmd = Command::new("runner");
+        cmd.args(["-c", "1024", "-p", "hello world"]);
+        let merged = merge(&with_extras("--mlock --rope-scale 2"), cmd, 2);
+        assert_eq!(
+            args(&merged),
+            [
+                "-c",
+                "1024",
+                "--mlock",
+                "--rope-scale",
+                "2",
+                "-p",
+                "hello world"
+            ]
+        );
+    }
+
+    #[test]
+    fn a_duplicate_flag_drops_the_generated_one() {
+        let mut cmd = Command::new("noxlocal");
+        cmd.env("NOX_NUM_THREADS", "4");
+        cmd.args([
+            "-fast", "-ctx", "1024", "-temp", "0", "-top-k", "-1", "prompt",
+        ]);
+        let merged = merge(&with_extras("-ctx 4096 --temp=0.9 -fast"), cmd, 1);
+        assert_eq!(
+            args(&merged),
+            [
+                "-top-k",
+                "-1",
+                "-ctx",
+                "4096",
+                "--temp=0.9",
+                "-fast",
+                "prompt"
+            ]
+        );
+        let envs: Vec<_> = merged.get_envs().collect();
+        assert_eq!(
+            envs,
+            [(OsStr::new("NOX_NUM_THREADS"), Some(OsStr::new("4")))]
+        );
+    }
+
+    #[test]
+    fn flag_names_ignore_dashes_values_and_numbers() {
+        assert_eq!(flag_name("-ctx").as_deref(), Some("ctx"));
+        assert_eq!(flag_name("--ctx=4096").as_deref(), Some("ctx"));
+        assert_eq!(flag_name("-1"), None);
+        assert_eq!(flag_name("-.5"), None);
+        assert_eq!(flag_name("prompt"), None);
+    }
+}
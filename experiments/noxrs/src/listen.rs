@@ -0,0 +1,341 @@
+//! `NOX_LISTEN=/path/nox.sock`: one warm runner shared by several clients
+//! over a Unix socket. Each connection speaks the `NOX_PERSIST_PROTO=jsonl`
+//! protocol. Prompts from all clients run one at a time, first come first
+//! served; one that has to wait is told `{"id":..,"type":"queued","ahead":N}`,
+//! and one arriving when `NOX_QUEUE_MAX` are waiting gets a `queue_full`
+//! error. Responses go only to the client that asked, and ids and cancels
+//! are per client; `ping` is answered at once. A client that disconnects
+//! has its running prompt cancelled and its queued ones dropped, so the
+//! runner is clean for the next. `shutdown` from any client, or
+//! SIGTERM/SIGINT, lets the running prompt finish, answers the queued ones
+//! with an error, and closes the runner so `-state-save` runs; a second
+//! signal aborts.
+
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::NoxError;
+use crate::json::{self, Json};
+use crate::proto::{self, Job, Rejected, Request};
+use crate::serve::ServeChild;
+use crate::session_log::SessionLog;
+use crate::signals;
+use crate::transcript::Transcript;
+use crate::Config;
+
+/// Time the runner gets to finish `-state-save` after its stdin closes.
+const SAVE_GRACE: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(100);
+/// A client that stops reading for this long is treated as gone, so it
+/// cannot stall everyone else.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Event {
+    Joined(u64, UnixStream),
+    Line(u64, Result<Option<Request>, Rejected>),
+    Left(u64),
+}
+
+struct Queued {
+    client: u64,
+    job: Job,
+}
+
+pub fn run(cfg: &Config, path: &Path) -> Result<(), NoxError> {
+    let cfg = &proto::prepare(cfg)?;
+    let (listener, _socket) = bind(path)?;
+    signals::install();
+    let started = Instant::now();
+    let mut serve = spawn(cfg)?;
+    if let Err(err) = proto::warm_up(cfg, &mut serve) {
+        serve.kill();
+        return Err(err);
+    }
+    let mut child = Some(serve);
+    if !cfg.quiet {
+        eprintln!("nox: listening on {}", path.display());
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for (client, stream) in (0u64..).zip(listener.incoming()) {
+            let Ok(stream) = stream else { continue };
+            let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+            let Ok(reader) = stream.try_clone() else {
+                continue;
+            };
+            if tx.send(Event::Joined(client, stream)).is_err() {
+                break;
+            }
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(reader).lines() {
+                    let Ok(line) = line else { break };
+                    let request = proto::parse_request(&line);
+                    if tx.send(Event::Line(client, request)).is_err() {
+                        return;
+                    }
+                }
+                let _ = tx.send(Event::Left(client));
+            });
+        }
+    });
+
+    let mut server = Server {
+        clients: HashMap::new(),
+        queue: VecDeque::new(),
+        max: cfg.queue_max,
+        deepest: 0,
+        rejected: 0,
+        started,
+        answered: 0,
+        stopping: false,
+    };
+    let mut log = Transcript::open(cfg.log_file.as_deref()).with_session(SessionLog::open(cfg));
+    let idle_limit = cfg.idle_timeout_secs.map(Duration::from_secs);
+    let mut last_activity = Instant::now();
+    let outcome = loop {
+        if signals::interrupts() > 0 {
+            server.stopping = true;
+        }
+        if server.stopping {
+            break Ok(());
+        }
+        let Some(Queued { client, job }) = server.queue.pop_front() else {
+            match rx.recv_timeout(POLL) {
+                Ok(event) => {
+                    last_activity = Instant::now();
+                    server.handle(event, None);
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if idle_limit.is_some_and(|limit| last_activity.elapsed() >= limit) {
+                        eprintln!(
+                            "nox: idle timeout (no activity for {}s, NOX_IDLE_TIMEOUT_SECS)",
+                            cfg.idle_timeout_secs.unwrap_or_default()
+                        );
+                        break Ok(());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => break Ok(()),
+            }
+            continue;
+        };
+        // Gone already: its queued prompts were dropped with it.
+        let Some(stream) = server.clients.get(&client).and_then(|s| s.try_clone().ok()) else {
+            continue;
+        };
+        if child.is_none() {
+            match spawn(cfg) {
+                Ok(spawned) => {
+                    log.event("restart", "started a fresh runner");
+                    child = Some(spawned);
+                }
+                Err(err) => {
+                    server.send(client, proto::error(&job.id, &err.to_string()));
+                    continue;
+                }
+            }
+        }
+        let gone = Rc::new(Cell::new(false));
+        let mut out = ClientOut {
+            stream,
+            gone: Rc::clone(&gone),
+        };
+        let result = proto::answer(cfg, &mut child, &mut log, &mut out, &job, &mut || {
+            let mut cancel = gone.get();
+            while let Ok(event) = rx.try_recv() {
+                cancel |= server.handle(event, Some((client, &job.id)));
+            }
+            cancel
+        });
+        last_activity = Instant::now();
+        match result {
+            Ok(()) => server.answered += 1,
+            Err(NoxError::Interrupted) => break Err(NoxError::Interrupted),
+            // Answers never fail to write, so this was the runner's pipe.
+            Err(err) => {
+                if let Some(serve) = child.take() {
+                    serve.kill();
+                }
+                server.send(client, proto::error(&job.id, &err.to_string()));
+            }
+        }
+    };
+    if signals::interrupts() > 0 && outcome.is_ok() {
+        eprintln!("nox: shutting down");
+    }
+    for Queued { client, job } in std::mem::take(&mut server.queue) {
+        server.send(client, proto::error(&job.id, "shut down before running"));
+    }
+    if !cfg.quiet {
+        eprintln!(
+            "{}",
+            proto::queue_stats(server.answered, server.deepest, server.rejected)
+        );
+    }
+    if let Some(child) = child {
+        child.close(SAVE_GRACE);
+    }
+    outcome
+}
+
+fn spawn(cfg: &Config) -> Result<ServeChild, NoxError> {
+    let mut child = proto::spawn(cfg)?;
+    child.drain_on_interrupt();
+    Ok(child)
+}
+
+/// Listens on `path`, replacing a socket no server answers on. The guard
+/// removes the socket again when dropped.
+fn bind(path: &Path) -> Result<(UnixListener, SocketFile), NoxError> {
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(NoxError::Usage(format!(
+                "NOX_LISTEN: {} exists and is not a socket",
+                path.display()
+            )));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(NoxError::Usage(format!(
+                "NOX_LISTEN: another server is listening on {}",
+                path.display()
+            )));
+        }
+        // Left behind by a server that did not exit cleanly.
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    let socket = SocketFile(path.to_path_buf());
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    Ok((listener, socket))
+}
+
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+struct Server {
+    clients: HashMap<u64, UnixStream>,
+    queue: VecDeque<Queued>,
+    /// `NOX_QUEUE_MAX`: prompts allowed to wait.
+    max: usize,
+    deepest: usize,
+    rejected: u64,
+    started: Instant,
+    answered: u64,
+    stopping: bool,
+}
+
+impl Server {
+    fn send(&mut self, client: u64, response: Json) {
+        if let Some(stream) = self.clients.get_mut(&client) {
+            // A client that went away is dropped when its reader sees EOF.
+            let _ = proto::respond(stream, response);
+        }
+    }
+
+    /// Applies one event; `running` is the client and id of the prompt in
+    /// flight. Returns true when the event cancels it.
+    fn handle(&mut self, event: Event, running: Option<(u64, &Json)>) -> bool {
+        let (client, request) = match event {
+            Event::Joined(client, stream) => {
+                self.clients.insert(client, stream);
+                return false;
+            }
+            Event::Left(client) => {
+                self.clients.remove(&client);
+                self.queue.retain(|queued| queued.client != client);
+                return running.is_some_and(|(owner, _)| owner == client);
+            }
+            Event::Line(client, Err((id, message))) => {
+                self.send(client, proto::error(&id, &message));
+                return false;
+            }
+            Event::Line(_, Ok(None)) => return false,
+            Event::Line(client, Ok(Some(request))) => (client, request),
+        };
+        match request {
+            Request::Prompt(job) if self.stopping => {
+                self.send(client, proto::error(&job.id, "shutting down"));
+            }
+            Request::Prompt(job) if self.queue.len() >= self.max => {
+                self.rejected += 1;
+                self.send(client, proto::queue_full(&job.id, self.max));
+            }
+            Request::Prompt(job) => {
+                let ahead = self.queue.len() + usize::from(running.is_some());
+                if ahead > 0 {
+                    let queued = json::obj([
+                        ("id", job.id.clone()),
+                        ("type", Json::from("queued")),
+                        ("ahead", Json::from(ahead)),
+                    ]);
+                    self.send(client, queued);
+                }
+                self.queue.push_back(Queued { client, job });
+                self.deepest = self.deepest.max(self.queue.len());
+            }
+            Request::Cancel(target) => {
+                if let Some((owner, id)) = running {
+                    if owner == client && target.as_ref().is_none_or(|t| t == id) {
+                        return true;
+                    }
+                }
+                let Some(target) = target else {
+                    return false;
+                };
+                let at = self
+                    .queue
+                    .iter()
+                    .position(|q| q.client == client && q.job.id == target);
+                if let Some(at) = at {
+                    self.queue.remove(at);
+                    self.send(client, proto::error(&target, "cancelled"));
+                }
+            }
+            Request::Ping(id) => {
+                let pong = proto::pong(id, self.started, self.answered);
+                self.send(client, pong);
+            }
+            Request::Shutdown => self.stopping = true,
+        }
+        false
+    }
+}
+
+/// The running prompt's client. A failed write means it went away: the
+/// error is swallowed so the response still drains from the runner, and
+/// `gone` tells the cancel check to stop it.
+struct ClientOut {
+    stream: UnixStream,
+    gone: Rc<Cell<bool>>,
+}
+
+impl Write for ClientOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.gone.get() && self.stream.write_all(buf).is_err() {
+            self.gone.set(true);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.gone.get() && self.stream.flush().is_err() {
+            self.gone.set(true);
+        }
+        Ok(())
+    }
+}
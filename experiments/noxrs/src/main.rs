@@ -2,17 +2,60 @@
 //! (stdin/stdout only, no HTTP). It forwards the prompt to the runner and
 //! streams stdout back immediately.
 
-use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitCode, Stdio};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-mod neuroute;
-mod routing_weights;
+mod batch;
+mod capabilities;
+mod chat;
+mod cli;
+mod config;
+mod constrain;
+mod context;
+mod dryrun;
+mod echo;
+mod embedding;
+mod error;
+mod extra_args;
+mod json;
+#[cfg(unix)]
+mod listen;
+mod models;
+mod persist;
+mod platform;
+mod progress;
+mod proto;
+mod rlimit;
+mod route_embed;
+mod route_json;
+mod route_report;
+mod sampling;
+mod serve;
+mod session_log;
+mod signals;
+mod sim;
+mod sim_script;
+mod sim_serve;
+mod state_cache;
+mod stderr_filter;
+mod template;
+mod tokens;
+mod transcript;
+mod watchdog;
+
+use cli::Cli;
+use error::NoxError;
+use noxroute_core::{
+    Anchors, Autochunk, Explanation, MaskStrategy, Order, Regex, RouteCache, RouteConfig,
+    RouteOptions, Routed, RoutingModel, Scorer, Split, TokenOptions, Weights, FEATURE_NAMES,
+};
+use transcript::{Tee, Transcript};
 
 const DEFAULT_CTX: u32 = 1024;
 const DEFAULT_BATCH: u32 = 1;
@@ -22,34 +65,365 @@ const DEFAULT_TOP_P: f32 = 1.0;
 const DEFAULT_TOP_K: u32 = 1;
 const DEFAULT_TTFT_MS: u64 = 150;
 const DEFAULT_TPS: f32 = 80.0;
+const DEFAULT_STATE_MAX_MB: u64 = 1024;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("nox: {err}");
+            for line in err.details() {
+                eprintln!("nox: {line}");
+            }
+            ExitCode::from(err.exit_code())
+        }
+    }
+}
 
-fn main() -> io::Result<()> {
-    let cfg = Config::from_env();
+fn run() -> Result<(), NoxError> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == sim_serve::FLAG) {
+        let sources = config::Sources::load(Cli::default().layer())?;
+        return sim_serve::run(Config::resolve(&sources), &args[1..]);
+    }
+    let cli = Cli::parse(args)?;
+    if cli.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    let sources = config::Sources::load(cli.layer())?;
+    let mut cfg = Config::resolve(&sources);
+    cfg.extra_args = extra_args::resolve(&sources, &cli.extra_args)?;
+    if cli.print_config {
+        sources.print(&mut io::stdout())?;
+        return Ok(());
+    }
+    cfg.sampling.validate()?;
+    constrain::validate(&cfg)?;
+    tokens::validate(&cfg)?;
+    if cfg.emulate_a1000 {
+        sim::validate(&cfg)?;
+    }
+    if cfg.route_enabled {
+        route_scorer(&cfg)?;
+        route_split(&cfg)?;
+        route_autochunk(&cfg)?;
+        route_json::format(&cfg)?;
+        route_anchors(&cfg)?;
+        route_profile(&cfg)?;
+        route_order(&cfg)?;
+        cfg.route_weights = route_weights(&cfg)?;
+    }
+    if cfg.json_schema_file.is_some() {
+        // Runner prefixes would make every answer invalid JSON.
+        cfg.raw = true;
+    }
+    let dry_run = cfg.dry_run;
+    if dry_run && cli.batch.is_some() {
+        return Err(NoxError::Usage(
+            "--dry-run cannot be combined with --batch".to_string(),
+        ));
+    }
+    if cli.jobs.is_some() && cli.batch.is_none() {
+        return Err(NoxError::Usage("--jobs only applies to --batch".to_string()));
+    }
+    if cli.chat {
+        if dry_run || cli.batch.is_some() || cfg.persist || cfg.listen.is_some() {
+            return Err(NoxError::Usage(
+                "--chat cannot be combined with --dry-run, --batch, NOX_PERSIST, or NOX_LISTEN"
+                    .to_string(),
+            ));
+        }
+        return chat::run(&cfg);
+    }
+    if let Some(input) = &cli.batch {
+        let opts = batch::BatchOptions {
+            input: input.clone(),
+            out: cli.out.clone(),
+            fail_fast: cli.fail_fast,
+            jobs: cli.jobs.unwrap_or(1),
+        };
+        return batch::run(&cfg, &opts);
+    }
+    if let Some(path) = &cfg.listen {
+        if dry_run {
+            let framed = proto::prepare(&cfg)?;
+            let runner = framed.serve_runner()?;
+            let model = framed.serve_model()?;
+            let cmd = serve_command(&framed, &runner, model.as_deref());
+            let notes = vec![format!(
+                "server mode: JSONL clients on {} share this runner (NOX_LISTEN)",
+                path.display()
+            )];
+            return Ok(dryrun::print(&notes, &cmd, None)?);
+        }
+        #[cfg(unix)]
+        return listen::run(&cfg, path);
+        #[cfg(not(unix))]
+        return Err(NoxError::Usage(
+            "NOX_LISTEN needs Unix domain sockets".to_string(),
+        ));
+    }
     if cfg.persist {
-        return run_persistent(&cfg);
+        let jsonl = match cfg.persist_proto.as_str() {
+            "text" => false,
+            "jsonl" => true,
+            other => {
+                return Err(NoxError::Usage(format!(
+                    "NOX_PERSIST_PROTO must be `text` or `jsonl`, got `{other}`"
+                )))
+            }
+        };
+        if dry_run {
+            let runner = cfg.serve_runner()?;
+            let model = cfg.serve_model()?;
+            let cmd = serve_command(&cfg, &runner, model.as_deref());
+            let mut notes =
+                vec!["persistent mode: prompts are streamed to the runner's stdin".to_string()];
+            if cfg.emulate_a1000 {
+                notes.push("NOX_EMULATE_A1000 is set: nox itself stands in for the runner".to_string());
+            }
+            if cfg.persist_warmup.is_some() {
+                notes.push("a warmup control request is sent first (NOX_WARMUP=0 skips it)".to_string());
+            } else {
+                notes.push("a no-op ping control request is sent first to wait for the model".to_string());
+            }
+            if let Some(dir) = &cfg.session_log {
+                notes.push(format!("requests are recorded under {} (NOX_SESSION_LOG)", dir.display()));
+            }
+            if jsonl {
+                notes.push("stdin and stdout carry JSONL requests and responses (NOX_PERSIST_PROTO=jsonl)".to_string());
+            }
+            return Ok(dryrun::print(&notes, &cmd, None)?);
+        }
+        if jsonl {
+            return proto::run(&cfg);
+        }
+        return persist::run(&cfg);
     }
-    let mut prompt = read_prompt()?;
-    if prompt.trim().is_empty() {
-        eprintln!("nox: empty prompt");
+    let mut prompt = read_prompt(&cli)?;
+    check_prompt(&prompt)?;
+    if matches!(cfg.runner_style, RunnerStyle::LlamaEmbedding) {
+        if cli.count_tokens && !dry_run {
+            return Ok(tokens::print(tokens::count(&cfg, &prompt), cli.json)?);
+        }
+        if dry_run {
+            let (joined, count) = embedding::join_prompts(&cfg, &prompt)?;
+            let notes = [format!("embedding {count} prompt(s)")];
+            return dry_run_one(&cfg, &joined, &notes);
+        }
+        let vectors = embedding::embed(&cfg, &prompt)?;
+        embedding::write_vectors(&mut io::stdout(), &vectors, cli.json)?;
         return Ok(());
     }
+    let mut notes = Vec::new();
     if cfg.route_enabled {
-        if let Some(routed) = route_prompt(&cfg, &prompt) {
-            prompt = routed;
+        if let Some(routed) = route_prompt(&cfg, &prompt, &mut RouteCache::default()) {
+            notes.push(format!(
+                "routing kept {} of {} chunks",
+                routed.selected, routed.candidates
+            ));
+            prompt = routed.prompt;
+        }
+    }
+    if cli.count_tokens {
+        let count = tokens::count(&cfg, &prompt);
+        if !dry_run {
+            return Ok(tokens::print(count, cli.json)?);
+        }
+        notes.push(count.line());
+    }
+    if dry_run {
+        return dry_run_one(&cfg, &prompt, &notes);
+    }
+    let mut log = Transcript::open(cfg.log_file.as_deref());
+    log.begin(&prompt);
+    let mut tee = Tee {
+        out: &mut io::stdout(),
+        log: &mut log,
+    };
+    let meter = Arc::new(progress::Meter::new(Instant::now()));
+    let indicator = progress::enabled(cfg.progress, cli.json, cfg.quiet)
+        .then(|| progress::Indicator::start(Arc::clone(&meter)));
+    let mut out = progress::Metered::new(&mut tee, &meter);
+    let result = if cfg.json_schema_file.is_some() {
+        let result = constrain::generate_json(&cfg, &prompt, &mut out, &mut |p, out| {
+            state_cache::generate(&cfg, p, out)
+        });
+        match result {
+            Ok((_, false)) => Err(NoxError::BadOutput(
+                "runner output is not valid JSON (NOX_JSON_SCHEMA_FILE)".to_string(),
+            )),
+            other => other.map(|(stats, _)| stats),
         }
+    } else {
+        state_cache::generate(&cfg, &prompt, &mut out)
+    };
+    if let Some(indicator) = indicator {
+        indicator.finish();
     }
+    log.finish(&result);
+    result?;
+    Ok(())
+}
+
+/// Resolves and prints the one-shot invocation for `prompt` without running it.
+fn dry_run_one(cfg: &Config, prompt: &str, notes: &[String]) -> Result<(), NoxError> {
+    let mut notes = notes.to_vec();
+    notes.insert(0, format!("runner style: {}", cfg.runner_style.name()));
     if cfg.emulate_a1000 {
-        return simulate_stream(&cfg, &prompt);
+        notes.push(
+            "NOX_EMULATE_A1000 is set: the simulator answers, nothing is spawned".to_string(),
+        );
     }
+    if cfg.resource_limits.is_set() && !cfg.emulate_a1000 {
+        notes.push(format!(
+            "resource limits: {}",
+            cfg.resource_limits.describe()
+        ));
+    }
+    let runner = cfg.resolve_runner()?;
+    let model = cfg.resolve_model()?;
+    let (cmd, prompt_on_stdin) = build_command(cfg, &runner, model.as_deref(), prompt)?;
+    dryrun::print(&notes, &cmd, prompt_on_stdin.then_some(prompt))?;
+    Ok(())
+}
 
-    let runner = cfg
-        .resolve_runner()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no runner binary found"))?;
+/// Timing for one completed generation.
+#[derive(Debug, Clone, Copy)]
+struct RunStats {
+    ttft: Option<Duration>,
+    duration: Duration,
+    /// Tokens streamed, when they were counted (the simulator counts its
+    /// own).
+    tokens: Option<u64>,
+    /// The part of `ttft` spent reading the prompt, when it is known apart
+    /// from the rest (only the simulator knows).
+    prefill: Option<Duration>,
+}
+
+impl RunStats {
+    /// The rate reached from the first token to the last, when the tokens
+    /// were counted and there was more than one.
+    fn tps(&self) -> Option<f64> {
+        let tokens = self.tokens.filter(|tokens| *tokens > 1)?;
+        let streaming = self.duration.saturating_sub(self.ttft.unwrap_or_default());
+        Some(progress::rate(tokens - 1, streaming))
+    }
+
+    /// `120 ms`, or `320 ms (prefill 200 ms, fixed 120 ms)` when the prefill
+    /// is known; `-` without a first token.
+    fn ttft_label(&self) -> String {
+        let Some(ttft) = self.ttft else {
+            return "-".to_string();
+        };
+        match self.prefill {
+            Some(prefill) => format!(
+                "{} ms (prefill {} ms, fixed {} ms)",
+                ttft.as_millis(),
+                prefill.as_millis(),
+                ttft.saturating_sub(prefill).as_millis()
+            ),
+            None => format!("{} ms", ttft.as_millis()),
+        }
+    }
+}
+
+/// Runs one prompt through a fresh runner (or the simulator), streaming the
+/// response into `out`.
+fn generate(cfg: &Config, prompt: &str, out: &mut dyn Write) -> Result<RunStats, NoxError> {
+    if cfg.emulate_a1000 {
+        return sim::stream(cfg, prompt, out, &mut thread::sleep);
+    }
 
-    let mut cmd = Command::new(&runner);
-    cmd.stdout(Stdio::piped()).stderr(Stdio::inherit());
+    let runner = cfg.resolve_runner()?;
+    let model = cfg.resolve_model()?;
 
-    match cfg.runner_style {
+    let filter_stderr = cfg.runner_style.is_llama() && !cfg.verbose;
+    let (mut cmd, prompt_on_stdin) = build_command(cfg, &runner, model.as_deref(), prompt)?;
+    if prompt_on_stdin {
+        cmd.stdin(Stdio::piped());
+    }
+    cmd.stdout(Stdio::piped()).stderr(if filter_stderr {
+        Stdio::piped()
+    } else {
+        Stdio::inherit()
+    });
+
+    signals::isolate(&mut cmd);
+    cfg.resource_limits.apply(&mut cmd);
+    signals::install();
+    let mut child = cmd.spawn().map_err(|source| {
+        cfg.resource_limits.explain(NoxError::Spawn {
+            runner: runner.clone(),
+            source,
+        })
+    })?;
+    let started = Instant::now();
+    if let Some(mut stdin) = child.stdin.take() {
+        // Written from a thread so a runner that streams before reading all
+        // of its input cannot deadlock against the stdout pump.
+        let prompt = prompt.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(prompt.as_bytes());
+        });
+    }
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("failed to open child stdout"))?;
+    let capture = child.stderr.take().map(stderr_filter::StderrCapture::spawn);
+
+    let strip_echo = matches!(cfg.runner_style, RunnerStyle::LlamaSimple) && !cfg.keep_echo;
+    let mut out = echo::EchoStrip::new(out, strip_echo.then_some(prompt));
+    let pumped = watchdog::pump(stdout, &mut out, cfg.limits(), started, &|| {
+        signals::interrupts() > 0
+    })
+    .and_then(|pumped| out.finish().map(|()| pumped));
+    let pumped = match pumped {
+        Ok(pumped) => pumped,
+        Err(err) => {
+            signals::terminate(&mut child, true);
+            let _ = child.wait();
+            return Err(err.into());
+        }
+    };
+    if let watchdog::PumpEnd::Interrupted = pumped.end {
+        signals::shutdown(&mut child, signals::GRACE);
+        return Err(NoxError::Interrupted);
+    }
+    if let Some(reason) = pumped.end.reason() {
+        signals::terminate(&mut child, true);
+        let _ = child.wait();
+        return Err(NoxError::Timeout(reason));
+    }
+
+    let status = child.wait()?;
+    if let Some(capture) = capture {
+        capture.finish(!status.success());
+    }
+    if !status.success() {
+        return Err(cfg.resource_limits.explain(NoxError::RunnerFailed(status)));
+    }
+    Ok(RunStats {
+        ttft: pumped.first_byte.map(|at| at.duration_since(started)),
+        duration: started.elapsed(),
+        tokens: None,
+        prefill: None,
+    })
+}
+
+/// Assembles the one-shot runner invocation for the configured style, and
+/// reports whether the prompt must be written to the runner's stdin.
+fn build_command(
+    cfg: &Config,
+    runner: &Path,
+    model: Option<&str>,
+    prompt: &str,
+) -> Result<(Command, bool), NoxError> {
+    let mut cmd = Command::new(runner);
+    // How the prompt travels: on stdin, and/or as the last `tail` arguments.
+    let (prompt_on_stdin, tail) = match cfg.runner_style {
         RunnerStyle::NoxLocal => {
             if cfg.raw {
                 cmd.arg("-raw");
@@ -63,8 +437,9 @@ fn main() -> io::Result<()> {
             cmd.args(["-temp", &cfg.temp.to_string()]);
             cmd.args(["-top-p", &cfg.top_p.to_string()]);
             cmd.args(["-top-k", &cfg.top_k.to_string()]);
-            if let Some(model) = cfg.model_path() {
-                cmd.args(["-model", &model]);
+            cfg.sampling.noxlocal_args(&mut cmd, runner);
+            if let Some(model) = model {
+                cmd.args(["-model", model]);
             }
             if let Some(threads) = cfg.threads {
                 cmd.env("NOX_NUM_THREADS", threads.to_string());
@@ -80,7 +455,14 @@ fn main() -> io::Result<()> {
                 cmd.arg("-state-save");
                 cmd.arg(state_save);
             }
-            cmd.arg(prompt);
+            // noxlocal reads a piped stdin when given no prompt argument;
+            // on Windows that sidesteps cmd quoting and the 32K argv limit.
+            if platform::WINDOWS {
+                (true, 0)
+            } else {
+                cmd.arg(prompt);
+                (false, 1)
+            }
         }
         RunnerStyle::LlamaCompletion => {
             cmd.arg("--simple-io");
@@ -88,8 +470,8 @@ fn main() -> io::Result<()> {
             if cfg.no_warmup {
                 cmd.arg("--no-warmup");
             }
-            if let Some(model) = cfg.model_path() {
-                cmd.args(["-m", &model]);
+            if let Some(model) = model {
+                cmd.args(["-m", model]);
             }
             if let Some(device) = &cfg.device {
                 cmd.args(["--device", device]);
@@ -103,55 +485,80 @@ fn main() -> io::Result<()> {
             cmd.args(["--temp", &cfg.temp.to_string()]);
             cmd.args(["--top-p", &cfg.top_p.to_string()]);
             cmd.args(["--top-k", &cfg.top_k.to_string()]);
+            cfg.sampling.llama_args(&mut cmd);
+            if let Some(grammar) = &cfg.grammar_file {
+                cmd.arg("--grammar-file").arg(grammar);
+            }
+            if let Some(schema) = &cfg.json_schema_file {
+                cmd.arg("--json-schema-file").arg(schema);
+            }
             if let Some(threads) = cfg.threads {
                 cmd.args(["-t", &threads.to_string()]);
             }
-            cmd.args(["-p", &prompt]);
+            cmd.args(["-p", prompt]);
+            (false, 2)
         }
         RunnerStyle::LlamaSimple => {
-            if let Some(model) = cfg.model_path() {
-                cmd.args(["-m", &model]);
+            if let Some(model) = model {
+                cmd.args(["-m", model]);
             }
             cmd.args(["-n", &cfg.max_tokens.to_string()]);
             if let Some(ngl) = cfg.gpu_layers {
                 cmd.args(["-ngl", &ngl.to_string()]);
             }
             cmd.arg(prompt);
+            (false, 1)
         }
-    }
-
-    let mut child = cmd.spawn()?;
-    let mut stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to open child stdout"))?;
-
-    let mut buf = [0u8; 4096];
-    let mut out = io::stdout();
-    loop {
-        let n = stdout.read(&mut buf)?;
-        if n == 0 {
-            break;
+        RunnerStyle::LlamaEmbedding => {
+            if let Some(model) = model {
+                cmd.args(["-m", model]);
+            }
+            if let Some(device) = &cfg.device {
+                cmd.args(["--device", device]);
+            }
+            if let Some(ngl) = cfg.gpu_layers {
+                cmd.args(["-ngl", &ngl.to_string()]);
+            }
+            cmd.args(["-c", &cfg.ctx.to_string()]);
+            if let Some(threads) = cfg.threads {
+                cmd.args(["-t", &threads.to_string()]);
+            }
+            cmd.args(["--embd-output-format", "json"]);
+            cmd.args(["--embd-separator", embedding::separator(cfg)]);
+            cmd.args(["-p", prompt]);
+            (false, 2)
         }
-        out.write_all(&buf[..n])?;
-        out.flush()?;
-    }
-
-    let status = child.wait()?;
-    if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("runner exited with status {status}"),
-        ));
-    }
-    Ok(())
+        RunnerStyle::Custom => {
+            let rendered = cfg
+                .runner_template()?
+                .render(&|name| match name {
+                    "model" => model.map(str::to_string),
+                    "prompt" => Some(prompt.to_string()),
+                    "ctx" => Some(cfg.ctx.to_string()),
+                    "max_tokens" => Some(cfg.max_tokens.to_string()),
+                    "temp" => Some(cfg.temp.to_string()),
+                    "top_p" => Some(cfg.top_p.to_string()),
+                    "top_k" => Some(cfg.top_k.to_string()),
+                    "threads" => cfg.threads.map(|t| t.to_string()),
+                    _ => None,
+                })
+                .map_err(|err| NoxError::Usage(format!("NOX_RUNNER_TEMPLATE: {err}")))?;
+            cmd.args(&rendered.argv[1..]);
+            // Templates place the prompt themselves; extras go last.
+            (rendered.prompt_on_stdin, 0)
+        }
+    };
+    Ok((extra_args::merge(cfg, cmd, tail), prompt_on_stdin))
 }
 
 #[derive(Debug, Clone)]
 struct Config {
     runner_override: Option<PathBuf>,
     model_override: Option<PathBuf>,
+    model_name: Option<String>,
+    models_dir: Option<PathBuf>,
     runner_style: RunnerStyle,
+    runner_template: Option<String>,
     device: Option<String>,
     gpu_layers: Option<i32>,
     ctx: u32,
@@ -160,6 +567,7 @@ struct Config {
     temp: f32,
     top_p: f32,
     top_k: u32,
+    sampling: sampling::Sampling,
     threads: Option<u32>,
     raw: bool,
     fast: bool,
@@ -167,183 +575,475 @@ struct Config {
     emulate_a1000: bool,
     sim_ttft_ms: u64,
     sim_tps: f32,
+    /// `NOX_SIM_PREFILL_TPS`: prompt tokens the simulator reads per second.
+    sim_prefill_tps: Option<f32>,
+    /// `NOX_SIM_JITTER_PCT`, `NOX_SIM_STALL`, and `NOX_SIM_SEED`; see
+    /// [`sim`].
+    sim_jitter_pct: f32,
+    sim_stall: Option<String>,
+    sim_seed: Option<u64>,
+    /// `NOX_SIM_FAIL`; checked by [`sim::fail`].
+    sim_fail: Option<String>,
+    /// `NOX_SIM_CHUNKING`; checked by [`sim::chunking`].
+    sim_chunking: Option<String>,
     sim_text: Option<String>,
+    /// `NOX_SIM_SCRIPT`; see [`sim_script`].
+    sim_script: Option<PathBuf>,
     prepack: bool,
     route_enabled: bool,
     route_query: Option<String>,
     route_delim: String,
+    /// `NOX_ROUTE_SPLIT`: `delim`, `regex`, `markdown`, or `blank`; checked
+    /// by [`route_split`].
+    route_split: String,
+    route_min_chars: usize,
+    /// `NOX_ROUTE_AUTOCHUNK_CHARS` (0: off) and the window size and
+    /// overlap; checked by [`route_autochunk`].
+    route_autochunk: usize,
+    route_window_chars: usize,
+    route_window_overlap: usize,
+    /// `NOX_ROUTE_FORMAT`: `auto`, `json`, or `text`; see [`route_json`].
+    route_format: String,
     route_keep: usize,
+    /// `NOX_ROUTE_THRESHOLD`: keep chunks at least this probable, rather
+    /// than letting the router choose between that and its top-k guess.
+    route_threshold: Option<f32>,
+    /// `NOX_ROUTE_MIN`: keep at least this many chunks; unset leaves it to
+    /// the profile.
+    route_min: Option<usize>,
+    /// `NOX_ROUTE_PROFILE`: a built-in router profile; checked by
+    /// [`route_profile`].
+    route_profile: String,
+    /// `NOX_ROUTE_WEIGHTS`: a file from `noxroute train` whose weights
+    /// replace the profile's.
+    route_weights_file: Option<PathBuf>,
+    /// Those weights, read by `run` before the first route.
+    route_weights: Option<Weights>,
+    /// `NOX_ROUTE_SCORER`: `overlap`, `tfidf`, or `bm25`; checked by
+    /// [`route_scorer`].
+    route_scorer: String,
+    route_bm25_k1: f32,
+    route_bm25_b: f32,
+    /// `NOX_ROUTE_EMBED_RUNNER` and `NOX_ROUTE_EMBED_MODEL`, for
+    /// [`route_embed`].
+    route_embed_runner: Option<PathBuf>,
+    route_embed_model: Option<PathBuf>,
+    /// `NOX_ROUTE_STOPWORDS`; unset means on for the overlap scorer only.
+    route_stopwords: Option<bool>,
+    route_stem: bool,
+    /// `NOX_ROUTE_BUDGET_CHARS`, or `NOX_ROUTE_BUDGET_TOKENS` in tokens;
+    /// see [`route_budget`].
+    route_budget_chars: Option<u64>,
+    route_budget_tokens: Option<u32>,
+    /// `NOX_ROUTE_DIVERSITY`, 0 to 1.
+    route_diversity: f32,
+    /// `NOX_ROUTE_ANCHOR`: `first`, `last`, or both; checked by
+    /// [`route_anchors`].
+    route_anchor: String,
+    /// `NOX_ROUTE_DEDUP`: the near-duplicate threshold, 0 to 1 (0: off).
+    route_dedup: f32,
+    /// `NOX_ROUTE_ORDER`: `document` or `score`; checked by
+    /// [`route_order`].
+    route_order: String,
+    /// `NOX_ROUTE_FUZZY`: score by trigrams when every chunk scores below
+    /// this (0: never).
+    route_fuzzy: f32,
     route_debug: bool,
+    /// `NOX_ROUTE_DEBUG=json`: one JSON report instead of the debug lines.
+    route_debug_json: bool,
     persist: bool,
     persist_rs: bool,
+    /// `NOX_PERSIST_PROTO`: `text` (the default) or `jsonl`.
+    persist_proto: String,
+    /// `NOX_LISTEN`: serve JSONL clients on this Unix socket.
+    listen: Option<PathBuf>,
+    max_restarts: u32,
+    idle_timeout_secs: Option<u64>,
+    /// `NOX_QUEUE_MAX`: prompts that may wait behind the running one in the
+    /// framed modes.
+    queue_max: usize,
+    /// `NOX_SESSION_LOG`: directory for persistent-session audit logs.
+    session_log: Option<PathBuf>,
+    session_log_max_mb: f32,
+    session_log_keep: u32,
     keep_cache: bool,
     append_only: bool,
     input_only: bool,
     state_save: Option<PathBuf>,
     state_load: Option<PathBuf>,
+    state_auto: bool,
+    state_max_mb: u64,
+    timeout_secs: Option<u64>,
+    stall_secs: Option<u64>,
+    resource_limits: rlimit::ResourceLimits,
+    verbose: bool,
+    quiet: bool,
+    progress: bool,
+    keep_echo: bool,
+    debug: bool,
+    dry_run: bool,
+    log_file: Option<PathBuf>,
+    grammar_file: Option<PathBuf>,
+    json_schema_file: Option<PathBuf>,
+    json_retries: u32,
+    chars_per_token: f32,
+    /// `NOX_WARMUP_PROMPT`, unless `NOX_WARMUP=0` turned warmup off.
+    persist_warmup: Option<String>,
+    system_prompt: Option<String>,
+    omit_marker: bool,
+    extra_args: Vec<String>,
 }
 
 impl Config {
-    fn from_env() -> Self {
-        let chip_emu = env_bool("NOX_CHIP_EMU")
-            .or_else(|| env_bool("NOX_EMULATE_CHIP"))
+    /// Builds the effective config from layered `sources`; anything unset
+    /// falls back to the defaults here.
+    fn resolve(src: &config::Sources) -> Self {
+        let chip_emu = src.bool("NOX_CHIP_EMU")
+            .or_else(|| src.bool("NOX_EMULATE_CHIP"))
             .unwrap_or(false);
         let runner_style = if chip_emu {
             RunnerStyle::NoxLocal
         } else {
-            RunnerStyle::from_env()
+            RunnerStyle::resolve(src)
         };
-        let warmup = env_bool("NOX_WARMUP");
-        let no_warmup = env_bool("NOX_NO_WARMUP");
-        let route_query = env::var("NOX_ROUTE_QUERY")
-            .ok()
+        let warmup = src.bool("NOX_WARMUP");
+        let no_warmup = src.bool("NOX_NO_WARMUP");
+        let route_query = src
+            .var("NOX_ROUTE_QUERY")
             .and_then(|v| if v.trim().is_empty() { None } else { Some(v) });
-        let route_enabled = env_bool("NOX_ROUTE").unwrap_or(false) || route_query.is_some();
+        let route_enabled = src.bool("NOX_ROUTE").unwrap_or(false) || route_query.is_some();
 
         Self {
-            runner_override: env::var_os("NOX_LOCAL_RUNNER").map(PathBuf::from),
-            model_override: env::var_os("NOX_MODEL_PATH").map(PathBuf::from),
+            runner_override: src.var("NOX_LOCAL_RUNNER").map(PathBuf::from),
+            model_override: src.var("NOX_MODEL_PATH").map(PathBuf::from),
+            model_name: src.var("NOX_MODEL").filter(|v| !v.trim().is_empty()),
+            models_dir: src.path("NOX_MODELS_DIR"),
             runner_style,
-            device: env::var("NOX_DEVICE")
-                .ok()
-                .and_then(|v| {
-                    let v = v.trim();
-                    if v.is_empty() || v.eq_ignore_ascii_case("auto") {
-                        None
-                    } else {
-                        Some(v.to_string())
-                    }
-                }),
-            gpu_layers: env_i32("NOX_GPU_LAYERS").or_else(|| env_i32("NOX_N_GPU_LAYERS")),
+            runner_template: src
+                .var("NOX_RUNNER_TEMPLATE")
+                .filter(|v| !v.trim().is_empty()),
+            device: src.var("NOX_DEVICE").and_then(|v| {
+                let v = v.trim();
+                if v.is_empty() || v.eq_ignore_ascii_case("auto") {
+                    None
+                } else {
+                    Some(v.to_string())
+                }
+            }),
+            gpu_layers: src.i32("NOX_GPU_LAYERS").or_else(|| src.i32("NOX_N_GPU_LAYERS")),
             ctx: if chip_emu {
                 DEFAULT_CTX
             } else {
-                env_u32("NOX_CTX")
-                    .or_else(|| env_u32("NOX_NUM_CTX"))
+                src.u32("NOX_CTX")
+                    .or_else(|| src.u32("NOX_NUM_CTX"))
                     .unwrap_or(DEFAULT_CTX)
             },
             max_tokens: if chip_emu {
                 DEFAULT_MAX_TOKENS
             } else {
-                env_u32("NOX_MAX_TOKENS").unwrap_or(DEFAULT_MAX_TOKENS)
+                src.u32("NOX_MAX_TOKENS").unwrap_or(DEFAULT_MAX_TOKENS)
             },
             batch: if chip_emu {
                 DEFAULT_BATCH
             } else {
-                env_u32("NOX_BATCH").unwrap_or(DEFAULT_BATCH)
+                src.u32("NOX_BATCH").unwrap_or(DEFAULT_BATCH)
             },
             temp: if chip_emu {
                 DEFAULT_TEMP
             } else {
-                env_f32("NOX_TEMP").unwrap_or(DEFAULT_TEMP)
+                src.f32("NOX_TEMP").unwrap_or(DEFAULT_TEMP)
             },
             top_p: if chip_emu {
                 DEFAULT_TOP_P
             } else {
-                env_f32("NOX_TOP_P").unwrap_or(DEFAULT_TOP_P)
+                src.f32("NOX_TOP_P").unwrap_or(DEFAULT_TOP_P)
             },
             top_k: if chip_emu {
                 DEFAULT_TOP_K
             } else {
-                env_u32("NOX_TOP_K").unwrap_or(DEFAULT_TOP_K)
+                src.u32("NOX_TOP_K").unwrap_or(DEFAULT_TOP_K)
             },
-            threads: env_u32("NOX_NUM_THREADS"),
-            raw: env::var("NOX_RAW").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false),
-            fast: env_bool("NOX_FAST").unwrap_or(false),
-            no_warmup: no_warmup.unwrap_or_else(|| {
-                if let Some(true) = warmup {
-                    false
-                } else {
-                    matches!(runner_style, RunnerStyle::LlamaCompletion)
-                }
+            sampling: sampling::Sampling::resolve(src),
+            threads: src.u32("NOX_NUM_THREADS"),
+            raw: src.var("NOX_RAW").map(|v| v == "1" || v.eq_ignore_ascii_case("true")).unwrap_or(false),
+            fast: src.bool("NOX_FAST").unwrap_or(false),
+            no_warmup: no_warmup.unwrap_or(if let Some(true) = warmup {
+                false
+            } else {
+                matches!(runner_style, RunnerStyle::LlamaCompletion)
             }),
             emulate_a1000: if chip_emu {
                 false
             } else {
-                env_bool("NOX_EMULATE_A1000")
-                    .or_else(|| env_bool("NOX_SIMULATE"))
-                    .or_else(|| env_bool("NOX_SIM_MODE"))
+                src.bool("NOX_EMULATE_A1000")
+                    .or_else(|| src.bool("NOX_SIMULATE"))
+                    .or_else(|| src.bool("NOX_SIM_MODE"))
                     .unwrap_or(false)
             },
-            sim_ttft_ms: env_u64("NOX_SIM_TTFT_MS")
-                .or_else(|| env_u64("NOX_SIM_TTFT"))
+            sim_ttft_ms: src.u64("NOX_SIM_TTFT_MS")
+                .or_else(|| src.u64("NOX_SIM_TTFT"))
                 .unwrap_or(DEFAULT_TTFT_MS),
-            sim_tps: env_f32("NOX_SIM_TOKENS_PER_SEC")
-                .or_else(|| env_f32("NOX_SIM_TPS"))
+            sim_tps: src.f32("NOX_SIM_TOKENS_PER_SEC")
+                .or_else(|| src.f32("NOX_SIM_TPS"))
                 .unwrap_or(DEFAULT_TPS),
-            sim_text: env::var("NOX_SIM_TEXT")
-                .ok()
+            sim_prefill_tps: src.f32("NOX_SIM_PREFILL_TPS"),
+            sim_jitter_pct: src.f32("NOX_SIM_JITTER_PCT").unwrap_or(0.0),
+            sim_stall: src.var("NOX_SIM_STALL").filter(|v| !v.trim().is_empty()),
+            sim_seed: src.u64("NOX_SIM_SEED"),
+            sim_fail: src.var("NOX_SIM_FAIL").filter(|v| !v.trim().is_empty()),
+            sim_chunking: src.var("NOX_SIM_CHUNKING").filter(|v| !v.trim().is_empty()),
+            sim_script: src.path("NOX_SIM_SCRIPT"),
+            sim_text: src
+                .var("NOX_SIM_TEXT")
                 .and_then(|v| if v.trim().is_empty() { None } else { Some(v) }),
-            prepack: env_bool("NOX_PREPACK")
-                .or_else(|| env_bool("NOX_MLOCK"))
+            prepack: src.bool("NOX_PREPACK")
+                .or_else(|| src.bool("NOX_MLOCK"))
                 .unwrap_or(false),
             route_enabled,
             route_query,
-            route_delim: env::var("NOX_ROUTE_DELIM").unwrap_or_else(|_| "---".to_string()),
-            route_keep: env_u32("NOX_ROUTE_KEEP").unwrap_or(4) as usize,
-            route_debug: env_bool("NOX_ROUTE_DEBUG").unwrap_or(false),
-            persist: env_bool("NOX_PERSIST")
-                .or_else(|| env_bool("NOX_DAEMON"))
-                .or_else(|| env_bool("NOX_REPL"))
+            route_delim: src.var("NOX_ROUTE_DELIM").unwrap_or_else(|| "---".to_string()),
+            route_split: src.var("NOX_ROUTE_SPLIT").unwrap_or_else(|| "delim".to_string()),
+            route_min_chars: src.u32("NOX_ROUTE_MIN_CHARS").unwrap_or(0) as usize,
+            route_autochunk: src.u32("NOX_ROUTE_AUTOCHUNK_CHARS").unwrap_or(2000) as usize,
+            route_window_chars: src.u32("NOX_ROUTE_WINDOW_CHARS").unwrap_or(1000) as usize,
+            route_window_overlap: src.u32("NOX_ROUTE_WINDOW_OVERLAP").unwrap_or(200) as usize,
+            route_format: src.var("NOX_ROUTE_FORMAT").unwrap_or_else(|| "auto".to_string()),
+            route_keep: src.u32("NOX_ROUTE_KEEP").unwrap_or(4) as usize,
+            route_threshold: src.f32("NOX_ROUTE_THRESHOLD").map(|v| v.clamp(0.0, 1.0)),
+            route_min: src.u32("NOX_ROUTE_MIN").map(|v| v as usize),
+            route_profile: src
+                .var("NOX_ROUTE_PROFILE")
+                .unwrap_or_else(|| "default".to_string()),
+            route_weights_file: src.var("NOX_ROUTE_WEIGHTS").map(PathBuf::from),
+            // Filled in by `run`, which reads the file once.
+            route_weights: None,
+            route_scorer: src
+                .var("NOX_ROUTE_SCORER")
+                .unwrap_or_else(|| "overlap".to_string()),
+            route_bm25_k1: src.f32("NOX_ROUTE_BM25_K1").unwrap_or(1.2),
+            route_bm25_b: src.f32("NOX_ROUTE_BM25_B").unwrap_or(0.75),
+            route_embed_runner: src.var("NOX_ROUTE_EMBED_RUNNER").map(PathBuf::from),
+            route_embed_model: src.var("NOX_ROUTE_EMBED_MODEL").map(PathBuf::from),
+            route_stopwords: src.bool("NOX_ROUTE_STOPWORDS"),
+            route_stem: src.bool("NOX_ROUTE_STEM").unwrap_or(false),
+            route_budget_chars: src.u64("NOX_ROUTE_BUDGET_CHARS"),
+            route_budget_tokens: src.u32("NOX_ROUTE_BUDGET_TOKENS"),
+            route_diversity: src
+                .f32("NOX_ROUTE_DIVERSITY")
+                .map_or(0.0, |v| v.clamp(0.0, 1.0)),
+            route_anchor: src.var("NOX_ROUTE_ANCHOR").unwrap_or_default(),
+            route_dedup: src
+                .f32("NOX_ROUTE_DEDUP")
+                .map_or(0.9, |v| v.clamp(0.0, 1.0)),
+            route_order: src
+                .var("NOX_ROUTE_ORDER")
+                .unwrap_or_else(|| "document".to_string()),
+            route_fuzzy: src
+                .f32("NOX_ROUTE_FUZZY")
+                .map_or(0.01, |v| v.clamp(0.0, 1.0)),
+            route_debug: src.bool("NOX_ROUTE_DEBUG").unwrap_or(false),
+            route_debug_json: route_report::wanted(src.var("NOX_ROUTE_DEBUG").as_deref()),
+            persist: src.bool("NOX_PERSIST")
+                .or_else(|| src.bool("NOX_DAEMON"))
+                .or_else(|| src.bool("NOX_REPL"))
                 .unwrap_or(false),
-            persist_rs: env_bool("NOX_PERSIST_RS").unwrap_or(false),
-            keep_cache: env_bool("NOX_KEEP_CACHE").unwrap_or(false),
-            append_only: env_bool("NOX_APPEND").unwrap_or(false),
-            input_only: env_bool("NOX_INPUT_ONLY").unwrap_or(false),
-            state_save: env_path("NOX_STATE_SAVE"),
-            state_load: env_path("NOX_STATE_LOAD"),
+            persist_rs: src.bool("NOX_PERSIST_RS").unwrap_or(false),
+            persist_proto: src
+                .var("NOX_PERSIST_PROTO")
+                .map(|v| v.trim().to_ascii_lowercase())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| "text".to_string()),
+            listen: src.path("NOX_LISTEN"),
+            max_restarts: src.u32("NOX_MAX_RESTARTS").unwrap_or(3),
+            idle_timeout_secs: src.u64("NOX_IDLE_TIMEOUT_SECS").filter(|v| *v > 0),
+            queue_max: src.u32("NOX_QUEUE_MAX").unwrap_or(32) as usize,
+            session_log: src.path("NOX_SESSION_LOG"),
+            session_log_max_mb: src
+                .f32("NOX_SESSION_LOG_MAX_MB")
+                .filter(|v| *v > 0.0)
+                .unwrap_or(50.0),
+            session_log_keep: src.u32("NOX_SESSION_LOG_KEEP").unwrap_or(5),
+            keep_cache: src.bool("NOX_KEEP_CACHE").unwrap_or(false),
+            append_only: src.bool("NOX_APPEND").unwrap_or(false),
+            input_only: src.bool("NOX_INPUT_ONLY").unwrap_or(false),
+            state_save: src.path("NOX_STATE_SAVE"),
+            state_load: src.path("NOX_STATE_LOAD"),
+            state_auto: src.bool("NOX_STATE_AUTO").unwrap_or(false),
+            state_max_mb: src.u64("NOX_STATE_MAX_MB").unwrap_or(DEFAULT_STATE_MAX_MB),
+            timeout_secs: src.u64("NOX_TIMEOUT_SECS").filter(|v| *v > 0),
+            stall_secs: src.u64("NOX_STALL_SECS").filter(|v| *v > 0),
+            resource_limits: rlimit::ResourceLimits::resolve(src),
+            verbose: src.bool("NOX_VERBOSE").unwrap_or(false),
+            quiet: src.bool("NOX_QUIET").unwrap_or(false),
+            progress: src.bool("NOX_PROGRESS").unwrap_or(false),
+            keep_echo: src.bool("NOX_KEEP_ECHO").unwrap_or(false),
+            debug: src.bool("NOX_DEBUG").unwrap_or(false),
+            dry_run: src.bool("NOX_DRY_RUN").unwrap_or(false),
+            log_file: src.path("NOX_LOG_FILE"),
+            grammar_file: src.path("NOX_GRAMMAR_FILE"),
+            json_schema_file: src.path("NOX_JSON_SCHEMA_FILE"),
+            json_retries: src.u32("NOX_JSON_RETRIES").unwrap_or(2),
+            chars_per_token: src
+                .f32("NOX_CHARS_PER_TOKEN")
+                .unwrap_or(tokens::DEFAULT_CHARS_PER_TOKEN),
+            persist_warmup: if warmup == Some(false) || no_warmup == Some(true) {
+                None
+            } else {
+                Some(src.var("NOX_WARMUP_PROMPT").unwrap_or_else(|| " ".to_string()))
+            },
+            system_prompt: src.var("NOX_SYSTEM_PROMPT").filter(|v| !v.trim().is_empty()),
+            omit_marker: src.bool("NOX_OMIT_MARKER").unwrap_or(true),
+            // Filled in by `run`, which also has the `--extra-arg` flags.
+            extra_args: Vec::new(),
         }
     }
 
-    fn resolve_runner(&self) -> Option<PathBuf> {
+    fn limits(&self) -> watchdog::Limits {
+        watchdog::Limits {
+            total: self.timeout_secs.map(Duration::from_secs),
+            stall: self.stall_secs.map(Duration::from_secs),
+        }
+    }
+
+    /// The runner persistent modes spawn: this binary, as the simulator's
+    /// serve child, under `NOX_EMULATE_A1000`.
+    fn serve_runner(&self) -> Result<PathBuf, NoxError> {
+        if self.emulate_a1000 {
+            return Ok(env::current_exe()?);
+        }
+        self.resolve_runner()
+    }
+
+    /// The model for a serve child; the simulator does without one.
+    fn serve_model(&self) -> Result<Option<String>, NoxError> {
+        if self.emulate_a1000 {
+            return Ok(self.resolve_model().ok().flatten());
+        }
+        self.resolve_model()
+    }
+
+    fn resolve_runner(&self) -> Result<PathBuf, NoxError> {
+        let mut checked = Vec::new();
         if let Some(p) = &self.runner_override {
-            if is_executable(p) {
-                return Some(p.clone());
+            if let Some(found) = find_executable(p) {
+                return Ok(found);
             }
+            checked.push(p.clone());
         }
-        let candidates: &[PathBuf] = match self.runner_style {
-            RunnerStyle::NoxLocal => &[
-                PathBuf::from("bin/noxlocal"),
-                PathBuf::from("noxpy/localrunner/noxlocal"),
-                PathBuf::from("../noxpy/localrunner/noxlocal"),
-            ],
-            RunnerStyle::LlamaCompletion => &[
-                PathBuf::from("bin/llama-completion"),
-                PathBuf::from("temp/llama.cpp/build/bin/llama-completion"),
-                PathBuf::from("../temp/llama.cpp/build/bin/llama-completion"),
-            ],
-            RunnerStyle::LlamaSimple => &[
-                PathBuf::from("bin/llama-simple"),
-                PathBuf::from("temp/llama.cpp/build/bin/llama-simple"),
-                PathBuf::from("../temp/llama.cpp/build/bin/llama-simple"),
+        if matches!(self.runner_style, RunnerStyle::Custom) {
+            let template = self.runner_template()?;
+            let program = template.program();
+            if let Some(found) = find_program(program) {
+                return Ok(found);
+            }
+            checked.push(PathBuf::from(program));
+            return Err(NoxError::RunnerNotFound {
+                style: self.runner_style.name(),
+                checked,
+            });
+        }
+        let candidates: Vec<PathBuf> = match self.runner_style {
+            RunnerStyle::NoxLocal => vec![
+                platform::rel(&["bin", "noxlocal"]),
+                platform::rel(&["noxpy", "localrunner", "noxlocal"]),
+                platform::rel(&["..", "noxpy", "localrunner", "noxlocal"]),
             ],
+            RunnerStyle::LlamaCompletion => llama_candidates("llama-completion"),
+            RunnerStyle::LlamaSimple => llama_candidates("llama-simple"),
+            RunnerStyle::LlamaEmbedding => llama_candidates("llama-embedding"),
+            RunnerStyle::Custom => Vec::new(),
         };
         for candidate in candidates {
-            if is_executable(candidate) {
-                return Some(candidate.clone());
+            if let Some(found) = find_executable(&candidate) {
+                return Ok(found);
             }
+            checked.push(candidate);
         }
-        None
+        Err(NoxError::RunnerNotFound {
+            style: self.runner_style.name(),
+            checked,
+        })
     }
 
-    fn model_path(&self) -> Option<String> {
+    fn runner_template(&self) -> Result<template::Template, NoxError> {
+        let text = self.runner_template.as_deref().ok_or_else(|| {
+            NoxError::Usage("NOX_RUNNER_STYLE=custom needs NOX_RUNNER_TEMPLATE".to_string())
+        })?;
+        template::Template::parse(text)
+            .map_err(|err| NoxError::Usage(format!("NOX_RUNNER_TEMPLATE: {err}")))
+    }
+
+    /// An explicit `NOX_MODEL_PATH` must exist. Otherwise the models
+    /// directories are scanned; `NOX_MODEL` may name a file there (or be a
+    /// path). Finding nothing without `NOX_MODEL` yields `None`, letting the
+    /// runner fall back to its own default.
+    fn resolve_model(&self) -> Result<Option<String>, NoxError> {
         if let Some(p) = &self.model_override {
-            if p.exists() {
-                return Some(p.to_string_lossy().into_owned());
-            }
+            return match models::check_file(p) {
+                Ok(()) => Ok(Some(p.to_string_lossy().into_owned())),
+                Err(reason) => Err(NoxError::ModelNotFound {
+                    setting: Some(format!("NOX_MODEL_PATH={}", p.display())),
+                    checked: vec![models::Checked {
+                        path: p.clone(),
+                        reason,
+                    }],
+                }),
+            };
         }
-        for candidate in [
-            "assets/models/mistral-7b-q4.gguf",
-            "assets/models/nox.gguf",
-            "../assets/models/mistral-7b-q4.gguf",
-            "../assets/models/nox.gguf",
-        ] {
-            let p = Path::new(candidate);
-            if p.exists() {
-                return Some(p.to_string_lossy().into_owned());
+        if let Some(name) = &self.model_name {
+            let p = Path::new(name);
+            if p.is_file() {
+                self.debug(format!("model: NOX_MODEL is a path, using {name}"));
+                return Ok(Some(name.clone()));
             }
         }
-        None
+        let dirs = models::search_dirs(self.models_dir.as_deref());
+        let scan = models::scan(&dirs, &mut |line| self.debug(format!("model: {line}")));
+        let chosen = match &self.model_name {
+            Some(name) => match models::fuzzy_match(&scan.found, name) {
+                Some(found) => {
+                    self.debug(format!("model: NOX_MODEL={name} matched"));
+                    found
+                }
+                None => {
+                    let mut checked = scan.rejected;
+                    checked.extend(scan.found.iter().map(|c| models::Checked {
+                        path: c.path.clone(),
+                        reason: models::Reason::NoNameMatch,
+                    }));
+                    return Err(NoxError::ModelNotFound {
+                        setting: Some(format!("NOX_MODEL={name}")),
+                        checked,
+                    });
+                }
+            },
+            None => match scan.found.first() {
+                Some(best) => best,
+                // Runners without a model either fail cryptically or load
+                // some default, so only templates may go without one.
+                None if self.runner_style.needs_model() => {
+                    return Err(NoxError::ModelNotFound {
+                        setting: None,
+                        checked: scan.rejected,
+                    });
+                }
+                None => {
+                    self.debug("model: nothing found, the template runs without one".to_string());
+                    return Ok(None);
+                }
+            },
+        };
+        self.debug(format!(
+            "model: chose {} (nox.gguf first, then newest)",
+            chosen.path.display()
+        ));
+        Ok(Some(chosen.path.to_string_lossy().into_owned()))
+    }
+
+    fn debug(&self, line: String) {
+        if self.debug {
+            eprintln!("nox: debug: {line}");
+        }
     }
 }
 
@@ -352,13 +1052,44 @@ enum RunnerStyle {
     NoxLocal,
     LlamaCompletion,
     LlamaSimple,
+    LlamaEmbedding,
+    Custom,
 }
 
 impl RunnerStyle {
-    fn from_env() -> Self {
-        let style = env::var("NOX_RUNNER_STYLE").unwrap_or_else(|_| "noxlocal".to_string());
+    fn name(self) -> &'static str {
+        match self {
+            RunnerStyle::NoxLocal => "noxlocal",
+            RunnerStyle::LlamaCompletion => "llama-completion",
+            RunnerStyle::LlamaSimple => "llama-simple",
+            RunnerStyle::LlamaEmbedding => "llama-embedding",
+            RunnerStyle::Custom => "custom",
+        }
+    }
+
+    /// Every built-in style needs a model; custom templates decide for
+    /// themselves through `{model}`.
+    fn needs_model(self) -> bool {
+        !matches!(self, RunnerStyle::Custom)
+    }
+
+    fn is_llama(self) -> bool {
+        matches!(
+            self,
+            RunnerStyle::LlamaCompletion | RunnerStyle::LlamaSimple | RunnerStyle::LlamaEmbedding
+        )
+    }
+
+    fn resolve(src: &config::Sources) -> Self {
+        let style = src
+            .var("NOX_RUNNER_STYLE")
+            .unwrap_or_else(|| "noxlocal".to_string());
         let value = style.trim().to_ascii_lowercase();
-        if value.contains("simple") {
+        if value == "custom" {
+            RunnerStyle::Custom
+        } else if value.contains("embed") {
+            RunnerStyle::LlamaEmbedding
+        } else if value.contains("simple") {
             RunnerStyle::LlamaSimple
         } else if value.starts_with("llama") || value == "completion" {
             RunnerStyle::LlamaCompletion
@@ -368,10 +1099,17 @@ impl RunnerStyle {
     }
 }
 
-fn read_prompt() -> io::Result<String> {
-    let args: Vec<String> = env::args().skip(1).collect();
-    if !args.is_empty() {
-        return Ok(args.join(" "));
+/// Refuses a prompt with nothing but whitespace in it.
+fn check_prompt(prompt: &str) -> Result<(), NoxError> {
+    if prompt.trim().is_empty() {
+        return Err(NoxError::Usage("empty prompt".to_string()));
+    }
+    Ok(())
+}
+
+fn read_prompt(cli: &Cli) -> io::Result<String> {
+    if !cli.prompt.is_empty() {
+        return Ok(cli.prompt.join(" "));
     }
     let stdin = io::stdin();
     let mut lock = stdin.lock();
@@ -380,38 +1118,31 @@ fn read_prompt() -> io::Result<String> {
     Ok(buf)
 }
 
-fn env_u32(key: &str) -> Option<u32> {
-    env::var(key).ok().and_then(|v| v.parse::<u32>().ok())
-}
-
-fn env_i32(key: &str) -> Option<i32> {
-    env::var(key).ok().and_then(|v| v.parse::<i32>().ok())
+/// `bin/<name>` plus the llama.cpp build tree locations.
+fn llama_candidates(name: &str) -> Vec<PathBuf> {
+    vec![
+        platform::rel(&["bin", name]),
+        platform::rel(&["temp", "llama.cpp", "build", "bin", name]),
+        platform::rel(&["..", "temp", "llama.cpp", "build", "bin", name]),
+    ]
 }
 
-fn env_f32(key: &str) -> Option<f32> {
-    env::var(key).ok().and_then(|v| v.parse::<f32>().ok())
-}
-
-fn env_u64(key: &str) -> Option<u64> {
-    env::var(key).ok().and_then(|v| v.parse::<u64>().ok())
-}
-
-fn env_bool(key: &str) -> Option<bool> {
-    env::var(key).ok().map(|v| {
-        let v = v.trim();
-        v == "1" || v.eq_ignore_ascii_case("true") || v.eq_ignore_ascii_case("yes")
-    })
+/// Resolves a template program: paths are used as given, bare names are
+/// looked up on `PATH` (`%PATH%` plus `%PATHEXT%` suffixes on Windows).
+fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return find_executable(path);
+    }
+    env::split_paths(&env::var_os("PATH")?).find_map(|dir| find_executable(&dir.join(program)))
 }
 
-fn env_path(key: &str) -> Option<PathBuf> {
-    env::var(key).ok().and_then(|v| {
-        let v = v.trim();
-        if v.is_empty() {
-            None
-        } else {
-            Some(PathBuf::from(v))
-        }
-    })
+/// The first executable among the platform variants of `path`.
+fn find_executable(path: &Path) -> Option<PathBuf> {
+    let suffixes = platform::current_suffixes();
+    platform::variants(path, platform::WINDOWS, &suffixes)
+        .into_iter()
+        .find(|candidate| is_executable(candidate))
 }
 
 fn is_executable(path: &Path) -> bool {
@@ -419,26 +1150,23 @@ fn is_executable(path: &Path) -> bool {
         return false;
     }
     fs::metadata(path)
-        .map(|m| m.is_file() && (cfg!(windows) || m.mode_bits_executable()))
+        .map(|m| {
+            m.is_file()
+                && if platform::WINDOWS {
+                    platform::has_suffix(path, &platform::current_suffixes())
+                } else {
+                    m.mode_bits_executable()
+                }
+        })
         .unwrap_or(false)
 }
 
-fn run_persistent(cfg: &Config) -> io::Result<()> {
-    if !matches!(cfg.runner_style, RunnerStyle::NoxLocal) {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "persistent mode requires NOX_RUNNER_STYLE=noxlocal",
-        ));
+/// Assembles the `noxlocal -serve` invocation used by persistent and batch modes.
+fn serve_command(cfg: &Config, runner: &Path, model: Option<&str>) -> Command {
+    let mut cmd = Command::new(runner);
+    if cfg.emulate_a1000 {
+        cmd.arg(sim_serve::FLAG);
     }
-    let runner = cfg
-        .resolve_runner()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no runner binary found"))?;
-
-    let mut cmd = Command::new(&runner);
-    cmd.stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::inherit());
-
     cmd.arg("-serve");
     if cfg.persist_rs {
         cmd.arg("-serve-rs");
@@ -475,166 +1203,334 @@ fn run_persistent(cfg: &Config) -> io::Result<()> {
     cmd.args(["-temp", &cfg.temp.to_string()]);
     cmd.args(["-top-p", &cfg.top_p.to_string()]);
     cmd.args(["-top-k", &cfg.top_k.to_string()]);
-    if let Some(model) = cfg.model_path() {
-        cmd.args(["-model", &model]);
+    cfg.sampling.noxlocal_args(&mut cmd, runner);
+    if let Some(model) = model {
+        cmd.args(["-model", model]);
     }
     if let Some(threads) = cfg.threads {
         cmd.env("NOX_NUM_THREADS", threads.to_string());
     }
-
-    let mut child = cmd.spawn()?;
-    let mut child_stdin = child
-        .stdin
-        .take()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to open child stdin"))?;
-    let mut child_stdout = child
-        .stdout
-        .take()
-        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "failed to open child stdout"))?;
-
-    let stdin_thread = thread::spawn(move || {
-        let stdin = io::stdin();
-        let _ = io::copy(&mut stdin.lock(), &mut child_stdin);
-    });
-
-    let mut stdout = io::stdout();
-    let _ = io::copy(&mut child_stdout, &mut stdout);
-    let _ = stdin_thread.join();
-
-    let status = child.wait()?;
-    if !status.success() {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("runner exited with status {status}"),
-        ));
-    }
-    Ok(())
+    let mut cmd = extra_args::merge(cfg, cmd, 0);
+    cfg.resource_limits.apply(&mut cmd);
+    cmd
 }
 
-fn route_prompt(cfg: &Config, prompt: &str) -> Option<String> {
-    let delim = cfg.route_delim.trim();
-    if delim.is_empty() {
-        return None;
-    }
-    let mut chunks: Vec<String> = prompt
-        .split(delim)
-        .map(|s| s.trim())
-        .filter(|s| !s.is_empty())
-        .map(|s| s.to_string())
-        .collect();
-
-    if chunks.len() < 2 {
-        return None;
-    }
-
-    let (query, candidates) = if let Some(query) = &cfg.route_query {
-        (query.trim().to_string(), chunks)
-    } else {
-        let query = chunks.remove(0);
-        (query, chunks)
+/// Routes `prompt` as the config says. `cache` keeps the chunks' words for
+/// the next call; modes that route many prompts hold one throughout.
+fn route_prompt(cfg: &Config, prompt: &str, cache: &mut RouteCache) -> Option<Routed> {
+    let route = route_config(cfg);
+    // Checked when the config was loaded.
+    let scorer = route_scorer(cfg).unwrap_or_default();
+    let mut tokens = TokenOptions::for_scorer(scorer);
+    tokens.stopwords = cfg.route_stopwords.unwrap_or(tokens.stopwords);
+    tokens.stem = cfg.route_stem;
+    let mut opts = RouteOptions {
+        delim: &cfg.route_delim,
+        split: route_split(cfg).unwrap_or_default(),
+        min_chars: cfg.route_min_chars,
+        autochunk: route_autochunk(cfg).unwrap_or_default(),
+        query: cfg.route_query.as_deref(),
+        keep: cfg.route_keep,
+        scorer,
+        tokens,
+        route,
+        budget: None,
+        diversity: cfg.route_diversity,
+        anchors: route_anchors(cfg).unwrap_or_default(),
+        dedup: (cfg.route_dedup > 0.0).then_some(cfg.route_dedup),
+        explain: cfg.route_debug,
+        report: cfg.route_debug_json,
+        order: route_order(cfg).unwrap_or_default(),
+        fuzzy: (cfg.route_fuzzy > 0.0).then_some(cfg.route_fuzzy),
     };
-
-    if candidates.is_empty() {
-        return None;
-    }
-
-    let scores: Vec<f32> = candidates
-        .iter()
-        .map(|chunk| overlap_score(&query, chunk))
-        .collect();
-
-    let mut selected = if scores.iter().all(|s| *s <= 0.0) {
-        top_k_indices(&scores, cfg.route_keep.max(1))
-    } else {
-        let route = neuroute::route_values(&scores);
-        let mut idxs: Vec<usize> = route
-            .mask
-            .iter()
-            .enumerate()
-            .filter_map(|(i, keep)| if *keep { Some(i) } else { None })
-            .collect();
-        if idxs.is_empty() || idxs.len() == scores.len() {
-            idxs = top_k_indices(&scores, cfg.route_keep.max(1));
+    // A dry run starts no runner, so it scores as embedding failed would.
+    let embed = route_embed::enabled(cfg) && !cfg.dry_run;
+    let mut score = |query: &str, chunks: &[String]| {
+        if !embed {
+            return None;
+        }
+        match route_embed::scores(cfg, query, chunks) {
+            Ok(scores) => Some(scores),
+            Err(err) => {
+                eprintln!(
+                    "nox: warning: could not embed the chunks ({err}); scoring by word overlap instead"
+                );
+                None
+            }
         }
-        idxs
     };
-
-    if cfg.route_keep > 0 && selected.len() > cfg.route_keep {
-        selected = top_k_indices(&scores, cfg.route_keep);
+    let given = route_json::chunks(cfg, prompt);
+    let query_chars = match &given {
+        Ok(Some((query, _))) => query.trim().chars().count(),
+        _ => noxroute_core::prompt_query(prompt, &opts).map_or(0, |query| query.chars().count()),
+    };
+    opts.budget = route_budget(cfg, query_chars);
+    let before = cache.stats();
+    let routed = match given {
+        Ok(Some((query, chunks))) => {
+            noxroute_core::route_chunks_scored(&query, chunks, &opts, cache, &mut score)?
+        }
+        Ok(None) => noxroute_core::route_prompt_scored(prompt, &opts, cache, &mut score)?,
+        Err(why) => {
+            if cfg.route_debug {
+                eprintln!(
+                    "nox: context is not a JSON array of chunks ({why}); splitting it instead"
+                );
+            }
+            noxroute_core::route_prompt_scored(prompt, &opts, cache, &mut score)?
+        }
+    };
+    if routed.anchors_over_budget {
+        eprintln!("nox: warning: the anchored chunks alone are over the routing budget; keeping only them");
+    }
+    if let (true, Some(report)) = (cfg.route_debug_json, &routed.report) {
+        eprintln!("{}", route_report::to_json(report));
     }
-    selected.sort_unstable();
-
-    let joiner = format!("\n{delim}\n");
-    let context = selected
-        .iter()
-        .map(|idx| candidates[*idx].clone())
-        .collect::<Vec<_>>()
-        .join(&joiner);
-
     if cfg.route_debug {
+        let after = cache.stats();
+        eprintln!(
+            "nox: route cache: {} hits, {} misses ({} chunks, {} bytes held)",
+            after.hits - before.hits,
+            after.misses - before.misses,
+            after.entries,
+            after.bytes
+        );
+        if routed.windows > 0 {
+            eprintln!(
+                "nox: no chunks to route; autochunked the context into {} windows",
+                routed.windows
+            );
+        }
+        if routed.fuzzy {
+            eprintln!(
+                "nox: no chunk scored {} or more; scored them by character trigrams instead",
+                cfg.route_fuzzy
+            );
+        }
+        for dup in &routed.duplicates {
+            eprintln!(
+                "nox: dropped chunk {} as a near-duplicate of chunk {} (similarity {:.3})",
+                dup.dropped + 1,
+                dup.kept + 1,
+                dup.similarity
+            );
+        }
+        let budget = match opts.budget {
+            Some(budget) => format!(
+                " (budget {budget} chars, used {}, skipped {})",
+                routed.chars.saturating_sub(query_chars),
+                routed.skipped
+            ),
+            None => String::new(),
+        };
         eprintln!(
-            "nox: routed {} -> {} chunks",
-            candidates.len(),
-            selected.len()
+            "nox: routed {} -> {} chunks{budget}",
+            routed.candidates, routed.selected
         );
+        if cfg.emulate_a1000 && cfg.sim_prefill_tps.is_some() {
+            let (full, pruned) = (sim::prefill(cfg, prompt), sim::prefill(cfg, &routed.prompt));
+            eprintln!(
+                "nox: simulated prefill {} ms -> {} ms, saving {} ms of TTFT",
+                full.as_millis(),
+                pruned.as_millis(),
+                full.saturating_sub(pruned).as_millis()
+            );
+        }
+        for (n, pick) in routed.picks.iter().enumerate() {
+            let anchored = if routed.anchored.contains(&pick.chunk) {
+                " (anchored)"
+            } else {
+                ""
+            };
+            eprintln!(
+                "nox: pick {}: chunk {} score {:.3} penalty {:.3}{anchored}",
+                n + 1,
+                pick.chunk + 1,
+                pick.score,
+                pick.penalty
+            );
+        }
+        if routed.picks.is_empty() {
+            for chunk in &routed.anchored {
+                eprintln!("nox: anchored chunk {}", chunk + 1);
+            }
+        }
+        for (n, ex) in routed.explanations.iter().enumerate() {
+            eprintln!(
+                "nox: chunk {}: score {:.3} logit {:.3} prob {:.3} {}; {}",
+                n + 1,
+                ex.features[0],
+                ex.logit,
+                ex.prob,
+                if ex.kept { "kept" } else { "dropped" },
+                top_contributions(ex)
+            );
+        }
     }
+    Some(routed)
+}
 
-    if context.is_empty() {
-        return Some(query);
+/// The three inputs that moved the router's logit most, signed, e.g.
+/// `rank +52.2, cdf +54.9, mean -22.4`.
+fn top_contributions(ex: &Explanation) -> String {
+    let mut order: Vec<usize> = (0..ex.contributions.len()).collect();
+    order.sort_by(|&a, &b| {
+        ex.contributions[b]
+            .abs()
+            .total_cmp(&ex.contributions[a].abs())
+    });
+    order
+        .iter()
+        .take(3)
+        .map(|&at| format!("{} {:+.1}", FEATURE_NAMES[at], ex.contributions[at]))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The `NOX_ROUTE_SPLIT` mode; for `regex`, `NOX_ROUTE_DELIM` must compile.
+fn route_split(cfg: &Config) -> Result<Split, NoxError> {
+    let split = Split::from_name(&cfg.route_split).ok_or_else(|| {
+        NoxError::Usage(format!(
+            "NOX_ROUTE_SPLIT must be `delim`, `regex`, `markdown`, or `blank`, got `{}`",
+            cfg.route_split.trim()
+        ))
+    })?;
+    if split == Split::Regex {
+        Regex::new(&cfg.route_delim).map_err(|err| {
+            NoxError::Usage(format!(
+                "NOX_ROUTE_DELIM `{}` is not a valid pattern: {err}",
+                cfg.route_delim
+            ))
+        })?;
     }
-    Some(format!("{query}{joiner}{context}"))
+    Ok(split)
 }
 
-fn overlap_score(query: &str, chunk: &str) -> f32 {
-    let q = token_set(query);
-    let c = token_set(chunk);
-    if q.is_empty() || c.is_empty() {
-        return 0.0;
+/// Windowing for prompts without chunks, unless `NOX_ROUTE_AUTOCHUNK_CHARS`
+/// is 0.
+fn route_autochunk(cfg: &Config) -> Result<Option<Autochunk>, NoxError> {
+    if cfg.route_autochunk == 0 {
+        return Ok(None);
     }
-    let mut common = 0usize;
-    for tok in q.iter() {
-        if c.contains(tok) {
-            common += 1;
-        }
+    let (window, overlap) = (cfg.route_window_chars, cfg.route_window_overlap);
+    if window == 0 || overlap > window / 2 {
+        return Err(NoxError::Usage(format!(
+            "NOX_ROUTE_WINDOW_CHARS must be above 0 and NOX_ROUTE_WINDOW_OVERLAP at most half of it, got {window} and {overlap}"
+        )));
     }
-    common as f32 / q.len() as f32
+    Ok(Some(Autochunk {
+        above: cfg.route_autochunk,
+        window,
+        overlap,
+    }))
 }
 
-fn token_set(text: &str) -> HashSet<String> {
-    let mut set = HashSet::new();
-    let mut buf = String::new();
-    for ch in text.chars() {
-        if ch.is_ascii_alphanumeric() {
-            for lower in ch.to_lowercase() {
-                buf.push(lower);
-            }
-        } else if !buf.is_empty() {
-            set.insert(buf.clone());
-            buf.clear();
+/// The `NOX_ROUTE_ANCHOR` chunks.
+fn route_anchors(cfg: &Config) -> Result<Anchors, NoxError> {
+    Anchors::from_names(&cfg.route_anchor).ok_or_else(|| {
+        NoxError::Usage(format!(
+            "NOX_ROUTE_ANCHOR must list `first`, `last`, or both, got `{}`",
+            cfg.route_anchor.trim()
+        ))
+    })
+}
+
+/// The `NOX_ROUTE_ORDER` for the kept chunks.
+fn route_order(cfg: &Config) -> Result<Order, NoxError> {
+    Order::from_name(&cfg.route_order).ok_or_else(|| {
+        NoxError::Usage(format!(
+            "NOX_ROUTE_ORDER must be `document` or `score`, got `{}`",
+            cfg.route_order.trim()
+        ))
+    })
+}
+
+/// Characters the routed chunks may take: `NOX_ROUTE_BUDGET_CHARS`, else
+/// `NOX_ROUTE_BUDGET_TOKENS` converted with `NOX_CHARS_PER_TOKEN`, else
+/// whatever `NOX_CTX` leaves after the `prompt_chars` of the query and
+/// `NOX_MAX_TOKENS` of answer. An explicit 0 means no budget.
+fn route_budget(cfg: &Config, prompt_chars: usize) -> Option<usize> {
+    let chars = |tokens: u32| (tokens as f32 * cfg.chars_per_token) as usize;
+    let budget = match (cfg.route_budget_chars, cfg.route_budget_tokens) {
+        (Some(budget), _) => budget as usize,
+        (None, Some(tokens)) => chars(tokens),
+        (None, None) => {
+            return Some(
+                chars(cfg.ctx)
+                    .saturating_sub(prompt_chars)
+                    .saturating_sub(chars(cfg.max_tokens)),
+            );
         }
+    };
+    (budget > 0).then_some(budget)
+}
+
+/// The router's tuning: the `NOX_ROUTE_PROFILE` profile, with
+/// `NOX_ROUTE_MIN` and `NOX_ROUTE_KEEP` as its keep bounds and the
+/// `NOX_ROUTE_WEIGHTS` weights in place of its own. A
+/// `NOX_ROUTE_THRESHOLD` replaces the profile's and keeps exactly what
+/// clears it.
+fn route_config(cfg: &Config) -> RouteConfig {
+    // Checked when the config was loaded.
+    let profile = route_profile(cfg).map_or_else(|_| RouteConfig::default(), |m| m.config);
+    let mut route = RouteConfig {
+        min_keep: cfg.route_min.unwrap_or(profile.min_keep),
+        max_keep: (cfg.route_keep > 0).then_some(cfg.route_keep),
+        weights: cfg.route_weights.unwrap_or(profile.weights),
+        ..profile
+    };
+    if let Some(threshold) = cfg.route_threshold {
+        route.threshold = threshold;
+        route.prefer = MaskStrategy::Threshold;
     }
-    if !buf.is_empty() {
-        set.insert(buf);
-    }
-    set
+    route
+}
+
+/// The `NOX_ROUTE_PROFILE` router profile.
+fn route_profile(cfg: &Config) -> Result<RoutingModel, NoxError> {
+    RoutingModel::builtin(&cfg.route_profile).ok_or_else(|| {
+        NoxError::Usage(format!(
+            "NOX_ROUTE_PROFILE must be one of {}, got `{}`",
+            RoutingModel::NAMES.join(", "),
+            cfg.route_profile.trim()
+        ))
+    })
+}
+
+/// The weights in the `NOX_ROUTE_WEIGHTS` file, if it is set.
+fn route_weights(cfg: &Config) -> Result<Option<Weights>, NoxError> {
+    let Some(path) = &cfg.route_weights_file else {
+        return Ok(None);
+    };
+    let text = fs::read_to_string(path)
+        .map_err(|err| NoxError::Usage(format!("NOX_ROUTE_WEIGHTS={}: {err}", path.display())))?;
+    let weights = Weights::parse(&text)
+        .map_err(|err| NoxError::Usage(format!("NOX_ROUTE_WEIGHTS={}: {err}", path.display())))?;
+    Ok(Some(weights))
 }
 
-fn top_k_indices(scores: &[f32], k: usize) -> Vec<usize> {
-    if scores.is_empty() {
-        return Vec::new();
+/// The `NOX_ROUTE_SCORER` scorer, with `NOX_ROUTE_BM25_K1`/`_B` for BM25.
+/// For `embed`, the one used when embedding fails.
+fn route_scorer(cfg: &Config) -> Result<Scorer, NoxError> {
+    if route_embed::enabled(cfg) {
+        return Ok(route_embed::FALLBACK);
     }
-    if k == 0 || k >= scores.len() {
-        return (0..scores.len()).collect();
+    let scorer = Scorer::from_name(&cfg.route_scorer).ok_or_else(|| {
+        NoxError::Usage(format!(
+            "NOX_ROUTE_SCORER must be `overlap`, `tfidf`, `bm25`, or `embed`, got `{}`",
+            cfg.route_scorer.trim()
+        ))
+    })?;
+    if !matches!(scorer, Scorer::Bm25 { .. }) {
+        return Ok(scorer);
     }
-    let mut idx: Vec<usize> = (0..scores.len()).collect();
-    idx.sort_by(|&a, &b| {
-        scores[b]
-            .partial_cmp(&scores[a])
-            .unwrap_or(std::cmp::Ordering::Equal)
-            .then_with(|| a.cmp(&b))
-    });
-    idx.truncate(k);
-    idx
+    let (k1, b) = (cfg.route_bm25_k1, cfg.route_bm25_b);
+    if !(0.0..=f32::MAX).contains(&k1) || !(0.0..=1.0).contains(&b) {
+        return Err(NoxError::Usage(format!(
+            "NOX_ROUTE_BM25_K1 must be at least 0 and NOX_ROUTE_BM25_B between 0 and 1, got {k1} and {b}"
+        )));
+    }
+    Ok(Scorer::Bm25 { k1, b })
 }
 
 trait MetadataExt {
@@ -656,66 +1552,160 @@ impl MetadataExt for fs::Metadata {
     }
 }
 
-fn simulate_stream(cfg: &Config, prompt: &str) -> io::Result<()> {
-    let mut out = io::stdout();
-    if !cfg.raw {
-        writeln!(out, "nox:")?;
-        out.flush()?;
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let text = cfg
-        .sim_text
-        .clone()
-        .unwrap_or_else(|| default_sim_text(prompt));
-    let chunks = split_chunks(&text);
+    /// A config from these settings alone, without the environment.
+    pub(crate) fn config(values: &[(&str, &str)]) -> Config {
+        let values = values
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::resolve(&config::Sources::new(vec![config::Layer::new(
+            "test", values,
+        )]))
+    }
 
-    if !chunks.is_empty() && cfg.sim_ttft_ms > 0 {
-        thread::sleep(Duration::from_millis(cfg.sim_ttft_ms));
+    #[test]
+    fn an_empty_prompt_is_a_usage_error() {
+        for prompt in ["", "  \n\t"] {
+            let err = check_prompt(prompt).unwrap_err();
+            assert_eq!(err.exit_code(), 2);
+            assert_eq!(err.to_string(), "empty prompt");
+        }
+        assert!(check_prompt("hi").is_ok());
     }
-    let delay = if cfg.sim_tps > 0.0 {
-        Duration::from_secs_f32(1.0 / cfg.sim_tps)
-    } else {
-        Duration::from_secs(0)
-    };
 
-    for (idx, chunk) in chunks.iter().enumerate() {
-        out.write_all(chunk.as_bytes())?;
-        out.flush()?;
-        if idx + 1 < chunks.len() && delay.as_nanos() > 0 {
-            thread::sleep(delay);
+    #[test]
+    fn a_missing_runner_exits_3_listing_the_candidates() {
+        let cfg = config(&[("NOX_LOCAL_RUNNER", "/nonexistent/noxlocal")]);
+        let err = cfg.resolve_runner().unwrap_err();
+        assert_eq!(err.exit_code(), 3);
+        let NoxError::RunnerNotFound { checked, .. } = &err else {
+            panic!("{err}");
+        };
+        assert_eq!(checked[0], PathBuf::from("/nonexistent/noxlocal"));
+        assert_eq!(checked.len(), 4);
+        for path in checked {
+            assert!(err.to_string().contains(&path.display().to_string()));
         }
     }
 
-    if !cfg.raw {
-        out.write_all(b"\n")?;
-        out.flush()?;
+    #[test]
+    fn a_missing_model_path_exits_4() {
+        let cfg = config(&[("NOX_MODEL_PATH", "/nonexistent/nox.gguf")]);
+        let err = cfg.resolve_model().unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let details = err.details().join("\n");
+        assert!(
+            details.contains("/nonexistent/nox.gguf: missing"),
+            "{details}"
+        );
     }
-    Ok(())
-}
 
-fn default_sim_text(prompt: &str) -> String {
-    let prompt = prompt.trim();
-    if prompt.is_empty() {
-        "simulated A1000 mode. streaming output to validate the pipeline. ".to_string()
-    } else {
-        format!(
-            "simulated A1000 mode. prompt: {}. streaming output to validate the pipeline. ",
-            prompt
-        )
+    #[test]
+    fn an_unmatched_model_name_exits_4_listing_what_was_scanned() {
+        let dir = env::temp_dir().join(format!("nox-models-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("other.gguf"), b"GGUF").unwrap();
+        let cfg = config(&[
+            ("NOX_MODELS_DIR", &dir.display().to_string()),
+            ("NOX_MODEL", "missing-model"),
+        ]);
+        let err = cfg.resolve_model().unwrap_err();
+        assert_eq!(err.exit_code(), 4);
+        let details = err.details().join("\n");
+        assert!(details.contains("other.gguf"), "{details}");
+        fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-fn split_chunks(text: &str) -> Vec<String> {
-    let mut chunks = Vec::new();
-    for (idx, word) in text.split_whitespace().enumerate() {
-        if idx == 0 {
-            chunks.push(word.to_string());
-        } else {
-            chunks.push(format!(" {}", word));
-        }
+    #[test]
+    fn ttft_splits_into_prefill_and_fixed() {
+        let stats = |ttft: Option<u64>, prefill: Option<u64>| RunStats {
+            ttft: ttft.map(Duration::from_millis),
+            duration: Duration::from_secs(1),
+            tokens: None,
+            prefill: prefill.map(Duration::from_millis),
+        };
+        assert_eq!(
+            stats(Some(4120), Some(4000)).ttft_label(),
+            "4120 ms (prefill 4000 ms, fixed 120 ms)"
+        );
+        assert_eq!(stats(Some(120), None).ttft_label(), "120 ms");
+        assert_eq!(stats(None, None).ttft_label(), "-");
+    }
+
+    #[test]
+    fn route_settings_fill_the_route_config() {
+        let route = route_config(&config(&[]));
+        let want = RouteConfig {
+            max_keep: Some(4),
+            ..RouteConfig::default()
+        };
+        assert_eq!(route, want);
+
+        let route = route_config(&config(&[
+            ("NOX_ROUTE_THRESHOLD", "1.5"),
+            ("NOX_ROUTE_MIN", "2"),
+            ("NOX_ROUTE_KEEP", "0"),
+        ]));
+        assert_eq!(route.threshold, 1.0);
+        assert_eq!(route.prefer, MaskStrategy::Threshold);
+        assert_eq!((route.min_keep, route.max_keep), (2, None));
+
+        let recall = [("NOX_ROUTE_PROFILE", "recall")];
+        assert_eq!(route_config(&config(&recall)).min_keep, 2);
+        let route = route_config(&config(&[recall[0], ("NOX_ROUTE_MIN", "0")]));
+        assert_eq!(route.min_keep, 0);
+    }
+
+    #[test]
+    fn route_weights_replace_the_profile_weights() {
+        let path = env::temp_dir().join(format!("nox-weights-{}.txt", std::process::id()));
+        let file = [("NOX_ROUTE_WEIGHTS", path.to_str().unwrap())];
+        let weights = "hidden 1\nw1 1 0 0 0 0 0 0 0\nb1 0\nw2 10\nb2 -5\n";
+        fs::write(&path, weights).unwrap();
+        let mut cfg = config(&[file[0], ("NOX_ROUTE_PROFILE", "aggressive")]);
+        cfg.route_weights = route_weights(&cfg).unwrap();
+        let route = route_config(&cfg);
+        assert_eq!(route.weights, Weights::parse(weights).unwrap());
+        // The rest of the profile stays.
+        assert_eq!(route.threshold, 0.7);
+
+        fs::write(&path, "hidden 1\nw1 1\n").unwrap();
+        let err = route_weights(&config(&file)).unwrap_err().to_string();
+        fs::remove_file(&path).unwrap();
+        assert!(err.ends_with(": `b1` is missing"), "{err}");
+        assert!(err.contains("NOX_ROUTE_WEIGHTS="), "{err}");
+        assert_eq!(route_weights(&config(&[])).unwrap(), None);
     }
-    if chunks.is_empty() && !text.is_empty() {
-        chunks.push(text.to_string());
+
+    #[test]
+    fn the_default_route_budget_leaves_room_for_the_prompt_and_answer() {
+        let window = [
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_CTX", "1000"),
+            ("NOX_MAX_TOKENS", "200"),
+            ("NOX_CHARS_PER_TOKEN", "4"),
+        ];
+        let cfg = config(&window);
+        assert_eq!(route_budget(&cfg, 0), Some(3200));
+        assert_eq!(route_budget(&cfg, 700), Some(2500));
+        // A query that fills the window leaves nothing, not an overflow.
+        assert_eq!(route_budget(&cfg, 5000), Some(0));
+
+        let cfg = config(&[window[0], window[3], ("NOX_ROUTE_BUDGET_TOKENS", "10")]);
+        assert_eq!(route_budget(&cfg, 700), Some(40));
+        let cfg = config(&[
+            ("NOX_ROUTE_BUDGET_CHARS", "90"),
+            ("NOX_ROUTE_BUDGET_TOKENS", "10"),
+        ]);
+        assert_eq!(route_budget(&cfg, 700), Some(90));
+        assert_eq!(
+            route_budget(&config(&[("NOX_ROUTE_BUDGET_CHARS", "0")]), 700),
+            None
+        );
     }
-    chunks
 }
@@ -0,0 +1,293 @@
+//! Model discovery: scans the models directories for `*.gguf` files with a
+//! valid GGUF header and picks one by preference (`nox.gguf` first, then the
+//! newest file). `NOX_MODEL=<name>` narrows the choice by fuzzy name match.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::json::{self, Json};
+
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+const PREFERRED: &str = "nox.gguf";
+
+/// Directories scanned in order; `extra` (from `NOX_MODELS_DIR`) goes first.
+pub fn search_dirs(extra: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = extra {
+        dirs.push(dir.to_path_buf());
+    }
+    dirs.push(PathBuf::from("assets/models"));
+    dirs.push(PathBuf::from("../assets/models"));
+    dirs
+}
+
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    modified: SystemTime,
+}
+
+impl Candidate {
+    fn stem(&self) -> String {
+        self.path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default()
+    }
+
+    fn is_preferred(&self) -> bool {
+        self.path.file_name().is_some_and(|n| n == PREFERRED)
+    }
+}
+
+/// Why a path was passed over while looking for a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Missing,
+    NotAFile,
+    NotADirectory,
+    BadMagic,
+    NoNameMatch,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Missing => "missing",
+            Reason::NotAFile => "not a file",
+            Reason::NotADirectory => "not a readable directory",
+            Reason::BadMagic => "no GGUF header",
+            Reason::NoNameMatch => "does not match NOX_MODEL",
+        }
+    }
+}
+
+/// A rejected model path, as listed in "model not found" reports.
+#[derive(Debug, Clone)]
+pub struct Checked {
+    pub path: PathBuf,
+    pub reason: Reason,
+}
+
+/// `[{"path": .., "reason": ..}, ..]` for JSONL error records.
+pub fn checked_json(checked: &[Checked]) -> Json {
+    Json::Arr(
+        checked
+            .iter()
+            .map(|c| {
+                json::obj([
+                    ("path", Json::from(c.path.to_string_lossy().into_owned())),
+                    ("reason", Json::from(c.reason.as_str())),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// Accepts `path` only if it is a file with a GGUF header.
+pub fn check_file(path: &Path) -> Result<(), Reason> {
+    if !path.exists() {
+        Err(Reason::Missing)
+    } else if !path.is_file() {
+        Err(Reason::NotAFile)
+    } else if !has_gguf_magic(path) {
+        Err(Reason::BadMagic)
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Scan {
+    /// Valid models, best choice first.
+    pub found: Vec<Candidate>,
+    /// Directories and files that were skipped, with the reason.
+    pub rejected: Vec<Checked>,
+}
+
+/// Lists every valid `*.gguf` in `dirs`, best choice first. Rejected paths
+/// are collected and reported through `log`.
+pub fn scan(dirs: &[PathBuf], log: &mut dyn FnMut(String)) -> Scan {
+    let mut found = Vec::new();
+    let mut rejected = Vec::new();
+    let mut reject = |path: PathBuf, reason: Reason| {
+        log(format!("{}: skipped, {}", path.display(), reason.as_str()));
+        rejected.push(Checked { path, reason });
+    };
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) if !dir.exists() => {
+                reject(dir.clone(), Reason::Missing);
+                continue;
+            }
+            Err(_) => {
+                reject(dir.clone(), Reason::NotADirectory);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("gguf"))
+            {
+                continue;
+            }
+            if let Err(reason) = check_file(&path) {
+                reject(path, reason);
+                continue;
+            }
+            let modified = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push(Candidate { path, modified });
+        }
+    }
+    found.sort_by(|a, b| {
+        b.is_preferred()
+            .cmp(&a.is_preferred())
+            .then(b.modified.cmp(&a.modified))
+            .then(a.path.cmp(&b.path))
+    });
+    for candidate in &found {
+        log(format!("{}: candidate", candidate.path.display()));
+    }
+    Scan { found, rejected }
+}
+
+/// Picks the candidate matching `name`: an exact stem match wins, otherwise
+/// the best-ranked file whose stem contains every `-`/`_`/`.`-separated part
+/// of the name.
+pub fn fuzzy_match<'a>(candidates: &'a [Candidate], name: &str) -> Option<&'a Candidate> {
+    let wanted = name.trim().trim_end_matches(".gguf").to_ascii_lowercase();
+    if wanted.is_empty() {
+        return None;
+    }
+    if let Some(exact) = candidates.iter().find(|c| c.stem() == wanted) {
+        return Some(exact);
+    }
+    let parts: Vec<&str> = wanted
+        .split(['-', '_', '.', ' '])
+        .filter(|p| !p.is_empty())
+        .collect();
+    candidates.iter().find(|c| {
+        let stem = c.stem();
+        parts.iter().all(|p| stem.contains(p))
+    })
+}
+
+fn has_gguf_magic(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .is_ok()
+        && &magic == GGUF_MAGIC
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// A scratch models dir holding valid models next to decoys.
+    fn models_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nox-scan-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::create_dir_all(dir.join("folder.gguf")).unwrap();
+        fs::write(dir.join("empty.gguf"), b"").unwrap();
+        fs::write(dir.join("zeros.gguf"), [0u8; 64]).unwrap();
+        fs::write(dir.join("weights.bin"), b"GGUF").unwrap();
+        fs::write(dir.join("readme.txt"), b"GGUF").unwrap();
+        fs::write(dir.join("nested/deep.gguf"), b"GGUF").unwrap();
+        dir
+    }
+
+    fn model(path: &Path, age_secs: u64) {
+        fs::write(path, b"GGUF\x03\0\0\0").unwrap();
+        let when = SystemTime::now() - Duration::from_secs(age_secs);
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_modified(when))
+            .unwrap();
+    }
+
+    fn names(scan: &Scan) -> Vec<String> {
+        scan.found
+            .iter()
+            .map(|c| c.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn decoys_are_skipped_and_the_newest_model_wins() {
+        let dir = models_dir("newest");
+        model(&dir.join("old-q8.gguf"), 3600);
+        model(&dir.join("new-q4.GGUF"), 60);
+        let mut log = Vec::new();
+        let scan = scan(std::slice::from_ref(&dir), &mut |line| log.push(line));
+        assert_eq!(names(&scan), ["new-q4.GGUF", "old-q8.gguf"]);
+
+        let mut rejected: Vec<(String, Reason)> = scan
+            .rejected
+            .iter()
+            .map(|c| {
+                let name = c.path.file_name().unwrap().to_string_lossy();
+                (name.into_owned(), c.reason)
+            })
+            .collect();
+        rejected.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            rejected,
+            [
+                ("empty.gguf".to_string(), Reason::BadMagic),
+                ("folder.gguf".to_string(), Reason::NotAFile),
+                ("zeros.gguf".to_string(), Reason::BadMagic),
+            ]
+        );
+        assert_eq!(log.len(), 5, "{log:?}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nox_gguf_beats_a_newer_model_in_a_later_dir() {
+        let first = models_dir("first");
+        let second = models_dir("second");
+        model(&first.join("fresh.gguf"), 0);
+        model(&second.join(PREFERRED), 86_400);
+        let missing = first.join("absent");
+        let scan = scan(
+            &[missing.clone(), first.clone(), second.clone()],
+            &mut |_| {},
+        );
+        assert_eq!(names(&scan), [PREFERRED, "fresh.gguf"]);
+        assert_eq!(scan.rejected[0].path, missing);
+        assert_eq!(scan.rejected[0].reason, Reason::Missing);
+        fs::remove_dir_all(&first).unwrap();
+        fs::remove_dir_all(&second).unwrap();
+    }
+
+    #[test]
+    fn a_bare_name_fuzzy_matches_the_scanned_files() {
+        let dir = models_dir("fuzzy");
+        model(&dir.join("mistral-7b-instruct.Q4_K_M.gguf"), 60);
+        model(&dir.join("mistral-7b-instruct.Q8_0.gguf"), 0);
+        model(&dir.join("tiny.gguf"), 0);
+        let found = scan(std::slice::from_ref(&dir), &mut |_| {}).found;
+        let pick = |name| fuzzy_match(&found, name).map(|c| c.path.file_name().unwrap().to_owned());
+        assert_eq!(
+            pick("mistral-7b-q4").unwrap(),
+            "mistral-7b-instruct.Q4_K_M.gguf"
+        );
+        assert_eq!(pick("mistral-7b").unwrap(), "mistral-7b-instruct.Q8_0.gguf");
+        assert_eq!(pick("TINY.gguf").unwrap(), "tiny.gguf");
+        assert_eq!(pick("llama"), None);
+        assert_eq!(pick("empty"), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
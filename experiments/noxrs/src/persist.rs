@@ -0,0 +1,632 @@
+//! Persistent mode (`NOX_PERSIST=1`): one `noxlocal -serve` child answers
+//! every prompt on our stdin, so the model loads once. Input is forwarded
+//! as-is; each prompt (a line, or a 0x1e-terminated record with
+//! `NOX_PERSIST_RS=1`) is one transcript turn. A child that dies while we
+//! still have input is respawned with the same flags, resuming from its
+//! `-state-save` file when one is configured, up to `NOX_MAX_RESTARTS` times
+//! within [`RESTART_WINDOW`]. With `NOX_IDLE_TIMEOUT_SECS` the session
+//! ends after that long without input or output, letting the runner save.
+//! A `!temp 0.7` line (also `!top_p`, `!top_k`, `!max_tokens`) is not
+//! forwarded; it applies to the next prompt only, on runners that take
+//! `-serve-params`. SIGTERM or SIGHUP stops reading stdin, lets the runner
+//! finish the response in flight and save (up to [`SAVE_GRACE`]), syncs the
+//! transcript, and exits 0; a second signal kills the runner at once.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, ChildStdout, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::capabilities;
+use crate::error::NoxError;
+use crate::sampling::Overrides;
+use crate::serve;
+use crate::session_log::SessionLog;
+use crate::signals;
+use crate::stderr_filter::StderrCapture;
+use crate::transcript::Transcript;
+use crate::{serve_command, Config, RunStats, RunnerStyle};
+
+/// Time a persistent child gets to finish `-state-save` after its stdin closes.
+const SAVE_GRACE: Duration = Duration::from_secs(10);
+/// Restarts older than this no longer count against `NOX_MAX_RESTARTS`.
+pub const RESTART_WINDOW: Duration = Duration::from_secs(300);
+/// Runner stderr lines repeated after a crash.
+const CRASH_LINES: usize = 10;
+
+/// A serve child with its pipes taken.
+struct Spawned {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    stderr: StderrCapture,
+}
+
+fn spawn(cfg: &Config, runner: &Path, model: Option<&str>) -> Result<Spawned, NoxError> {
+    let mut cmd = serve_command(cfg, runner, model);
+    if capabilities::probe(runner).supports("-serve-params") {
+        cmd.arg("-serve-params");
+    }
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    signals::isolate(&mut cmd);
+    let mut child = cmd.spawn().map_err(|source| {
+        cfg.resource_limits.explain(NoxError::Spawn {
+            runner: runner.to_path_buf(),
+            source,
+        })
+    })?;
+    let stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("failed to open child stdin"))?;
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| io::Error::other("failed to open child stdout"))?;
+    let stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| io::Error::other("failed to open child stderr"))?;
+    Ok(Spawned {
+        child,
+        stdin,
+        stdout,
+        stderr: StderrCapture::forward(stderr),
+    })
+}
+
+pub fn run(cfg: &Config) -> Result<(), NoxError> {
+    if !matches!(cfg.runner_style, RunnerStyle::NoxLocal) {
+        return Err(NoxError::Usage(
+            "persistent mode requires NOX_RUNNER_STYLE=noxlocal".to_string(),
+        ));
+    }
+    let runner = cfg.serve_runner()?;
+    let model = cfg.serve_model()?;
+
+    signals::install();
+    let started = Instant::now();
+    let Spawned {
+        child,
+        stdin: child_stdin,
+        stdout: mut child_stdout,
+        stderr,
+    } = spawn(cfg, &runner, model.as_deref())?;
+    let mut child_stderr = Some(stderr);
+
+    // Shared so the interrupt watcher can close it: EOF on stdin is the
+    // runner's cue to run `-state-save` and exit cleanly. Empty while a
+    // crashed runner is being replaced.
+    let child_stdin = Arc::new(Mutex::new(Some(child_stdin)));
+    let child = Arc::new(Mutex::new(child));
+    let done = Arc::new(AtomicBool::new(false));
+    // Set once our stdin ends or an interrupt closes the runner's, so the
+    // runner exiting after that is a shutdown rather than a crash.
+    let closing = Arc::new(AtomicBool::new(false));
+    let activity = Arc::new(Activity::new());
+    let idle = Arc::new(AtomicBool::new(false));
+    let idle_limit = cfg.idle_timeout_secs.map(Duration::from_secs);
+
+    // Prompts are reported to the stdout loop (before the runner sees them)
+    // so the transcript can frame each turn.
+    let (prompt_tx, prompt_rx) = mpsc::channel::<(String, Instant)>();
+    let prompt_end = if cfg.persist_rs {
+        serve::RECORD_SEP
+    } else {
+        b'\n'
+    };
+    let params = capabilities::probe(&runner).supports("-serve-params");
+
+    let watcher = {
+        let child_stdin = Arc::clone(&child_stdin);
+        let child = Arc::clone(&child);
+        let done = Arc::clone(&done);
+        let closing = Arc::clone(&closing);
+        let activity = Arc::clone(&activity);
+        let idle = Arc::clone(&idle);
+        thread::spawn(move || {
+            let mut closed_at: Option<Instant> = None;
+            while !done.load(Ordering::SeqCst) {
+                let seen = signals::interrupts();
+                if seen > 0 && closed_at.is_none() {
+                    if signals::terminated() {
+                        eprintln!("nox: terminating, letting the runner finish and save (signal again to force)");
+                    } else {
+                        eprintln!(
+                            "nox: interrupt, letting the runner save (Ctrl-C again to force)"
+                        );
+                    }
+                    closing.store(true, Ordering::SeqCst);
+                    child_stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+                    closed_at = Some(Instant::now());
+                }
+                let idle_now = idle_limit.is_some_and(|limit| activity.idle_for(limit));
+                if idle_now && closed_at.is_none() && !closing.load(Ordering::SeqCst) {
+                    // Same path as an interrupt: EOF lets the runner save.
+                    idle.store(true, Ordering::SeqCst);
+                    closing.store(true, Ordering::SeqCst);
+                    child_stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+                    closed_at = Some(Instant::now());
+                }
+                if let Some(at) = closed_at {
+                    if seen > 1 || at.elapsed() >= SAVE_GRACE {
+                        let mut child = child.lock().unwrap_or_else(|e| e.into_inner());
+                        // Once reaped, its group id may be someone else's.
+                        if let Ok(None) = child.try_wait() {
+                            signals::terminate(&mut child, true);
+                        }
+                        break;
+                    }
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+        })
+    };
+
+    // The warmup finishes before stdin is forwarded, so its reply can never
+    // be mistaken for the first turn's. Without warmup a no-op control
+    // request still tells us when the model is loaded.
+    let command = match &cfg.persist_warmup {
+        Some(text) => format!("warmup {text}"),
+        None => "ping".to_string(),
+    };
+    if warm_up(cfg, &command, &child_stdin, &mut child_stdout) {
+        eprintln!(
+            "nox: ready model={} ctx={} load_ms={}",
+            model.as_deref().unwrap_or("-"),
+            cfg.ctx,
+            started.elapsed().as_millis()
+        );
+    } else if signals::interrupts() == 0 && !closing.load(Ordering::SeqCst) {
+        done.store(true, Ordering::SeqCst);
+        let _ = watcher.join();
+        let status = child.lock().unwrap_or_else(|e| e.into_inner()).wait()?;
+        eprintln!("nox: the runner died while loading the model");
+        show_tail(
+            &child_stderr
+                .take()
+                .map(StderrCapture::join)
+                .unwrap_or_default(),
+        );
+        return Err(cfg.resource_limits.explain(NoxError::RunnerFailed(status)));
+    }
+
+    let stdin_thread = {
+        let child_stdin = Arc::clone(&child_stdin);
+        let done = Arc::clone(&done);
+        let closing = Arc::clone(&closing);
+        let activity = Arc::clone(&activity);
+        thread::spawn(move || {
+            let stdin = io::stdin();
+            let mut lock = stdin.lock();
+            let mut buf = [0u8; 4096];
+            let mut partial = Vec::new();
+            let mut overrides = Overrides::default();
+            let mut eof = false;
+            'read: while !eof {
+                let n = match lock.read(&mut buf) {
+                    Ok(0) | Err(_) => 0,
+                    Ok(n) => n,
+                };
+                eof = n == 0;
+                if eof && partial.is_empty() {
+                    break;
+                }
+                activity.touch(&activity.input);
+                partial.extend_from_slice(&buf[..n]);
+                if eof {
+                    // A last prompt without its terminator still counts.
+                    partial.push(prompt_end);
+                }
+                // Whole records only, so override lines can be held back.
+                let mut forward = Vec::new();
+                let mut prompts = Vec::new();
+                while let Some(end) = partial.iter().position(|b| *b == prompt_end) {
+                    let record: Vec<u8> = partial.drain(..=end).collect();
+                    let prompt = String::from_utf8_lossy(&record[..end]).trim().to_string();
+                    if let Some(setting) = prompt.strip_prefix('!').and_then(parse_setting) {
+                        if let Err(err) = overrides.set(setting.0, setting.1) {
+                            eprintln!("nox: {err}");
+                        }
+                        continue;
+                    }
+                    if !prompt.is_empty() && !overrides.is_empty() {
+                        if params {
+                            forward.push(serve::PARAMS);
+                            forward.extend_from_slice(overrides.header().as_bytes());
+                            forward.push(serve::PARAMS);
+                        } else {
+                            eprintln!(
+                                "nox: the runner does not accept -serve-params; ignoring the settings for this prompt"
+                            );
+                        }
+                        overrides = Overrides::default();
+                    }
+                    forward.extend_from_slice(&record);
+                    if !prompt.is_empty() {
+                        prompts.push(prompt);
+                    }
+                }
+                // The slot is empty while a crashed runner is replaced; the
+                // input waits for the new one. Prompts are reported under the
+                // lock, so a restart knows which ones the old runner took.
+                while !closing.load(Ordering::SeqCst) && !done.load(Ordering::SeqCst) {
+                    let mut slot = child_stdin.lock().unwrap_or_else(|e| e.into_inner());
+                    let Some(pipe) = slot.as_mut() else {
+                        drop(slot);
+                        thread::sleep(Duration::from_millis(50));
+                        continue;
+                    };
+                    for prompt in prompts.drain(..) {
+                        activity.touch(&activity.prompt);
+                        let _ = prompt_tx.send((prompt, Instant::now()));
+                    }
+                    if pipe.write_all(&forward).and_then(|_| pipe.flush()).is_err() {
+                        // The runner is gone; the restart reports what it lost.
+                        *slot = None;
+                    }
+                    continue 'read;
+                }
+                break;
+            }
+            closing.store(true, Ordering::SeqCst);
+            child_stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+        })
+    };
+
+    let mut log = Transcript::open(cfg.log_file.as_deref()).with_session(SessionLog::open(cfg));
+    let mut turn: Option<TurnClock> = None;
+    let mut queued = VecDeque::new();
+    let mut stdout = io::stdout();
+    let mut restarts: VecDeque<Instant> = VecDeque::new();
+    // The last bytes of output, to spot the text end marker.
+    let mut recent: Vec<u8> = Vec::new();
+    let outcome: Result<ExitStatus, NoxError> = loop {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match child_stdout.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            activity.touch(&activity.output);
+            queued.extend(prompt_rx.try_iter());
+            let chunk = &buf[..n];
+            if stdout
+                .write_all(chunk)
+                .and_then(|_| stdout.flush())
+                .is_err()
+            {
+                // Nobody is reading any more: shut down, don't restart.
+                closing.store(true, Ordering::SeqCst);
+                child_stdin.lock().unwrap_or_else(|e| e.into_inner()).take();
+                break;
+            }
+            // Without -serve-rs the text end marker can arrive split across
+            // reads, so output for a newer prompt also ends the last turn.
+            if !cfg.persist_rs && turn.is_some() && !queued.is_empty() {
+                if let Some(clock) = turn.take() {
+                    log.end(Some(&clock.stats()), None);
+                }
+            }
+            let mut rest = chunk;
+            while !rest.is_empty() {
+                if turn.is_none() {
+                    if let Some((prompt, sent)) = queued.pop_front() {
+                        log.begin(&prompt);
+                        turn = Some(TurnClock::new(sent));
+                    }
+                }
+                if let Some(clock) = turn.as_mut() {
+                    clock.saw_output();
+                }
+                let end = match rest.iter().position(|b| *b == serve::RECORD_SEP) {
+                    Some(end) if cfg.persist_rs => end,
+                    _ => {
+                        log.output(rest);
+                        break;
+                    }
+                };
+                log.output(&rest[..end]);
+                if let Some(clock) = turn.take() {
+                    log.end(Some(&clock.stats()), None);
+                }
+                rest = &rest[end + 1..];
+            }
+            // A finished turn must not be reported lost if the runner dies
+            // before the next prompt.
+            if !cfg.persist_rs {
+                recent.extend_from_slice(chunk);
+                recent.drain(..recent.len().saturating_sub(serve::LINE_END.len()));
+                if recent == serve::LINE_END {
+                    if let Some(clock) = turn.take() {
+                        log.end(Some(&clock.stats()), None);
+                    }
+                }
+            }
+        }
+        if signals::interrupts() > 0 {
+            break Err(NoxError::Interrupted);
+        }
+        let status = match child.lock().unwrap_or_else(|e| e.into_inner()).wait() {
+            Ok(status) => status,
+            Err(err) => break Err(err.into()),
+        };
+        if closing.load(Ordering::SeqCst) || status.success() {
+            break Ok(status);
+        }
+
+        let err = cfg.resource_limits.explain(NoxError::RunnerFailed(status));
+        let tail = child_stderr
+            .take()
+            .map(StderrCapture::join)
+            .unwrap_or_default();
+        eprintln!("nox: runner died: {err}");
+        show_tail(&tail);
+        log.event("runner_died", &err.to_string());
+        restarts.retain(|at| at.elapsed() < RESTART_WINDOW);
+        let next = if restarts.len() < cfg.max_restarts as usize {
+            // Resume from the last save so the conversation survives.
+            let mut next_cfg = cfg.clone();
+            if let Some(save) = cfg.state_save.as_ref().filter(|p| p.is_file()) {
+                next_cfg.state_load = Some(save.clone());
+            }
+            Some(spawn(&next_cfg, &runner, model.as_deref()))
+        } else {
+            None
+        };
+
+        let mut slot = child_stdin.lock().unwrap_or_else(|e| e.into_inner());
+        queued.extend(prompt_rx.try_iter());
+        if let Err(write_err) =
+            report_lost(cfg, &mut stdout, &mut log, &mut turn, &mut queued, &err)
+        {
+            break Err(write_err.into());
+        }
+        let spawned = match next {
+            None => {
+                eprintln!(
+                    "nox: not restarting: {} restarts in the last {} minutes (NOX_MAX_RESTARTS={})",
+                    restarts.len(),
+                    RESTART_WINDOW.as_secs() / 60,
+                    cfg.max_restarts
+                );
+                break Err(err);
+            }
+            Some(Err(spawn_err)) => break Err(spawn_err),
+            Some(Ok(spawned)) => spawned,
+        };
+        // If our stdin ended meanwhile, leaving the slot empty hands the new
+        // runner EOF right away and it shuts down cleanly.
+        if !closing.load(Ordering::SeqCst) {
+            *slot = Some(spawned.stdin);
+        }
+        drop(slot);
+        *child.lock().unwrap_or_else(|e| e.into_inner()) = spawned.child;
+        child_stdout = spawned.stdout;
+        child_stderr = Some(spawned.stderr);
+        restarts.push_back(Instant::now());
+        let detail = format!(
+            "restarted the runner ({}/{} in the last {} minutes)",
+            restarts.len(),
+            cfg.max_restarts,
+            RESTART_WINDOW.as_secs() / 60
+        );
+        eprintln!("nox: {detail}");
+        log.event("restart", &detail);
+    };
+    if let Some(clock) = turn.take() {
+        log.end(Some(&clock.stats()), None);
+    }
+    done.store(true, Ordering::SeqCst);
+    let _ = watcher.join();
+
+    let mut child = child.lock().unwrap_or_else(|e| e.into_inner());
+    if signals::interrupts() > 0 {
+        signals::reap(&mut child, signals::GRACE, 1);
+        log.sync();
+        if signals::terminated() && signals::interrupts() == 1 {
+            return Ok(());
+        }
+        return Err(NoxError::Interrupted);
+    }
+    let status = outcome?;
+    let idled = idle.load(Ordering::SeqCst);
+    if idled {
+        eprintln!(
+            "nox: idle timeout (no activity for {}s, NOX_IDLE_TIMEOUT_SECS)",
+            cfg.idle_timeout_secs.unwrap_or_default()
+        );
+    } else if closing.load(Ordering::SeqCst) {
+        // An idle shutdown leaves it blocked on our stdin; only join when
+        // it stopped on its own.
+        let _ = stdin_thread.join();
+    }
+    if let Some(capture) = child_stderr {
+        capture.join();
+    }
+    if !status.success() {
+        return Err(cfg.resource_limits.explain(NoxError::RunnerFailed(status)));
+    }
+    Ok(())
+}
+
+/// `temp 0.7` from a `!temp 0.7` line; `None` if the line is not a known
+/// setting followed by a number, so it stays an ordinary prompt.
+fn parse_setting(line: &str) -> Option<(&str, f64)> {
+    let mut words = line.split_whitespace();
+    let (name, value) = (words.next()?, words.next()?);
+    let known = Overrides::NAMES.contains(&name.replace('-', "_").as_str());
+    if !known || words.next().is_some() {
+        return None;
+    }
+    Some((name, value.parse().ok()?))
+}
+
+/// Last-activity times for the idle timer, in milliseconds since `epoch`.
+struct Activity {
+    epoch: Instant,
+    input: AtomicU64,
+    prompt: AtomicU64,
+    output: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            input: AtomicU64::new(0),
+            prompt: AtomicU64::new(0),
+            output: AtomicU64::new(0),
+        }
+    }
+
+    fn touch(&self, slot: &AtomicU64) {
+        slot.store(self.epoch.elapsed().as_millis() as u64, Ordering::SeqCst);
+    }
+
+    /// True after `limit` without input or output, unless a prompt is still
+    /// waiting for its first byte of response.
+    fn idle_for(&self, limit: Duration) -> bool {
+        let output = self.output.load(Ordering::SeqCst);
+        if self.prompt.load(Ordering::SeqCst) > output {
+            return false;
+        }
+        let last = self.input.load(Ordering::SeqCst).max(output);
+        let now = self.epoch.elapsed().as_millis() as u64;
+        now.saturating_sub(last) >= limit.as_millis() as u64
+    }
+}
+
+/// Answers the prompt the crashed runner was working on, and any it had
+/// received but not started, with an error line ending like a response.
+fn report_lost(
+    cfg: &Config,
+    stdout: &mut dyn Write,
+    log: &mut Transcript,
+    turn: &mut Option<TurnClock>,
+    queued: &mut VecDeque<(String, Instant)>,
+    err: &NoxError,
+) -> io::Result<()> {
+    let end: &[u8] = if cfg.persist_rs {
+        &[serve::RECORD_SEP]
+    } else {
+        serve::LINE_END
+    };
+    let lost = |stdout: &mut dyn Write, mid_response: bool| -> io::Result<()> {
+        if mid_response {
+            stdout.write_all(b"\n")?;
+        }
+        write!(
+            stdout,
+            "nox: error: the runner crashed before answering ({err})"
+        )?;
+        stdout.write_all(end)?;
+        stdout.flush()
+    };
+    if let Some(clock) = turn.take() {
+        lost(stdout, clock.first.is_some())?;
+        log.end(None, Some(err));
+    }
+    for (prompt, _) in queued.drain(..) {
+        lost(stdout, false)?;
+        log.begin(&prompt);
+        log.end(None, Some(err));
+    }
+    Ok(())
+}
+
+/// Prints the last [`CRASH_LINES`] of a dead runner's stderr.
+fn show_tail(tail: &[String]) {
+    if tail.is_empty() {
+        return;
+    }
+    let shown = &tail[tail.len().saturating_sub(CRASH_LINES)..];
+    eprintln!("nox: last {} runner stderr lines:", shown.len());
+    for line in shown {
+        eprintln!("  {line}");
+    }
+}
+
+/// Sends one control request (`warmup <text>` or `ping`) and discards the
+/// reply, so the first real prompt does not pay for graph warmup and waits
+/// for the model to load. Returns false if the runner closed its stdout (or
+/// stdin was closed by an interrupt) first.
+fn warm_up(
+    cfg: &Config,
+    command: &str,
+    stdin: &Mutex<Option<ChildStdin>>,
+    stdout: &mut ChildStdout,
+) -> bool {
+    let (end, marker) = if cfg.persist_rs {
+        (serve::RECORD_SEP, &[serve::RECORD_SEP][..])
+    } else {
+        (b'\n', serve::LINE_END)
+    };
+    let mut request = vec![serve::CONTROL];
+    request.extend(command.bytes().map(|b| if b == end { b' ' } else { b }));
+    request.push(end);
+    {
+        let mut slot = stdin.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(pipe) = slot.as_mut() else {
+            return false;
+        };
+        if pipe.write_all(&request).and_then(|_| pipe.flush()).is_err() {
+            return false;
+        }
+    }
+    let mut seen = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        match stdout.read(&mut buf) {
+            Ok(0) => return false,
+            Ok(n) => seen.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+        if seen.windows(marker.len()).any(|w| w == marker) {
+            return true;
+        }
+    }
+}
+
+/// Timing for one persistent-mode turn, measured from when the prompt was
+/// forwarded to the runner.
+struct TurnClock {
+    sent: Instant,
+    first: Option<Instant>,
+    last: Instant,
+}
+
+impl TurnClock {
+    fn new(sent: Instant) -> Self {
+        Self {
+            sent,
+            first: None,
+            last: sent,
+        }
+    }
+
+    fn saw_output(&mut self) {
+        let now = Instant::now();
+        self.first.get_or_insert(now);
+        self.last = now;
+    }
+
+    fn stats(&self) -> RunStats {
+        RunStats {
+            ttft: self.first.map(|at| at.duration_since(self.sent)),
+            duration: self.last.duration_since(self.sent),
+            tokens: None,
+            prefill: None,
+        }
+    }
+}
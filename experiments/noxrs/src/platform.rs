@@ -0,0 +1,116 @@
+//! Platform differences in finding runner binaries. On Windows a runner is
+//! `noxlocal.exe` (or `.bat`/`.cmd`) rather than a mode-bit executable, so
+//! every candidate is tried with each suffix from `%PATHEXT%`. The helpers
+//! take the platform as a parameter so the Windows rules are plain functions.
+
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+pub const WINDOWS: bool = cfg!(windows);
+
+const DEFAULT_SUFFIXES: &[&str] = &[".exe", ".bat", ".cmd"];
+
+/// Builds a relative path from components so the native separator is used.
+pub fn rel(parts: &[&str]) -> PathBuf {
+    parts.iter().collect()
+}
+
+/// Executable suffixes from a `PATHEXT`-style list, lowercased, falling back
+/// to `.exe`/`.bat`/`.cmd`.
+pub fn suffixes(pathext: Option<&str>) -> Vec<String> {
+    let parsed: Vec<String> = pathext
+        .unwrap_or_default()
+        .split(';')
+        .map(|s| s.trim().to_ascii_lowercase())
+        .filter(|s| s.starts_with('.') && s.len() > 1)
+        .collect();
+    if parsed.is_empty() {
+        DEFAULT_SUFFIXES.iter().map(|s| s.to_string()).collect()
+    } else {
+        parsed
+    }
+}
+
+/// Whether `path` already ends in one of `suffixes`.
+pub fn has_suffix(path: &Path, suffixes: &[String]) -> bool {
+    path.extension().is_some_and(|ext| {
+        let ext = format!(".{}", ext.to_string_lossy().to_ascii_lowercase());
+        suffixes.contains(&ext)
+    })
+}
+
+/// The file names to try for `path`: itself on Unix; on Windows itself if
+/// it already carries a suffix, otherwise one variant per suffix.
+pub fn variants(path: &Path, windows: bool, suffixes: &[String]) -> Vec<PathBuf> {
+    if !windows || has_suffix(path, suffixes) {
+        return vec![path.to_path_buf()];
+    }
+    suffixes
+        .iter()
+        .map(|suffix| {
+            let mut name = OsString::from(path.as_os_str());
+            name.push(suffix);
+            PathBuf::from(name)
+        })
+        .collect()
+}
+
+/// Suffixes for the current process (`%PATHEXT%` on Windows).
+pub fn current_suffixes() -> Vec<String> {
+    suffixes(env::var("PATHEXT").ok().as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(paths: Vec<PathBuf>) -> Vec<String> {
+        paths
+            .into_iter()
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn pathext_is_parsed_lowercased_with_a_default() {
+        assert_eq!(
+            suffixes(Some(".COM;.EXE; .Bat ;;exe;.")),
+            [".com", ".exe", ".bat"]
+        );
+        for empty in [None, Some(""), Some(";;")] {
+            assert_eq!(suffixes(empty), [".exe", ".bat", ".cmd"]);
+        }
+    }
+
+    #[test]
+    fn windows_tries_every_suffix_on_a_bare_name() {
+        let exts = suffixes(None);
+        let path = rel(&["bin", "noxlocal"]);
+        let expected: Vec<String> = [".exe", ".bat", ".cmd"]
+            .iter()
+            .map(|ext| format!("{}{ext}", path.display()))
+            .collect();
+        assert_eq!(strings(variants(&path, true, &exts)), expected);
+    }
+
+    #[test]
+    fn a_name_with_a_suffix_is_tried_as_is() {
+        let exts = suffixes(None);
+        for name in ["noxlocal.exe", "noxlocal.CMD", "run.Bat"] {
+            let path = rel(&["bin", name]);
+            assert!(has_suffix(&path, &exts), "{name}");
+            assert_eq!(variants(&path, true, &exts), [path]);
+        }
+        let dotted = Path::new("llama.cpp");
+        assert!(!has_suffix(dotted, &exts));
+        assert_eq!(strings(variants(dotted, true, &exts))[0], "llama.cpp.exe");
+    }
+
+    #[test]
+    fn unix_uses_the_path_unchanged() {
+        let exts = suffixes(None);
+        let path = Path::new("bin/noxlocal");
+        assert_eq!(variants(path, false, &exts), [path.to_path_buf()]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Live throughput line on stderr (`⏳ 87 tok  41.2 tok/s  2.1s`) while a
+//! one-shot generation streams to a terminal. The output path only bumps
+//! atomics in [`Meter`]; a separate thread redraws the line every 500ms and
+//! clears it when generation ends. Tokens are counted like the simulator
+//! chunks text: one per whitespace-separated word.
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const REDRAW: Duration = Duration::from_millis(500);
+const CLEAR_LINE: &str = "\r\x1b[2K";
+
+/// Whether to show the indicator: both streams are terminals, output is not
+/// JSON, and `NOX_QUIET` is off. `force` (`NOX_PROGRESS=1`) skips the
+/// terminal check.
+pub fn enabled(force: bool, json: bool, quiet: bool) -> bool {
+    if json || quiet {
+        return false;
+    }
+    force || (io::stdout().is_terminal() && io::stderr().is_terminal())
+}
+
+/// Counters shared between the output path and the indicator thread.
+#[derive(Debug)]
+pub struct Meter {
+    started: Instant,
+    tokens: AtomicU64,
+    /// Nanoseconds from `started` to the first byte, plus one; 0 = none yet.
+    first_ns: AtomicU64,
+}
+
+impl Meter {
+    pub fn new(started: Instant) -> Self {
+        Self {
+            started,
+            tokens: AtomicU64::new(0),
+            first_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// The current indicator line.
+    pub fn line(&self) -> String {
+        let first = match self.first_ns.load(Ordering::Relaxed) {
+            0 => None,
+            ns => Some(Duration::from_nanos(ns - 1)),
+        };
+        render(
+            self.tokens.load(Ordering::Relaxed),
+            self.started.elapsed(),
+            first,
+        )
+    }
+}
+
+/// `tokens` generated `elapsed` after start; the rate only counts time
+/// since the first byte so model load does not drag it down.
+pub fn render(tokens: u64, elapsed: Duration, first: Option<Duration>) -> String {
+    format!(
+        "⏳ {tokens} tok  {:.1} tok/s  {:.1}s",
+        rate(tokens, elapsed.saturating_sub(first.unwrap_or_default())),
+        elapsed.as_secs_f64()
+    )
+}
+
+pub fn rate(tokens: u64, over: Duration) -> f64 {
+    let secs = over.as_secs_f64();
+    if secs <= 0.0 {
+        0.0
+    } else {
+        tokens as f64 / secs
+    }
+}
+
+/// Counts words in `bytes` that start in this chunk; `in_word` carries the
+/// state across chunk boundaries.
+pub fn count_words(bytes: &[u8], in_word: &mut bool) -> u64 {
+    let mut words = 0;
+    for b in bytes {
+        let word_byte = !b.is_ascii_whitespace();
+        if word_byte && !*in_word {
+            words += 1;
+        }
+        *in_word = word_byte;
+    }
+    words
+}
+
+/// Forwards writes to `out` while feeding the meter.
+pub struct Metered<'a> {
+    pub out: &'a mut dyn Write,
+    pub meter: &'a Meter,
+    in_word: bool,
+}
+
+impl<'a> Metered<'a> {
+    pub fn new(out: &'a mut dyn Write, meter: &'a Meter) -> Self {
+        Self {
+            out,
+            meter,
+            in_word: false,
+        }
+    }
+}
+
+impl Write for Metered<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        if n > 0 {
+            let ns = self.meter.started.elapsed().as_nanos() as u64 + 1;
+            let _ =
+                self.meter
+                    .first_ns
+                    .compare_exchange(0, ns, Ordering::Relaxed, Ordering::Relaxed);
+            let words = count_words(&buf[..n], &mut self.in_word);
+            self.meter.tokens.fetch_add(words, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// The redraw thread; [`Indicator::finish`] stops it and clears the line.
+pub struct Indicator {
+    stop: Arc<AtomicBool>,
+    /// Reports whether anything was drawn.
+    thread: JoinHandle<bool>,
+}
+
+impl Indicator {
+    pub fn start(meter: Arc<Meter>) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut next = Instant::now() + REDRAW;
+            let mut drawn = false;
+            while !flag.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(50));
+                if Instant::now() < next {
+                    continue;
+                }
+                next += REDRAW;
+                let mut err = io::stderr().lock();
+                let _ = write!(err, "{CLEAR_LINE}{}", meter.line());
+                let _ = err.flush();
+                drawn = true;
+            }
+            drawn
+        });
+        Self { stop, thread }
+    }
+
+    pub fn finish(self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if !self.thread.join().unwrap_or(true) {
+            return;
+        }
+        let mut err = io::stderr().lock();
+        let _ = write!(err, "{CLEAR_LINE}");
+        let _ = err.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_counted_once_across_chunk_boundaries() {
+        let mut in_word = false;
+        let counts: Vec<u64> = ["Hel", "lo wor", "ld", "\n", " again  ", "and"]
+            .iter()
+            .map(|chunk| count_words(chunk.as_bytes(), &mut in_word))
+            .collect();
+        assert_eq!(counts, [1, 1, 0, 0, 1, 1]);
+        assert_eq!(count_words(b"", &mut in_word), 0);
+    }
+
+    #[test]
+    fn the_rate_excludes_time_to_first_token() {
+        let line = render(
+            87,
+            Duration::from_millis(3100),
+            Some(Duration::from_secs(1)),
+        );
+        assert_eq!(line, "⏳ 87 tok  41.4 tok/s  3.1s");
+        assert_eq!(
+            render(10, Duration::from_secs(2), None),
+            "⏳ 10 tok  5.0 tok/s  2.0s"
+        );
+        assert_eq!(rate(5, Duration::ZERO), 0.0);
+        assert_eq!(
+            render(0, Duration::from_millis(400), None),
+            "⏳ 0 tok  0.0 tok/s  0.4s"
+        );
+    }
+
+    #[test]
+    fn metered_writes_pass_through_and_feed_the_meter() {
+        let meter = Meter::new(Instant::now());
+        assert!(meter.line().starts_with("⏳ 0 tok  0.0 tok/s  "));
+        let mut out = Vec::new();
+        {
+            let mut metered = Metered::new(&mut out, &meter);
+            for chunk in ["one tw", "o three", " four\n"] {
+                metered.write_all(chunk.as_bytes()).unwrap();
+            }
+        }
+        assert_eq!(out, b"one two three four\n");
+        assert_eq!(meter.tokens.load(Ordering::Relaxed), 4);
+        assert_ne!(meter.first_ns.load(Ordering::Relaxed), 0);
+        assert!(meter.line().starts_with("⏳ 4 tok  "), "{}", meter.line());
+    }
+
+    #[test]
+    fn json_and_quiet_win_over_force() {
+        assert!(enabled(true, false, false));
+        assert!(!enabled(true, true, false));
+        assert!(!enabled(true, false, true));
+        assert!(!enabled(false, true, true));
+    }
+}
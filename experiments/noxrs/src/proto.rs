@@ -0,0 +1,645 @@
+//! `NOX_PERSIST_PROTO=jsonl`: persistent mode with framed, addressable
+//! requests. Each stdin line is a JSON request:
+//!
+//! - `{"id":1,"type":"prompt","text":"..."}` queues a prompt, optionally
+//!   with `temp`, `top_p`, `top_k`, and `max_tokens` for it alone. Once
+//!   `NOX_QUEUE_MAX` prompts are waiting, more are refused at once with an
+//!   `error` whose `reason` is `queue_full`;
+//! - `{"id":1,"type":"cancel"}` cancels prompt 1 (no id: the one running);
+//! - `{"type":"ping"}` is answered `{"type":"pong","uptime_ms":..,"requests":N}`
+//!   at once, even while a prompt runs;
+//! - `{"type":"shutdown"}` lets the runner save and exit, like EOF.
+//!
+//! Once the runner has loaded its model (the warmup request, or a no-op
+//! `ping` control request without warmup, has come back), stdout gets
+//! `{"type":"ready","model":..,"ctx":..,"load_ms":..}`, once. A runner that
+//! dies first gets an `error` with `reason` `load_failed` and its last
+//! `stderr` lines instead, and nox exits with the runner's failure.
+//!
+//! Every stdout line is a response tagged with the prompt's id: `delta`
+//! chunks of text, then `done` with timings or `error` with a message
+//! (`cancelled` for cancelled prompts). Lines without an id,
+//! `{"type":"queue","depth":N}`, report the number of prompts waiting
+//! whenever it changes. Prompts run one at a time on a
+//! `noxlocal -serve -serve-rs` child. Cancelling the running prompt sends
+//! the runner SIGUSR1 when it advertises `-cancel-signal`; otherwise the
+//! child is killed and the next prompt starts a fresh one, losing its
+//! cache. Per-prompt settings go to runners with `-serve-params`; for others
+//! `max_tokens` is enforced by cutting the response and the rest are
+//! ignored with a warning.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, Write};
+use std::process::Stdio;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::capabilities;
+use crate::error::NoxError;
+use crate::json::{self, Json};
+use crate::sampling::Overrides;
+use crate::serve::{self, Exchange, ServeChild};
+use crate::session_log::SessionLog;
+use crate::signals;
+use crate::transcript::Transcript;
+use crate::{serve_command, Config, RunStats, RunnerStyle};
+
+/// Time the child gets to finish `-state-save` after its stdin closes.
+const SAVE_GRACE: Duration = Duration::from_secs(10);
+const POLL: Duration = Duration::from_millis(100);
+
+/// One prompt and its per-request settings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub id: Json,
+    pub text: String,
+    pub overrides: Overrides,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Prompt(Job),
+    /// `None` targets whichever prompt is running.
+    Cancel(Option<Json>),
+    /// Carries the id to echo, if the request had one.
+    Ping(Option<Json>),
+    Shutdown,
+}
+
+/// A line that could not be taken, with the id to answer (null if the
+/// line had none).
+pub type Rejected = (Json, String);
+
+/// Parses one request line. Blank lines yield `None`.
+pub fn parse_request(line: &str) -> Result<Option<Request>, Rejected> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let value = Json::parse(line).map_err(|err| (Json::Null, err))?;
+    let id = value.get("id").filter(|id| !id.is_null()).cloned();
+    let reject = |message: &str| (id.clone().unwrap_or(Json::Null), message.to_string());
+    match value.get("type").and_then(Json::as_str) {
+        Some("prompt") => {
+            let id = id
+                .clone()
+                .ok_or_else(|| reject("prompt requests need an \"id\""))?;
+            let text = value
+                .get("text")
+                .and_then(Json::as_str)
+                .ok_or_else(|| reject("missing string field \"text\""))?;
+            let mut overrides = Overrides::default();
+            for name in Overrides::NAMES {
+                let Some(field) = value.get(name).filter(|v| !v.is_null()) else {
+                    continue;
+                };
+                let number = field
+                    .as_f64()
+                    .ok_or_else(|| reject(&format!("{name} must be a number")))?;
+                overrides.set(name, number).map_err(|err| reject(&err))?;
+            }
+            Ok(Some(Request::Prompt(Job {
+                id,
+                text: text.to_string(),
+                overrides,
+            })))
+        }
+        Some("cancel") => Ok(Some(Request::Cancel(id))),
+        Some("ping") => Ok(Some(Request::Ping(id))),
+        Some("shutdown") => Ok(Some(Request::Shutdown)),
+        Some(other) => Err(reject(&format!("unknown request type `{other}`"))),
+        None => Err(reject("missing string field \"type\"")),
+    }
+}
+
+pub fn run(cfg: &Config) -> Result<(), NoxError> {
+    let cfg = &prepare(cfg)?;
+    signals::install();
+    let started = Instant::now();
+    let mut serve = spawn(cfg)?;
+    if let Err(err) = warm_up(cfg, &mut serve) {
+        if !matches!(err, NoxError::Interrupted) {
+            let _ = respond(&mut io::stdout(), load_failed(&err, serve.stderr_tail()));
+        }
+        serve.kill();
+        return Err(err);
+    }
+    let ready = json::obj([
+        ("type", Json::from("ready")),
+        ("model", cfg.serve_model()?.map_or(Json::Null, Json::from)),
+        ("ctx", Json::from(u64::from(cfg.ctx))),
+        ("load_ms", Json::from(started.elapsed().as_millis() as u64)),
+    ]);
+    respond(&mut io::stdout(), ready)?;
+    let mut child = Some(serve);
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(parse_request(&line)).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session {
+        rx,
+        backlog: Backlog::new(cfg.queue_max),
+        log: Transcript::open(cfg.log_file.as_deref()).with_session(SessionLog::open(cfg)),
+        closed: false,
+    };
+    let idle_limit = cfg.idle_timeout_secs.map(Duration::from_secs);
+    let mut last_activity = Instant::now();
+    let mut answered = 0u64;
+    let outcome = loop {
+        if signals::interrupts() > 0 {
+            break Err(NoxError::Interrupted);
+        }
+        let Some(request) = session.next() else {
+            if session.closed {
+                break Ok(());
+            }
+            if idle_limit.is_some_and(|limit| last_activity.elapsed() >= limit) {
+                eprintln!(
+                    "nox: idle timeout (no activity for {}s, NOX_IDLE_TIMEOUT_SECS)",
+                    cfg.idle_timeout_secs.unwrap_or_default()
+                );
+                break Ok(());
+            }
+            continue;
+        };
+        let job = match request {
+            Request::Prompt(job) => job,
+            // Nothing is running, so there is nothing to cancel.
+            Request::Cancel(_) => continue,
+            Request::Ping(id) => {
+                let _ = respond(&mut io::stdout(), pong(id, started, answered));
+                continue;
+            }
+            Request::Shutdown => break Ok(()),
+        };
+        let (rx, backlog) = (&session.rx, &mut session.backlog);
+        let result = answer(
+            cfg,
+            &mut child,
+            &mut session.log,
+            &mut io::stdout(),
+            &job,
+            &mut || poll_cancel(rx, backlog, &job.id, started, answered),
+        );
+        match result {
+            Ok(()) => {
+                answered += 1;
+                last_activity = Instant::now();
+            }
+            // Nobody is reading any more.
+            Err(NoxError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => break Ok(()),
+            Err(err) => break Err(err),
+        }
+    };
+    // Prompts queued behind a shutdown still get an answer.
+    for request in session.backlog.requests.drain(..) {
+        if let Request::Prompt(job) = request {
+            let _ = respond(
+                &mut io::stdout(),
+                error(&job.id, "shut down before running"),
+            );
+        }
+    }
+    if !cfg.quiet {
+        let backlog = &session.backlog;
+        eprintln!(
+            "{}",
+            queue_stats(answered, backlog.deepest, backlog.rejected)
+        );
+    }
+    if let Some(child) = child {
+        child.close(SAVE_GRACE);
+    }
+    outcome
+}
+
+/// Checks the runner style and forces the framing the protocol relies on:
+/// responses end with a record separator and carry no runner prefixes.
+pub fn prepare(cfg: &Config) -> Result<Config, NoxError> {
+    if !matches!(cfg.runner_style, RunnerStyle::NoxLocal) {
+        return Err(NoxError::Usage(
+            "persistent mode requires NOX_RUNNER_STYLE=noxlocal".to_string(),
+        ));
+    }
+    let mut cfg = cfg.clone();
+    cfg.persist_rs = true;
+    cfg.raw = true;
+    Ok(cfg)
+}
+
+/// Starts a serve child with whichever of `-cancel-signal` and
+/// `-serve-params` the runner advertises.
+pub fn spawn(cfg: &Config) -> Result<ServeChild, NoxError> {
+    let runner = cfg.serve_runner()?;
+    let model = cfg.serve_model()?;
+    let mut cmd = serve_command(cfg, &runner, model.as_deref());
+    cmd.stderr(Stdio::piped());
+    let caps = capabilities::probe(&runner);
+    let by_signal = cfg!(unix) && caps.supports("-cancel-signal");
+    if by_signal {
+        cmd.arg("-cancel-signal");
+    }
+    let params = caps.supports("-serve-params");
+    if params {
+        cmd.arg("-serve-params");
+    }
+    let mut child = ServeChild::spawn(cmd).map_err(|source| {
+        cfg.resource_limits
+            .explain(NoxError::Spawn { runner, source })
+    })?;
+    if by_signal {
+        child.cancel_by_signal();
+    }
+    if params {
+        child.accept_params();
+    }
+    child.capture_stderr();
+    Ok(child)
+}
+
+/// Waits for the runner to load its model: sends the `NOX_WARMUP_PROMPT`
+/// control request, or a no-op `ping` one when warmup is off. An error
+/// means the runner died (or we were interrupted) first.
+pub fn warm_up(cfg: &Config, child: &mut ServeChild) -> Result<(), NoxError> {
+    let command = match &cfg.persist_warmup {
+        Some(text) => format!("warmup {text}"),
+        None => "ping".to_string(),
+    };
+    let control = format!("{}{command}", serve::CONTROL as char);
+    child.request(&control, &mut io::sink(), None)?;
+    if cfg.persist_warmup.is_some() && !cfg.quiet {
+        eprintln!("nox: warm");
+    }
+    Ok(())
+}
+
+/// The event for a runner that died before it was ready.
+fn load_failed(err: &NoxError, stderr: Vec<String>) -> Json {
+    json::obj([
+        ("type", Json::from("error")),
+        ("reason", Json::from("load_failed")),
+        ("message", Json::from(err.to_string())),
+        (
+            "stderr",
+            Json::Arr(stderr.into_iter().map(Json::from).collect()),
+        ),
+    ])
+}
+
+/// The answer to a ping: time since nox started and prompts answered.
+pub fn pong(id: Option<Json>, started: Instant, requests: u64) -> Json {
+    let mut fields = vec![
+        ("type".to_string(), Json::from("pong")),
+        (
+            "uptime_ms".to_string(),
+            Json::from(started.elapsed().as_millis() as u64),
+        ),
+        ("requests".to_string(), Json::from(requests)),
+    ];
+    if let Some(id) = id {
+        fields.insert(0, ("id".to_string(), id));
+    }
+    Json::Obj(fields)
+}
+
+pub fn error(id: &Json, message: &str) -> Json {
+    json::obj([
+        ("id", id.clone()),
+        ("type", Json::from("error")),
+        ("message", Json::from(message)),
+    ])
+}
+
+/// Writes one response line, in a single write: formatting straight into
+/// an unbuffered socket would send every piece on its own, and a client
+/// that is slow to read could fill its buffer with a few hundred of them.
+pub fn respond(out: &mut dyn Write, response: Json) -> io::Result<()> {
+    out.write_all(format!("{response}\n").as_bytes())?;
+    out.flush()
+}
+
+/// Runs one prompt on `child`, spawning a fresh one if there is none, and
+/// answers it on `out` with deltas and `done`, or `error`. `cancel` is
+/// polled while the runner works. A child that fails or cannot stop is
+/// killed and left for the next prompt to replace. Only failures that
+/// should end the session are returned.
+pub fn answer(
+    cfg: &Config,
+    child: &mut Option<ServeChild>,
+    log: &mut Transcript,
+    out: &mut dyn Write,
+    job: &Job,
+    cancel: &mut dyn FnMut() -> bool,
+) -> Result<(), NoxError> {
+    let Job {
+        id,
+        text,
+        overrides,
+    } = job;
+    if child.is_none() {
+        match spawn(cfg) {
+            Ok(spawned) => {
+                log.event("restart", "started a fresh runner");
+                *child = Some(spawned);
+            }
+            Err(err) => return Ok(respond(out, error(id, &err.to_string()))?),
+        }
+    }
+    let serve = child.as_mut().expect("serve child spawned above");
+    if overrides.sampling() && !serve.accepts_params() {
+        eprintln!(
+            "nox: the runner does not accept -serve-params; ignoring temp/top_p/top_k for request {id}"
+        );
+    }
+    log.begin(text);
+    let mut deltas = Deltas {
+        id,
+        out: &mut *out,
+        log: &mut *log,
+        partial: Vec::new(),
+    };
+    let result = serve
+        .request_until(text, overrides, &mut deltas, cfg.limits().stall, cancel)
+        .map_err(|err| cfg.resource_limits.explain(err));
+    let flushed = deltas.finish();
+    match result {
+        Ok(Some(Exchange {
+            rejected: Some(why),
+            ..
+        })) => {
+            flushed?;
+            // The runner answered, so it is still good for the next prompt.
+            let err = NoxError::BadOutput(format!("runner rejected the request: {why}"));
+            log.end(None, Some(&err));
+            respond(out, error(id, &err.to_string()))?;
+        }
+        Ok(Some(exchange)) => {
+            flushed?;
+            let stats = RunStats {
+                ttft: exchange.ttft,
+                duration: exchange.duration,
+                tokens: None,
+                prefill: None,
+            };
+            log.end(Some(&stats), None);
+            if exchange.abandoned {
+                if let Some(serve) = child.take() {
+                    serve.kill();
+                }
+            }
+            let ms = |d: Duration| d.as_millis() as u64;
+            respond(
+                out,
+                json::obj([
+                    ("id", id.clone()),
+                    ("type", Json::from("done")),
+                    ("ttft_ms", Json::from(stats.ttft.map(ms))),
+                    ("duration_ms", Json::from(ms(stats.duration))),
+                    ("truncated", Json::from(exchange.truncated)),
+                ]),
+            )?;
+        }
+        Ok(None) => {
+            log.end(None, Some(&NoxError::Interrupted));
+            // Without signal support the runner is still generating.
+            if let Some(serve) = child.take() {
+                if serve.cancels_by_signal() {
+                    *child = Some(serve);
+                } else {
+                    serve.kill();
+                }
+            }
+            respond(out, error(id, "cancelled"))?;
+        }
+        Err(err) => {
+            log.end(None, Some(&err));
+            if matches!(err, NoxError::Interrupted | NoxError::Io(_)) {
+                return Err(err);
+            }
+            if let Some(serve) = child.take() {
+                serve.kill();
+            }
+            respond(out, error(id, &err.to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+struct Session {
+    rx: Receiver<Result<Option<Request>, Rejected>>,
+    /// Requests that arrived while a prompt was running.
+    backlog: Backlog,
+    log: Transcript,
+    /// Our stdin has ended.
+    closed: bool,
+}
+
+impl Session {
+    /// The next request, waiting up to one poll interval. Malformed lines
+    /// are answered here.
+    fn next(&mut self) -> Option<Request> {
+        if let Some(request) = self.backlog.pop() {
+            return Some(request);
+        }
+        if self.closed {
+            return None;
+        }
+        match self.rx.recv_timeout(POLL) {
+            Ok(Ok(request)) => request,
+            Ok(Err((id, message))) => {
+                let _ = respond(&mut io::stdout(), error(&id, &message));
+                None
+            }
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.closed = true;
+                None
+            }
+        }
+    }
+}
+
+/// Requests waiting behind the running prompt. At most `max` prompts wait;
+/// more are refused with `queue_full`. Each change in the number waiting is
+/// reported as `{"type":"queue","depth":N}`.
+struct Backlog {
+    requests: VecDeque<Request>,
+    max: usize,
+    reported: usize,
+    deepest: usize,
+    rejected: u64,
+}
+
+impl Backlog {
+    fn new(max: usize) -> Self {
+        Backlog {
+            requests: VecDeque::new(),
+            max,
+            reported: 0,
+            deepest: 0,
+            rejected: 0,
+        }
+    }
+
+    fn depth(&self) -> usize {
+        self.requests
+            .iter()
+            .filter(|r| matches!(r, Request::Prompt(_)))
+            .count()
+    }
+
+    fn push(&mut self, request: Request) {
+        if let Request::Prompt(job) = &request {
+            if self.depth() >= self.max {
+                self.rejected += 1;
+                let _ = respond(&mut io::stdout(), queue_full(&job.id, self.max));
+                return;
+            }
+        }
+        self.requests.push_back(request);
+        self.report();
+    }
+
+    fn pop(&mut self) -> Option<Request> {
+        let request = self.requests.pop_front()?;
+        self.report();
+        Some(request)
+    }
+
+    /// Drops queued prompt `id`; false if it was not waiting.
+    fn cancel(&mut self, id: &Json) -> bool {
+        let at = self
+            .requests
+            .iter()
+            .position(|r| matches!(r, Request::Prompt(job) if job.id == *id));
+        let Some(at) = at else { return false };
+        self.requests.remove(at);
+        self.report();
+        true
+    }
+
+    fn report(&mut self) {
+        let depth = self.depth();
+        if depth == self.reported {
+            return;
+        }
+        self.reported = depth;
+        self.deepest = self.deepest.max(depth);
+        let status = json::obj([("type", Json::from("queue")), ("depth", Json::from(depth))]);
+        let _ = respond(&mut io::stdout(), status);
+    }
+}
+
+/// The response to a prompt refused because `max` are already waiting.
+pub fn queue_full(id: &Json, max: usize) -> Json {
+    json::obj([
+        ("id", id.clone()),
+        ("type", Json::from("error")),
+        ("reason", Json::from("queue_full")),
+        (
+            "message",
+            Json::from(format!("queue full ({max} waiting, NOX_QUEUE_MAX)")),
+        ),
+    ])
+}
+
+/// The line printed at shutdown: prompts answered, and how the queue fared.
+pub fn queue_stats(answered: u64, deepest: usize, rejected: u64) -> String {
+    format!(
+        "nox: {answered} answered, queue max depth {deepest}, {rejected} rejected as queue_full"
+    )
+}
+
+/// Moves newly arrived requests into `backlog`; true if one of them cancels
+/// prompt `id`. A cancel for a queued prompt answers it at once.
+fn poll_cancel(
+    rx: &Receiver<Result<Option<Request>, Rejected>>,
+    backlog: &mut Backlog,
+    id: &Json,
+    started: Instant,
+    answered: u64,
+) -> bool {
+    let mut cancelled = false;
+    while let Ok(line) = rx.try_recv() {
+        let request = match line {
+            Ok(Some(request)) => request,
+            Ok(None) => continue,
+            Err((id, message)) => {
+                let _ = respond(&mut io::stdout(), error(&id, &message));
+                continue;
+            }
+        };
+        match request {
+            Request::Cancel(None) => cancelled = true,
+            Request::Cancel(Some(target)) if target == *id => cancelled = true,
+            Request::Cancel(Some(target)) => {
+                if backlog.cancel(&target) {
+                    let _ = respond(&mut io::stdout(), error(&target, "cancelled"));
+                }
+            }
+            Request::Ping(ping) => {
+                let _ = respond(&mut io::stdout(), pong(ping, started, answered));
+            }
+            other => backlog.push(other),
+        }
+    }
+    cancelled
+}
+
+/// Turns response bytes into `delta` lines, holding back a UTF-8 sequence
+/// split across chunks.
+struct Deltas<'a> {
+    id: &'a Json,
+    out: &'a mut dyn Write,
+    log: &'a mut Transcript,
+    partial: Vec<u8>,
+}
+
+impl Deltas<'_> {
+    fn emit(&mut self, text: &str) -> io::Result<()> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let delta = json::obj([
+            ("id", self.id.clone()),
+            ("type", Json::from("delta")),
+            ("text", Json::from(text)),
+        ]);
+        respond(self.out, delta)
+    }
+
+    /// Emits whatever is still held back.
+    fn finish(mut self) -> io::Result<()> {
+        let rest = std::mem::take(&mut self.partial);
+        self.emit(&String::from_utf8_lossy(&rest))
+    }
+}
+
+impl Write for Deltas<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.log.output(buf);
+        self.partial.extend_from_slice(buf);
+        let bytes = std::mem::take(&mut self.partial);
+        let complete = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            // An incomplete sequence at the end waits for the next chunk.
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(_) => bytes.len(),
+        };
+        self.partial = bytes[complete..].to_vec();
+        self.emit(&String::from_utf8_lossy(&bytes[..complete]))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,257 @@
+//! Per-runner resource limits (`NOX_MAX_RSS_MB`, `NOX_CPU_SECS`). On Unix
+//! they are applied with `setrlimit` between fork and exec, so only the
+//! runner is constrained; runner deaths that look like a limit being hit are
+//! reported as such instead of as a bare exit status.
+
+use std::io;
+use std::process::{Command, ExitStatus};
+
+use crate::config::Sources;
+use crate::error::NoxError;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ResourceLimits {
+    pub max_rss_mb: Option<u64>,
+    pub cpu_secs: Option<u64>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+mod sys {
+    use std::os::raw::c_int;
+
+    #[cfg(target_os = "linux")]
+    pub type RlimT = std::os::raw::c_ulong;
+    #[cfg(target_os = "macos")]
+    pub type RlimT = u64;
+
+    #[cfg(target_os = "linux")]
+    pub const RLIMIT_AS: c_int = 9;
+    #[cfg(target_os = "macos")]
+    pub const RLIMIT_AS: c_int = 5;
+    pub const RLIMIT_CPU: c_int = 0;
+
+    pub const SIGABRT: c_int = 6;
+    #[cfg(target_os = "linux")]
+    pub const SIGBUS: c_int = 7;
+    #[cfg(target_os = "macos")]
+    pub const SIGBUS: c_int = 10;
+    pub const SIGKILL: c_int = 9;
+    pub const SIGSEGV: c_int = 11;
+    pub const SIGXCPU: c_int = 24;
+
+    pub const ENOMEM: i32 = 12;
+
+    #[repr(C)]
+    pub struct Rlimit {
+        pub cur: RlimT,
+        pub max: RlimT,
+    }
+
+    extern "C" {
+        pub fn setrlimit(resource: c_int, rlim: *const Rlimit) -> c_int;
+    }
+}
+
+impl ResourceLimits {
+    pub fn resolve(src: &Sources) -> Self {
+        Self {
+            max_rss_mb: src.u64("NOX_MAX_RSS_MB").filter(|v| *v > 0),
+            cpu_secs: src.u64("NOX_CPU_SECS").filter(|v| *v > 0),
+        }
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.max_rss_mb.is_some() || self.cpu_secs.is_some()
+    }
+
+    /// `NOX_MAX_RSS_MB=4096 NOX_CPU_SECS=600`, for dry-run notes.
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(mb) = self.max_rss_mb {
+            parts.push(format!("NOX_MAX_RSS_MB={mb}"));
+        }
+        if let Some(secs) = self.cpu_secs {
+            parts.push(format!("NOX_CPU_SECS={secs}"));
+        }
+        parts.join(" ")
+    }
+
+    /// Installs the limits on `cmd`, or warns that this platform cannot.
+    pub fn apply(&self, cmd: &mut Command) {
+        if !self.is_set() {
+            return;
+        }
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            use std::os::unix::process::CommandExt;
+            let limits = *self;
+            unsafe {
+                cmd.pre_exec(move || {
+                    if let Some(mb) = limits.max_rss_mb {
+                        let bytes = mb.saturating_mul(1024 * 1024) as sys::RlimT;
+                        set(sys::RLIMIT_AS, bytes, bytes)?;
+                    }
+                    if let Some(secs) = limits.cpu_secs {
+                        // SIGXCPU at the soft limit, SIGKILL a second later.
+                        let secs = secs as sys::RlimT;
+                        set(sys::RLIMIT_CPU, secs, secs.saturating_add(1))?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            use std::sync::Once;
+            static WARN: Once = Once::new();
+            WARN.call_once(|| {
+                eprintln!(
+                    "nox: NOX_MAX_RSS_MB/NOX_CPU_SECS are not supported on this platform; ignoring"
+                )
+            });
+            let _ = cmd;
+        }
+    }
+
+    /// Rewrites runner failures that match a configured limit into
+    /// [`NoxError::LimitExceeded`]; anything else passes through.
+    pub fn explain(&self, err: NoxError) -> NoxError {
+        if !self.is_set() {
+            return err;
+        }
+        match err {
+            NoxError::RunnerFailed(status) => match self.classify(status) {
+                Some(msg) => NoxError::LimitExceeded(msg),
+                None => NoxError::RunnerFailed(status),
+            },
+            NoxError::Spawn { runner, source } if self.out_of_memory(&source) => {
+                NoxError::LimitExceeded(format!(
+                    "{} could not start within the memory limit ({}): {source}",
+                    runner.display(),
+                    self.describe()
+                ))
+            }
+            other => other,
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    fn classify(&self, status: ExitStatus) -> Option<String> {
+        use std::os::unix::process::ExitStatusExt;
+        let memory = self
+            .max_rss_mb
+            .map(|mb| format!("runner exceeded memory limit (NOX_MAX_RSS_MB={mb})"));
+        let cpu = self
+            .cpu_secs
+            .map(|secs| format!("runner exceeded CPU limit (NOX_CPU_SECS={secs})"));
+        match status.signal() {
+            Some(sys::SIGXCPU) => cpu,
+            Some(sys::SIGKILL) => match (memory, cpu) {
+                (Some(_), Some(_)) => Some(format!(
+                    "runner was killed, likely by a resource limit ({})",
+                    self.describe()
+                )),
+                (memory, cpu) => memory.or(cpu),
+            },
+            Some(sys::SIGSEGV | sys::SIGABRT | sys::SIGBUS) => memory,
+            Some(_) => None,
+            // Allocation failures usually end in an ordinary error exit.
+            None => self.max_rss_mb.map(|mb| {
+                format!("runner exited with {status}; it may have run out of memory (NOX_MAX_RSS_MB={mb})")
+            }),
+        }
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    fn classify(&self, _status: ExitStatus) -> Option<String> {
+        None
+    }
+
+    fn out_of_memory(&self, err: &io::Error) -> bool {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        {
+            self.max_rss_mb.is_some() && err.raw_os_error() == Some(sys::ENOMEM)
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        {
+            let _ = err;
+            false
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn set(resource: std::os::raw::c_int, cur: sys::RlimT, max: sys::RlimT) -> io::Result<()> {
+    let limit = sys::Rlimit { cur, max };
+    if unsafe { sys::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "macos")))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn limits(max_rss_mb: Option<u64>, cpu_secs: Option<u64>) -> ResourceLimits {
+        ResourceLimits {
+            max_rss_mb,
+            cpu_secs,
+        }
+    }
+
+    /// Runs `script` under `limits` and explains how it ended.
+    fn run(limits: ResourceLimits, script: &str) -> NoxError {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        limits.apply(&mut cmd);
+        let output = cmd.output().unwrap();
+        assert!(!output.status.success(), "{script} ran within {limits:?}");
+        limits.explain(NoxError::RunnerFailed(output.status))
+    }
+
+    #[test]
+    fn a_tiny_address_space_stops_an_allocation() {
+        // dd allocates its whole block up front.
+        let err = run(
+            limits(Some(32), None),
+            "dd if=/dev/zero of=/dev/null bs=256M count=1 2>/dev/null",
+        );
+        assert_eq!(err.exit_code(), 6);
+        assert!(matches!(err, NoxError::LimitExceeded(_)), "{err:?}");
+        assert!(err.to_string().contains("NOX_MAX_RSS_MB=32"), "{err}");
+    }
+
+    #[test]
+    fn a_busy_loop_hits_the_cpu_limit() {
+        let err = run(limits(None, Some(1)), "while :; do :; done");
+        assert_eq!(
+            err.to_string(),
+            "runner exceeded CPU limit (NOX_CPU_SECS=1)"
+        );
+    }
+
+    #[test]
+    fn deaths_are_classified_by_signal() {
+        let signalled = |sig| NoxError::RunnerFailed(ExitStatus::from_raw(sig));
+        let both = limits(Some(512), Some(60));
+        assert_eq!(
+            both.explain(signalled(sys::SIGKILL)).to_string(),
+            "runner was killed, likely by a resource limit (NOX_MAX_RSS_MB=512 NOX_CPU_SECS=60)"
+        );
+        assert_eq!(
+            both.explain(signalled(sys::SIGSEGV)).to_string(),
+            "runner exceeded memory limit (NOX_MAX_RSS_MB=512)"
+        );
+        let cpu = limits(None, Some(60));
+        assert!(matches!(
+            cpu.explain(signalled(sys::SIGSEGV)),
+            NoxError::RunnerFailed(_)
+        ));
+        assert!(matches!(
+            limits(None, None).explain(signalled(sys::SIGKILL)),
+            NoxError::RunnerFailed(_)
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+//! `NOX_ROUTE_SCORER=embed`: scores routing chunks by how close their
+//! embeddings are to the query's, so a chunk that paraphrases the query
+//! still matches it. The query and every chunk go to one `llama-embedding`
+//! run (`NOX_ROUTE_EMBED_RUNNER`, else found as for
+//! `NOX_RUNNER_STYLE=embedding`, with `NOX_ROUTE_EMBED_MODEL` or else the
+//! usual model). When that fails the chunks are scored by word overlap
+//! instead, with a warning.
+
+use noxroute_core::Scorer;
+
+use crate::error::NoxError;
+use crate::{embedding, Config, RunnerStyle};
+
+/// Whether `NOX_ROUTE_SCORER` asks for embeddings.
+pub fn enabled(cfg: &Config) -> bool {
+    cfg.route_scorer.trim().eq_ignore_ascii_case("embed")
+}
+
+/// The scorer to fall back on when embedding fails.
+pub const FALLBACK: Scorer = Scorer::Overlap;
+
+/// Each chunk's score against `query`: the cosine similarity of their
+/// embeddings, negatives as 0, scaled so the best chunk gets 1.
+pub fn scores(cfg: &Config, query: &str, chunks: &[String]) -> Result<Vec<f32>, NoxError> {
+    let cfg = runner_config(cfg);
+    let texts: Vec<&str> = std::iter::once(query)
+        .chain(chunks.iter().map(String::as_str))
+        .collect();
+    let vectors = embedding::embed(&cfg, &texts.join(embedding::separator(&cfg)))?;
+    let (query, chunks) = vectors
+        .split_first()
+        .ok_or_else(|| NoxError::BadOutput("no embeddings".to_string()))?;
+    let mut scores: Vec<f32> = chunks
+        .iter()
+        .map(|chunk| cosine(query, chunk).max(0.0))
+        .collect();
+    let best = scores.iter().copied().fold(0.0_f32, f32::max);
+    if best > 0.0 {
+        for score in &mut scores {
+            *score /= best;
+        }
+    }
+    Ok(scores)
+}
+
+/// `cfg` set up to run the embedding runner rather than the model.
+fn runner_config(cfg: &Config) -> Config {
+    let mut cfg = cfg.clone();
+    cfg.runner_style = RunnerStyle::LlamaEmbedding;
+    cfg.runner_override = cfg.route_embed_runner.clone();
+    if let Some(model) = &cfg.route_embed_model {
+        cfg.model_override = Some(model.clone());
+    }
+    // The record separator, which ordinary text does not hold, so every
+    // text comes back as one vector.
+    cfg.route_delim = String::new();
+    cfg.extra_args = Vec::new();
+    cfg
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if a.len() != b.len() || norms == 0.0 {
+        return 0.0;
+    }
+    dot / norms
+}
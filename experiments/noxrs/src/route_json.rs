@@ -0,0 +1,163 @@
+//! `NOX_ROUTE_FORMAT`: routing chunks that arrive as a JSON array, as from
+//! a retrieval step, instead of a delimited string. Elements are strings or
+//! `{"text": .., "score": ..}` objects; when every element has a score the
+//! router uses those instead of scoring against the query. By default
+//! (`auto`) the context is read as JSON when it looks like an array; with
+//! `json` the whole prompt is the array; `text` never reads JSON. A
+//! context that does not parse is split as usual.
+
+use noxroute_core::{Chunk, Split};
+
+use crate::error::NoxError;
+use crate::json::Json;
+use crate::{route_split, Config};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Auto,
+    Json,
+    Text,
+}
+
+pub fn format(cfg: &Config) -> Result<Format, NoxError> {
+    match cfg.route_format.trim().to_ascii_lowercase().as_str() {
+        "auto" => Ok(Format::Auto),
+        "json" => Ok(Format::Json),
+        "text" => Ok(Format::Text),
+        other => Err(NoxError::Usage(format!(
+            "NOX_ROUTE_FORMAT must be `auto`, `json`, or `text`, got `{other}`"
+        ))),
+    }
+}
+
+/// The query and chunks when the prompt carries its context as JSON.
+/// `Ok(None)` when it does not; `Err` says why a context meant as JSON
+/// could not be read, for the debug note.
+pub fn chunks(cfg: &Config, prompt: &str) -> Result<Option<(String, Vec<Chunk>)>, String> {
+    let query = cfg.route_query.as_deref();
+    match format(cfg).unwrap_or(Format::Auto) {
+        Format::Text => Ok(None),
+        Format::Json => {
+            let mut chunks = parse(prompt)?;
+            let query = match query {
+                Some(query) => query.to_string(),
+                None if chunks.is_empty() => return Err("the array is empty".to_string()),
+                None => chunks.remove(0).text,
+            };
+            Ok(Some((query, chunks)))
+        }
+        Format::Auto => {
+            let (query, context) = match query {
+                Some(query) => (query, prompt),
+                None => match context_after_query(cfg, prompt) {
+                    Some(split) => split,
+                    None => return Ok(None),
+                },
+            };
+            if !context.trim_start().starts_with('[') {
+                return Ok(None);
+            }
+            Ok(Some((query.trim().to_string(), parse(context)?)))
+        }
+    }
+}
+
+/// The prompt cut after its query: at the first delimiter, or the first
+/// blank line for splits without a literal one.
+fn context_after_query<'a>(cfg: &Config, prompt: &'a str) -> Option<(&'a str, &'a str)> {
+    if route_split(cfg).unwrap_or_default() == Split::Delim {
+        let delim = cfg.route_delim.trim();
+        if delim.is_empty() {
+            return None;
+        }
+        return prompt.split_once(delim);
+    }
+    noxroute_core::first_paragraph(prompt)
+}
+
+fn parse(text: &str) -> Result<Vec<Chunk>, String> {
+    let json = Json::parse(text.trim())?;
+    let items = json.as_array().ok_or("not an array")?;
+    items
+        .iter()
+        .enumerate()
+        .map(|(n, item)| {
+            if let Some(text) = item.as_str() {
+                return Ok(Chunk {
+                    text: text.to_string(),
+                    score: None,
+                });
+            }
+            let text = item.get("text").and_then(Json::as_str).ok_or_else(|| {
+                format!("element {n} is neither a string nor an object with a \"text\" string")
+            })?;
+            let score = match item.get("score").filter(|score| !score.is_null()) {
+                None => None,
+                Some(score) => {
+                    let score = score.as_f64().ok_or_else(|| {
+                        format!("element {n} has a \"score\" that is not a number")
+                    })?;
+                    Some(score as f32)
+                }
+            };
+            Ok(Chunk {
+                text: text.to_string(),
+                score,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+
+    fn texts(chunks: &[Chunk]) -> Vec<&str> {
+        chunks.iter().map(|chunk| chunk.text.as_str()).collect()
+    }
+
+    #[test]
+    fn array_elements_keep_their_delimiters() {
+        let prompt = "which step?\n---\n[\"step one --- then two\", \"--- alone\"]";
+        let (query, chunks) = chunks(&config(&[]), prompt).unwrap().unwrap();
+        assert_eq!(query, "which step?");
+        assert_eq!(texts(&chunks), ["step one --- then two", "--- alone"]);
+        assert!(chunks.iter().all(|chunk| chunk.score.is_none()));
+    }
+
+    #[test]
+    fn objects_bring_their_scores() {
+        let cfg = config(&[("NOX_ROUTE_FORMAT", "json")]);
+        let prompt = r#"["the query", {"text": "a", "score": 0.9}, {"text": "b", "score": null}, {"text": "c", "score": 0}]"#;
+        let (query, read) = chunks(&cfg, prompt).unwrap().unwrap();
+        assert_eq!(query, "the query");
+        assert_eq!(texts(&read), ["a", "b", "c"]);
+        let scores: Vec<Option<f32>> = read.iter().map(|chunk| chunk.score).collect();
+        assert_eq!(scores, [Some(0.9), None, Some(0.0)]);
+
+        let cfg = config(&[("NOX_ROUTE_FORMAT", "json"), ("NOX_ROUTE_QUERY", "given")]);
+        let (query, given) = chunks(&cfg, r#"[{"text": "a", "score": 1}]"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!((query.as_str(), given.len()), ("given", 1));
+    }
+
+    #[test]
+    fn a_context_that_is_not_json_is_left_to_the_split() {
+        let cfg = config(&[]);
+        assert_eq!(chunks(&cfg, "q\n---\nplain text\n---\nmore"), Ok(None));
+        assert_eq!(
+            chunks(&cfg, "q\n---\n[\"unterminated"),
+            Err("unterminated string at byte 14".to_string())
+        );
+        assert_eq!(
+            chunks(&cfg, "q\n---\n[1]"),
+            Err("element 0 is neither a string nor an object with a \"text\" string".to_string())
+        );
+        let text = config(&[("NOX_ROUTE_FORMAT", "text")]);
+        assert_eq!(chunks(&text, "q\n---\n[\"a\"]"), Ok(None));
+        let json = config(&[("NOX_ROUTE_FORMAT", "json")]);
+        assert_eq!(chunks(&json, "[]"), Err("the array is empty".to_string()));
+    }
+}
@@ -0,0 +1,160 @@
+//! `NOX_ROUTE_DEBUG=json`: what routing did, as one JSON object on stderr
+//! in place of the `NOX_ROUTE_DEBUG=1` lines, for tuning. It holds the
+//! query, the settings in effect, each candidate chunk (its index from 0,
+//! first 80 characters, score, router probability, whether it was kept,
+//! and which step decided that), and how long tokenizing, scoring, and
+//! routing took in microseconds.
+
+use noxroute_core::RouteReport;
+
+use crate::json::{obj, Json};
+
+/// Whether `NOX_ROUTE_DEBUG` asks for JSON.
+pub fn wanted(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v.trim().eq_ignore_ascii_case("json"))
+}
+
+pub fn to_json(report: &RouteReport) -> Json {
+    let chunks = report
+        .chunks
+        .iter()
+        .map(|chunk| {
+            obj([
+                ("index", Json::from(chunk.index)),
+                ("text", Json::from(chunk.preview.as_str())),
+                ("score", num(chunk.score)),
+                ("prob", chunk.prob.map_or(Json::Null, num)),
+                ("selected", Json::from(chunk.selected)),
+                ("reason", Json::from(chunk.reason.name())),
+            ])
+        })
+        .collect();
+    // The only scorer nox hands the router is the embedding one.
+    let scorer = match report.scorer {
+        "custom" => "embed",
+        name => name,
+    };
+    let micros = |time: std::time::Duration| Json::from(time.as_micros() as u64);
+    obj([
+        ("query", Json::from(report.query.as_str())),
+        (
+            "config",
+            obj([
+                ("scorer", Json::from(scorer)),
+                ("threshold", num(report.threshold)),
+                ("min_keep", Json::from(report.min_keep)),
+                ("keep", Json::from(report.max_keep)),
+                ("budget", Json::from(report.budget)),
+            ]),
+        ),
+        ("chunks", Json::Arr(chunks)),
+        (
+            "timing_us",
+            obj([
+                ("tokenize", micros(report.timings.tokenize)),
+                ("score", micros(report.timings.score)),
+                ("route", micros(report.timings.route)),
+            ]),
+        ),
+    ])
+}
+
+/// `x` as the shortest decimal that reads back as it, not the long tail
+/// widening it to `f64` gives.
+fn num(x: f32) -> Json {
+    Json::from(x.to_string().parse::<f64>().unwrap_or(f64::from(x)))
+}
+
+#[cfg(test)]
+mod tests {
+    use noxroute_core::RouteCache;
+
+    use super::*;
+    use crate::tests::config;
+
+    const PROMPT: &str = "how do I restart the runner?\n---\n\
+                          restart the runner with nox --restart\n---\n\
+                          the runner restarts by itself after a crash, \
+                          unless NOX_RESTART is 0\n---\n\
+                          models live in assets/models\n---\n\
+                          the latest turn of the chat";
+
+    /// Routes [`PROMPT`] with `vars`, and reads back the JSON report of
+    /// it: each chunk's text, whether it was kept, and why.
+    fn report(vars: &[(&str, &str)]) -> (String, Vec<(String, bool, String)>) {
+        let mut all = vec![("NOX_ROUTE", "1"), ("NOX_ROUTE_DEBUG", "json")];
+        all.extend_from_slice(vars);
+        let cfg = config(&all);
+        let routed = crate::route_prompt(&cfg, PROMPT, &mut RouteCache::default()).unwrap();
+        let json = Json::parse(&to_json(routed.report.as_ref().unwrap()).to_string()).unwrap();
+        let chunks = json
+            .get("chunks")
+            .and_then(Json::as_array)
+            .unwrap()
+            .iter()
+            .enumerate()
+            .map(|(n, chunk)| {
+                assert_eq!(chunk.get("index").and_then(Json::as_f64), Some(n as f64));
+                let text = chunk.get("text").and_then(Json::as_str).unwrap();
+                let selected = match chunk.get("selected") {
+                    Some(Json::Bool(selected)) => *selected,
+                    other => panic!("selected is {other:?}"),
+                };
+                let reason = chunk.get("reason").and_then(Json::as_str).unwrap();
+                (text.to_string(), selected, reason.to_string())
+            })
+            .collect();
+        (routed.prompt, chunks)
+    }
+
+    /// Each chunk the report says was kept is in the prompt and no other
+    /// is, and only kept chunks have a reason to be kept.
+    fn assert_consistent(prompt: &str, chunks: &[(String, bool, String)]) {
+        let context = prompt.split_once("\n---\n").unwrap().1;
+        let kept: Vec<&str> = context.split("\n---\n").collect();
+        for (text, selected, reason) in chunks {
+            assert_eq!(kept.contains(&text.as_str()), *selected, "{text}: {reason}");
+            if reason == "budget" {
+                assert!(!selected, "{text} was kept over the budget");
+            }
+        }
+        assert_eq!(
+            chunks.iter().filter(|(_, selected, _)| *selected).count(),
+            kept.len()
+        );
+    }
+
+    #[test]
+    fn reasons_match_the_mask() {
+        let (prompt, chunks) = report(&[("NOX_ROUTE_KEEP", "2")]);
+        assert_consistent(&prompt, &chunks);
+        assert_eq!(chunks.len(), 4);
+
+        let (prompt, chunks) = report(&[("NOX_ROUTE_THRESHOLD", "0"), ("NOX_ROUTE_KEEP", "0")]);
+        assert_consistent(&prompt, &chunks);
+        assert!(chunks
+            .iter()
+            .all(|(_, selected, reason)| *selected && reason == "threshold"));
+    }
+
+    #[test]
+    fn budget_and_anchor_reasons_match_the_mask() {
+        let (prompt, chunks) = report(&[
+            ("NOX_ROUTE_THRESHOLD", "0"),
+            ("NOX_ROUTE_KEEP", "0"),
+            ("NOX_ROUTE_BUDGET_CHARS", "80"),
+        ]);
+        assert_consistent(&prompt, &chunks);
+        let reasons: Vec<&str> = chunks
+            .iter()
+            .map(|(_, _, reason)| reason.as_str())
+            .collect();
+        assert!(reasons.contains(&"budget"), "{reasons:?}");
+        assert!(reasons.contains(&"threshold"), "{reasons:?}");
+
+        let (prompt, chunks) = report(&[("NOX_ROUTE_KEEP", "1"), ("NOX_ROUTE_ANCHOR", "last")]);
+        assert_consistent(&prompt, &chunks);
+        let last = chunks.last().unwrap();
+        assert_eq!((last.1, last.2.as_str()), (true, "anchor"));
+    }
+}
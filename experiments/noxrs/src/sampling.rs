@@ -0,0 +1,176 @@
+//! Optional sampling knobs beyond temp/top-p/top-k. Each comes from a
+//! `NOX_*` variable or the matching `--flag`, is range-checked up front, and
+//! is only forwarded to runners that accept it. [`Overrides`] carries the
+//! per-request temp/top-p/top-k/max-tokens of persistent mode.
+
+use std::path::Path;
+use std::process::Command;
+
+use crate::capabilities;
+use crate::config::Sources;
+use crate::error::NoxError;
+
+#[derive(Debug, Clone, Default)]
+pub struct Sampling {
+    pub seed: Option<u32>,
+    pub repeat_penalty: Option<f32>,
+    pub repeat_last_n: Option<i32>,
+    pub min_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+}
+
+impl Sampling {
+    pub fn resolve(src: &Sources) -> Self {
+        Self {
+            seed: src.u32("NOX_SEED"),
+            repeat_penalty: src.f32("NOX_REPEAT_PENALTY"),
+            repeat_last_n: src.i32("NOX_REPEAT_LAST_N"),
+            min_p: src.f32("NOX_MIN_P"),
+            presence_penalty: src.f32("NOX_PRESENCE_PENALTY"),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), NoxError> {
+        let bad = |msg: String| Err(NoxError::Usage(msg));
+        for (name, value) in [
+            ("repeat penalty (NOX_REPEAT_PENALTY)", self.repeat_penalty),
+            (
+                "presence penalty (NOX_PRESENCE_PENALTY)",
+                self.presence_penalty,
+            ),
+        ] {
+            if let Some(v) = value {
+                if !(v >= 0.0 && v.is_finite()) {
+                    return bad(format!("{name} must be >= 0, got {v}"));
+                }
+            }
+        }
+        if let Some(v) = self.min_p {
+            if !(0.0..=1.0).contains(&v) {
+                return bad(format!("min-p (NOX_MIN_P) must be within [0, 1], got {v}"));
+            }
+        }
+        if let Some(v) = self.repeat_last_n {
+            if v < -1 {
+                return bad(format!(
+                    "repeat-last-n (NOX_REPEAT_LAST_N) must be >= -1 (-1 = whole context), got {v}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn values(&self) -> [(&'static str, &'static str, Option<String>); 5] {
+        [
+            ("seed", "NOX_SEED", self.seed.map(|v| v.to_string())),
+            (
+                "repeat-penalty",
+                "NOX_REPEAT_PENALTY",
+                self.repeat_penalty.map(|v| v.to_string()),
+            ),
+            (
+                "repeat-last-n",
+                "NOX_REPEAT_LAST_N",
+                self.repeat_last_n.map(|v| v.to_string()),
+            ),
+            ("min-p", "NOX_MIN_P", self.min_p.map(|v| v.to_string())),
+            (
+                "presence-penalty",
+                "NOX_PRESENCE_PENALTY",
+                self.presence_penalty.map(|v| v.to_string()),
+            ),
+        ]
+    }
+
+    /// llama-completion understands all of them as `--name value`.
+    pub fn llama_args(&self, cmd: &mut Command) {
+        for (name, _, value) in self.values() {
+            if let Some(value) = value {
+                cmd.args([format!("--{name}"), value]);
+            }
+        }
+    }
+
+    /// noxlocal builds differ, so each `-name` is passed only when the
+    /// runner's help text lists it; the rest are dropped with a warning.
+    pub fn noxlocal_args(&self, cmd: &mut Command, runner: &Path) {
+        let values = self.values();
+        if values.iter().all(|(_, _, v)| v.is_none()) {
+            return;
+        }
+        let caps = capabilities::probe(runner);
+        for (name, env, value) in values {
+            let Some(value) = value else { continue };
+            let flag = format!("-{name}");
+            if caps.supports(&flag) {
+                cmd.args([flag, value]);
+            } else {
+                eprintln!(
+                    "nox: {} does not accept {flag}; ignoring {env}",
+                    runner.display()
+                );
+            }
+        }
+    }
+}
+
+/// Per-request settings in persistent mode: `"temp"` etc. on a JSONL prompt,
+/// or `!temp 0.7` lines before a plain-mode prompt. Unset fields keep the
+/// runner's values; nothing carries over to the next request.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Overrides {
+    pub temp: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<u32>,
+    pub max_tokens: Option<u32>,
+}
+
+impl Overrides {
+    /// Field names as they appear in requests.
+    pub const NAMES: [&'static str; 4] = ["temp", "top_p", "top_k", "max_tokens"];
+
+    /// Range-checks and sets `name` (`top_p` or `top-p`).
+    pub fn set(&mut self, name: &str, value: f64) -> Result<(), String> {
+        let whole = |v: f64| v.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(&v);
+        match name.replace('-', "_").as_str() {
+            "temp" if value >= 0.0 && value.is_finite() => self.temp = Some(value as f32),
+            "temp" => return Err(format!("temp must be >= 0, got {value}")),
+            "top_p" if (0.0..=1.0).contains(&value) => self.top_p = Some(value as f32),
+            "top_p" => return Err(format!("top_p must be within [0, 1], got {value}")),
+            "top_k" if whole(value) => self.top_k = Some(value as u32),
+            "top_k" => return Err(format!("top_k must be a whole number >= 0, got {value}")),
+            "max_tokens" if whole(value) && value >= 1.0 => self.max_tokens = Some(value as u32),
+            "max_tokens" => {
+                return Err(format!(
+                    "max_tokens must be a whole number >= 1, got {value}"
+                ))
+            }
+            other => return Err(format!("unknown setting `{other}`")),
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Whether anything besides `max_tokens` is set; only a runner with
+    /// `-serve-params` can apply those.
+    pub fn sampling(&self) -> bool {
+        self.temp.is_some() || self.top_p.is_some() || self.top_k.is_some()
+    }
+
+    /// `temp=0.7 top-k=40`, the header of a `-serve-params` request.
+    pub fn header(&self) -> String {
+        [
+            ("temp", self.temp.map(|v| v.to_string())),
+            ("top-p", self.top_p.map(|v| v.to_string())),
+            ("top-k", self.top_k.map(|v| v.to_string())),
+            ("max-tokens", self.max_tokens.map(|v| v.to_string())),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some(format!("{name}={}", value?)))
+        .collect::<Vec<_>>()
+        .join(" ")
+    }
+}
@@ -0,0 +1,327 @@
+//! Request/response driver for a `noxlocal -serve -serve-rs` child. Prompts
+//! are written to its stdin terminated by an ASCII record separator and each
+//! response ends with the same byte, so one warm process can answer many
+//! prompts in sequence. A request can be cancelled midway: runners started
+//! with `-cancel-signal` stop generating on SIGUSR1 and still end the
+//! response, others have to be killed. Runners started with `-serve-params`
+//! take per-request sampling settings in a header before the prompt; for
+//! others only `max_tokens` is enforced, by cutting the response short. A
+//! header the runner cannot apply gets a [`REJECTED`] response instead.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::NoxError;
+use crate::sampling::Overrides;
+use crate::signals;
+use crate::stderr_filter::StderrCapture;
+
+pub const RECORD_SEP: u8 = 0x1e;
+/// Starts a request that is a command for the runner, not a prompt.
+pub const CONTROL: u8 = 0x05;
+/// What `noxlocal -serve` prints after each response without `-serve-rs`.
+pub const LINE_END: &[u8] = b"\n<<<NOX_END>>>\n";
+/// Opens and closes the settings header of a `-serve-params` request:
+/// `\x06temp=0.7 top-k=40\x06prompt`.
+pub const PARAMS: u8 = 0x06;
+/// Starts a response refusing the request, e.g. for a settings header the
+/// runner cannot apply; the rest of the response says why.
+pub const REJECTED: u8 = 0x15;
+
+const POLL: Duration = Duration::from_millis(100);
+
+pub struct ServeChild {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    rx: Receiver<io::Result<Vec<u8>>>,
+    pending: Vec<u8>,
+    cancel_signal: bool,
+    params: bool,
+    drain: bool,
+    stderr: Option<StderrCapture>,
+}
+
+/// Timing for one answered request.
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    pub ttft: Option<Duration>,
+    pub duration: Duration,
+    /// The response was cut at `max_tokens` by us rather than the runner.
+    pub truncated: bool,
+    /// The runner was left mid-response (no `-cancel-signal` to stop it)
+    /// and has to be killed.
+    pub abandoned: bool,
+    /// Why the runner refused the request; nothing was written to `out`.
+    pub rejected: Option<String>,
+}
+
+/// Why we stopped passing the response on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+    Cancelled,
+    Truncated,
+}
+
+impl ServeChild {
+    /// Spawns `cmd`, which must already carry `-serve -serve-rs`.
+    pub fn spawn(mut cmd: Command) -> io::Result<Self> {
+        cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+        signals::isolate(&mut cmd);
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take();
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| io::Error::other("failed to open child stdout"))?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            loop {
+                match stdout.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.send(Ok(buf[..n].to_vec())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => {
+                        let _ = tx.send(Err(err));
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Self {
+            child,
+            stdin,
+            rx,
+            pending: Vec::new(),
+            cancel_signal: false,
+            params: false,
+            drain: false,
+            stderr: None,
+        })
+    }
+
+    /// Marks the child as started with `-cancel-signal`.
+    pub fn cancel_by_signal(&mut self) {
+        self.cancel_signal = true;
+    }
+
+    pub fn cancels_by_signal(&self) -> bool {
+        self.cancel_signal
+    }
+
+    /// Marks the child as started with `-serve-params`.
+    pub fn accept_params(&mut self) {
+        self.params = true;
+    }
+
+    pub fn accepts_params(&self) -> bool {
+        self.params
+    }
+
+    /// Lets a request finish through the first interrupt (the caller stops
+    /// after it); only a second one aborts it.
+    pub fn drain_on_interrupt(&mut self) {
+        self.drain = true;
+    }
+
+    /// Echoes the child's stderr, which it was spawned with piped, keeping
+    /// the last lines for [`ServeChild::stderr_tail`].
+    pub fn capture_stderr(&mut self) {
+        self.stderr = self.child.stderr.take().map(StderrCapture::forward);
+    }
+
+    /// The last stderr lines of a child that has exited; empty unless
+    /// [`ServeChild::capture_stderr`] was called.
+    pub fn stderr_tail(&mut self) -> Vec<String> {
+        self.stderr
+            .take()
+            .map(StderrCapture::join)
+            .unwrap_or_default()
+    }
+
+    /// Sends one prompt and streams the response body into `out`. A
+    /// refused request is an error.
+    pub fn request(
+        &mut self,
+        prompt: &str,
+        out: &mut dyn Write,
+        stall: Option<Duration>,
+    ) -> Result<Exchange, NoxError> {
+        let overrides = Overrides::default();
+        let exchange = self.request_until(prompt, &overrides, out, stall, &mut || false)?;
+        let exchange = exchange.expect("never cancelled");
+        match exchange.rejected {
+            Some(why) => Err(NoxError::BadOutput(format!("runner rejected the request: {why}"))),
+            None => Ok(exchange),
+        }
+    }
+
+    /// Like [`ServeChild::request`] with per-request `overrides`, polling
+    /// `cancel` while waiting. A cancelled request returns `None`: with
+    /// `-cancel-signal` the rest of the response has been discarded and the
+    /// child is ready for more, otherwise it is still generating and must be
+    /// killed. Sampling overrides need `-serve-params`; without it they are
+    /// left out of the request.
+    pub fn request_until(
+        &mut self,
+        prompt: &str,
+        overrides: &Overrides,
+        out: &mut dyn Write,
+        stall: Option<Duration>,
+        cancel: &mut dyn FnMut() -> bool,
+    ) -> Result<Option<Exchange>, NoxError> {
+        let started = Instant::now();
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| io::Error::other("runner stdin already closed"))?;
+        let mut frame = Vec::new();
+        if self.params && !overrides.is_empty() {
+            frame.push(PARAMS);
+            frame.extend_from_slice(overrides.header().as_bytes());
+            frame.push(PARAMS);
+        }
+        frame.extend(prompt.bytes().filter(|b| *b != RECORD_SEP));
+        frame.push(RECORD_SEP);
+        if let Err(err) = stdin.write_all(&frame).and_then(|()| stdin.flush()) {
+            // A runner that exited (say, failing to load its model) is a
+            // runner failure, not an I/O error of ours.
+            if err.kind() == io::ErrorKind::BrokenPipe {
+                return Err(NoxError::RunnerFailed(self.child.wait()?));
+            }
+            return Err(err.into());
+        }
+
+        let mut ttft = None;
+        let mut last_activity = started;
+        let mut stop = None;
+        // Words still allowed when we enforce max_tokens ourselves; tokens
+        // are counted as whitespace-separated words, like the simulator.
+        let mut budget = overrides.max_tokens.filter(|_| !self.params);
+        let mut in_word = false;
+        let mut rejected: Option<Vec<u8>> = None;
+        loop {
+            if !self.pending.is_empty() {
+                let chunk = std::mem::take(&mut self.pending);
+                let first = ttft.is_none();
+                if first {
+                    ttft = Some(started.elapsed());
+                }
+                let end = chunk.iter().position(|b| *b == RECORD_SEP);
+                let mut body = &chunk[..end.unwrap_or(chunk.len())];
+                if first && body.first() == Some(&REJECTED) {
+                    rejected = Some(Vec::new());
+                    body = &body[1..];
+                }
+                if let Some(why) = rejected.as_mut() {
+                    why.extend_from_slice(body);
+                } else if stop.is_none() {
+                    let cut = budget
+                        .as_mut()
+                        .and_then(|left| cut_after(body, left, &mut in_word));
+                    out.write_all(&body[..cut.unwrap_or(body.len())])?;
+                    out.flush()?;
+                    if cut.is_some() {
+                        stop = Some(Stop::Truncated);
+                    }
+                    if cut.is_some() && end.is_none() {
+                        if !self.cancel_signal {
+                            return Ok(Some(Exchange {
+                                ttft,
+                                duration: started.elapsed(),
+                                truncated: true,
+                                abandoned: true,
+                                rejected: None,
+                            }));
+                        }
+                        signals::cancel(&self.child);
+                    }
+                }
+                if let Some(end) = end {
+                    self.pending = chunk[end + 1..].to_vec();
+                    if stop == Some(Stop::Cancelled) {
+                        return Ok(None);
+                    }
+                    return Ok(Some(Exchange {
+                        ttft,
+                        duration: started.elapsed(),
+                        truncated: stop == Some(Stop::Truncated),
+                        abandoned: false,
+                        rejected: rejected
+                            .map(|why| String::from_utf8_lossy(&why).trim().to_string()),
+                    }));
+                }
+            }
+            if signals::interrupts() > usize::from(self.drain) {
+                return Err(NoxError::Interrupted);
+            }
+            if stop.is_none() && cancel() {
+                if !self.cancel_signal {
+                    return Ok(None);
+                }
+                signals::cancel(&self.child);
+                stop = Some(Stop::Cancelled);
+            }
+            let wait = stall
+                .map(|s| (last_activity + s).saturating_duration_since(Instant::now()))
+                .map_or(POLL, |left| left.min(POLL));
+            match self.rx.recv_timeout(wait) {
+                Ok(Ok(chunk)) => {
+                    last_activity = Instant::now();
+                    self.pending = chunk;
+                }
+                Ok(Err(err)) => return Err(err.into()),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = self.child.wait()?;
+                    return Err(NoxError::RunnerFailed(status));
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(stall) = stall {
+                        if last_activity.elapsed() >= stall {
+                            return Err(NoxError::Timeout(format!(
+                                "runner stalled: no output for {}s (NOX_STALL_SECS)",
+                                stall.as_secs()
+                            )));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Closes stdin so the runner can save state and exit, force-killing it
+    /// after `grace`.
+    pub fn close(mut self, grace: Duration) {
+        self.stdin.take();
+        let seen = signals::interrupts();
+        signals::reap(&mut self.child, grace, seen);
+    }
+
+    pub fn kill(mut self) {
+        signals::terminate(&mut self.child, true);
+        let _ = self.child.wait();
+    }
+}
+
+/// Where the word after the `left` allowed ones starts in `bytes`, if it
+/// does; `left` and `in_word` carry over between chunks.
+fn cut_after(bytes: &[u8], left: &mut u32, in_word: &mut bool) -> Option<usize> {
+    for (i, b) in bytes.iter().enumerate() {
+        let word_byte = !b.is_ascii_whitespace();
+        if word_byte && !*in_word {
+            if *left == 0 {
+                return Some(i);
+            }
+            *left -= 1;
+        }
+        *in_word = word_byte;
+    }
+    None
+}
@@ -0,0 +1,192 @@
+//! `NOX_SESSION_LOG=DIR`: an audit trail for persistent sessions. Each
+//! session writes `DIR/nox-<pid>-<timestamp>.jsonl`, one JSON record per
+//! request plus one per runner restart. Once a file passes
+//! `NOX_SESSION_LOG_MAX_MB` the next record starts a new one, and the
+//! session's oldest files beyond `NOX_SESSION_LOG_KEEP` are deleted. Every
+//! record is a single append of one whole line. A failed write warns once
+//! and turns the log off; it never fails a request.
+
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+use crate::json::Json;
+use crate::transcript::iso8601;
+use crate::Config;
+
+pub struct SessionLog {
+    dir: PathBuf,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    keep: usize,
+    /// This session's files, oldest first; the last is being written.
+    files: VecDeque<PathBuf>,
+}
+
+impl SessionLog {
+    /// Starts the session's first file when `NOX_SESSION_LOG` is set. A
+    /// directory that cannot be created or written warns and gives `None`.
+    pub fn open(cfg: &Config) -> Option<Self> {
+        let dir = cfg.session_log.as_ref()?;
+        let opened = fs::create_dir_all(dir).and_then(|_| create(dir));
+        match opened {
+            Ok((path, file)) => Some(Self {
+                dir: dir.clone(),
+                file,
+                size: 0,
+                max_bytes: (f64::from(cfg.session_log_max_mb) * 1024.0 * 1024.0) as u64,
+                keep: cfg.session_log_keep.max(1) as usize,
+                files: VecDeque::from([path]),
+            }),
+            Err(err) => {
+                warn(dir, &err);
+                None
+            }
+        }
+    }
+
+    /// Appends `record` as one line, rotating first if it would push the
+    /// current file past the size cap.
+    pub fn write(&mut self, record: &Json) -> io::Result<()> {
+        let line = format!("{record}\n");
+        let len = line.len() as u64;
+        if self.size > 0 && self.size + len > self.max_bytes {
+            self.rotate()?;
+        }
+        if let Err(err) = self.file.write_all(line.as_bytes()) {
+            // Drop a partly written line so the file stays whole records.
+            let _ = self.file.set_len(self.size);
+            return Err(err);
+        }
+        self.size += len;
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let (path, file) = create(&self.dir)?;
+        self.file = file;
+        self.size = 0;
+        self.files.push_back(path);
+        while self.files.len() > self.keep {
+            if let Some(old) = self.files.pop_front() {
+                let _ = fs::remove_file(old);
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn warn(dir: &Path, err: &io::Error) {
+    eprintln!(
+        "nox: cannot write session log in {}: {err}; session logging disabled",
+        dir.display()
+    );
+}
+
+/// Creates `nox-<pid>-<timestamp>.jsonl` in `dir`, with a `-N` suffix when
+/// a file from the same second exists.
+fn create(dir: &Path) -> io::Result<(PathBuf, File)> {
+    let stamp: String = iso8601(SystemTime::now())
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .collect();
+    let base = format!("nox-{}-{stamp}", process::id());
+    for n in 0u32.. {
+        let name = match n {
+            0 => format!("{base}.jsonl"),
+            n => format!("{base}-{n}.jsonl"),
+        };
+        let path = dir.join(name);
+        match OpenOptions::new().append(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!("ran out of session log names")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcript::Transcript;
+
+    fn config(dir: &Path, max_mb: &str, keep: &str) -> Config {
+        crate::tests::config(&[
+            ("NOX_SESSION_LOG", &dir.display().to_string()),
+            ("NOX_SESSION_LOG_MAX_MB", max_mb),
+            ("NOX_SESSION_LOG_KEEP", keep),
+        ])
+    }
+
+    fn lines(dir: &Path) -> Vec<Vec<String>> {
+        let mut files: Vec<PathBuf> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        files
+            .iter()
+            .map(|file| {
+                fs::read_to_string(file)
+                    .unwrap()
+                    .lines()
+                    .map(str::to_string)
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn three_requests(cfg: &Config) {
+        let mut log = Transcript::open(None).with_session(SessionLog::open(cfg));
+        for n in 1..=3 {
+            log.begin(&format!("prompt {n}"));
+            log.output(format!("response {n}").as_bytes());
+            log.end(None, None);
+        }
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nox-session-{}-{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn one_file_holds_every_record_under_the_cap() {
+        let dir = scratch("whole");
+        three_requests(&config(&dir, "50", "5"));
+        let files = lines(&dir);
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].len(), 3);
+        for (n, line) in files[0].iter().enumerate() {
+            assert!(line.starts_with("{\"type\":\"request\""), "{line}");
+            assert!(line.contains(&format!("\"prompt\":\"prompt {}\"", n + 1)));
+            assert!(line.contains(&format!("\"response\":\"response {}\"", n + 1)));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_full_file_rotates_and_old_ones_go() {
+        let dir = scratch("rotate");
+        // About 300 bytes: one record per file.
+        three_requests(&config(&dir, "0.0003", "2"));
+        let files = lines(&dir);
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|lines| lines.len() == 1));
+        let kept = files.concat().join("\n");
+        assert!(!kept.contains("\"prompt\":\"prompt 1\""));
+        assert!(kept.contains("\"prompt\":\"prompt 2\""));
+        assert!(kept.contains("\"prompt\":\"prompt 3\""));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
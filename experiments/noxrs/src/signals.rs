@@ -0,0 +1,320 @@
+//! Hand-rolled SIGINT/SIGTERM/SIGHUP handling. The handler only bumps an
+//! atomic counter (and notes whether a SIGTERM or SIGHUP was among them);
+//! the run loops poll it and decide how hard to stop the child.
+//! Children are spawned in their own process group so a group kill also
+//! reaches anything the runner forked.
+
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// How long a signalled child gets to exit on its own before SIGKILL.
+pub const GRACE: Duration = Duration::from_secs(2);
+
+#[cfg(unix)]
+mod sys {
+    use std::os::raw::c_int;
+
+    pub const SIGHUP: c_int = 1;
+    pub const SIGINT: c_int = 2;
+    pub const SIGKILL: c_int = 9;
+    pub const SIGTERM: c_int = 15;
+    #[cfg(target_os = "macos")]
+    pub const SIGUSR1: c_int = 30;
+    #[cfg(not(target_os = "macos"))]
+    pub const SIGUSR1: c_int = 10;
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub const P_PID: c_int = 1;
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub const WNOHANG: c_int = 1;
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    pub const WEXITED: c_int = 4;
+    #[cfg(target_os = "linux")]
+    pub const WNOWAIT: c_int = 0x0100_0000;
+    #[cfg(target_os = "macos")]
+    pub const WNOWAIT: c_int = 0x20;
+    /// Where `si_pid` sits in a `siginfo_t`, counted in `c_int`s.
+    #[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+    pub const SI_PID: usize = 4;
+    #[cfg(any(
+        all(target_os = "linux", not(target_pointer_width = "64")),
+        target_os = "macos"
+    ))]
+    pub const SI_PID: usize = 3;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        pub fn kill(pid: c_int, sig: c_int) -> c_int;
+        pub fn setpgid(pid: c_int, pgid: c_int) -> c_int;
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        pub fn waitid(idtype: c_int, id: u32, info: *mut c_int, options: c_int) -> c_int;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(sig: std::os::raw::c_int) {
+    if sig != sys::SIGINT {
+        TERMINATED.store(true, Ordering::SeqCst);
+    }
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+extern "C" fn on_cancel(_: std::os::raw::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Routes SIGINT/SIGTERM/SIGHUP into the interrupt counter. Call right
+/// before spawning so Ctrl-C still kills noxrs outright while it reads stdin.
+pub fn install() {
+    #[cfg(unix)]
+    unsafe {
+        sys::signal(sys::SIGINT, on_signal);
+        sys::signal(sys::SIGTERM, on_signal);
+        sys::signal(sys::SIGHUP, on_signal);
+    }
+}
+
+/// Routes SIGUSR1 into [`cancelled`], for the simulator's serve child
+/// standing in for a `-cancel-signal` runner. Nothing else listens for it.
+pub fn install_cancel() {
+    #[cfg(unix)]
+    unsafe {
+        sys::signal(sys::SIGUSR1, on_cancel);
+    }
+}
+
+/// Whether SIGUSR1 has arrived since [`clear_cancel`].
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+pub fn clear_cancel() {
+    CANCELLED.store(false, Ordering::SeqCst);
+}
+
+pub fn interrupts() -> usize {
+    INTERRUPTS.load(Ordering::SeqCst)
+}
+
+/// Whether a SIGTERM or SIGHUP arrived: a service manager or a closing
+/// terminal asking for a clean stop, rather than Ctrl-C.
+pub fn terminated() -> bool {
+    TERMINATED.load(Ordering::SeqCst)
+}
+
+/// Puts the child in a fresh process group (Unix) so signals sent to the
+/// group also hit grandchildren; on Windows it just suppresses the console.
+pub fn isolate(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        unsafe {
+            cmd.pre_exec(|| {
+                if sys::setpgid(0, 0) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        // Keep GUI hosts from flashing a console window per runner.
+        const CREATE_NO_WINDOW: u32 = 0x0800_0000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    #[cfg(not(any(unix, windows)))]
+    let _ = cmd;
+}
+
+/// Sends SIGTERM (or SIGKILL when `force`) to the child's process group.
+pub fn terminate(child: &mut Child, force: bool) {
+    #[cfg(unix)]
+    {
+        let sig = if force { sys::SIGKILL } else { sys::SIGTERM };
+        let pgid = child.id() as i32;
+        unsafe {
+            sys::kill(-pgid, sig);
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = force;
+        let _ = child.kill();
+    }
+}
+
+/// Sends SIGUSR1 to the runner itself, which a `-cancel-signal` runner
+/// takes as "stop this generation". A no-op off Unix.
+pub fn cancel(child: &Child) {
+    #[cfg(unix)]
+    unsafe {
+        sys::kill(child.id() as i32, sys::SIGUSR1);
+    }
+    #[cfg(not(unix))]
+    let _ = child;
+}
+
+/// Terminates the child group, giving it `grace` to exit unless another
+/// interrupt arrives, then force-kills whatever is left and reaps the child.
+pub fn shutdown(child: &mut Child, grace: Duration) {
+    let seen = interrupts();
+    terminate(child, false);
+    reap(child, grace, seen);
+}
+
+/// Waits up to `grace` for the child to exit on its own, force-killing the
+/// group early if the interrupt counter moves past `seen`.
+pub fn reap(child: &mut Child, grace: Duration, seen: usize) {
+    let deadline = Instant::now() + grace;
+    loop {
+        match leader(child) {
+            Leader::Running => {}
+            // Still unreaped, so its pid, and with it the group id, can't
+            // have been handed out again: sweep the stragglers that kept
+            // the group alive, then reap it.
+            Leader::Exited => {
+                terminate(child, true);
+                let _ = child.wait();
+                return;
+            }
+            Leader::Reaped => return,
+        }
+        if interrupts() > seen || Instant::now() >= deadline {
+            terminate(child, true);
+            let _ = child.wait();
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+enum Leader {
+    Running,
+    /// Exited and not yet reaped.
+    Exited,
+    /// Exited and reaped; its group id may already belong to someone else.
+    Reaped,
+}
+
+/// Checks on the group leader without reaping it where the platform can.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn leader(child: &mut Child) -> Leader {
+    // A `siginfo_t` is 128 bytes on both.
+    let mut info = [0; 32];
+    let options = sys::WEXITED | sys::WNOHANG | sys::WNOWAIT;
+    if unsafe { sys::waitid(sys::P_PID, child.id(), info.as_mut_ptr(), options) } != 0 {
+        // Not ours to wait for any more: reaped already.
+        return Leader::Reaped;
+    }
+    if info[sys::SI_PID] == 0 {
+        Leader::Running
+    } else {
+        Leader::Exited
+    }
+}
+
+/// Elsewhere the check reaps, so there is no sweep after it.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn leader(child: &mut Child) -> Leader {
+    match child.try_wait() {
+        Ok(None) => Leader::Running,
+        Ok(Some(_)) | Err(_) => Leader::Reaped,
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::process::Stdio;
+
+    /// Whether `pid` is still running; a zombie waiting for init to reap
+    /// it is not.
+    fn alive(pid: i32) -> bool {
+        if unsafe { sys::kill(pid, 0) } != 0 {
+            return false;
+        }
+        match fs::read_to_string(format!("/proc/{pid}/stat")) {
+            Ok(stat) => !stat
+                .rsplit_once(')')
+                .is_some_and(|(_, rest)| rest.trim_start().starts_with('Z')),
+            Err(_) => true,
+        }
+    }
+
+    /// A runner that forks a long-running grandchild, as llama wrappers
+    /// do, and reports its pid.
+    fn runner_with_grandchild(script: &str) -> (Child, i32) {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]).stdout(Stdio::piped());
+        isolate(&mut cmd);
+        let mut child = cmd.spawn().unwrap();
+        let mut line = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        (child, line.trim().parse().unwrap())
+    }
+
+    fn gone(pid: i32) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if !alive(pid) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn shutting_a_runner_down_leaves_no_orphan() {
+        let (mut child, grandchild) = runner_with_grandchild("sleep 60 & echo $!; wait");
+        assert!(alive(grandchild));
+        shutdown(&mut child, GRACE);
+        assert!(child.try_wait().unwrap().is_some());
+        assert!(gone(grandchild), "pid {grandchild} outlived its runner");
+    }
+
+    #[test]
+    fn a_runner_that_exits_first_has_its_group_swept() {
+        let (mut child, grandchild) =
+            runner_with_grandchild("trap '' TERM; sleep 60 & echo $!; exec sleep 0.1");
+        reap(&mut child, GRACE, interrupts());
+        assert!(child.try_wait().unwrap().is_some());
+        assert!(gone(grandchild), "pid {grandchild} outlived its runner");
+    }
+
+    #[test]
+    fn a_reaped_runner_is_not_signalled_again() {
+        let (mut child, grandchild) = runner_with_grandchild("sleep 60 & echo $!");
+        child.wait().unwrap();
+        reap(&mut child, GRACE, interrupts());
+        // No sweep once the leader is reaped: its group id may be reused.
+        assert!(alive(grandchild));
+        unsafe {
+            sys::kill(grandchild, sys::SIGKILL);
+        }
+    }
+
+    #[test]
+    fn a_runner_ignoring_sigterm_is_killed_after_the_grace() {
+        let (mut child, grandchild) =
+            runner_with_grandchild("trap '' TERM; sleep 60 & echo $!; while :; do sleep 1; done");
+        let started = Instant::now();
+        shutdown(&mut child, Duration::from_millis(300));
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert!(child.try_wait().unwrap().is_some());
+        assert!(gone(grandchild), "pid {grandchild} outlived its runner");
+    }
+}
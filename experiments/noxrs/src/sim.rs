@@ -0,0 +1,837 @@
+//! The A1000 simulator (`NOX_EMULATE_A1000=1`): answers without starting a
+//! runner, streaming a canned, scripted (see [`crate::sim_script`]), or
+//! `NOX_SIM_TEXT` reply a word (or, with `NOX_SIM_CHUNKING`, a piece of one)
+//! at a time, up to `NOX_MAX_TOKENS`. Its pace is worked out up front as
+//! a [`SimPlan`], from `NOX_SIM_TTFT_MS` plus the prompt's prefill at
+//! `NOX_SIM_PREFILL_TPS`, and `NOX_SIM_TOKENS_PER_SEC` roughed up by
+//! `NOX_SIM_JITTER_PCT` and `NOX_SIM_STALL`. The randomness comes from
+//! `NOX_SIM_SEED`, so a seeded run streams the same way every time, and the
+//! plan can be checked without sleeping through it. `NOX_SIM_FAIL` makes
+//! runs fail the ways a runner can, through the same errors.
+
+use std::io::Write;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::NoxError;
+use crate::signals;
+use crate::sim_script::Script;
+use crate::tokens;
+use crate::watchdog::PumpEnd;
+use crate::{Config, RunStats};
+
+/// How often a hung run checks whether a limit or Ctrl-C has ended it.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Written halfway through a reply by `NOX_SIM_FAIL=garbage`: two bytes
+/// that never appear in UTF-8, then the start of a sequence cut short.
+const GARBAGE: &[u8] = b"\xff\xfe\xc3";
+
+/// Simulated runs so far in this process; each takes the next line of a
+/// plain script, and seeds its jitter apart from the others.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// `NOX_SIM_STALL`: each gap between tokens has chance `p` of running `ms`
+/// longer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stall {
+    pub p: f64,
+    pub ms: u64,
+}
+
+impl Stall {
+    /// Parses `p=0.02,ms=400`, in either order.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (mut p, mut ms) = (None, None);
+        for part in text
+            .split(',')
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+        {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("`{part}` is not key=value"))?;
+            let value = value.trim();
+            match key.trim() {
+                "p" => {
+                    p = Some(
+                        value
+                            .parse::<f64>()
+                            .ok()
+                            .filter(|p| (0.0..=1.0).contains(p))
+                            .ok_or_else(|| format!("p must be between 0 and 1, got `{value}`"))?,
+                    )
+                }
+                "ms" => {
+                    ms = Some(
+                        value
+                            .parse::<u64>()
+                            .map_err(|_| format!("ms must be a whole number, got `{value}`"))?,
+                    )
+                }
+                other => return Err(format!("unknown key `{other}`")),
+            }
+        }
+        match (p, ms) {
+            (Some(p), Some(ms)) => Ok(Self { p, ms }),
+            _ => Err("both p and ms are needed".to_string()),
+        }
+    }
+}
+
+/// `NOX_SIM_FAIL`: how each simulated run goes wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fail {
+    /// The runner exits with this code before any output.
+    Exit(u8),
+    /// The runner exits 1 after this many chunks, saying why on stderr.
+    Midstream(usize),
+    /// The runner sends one chunk and then nothing, without exiting.
+    Hang,
+    /// Bytes that are not UTF-8 turn up halfway through the reply.
+    Garbage,
+}
+
+impl Fail {
+    /// Parses `exit:N`, `midstream:K`, `hang`, or `garbage`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (mode, arg) = match text.trim().split_once(':') {
+            Some((mode, arg)) => (mode.trim(), Some(arg.trim())),
+            None => (text.trim(), None),
+        };
+        match (mode, arg) {
+            ("exit", Some(code)) => code
+                .parse::<u8>()
+                .ok()
+                .filter(|code| *code > 0)
+                .map(Fail::Exit)
+                .ok_or_else(|| format!("exit needs a code from 1 to 255, got `{code}`")),
+            ("midstream", Some(count)) => count
+                .parse()
+                .map(Fail::Midstream)
+                .map_err(|_| format!("midstream needs a chunk count, got `{count}`")),
+            ("hang", None) => Ok(Fail::Hang),
+            ("garbage", None) => Ok(Fail::Garbage),
+            ("exit" | "midstream", None) => {
+                Err(format!("`{mode}` needs an argument, e.g. `{mode}:3`"))
+            }
+            ("hang" | "garbage", Some(_)) => Err(format!("`{mode}` takes no argument")),
+            _ => Err(format!("unknown mode `{mode}`")),
+        }
+    }
+}
+
+/// `NOX_SIM_CHUNKING`: what the simulator streams as one token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunking {
+    /// A whole word, with the space before it.
+    Word,
+    /// Two to five characters of a word at a time, at random.
+    Subword,
+    Char,
+    /// This many bytes, cutting through UTF-8 sequences where they fall.
+    Bytes(usize),
+}
+
+impl Chunking {
+    /// Parses `word`, `subword`, `char`, or `bytes:N`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim() {
+            "word" => Ok(Chunking::Word),
+            "subword" => Ok(Chunking::Subword),
+            "char" => Ok(Chunking::Char),
+            other => match other.strip_prefix("bytes:") {
+                Some(size) => size
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|size| *size > 0)
+                    .map(Chunking::Bytes)
+                    .ok_or_else(|| format!("bytes needs a size of at least 1, got `{size}`")),
+                None => Err(format!("unknown mode `{other}`")),
+            },
+        }
+    }
+}
+
+/// The `NOX_SIM_CHUNKING` mode, `word` when unset.
+pub fn chunking(cfg: &Config) -> Result<Chunking, NoxError> {
+    cfg.sim_chunking.as_deref().map_or(Ok(Chunking::Word), |text| {
+        Chunking::parse(text).map_err(|why| {
+            NoxError::Usage(format!(
+                "NOX_SIM_CHUNKING `{text}`: {why} (expected word, subword, char, or bytes:N)"
+            ))
+        })
+    })
+}
+
+/// The `NOX_SIM_FAIL` failure, if any.
+pub fn fail(cfg: &Config) -> Result<Option<Fail>, NoxError> {
+    cfg.sim_fail
+        .as_deref()
+        .map(|text| {
+            Fail::parse(text).map_err(|why| {
+                NoxError::Usage(format!(
+                    "NOX_SIM_FAIL `{text}`: {why} (expected exit:N, midstream:K, hang, or garbage)"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Checks the simulator's settings when the config is loaded.
+pub fn validate(cfg: &Config) -> Result<(), NoxError> {
+    let jitter = cfg.sim_jitter_pct;
+    if !(0.0..=100.0).contains(&jitter) {
+        return Err(NoxError::Usage(format!(
+            "NOX_SIM_JITTER_PCT must be between 0 and 100, got {jitter}"
+        )));
+    }
+    if let Some(tps) = cfg
+        .sim_prefill_tps
+        .filter(|tps| !(*tps > 0.0 && tps.is_finite()))
+    {
+        return Err(NoxError::Usage(format!(
+            "NOX_SIM_PREFILL_TPS must be > 0, got {tps}"
+        )));
+    }
+    stall(cfg)?;
+    fail(cfg)?;
+    chunking(cfg)?;
+    if let Some(path) = &cfg.sim_script {
+        Script::load(path)?;
+    }
+    Ok(())
+}
+
+/// How long the simulated runner spends reading `prompt`: its estimated
+/// tokens at `NOX_SIM_PREFILL_TPS`, or no time when that is unset.
+pub fn prefill(cfg: &Config, prompt: &str) -> Duration {
+    let Some(tps) = cfg.sim_prefill_tps else {
+        return Duration::ZERO;
+    };
+    let tokens = tokens::estimate(prompt, cfg.chars_per_token);
+    Duration::from_secs_f64(tokens as f64 / f64::from(tps))
+}
+
+/// The `NOX_SIM_STALL` stalls, if any.
+pub fn stall(cfg: &Config) -> Result<Option<Stall>, NoxError> {
+    cfg.sim_stall
+        .as_deref()
+        .map(|text| {
+            Stall::parse(text).map_err(|why| {
+                NoxError::Usage(format!(
+                    "NOX_SIM_STALL `{text}`: {why} (e.g. `p=0.02,ms=400`)"
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// SplitMix64: small and seedable. The simulator needs its runs to repeat,
+/// not good randomness.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// What a reply's timing is planned from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pace {
+    /// The wait before the first token whatever the prompt.
+    pub ttft: Duration,
+    /// The wait on top of that for reading the prompt.
+    pub prefill: Duration,
+    /// Tokens per second before jitter and stalls; 0 streams without gaps.
+    pub tps: f32,
+    /// How far each gap may stray from `1 / tps`, either way, in percent.
+    pub jitter_pct: f32,
+    pub stall: Option<Stall>,
+}
+
+impl Pace {
+    /// The configured pace for answering `prompt`.
+    pub fn new(cfg: &Config, prompt: &str) -> Self {
+        Self {
+            ttft: Duration::from_millis(cfg.sim_ttft_ms),
+            prefill: prefill(cfg, prompt),
+            tps: cfg.sim_tps,
+            jitter_pct: cfg.sim_jitter_pct,
+            // Checked when the config was loaded.
+            stall: stall(cfg).ok().flatten(),
+        }
+    }
+}
+
+/// How one simulated reply is paced: the wait before its first token, and
+/// the gap before each one after that.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimPlan {
+    pub ttft: Duration,
+    /// The part of `ttft` spent reading the prompt.
+    pub prefill: Duration,
+    pub gaps: Vec<Duration>,
+}
+
+impl SimPlan {
+    /// Plans `tokens` tokens at `pace`, drawing jitter and stalls from `rng`.
+    /// An empty reply has nothing to wait for.
+    pub fn new(pace: &Pace, tokens: usize, rng: &mut Rng) -> Self {
+        let base = if pace.tps > 0.0 {
+            1.0 / f64::from(pace.tps)
+        } else {
+            0.0
+        };
+        let spread = f64::from(pace.jitter_pct) / 100.0;
+        let gaps = (1..tokens)
+            .map(|_| {
+                let mut secs = base * (1.0 + spread * (2.0 * rng.unit() - 1.0));
+                if let Some(stall) = pace.stall {
+                    if rng.unit() < stall.p {
+                        secs += stall.ms as f64 / 1000.0;
+                    }
+                }
+                Duration::from_secs_f64(secs.max(0.0))
+            })
+            .collect();
+        if tokens == 0 {
+            return Self {
+                ttft: Duration::ZERO,
+                prefill: Duration::ZERO,
+                gaps,
+            };
+        }
+        Self {
+            ttft: pace.ttft + pace.prefill,
+            prefill: pace.prefill,
+            gaps,
+        }
+    }
+}
+
+/// Answers `prompt` the way a runner would, waiting with `sleep`, which a
+/// caller can swap out to run the plan without waiting. The stats carry the
+/// rate actually reached, not the configured one.
+pub fn stream(
+    cfg: &Config,
+    prompt: &str,
+    out: &mut dyn Write,
+    sleep: &mut dyn FnMut(Duration),
+) -> Result<RunStats, NoxError> {
+    // Checked when the config was loaded.
+    let fail = fail(cfg).ok().flatten();
+    if let Some(Fail::Exit(code)) = fail {
+        return Err(exited(code));
+    }
+    let started = Instant::now();
+    if !cfg.raw {
+        writeln!(out, "nox:")?;
+        out.flush()?;
+    }
+
+    let run = RUNS.fetch_add(1, Ordering::SeqCst);
+    // Checked when the config was loaded.
+    let scripted = cfg
+        .sim_script
+        .as_deref()
+        .and_then(|path| Script::load(path).ok())
+        .and_then(|script| script.reply(prompt, run));
+    let mut pace = Pace::new(cfg, prompt);
+    if let Some(reply) = &scripted {
+        pace.ttft = reply.ttft_ms.map_or(pace.ttft, Duration::from_millis);
+        pace.tps = reply.tps.unwrap_or(pace.tps);
+    }
+    let text = scripted
+        .map(|reply| reply.text)
+        .or_else(|| cfg.sim_text.clone())
+        .unwrap_or_else(|| default_text(prompt));
+    let mut rng = Rng::new(seed(cfg).wrapping_add(run as u64));
+    // Checked when the config was loaded.
+    let chunking = chunking(cfg).unwrap_or(Chunking::Word);
+    let mut chunks = split_chunks(&text, chunking, &mut rng);
+    chunks.truncate(cfg.max_tokens as usize);
+    let plan = SimPlan::new(&pace, chunks.len(), &mut rng);
+
+    if !plan.ttft.is_zero() {
+        sleep(plan.ttft);
+    }
+    let ttft = (!chunks.is_empty()).then(|| started.elapsed());
+    let mut sent = 0;
+    for (idx, chunk) in chunks.iter().enumerate() {
+        if let Some(gap) = idx.checked_sub(1).map(|at| plan.gaps[at]) {
+            if !gap.is_zero() {
+                sleep(gap);
+            }
+        }
+        // Set by SIGUSR1 in a `-cancel-signal` serve child; the reply ends
+        // early but cleanly.
+        if signals::cancelled() {
+            break;
+        }
+        if fail == Some(Fail::Midstream(idx)) {
+            return Err(broke_off(idx));
+        }
+        if fail == Some(Fail::Garbage) && idx == chunks.len() / 2 {
+            out.write_all(GARBAGE)?;
+        }
+        out.write_all(chunk)?;
+        out.flush()?;
+        sent += 1;
+        if fail == Some(Fail::Hang) {
+            if let Some(err) = hang(cfg, started, Instant::now(), sleep) {
+                return Err(err);
+            }
+            break;
+        }
+    }
+    if matches!(fail, Some(Fail::Midstream(_))) && sent == chunks.len() {
+        return Err(broke_off(sent));
+    }
+
+    if !cfg.raw {
+        out.write_all(b"\n")?;
+        out.flush()?;
+    }
+    Ok(RunStats {
+        ttft,
+        duration: started.elapsed(),
+        tokens: Some(sent as u64),
+        prefill: ttft.map(|_| plan.prefill),
+    })
+}
+
+/// What a runner exiting with `code` leaves behind.
+fn exited(code: u8) -> NoxError {
+    #[cfg(unix)]
+    let status = {
+        use std::os::unix::process::ExitStatusExt;
+        ExitStatus::from_raw(i32::from(code) << 8)
+    };
+    #[cfg(windows)]
+    let status = {
+        use std::os::windows::process::ExitStatusExt;
+        ExitStatus::from_raw(u32::from(code))
+    };
+    NoxError::RunnerFailed(status)
+}
+
+/// A runner dying after `sent` chunks, as `NOX_SIM_FAIL=midstream` has it.
+fn broke_off(sent: usize) -> NoxError {
+    eprintln!(
+        "simulated runner failure after {sent} chunk{} (NOX_SIM_FAIL)",
+        if sent == 1 { "" } else { "s" }
+    );
+    exited(1)
+}
+
+/// Waits forever, like a runner stuck after its first chunk, until the
+/// limits or an interrupt end the run the way they end a real one. `last`
+/// is when that chunk went out. `None` when a cancel ended the wait.
+fn hang(
+    cfg: &Config,
+    started: Instant,
+    last: Instant,
+    sleep: &mut dyn FnMut(Duration),
+) -> Option<NoxError> {
+    let limits = cfg.limits();
+    loop {
+        if signals::interrupts() > 0 {
+            return Some(NoxError::Interrupted);
+        }
+        if signals::cancelled() {
+            return None;
+        }
+        let now = Instant::now();
+        let ended = [
+            limits
+                .total
+                .filter(|total| now >= started + *total)
+                .map(PumpEnd::TimedOut),
+            limits
+                .stall
+                .filter(|stall| now >= last + *stall)
+                .map(PumpEnd::Stalled),
+        ];
+        if let Some(reason) = ended.into_iter().flatten().find_map(|end| end.reason()) {
+            return Some(NoxError::Timeout(reason));
+        }
+        sleep(POLL);
+    }
+}
+
+/// `NOX_SIM_SEED`, or the clock when it is unset so unseeded runs differ.
+fn seed(cfg: &Config) -> u64 {
+    cfg.sim_seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    })
+}
+
+fn default_text(prompt: &str) -> String {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        "simulated A1000 mode. streaming output to validate the pipeline. ".to_string()
+    } else {
+        format!(
+            "simulated A1000 mode. prompt: {}. streaming output to validate the pipeline. ",
+            prompt
+        )
+    }
+}
+
+/// Cuts `text` into the chunks streamed as tokens. Words are joined by
+/// single spaces first, so every mode streams the same bytes as `word`,
+/// only cut differently. `rng` is only drawn from for `subword`.
+pub fn split_chunks(text: &str, chunking: Chunking, rng: &mut Rng) -> Vec<Vec<u8>> {
+    let mut words = Vec::new();
+    for (idx, word) in text.split_whitespace().enumerate() {
+        if idx == 0 {
+            words.push(word.to_string());
+        } else {
+            words.push(format!(" {}", word));
+        }
+    }
+    if words.is_empty() && !text.is_empty() {
+        words.push(text.to_string());
+    }
+    match chunking {
+        Chunking::Word => words.into_iter().map(String::into_bytes).collect(),
+        Chunking::Subword => {
+            let mut chunks = Vec::new();
+            for word in &words {
+                let chars: Vec<char> = word.chars().collect();
+                // The space before a word rides along with its first piece.
+                let mut at = usize::from(chars.first() == Some(&' '));
+                let mut start = 0;
+                while at < chars.len() {
+                    let left = chars.len() - at;
+                    let mut take = (2 + (rng.next_u64() % 4) as usize).min(left);
+                    // Never leave a single character for a piece of its own.
+                    if left - take == 1 {
+                        take = if take < 5 { take + 1 } else { take - 1 };
+                    }
+                    at += take;
+                    chunks.push(chars[start..at].iter().collect::<String>().into_bytes());
+                    start = at;
+                }
+                // A lone space has nothing to cut.
+                if start < chars.len() {
+                    chunks.push(word.as_bytes().to_vec());
+                }
+            }
+            chunks
+        }
+        Chunking::Char => words
+            .concat()
+            .chars()
+            .map(|c| c.to_string().into_bytes())
+            .collect(),
+        Chunking::Bytes(size) => words
+            .concat()
+            .as_bytes()
+            .chunks(size)
+            .map(<[u8]>::to_vec)
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(n: u64) -> Duration {
+        Duration::from_millis(n)
+    }
+
+    fn pace(tps: f32, jitter_pct: f32, stall: Option<Stall>) -> Pace {
+        Pace {
+            ttft: ms(120),
+            prefill: Duration::ZERO,
+            tps,
+            jitter_pct,
+            stall,
+        }
+    }
+
+    #[test]
+    fn stalls_parse_in_either_order() {
+        let stall = Stall { p: 0.02, ms: 400 };
+        assert_eq!(Stall::parse("p=0.02,ms=400"), Ok(stall));
+        assert_eq!(Stall::parse(" ms = 400 , p = 0.02 "), Ok(stall));
+        assert_eq!(
+            Stall::parse("p=1.5,ms=400").unwrap_err(),
+            "p must be between 0 and 1, got `1.5`"
+        );
+        assert_eq!(
+            Stall::parse("p=0.1,ms=-4").unwrap_err(),
+            "ms must be a whole number, got `-4`"
+        );
+        assert_eq!(
+            Stall::parse("p=0.1").unwrap_err(),
+            "both p and ms are needed"
+        );
+        assert_eq!(
+            Stall::parse("p=0.1,ms=4,q=1").unwrap_err(),
+            "unknown key `q`"
+        );
+        assert_eq!(Stall::parse("p0.1").unwrap_err(), "`p0.1` is not key=value");
+    }
+
+    #[test]
+    fn a_steady_plan_has_even_gaps() {
+        let plan = SimPlan::new(&pace(20.0, 0.0, None), 5, &mut Rng::new(1));
+        assert_eq!(plan.ttft, ms(120));
+        assert_eq!(plan.gaps, [ms(50); 4]);
+        let empty = SimPlan::new(&pace(20.0, 0.0, None), 0, &mut Rng::new(1));
+        assert_eq!(empty.ttft, Duration::ZERO);
+        assert!(empty.gaps.is_empty());
+        let flat_out = SimPlan::new(&pace(0.0, 50.0, None), 3, &mut Rng::new(1));
+        assert_eq!(flat_out.gaps, [Duration::ZERO; 2]);
+    }
+
+    #[test]
+    fn jitter_stays_in_bounds_and_repeats_for_a_seed() {
+        let pace = pace(10.0, 20.0, None);
+        let plan = SimPlan::new(&pace, 500, &mut Rng::new(42));
+        assert!(plan.gaps.iter().all(|gap| (ms(80)..=ms(120)).contains(gap)));
+        assert!(plan.gaps.iter().any(|gap| *gap < ms(90)));
+        assert!(plan.gaps.iter().any(|gap| *gap > ms(110)));
+        assert_eq!(plan, SimPlan::new(&pace, 500, &mut Rng::new(42)));
+        assert_ne!(plan, SimPlan::new(&pace, 500, &mut Rng::new(43)));
+    }
+
+    #[test]
+    fn stalls_come_about_as_often_as_asked() {
+        let stall = Stall { p: 0.25, ms: 400 };
+        let plan = SimPlan::new(&pace(100.0, 0.0, Some(stall)), 4001, &mut Rng::new(7));
+        let stalled = plan.gaps.iter().filter(|gap| **gap == ms(410)).count();
+        let plain = plan.gaps.iter().filter(|gap| **gap == ms(10)).count();
+        assert_eq!(stalled + plain, 4000);
+        assert!((850..1150).contains(&stalled), "{stalled} stalls");
+    }
+
+    #[test]
+    fn the_stream_sleeps_through_its_plan() {
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_TEXT", "one two three four five"),
+            ("NOX_SIM_TTFT_MS", "120"),
+            ("NOX_SIM_TPS", "20"),
+            ("NOX_SIM_SEED", "3"),
+        ]);
+        let mut slept = Vec::new();
+        let mut out = Vec::new();
+        let stats = stream(&cfg, "hi", &mut out, &mut |gap| slept.push(gap)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "nox:\none two three four five\n"
+        );
+        assert_eq!(slept, [ms(120), ms(50), ms(50), ms(50), ms(50)]);
+        assert_eq!(stats.tokens, Some(5));
+        assert_eq!(stats.prefill, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn stats_report_the_rate_reached() {
+        // Every gap stalls, so the stream runs at half the configured 40/s.
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_TEXT", "a b c d e"),
+            ("NOX_SIM_TTFT_MS", "0"),
+            ("NOX_SIM_TPS", "40"),
+            ("NOX_SIM_STALL", "p=1,ms=25"),
+            ("NOX_RAW", "1"),
+        ]);
+        let mut out = Vec::new();
+        let stats = stream(&cfg, "hi", &mut out, &mut std::thread::sleep).unwrap();
+        assert_eq!(out, b"a b c d e");
+        let tps = stats.tps().unwrap();
+        assert!((15.0..=20.5).contains(&tps), "{tps} tok/s");
+    }
+
+    #[test]
+    fn a_long_prompt_takes_longer_to_first_token() {
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_TTFT_MS", "120"),
+            ("NOX_SIM_PREFILL_TPS", "1000"),
+            ("NOX_CHARS_PER_TOKEN", "4"),
+        ]);
+        let short = "What is the capital of France?";
+        let long = "word ".repeat(3200);
+        assert_eq!(prefill(&cfg, short), ms(8));
+        assert_eq!(prefill(&cfg, &long), ms(4000));
+
+        let mut rng = Rng::new(1);
+        let quick = SimPlan::new(&Pace::new(&cfg, short), 3, &mut rng);
+        let slow = SimPlan::new(&Pace::new(&cfg, &long), 3, &mut rng);
+        assert_eq!((quick.ttft, quick.prefill), (ms(128), ms(8)));
+        assert_eq!((slow.ttft, slow.prefill), (ms(4120), ms(4000)));
+
+        let unset = crate::tests::config(&[("NOX_SIM_TTFT_MS", "120")]);
+        assert_eq!(prefill(&unset, &long), Duration::ZERO);
+        assert_eq!(
+            SimPlan::new(&Pace::new(&unset, &long), 3, &mut rng).ttft,
+            ms(120)
+        );
+    }
+
+    #[test]
+    fn the_stream_waits_out_the_prefill() {
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_TEXT", "ok"),
+            ("NOX_SIM_TTFT_MS", "50"),
+            ("NOX_SIM_PREFILL_TPS", "100"),
+            ("NOX_CHARS_PER_TOKEN", "4"),
+        ]);
+        let mut slept = Vec::new();
+        let prompt = "x".repeat(400);
+        let stats = stream(&cfg, &prompt, &mut Vec::new(), &mut |gap| slept.push(gap)).unwrap();
+        assert_eq!(slept, [ms(1050)]);
+        assert_eq!(stats.prefill, Some(ms(1000)));
+    }
+
+    #[test]
+    fn failure_modes_parse() {
+        assert_eq!(Fail::parse("exit:4"), Ok(Fail::Exit(4)));
+        assert_eq!(Fail::parse(" midstream : 3 "), Ok(Fail::Midstream(3)));
+        assert_eq!(Fail::parse("hang"), Ok(Fail::Hang));
+        assert_eq!(Fail::parse("garbage"), Ok(Fail::Garbage));
+        let errors = [
+            ("exit:0", "exit needs a code from 1 to 255, got `0`"),
+            ("exit:256", "exit needs a code from 1 to 255, got `256`"),
+            ("midstream:x", "midstream needs a chunk count, got `x`"),
+            ("exit", "`exit` needs an argument, e.g. `exit:3`"),
+            ("hang:5", "`hang` takes no argument"),
+            ("explode", "unknown mode `explode`"),
+        ];
+        for (text, want) in errors {
+            assert_eq!(Fail::parse(text).unwrap_err(), want);
+        }
+    }
+
+    #[test]
+    fn midstream_past_the_end_still_fails() {
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_TEXT", "a b"),
+            ("NOX_SIM_TTFT_MS", "0"),
+            ("NOX_SIM_FAIL", "midstream:9"),
+            ("NOX_RAW", "1"),
+        ]);
+        let mut out = Vec::new();
+        let err = stream(&cfg, "hi", &mut out, &mut |_| {}).unwrap_err();
+        assert_eq!(out, b"a b");
+        assert_eq!(err.exit_code(), 6);
+    }
+
+    const SAMPLES: [&str; 4] = [
+        "simulated A1000 mode. streaming output to validate the pipeline.",
+        "naïve café — déjà vu 🦀 日本語のテキスト",
+        "x",
+        "",
+    ];
+
+    fn joined(chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunks.concat()
+    }
+
+    #[test]
+    fn every_mode_streams_the_same_text() {
+        let modes = [
+            Chunking::Word,
+            Chunking::Subword,
+            Chunking::Char,
+            Chunking::Bytes(1),
+            Chunking::Bytes(3),
+            Chunking::Bytes(64),
+        ];
+        for seed in 0..20 {
+            for text in SAMPLES {
+                for mode in modes {
+                    let chunks = split_chunks(text, mode, &mut Rng::new(seed));
+                    assert_eq!(joined(&chunks), text.as_bytes(), "{mode:?} on {text:?}");
+                    assert!(chunks.iter().all(|chunk| !chunk.is_empty()));
+                }
+            }
+        }
+        // Runs of whitespace come out as single spaces in every mode.
+        let chunks = split_chunks("  a \n b\t", Chunking::Char, &mut Rng::new(0));
+        assert_eq!(joined(&chunks), b"a b");
+    }
+
+    #[test]
+    fn word_and_char_cut_where_expected() {
+        let mut rng = Rng::new(0);
+        let words = split_chunks("one two  three", Chunking::Word, &mut rng);
+        assert_eq!(words, [&b"one"[..], b" two", b" three"]);
+        let chars = split_chunks("añ 🦀", Chunking::Char, &mut rng);
+        let chars: Vec<_> = chars
+            .iter()
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect();
+        assert_eq!(chars, ["a", "ñ", " ", "🦀"]);
+    }
+
+    #[test]
+    fn subwords_are_two_to_five_characters_and_seeded() {
+        let text = "internationalization of streaming tokenizers";
+        let chunks = split_chunks(text, Chunking::Subword, &mut Rng::new(9));
+        for chunk in &chunks {
+            let piece = std::str::from_utf8(chunk).unwrap();
+            let len = piece.trim_start_matches(' ').chars().count();
+            assert!((2..=5).contains(&len), "{piece:?}");
+        }
+        assert!(chunks.len() > text.split_whitespace().count());
+        assert_eq!(
+            chunks,
+            split_chunks(text, Chunking::Subword, &mut Rng::new(9))
+        );
+        assert_ne!(
+            chunks,
+            split_chunks(text, Chunking::Subword, &mut Rng::new(10))
+        );
+    }
+
+    #[test]
+    fn byte_chunks_split_multibyte_text() {
+        let text = "déjà vu 🦀";
+        let chunks = split_chunks(text, Chunking::Bytes(2), &mut Rng::new(0));
+        assert!(chunks.iter().all(|chunk| chunk.len() <= 2));
+        let broken = chunks
+            .iter()
+            .filter(|chunk| std::str::from_utf8(chunk).is_err())
+            .count();
+        assert!(broken >= 2, "{broken} chunks are not UTF-8 alone");
+        assert_eq!(String::from_utf8(joined(&chunks)).unwrap(), text);
+    }
+
+    #[test]
+    fn chunking_modes_parse() {
+        assert_eq!(Chunking::parse("word"), Ok(Chunking::Word));
+        assert_eq!(Chunking::parse(" subword "), Ok(Chunking::Subword));
+        assert_eq!(Chunking::parse("char"), Ok(Chunking::Char));
+        assert_eq!(Chunking::parse("bytes:3"), Ok(Chunking::Bytes(3)));
+        assert_eq!(
+            Chunking::parse("bytes:0").unwrap_err(),
+            "bytes needs a size of at least 1, got `0`"
+        );
+        assert_eq!(Chunking::parse("bpe").unwrap_err(), "unknown mode `bpe`");
+        let unset = crate::tests::config(&[]);
+        assert_eq!(chunking(&unset).unwrap(), Chunking::Word);
+    }
+}
@@ -0,0 +1,333 @@
+//! `NOX_SIM_SCRIPT=FILE`: what the simulator says. A plain file has one
+//! response per line, taken in turn by each simulated run (the last one
+//! repeats). A playbook instead answers by what the prompt says: records of
+//! `key: value` lines, separated by blank lines, are tried top to bottom
+//! and the first whose `match` the prompt contains answers it:
+//!
+//! ```text
+//! # Lines starting with # are comments.
+//! match: weather
+//! response: Sunny all week.
+//! ttft_ms: 40
+//! tps: 12
+//!
+//! match: /^(hi|hello)/
+//! response: Hello!
+//!
+//! response: I only know about the weather.
+//! ```
+//!
+//! `match` is a substring, or a pattern between slashes; a last record
+//! without one answers everything else. `ttft_ms` and `tps` stand in for
+//! `NOX_SIM_TTFT_MS` and `NOX_SIM_TOKENS_PER_SEC` for that record alone.
+//! A file is a playbook when its first entry is a `match:` or `response:`
+//! line.
+
+use std::fs;
+use std::path::Path;
+
+use noxroute_core::Regex;
+
+use crate::error::NoxError;
+
+const KEYS: [&str; 4] = ["match", "response", "ttft_ms", "tps"];
+
+#[derive(Debug, Clone)]
+pub enum Script {
+    Lines(Vec<String>),
+    Playbook(Vec<Record>),
+}
+
+/// One playbook record.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// `None` for the last record, which answers whatever is left.
+    pub pattern: Option<Pattern>,
+    pub response: String,
+    pub ttft_ms: Option<u64>,
+    pub tps: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn parse(text: &str) -> Result<Self, String> {
+        match text
+            .strip_prefix('/')
+            .and_then(|rest| rest.strip_suffix('/'))
+        {
+            Some(pattern) if !pattern.is_empty() => Regex::new(pattern)
+                .map(Pattern::Regex)
+                .map_err(|err| format!("bad pattern `{pattern}`: {err}")),
+            _ if text.is_empty() => Err("match is empty".to_string()),
+            _ => Ok(Pattern::Substring(text.to_string())),
+        }
+    }
+
+    fn matches(&self, prompt: &str) -> bool {
+        match self {
+            Pattern::Substring(text) => prompt.contains(text.as_str()),
+            Pattern::Regex(regex) => regex.find_at(prompt, 0).is_some(),
+        }
+    }
+}
+
+/// A response picked for a prompt, with its record's timing if it has any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub text: String,
+    pub ttft_ms: Option<u64>,
+    pub tps: Option<f32>,
+}
+
+impl Script {
+    /// Reads and parses the script at `path`.
+    pub fn load(path: &Path) -> Result<Self, NoxError> {
+        let fail =
+            |why: String| NoxError::Usage(format!("NOX_SIM_SCRIPT {}: {why}", path.display()));
+        let text = fs::read_to_string(path).map_err(|err| fail(err.to_string()))?;
+        Self::parse(&text).map_err(fail)
+    }
+
+    /// Parses a script; errors name the line at fault.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let first = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'));
+        let playbook = first
+            .and_then(|line| line.split_once(':'))
+            .is_some_and(|(key, _)| matches!(key.trim(), "match" | "response"));
+        if !playbook {
+            return Ok(Script::Lines(
+                text.lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ));
+        }
+        let mut records = Vec::new();
+        let mut fields: Vec<(usize, &str, &str)> = Vec::new();
+        for (idx, line) in text.lines().enumerate().chain([(usize::MAX, "")]) {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.is_empty() {
+                if !fields.is_empty() {
+                    records.push(record(&fields)?);
+                    fields.clear();
+                }
+                continue;
+            }
+            let number = idx + 1;
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("line {number}: expected `key: value`"))?;
+            let key = key.trim();
+            if !KEYS.contains(&key) {
+                return Err(format!(
+                    "line {number}: unknown key `{key}` (expected {})",
+                    KEYS.join(", ")
+                ));
+            }
+            if fields.iter().any(|(_, seen, _)| *seen == key) {
+                return Err(format!("line {number}: `{key}` given twice in one record"));
+            }
+            fields.push((number, key, value.trim()));
+        }
+        if let Some(at) = records[..records.len().saturating_sub(1)]
+            .iter()
+            .position(|(_, record)| record.pattern.is_none())
+        {
+            return Err(format!(
+                "line {}: only the last record may leave out `match`",
+                records[at].0
+            ));
+        }
+        Ok(Script::Playbook(
+            records.into_iter().map(|(_, record)| record).collect(),
+        ))
+    }
+
+    /// What the simulator says to `prompt` on its `run`th run, if the script
+    /// has anything for it.
+    pub fn reply(&self, prompt: &str, run: usize) -> Option<Reply> {
+        match self {
+            Script::Lines(lines) => lines
+                .get(run.min(lines.len().saturating_sub(1)))
+                .map(|text| Reply {
+                    text: text.clone(),
+                    ttft_ms: None,
+                    tps: None,
+                }),
+            Script::Playbook(records) => records
+                .iter()
+                .find(|record| record.pattern.as_ref().is_none_or(|p| p.matches(prompt)))
+                .map(|record| Reply {
+                    text: record.response.clone(),
+                    ttft_ms: record.ttft_ms,
+                    tps: record.tps,
+                }),
+        }
+    }
+}
+
+/// Builds a record from its `(line, key, value)` fields, paired with the
+/// line it starts on.
+fn record(fields: &[(usize, &str, &str)]) -> Result<(usize, Record), String> {
+    let start = fields[0].0;
+    let mut record = Record {
+        pattern: None,
+        response: String::new(),
+        ttft_ms: None,
+        tps: None,
+    };
+    let mut response = false;
+    for &(number, key, value) in fields {
+        let at = |why: String| format!("line {number}: {why}");
+        match key {
+            "match" => record.pattern = Some(Pattern::parse(value).map_err(at)?),
+            "response" => {
+                record.response = value.to_string();
+                response = true;
+            }
+            "ttft_ms" => {
+                record.ttft_ms =
+                    Some(value.parse().map_err(|_| {
+                        at(format!("ttft_ms must be a whole number, got `{value}`"))
+                    })?)
+            }
+            _ => {
+                record.tps = Some(
+                    value
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|tps| *tps >= 0.0 && tps.is_finite())
+                        .ok_or_else(|| at(format!("tps must be a number >= 0, got `{value}`")))?,
+                )
+            }
+        }
+    }
+    if !response {
+        return Err(format!("line {start}: record has no `response`"));
+    }
+    Ok((start, record))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const PLAYBOOK: &str = "\
+# Lines starting with # are comments.
+match: weather
+response: Sunny all week.
+ttft_ms: 40
+tps: 12
+
+match: /^(hi|hello)/
+response: Hello!
+
+response: I only know about the weather.
+";
+
+    fn reply(script: &Script, prompt: &str) -> (String, Option<u64>, Option<f32>) {
+        let reply = script.reply(prompt, 0).unwrap();
+        (reply.text, reply.ttft_ms, reply.tps)
+    }
+
+    #[test]
+    fn the_first_matching_record_answers() {
+        let script = Script::parse(PLAYBOOK).unwrap();
+        let sunny = ("Sunny all week.".to_string(), Some(40), Some(12.0));
+        assert_eq!(reply(&script, "what's the weather?"), sunny);
+        // Both of the first two match; the earlier one wins.
+        assert_eq!(reply(&script, "hi, how's the weather?"), sunny);
+        assert_eq!(
+            reply(&script, "hello there"),
+            ("Hello!".to_string(), None, None)
+        );
+        // The pattern is anchored, so this falls through to the default.
+        assert_eq!(
+            reply(&script, "oh hi"),
+            ("I only know about the weather.".to_string(), None, None)
+        );
+    }
+
+    #[test]
+    fn without_a_default_unmatched_prompts_get_nothing() {
+        let script = Script::parse("match: weather\nresponse: Sunny.\n").unwrap();
+        assert_eq!(script.reply("what time is it?", 0), None);
+    }
+
+    #[test]
+    fn plain_lines_are_taken_in_turn() {
+        let script = Script::parse("first\n\nsecond\n").unwrap();
+        let text = |run| script.reply("anything", run).unwrap().text;
+        assert_eq!([text(0), text(1), text(2)], ["first", "second", "second"]);
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let cases = [
+            (
+                "match: a\nresponse: b\nspeed: 3\n",
+                "line 3: unknown key `speed`",
+            ),
+            ("match: a\nresponse b\n", "line 2: expected `key: value`"),
+            (
+                "match: a\nresponse: b\nmatch: c\n",
+                "line 3: `match` given twice",
+            ),
+            (
+                "match: a\nresponse: b\nttft_ms: soon\n",
+                "line 3: ttft_ms must be",
+            ),
+            ("match: a\nresponse: b\ntps: -1\n", "line 3: tps must be"),
+            (
+                "# hi\n\nmatch: a\nttft_ms: 4\n",
+                "line 3: record has no `response`",
+            ),
+            (
+                "response: a\n\nmatch: b\nresponse: c\n",
+                "line 1: only the last record",
+            ),
+            ("match: /(unclosed/\nresponse: a\n", "line 1: bad pattern"),
+        ];
+        for (text, want) in cases {
+            let err = Script::parse(text).unwrap_err();
+            assert!(err.starts_with(want), "{err:?} for {text:?}");
+        }
+    }
+
+    #[test]
+    fn a_matched_record_sets_the_pace() {
+        let path = std::env::temp_dir().join(format!("nox-playbook-{}.txt", std::process::id()));
+        fs::write(&path, PLAYBOOK).unwrap();
+        let cfg = crate::tests::config(&[
+            ("NOX_SIM_SCRIPT", path.to_str().unwrap()),
+            ("NOX_SIM_TTFT_MS", "500"),
+            ("NOX_SIM_TPS", "100"),
+            ("NOX_RAW", "1"),
+        ]);
+        let run = |prompt: &str| {
+            let (mut out, mut slept) = (Vec::new(), Vec::new());
+            crate::sim::stream(&cfg, prompt, &mut out, &mut |gap| slept.push(gap)).unwrap();
+            (String::from_utf8(out).unwrap(), slept)
+        };
+        let (text, slept) = run("weather?");
+        assert_eq!(text, "Sunny all week.");
+        assert_eq!(slept[0], Duration::from_millis(40));
+        assert_eq!(slept[1..], [Duration::from_secs_f64(1.0 / 12.0); 2]);
+        let (text, slept) = run("hello");
+        assert_eq!(text, "Hello!");
+        assert_eq!(slept, [Duration::from_millis(500)]);
+        fs::remove_file(&path).unwrap();
+    }
+}
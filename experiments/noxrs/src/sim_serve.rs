@@ -0,0 +1,184 @@
+//! The simulator as a serve child: with `NOX_EMULATE_A1000=1`, persistent
+//! modes start this binary as `nox --sim-serve -serve ...` where they would
+//! start `noxlocal -serve`, so framing, restarts, cancelling, and the idle
+//! timeout run the same code against it. It speaks the runner's side of
+//! [`crate::serve`]: each request (a line, or 0x1e-terminated with
+//! `-serve-rs`) is answered by [`sim::stream`] with the usual knobs, then
+//! the end marker. Control requests are answered at once. It takes
+//! `-serve-params` headers, though only `max-tokens` means anything to it,
+//! and with `-cancel-signal` SIGUSR1 ends the reply in flight. A
+//! `NOX_SIM_FAIL` failure ends the process the way it would end a runner;
+//! a hung reply is left for the parent's watchdog.
+
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
+
+use crate::error::NoxError;
+use crate::sampling::Overrides;
+use crate::serve::{CONTROL, LINE_END, PARAMS, RECORD_SEP, REJECTED};
+use crate::signals;
+use crate::sim;
+use crate::Config;
+
+/// The first argument that makes nox a serve child.
+pub const FLAG: &str = "--sim-serve";
+
+/// What the capability probe reads.
+const USAGE: &str = "\
+usage: nox --sim-serve -serve [flags]
+
+The A1000 simulator standing in for `noxlocal -serve`; persistent modes
+start it themselves when NOX_EMULATE_A1000=1.
+
+flags:
+  -serve-rs        end requests and replies with 0x1e, not a newline and marker
+  -serve-params    take per-request settings in a 0x06 header
+  -cancel-signal   end the reply in flight on SIGUSR1
+  -raw             leave out the `nox:` prefix
+  -max-tokens N    stop each reply after N tokens
+
+Other runner flags are accepted and ignored.";
+
+/// Whether `runner` is this binary, standing in for one.
+pub fn is_self(runner: &Path) -> bool {
+    env::current_exe().is_ok_and(|exe| exe == runner)
+}
+
+/// Answers requests on stdin until it ends. `args` are the runner flags
+/// after [`FLAG`].
+pub fn run(mut cfg: Config, args: &[String]) -> Result<(), NoxError> {
+    let has = |flag: &str| args.iter().any(|arg| arg == flag);
+    if has("-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let rs = has("-serve-rs");
+    cfg.raw = has("-raw");
+    if let Some(max) = args
+        .iter()
+        .position(|arg| arg == "-max-tokens")
+        .and_then(|at| args.get(at + 1)?.parse().ok())
+    {
+        cfg.max_tokens = max;
+    }
+    // A hung reply hangs; the parent's NOX_STALL_SECS is what ends it.
+    cfg.timeout_secs = None;
+    cfg.stall_secs = None;
+    if has("-cancel-signal") {
+        signals::install_cancel();
+    }
+
+    let (end, marker): (u8, &[u8]) = if rs {
+        (RECORD_SEP, &[RECORD_SEP])
+    } else {
+        (b'\n', LINE_END)
+    };
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut request = Vec::new();
+    loop {
+        request.clear();
+        if stdin.read_until(end, &mut request)? == 0 {
+            return Ok(());
+        }
+        if request.last() == Some(&end) {
+            request.pop();
+        }
+        let (overrides, body) = match split_params(&request) {
+            Ok(split) => split,
+            Err(why) => {
+                stdout.write_all(&[REJECTED])?;
+                write!(stdout, "request params: {why}")?;
+                stdout.write_all(marker)?;
+                stdout.flush()?;
+                continue;
+            }
+        };
+        if body.first() != Some(&CONTROL) {
+            let prompt = String::from_utf8_lossy(body);
+            // Blank records are not prompts; the parent expects no reply.
+            if prompt.trim().is_empty() {
+                continue;
+            }
+            let mut turn = cfg.clone();
+            turn.max_tokens = overrides.max_tokens.unwrap_or(cfg.max_tokens);
+            signals::clear_cancel();
+            match sim::stream(&turn, &prompt, &mut stdout, &mut thread::sleep) {
+                Ok(_) => {}
+                Err(NoxError::RunnerFailed(status)) => {
+                    let _ = stdout.flush();
+                    process::exit(status.code().unwrap_or(1));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        stdout.write_all(marker)?;
+        stdout.flush()?;
+    }
+}
+
+/// Splits a `\x06temp=0.7 max-tokens=5\x06prompt` request into its
+/// settings and the rest. A header that does not parse, or asks for an
+/// unknown or out-of-range setting, is refused like noxlocal refuses it.
+fn split_params(request: &[u8]) -> Result<(Overrides, &[u8]), String> {
+    let mut overrides = Overrides::default();
+    let Some(rest) = request.strip_prefix(&[PARAMS]) else {
+        return Ok((overrides, request));
+    };
+    let close = rest
+        .iter()
+        .position(|b| *b == PARAMS)
+        .ok_or("unterminated params header")?;
+    for setting in String::from_utf8_lossy(&rest[..close]).split_whitespace() {
+        let (name, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("malformed setting `{setting}`"))?;
+        let value = value
+            .parse()
+            .map_err(|_| format!("{name} must be a number, got `{value}`"))?;
+        overrides.set(name, value)?;
+    }
+    Ok((overrides, &rest[close + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_params_header_is_split_off() {
+        let (overrides, body) = split_params(b"\x06temp=0.7 max-tokens=5\x06hello").unwrap();
+        assert_eq!(overrides.temp, Some(0.7));
+        assert_eq!(overrides.max_tokens, Some(5));
+        assert_eq!(body, b"hello");
+    }
+
+    #[test]
+    fn bad_settings_refuse_the_request() {
+        for (request, why) in [
+            (
+                &b"\x06max_tokens=0\x06hi"[..],
+                "max_tokens must be a whole number >= 1, got 0",
+            ),
+            (b"\x06top_k=x\x06hi", "top_k must be a number, got `x`"),
+            (b"\x06speed=9\x06hi", "unknown setting `speed`"),
+            (b"\x06temp\x06hi", "malformed setting `temp`"),
+            (b"\x06temp=-1\x06hi", "temp must be >= 0, got -1"),
+            (b"\x06max_tokens=5 hello", "unterminated params header"),
+        ] {
+            assert_eq!(split_params(request).unwrap_err(), why);
+        }
+    }
+
+    #[test]
+    fn a_request_without_a_header_is_left_alone() {
+        for request in [&b"hello"[..], b"\x05ping", b""] {
+            let (overrides, body) = split_params(request).unwrap();
+            assert!(overrides.is_empty());
+            assert_eq!(body, request);
+        }
+    }
+}
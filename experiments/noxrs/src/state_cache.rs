@@ -0,0 +1,238 @@
+//! Automatic prompt-cache state for `noxlocal` (`NOX_STATE_AUTO=1`). The
+//! prompt's prefix (everything up to its last blank line: system prompt,
+//! instructions, few-shot examples) is evaluated once and saved with
+//! `-state-save` under `$XDG_CACHE_HOME/nox/state/<hash>.bin`; later runs
+//! with the same prefix pass `-state-load` and only the remaining text, so
+//! the prefix is never re-evaluated. `NOX_STATE_MAX_MB` caps the directory,
+//! evicting the least recently used files first.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::NoxError;
+use crate::{Config, RunStats, RunnerStyle};
+
+/// Runs `prompt` like [`crate::generate`], going through the state cache
+/// when it applies.
+pub fn generate(cfg: &Config, prompt: &str, out: &mut dyn Write) -> Result<RunStats, NoxError> {
+    let Some(plan) = plan(cfg, prompt)? else {
+        return crate::generate(cfg, prompt, out);
+    };
+    if plan.path.is_file() {
+        cfg.debug(format!("state cache hit: {}", plan.path.display()));
+        touch(&plan.path);
+    } else {
+        cfg.debug(format!("state cache miss: saving {}", plan.path.display()));
+        if let Err(err) = prime(cfg, &plan) {
+            eprintln!("nox: could not save prompt state ({err}); running without it");
+            let _ = fs::remove_file(&plan.path);
+            return crate::generate(cfg, prompt, out);
+        }
+        evict(&plan.dir, cfg.state_max_mb, &plan.path);
+    }
+
+    let mut load_cfg = cfg.clone();
+    load_cfg.state_load = Some(plan.path.clone());
+    let mut counted = Counted { out, written: 0 };
+    match crate::generate(&load_cfg, plan.suffix, &mut counted) {
+        // A runner that dies before printing anything has rejected the state
+        // file (corrupt, or saved by another runner build).
+        Err(NoxError::RunnerFailed(status)) if counted.written == 0 => {
+            eprintln!(
+                "nox: runner rejected {} ({status}); deleting it and retrying without it",
+                plan.path.display()
+            );
+            let _ = fs::remove_file(&plan.path);
+            crate::generate(cfg, prompt, counted.out)
+        }
+        other => other,
+    }
+}
+
+struct Plan<'a> {
+    dir: PathBuf,
+    path: PathBuf,
+    prefix: &'a str,
+    suffix: &'a str,
+}
+
+fn plan<'a>(cfg: &Config, prompt: &'a str) -> Result<Option<Plan<'a>>, NoxError> {
+    if !cfg.state_auto || cfg.emulate_a1000 || !matches!(cfg.runner_style, RunnerStyle::NoxLocal) {
+        return Ok(None);
+    }
+    if cfg.state_load.is_some() || cfg.state_save.is_some() {
+        cfg.debug("NOX_STATE_LOAD/NOX_STATE_SAVE set; NOX_STATE_AUTO is ignored".to_string());
+        return Ok(None);
+    }
+    let Some((prefix, suffix)) = split(prompt) else {
+        cfg.debug("state cache: prompt has no blank-line prefix to cache".to_string());
+        return Ok(None);
+    };
+    let Some(dir) = state_dir() else {
+        return Ok(None);
+    };
+    if let Err(err) = fs::create_dir_all(&dir) {
+        eprintln!(
+            "nox: cannot create {} ({err}); NOX_STATE_AUTO disabled",
+            dir.display()
+        );
+        return Ok(None);
+    }
+    let model = cfg.resolve_model()?.unwrap_or_default();
+    let key = format!("{model}\0{}\0{prefix}", cfg.ctx);
+    let path = dir.join(format!("{:016x}.bin", fnv1a(key.as_bytes())));
+    Ok(Some(Plan {
+        dir,
+        path,
+        prefix,
+        suffix,
+    }))
+}
+
+/// Splits at the last blank line: the prefix keeps its trailing newlines so
+/// the suffix tokenizes as a continuation.
+fn split(prompt: &str) -> Option<(&str, &str)> {
+    let at = prompt.trim_end().rfind("\n\n")? + 2;
+    let (prefix, suffix) = prompt.split_at(at);
+    if prefix.trim().is_empty() || suffix.trim().is_empty() {
+        return None;
+    }
+    Some((prefix, suffix))
+}
+
+/// Evaluates the prefix alone and saves the resulting state. No tokens are
+/// generated; the runner saves right after prefill.
+fn prime(cfg: &Config, plan: &Plan) -> Result<(), NoxError> {
+    let mut prime_cfg = cfg.clone();
+    prime_cfg.state_save = Some(plan.path.clone());
+    prime_cfg.max_tokens = 0;
+    prime_cfg.raw = true;
+    prime_cfg.log_file = None;
+    crate::generate(&prime_cfg, plan.prefix, &mut io::sink())?;
+    if !plan.path.is_file() {
+        return Err(NoxError::Io(io::Error::other("runner wrote no state file")));
+    }
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/nox/state`, falling back to `~/.cache`.
+fn state_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(base.join("nox").join("state"))
+}
+
+/// Marks a state file as recently used.
+fn touch(path: &Path) {
+    if let Ok(file) = fs::File::options().write(true).open(path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Deletes the least recently used state files until the directory fits in
+/// `max_mb`, never removing `keep`.
+fn evict(dir: &Path, max_mb: u64, keep: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "bin"))
+        .filter_map(|e| {
+            let meta = e.metadata().ok()?;
+            Some((meta.modified().ok()?, meta.len(), e.path()))
+        })
+        .collect();
+    files.sort();
+    let budget = max_mb.saturating_mul(1024 * 1024);
+    let mut total: u64 = files.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in files {
+        if total <= budget {
+            break;
+        }
+        if path == keep {
+            continue;
+        }
+        if fs::remove_file(&path).is_ok() {
+            total -= len;
+        }
+    }
+}
+
+/// 64-bit FNV-1a: stable across builds, unlike `DefaultHasher`.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ u64::from(b)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Forwards writes to `out`, counting the bytes.
+struct Counted<'a> {
+    out: &'a mut dyn Write,
+    written: u64,
+}
+
+impl Write for Counted<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn the_prefix_ends_at_the_last_blank_line() {
+        assert_eq!(
+            split("sys\n\nshot 1\n\nquestion\n"),
+            Some(("sys\n\nshot 1\n\n", "question\n"))
+        );
+        assert_eq!(split("question"), None);
+        assert_eq!(split("\n\nquestion"), None);
+        assert_eq!(split("sys\n\n  \n"), None);
+    }
+
+    #[test]
+    fn eviction_drops_the_least_recently_used_but_never_the_kept_file() {
+        let dir = env::temp_dir().join(format!("nox-state-evict-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let file = |name: &str, age_secs: u64| {
+            let path = dir.join(name);
+            fs::write(&path, vec![0u8; 600 * 1024]).unwrap();
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - Duration::from_secs(age_secs))
+                .unwrap();
+            path
+        };
+        let kept = file("kept.bin", 300);
+        let oldest = file("oldest.bin", 200);
+        let newer = file("newer.bin", 100);
+        let other = file("notes.txt", 400);
+
+        evict(&dir, 1, &kept);
+        assert!(kept.exists());
+        assert!(!oldest.exists());
+        assert!(!newer.exists(), "still over budget with kept and newer");
+        assert!(other.exists());
+
+        let newer = file("newer.bin", 100);
+        evict(&dir, 2, &kept);
+        assert!(kept.exists() && newer.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
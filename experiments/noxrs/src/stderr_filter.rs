@@ -0,0 +1,228 @@
+//! Filters llama.cpp stderr: model-loading and perf chatter is suppressed,
+//! warnings and errors pass through. The last suppressed lines are kept so a
+//! failed run can replay them.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Number of suppressed lines replayed when the runner fails.
+pub const REPLAY_LINES: usize = 40;
+
+const PROGRESS_PREFIXES: &[&str] = &[
+    "llama_model_loader:",
+    "llama_model_load",
+    "llama_context:",
+    "llama_kv_cache",
+    "llama_new_context",
+    "llama_perf_",
+    "llama_print_timings",
+    "llm_load_",
+    "load_tensors:",
+    "load:",
+    "print_info:",
+    "ggml_",
+    "gguf_",
+    "common_init",
+    "system_info:",
+    "sampler",
+    "generate:",
+    "build:",
+    "main:",
+    "graph_",
+    "sched_",
+    "CPU_",
+    "AVX",
+    "Vulkan",
+    "vk_",
+    "warmup:",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineClass {
+    Progress,
+    Warning,
+    Error,
+    Other,
+}
+
+pub fn classify(line: &str) -> LineClass {
+    let trimmed = line.trim();
+    let lower = trimmed.to_ascii_lowercase();
+    if lower.starts_with("warn") {
+        return LineClass::Warning;
+    }
+    if lower.contains("error")
+        || lower.contains("failed")
+        || lower.contains("unable to")
+        || lower.contains("cannot")
+        || lower.contains("abort")
+        || lower.contains("segmentation fault")
+        || lower.contains("no such file")
+        || trimmed.starts_with("GGML_ASSERT")
+        || trimmed.starts_with("terminate called")
+    {
+        return LineClass::Error;
+    }
+    if lower.contains("warning") {
+        return LineClass::Warning;
+    }
+    if trimmed.is_empty()
+        || trimmed.chars().all(|c| c == '.')
+        || PROGRESS_PREFIXES.iter().any(|p| trimmed.starts_with(p))
+    {
+        return LineClass::Progress;
+    }
+    LineClass::Other
+}
+
+pub struct StderrCapture {
+    handle: JoinHandle<()>,
+    tail: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl StderrCapture {
+    /// Drains `src` on a background thread so the child never blocks on a
+    /// full pipe. Everything except the progress class is echoed to our stderr.
+    pub fn spawn<R: Read + Send + 'static>(src: R) -> Self {
+        Self::start(src, true)
+    }
+
+    /// Like [`StderrCapture::spawn`], but every line is echoed and the last
+    /// [`REPLAY_LINES`] are kept for [`StderrCapture::join`].
+    pub fn forward<R: Read + Send + 'static>(src: R) -> Self {
+        Self::start(src, false)
+    }
+
+    fn start<R: Read + Send + 'static>(src: R, filter: bool) -> Self {
+        let tail = Arc::new(Mutex::new(VecDeque::with_capacity(REPLAY_LINES)));
+        let kept = Arc::clone(&tail);
+        let handle = thread::spawn(move || {
+            let mut reader = BufReader::new(src);
+            let mut raw = Vec::new();
+            loop {
+                raw.clear();
+                match reader.read_until(b'\n', &mut raw) {
+                    Ok(0) | Err(_) => break,
+                    Ok(_) => {}
+                }
+                let line = String::from_utf8_lossy(&raw);
+                let line = line.trim_end_matches(['\n', '\r']);
+                let hide = filter && classify(line) == LineClass::Progress;
+                if !hide {
+                    let _ = writeln!(io::stderr().lock(), "{line}");
+                }
+                if hide || !filter {
+                    let mut kept = kept.lock().unwrap_or_else(|e| e.into_inner());
+                    if kept.len() == REPLAY_LINES {
+                        kept.pop_front();
+                    }
+                    kept.push_back(line.to_string());
+                }
+            }
+        });
+        Self { handle, tail }
+    }
+
+    /// Waits for the drain thread and returns the kept lines.
+    pub fn join(self) -> Vec<String> {
+        let _ = self.handle.join();
+        let tail = self.tail.lock().unwrap_or_else(|e| e.into_inner());
+        tail.iter().cloned().collect()
+    }
+
+    /// Waits for the drain thread; when `failed`, replays the suppressed tail.
+    pub fn finish(self, failed: bool) {
+        let tail = self.join();
+        if !failed || tail.is_empty() {
+            return;
+        }
+        let mut err = io::stderr().lock();
+        let _ = writeln!(err, "nox: last {} suppressed runner log lines:", tail.len());
+        for line in &tail {
+            let _ = writeln!(err, "  {line}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What llama-completion writes to stderr for a run that loads, warns
+    /// about its context, and then fails to allocate.
+    const TRANSCRIPT: &str = "\
+build: 4589 (d2fe216f) with cc (GCC) 13.2.1 for x86_64-pc-linux-gnu
+main: llama backend init
+llama_model_loader: loaded meta data with 24 key-value pairs and 291 tensors from nox.gguf (version GGUF V3 (latest))
+llama_model_loader: - kv   0:                       general.architecture str              = llama
+llm_load_print_meta: n_ctx_train      = 4096
+load_tensors: offloading 0 repeating layers to GPU
+..................................................................................
+
+llama_new_context_with_model: n_ctx      = 8192
+warning: n_ctx (8192) is larger than n_ctx_train (4096)
+llama_kv_cache_init:        CPU KV buffer size =  1024.00 MiB
+ggml_backend_cpu_buffer_type_alloc_buffer: failed to allocate buffer of size 1073741824
+llama_init_from_model: failed to initialize the context
+common_init_from_params: warning: failed to load model 'nox.gguf'
+GGML_ASSERT(ctx != nullptr) at ggml.c:1234
+system_info: n_threads = 8 (n_threads_batch = 8) / 16 | AVX = 1 | AVX2 = 1
+sampler seed: 42
+llama_perf_context_print:        load time =     812.34 ms
+the quick brown fox
+";
+
+    #[test]
+    fn a_llama_transcript_keeps_warnings_and_errors() {
+        let kept: Vec<(LineClass, &str)> = TRANSCRIPT
+            .lines()
+            .map(|line| (classify(line), line))
+            .filter(|(class, _)| *class != LineClass::Progress)
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                (
+                    LineClass::Warning,
+                    "warning: n_ctx (8192) is larger than n_ctx_train (4096)"
+                ),
+                (
+                    LineClass::Error,
+                    "ggml_backend_cpu_buffer_type_alloc_buffer: failed to allocate buffer of size 1073741824"
+                ),
+                (
+                    LineClass::Error,
+                    "llama_init_from_model: failed to initialize the context"
+                ),
+                (
+                    LineClass::Error,
+                    "common_init_from_params: warning: failed to load model 'nox.gguf'"
+                ),
+                (LineClass::Error, "GGML_ASSERT(ctx != nullptr) at ggml.c:1234"),
+                (LineClass::Other, "the quick brown fox"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_last_suppressed_lines_are_kept_for_replay() {
+        let noise: String = (0..100)
+            .map(|n| format!("llama_model_loader: - kv {n:3}\n"))
+            .collect();
+        let input = format!("{noise}error: out of memory\n");
+        let tail = StderrCapture::spawn(io::Cursor::new(input)).join();
+        assert_eq!(tail.len(), REPLAY_LINES);
+        assert_eq!(tail[0], "llama_model_loader: - kv  60");
+        assert_eq!(tail[REPLAY_LINES - 1], "llama_model_loader: - kv  99");
+    }
+
+    #[test]
+    fn forwarding_keeps_every_line() {
+        let input: String = (0..50).map(|n| format!("line {n}\n")).collect();
+        let tail = StderrCapture::forward(io::Cursor::new(input)).join();
+        let expected: Vec<String> = (10..50).map(|n| format!("line {n}")).collect();
+        assert_eq!(tail, expected);
+    }
+}
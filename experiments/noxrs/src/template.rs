@@ -0,0 +1,353 @@
+//! `NOX_RUNNER_STYLE=custom`: the runner argv comes from `NOX_RUNNER_TEMPLATE`
+//! instead of a hard-coded style. The template is split like a shell would
+//! (single/double quotes, backslash escapes), then `{name}` placeholders are
+//! substituted. `{?...}` marks an optional group that is dropped whole when a
+//! placeholder inside it has no value, e.g. `{?-t {threads}}`. A bare
+//! `{prompt:stdin}` word writes the prompt to the runner's stdin instead.
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "model",
+    "prompt",
+    "ctx",
+    "max_tokens",
+    "temp",
+    "top_p",
+    "top_k",
+    "threads",
+];
+
+const STDIN_PROMPT: &str = "{prompt:stdin}";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Word {
+    Text(String),
+    Optional(Vec<String>),
+    StdinPrompt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    words: Vec<Word>,
+}
+
+/// A template with every placeholder filled in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rendered {
+    pub argv: Vec<String>,
+    pub prompt_on_stdin: bool,
+}
+
+impl Template {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut words = Vec::new();
+        for token in split(text, true)? {
+            let word = match token {
+                Token::Group(inner) => {
+                    let parts = split(&inner, true)?
+                        .into_iter()
+                        .map(|t| match t {
+                            Token::Word(w) => Ok(w),
+                            Token::Group(_) => Err("optional groups cannot nest".to_string()),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    for part in &parts {
+                        check_placeholders(part)?;
+                    }
+                    Word::Optional(parts)
+                }
+                Token::Word(w) if w == STDIN_PROMPT => Word::StdinPrompt,
+                Token::Word(w) => {
+                    check_placeholders(&w)?;
+                    Word::Text(w)
+                }
+            };
+            words.push(word);
+        }
+        if !matches!(words.first(), Some(Word::Text(_))) {
+            return Err("template must start with the runner program".to_string());
+        }
+        Ok(Self { words })
+    }
+
+    /// The first word, i.e. the runner program.
+    pub fn program(&self) -> &str {
+        match self.words.first() {
+            Some(Word::Text(t)) => t,
+            _ => "",
+        }
+    }
+
+    /// Fills placeholders via `lookup`. A required placeholder without a
+    /// value is an error; inside an optional group it drops the group.
+    pub fn render(&self, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Rendered, String> {
+        let mut argv = Vec::new();
+        let mut prompt_on_stdin = false;
+        for word in &self.words {
+            match word {
+                Word::Text(text) => match substitute(text, lookup) {
+                    Ok(value) => argv.push(value),
+                    Err(name) => return Err(format!("no value for {{{name}}}")),
+                },
+                Word::Optional(parts) => {
+                    let filled: Result<Vec<_>, _> =
+                        parts.iter().map(|p| substitute(p, lookup)).collect();
+                    if let Ok(filled) = filled {
+                        argv.extend(filled);
+                    }
+                }
+                Word::StdinPrompt => prompt_on_stdin = true,
+            }
+        }
+        Ok(Rendered {
+            argv,
+            prompt_on_stdin,
+        })
+    }
+}
+
+/// Replaces `{name}` occurrences; returns the first missing name on failure.
+fn substitute(text: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        out.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}') else {
+            break;
+        };
+        let name = &rest[open + 1..open + close];
+        if PLACEHOLDERS.contains(&name) {
+            out.push_str(&lookup(name).ok_or_else(|| name.to_string())?);
+        } else {
+            out.push_str(&rest[open..=open + close]);
+        }
+        rest = &rest[open + close + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn check_placeholders(word: &str) -> Result<(), String> {
+    let mut rest = word;
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            return Ok(());
+        };
+        let name = &rest[open + 1..open + close];
+        if name == "prompt:stdin" {
+            return Err("{prompt:stdin} must be a word of its own".to_string());
+        }
+        if !name.is_empty()
+            && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+            && !PLACEHOLDERS.contains(&name)
+        {
+            return Err(format!(
+                "unknown placeholder {{{name}}} (known: {})",
+                PLACEHOLDERS.join(", ")
+            ));
+        }
+        rest = &rest[open + close + 1..];
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(String),
+    /// Raw text between `{?` and its closing brace, split later.
+    Group(String),
+}
+
+/// Splits `text` into words like a shell would, without template groups.
+pub fn shell_words(text: &str) -> Result<Vec<String>, String> {
+    Ok(split(text, false)?
+        .into_iter()
+        .filter_map(|t| match t {
+            Token::Word(w) => Some(w),
+            Token::Group(_) => None,
+        })
+        .collect())
+}
+
+/// Shell-words splitter. With `groups`, an unquoted `{?` at the start of a
+/// word opens an optional group that runs to the matching `}`.
+fn split(text: &str, groups: bool) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if groups && chars[i] == '{' && chars.get(i + 1) == Some(&'?') {
+            let (inner, end) = group_body(&chars, i + 2)?;
+            tokens.push(Token::Group(inner));
+            i = end;
+            if chars.get(i).is_some_and(|c| !c.is_whitespace()) {
+                return Err("optional group must be followed by whitespace".to_string());
+            }
+            continue;
+        }
+        let mut word = String::new();
+        while i < chars.len() && !chars[i].is_whitespace() {
+            match chars[i] {
+                '\'' => {
+                    i += 1;
+                    while i < chars.len() && chars[i] != '\'' {
+                        word.push(chars[i]);
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err("unterminated single quote".to_string());
+                    }
+                    i += 1;
+                }
+                '"' => {
+                    i += 1;
+                    while i < chars.len() && chars[i] != '"' {
+                        if chars[i] == '\\' && matches!(chars.get(i + 1), Some('"' | '\\')) {
+                            i += 1;
+                        }
+                        word.push(chars[i]);
+                        i += 1;
+                    }
+                    if i == chars.len() {
+                        return Err("unterminated double quote".to_string());
+                    }
+                    i += 1;
+                }
+                '\\' => {
+                    i += 1;
+                    match chars.get(i) {
+                        Some(&c) => word.push(c),
+                        None => return Err("trailing backslash".to_string()),
+                    }
+                    i += 1;
+                }
+                c => {
+                    word.push(c);
+                    i += 1;
+                }
+            }
+        }
+        tokens.push(Token::Word(word));
+    }
+    Ok(tokens)
+}
+
+/// Scans from `start` (just past `{?`) to the matching `}`, skipping quoted
+/// text. Returns the raw body and the index after the closing brace.
+fn group_body(chars: &[char], start: usize) -> Result<(String, usize), String> {
+    let mut depth = 1;
+    let mut quote = None;
+    let mut i = start;
+    while i < chars.len() {
+        let c = chars[i];
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some('"') if c == '\\' => i += 1,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '\\' => i += 1,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok((chars[start..i].iter().collect(), i + 1));
+                    }
+                }
+                _ => {}
+            },
+        }
+        i += 1;
+    }
+    Err("unterminated optional group".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "model" => Some("/m/tiny model.gguf".to_string()),
+            "prompt" => Some("say \"hi\"".to_string()),
+            "ctx" => Some("2048".to_string()),
+            "max_tokens" => Some("64".to_string()),
+            "temp" => Some("0.7".to_string()),
+            _ => None,
+        }
+    }
+
+    fn render(template: &str) -> Result<Rendered, String> {
+        Template::parse(template)?.render(&lookup)
+    }
+
+    #[test]
+    fn placeholders_are_substituted_inside_and_across_words() {
+        let rendered = render("bin/run -m {model} -p {prompt} --ctx={ctx} -n{max_tokens}").unwrap();
+        assert_eq!(
+            rendered.argv,
+            [
+                "bin/run",
+                "-m",
+                "/m/tiny model.gguf",
+                "-p",
+                "say \"hi\"",
+                "--ctx=2048",
+                "-n64"
+            ]
+        );
+        assert!(!rendered.prompt_on_stdin);
+    }
+
+    #[test]
+    fn quoting_follows_shell_words() {
+        let rendered = render(r#"'my runner' "--stop=\"END\"" a\ b '{ctx}' {"n":1}"#).unwrap();
+        assert_eq!(
+            rendered.argv,
+            ["my runner", "--stop=\"END\"", "a b", "2048", "{n:1}"]
+        );
+        assert_eq!(
+            Template::parse("run 'open").unwrap_err(),
+            "unterminated single quote"
+        );
+    }
+
+    #[test]
+    fn optional_groups_drop_whole_when_a_value_is_missing() {
+        let rendered =
+            render("run {?-t {threads}} {?--temp {temp}} {?--top-k {top_k} --top-p {top_p}}")
+                .unwrap();
+        assert_eq!(rendered.argv, ["run", "--temp", "0.7"]);
+        assert_eq!(
+            render("run -t {threads}").unwrap_err(),
+            "no value for {threads}"
+        );
+        assert_eq!(
+            Template::parse("run {?-a {?-b}}").unwrap_err(),
+            "optional groups cannot nest"
+        );
+        assert!(Template::parse("run {?-t {threads}").is_err());
+    }
+
+    #[test]
+    fn the_stdin_prompt_word_moves_the_prompt_off_argv() {
+        let rendered = render("run -m {model} {prompt:stdin}").unwrap();
+        assert_eq!(rendered.argv, ["run", "-m", "/m/tiny model.gguf"]);
+        assert!(rendered.prompt_on_stdin);
+        assert!(Template::parse("run --in={prompt:stdin}").is_err());
+    }
+
+    #[test]
+    fn bad_templates_are_rejected() {
+        assert!(Template::parse("run {modle}")
+            .unwrap_err()
+            .starts_with("unknown placeholder {modle}"));
+        assert_eq!(
+            Template::parse("{?-t {threads}} run").unwrap_err(),
+            "template must start with the runner program"
+        );
+    }
+}
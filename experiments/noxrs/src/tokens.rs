@@ -0,0 +1,157 @@
+//! `--count-tokens`: estimates how many tokens the final prompt will take
+//! and whether it leaves room for `NOX_MAX_TOKENS` within the context. The
+//! estimate is characters / `NOX_CHARS_PER_TOKEN` (default 4); llama.cpp
+//! styles get an exact count from the `llama-tokenize` next to the runner
+//! when one exists.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use crate::error::NoxError;
+use crate::json::{self, Json};
+use crate::{find_executable, Config};
+
+pub const DEFAULT_CHARS_PER_TOKEN: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TokenCount {
+    pub tokens: usize,
+    /// Whether `tokens` came from the model's tokenizer.
+    pub exact: bool,
+    pub ctx: u32,
+    pub fits: bool,
+}
+
+impl TokenCount {
+    /// `tokens≈1234 ctx=4096 fits=yes` (`=` instead of `≈` when exact).
+    pub fn line(&self) -> String {
+        format!(
+            "tokens{}{} ctx={} fits={}",
+            if self.exact { "=" } else { "≈" },
+            self.tokens,
+            self.ctx,
+            if self.fits { "yes" } else { "no" }
+        )
+    }
+
+    pub fn to_json(self) -> Json {
+        json::obj([
+            ("tokens", Json::from(self.tokens)),
+            ("exact", Json::from(self.exact)),
+            ("ctx", Json::from(self.ctx as usize)),
+            ("fits", Json::from(self.fits)),
+        ])
+    }
+}
+
+pub fn validate(cfg: &Config) -> Result<(), NoxError> {
+    let cpt = cfg.chars_per_token;
+    if !(cpt > 0.0 && cpt.is_finite()) {
+        return Err(NoxError::Usage(format!(
+            "NOX_CHARS_PER_TOKEN must be > 0, got {cpt}"
+        )));
+    }
+    Ok(())
+}
+
+/// Counts `prompt`, exactly if a tokenizer is at hand.
+pub fn count(cfg: &Config, prompt: &str) -> TokenCount {
+    let exact = exact_count(cfg, prompt);
+    let tokens = exact.unwrap_or_else(|| estimate(prompt, cfg.chars_per_token));
+    TokenCount {
+        tokens,
+        exact: exact.is_some(),
+        ctx: cfg.ctx,
+        fits: tokens + cfg.max_tokens as usize <= cfg.ctx as usize,
+    }
+}
+
+pub fn estimate(prompt: &str, chars_per_token: f32) -> usize {
+    estimate_chars(prompt.chars().count(), chars_per_token)
+}
+
+pub fn estimate_chars(chars: usize, chars_per_token: f32) -> usize {
+    (chars as f32 / chars_per_token).ceil() as usize
+}
+
+pub fn print(count: TokenCount, json: bool) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    if json {
+        writeln!(out, "{}", count.to_json())?;
+    } else {
+        writeln!(out, "{}", count.line())?;
+    }
+    out.flush()
+}
+
+/// Runs `llama-tokenize --show-count` from the runner's directory. Any
+/// failure falls back to the estimate.
+fn exact_count(cfg: &Config, prompt: &str) -> Option<usize> {
+    if !cfg.runner_style.is_llama() || cfg.emulate_a1000 {
+        return None;
+    }
+    let runner = cfg.resolve_runner().ok()?;
+    let model = cfg.resolve_model().ok()??;
+    let tokenizer = find_executable(&runner.with_file_name("llama-tokenize"))?;
+    let output = Command::new(&tokenizer)
+        .args(["-m", &model])
+        .args(["--show-count", "--log-disable"])
+        .args(["-p", prompt])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        cfg.debug(format!(
+            "{} failed; estimating instead",
+            tokenizer.display()
+        ));
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.split_once("Total number of tokens:"))
+        .and_then(|(_, n)| n.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::config;
+
+    #[test]
+    fn the_heuristic_counts_characters_not_bytes_and_rounds_up() {
+        assert_eq!(estimate("", 4.0), 0);
+        assert_eq!(estimate("abcd", 4.0), 1);
+        assert_eq!(estimate("abcde", 4.0), 2);
+        assert_eq!(estimate("日本語のテキスト", 4.0), 2);
+        assert_eq!(estimate("abcdefghij", 2.5), 4);
+        assert_eq!(estimate_chars(10_001, 3.0), 3_334);
+    }
+
+    #[test]
+    fn a_prompt_fits_while_it_leaves_room_for_max_tokens() {
+        let cfg = config(&[("NOX_CTX", "100"), ("NOX_MAX_TOKENS", "20")]);
+        let fits = count(&cfg, &"x".repeat(320));
+        assert_eq!((fits.tokens, fits.exact, fits.fits), (80, false, true));
+        assert_eq!(fits.line(), "tokens≈80 ctx=100 fits=yes");
+
+        let over = count(&cfg, &"x".repeat(321));
+        assert_eq!((over.tokens, over.fits), (81, false));
+        assert_eq!(over.line(), "tokens≈81 ctx=100 fits=no");
+        assert_eq!(
+            over.to_json().to_string(),
+            r#"{"tokens":81,"exact":false,"ctx":100,"fits":false}"#
+        );
+    }
+
+    #[test]
+    fn chars_per_token_is_configurable_and_checked() {
+        let cfg = config(&[("NOX_CHARS_PER_TOKEN", "2"), ("NOX_CTX", "64")]);
+        assert_eq!(count(&cfg, "abcdef").tokens, 3);
+        for bad in ["0", "-1", "inf"] {
+            let cfg = config(&[("NOX_CHARS_PER_TOKEN", bad)]);
+            assert_eq!(validate(&cfg).unwrap_err().exit_code(), 2, "{bad}");
+        }
+    }
+}
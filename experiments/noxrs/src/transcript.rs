@@ -0,0 +1,277 @@
+//! `--log FILE` / `NOX_LOG_FILE`: appends every prompt/response pair to a
+//! transcript. The default format is framed plain text written line by line,
+//! so a crash mid-response still leaves the partial answer on disk; a
+//! `.jsonl` path gets one JSON object per completed turn instead. A failed
+//! write warns once and disables logging rather than aborting the run.
+//! Persistent modes can attach a [`SessionLog`] that gets a JSON record of
+//! every turn as well, whatever the transcript format.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::error::NoxError;
+use crate::json::{self, Json};
+use crate::models;
+use crate::session_log::{self, SessionLog};
+use crate::RunStats;
+
+pub struct Transcript {
+    path: PathBuf,
+    file: Option<File>,
+    jsonl: bool,
+    session: Option<SessionLog>,
+    turn: Option<Turn>,
+}
+
+struct Turn {
+    at: String,
+    prompt: String,
+    /// The whole response, kept for JSON records.
+    response: Vec<u8>,
+    /// The unterminated tail line for text.
+    pending: Vec<u8>,
+}
+
+impl Transcript {
+    /// Opens `path` for appending; `None` (or an open failure, after a
+    /// warning) gives a transcript that ignores everything.
+    pub fn open(path: Option<&Path>) -> Self {
+        let mut log = Self {
+            path: path.map(Path::to_path_buf).unwrap_or_default(),
+            file: None,
+            jsonl: path.is_some_and(|p| p.extension().is_some_and(|e| e == "jsonl")),
+            session: None,
+            turn: None,
+        };
+        if let Some(path) = path {
+            match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => log.file = Some(file),
+                Err(err) => log.disable(err),
+            }
+        }
+        log
+    }
+
+    /// Also records every turn, and [`Transcript::event`]s, in `session`.
+    pub fn with_session(mut self, session: Option<SessionLog>) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Starts a turn for `prompt`, closing any turn still open.
+    pub fn begin(&mut self, prompt: &str) {
+        if self.file.is_none() && self.session.is_none() {
+            return;
+        }
+        if self.turn.is_some() {
+            self.end(None, None);
+        }
+        let turn = Turn {
+            at: iso8601(SystemTime::now()),
+            prompt: prompt.to_string(),
+            response: Vec::new(),
+            pending: Vec::new(),
+        };
+        if !self.jsonl {
+            let mut header = format!("=== {} ===\n", turn.at);
+            for line in prompt.trim_end_matches('\n').split('\n') {
+                header.push_str(">>> ");
+                header.push_str(line);
+                header.push('\n');
+            }
+            header.push_str("--- response\n");
+            self.write(header.as_bytes());
+        }
+        self.turn = Some(turn);
+    }
+
+    /// Records response bytes for the open turn.
+    pub fn output(&mut self, bytes: &[u8]) {
+        let Some(turn) = self.turn.as_mut() else {
+            return;
+        };
+        if self.jsonl || self.session.is_some() {
+            turn.response.extend_from_slice(bytes);
+        }
+        if self.jsonl || self.file.is_none() {
+            return;
+        }
+        turn.pending.extend_from_slice(bytes);
+        if let Some(last_nl) = turn.pending.iter().rposition(|b| *b == b'\n') {
+            let lines: Vec<u8> = turn.pending.drain(..=last_nl).collect();
+            self.write(&lines);
+        }
+    }
+
+    /// Closes the open turn with its timing, or the error that ended it.
+    pub fn end(&mut self, stats: Option<&RunStats>, error: Option<&NoxError>) {
+        let Some(turn) = self.turn.take() else {
+            return;
+        };
+        let ms = |d: Duration| d.as_millis() as u64;
+        let error_text = error.map(|e| e.to_string());
+        if self.jsonl || self.session.is_some() {
+            let mut record = json::obj([
+                ("at", Json::from(turn.at.clone())),
+                ("prompt", Json::from(turn.prompt.clone())),
+                (
+                    "response",
+                    Json::from(String::from_utf8_lossy(&turn.response).into_owned()),
+                ),
+                ("ttft_ms", Json::from(stats.and_then(|s| s.ttft).map(ms))),
+                ("prefill_ms", Json::from(stats.and_then(|s| s.prefill).map(ms))),
+                ("duration_ms", Json::from(stats.map(|s| ms(s.duration)))),
+                ("tokens_per_sec", Json::from(stats.and_then(RunStats::tps))),
+                ("error", Json::from(error_text.clone())),
+            ]);
+            if let (Some(checked), Json::Obj(fields)) =
+                (error.and_then(NoxError::model_candidates), &mut record)
+            {
+                fields.push((
+                    "model_candidates".to_string(),
+                    models::checked_json(checked),
+                ));
+            }
+            if self.session.is_some() {
+                let mut tagged = record.clone();
+                if let Json::Obj(fields) = &mut tagged {
+                    fields.insert(0, ("type".to_string(), Json::from("request")));
+                }
+                self.record(&tagged);
+            }
+            if self.jsonl {
+                self.write(format!("{record}\n").as_bytes());
+                return;
+            }
+        }
+        if self.file.is_none() {
+            return;
+        }
+        let mut footer = turn.pending;
+        if !footer.is_empty() {
+            footer.push(b'\n');
+        }
+        let summary = match (stats, error_text) {
+            (_, Some(error)) => format!("--- error: {error}\n\n"),
+            (Some(stats), None) => {
+                let ttft = stats.ttft_label();
+                let tps = stats
+                    .tps()
+                    .map_or(String::new(), |tps| format!("{tps:.1} tok/s, "));
+                format!("--- ttft {ttft}, {tps}total {} ms\n\n", ms(stats.duration))
+            }
+            (None, None) => "--- end\n\n".to_string(),
+        };
+        footer.extend_from_slice(summary.as_bytes());
+        self.write(&footer);
+    }
+
+    /// Convenience for callers holding a whole `generate` result.
+    pub fn finish(&mut self, result: &Result<RunStats, NoxError>) {
+        match result {
+            Ok(stats) => self.end(Some(stats), None),
+            Err(err) => self.end(None, Some(err)),
+        }
+    }
+
+    /// Records a session event, such as a runner restart, in the session
+    /// log only.
+    pub fn event(&mut self, kind: &str, detail: &str) {
+        if self.session.is_none() {
+            return;
+        }
+        let record = json::obj([
+            ("type", Json::from(kind)),
+            ("at", Json::from(iso8601(SystemTime::now()))),
+            ("detail", Json::from(detail)),
+        ]);
+        self.record(&record);
+    }
+
+    fn record(&mut self, record: &Json) {
+        let Some(session) = self.session.as_mut() else {
+            return;
+        };
+        if let Err(err) = session.write(record) {
+            session_log::warn(session.dir(), &err);
+            self.session = None;
+        }
+    }
+
+    /// Forces what has been written to disk, for shutdowns that may be
+    /// followed by a power-off.
+    pub fn sync(&mut self) {
+        let Some(file) = self.file.as_ref() else {
+            return;
+        };
+        if let Err(err) = file.sync_all() {
+            self.disable(err);
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let Some(file) = self.file.as_mut() else {
+            return;
+        };
+        if let Err(err) = file.write_all(bytes).and_then(|_| file.flush()) {
+            self.disable(err);
+        }
+    }
+
+    fn disable(&mut self, err: io::Error) {
+        eprintln!(
+            "nox: cannot write log {}: {err}; logging disabled",
+            self.path.display()
+        );
+        self.file = None;
+        if self.session.is_none() {
+            self.turn = None;
+        }
+    }
+}
+
+/// Forwards writes to `out` and copies them into the transcript.
+pub struct Tee<'a> {
+    pub out: &'a mut dyn Write,
+    pub log: &'a mut Transcript,
+}
+
+impl Write for Tee<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.log.output(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Formats `at` as an ISO-8601 UTC timestamp (`2024-05-01T12:34:56Z`).
+pub fn iso8601(at: SystemTime) -> String {
+    let secs = at
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86_400) as i64;
+    let rem = secs % 86_400;
+    // Civil-from-days (Howard Hinnant's algorithm).
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
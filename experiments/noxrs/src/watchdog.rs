@@ -0,0 +1,255 @@
+//! Bounded stdout pump for runner children. The blocking read lives on its own
+//! thread and hands chunks over a channel so the caller can enforce a total
+//! run deadline and a no-output stall window with `recv_timeout`, and notice
+//! interrupts between chunks.
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub total: Option<Duration>,
+    pub stall: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PumpReport {
+    pub end: PumpEnd,
+    pub first_byte: Option<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PumpEnd {
+    Eof,
+    Interrupted,
+    TimedOut(Duration),
+    Stalled(Duration),
+}
+
+impl PumpEnd {
+    /// Why a limit cut the run short, if one did.
+    pub fn reason(&self) -> Option<String> {
+        match self {
+            PumpEnd::TimedOut(limit) => Some(format!(
+                "runner timed out after {}s (NOX_TIMEOUT_SECS)",
+                limit.as_secs()
+            )),
+            PumpEnd::Stalled(limit) => Some(format!(
+                "runner stalled: no output for {}s (NOX_STALL_SECS)",
+                limit.as_secs()
+            )),
+            PumpEnd::Eof | PumpEnd::Interrupted => None,
+        }
+    }
+}
+
+/// Copies `src` into `out` until EOF or until one of the limits expires.
+/// `started` should be taken right after spawn so the stall window also
+/// covers the wait for the first byte (model load). `cancelled` is polled
+/// at least every 100ms.
+pub fn pump<R, W>(
+    src: R,
+    out: &mut W,
+    limits: Limits,
+    started: Instant,
+    cancelled: &dyn Fn() -> bool,
+) -> io::Result<PumpReport>
+where
+    R: Read + Send + 'static,
+    W: Write + ?Sized,
+{
+    let (tx, rx) = mpsc::channel::<io::Result<Vec<u8>>>();
+    thread::spawn(move || {
+        let mut src = src;
+        let mut buf = [0u8; 4096];
+        loop {
+            match src.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(Ok(buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    let _ = tx.send(Err(err));
+                    break;
+                }
+            }
+        }
+    });
+
+    let deadline = limits.total.map(|d| started + d);
+    let mut last_activity = started;
+    let mut first_byte = None;
+    loop {
+        if cancelled() {
+            return Ok(PumpReport {
+                end: PumpEnd::Interrupted,
+                first_byte,
+            });
+        }
+        let now = Instant::now();
+        // Checked before every wait, not only when one times out: a runner
+        // that never stops writing never lets `recv_timeout` time out.
+        if let (Some(at), Some(total)) = (deadline, limits.total) {
+            if now >= at {
+                return Ok(PumpReport {
+                    end: PumpEnd::TimedOut(total),
+                    first_byte,
+                });
+            }
+        }
+        let stall_at = limits.stall.map(|d| last_activity + d);
+        let wait = [deadline, stall_at]
+            .into_iter()
+            .flatten()
+            .map(|at| at.saturating_duration_since(now))
+            .fold(POLL, Duration::min);
+        match rx.recv_timeout(wait) {
+            Ok(Ok(chunk)) => {
+                last_activity = Instant::now();
+                first_byte.get_or_insert(last_activity);
+                out.write_all(&chunk)?;
+                out.flush()?;
+            }
+            Ok(Err(err)) => return Err(err),
+            Err(RecvTimeoutError::Disconnected) => {
+                return Ok(PumpReport {
+                    end: PumpEnd::Eof,
+                    first_byte,
+                })
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if let (Some(at), Some(stall)) = (stall_at, limits.stall) {
+                    if Instant::now() >= at {
+                        return Ok(PumpReport {
+                            end: PumpEnd::Stalled(stall),
+                            first_byte,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A runner that writes a line every `every`, forever.
+    struct Streaming {
+        every: Duration,
+    }
+
+    impl Read for Streaming {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.every);
+            let line = b"y\n";
+            buf[..line.len()].copy_from_slice(line);
+            Ok(line.len())
+        }
+    }
+
+    /// A runner that never writes, like one wedged loading its model.
+    struct Hung;
+
+    impl Read for Hung {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_secs(3600));
+            Ok(0)
+        }
+    }
+
+    fn run(src: impl Read + Send + 'static, limits: Limits) -> (PumpEnd, Duration) {
+        let started = Instant::now();
+        let report = pump(src, &mut io::sink(), limits, started, &|| false).unwrap();
+        (report.end, started.elapsed())
+    }
+
+    #[test]
+    fn a_runner_that_never_stops_writing_times_out() {
+        let limits = Limits {
+            total: Some(Duration::from_millis(300)),
+            stall: Some(Duration::from_secs(5)),
+        };
+        let every = Duration::from_millis(1);
+        let (end, took) = run(Streaming { every }, limits);
+        assert!(matches!(end, PumpEnd::TimedOut(_)), "{end:?}");
+        assert!(took < Duration::from_secs(2), "took {took:?}");
+    }
+
+    #[test]
+    fn a_silent_runner_stalls() {
+        let limits = Limits {
+            total: None,
+            stall: Some(Duration::from_millis(200)),
+        };
+        let (end, took) = run(Hung, limits);
+        assert!(matches!(end, PumpEnd::Stalled(_)), "{end:?}");
+        assert!(took >= Duration::from_millis(200), "took {took:?}");
+    }
+
+    #[test]
+    fn a_silent_runner_times_out_without_a_stall_window() {
+        let limits = Limits {
+            total: Some(Duration::from_millis(200)),
+            stall: None,
+        };
+        let (end, _) = run(Hung, limits);
+        assert!(matches!(end, PumpEnd::TimedOut(_)), "{end:?}");
+    }
+
+    #[test]
+    fn steady_output_keeps_the_stall_window_open() {
+        let limits = Limits {
+            total: Some(Duration::from_millis(400)),
+            stall: Some(Duration::from_millis(100)),
+        };
+        let every = Duration::from_millis(20);
+        let (end, _) = run(Streaming { every }, limits);
+        assert!(matches!(end, PumpEnd::TimedOut(_)), "{end:?}");
+    }
+
+    #[test]
+    fn output_is_copied_until_eof() {
+        let mut out = Vec::new();
+        let report = pump(
+            &b"hello"[..],
+            &mut out,
+            Limits::default(),
+            Instant::now(),
+            &|| false,
+        )
+        .unwrap();
+        assert!(matches!(report.end, PumpEnd::Eof));
+        assert!(report.first_byte.is_some());
+        assert_eq!(out, b"hello");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_real_runner_printing_forever_times_out() {
+        use std::process::{Command, Stdio};
+
+        let mut child = Command::new("yes")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let limits = Limits {
+            total: Some(Duration::from_millis(300)),
+            stall: None,
+        };
+        let (end, took) = run(stdout, limits);
+        let _ = child.kill();
+        let _ = child.wait();
+        assert!(matches!(end, PumpEnd::TimedOut(_)), "{end:?}");
+        assert!(took < Duration::from_secs(2), "took {took:?}");
+    }
+}
@@ -0,0 +1,76 @@
+//! `--batch FILE --jobs N` on the simulator: records come out in input order
+//! whatever order the prompts finish in, and the jobs really overlap.
+
+mod common;
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use common::{nox, scratch, text};
+
+/// Prompts mentioning `slow` take 400 ms to their first token, the rest 150.
+const PLAYBOOK: &str = "\
+match: slow
+response: slow answer
+ttft_ms: 400
+
+response: quick answer
+ttft_ms: 150
+";
+
+const PROMPTS: [&str; 8] = [
+    "slow one",
+    "two",
+    "three",
+    "slow four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+];
+
+#[test]
+fn eight_prompts_over_three_jobs_keep_input_order_and_overlap() {
+    let dir = scratch();
+    let script = dir.join("playbook.txt");
+    let batch = dir.join("prompts.txt");
+    fs::write(&script, PLAYBOOK).unwrap();
+    fs::write(&batch, PROMPTS.join("\n") + "\n").unwrap();
+    let serial: Duration = PROMPTS
+        .iter()
+        .map(|p| Duration::from_millis(if p.contains("slow") { 400 } else { 150 }))
+        .sum();
+
+    let started = Instant::now();
+    let out = nox(
+        &[
+            ("NOX_RAW", "1"),
+            ("NOX_SIM_SCRIPT", script.to_str().unwrap()),
+        ],
+        &["--batch", batch.to_str().unwrap(), "--jobs", "3"],
+        "",
+    );
+    let wall = started.elapsed();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(out.status.success(), "{}", text(&out.stderr));
+
+    let stdout = text(&out.stdout);
+    let records: Vec<&str> = stdout.lines().collect();
+    assert_eq!(records.len(), PROMPTS.len(), "{stdout}");
+    for (n, (record, prompt)) in records.iter().zip(PROMPTS).enumerate() {
+        let answer = if prompt.contains("slow") {
+            "slow answer"
+        } else {
+            "quick answer"
+        };
+        let head = format!("{{\"id\":{},\"output\":\"{answer}\",", n + 1);
+        assert!(record.starts_with(&head), "{record}");
+        assert!(record.contains("\"error\":null"), "{record}");
+    }
+    // Three workers need about a third of the serial time; allow slack for
+    // process start-up on a loaded machine.
+    assert!(
+        wall < serial * 2 / 3,
+        "took {wall:?}, serial would be {serial:?}"
+    );
+}
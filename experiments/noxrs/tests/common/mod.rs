@@ -0,0 +1,55 @@
+//! Runs the built `nox` with the A1000 simulator and nothing else from the
+//! environment: no `NOX_*` variables but the ones given, and no config file
+//! (the working directory is empty and `HOME` unset).
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// A fast simulator: no wait for the first token and no gaps after it.
+pub const FAST: [(&str, &str); 3] = [
+    ("NOX_EMULATE_A1000", "1"),
+    ("NOX_SIM_TTFT_MS", "0"),
+    ("NOX_SIM_TPS", "0"),
+];
+
+/// Runs `nox args...` with `FAST` plus `vars`, writing `stdin` to it.
+pub fn nox(vars: &[(&str, &str)], args: &[&str], stdin: &str) -> Output {
+    let dir = scratch();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nox"))
+        .args(args)
+        .env_clear()
+        .envs(FAST)
+        .envs(vars.iter().copied())
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("nox starts");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    let out = child.wait_with_output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    out
+}
+
+pub fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// An empty directory of this run's own.
+pub fn scratch() -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::SeqCst);
+    let dir = env::temp_dir().join(format!("nox-it-{}-{run}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
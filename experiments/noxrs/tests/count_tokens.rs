@@ -0,0 +1,24 @@
+//! `--count-tokens` on the command line: the estimate, the `fits=no` case,
+//! and `--json`. Nothing is generated.
+
+mod common;
+
+use common::{nox, text};
+
+#[test]
+fn a_prompt_too_big_for_the_context_does_not_fit() {
+    let prompt = "x".repeat(300);
+    let out = nox(&[("NOX_CTX", "64")], &["--count-tokens", &prompt], "");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "tokens≈75 ctx=64 fits=no\n");
+}
+
+#[test]
+fn the_count_comes_as_json_and_reads_stdin() {
+    let out = nox(&[], &["--count-tokens", "--json"], "hello there");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(
+        text(&out.stdout),
+        "{\"tokens\":3,\"exact\":false,\"ctx\":1024,\"fits\":true}\n"
+    );
+}
@@ -0,0 +1,416 @@
+//! `--dry-run`: the printed command, for every runner style, is the argv
+//! nox would spawn, and pasting it into a shell passes the same argv;
+//! sampling flags reach each style in its own spelling.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use common::{nox, scratch, text};
+
+/// A prompt that needs every kind of quoting.
+const PROMPT: &str = "it's a \"test\"\n  $HOME `x`";
+
+/// Prints each argument in brackets, then whatever came on stdin; `-h`
+/// lists the only sampling flags it takes, like an older noxlocal.
+const RUNNER: &str = "#!/bin/sh
+if [ \"$1\" = -h ]; then echo '  -seed N  -repeat-penalty X'; exit 0; fi
+printf '[%s]\\n' \"$@\"
+cat
+";
+
+/// A runner that prints each argument in brackets, then whatever came on
+/// stdin, and a model file with a GGUF header.
+struct Fixtures {
+    dir: PathBuf,
+    runner: PathBuf,
+    model: PathBuf,
+}
+
+impl Fixtures {
+    fn new() -> Self {
+        let dir = scratch();
+        let runner = dir.join("runner");
+        fs::write(&runner, RUNNER).unwrap();
+        fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+        let model = dir.join("m.gguf");
+        fs::write(&model, b"GGUF\x03\0\0\0").unwrap();
+        Self { dir, runner, model }
+    }
+}
+
+impl Drop for Fixtures {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The dry-run report for `vars`: its `#` notes and the command line.
+fn dry_run(vars: &[(&str, &str)]) -> (Vec<String>, String) {
+    let mut all = vec![("NOX_EMULATE_A1000", "0")];
+    all.extend_from_slice(vars);
+    let out = nox(&all, &["--dry-run", PROMPT], "");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let (notes, command): (Vec<&str>, Vec<&str>) =
+        stdout.lines().partition(|line| line.starts_with("# "));
+    (
+        notes.iter().map(|n| n[2..].to_string()).collect(),
+        command.join("\n"),
+    )
+}
+
+/// What the shell hands the runner when `command` is pasted.
+fn pasted(command: &str) -> String {
+    let out = Command::new("sh")
+        .args(["-c", command])
+        .env_clear()
+        .output()
+        .unwrap();
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    text(&out.stdout)
+}
+
+fn bracketed(argv: &[&str]) -> String {
+    argv.iter().map(|arg| format!("[{arg}]\n")).collect()
+}
+
+fn path(p: &Path) -> &str {
+    p.to_str().unwrap()
+}
+
+#[test]
+fn noxlocal_gets_dash_flags_and_the_prompt_last() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let (notes, command) = dry_run(&[
+        ("NOX_LOCAL_RUNNER", path(runner)),
+        ("NOX_MODEL_PATH", path(model)),
+        ("NOX_CTX", "2048"),
+        ("NOX_NUM_THREADS", "4"),
+    ]);
+    assert_eq!(notes[0], "runner style: noxlocal");
+    assert!(command.starts_with("NOX_NUM_THREADS=4 "), "{command}");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&[
+            "-ctx",
+            "2048",
+            "-max-tokens",
+            "128",
+            "-batch",
+            "1",
+            "-temp",
+            "0",
+            "-top-p",
+            "1",
+            "-top-k",
+            "1",
+            "-model",
+            path(model),
+            PROMPT,
+        ])
+    );
+}
+
+#[test]
+fn llama_completion_gets_double_dash_flags() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let (notes, command) = dry_run(&[
+        ("NOX_RUNNER_STYLE", "llama-completion"),
+        ("NOX_LOCAL_RUNNER", path(runner)),
+        ("NOX_MODEL_PATH", path(model)),
+        ("NOX_MAX_TOKENS", "32"),
+        ("NOX_NUM_THREADS", "4"),
+    ]);
+    assert_eq!(notes[0], "runner style: llama-completion");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&[
+            "--simple-io",
+            "--no-display-prompt",
+            "--no-warmup",
+            "-m",
+            path(model),
+            "-c",
+            "1024",
+            "-n",
+            "32",
+            "-b",
+            "1",
+            "--temp",
+            "0",
+            "--top-p",
+            "1",
+            "--top-k",
+            "1",
+            "-t",
+            "4",
+            "-p",
+            PROMPT,
+        ])
+    );
+}
+
+#[test]
+fn llama_simple_gets_the_model_count_and_prompt() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let (notes, command) = dry_run(&[
+        ("NOX_RUNNER_STYLE", "llama-simple"),
+        ("NOX_LOCAL_RUNNER", path(runner)),
+        ("NOX_MODEL_PATH", path(model)),
+        ("NOX_GPU_LAYERS", "99"),
+    ]);
+    assert_eq!(notes[0], "runner style: llama-simple");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&["-m", path(model), "-n", "128", "-ngl", "99", PROMPT])
+    );
+}
+
+#[test]
+fn llama_embedding_asks_for_json_vectors() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let (notes, command) = dry_run(&[
+        ("NOX_RUNNER_STYLE", "llama-embedding"),
+        ("NOX_LOCAL_RUNNER", path(runner)),
+        ("NOX_MODEL_PATH", path(model)),
+    ]);
+    assert_eq!(notes[0], "runner style: llama-embedding");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&[
+            "-m",
+            path(model),
+            "-c",
+            "1024",
+            "--embd-output-format",
+            "json",
+            "--embd-separator",
+            "---",
+            "-p",
+            PROMPT,
+        ])
+    );
+}
+
+#[test]
+fn a_custom_template_can_pipe_the_prompt_in() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let template = format!(
+        "{} -m {{model}} {{?-t {{threads}}}} --ctx={{ctx}} {{prompt:stdin}}",
+        path(runner)
+    );
+    let (notes, command) = dry_run(&[
+        ("NOX_RUNNER_STYLE", "custom"),
+        ("NOX_RUNNER_TEMPLATE", &template),
+        ("NOX_MODEL_PATH", path(model)),
+    ]);
+    assert_eq!(notes[0], "runner style: custom");
+    assert!(command.starts_with("printf '%s' "), "{command}");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&["-m", path(model), "--ctx=1024"]) + PROMPT
+    );
+}
+
+#[test]
+fn persistent_mode_prints_the_serve_invocation() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let out = nox(
+        &[
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_LOCAL_RUNNER", path(runner)),
+            ("NOX_MODEL_PATH", path(model)),
+        ],
+        &["--dry-run"],
+        "",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let command = stdout.lines().last().unwrap();
+    assert_eq!(
+        pasted(command),
+        bracketed(&[
+            "-serve",
+            "-ctx",
+            "1024",
+            "-max-tokens",
+            "128",
+            "-batch",
+            "1",
+            "-temp",
+            "0",
+            "-top-p",
+            "1",
+            "-top-k",
+            "1",
+            "-model",
+            path(model),
+        ])
+    );
+}
+
+const SAMPLING: [(&str, &str); 5] = [
+    ("NOX_SEED", "42"),
+    ("NOX_REPEAT_PENALTY", "1.1"),
+    ("NOX_REPEAT_LAST_N", "-1"),
+    ("NOX_MIN_P", "0.05"),
+    ("NOX_PRESENCE_PENALTY", "0.5"),
+];
+
+#[test]
+fn llama_completion_gets_every_sampling_flag() {
+    let fx = Fixtures::new();
+    let mut vars = vec![
+        ("NOX_RUNNER_STYLE", "llama-completion"),
+        ("NOX_LOCAL_RUNNER", path(&fx.runner)),
+        ("NOX_MODEL_PATH", path(&fx.model)),
+    ];
+    vars.extend(SAMPLING);
+    let (_, command) = dry_run(&vars);
+    let argv = pasted(&command);
+    let expected = bracketed(&[
+        "--top-k",
+        "1",
+        "--seed",
+        "42",
+        "--repeat-penalty",
+        "1.1",
+        "--repeat-last-n",
+        "-1",
+        "--min-p",
+        "0.05",
+        "--presence-penalty",
+        "0.5",
+        "-p",
+    ]);
+    assert!(argv.contains(&expected), "{argv}");
+}
+
+#[test]
+fn noxlocal_gets_only_the_sampling_flags_its_help_lists() {
+    let fx = Fixtures::new();
+    let mut vars = vec![
+        ("NOX_EMULATE_A1000", "0"),
+        ("NOX_LOCAL_RUNNER", path(&fx.runner)),
+        ("NOX_MODEL_PATH", path(&fx.model)),
+    ];
+    vars.extend(SAMPLING);
+    let out = nox(&vars, &["--dry-run", "hi"], "");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let command = text(&out.stdout);
+    let argv = pasted(command.lines().last().unwrap());
+    let expected = bracketed(&[
+        "-top-k",
+        "1",
+        "-seed",
+        "42",
+        "-repeat-penalty",
+        "1.1",
+        "-model",
+    ]);
+    assert!(argv.contains(&expected), "{argv}");
+    let stderr = text(&out.stderr);
+    for dropped in ["NOX_REPEAT_LAST_N", "NOX_MIN_P", "NOX_PRESENCE_PENALTY"] {
+        assert!(stderr.contains(&format!("ignoring {dropped}")), "{stderr}");
+    }
+}
+
+#[test]
+fn flags_override_variables_and_bad_values_fail_before_spawning() {
+    let fx = Fixtures::new();
+    let base = [
+        ("NOX_EMULATE_A1000", "0"),
+        ("NOX_RUNNER_STYLE", "llama-completion"),
+        ("NOX_LOCAL_RUNNER", path(&fx.runner)),
+        ("NOX_MODEL_PATH", path(&fx.model)),
+        ("NOX_MIN_P", "0.5"),
+    ];
+    let out = nox(
+        &base,
+        &["--dry-run", "--min-p", "0.2", "--seed", "7", "hi"],
+        "",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let argv = pasted(text(&out.stdout).lines().last().unwrap());
+    assert!(argv.contains("[--seed]\n[7]\n[--min-p]\n[0.2]\n"), "{argv}");
+
+    for (flag, value, message) in [
+        (
+            "--min-p",
+            "1.5",
+            "min-p (NOX_MIN_P) must be within [0, 1], got 1.5",
+        ),
+        (
+            "--repeat-penalty",
+            "-0.1",
+            "repeat penalty (NOX_REPEAT_PENALTY) must be >= 0, got -0.1",
+        ),
+        (
+            "--presence-penalty",
+            "-2",
+            "presence penalty (NOX_PRESENCE_PENALTY) must be >= 0, got -2",
+        ),
+        (
+            "--repeat-last-n",
+            "-2",
+            "repeat-last-n (NOX_REPEAT_LAST_N) must be >= -1",
+        ),
+    ] {
+        let out = nox(&base, &["--dry-run", flag, value, "hi"], "");
+        assert_eq!(out.status.code(), Some(2), "{flag} {value}");
+        let stderr = text(&out.stderr);
+        assert!(stderr.starts_with(&format!("nox: {message}")), "{stderr}");
+        assert!(out.stdout.is_empty());
+    }
+}
+
+#[test]
+fn extra_args_come_before_the_prompt_and_win_over_generated_flags() {
+    let fx = Fixtures::new();
+    let (runner, model) = (&fx.runner, &fx.model);
+    let (notes, command) = dry_run(&[
+        ("NOX_RUNNER_STYLE", "llama-completion"),
+        ("NOX_LOCAL_RUNNER", path(runner)),
+        ("NOX_MODEL_PATH", path(model)),
+        ("NOX_EXTRA_ARGS", "-c 4096 --reverse-prompt 'User:  '"),
+    ]);
+    assert_eq!(notes[0], "runner style: llama-completion");
+    assert_eq!(
+        pasted(&command),
+        bracketed(&[
+            "--simple-io",
+            "--no-display-prompt",
+            "--no-warmup",
+            "-m",
+            path(model),
+            "-n",
+            "128",
+            "-b",
+            "1",
+            "--temp",
+            "0",
+            "--top-p",
+            "1",
+            "--top-k",
+            "1",
+            "-c",
+            "4096",
+            "--reverse-prompt",
+            "User:  ",
+            "-p",
+            PROMPT,
+        ])
+    );
+}
@@ -0,0 +1,123 @@
+//! `NOX_JSON_SCHEMA_FILE` with the simulator, which cannot enforce it: an
+//! invalid answer is retried with the corrective suffix, scripted through
+//! `NOX_SIM_SCRIPT`.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+const SCHEMA: &str = r#"{"type": "object", "required": ["ok"]}"#;
+
+/// A schema and simulator script in a directory of their own.
+struct Files {
+    dir: PathBuf,
+}
+
+impl Files {
+    fn new(script: &str) -> Self {
+        let dir = scratch();
+        fs::write(dir.join("schema.json"), SCHEMA).unwrap();
+        fs::write(dir.join("script.txt"), script).unwrap();
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn run(&self, vars: &[(&str, &str)], args: &[&str]) -> std::process::Output {
+        let schema = self.path("schema.json");
+        let script = self.path("script.txt");
+        let mut all = vec![
+            ("NOX_RAW", "1"),
+            ("NOX_JSON_SCHEMA_FILE", schema.as_str()),
+            ("NOX_SIM_SCRIPT", script.as_str()),
+        ];
+        all.extend_from_slice(vars);
+        nox(&all, args, "")
+    }
+}
+
+impl Drop for Files {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Answers with broken JSON until the prompt carries the schema, which
+/// only the corrective suffix appends.
+const PLAYBOOK: &str = "\
+match: \"required\": [\"ok\"]
+response: {\"ok\": true}
+
+response: Sure! {\"ok\": tru
+";
+
+#[test]
+fn an_invalid_answer_is_retried_with_the_schema_appended() {
+    let files = Files::new(PLAYBOOK);
+    let out = files.run(&[], &["give me json"]);
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), r#"{"ok": true}"#);
+    let stderr = text(&out.stderr);
+    assert_eq!(stderr.matches("is not valid JSON").count(), 1, "{stderr}");
+    assert!(stderr.contains("retry 1/2"), "{stderr}");
+}
+
+#[test]
+fn each_retry_takes_the_next_scripted_answer() {
+    let files = Files::new("no\nstill no\n{\"ok\": 3}\n");
+    let out = files.run(&[], &["give me json"]);
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), r#"{"ok": 3}"#);
+    let stderr = text(&out.stderr);
+    assert!(stderr.contains("retry 1/2"), "{stderr}");
+    assert!(stderr.contains("retry 2/2"), "{stderr}");
+}
+
+#[test]
+fn running_out_of_retries_prints_the_last_answer_and_exits_8() {
+    let files = Files::new("no\nstill no\n{\"ok\": 3}\n");
+    let out = files.run(&[("NOX_JSON_RETRIES", "1")], &["give me json"]);
+    assert_eq!(out.status.code(), Some(8), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "still no");
+    let stderr = text(&out.stderr);
+    assert!(stderr.contains("giving up after 2 attempt(s)"), "{stderr}");
+}
+
+#[test]
+fn batch_records_say_whether_the_output_was_valid() {
+    let files = Files::new(PLAYBOOK);
+    let prompts = files.path("prompts.txt");
+    fs::write(&prompts, "first\nsecond\n").unwrap();
+    let out = files.run(&[], &["--batch", &prompts]);
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let records: Vec<&str> = stdout.lines().collect();
+    assert_eq!(records.len(), 2, "{stdout}");
+    for record in records {
+        assert!(record.contains(r#""output":"{\"ok\": true}""#), "{record}");
+        assert!(record.ends_with(r#""valid_json":true}"#), "{record}");
+    }
+
+    let out = files.run(&[("NOX_JSON_RETRIES", "0")], &["--batch", &prompts]);
+    let stdout = text(&out.stdout);
+    for record in stdout.lines() {
+        assert!(record.ends_with(r#""valid_json":false}"#), "{record}");
+    }
+}
+
+#[test]
+fn a_grammar_and_a_schema_together_are_a_usage_error() {
+    let files = Files::new(PLAYBOOK);
+    let grammar = files.path("script.txt");
+    let out = files.run(&[("NOX_GRAMMAR_FILE", &grammar)], &["hi"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert_eq!(
+        text(&out.stderr),
+        "nox: NOX_GRAMMAR_FILE and NOX_JSON_SCHEMA_FILE are mutually exclusive\n"
+    );
+}
@@ -0,0 +1,160 @@
+//! `NOX_LISTEN`: two clients connected at once to one simulated runner.
+//! Both use id 1; each gets only its own answer, the later one is told it
+//! is queued, and a bad line from one client is an error for that client
+//! alone. A file already at the socket path is never replaced.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{nox, scratch, text, FAST};
+
+/// Each prompt gets its own answer, slow enough for the two to overlap.
+const PLAYBOOK: &str = "\
+match: alpha
+response: apple apple apple apple apple apple
+
+match: beta
+response: banana banana banana
+";
+
+struct Client {
+    stream: UnixStream,
+    lines: BufReader<UnixStream>,
+}
+
+impl Client {
+    fn connect(path: &Path) -> Self {
+        let stream = UnixStream::connect(path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(20)))
+            .unwrap();
+        let lines = BufReader::new(stream.try_clone().unwrap());
+        Self { stream, lines }
+    }
+
+    fn send(&mut self, line: &str) {
+        writeln!(self.stream, "{line}").unwrap();
+    }
+
+    /// Response lines up to and including the first `done` or `error` for
+    /// `id` (a JSON literal such as `1` or `null`).
+    fn until_end(&mut self, id: &str) -> Vec<String> {
+        let prefix = format!("{{\"id\":{id},");
+        let mut seen = Vec::new();
+        loop {
+            let mut line = String::new();
+            assert!(
+                self.lines.read_line(&mut line).unwrap() > 0,
+                "closed: {seen:?}"
+            );
+            let line = line.trim_end().to_string();
+            let end = line.starts_with(&prefix)
+                && (line.contains("\"type\":\"done\"") || line.contains("\"type\":\"error\""));
+            seen.push(line);
+            if end {
+                return seen;
+            }
+        }
+    }
+}
+
+/// The text of the `delta` lines for id 1.
+fn answer(lines: &[String]) -> String {
+    lines
+        .iter()
+        .filter_map(|l| l.strip_prefix("{\"id\":1,\"type\":\"delta\",\"text\":\""))
+        .map(|l| l.strip_suffix("\"}").unwrap())
+        .collect()
+}
+
+#[test]
+fn two_clients_share_the_runner_and_keep_their_own_ids() {
+    let dir = scratch();
+    let socket = dir.join("nox.sock");
+    let script = dir.join("playbook.txt");
+    fs::write(&script, PLAYBOOK).unwrap();
+    let server = Command::new(env!("CARGO_BIN_EXE_nox"))
+        .env_clear()
+        .envs(FAST)
+        .env("NOX_SIM_TPS", "20")
+        .env("NOX_SIM_SCRIPT", &script)
+        .env("NOX_LISTEN", &socket)
+        .current_dir(&dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    while UnixStream::connect(&socket).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(10), "no socket");
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mode = fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let mut a = Client::connect(&socket);
+    let mut b = Client::connect(&socket);
+    a.send("{\"id\":1,\"type\":\"prompt\",\"text\":\"alpha\"}");
+    thread::sleep(Duration::from_millis(100));
+    b.send("{\"id\":1,\"type\":\"prompt\",\"text\":\"beta\"}");
+    b.send(&"[".repeat(300_000));
+
+    let b_lines = b.until_end("1");
+    let a_lines = a.until_end("1");
+    assert_eq!(answer(&a_lines), "apple apple apple apple apple apple");
+    assert_eq!(answer(&b_lines), "banana banana banana");
+    assert!(a_lines.last().unwrap().contains("\"type\":\"done\""));
+    assert!(b_lines.last().unwrap().contains("\"type\":\"done\""));
+    assert!(
+        b_lines[0].starts_with("{\"id\":1,\"type\":\"queued\","),
+        "{b_lines:?}"
+    );
+    assert!(!a_lines.iter().any(|l| l.contains("queued")), "{a_lines:?}");
+    assert!(!a_lines.iter().any(|l| l.contains("null")), "{a_lines:?}");
+
+    // The bomb was answered while a's prompt ran, to b only, and b's
+    // session goes on.
+    assert_eq!(
+        b_lines[1],
+        "{\"id\":null,\"type\":\"error\",\"message\":\"nesting deeper than 128 at byte 128\"}"
+    );
+    b.send("{\"id\":2,\"type\":\"prompt\",\"text\":\"beta\"}");
+    let again = b.until_end("2");
+    assert!(
+        again.last().unwrap().contains("\"type\":\"done\""),
+        "{again:?}"
+    );
+
+    a.send("{\"type\":\"shutdown\"}");
+    let out = server.wait_with_output().unwrap();
+    let _ = fs::remove_dir_all(&dir);
+    assert!(out.status.success(), "{}", text(&out.stderr));
+}
+
+#[test]
+fn a_file_in_the_way_is_not_replaced() {
+    let dir = scratch();
+    let path = dir.join("nox.sock");
+    fs::write(&path, "keep me").unwrap();
+    let out = nox(&[("NOX_LISTEN", path.to_str().unwrap())], &[], "");
+    assert_eq!(out.status.code(), Some(2), "{}", text(&out.stderr));
+    assert_eq!(
+        text(&out.stderr),
+        format!(
+            "nox: NOX_LISTEN: {} exists and is not a socket\n",
+            path.display()
+        )
+    );
+    assert_eq!(fs::read_to_string(&path).unwrap(), "keep me");
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,140 @@
+//! No usable model is a pre-spawn error (exit 4) that names every path
+//! checked and why it was passed over, on stderr and in batch records.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+/// A runner that must never be started, and a models dir of decoys.
+struct Setup {
+    dir: PathBuf,
+    runner: String,
+    models: String,
+}
+
+impl Setup {
+    fn new() -> Self {
+        let dir = scratch();
+        let runner = dir.join("runner");
+        let spawned = dir.join("spawned");
+        fs::write(&runner, format!("#!/bin/sh\ntouch {}\n", spawned.display())).unwrap();
+        fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+        let models = dir.join("models");
+        fs::create_dir_all(models.join("folder.gguf")).unwrap();
+        fs::write(models.join("empty.gguf"), b"").unwrap();
+        fs::write(models.join("notes.txt"), b"GGUF").unwrap();
+        Self {
+            runner: runner.to_str().unwrap().to_string(),
+            models: models.to_str().unwrap().to_string(),
+            dir,
+        }
+    }
+
+    fn vars(&self, style: &'static str) -> Vec<(&'static str, &str)> {
+        vec![
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_RUNNER_STYLE", style),
+            ("NOX_LOCAL_RUNNER", &self.runner),
+            ("NOX_MODELS_DIR", &self.models),
+        ]
+    }
+
+    fn spawned(&self) -> bool {
+        self.dir.join("spawned").exists()
+    }
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn every_candidate_is_listed_with_its_reason() {
+    let setup = Setup::new();
+    for style in ["llama-simple", "llama-completion", "noxlocal"] {
+        let out = nox(&setup.vars(style), &["hi"], "");
+        assert_eq!(out.status.code(), Some(4), "{style}");
+        assert!(out.stdout.is_empty());
+        let stderr = text(&out.stderr);
+        let mut lines: Vec<&str> = stderr.lines().collect();
+        assert_eq!(
+            lines[0], "nox: model not found; 4 paths rejected",
+            "{style}"
+        );
+        assert!(lines
+            .pop()
+            .unwrap()
+            .starts_with("nox: hint: set NOX_MODEL_PATH"));
+        let mut listed = lines[1..].to_vec();
+        listed.sort();
+        let m = &setup.models;
+        assert_eq!(
+            listed,
+            [
+                "nox:   ../assets/models: missing".to_string(),
+                format!("nox:   {m}/empty.gguf: no GGUF header"),
+                format!("nox:   {m}/folder.gguf: not a file"),
+                "nox:   assets/models: missing".to_string(),
+            ]
+        );
+    }
+    assert!(
+        !setup.spawned(),
+        "the runner must not start without a model"
+    );
+}
+
+#[test]
+fn a_bad_model_path_names_the_setting() {
+    let setup = Setup::new();
+    let path = format!("{}/empty.gguf", setup.models);
+    let mut vars = setup.vars("llama-simple");
+    vars.push(("NOX_MODEL_PATH", &path));
+    let out = nox(&vars, &["hi"], "");
+    assert_eq!(out.status.code(), Some(4));
+    assert_eq!(
+        text(&out.stderr).lines().take(2).collect::<Vec<_>>(),
+        [
+            format!("nox: model not found (NOX_MODEL_PATH={path}); 1 path rejected"),
+            format!("nox:   {path}: no GGUF header"),
+        ]
+    );
+    assert!(!setup.spawned());
+}
+
+#[test]
+fn batch_records_carry_the_candidates() {
+    let setup = Setup::new();
+    let prompts = setup.dir.join("prompts.txt");
+    fs::write(&prompts, "hi\n").unwrap();
+    let out = nox(
+        &setup.vars("llama-simple"),
+        &["--batch", prompts.to_str().unwrap()],
+        "",
+    );
+    let stdout = text(&out.stdout);
+    assert!(
+        stdout.contains(r#""error":"model not found; 4 paths rejected","model_candidates":[{"#),
+        "{stdout}"
+    );
+    let m = &setup.models;
+    for candidate in [
+        format!(r#"{{"path":"{m}/empty.gguf","reason":"no GGUF header"}}"#),
+        format!(r#"{{"path":"{m}/folder.gguf","reason":"not a file"}}"#),
+        r#"{"path":"assets/models","reason":"missing"}"#.to_string(),
+        r#"{"path":"../assets/models","reason":"missing"}"#.to_string(),
+    ] {
+        assert!(
+            stdout.contains(&candidate),
+            "{candidate} missing from {stdout}"
+        );
+    }
+    assert!(!setup.spawned());
+}
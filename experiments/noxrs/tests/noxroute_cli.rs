@@ -0,0 +1,408 @@
+//! The `noxroute` binary as scripts see it: the one-line `--json` object,
+//! the bare values of `--quiet`, and the exit status, 0 when a value was
+//! selected, 1 when none parsed, and 2 for bad usage; and the `--csv` and
+//! `--lines` sources, whose errors point at a line and column; the
+//! `--bench` report; and `train --weights` read back by `--weights`.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+const VALUES: &str = "1.2,0.5,3.4,-1.0";
+
+/// Runs `noxroute args...` with nothing from the environment, writing
+/// `stdin` to it.
+fn noxroute(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_noxroute"))
+        .args(args)
+        .env_clear()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("noxroute starts");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// A file holding `contents` in a directory of this run's own.
+fn file(name: &str, contents: &str) -> PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::SeqCst);
+    let dir = env::temp_dir().join(format!("noxroute-it-{}-{run}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+/// `noxroute --json --csv` on a file holding `contents`: the exit status,
+/// the routed values or `None`, and stderr with the file's path as `CSV`.
+fn csv(contents: &str, column: &str) -> (Option<i32>, Option<Vec<f32>>, String) {
+    let path = file("values.csv", contents);
+    let out = noxroute(
+        &[
+            "--json",
+            "--csv",
+            path.to_str().unwrap(),
+            "--column",
+            column,
+        ],
+        "",
+    );
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    let stdout = text(&out.stdout);
+    let input = (!stdout.is_empty()).then(|| numbers(&stdout, "input"));
+    let stderr = text(&out.stderr).replace(path.to_str().unwrap(), "CSV");
+    (out.status.code(), input, stderr)
+}
+
+fn text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// The items of the flat array under `"key":` in `json`.
+fn array<'a>(json: &'a str, key: &str) -> Vec<&'a str> {
+    let head = format!("\"{key}\":[");
+    let at = json
+        .find(&head)
+        .unwrap_or_else(|| panic!("no {key} in {json}"))
+        + head.len();
+    let body = &json[at..at + json[at..].find(']').unwrap()];
+    if body.is_empty() {
+        return Vec::new();
+    }
+    body.split(',').collect()
+}
+
+fn numbers(json: &str, key: &str) -> Vec<f32> {
+    array(json, key)
+        .iter()
+        .map(|item| item.parse().unwrap())
+        .collect()
+}
+
+#[test]
+fn json_is_one_line_with_every_field() {
+    let out = noxroute(&["--json", VALUES], "");
+    assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 1, "{stdout}");
+    let json = lines[0];
+    assert!(
+        json.starts_with("{\"input\":[1.2,0.5,3.4,-1],\"probs\":["),
+        "{json}"
+    );
+    let keys: Vec<usize> = ["input", "probs", "mask", "perm", "selected", "rest"]
+        .iter()
+        .map(|key| json.find(&format!("\"{key}\":[")).unwrap())
+        .collect();
+    assert!(keys.windows(2).all(|w| w[0] < w[1]), "{json}");
+    assert!(json.ends_with("]}"), "{json}");
+
+    let probs = numbers(json, "probs");
+    assert_eq!(probs.len(), 4);
+    assert!(probs.iter().all(|p| (0.0..=1.0).contains(p)), "{json}");
+    let mask = array(json, "mask");
+    assert_eq!(mask.len(), 4);
+    assert!(mask.iter().all(|m| *m == "true" || *m == "false"), "{json}");
+    let mut perm: Vec<usize> = array(json, "perm")
+        .iter()
+        .map(|i| i.parse().unwrap())
+        .collect();
+    perm.sort_unstable();
+    assert_eq!(perm, [0, 1, 2, 3]);
+
+    // The selected values are the masked ones, in document order.
+    let input = numbers(json, "input");
+    let picked = |keep: bool| -> Vec<f32> {
+        input
+            .iter()
+            .zip(&mask)
+            .filter(|(_, m)| (**m == "true") == keep)
+            .map(|(v, _)| *v)
+            .collect()
+    };
+    let selected = numbers(json, "selected");
+    assert!(!selected.is_empty());
+    assert_eq!(selected, picked(true));
+    assert_eq!(numbers(json, "rest"), picked(false));
+}
+
+#[test]
+fn quiet_prints_only_the_selected_values() {
+    let json = text(&noxroute(&["--json", VALUES], "").stdout);
+    let selected: Vec<&str> = array(&json, "selected");
+
+    let out = noxroute(&["--quiet", VALUES], "");
+    assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), format!("{}\n", selected.join(",")));
+    assert!(out.stderr.is_empty(), "{}", text(&out.stderr));
+
+    // Stdin, for `$(... | noxroute --quiet)`.
+    let out = noxroute(&["--quiet"], VALUES);
+    assert_eq!(text(&out.stdout), format!("{}\n", selected.join(",")));
+}
+
+#[test]
+fn exit_codes_tell_selected_unparsed_and_bad_usage_apart() {
+    assert_eq!(noxroute(&[VALUES], "").status.code(), Some(0));
+
+    let out = noxroute(&["--json", "abc,def"], "");
+    assert_eq!(out.status.code(), Some(1));
+    assert!(out.stdout.is_empty());
+    assert_eq!(text(&out.stderr), "noxroute: no values to route\n");
+
+    for args in [
+        &["--json", "--quiet", VALUES][..],
+        &["--order", "sideways", VALUES],
+        &["--profile", "nope", VALUES],
+        &[],
+    ] {
+        let out = noxroute(args, "");
+        assert_eq!(out.status.code(), Some(2), "{args:?}");
+        assert!(out.stdout.is_empty(), "{args:?}");
+        let stderr = text(&out.stderr);
+        assert!(
+            stderr.starts_with("noxroute: ") || stderr.starts_with("usage: "),
+            "{stderr}"
+        );
+    }
+    assert_eq!(noxroute(&["--help"], "").status.code(), Some(0));
+}
+
+#[test]
+fn the_usage_describes_each_mode_itself() {
+    let out = noxroute(&["--help"], "");
+    let usage = text(&out.stderr);
+    for detail in [
+        "values;mask",
+        "NOX_ROUTE_WEIGHTS",
+        "--scorer overlap|tfidf|bm25",
+        "median split",
+        "QUERY<TAB>CHUNK",
+    ] {
+        assert!(usage.contains(detail), "{detail} missing from {usage}");
+    }
+    assert!(!usage.contains(".rs)"), "{usage}");
+}
+
+#[test]
+fn the_default_output_is_still_the_dump() {
+    let out = noxroute(&[VALUES], "");
+    let stdout = text(&out.stdout);
+    assert!(stdout.starts_with("config: "), "{stdout}");
+    assert!(
+        stdout.contains("\ninput: [1.2, 0.5, 3.4, -1.0]\n"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("\nselected+rest: "), "{stdout}");
+}
+
+#[test]
+fn a_headered_csv_gives_the_named_column() {
+    let (status, input, stderr) = csv(
+        "id,score,label\n1,0.9,a\n2,n/a,b\n\n3,0.1,c\n4,2.5,\"d, e\"\n",
+        "score",
+    );
+    assert_eq!(status, Some(0), "{stderr}");
+    assert_eq!(input.unwrap(), [0.9, 0.1, 2.5]);
+    assert_eq!(
+        stderr,
+        "noxroute: CSV: skipped 1 non-numeric cells, the first at line 3, column 2\n"
+    );
+
+    // Semicolons, and decimal commas with them.
+    let (status, input, _) = csv("id;score\n1;0,5\n2;3,25\n", "score");
+    assert_eq!(status, Some(0));
+    assert_eq!(input.unwrap(), [0.5, 3.25]);
+}
+
+#[test]
+fn a_headerless_csv_gives_the_column_at_an_index() {
+    let (status, input, stderr) = csv("1,0.9\n2,0.2\n3,3.5\n", "1");
+    assert_eq!(status, Some(0), "{stderr}");
+    assert_eq!(input.unwrap(), [0.9, 0.2, 3.5]);
+    assert!(stderr.is_empty(), "{stderr}");
+
+    // A header over an index is passed over.
+    let (_, input, _) = csv("id,score\n1,0.9\n2,0.2\n", "1");
+    assert_eq!(input.unwrap(), [0.9, 0.2]);
+}
+
+#[test]
+fn a_malformed_row_is_reported_at_its_line_and_column() {
+    let (status, input, stderr) = csv("a,b\n1,2\n3,\"4\n", "b");
+    assert_eq!(status, Some(1));
+    assert_eq!(input, None);
+    assert_eq!(
+        stderr,
+        "noxroute: CSV:3: column 3: the quote is never closed\n"
+    );
+
+    let (status, _, stderr) = csv("a,b\n1,2\n3\n", "b");
+    assert_eq!(status, Some(1));
+    assert_eq!(
+        stderr,
+        "noxroute: CSV:3: the row has no column 1 (counting from 0), only 1\n"
+    );
+
+    let (status, _, stderr) = csv("a,b\n1,2\n", "c");
+    assert_eq!(status, Some(1));
+    assert_eq!(
+        stderr,
+        "noxroute: CSV:1: no column named `c` in the header\n"
+    );
+}
+
+#[test]
+fn lines_routes_each_line_and_blanks_a_bad_one() {
+    let out = noxroute(&["--lines", "--quiet"], "1 2 3\n0.5,9\n4 x 6\n7\n");
+    assert_eq!(out.status.code(), Some(1));
+    assert_eq!(text(&out.stdout), "3\n9\n\n7\n");
+    assert_eq!(
+        text(&out.stderr),
+        "noxroute: stdin:3: column 3: `x` is not a number\n"
+    );
+
+    let out = noxroute(&["--lines", "--quiet"], "1 2 3\n0.5,9\n");
+    assert_eq!(out.status.code(), Some(0));
+}
+
+/// The number after the first `"key":` in `json`.
+fn number(json: &str, key: &str) -> f64 {
+    let head = format!("\"{key}\":");
+    let at = json
+        .find(&head)
+        .unwrap_or_else(|| panic!("no {key} in {json}"))
+        + head.len();
+    let end = json[at..].find([',', '}']).unwrap();
+    json[at..at + end].parse().unwrap()
+}
+
+#[test]
+fn a_tiny_bench_reports_positive_timings() {
+    let out = noxroute(&["--bench", "100", "--reps", "2", "--json"], "");
+    assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+    let json = text(&out.stdout);
+    assert!(
+        json.starts_with("{\"n\":100,\"reps\":2,\"kept\":"),
+        "{json}"
+    );
+    assert!(json.contains(",\"explained\":null,"), "{json}");
+    let route = &json[json.find("\"route\":{").unwrap()..];
+    for key in ["min_us", "median_us", "mean_us", "values_per_sec"] {
+        assert!(number(route, key) > 0.0, "{key} in {json}");
+    }
+    assert!(number(route, "min_us") <= number(route, "median_us"));
+    if cfg!(target_os = "linux") {
+        assert!(number(&json, "peak_rss_kib") > 0.0, "{json}");
+    }
+
+    // The values come from a fixed seed.
+    let again = text(&noxroute(&["--bench", "100", "--reps", "2", "--json"], "").stdout);
+    assert_eq!(number(&again, "kept"), number(&json, "kept"));
+
+    let out = noxroute(
+        &[
+            "--bench",
+            "100",
+            "--reps",
+            "2",
+            "--bench-explained",
+            "--json",
+        ],
+        "",
+    );
+    let json = text(&out.stdout);
+    let explained = &json[json.find("\"explained\":{").expect(&json)..];
+    assert!(number(explained, "median_us") > 0.0, "{json}");
+}
+
+/// Routes where only the scores of 0.6 or more are kept.
+const ROUTES: &str = "\
+0.9,0.1,0.7,0.3;1,0,1,0
+0.2,0.65,0.4;0,1,0
+0.8,0.95,0.1,0.5,0.61;1,1,0,0,1
+0.3,0.2;0,0
+";
+
+#[test]
+fn trained_weights_load_without_a_rebuild() {
+    let data = file("routes.txt", ROUTES);
+    let dir = data.parent().unwrap();
+    let weights = dir.join("router.txt");
+    let source = dir.join("routing_weights.rs");
+    let out = noxroute(
+        &[
+            "train",
+            data.to_str().unwrap(),
+            "--weights",
+            weights.to_str().unwrap(),
+            "--out",
+            source.to_str().unwrap(),
+            "--hidden",
+            "4",
+            "--epochs",
+            "3",
+            "--val",
+            "0",
+        ],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+    let file = fs::read_to_string(&weights).unwrap();
+    assert!(
+        file.starts_with("# Fitted by `noxroute train` on "),
+        "{file}"
+    );
+    assert!(file.contains("\nhidden 4\n"), "{file}");
+    assert!(fs::read_to_string(&source)
+        .unwrap()
+        .contains("pub const HIDDEN: usize = 4;\n"));
+
+    let route = |extra: &[&str]| {
+        let mut args = vec!["--json"];
+        args.extend_from_slice(extra);
+        args.push("0.9,0.2,0.7,0.4");
+        let out = noxroute(&args, "");
+        assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+        numbers(&text(&out.stdout), "probs")
+    };
+    let trained = route(&["--weights", weights.to_str().unwrap()]);
+    assert_ne!(trained, route(&[]));
+    // The file wins over a profile's weights, whichever flag comes first.
+    let profile_first = route(&[
+        "--profile",
+        "recall",
+        "--weights",
+        weights.to_str().unwrap(),
+    ]);
+    let file_first = route(&[
+        "--weights",
+        weights.to_str().unwrap(),
+        "--profile",
+        "recall",
+    ]);
+    assert_eq!(profile_first, trained);
+    assert_eq!(file_first, trained);
+
+    fs::write(&weights, "hidden 4\nw1 1 x\n").unwrap();
+    let out = noxroute(&["--weights", weights.to_str().unwrap(), "0.5"], "");
+    let stderr = text(&out.stderr);
+    fs::remove_dir_all(dir).unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(
+        stderr.ends_with("router.txt: line 2: `x` is not a number\n"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,183 @@
+//! `NOX_PERSIST=1` against a fake serve runner that crashes on the second
+//! prompt each process gets: the lost prompt is answered with an error
+//! line, a new runner resumes from the `-state-save` file, and a clean EOF
+//! on stdin ends the session without a restart.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+use common::{nox, scratch, text, FAST};
+
+/// Logs its arguments on start, saves state after each answer, and on its
+/// second prompt complains on stderr and exits 139 like a segfault.
+const RUNNER: &str = r#"#!/bin/sh
+[ "$1" = -h ] && exit 0
+echo "start $*" >> "$LOG"
+ctrl=$(printf '\005')
+n=0
+while IFS= read -r line; do
+  case "$line" in
+    "$ctrl"*) printf '\n<<<NOX_END>>>\n'; continue ;;
+  esac
+  n=$((n + 1))
+  echo "prompt $line" >> "$LOG"
+  if [ $n -eq 2 ]; then echo "fatal: bad access" >&2; exit 139; fi
+  printf 'got %s\n<<<NOX_END>>>\n' "$line"
+  while [ $# -gt 0 ]; do
+    [ "$1" = -state-save ] && printf STATE > "$2"
+    shift
+  done
+done
+"#;
+
+const LOST: &str = "nox: error: the runner crashed before answering \
+                    (runner exited with exit status: 139)\n<<<NOX_END>>>\n";
+
+/// A scratch dir holding the runner (logging to `calls.log` there) and a
+/// model file.
+fn fixture() -> PathBuf {
+    let dir = scratch();
+    let script = RUNNER.replacen(
+        "echo \"start",
+        &format!("LOG={}\necho \"start", dir.join("calls.log").display()),
+        1,
+    );
+    fs::write(dir.join("runner"), script).unwrap();
+    fs::set_permissions(dir.join("runner"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+    dir
+}
+
+fn calls(dir: &Path) -> Vec<String> {
+    let log = fs::read_to_string(dir.join("calls.log")).unwrap_or_default();
+    log.lines().map(str::to_string).collect()
+}
+
+/// A persistent session whose stdin stays open between prompts.
+struct Session {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    dir: PathBuf,
+}
+
+impl Session {
+    fn start(vars: &[(&str, &str)]) -> Self {
+        let dir = fixture();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_nox"))
+            .env_clear()
+            .envs(FAST)
+            .env("NOX_EMULATE_A1000", "0")
+            .env("NOX_PERSIST", "1")
+            .env("NOX_LOCAL_RUNNER", dir.join("runner"))
+            .env("NOX_MODEL_PATH", dir.join("m.gguf"))
+            .envs(vars.iter().copied())
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("nox starts");
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self { child, stdout, dir }
+    }
+
+    /// Sends `prompt` and reads its reply up to the end marker.
+    fn ask(&mut self, prompt: &str) -> String {
+        let stdin = self.child.stdin.as_mut().unwrap();
+        writeln!(stdin, "{prompt}").unwrap();
+        stdin.flush().unwrap();
+        let mut reply = String::new();
+        while !reply.ends_with("<<<NOX_END>>>\n") {
+            let read = self.stdout.read_line(&mut reply).unwrap();
+            assert!(read > 0, "stdout ended mid-reply: {reply:?}");
+        }
+        reply
+    }
+
+    /// Closes stdin and waits for nox, returning its exit code, stderr, and
+    /// what the runners logged.
+    fn finish(mut self) -> (Option<i32>, String, Vec<String>) {
+        drop(self.child.stdin.take());
+        let out = self.child.wait_with_output().unwrap();
+        let calls = calls(&self.dir);
+        let _ = fs::remove_dir_all(&self.dir);
+        (out.status.code(), text(&out.stderr), calls)
+    }
+}
+
+#[test]
+fn a_crash_answers_the_lost_prompt_and_the_next_runner_resumes() {
+    let mut session = Session::start(&[("NOX_STATE_SAVE", "state.bin")]);
+    assert_eq!(session.ask("first"), "got first\n<<<NOX_END>>>\n");
+    assert_eq!(session.ask("second"), LOST);
+    assert_eq!(session.ask("third"), "got third\n<<<NOX_END>>>\n");
+    let (code, stderr, calls) = session.finish();
+    assert_eq!(code, Some(0), "{stderr}");
+    assert!(
+        stderr.contains("nox: runner died: runner exited with exit status: 139"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("  fatal: bad access"), "{stderr}");
+    assert!(
+        stderr.contains("nox: restarted the runner (1/3 in the last 5 minutes)"),
+        "{stderr}"
+    );
+    let starts: Vec<&String> = calls.iter().filter(|c| c.starts_with("start ")).collect();
+    assert_eq!(starts.len(), 2, "{calls:?}");
+    assert!(!starts[0].contains("-state-load"), "{}", starts[0]);
+    assert!(starts[1].contains("-state-load state.bin"), "{}", starts[1]);
+    let prompts: Vec<&String> = calls.iter().filter(|c| c.starts_with("prompt ")).collect();
+    assert_eq!(prompts, ["prompt first", "prompt second", "prompt third"]);
+}
+
+#[test]
+fn no_restarts_left_ends_the_session() {
+    let mut session = Session::start(&[("NOX_MAX_RESTARTS", "0")]);
+    assert_eq!(session.ask("first"), "got first\n<<<NOX_END>>>\n");
+    assert_eq!(session.ask("second"), LOST);
+    let (code, stderr, calls) = session.finish();
+    assert_eq!(code, Some(6), "{stderr}");
+    assert!(
+        stderr.contains("nox: not restarting: 0 restarts"),
+        "{stderr}"
+    );
+    assert_eq!(calls.iter().filter(|c| c.starts_with("start ")).count(), 1);
+}
+
+#[test]
+fn a_clean_eof_shuts_down_without_a_restart() {
+    let dir = fixture();
+    let (runner, model) = (dir.join("runner"), dir.join("m.gguf"));
+    let out = nox(
+        &[
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_LOCAL_RUNNER", runner.to_str().unwrap()),
+            ("NOX_MODEL_PATH", model.to_str().unwrap()),
+        ],
+        &[],
+        "only\n",
+    );
+    let calls = calls(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    let stderr = text(&out.stderr);
+    assert_eq!(out.status.code(), Some(0), "{stderr}");
+    assert_eq!(text(&out.stdout), "got only\n<<<NOX_END>>>\n");
+    assert!(!stderr.contains("restart"), "{stderr}");
+    assert!(!stderr.contains("runner died"), "{stderr}");
+    assert_eq!(
+        calls,
+        [
+            "start -serve -ctx 1024 -max-tokens 128 -batch 1 -temp 0 -top-p 1 -top-k 1 -model "
+                .to_string()
+                + model.to_str().unwrap(),
+            "prompt only".to_string()
+        ]
+    );
+}
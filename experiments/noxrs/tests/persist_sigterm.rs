@@ -0,0 +1,157 @@
+//! SIGTERM in the middle of a `NOX_PERSIST=1` response: the response is
+//! finished, the runner gets EOF and writes its `-state-save` file, the
+//! transcript holds the whole turn, and nox exits 0. A second signal kills
+//! the runner without waiting. Plain EOF on stdin saves the same way.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use common::{nox, scratch, text, FAST};
+
+/// Answers each prompt in two halves `$PAUSE` seconds apart, and saves
+/// `STATE` only once its stdin ends.
+const RUNNER: &str = r#"#!/bin/bash
+[ "$1" = -h ] && exit 0
+save=
+while [ $# -gt 0 ]; do
+  [ "$1" = -state-save ] && save=$2
+  shift
+done
+while IFS= read -r line; do
+  case "$line" in
+    $'\005'*) printf '\n<<<NOX_END>>>\n'; continue ;;
+  esac
+  printf 'half of %s' "$line"
+  sleep "$PAUSE"
+  printf ', then the rest\n<<<NOX_END>>>\n'
+done
+printf STATE > "$save"
+"#;
+
+/// A runner and model in a scratch dir, the session's working directory.
+fn fixture() -> PathBuf {
+    let dir = scratch();
+    fs::write(dir.join("runner"), RUNNER).unwrap();
+    fs::set_permissions(dir.join("runner"), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+    dir
+}
+
+fn start(dir: &Path, pause: &str) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_nox"))
+        .env_clear()
+        .envs(FAST)
+        .env("NOX_EMULATE_A1000", "0")
+        .env("NOX_PERSIST", "1")
+        .env("NOX_LOCAL_RUNNER", dir.join("runner"))
+        .env("NOX_MODEL_PATH", dir.join("m.gguf"))
+        .env("NOX_STATE_SAVE", "state.bin")
+        .env("NOX_LOG_FILE", "nox.log")
+        .env("PAUSE", pause)
+        .current_dir(dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("nox starts")
+}
+
+/// Sends `prompt` and returns once the first half of its answer is out.
+fn ask_and_wait_for_half(child: &mut Child, prompt: &str) -> String {
+    let stdin = child.stdin.as_mut().unwrap();
+    writeln!(stdin, "{prompt}").unwrap();
+    stdin.flush().unwrap();
+    let stdout = child.stdout.as_mut().unwrap();
+    let mut seen = Vec::new();
+    let mut buf = [0; 256];
+    while !text(&seen).contains("half of") {
+        let n = stdout.read(&mut buf).unwrap();
+        assert!(n > 0, "stdout ended early: {:?}", text(&seen));
+        seen.extend_from_slice(&buf[..n]);
+    }
+    text(&seen)
+}
+
+fn signal(child: &Child, name: &str) {
+    let status = Command::new("kill")
+        .args([name, &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn sigterm_mid_response_finishes_it_and_saves() {
+    let dir = fixture();
+    let mut child = start(&dir, "0.5");
+    let half = ask_and_wait_for_half(&mut child, "hello");
+    signal(&child, "-TERM");
+    let out = child.wait_with_output().unwrap();
+    let stderr = text(&out.stderr);
+    let stdout = half + &text(&out.stdout);
+    let state = fs::read_to_string(dir.join("state.bin"));
+    let log = fs::read_to_string(dir.join("nox.log")).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(out.status.code(), Some(0), "{stderr}");
+    assert!(
+        stderr.contains("nox: terminating, letting the runner finish and save"),
+        "{stderr}"
+    );
+    assert_eq!(stdout, "half of hello, then the rest\n<<<NOX_END>>>\n");
+    assert_eq!(state.as_deref().ok(), Some("STATE"));
+    assert!(log.contains(">>> hello\n"), "{log}");
+    assert!(log.contains("half of hello, then the rest"), "{log}");
+}
+
+#[test]
+fn a_second_signal_kills_the_runner_at_once() {
+    let dir = fixture();
+    let mut child = start(&dir, "30");
+    ask_and_wait_for_half(&mut child, "hello");
+    let started = Instant::now();
+    signal(&child, "-TERM");
+    std::thread::sleep(Duration::from_millis(200));
+    signal(&child, "-TERM");
+    let out = child.wait_with_output().unwrap();
+    let saved = dir.join("state.bin").exists();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(out.status.code(), Some(130), "{}", text(&out.stderr));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert!(!saved, "a forced stop has no time to save");
+}
+
+#[test]
+fn a_plain_eof_saves_the_same_way() {
+    let dir = fixture();
+    let (runner, model) = (dir.join("runner"), dir.join("m.gguf"));
+    let state = dir.join("state.bin");
+    let out = nox(
+        &[
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_LOCAL_RUNNER", runner.to_str().unwrap()),
+            ("NOX_MODEL_PATH", model.to_str().unwrap()),
+            ("NOX_STATE_SAVE", state.to_str().unwrap()),
+            ("PAUSE", "0"),
+        ],
+        &[],
+        "hi\n",
+    );
+    let saved = fs::read_to_string(&state);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(out.status.code(), Some(0), "{}", text(&out.stderr));
+    assert_eq!(
+        text(&out.stdout),
+        "half of hi, then the rest\n<<<NOX_END>>>\n"
+    );
+    assert_eq!(saved.as_deref().ok(), Some("STATE"));
+}
@@ -0,0 +1,127 @@
+//! `NOX_PERSIST=1` against a scripted serve-mode runner whose first
+//! generation is slow, like a real one building its compute graph: the
+//! warmup control request absorbs that cost, so the first real prompt
+//! answers fast, and with `NOX_WARMUP=0` it does not.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+/// Logs each request, pays 600 ms on its first warmup or prompt, and
+/// answers prompts with `answer`.
+const RUNNER: &str = r#"#!/bin/sh
+[ "$1" = -h ] && exit 0
+ctrl=$(printf '\005')
+cold=yes
+warm() { if [ $cold = yes ]; then sleep 0.6; cold=no; fi; }
+while IFS= read -r line; do
+  case "$line" in
+    "$ctrl"warmup*) echo "control|${line#?}" >> "$LOG"; warm; printf '\n<<<NOX_END>>>\n' ;;
+    "$ctrl"*) echo "control|${line#?}" >> "$LOG"; printf '\n<<<NOX_END>>>\n' ;;
+    *) echo "prompt|$line" >> "$LOG"; warm; printf 'answer\n<<<NOX_END>>>\n' ;;
+  esac
+done
+"#;
+
+struct Fake {
+    dir: PathBuf,
+}
+
+impl Fake {
+    fn new() -> Self {
+        let dir = scratch();
+        let script = RUNNER.replacen(
+            "ctrl=",
+            &format!("LOG={}\nctrl=", dir.join("calls.log").display()),
+            1,
+        );
+        fs::write(dir.join("runner"), script).unwrap();
+        fs::set_permissions(dir.join("runner"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    /// Runs one persistent session over `hello`, returning the calls the
+    /// runner saw, the transcript records, and stderr.
+    fn session(&self, warmup: Option<&str>) -> (Vec<String>, Vec<String>, String) {
+        let (runner, model, log) = (
+            self.path("runner"),
+            self.path("m.gguf"),
+            self.path("t.jsonl"),
+        );
+        let mut vars = vec![
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_LOCAL_RUNNER", runner.as_str()),
+            ("NOX_MODEL_PATH", model.as_str()),
+            ("NOX_LOG_FILE", log.as_str()),
+        ];
+        if let Some(warmup) = warmup {
+            vars.push(("NOX_WARMUP", warmup));
+        }
+        let out = nox(&vars, &[], "hello\n");
+        assert!(out.status.success(), "{}", text(&out.stderr));
+        assert_eq!(text(&out.stdout).matches("answer").count(), 1);
+        let calls = fs::read_to_string(self.dir.join("calls.log")).unwrap();
+        let records = fs::read_to_string(&log).unwrap();
+        (
+            calls.lines().map(str::to_string).collect(),
+            records.lines().map(str::to_string).collect(),
+            text(&out.stderr),
+        )
+    }
+}
+
+impl Drop for Fake {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn ttft_ms(record: &str) -> u64 {
+    let at = record.find("\"ttft_ms\":").unwrap() + 10;
+    let digits: String = record[at..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().unwrap()
+}
+
+#[test]
+fn the_warmup_goes_first_and_takes_the_cold_start() {
+    let fake = Fake::new();
+    let (calls, records, stderr) = fake.session(None);
+    assert_eq!(calls, ["control|warmup  ", "prompt|hello"]);
+    assert!(stderr.contains("nox: ready "), "{stderr}");
+    assert_eq!(
+        records.len(),
+        1,
+        "the warmup must not be logged: {records:?}"
+    );
+    assert!(
+        records[0].contains("\"prompt\":\"hello\""),
+        "{}",
+        records[0]
+    );
+    let warm = ttft_ms(&records[0]);
+    assert!(warm < 400, "warm ttft {warm} ms");
+}
+
+#[test]
+fn without_warmup_the_first_prompt_pays_for_it() {
+    let fake = Fake::new();
+    let (calls, records, _) = fake.session(Some("0"));
+    assert_eq!(calls, ["control|ping", "prompt|hello"]);
+    assert_eq!(records.len(), 1);
+    let cold = ttft_ms(&records[0]);
+    assert!(cold >= 600, "cold ttft {cold} ms");
+}
@@ -0,0 +1,56 @@
+//! `NOX_PROGRESS=1` forces the throughput line on with piped streams: it is
+//! redrawn on stderr while the simulator streams, cleared at the end, and
+//! never reaches stdout. `--json` and `NOX_QUIET=1` still turn it off.
+
+mod common;
+
+use common::{nox, text};
+
+/// Twelve words at eight a second: long enough for two redraws.
+const SLOW: [(&str, &str); 4] = [
+    ("NOX_RAW", "1"),
+    ("NOX_SIM_TEXT", "a b c d e f g h i j k l"),
+    ("NOX_SIM_TPS", "8"),
+    ("NOX_PROGRESS", "1"),
+];
+
+#[test]
+fn the_line_is_redrawn_on_stderr_and_cleared() {
+    let out = nox(&SLOW, &["hi"], "");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "a b c d e f g h i j k l");
+    let stderr = text(&out.stderr);
+    let draws: Vec<&str> = stderr
+        .split("\r\x1b[2K")
+        .filter(|line| line.starts_with("⏳ "))
+        .collect();
+    assert!(draws.len() >= 2, "{stderr:?}");
+    for draw in &draws {
+        assert!(draw.contains(" tok/s  "), "{draw:?}");
+    }
+    let counts: Vec<u64> = draws
+        .iter()
+        .map(|d| d[4..].split(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(counts.windows(2).all(|w| w[0] <= w[1]), "{counts:?}");
+    assert!(*counts.last().unwrap() <= 12, "{counts:?}");
+    let after = stderr.rsplit("\r\x1b[2K").next().unwrap();
+    assert!(
+        !after.starts_with("⏳"),
+        "the last draw was not cleared: {stderr:?}"
+    );
+}
+
+#[test]
+fn json_and_quiet_turn_it_off() {
+    let json = nox(&SLOW, &["--json", "hi"], "");
+    assert!(json.status.success(), "{}", text(&json.stderr));
+    let mut vars = SLOW.to_vec();
+    vars.push(("NOX_QUIET", "1"));
+    let quiet = nox(&vars, &["hi"], "");
+    assert!(quiet.status.success(), "{}", text(&quiet.stderr));
+    for out in [json, quiet] {
+        assert!(!text(&out.stderr).contains('⏳'), "{}", text(&out.stderr));
+        assert!(!text(&out.stdout).contains('⏳'));
+    }
+}
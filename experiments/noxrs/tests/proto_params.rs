@@ -0,0 +1,75 @@
+//! `NOX_PERSIST_PROTO=jsonl` against a fake `-serve-params` runner that
+//! refuses every settings header: the request gets an error for its id,
+//! and the same runner answers the next one.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+use common::{nox, scratch, text};
+
+/// Echoes each prompt; a 0x06 header gets a 0x15 refusal instead.
+const RUNNER: &str = r#"#!/bin/bash
+if [ "$1" = -h ]; then echo '  -serve-params  take per-request settings'; exit 0; fi
+echo start >> "$LOG"
+while IFS= read -r -d $'\036' request; do
+  case "$request" in
+    $'\006'*) printf '\025request params: top-k must be >= 0\036' ;;
+    $'\005'*) printf '\036' ;;
+    *) printf 'echo %s\036' "$request" ;;
+  esac
+done
+"#;
+
+#[test]
+fn a_refused_header_is_an_error_for_that_id_only() {
+    let dir = scratch();
+    let log = dir.join("starts.log");
+    let runner = dir.join("runner");
+    let script = RUNNER.replacen(
+        "echo start",
+        &format!("LOG={}\necho start", log.display()),
+        1,
+    );
+    fs::write(&runner, script).unwrap();
+    fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+    fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+    let out = nox(
+        &[
+            ("NOX_EMULATE_A1000", "0"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_PERSIST_PROTO", "jsonl"),
+            ("NOX_LOCAL_RUNNER", runner.to_str().unwrap()),
+            ("NOX_MODEL_PATH", dir.join("m.gguf").to_str().unwrap()),
+        ],
+        &[],
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\",\"top_k\":40}\n\
+         {\"id\":2,\"type\":\"prompt\",\"text\":\"hi\"}\n",
+    );
+    let starts = fs::read_to_string(&log).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    // Queue depth reports depend on when id 2 arrives; leave them out.
+    let lines: Vec<&str> = stdout
+        .lines()
+        .skip(1)
+        .filter(|l| !l.starts_with("{\"type\":\"queue\","))
+        .collect();
+    assert_eq!(
+        lines[0],
+        "{\"id\":1,\"type\":\"error\",\"message\":\"runner rejected the request: \
+         request params: top-k must be >= 0\"}"
+    );
+    assert_eq!(
+        lines[1],
+        "{\"id\":2,\"type\":\"delta\",\"text\":\"echo hi\"}"
+    );
+    assert!(
+        lines[2].starts_with("{\"id\":2,\"type\":\"done\","),
+        "{stdout}"
+    );
+    assert_eq!(starts, "start\n", "the runner must not be replaced");
+}
@@ -0,0 +1,97 @@
+//! `NOX_QUEUE_MAX` on the jsonl protocol: a flood of prompts against a slow
+//! simulated runner fills the queue, the overflow is refused at once with
+//! `queue_full`, depth changes are reported, and the shutdown line counts
+//! what happened.
+
+mod common;
+
+use common::{nox, text};
+
+/// Eight prompts in one write, so all arrive while the first is running.
+fn flood() -> String {
+    (1..=8)
+        .map(|id| format!("{{\"id\":{id},\"type\":\"prompt\",\"text\":\"p{id}\"}}\n"))
+        .collect()
+}
+
+/// Runs the flood: the stdout lines other than deltas, and stderr.
+fn events(vars: &[(&str, &str)]) -> (Vec<String>, String) {
+    let mut all = vec![
+        ("NOX_PERSIST", "1"),
+        ("NOX_PERSIST_PROTO", "jsonl"),
+        ("NOX_SIM_TEXT", "a b c d e"),
+        ("NOX_SIM_TPS", "20"),
+    ];
+    all.extend_from_slice(vars);
+    let out = nox(&all, &[], &flood());
+    let stderr = text(&out.stderr);
+    assert!(out.status.success(), "{stderr}");
+    let lines = text(&out.stdout)
+        .lines()
+        .filter(|l| !l.contains("\"type\":\"delta\""))
+        .map(str::to_string)
+        .collect();
+    (lines, stderr)
+}
+
+fn done(lines: &[String]) -> Vec<&str> {
+    lines
+        .iter()
+        .filter(|l| l.contains("\"type\":\"done\""))
+        .map(|l| &l[..l.find(',').unwrap()])
+        .collect()
+}
+
+#[test]
+fn a_flood_past_the_limit_is_refused_and_counted() {
+    let (lines, stderr) = events(&[("NOX_QUEUE_MAX", "2")]);
+    let refused: Vec<&String> = lines
+        .iter()
+        .filter(|l| l.contains("\"reason\":\"queue_full\""))
+        .collect();
+    assert_eq!(refused.len(), 5, "{lines:#?}");
+    for (line, id) in refused.iter().zip(4..) {
+        assert_eq!(
+            **line,
+            format!(
+                "{{\"id\":{id},\"type\":\"error\",\"reason\":\"queue_full\",\
+                 \"message\":\"queue full (2 waiting, NOX_QUEUE_MAX)\"}}"
+            )
+        );
+    }
+    // Refusals come out while prompt 1 is still running.
+    let first_done = lines
+        .iter()
+        .position(|l| l.starts_with("{\"id\":1,"))
+        .unwrap();
+    let last_refusal = lines
+        .iter()
+        .rposition(|l| l.contains("queue_full"))
+        .unwrap();
+    assert!(last_refusal < first_done, "{lines:#?}");
+    assert_eq!(done(&lines), ["{\"id\":1", "{\"id\":2", "{\"id\":3"]);
+
+    let depths: Vec<&str> = lines
+        .iter()
+        .filter_map(|l| l.strip_prefix("{\"type\":\"queue\",\"depth\":"))
+        .collect();
+    assert_eq!(depths, ["1}", "2}", "1}", "0}"]);
+    assert!(
+        stderr.contains("nox: 3 answered, queue max depth 2, 5 rejected as queue_full"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn the_default_limit_holds_the_whole_flood() {
+    let (lines, stderr) = events(&[]);
+    assert!(
+        !lines.iter().any(|l| l.contains("queue_full")),
+        "{lines:#?}"
+    );
+    assert_eq!(done(&lines).len(), 8);
+    assert!(
+        stderr.contains("nox: 8 answered, queue max depth 7, 0 rejected as queue_full"),
+        "{stderr}"
+    );
+}
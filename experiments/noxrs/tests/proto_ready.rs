@@ -0,0 +1,215 @@
+//! Readiness in persistent mode against a fake runner that takes `$LOAD_S`
+//! seconds to load its model, or fails to load it when `$LOAD` is `fail`:
+//! the ready line comes once, after loading; a failed load gets an error
+//! with the runner's stderr instead and a non-zero exit; and `ping` reports
+//! uptime and the prompts answered so far.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use common::{nox, scratch, text, FAST};
+
+/// Echoes prompts, framed with 0x1e under `-serve-rs` and with the end
+/// marker otherwise; control requests get an empty reply.
+const RUNNER: &str = r#"#!/bin/bash
+[ "$1" = -h ] && exit 0
+if [ "$LOAD" = fail ]; then
+  echo "llama_model_load: loading tensors" >&2
+  echo "llama_model_load: error loading model: tensor data is not within file bounds" >&2
+  exit 1
+fi
+sleep "$LOAD_S"
+case " $* " in
+  *" -serve-rs "*) end=$'\036'; sep=$'\036' ;;
+  *) end=$'\n<<<NOX_END>>>\n'; sep=$'\n' ;;
+esac
+while IFS= read -r -d "$sep" request; do
+  case "$request" in
+    $'\005'*) printf '%s' "$end" ;;
+    *) printf 'echo %s%s' "$request" "$end" ;;
+  esac
+done
+"#;
+
+const BAD_LOAD: &str =
+    "llama_model_load: error loading model: tensor data is not within file bounds";
+
+/// A runner and model in a scratch dir.
+struct Fake {
+    dir: PathBuf,
+}
+
+impl Fake {
+    fn new() -> Self {
+        let dir = scratch();
+        fs::write(dir.join("runner"), RUNNER).unwrap();
+        fs::set_permissions(dir.join("runner"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    fn vars(&self, load: &'static str, jsonl: bool) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("NOX_EMULATE_A1000", "0".to_string()),
+            ("NOX_PERSIST", "1".to_string()),
+            ("NOX_LOCAL_RUNNER", self.path("runner")),
+            ("NOX_MODEL_PATH", self.path("m.gguf")),
+            ("LOAD", load.to_string()),
+            ("LOAD_S", "0.3".to_string()),
+        ];
+        if jsonl {
+            vars.push(("NOX_PERSIST_PROTO", "jsonl".to_string()));
+        }
+        vars
+    }
+
+    fn run(&self, load: &'static str, jsonl: bool, stdin: &str) -> std::process::Output {
+        let vars = self.vars(load, jsonl);
+        let vars: Vec<(&str, &str)> = vars.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        nox(&vars, &[], stdin)
+    }
+}
+
+impl Drop for Fake {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// The number after `"key":` in a response line.
+fn field(line: &str, key: &str) -> u64 {
+    let at = line.find(&format!("\"{key}\":")).unwrap() + key.len() + 3;
+    let digits: String = line[at..]
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    digits.parse().unwrap()
+}
+
+#[test]
+fn jsonl_reports_ready_once_after_the_model_loads() {
+    let fake = Fake::new();
+    let out = fake.run(
+        "ok",
+        true,
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let model = fake.path("m.gguf");
+    let head = format!("{{\"type\":\"ready\",\"model\":\"{model}\",\"ctx\":1024,\"load_ms\":");
+    assert!(lines[0].starts_with(&head), "{stdout}");
+    assert!(field(lines[0], "load_ms") >= 300, "{}", lines[0]);
+    assert_eq!(stdout.matches("\"ready\"").count(), 1, "{stdout}");
+    assert_eq!(
+        lines[1],
+        "{\"id\":1,\"type\":\"delta\",\"text\":\"echo hi\"}"
+    );
+}
+
+#[test]
+fn jsonl_reports_a_failed_load_instead_of_ready() {
+    let fake = Fake::new();
+    let out = fake.run(
+        "fail",
+        true,
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}\n",
+    );
+    assert_eq!(out.status.code(), Some(6), "{}", text(&out.stderr));
+    assert_eq!(
+        text(&out.stdout),
+        format!(
+            "{{\"type\":\"error\",\"reason\":\"load_failed\",\
+             \"message\":\"runner exited with exit status: 1\",\
+             \"stderr\":[\"llama_model_load: loading tensors\",\"{BAD_LOAD}\"]}}\n"
+        )
+    );
+}
+
+#[test]
+fn plain_mode_reports_ready_or_the_failed_load_on_stderr() {
+    let fake = Fake::new();
+    let out = fake.run("ok", false, "hi\n");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "echo hi\n<<<NOX_END>>>\n");
+    let stderr = text(&out.stderr);
+    let ready: Vec<&str> = stderr
+        .lines()
+        .filter(|l| l.starts_with("nox: ready "))
+        .collect();
+    assert_eq!(ready.len(), 1, "{stderr}");
+    let model = fake.path("m.gguf");
+    assert!(
+        ready[0].starts_with(&format!("nox: ready model={model} ctx=1024 load_ms=")),
+        "{stderr}"
+    );
+    let load_ms: u64 = ready[0].rsplit("load_ms=").next().unwrap().parse().unwrap();
+    assert!(load_ms >= 300, "{stderr}");
+
+    let out = fake.run("fail", false, "hi\n");
+    assert_eq!(out.status.code(), Some(6));
+    assert!(out.stdout.is_empty());
+    let stderr = text(&out.stderr);
+    assert!(!stderr.contains("nox: ready"), "{stderr}");
+    assert!(
+        stderr.contains("nox: the runner died while loading the model"),
+        "{stderr}"
+    );
+    assert!(stderr.contains(&format!("  {BAD_LOAD}\n")), "{stderr}");
+}
+
+#[test]
+fn ping_reports_uptime_and_prompts_answered() {
+    let fake = Fake::new();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_nox"))
+        .env_clear()
+        .envs(FAST)
+        .envs(fake.vars("ok", true))
+        .current_dir(&fake.dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("nox starts");
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    let mut next = || {
+        let mut line = String::new();
+        stdout.read_line(&mut line).unwrap();
+        line
+    };
+    assert!(next().starts_with("{\"type\":\"ready\","));
+
+    writeln!(stdin, "{{\"id\":\"a\",\"type\":\"ping\"}}").unwrap();
+    let pong = next();
+    assert!(
+        pong.starts_with("{\"id\":\"a\",\"type\":\"pong\",\"uptime_ms\":"),
+        "{pong}"
+    );
+    assert!(field(&pong, "uptime_ms") >= 300, "{pong}");
+    assert!(pong.ends_with(",\"requests\":0}\n"), "{pong}");
+
+    writeln!(stdin, "{{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}}").unwrap();
+    assert!(next().starts_with("{\"id\":1,\"type\":\"delta\","));
+    assert!(next().starts_with("{\"id\":1,\"type\":\"done\","));
+
+    writeln!(stdin, "{{\"type\":\"ping\"}}").unwrap();
+    let pong = next();
+    assert!(pong.starts_with("{\"type\":\"pong\","), "{pong}");
+    assert!(pong.ends_with(",\"requests\":1}\n"), "{pong}");
+
+    drop(stdin);
+    let out = child.wait_with_output().unwrap();
+    assert!(out.status.success(), "{}", text(&out.stderr));
+}
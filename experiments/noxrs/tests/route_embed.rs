@@ -0,0 +1,127 @@
+//! `NOX_ROUTE_SCORER=embed` against a mock `llama-embedding` that gives
+//! fixed vectors: cosine similarity picks the chunk that paraphrases the
+//! query over the one that only shares a word with it, and a runner that
+//! fails leaves the choice to word overlap, with a warning.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+/// Splits its `-p` text on the record separator and prints one vector per
+/// text: cars point one way, parks the other.
+const EMBED: &str = r#"#!/bin/bash
+[ "$1" = -h ] && exit 0
+while [ $# -gt 0 ]; do
+  [ "$1" = -p ] && prompt=$2
+  shift
+done
+printf 'llama_model_loader: loaded meta data\n{"data":['
+sep=
+while IFS= read -r -d $'\036' text || [ -n "$text" ]; do
+  case "$text" in
+    *car*|*automobile*) vector='[1,0.2]' ;;
+    *pond*) vector='[0,1]' ;;
+    *) vector='[0.1,0.1]' ;;
+  esac
+  printf '%s{"embedding":%s}' "$sep" "$vector"
+  sep=,
+done <<< "$prompt"
+printf ']}\n'
+"#;
+
+/// Prints the prompt it was given, after a label so nox does not take it
+/// for llama-simple's echo of the prompt.
+const RUNNER: &str = "#!/bin/sh
+[ \"$1\" = -h ] && exit 0
+for last; do :; done
+printf 'got: %s\\n' \"$last\"
+";
+
+const PROMPT: &str = "where can I park my car?\n---\n\
+                      the park has a lovely pond\n---\n\
+                      automobiles may be left in lot B";
+
+struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    fn new() -> Self {
+        let dir = scratch();
+        for (name, script) in [
+            ("embed", EMBED),
+            ("runner", RUNNER),
+            ("broken", "#!/bin/sh\nexit 3\n"),
+        ] {
+            fs::write(dir.join(name), script).unwrap();
+            fs::set_permissions(dir.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+        Self { dir }
+    }
+
+    fn path(&self, name: &str) -> String {
+        self.dir.join(name).to_str().unwrap().to_string()
+    }
+
+    /// Routes [`PROMPT`] keeping one chunk, embedding with `embed`.
+    fn route(&self, embed: &str) -> (String, String) {
+        let (runner, model, embed) = (self.path("runner"), self.path("m.gguf"), self.path(embed));
+        let out = nox(
+            &[
+                ("NOX_EMULATE_A1000", "0"),
+                ("NOX_RUNNER_STYLE", "llama-simple"),
+                ("NOX_LOCAL_RUNNER", &runner),
+                ("NOX_MODEL_PATH", &model),
+                ("NOX_ROUTE", "1"),
+                ("NOX_ROUTE_KEEP", "1"),
+                ("NOX_ROUTE_SCORER", "embed"),
+                ("NOX_ROUTE_EMBED_RUNNER", &embed),
+            ],
+            &[PROMPT],
+            "",
+        );
+        assert!(out.status.success(), "{}", text(&out.stderr));
+        (text(&out.stdout), text(&out.stderr))
+    }
+}
+
+impl Drop for Fixtures {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn cosine_similarity_picks_the_paraphrase() {
+    let fixtures = Fixtures::new();
+    let (stdout, stderr) = fixtures.route("embed");
+    assert_eq!(
+        stdout, "got: where can I park my car?\n---\nautomobiles may be left in lot B\n",
+        "{stderr}"
+    );
+    assert!(!stderr.contains("warning"), "{stderr}");
+}
+
+#[test]
+fn a_failed_embedding_falls_back_to_word_overlap() {
+    let fixtures = Fixtures::new();
+    let (stdout, stderr) = fixtures.route("broken");
+    assert_eq!(
+        stdout,
+        "got: where can I park my car?\n---\nthe park has a lovely pond\n"
+    );
+    assert!(
+        stderr.contains("nox: warning: could not embed the chunks ("),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("scoring by word overlap instead"),
+        "{stderr}"
+    );
+}
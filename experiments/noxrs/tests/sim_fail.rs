@@ -0,0 +1,124 @@
+//! `NOX_SIM_FAIL`: each mode, seen from outside, one-shot and in the
+//! `NOX_PERSIST_PROTO=jsonl` protocol.
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use common::{nox, text};
+
+const SIX: (&str, &str) = ("NOX_SIM_TEXT", "one two three four five six");
+
+/// What `NOX_PERSIST_PROTO=jsonl` streams for prompt 1, however the deltas
+/// happen to be cut, and the line that ends it.
+fn jsonl(fail: &str) -> (String, String) {
+    let out = nox(
+        &[
+            SIX,
+            ("NOX_SIM_FAIL", fail),
+            ("NOX_PERSIST", "1"),
+            ("NOX_PERSIST_PROTO", "jsonl"),
+            ("NOX_STALL_SECS", "1"),
+        ],
+        &[],
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let mut lines: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with("{\"id\":1,"))
+        .collect();
+    let last = lines.pop().unwrap_or_default().to_string();
+    let streamed = lines
+        .iter()
+        .map(|line| {
+            let text = line
+                .strip_prefix("{\"id\":1,\"type\":\"delta\",\"text\":\"")
+                .and_then(|rest| rest.strip_suffix("\"}"))
+                .unwrap_or_else(|| panic!("not a delta: {line}"));
+            text.to_string()
+        })
+        .collect();
+    (streamed, last)
+}
+
+#[test]
+fn exit_fails_before_any_output() {
+    let out = nox(&[SIX, ("NOX_SIM_FAIL", "exit:4")], &["hi"], "");
+    assert_eq!(out.status.code(), Some(6));
+    assert_eq!(text(&out.stdout), "");
+    assert!(text(&out.stderr).contains("runner exited with exit status: 4"));
+    assert_eq!(
+        jsonl("exit:4"),
+        (
+            String::new(),
+            r#"{"id":1,"type":"error","message":"runner exited with exit status: 4"}"#.to_string()
+        )
+    );
+}
+
+#[test]
+fn midstream_leaves_the_first_chunks() {
+    let out = nox(&[SIX, ("NOX_SIM_FAIL", "midstream:2")], &["hi"], "");
+    assert_eq!(out.status.code(), Some(6));
+    assert_eq!(text(&out.stdout), "nox:\none two");
+    let stderr = text(&out.stderr);
+    assert!(stderr.contains("simulated runner failure after 2 chunks (NOX_SIM_FAIL)"));
+    assert!(stderr.contains("runner exited with exit status: 1"));
+    assert_eq!(
+        jsonl("midstream:2"),
+        (
+            "one two".to_string(),
+            r#"{"id":1,"type":"error","message":"runner exited with exit status: 1"}"#.to_string()
+        )
+    );
+}
+
+#[test]
+fn hang_trips_the_stall_timeout() {
+    let started = Instant::now();
+    let out = nox(
+        &[SIX, ("NOX_SIM_FAIL", "hang"), ("NOX_STALL_SECS", "1")],
+        &["hi"],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(7));
+    assert!(started.elapsed() < Duration::from_secs(10));
+    assert_eq!(text(&out.stdout), "nox:\none");
+    assert!(text(&out.stderr).contains("runner stalled: no output for 1s (NOX_STALL_SECS)"));
+    assert_eq!(
+        jsonl("hang"),
+        (
+            "one".to_string(),
+            r#"{"id":1,"type":"error","message":"runner stalled: no output for 1s (NOX_STALL_SECS)"}"#
+                .to_string()
+        )
+    );
+}
+
+#[test]
+fn garbage_reaches_the_decoder() {
+    let out = nox(&[SIX, ("NOX_SIM_FAIL", "garbage")], &["hi"], "");
+    assert!(out.status.success());
+    assert_eq!(
+        out.stdout,
+        b"nox:\none two three\xff\xfe\xc3 four five six\n"
+    );
+    // Each bad byte is one replacement character, and the reply goes on.
+    let (streamed, last) = jsonl("garbage");
+    assert_eq!(
+        streamed,
+        "one two three\u{fffd}\u{fffd}\u{fffd} four five six"
+    );
+    assert!(last.contains("\"type\":\"done\""), "{last}");
+}
+
+#[test]
+fn a_bad_mode_is_a_usage_error() {
+    let out = nox(&[("NOX_SIM_FAIL", "explode")], &["hi"], "");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(text(&out.stderr).contains(
+        "NOX_SIM_FAIL `explode`: unknown mode `explode` (expected exit:N, midstream:K, hang, or garbage)"
+    ));
+}
@@ -0,0 +1,232 @@
+//! `NOX_PERSIST=1` against the simulator standing in for the `-serve`
+//! child: two prompts through each framing, per-request settings, and a
+//! restart after the child dies.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use common::{nox, scratch, text, FAST};
+
+const REPLY: (&str, &str) = ("NOX_SIM_TEXT", "a b c");
+
+#[test]
+fn two_prompts_in_plain_text() {
+    let out = nox(&[REPLY, ("NOX_PERSIST", "1")], &[], "first\nsecond\n");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let reply = "nox:\na b c\n\n<<<NOX_END>>>\n";
+    assert_eq!(text(&out.stdout), reply.repeat(2));
+    assert!(text(&out.stderr).contains("nox: ready model=- ctx="));
+}
+
+#[test]
+fn two_prompts_framed_with_record_separators() {
+    let out = nox(
+        &[REPLY, ("NOX_PERSIST", "1"), ("NOX_PERSIST_RS", "1")],
+        &[],
+        "first\nline two\x1esecond\x1e",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "nox:\na b c\n\x1e".repeat(2));
+}
+
+#[test]
+fn a_setting_line_applies_to_the_next_prompt_only() {
+    let out = nox(
+        &[REPLY, ("NOX_PERSIST", "1")],
+        &[],
+        "!max_tokens 1\nfirst\nsecond\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(
+        text(&out.stdout),
+        "nox:\na\n\n<<<NOX_END>>>\nnox:\na b c\n\n<<<NOX_END>>>\n"
+    );
+}
+
+#[test]
+fn two_prompts_over_jsonl_with_their_own_settings() {
+    let out = nox(
+        &[REPLY, ("NOX_PERSIST", "1"), ("NOX_PERSIST_PROTO", "jsonl")],
+        &[],
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"first\",\"max_tokens\":2}\n\
+         {\"id\":2,\"type\":\"prompt\",\"text\":\"second\"}\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    let answer = |id| streamed(&stdout, id);
+    assert_eq!(answer(1), ("a b".to_string(), true));
+    assert_eq!(answer(2), ("a b c".to_string(), true));
+    assert!(stdout.starts_with("{\"type\":\"ready\","), "{stdout}");
+}
+
+#[test]
+fn jsonl_overrides_do_not_leak_into_the_next_request() {
+    let out = nox(
+        &[REPLY, ("NOX_PERSIST", "1"), ("NOX_PERSIST_PROTO", "jsonl")],
+        &[],
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"one\",\"max_tokens\":1,\"temp\":0.7}\n\
+         {\"id\":2,\"type\":\"prompt\",\"text\":\"two\"}\n\
+         {\"id\":3,\"type\":\"prompt\",\"text\":\"three\",\"top_p\":1.5}\n\
+         {\"id\":4,\"type\":\"prompt\",\"text\":\"four\",\"max_tokens\":2}\n\
+         {\"id\":5,\"type\":\"prompt\",\"text\":\"five\"}\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    assert_eq!(streamed(&stdout, 1), ("a".to_string(), true));
+    assert_eq!(streamed(&stdout, 2), ("a b c".to_string(), true));
+    assert!(
+        stdout.contains(
+            "{\"id\":3,\"type\":\"error\",\"message\":\"top_p must be within [0, 1], got 1.5\"}"
+        ),
+        "{stdout}"
+    );
+    assert_eq!(streamed(&stdout, 4), ("a b".to_string(), true));
+    assert_eq!(streamed(&stdout, 5), ("a b c".to_string(), true));
+    let stderr = text(&out.stderr);
+    assert!(!stderr.contains("restart"), "{stderr}");
+}
+
+#[test]
+fn byte_chunks_reach_jsonl_clients_whole() {
+    let reply = "déjà vu 🦀 日本語";
+    let out = nox(
+        &[
+            ("NOX_SIM_TEXT", reply),
+            ("NOX_SIM_CHUNKING", "bytes:1"),
+            ("NOX_PERSIST", "1"),
+            ("NOX_PERSIST_PROTO", "jsonl"),
+        ],
+        &[],
+        "{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}\n",
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    // Split sequences are held back until they are whole, never replaced.
+    assert_eq!(streamed(&text(&out.stdout), 1), (reply.to_string(), true));
+}
+
+/// The deltas for prompt `id` in jsonl output, joined, and whether a
+/// `done` ended them.
+fn streamed(stdout: &str, id: u32) -> (String, bool) {
+    let prefix = format!("{{\"id\":{id},");
+    let lines: Vec<&str> = stdout
+        .lines()
+        .filter(|line| line.starts_with(&prefix))
+        .collect();
+    let (last, deltas) = lines.split_last().unwrap();
+    let text = deltas
+        .iter()
+        .map(|line| {
+            line.strip_prefix(&format!("{prefix}\"type\":\"delta\",\"text\":\""))
+                .and_then(|rest| rest.strip_suffix("\"}"))
+                .unwrap_or_else(|| panic!("not a delta: {line}"))
+        })
+        .collect();
+    (text, last.contains("\"type\":\"done\""))
+}
+
+/// A persistent session whose stdin stays open between prompts, so a
+/// runner that dies is restarted rather than taken for the end.
+struct Session {
+    child: std::process::Child,
+    stdout: BufReader<std::process::ChildStdout>,
+    dir: PathBuf,
+}
+
+impl Session {
+    fn start(vars: &[(&str, &str)]) -> Self {
+        let dir = scratch();
+        let mut child = Command::new(env!("CARGO_BIN_EXE_nox"))
+            .env_clear()
+            .envs(FAST)
+            .envs(vars.iter().copied())
+            .env("NOX_PERSIST", "1")
+            .current_dir(&dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("nox starts");
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self { child, stdout, dir }
+    }
+
+    /// Sends `prompt` and reads its reply up to the end marker.
+    fn ask(&mut self, prompt: &str) -> String {
+        let stdin = self.child.stdin.as_mut().unwrap();
+        writeln!(stdin, "{prompt}").unwrap();
+        stdin.flush().unwrap();
+        let mut reply = String::new();
+        while !reply.ends_with("<<<NOX_END>>>\n") {
+            let read = self.stdout.read_line(&mut reply).unwrap();
+            assert!(read > 0, "stdout ended mid-reply: {reply:?}");
+        }
+        reply
+    }
+
+    /// Closes stdin and waits for nox to exit, returning its exit code and
+    /// stderr.
+    fn finish(mut self) -> (Option<i32>, String) {
+        drop(self.child.stdin.take());
+        let out = self.child.wait_with_output().unwrap();
+        let _ = std::fs::remove_dir_all(&self.dir);
+        (out.status.code(), text(&out.stderr))
+    }
+}
+
+#[test]
+fn a_dead_runner_is_restarted_for_the_next_prompt() {
+    let mut session = Session::start(&[
+        REPLY,
+        ("NOX_SIM_FAIL", "midstream:1"),
+        ("NOX_MAX_RESTARTS", "1"),
+    ]);
+    let lost = "\nnox: error: the runner crashed before answering \
+                (runner exited with exit status: 1)\n<<<NOX_END>>>\n";
+    assert_eq!(session.ask("first"), format!("nox:\na{lost}"));
+    // The restarted runner fails the same way, and there are no more
+    // restarts left after it.
+    assert_eq!(session.ask("second"), format!("nox:\na{lost}"));
+    let (code, stderr) = session.finish();
+    assert_eq!(code, Some(6));
+    assert!(stderr.contains("nox: restarted the runner (1/1 in the last 5 minutes)"));
+    assert!(stderr.contains("nox: not restarting: 1 restarts"));
+}
+
+#[test]
+fn an_idle_session_ends_on_its_own() {
+    let mut session = Session::start(&[REPLY, ("NOX_IDLE_TIMEOUT_SECS", "1")]);
+    assert_eq!(session.ask("first"), "nox:\na b c\n\n<<<NOX_END>>>\n");
+    // Polled rather than waited for, as waiting would close its stdin.
+    let started = Instant::now();
+    while session.child.try_wait().unwrap().is_none() {
+        assert!(started.elapsed() < Duration::from_secs(10), "still running");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    let (code, stderr) = session.finish();
+    assert_eq!(code, Some(0));
+    assert!(stderr.contains("nox: idle timeout"), "{stderr}");
+}
+
+#[test]
+fn a_deeply_nested_line_is_rejected_and_the_session_goes_on() {
+    let bomb = "[".repeat(300_000);
+    let out = nox(
+        &[REPLY, ("NOX_PERSIST", "1"), ("NOX_PERSIST_PROTO", "jsonl")],
+        &[],
+        &format!("{bomb}\n{{\"id\":1,\"type\":\"prompt\",\"text\":\"hi\"}}\n"),
+    );
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    let stdout = text(&out.stdout);
+    assert!(
+        stdout.contains(
+            "{\"id\":null,\"type\":\"error\",\"message\":\"nesting deeper than 128 at byte 128\"}"
+        ),
+        "{stdout}"
+    );
+    assert_eq!(streamed(&stdout, 1), ("a b c".to_string(), true));
+}
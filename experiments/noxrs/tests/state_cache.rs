@@ -0,0 +1,148 @@
+//! `NOX_STATE_AUTO=1` against a fake noxlocal that records, per call,
+//! whether it was asked to save or load prompt state and what prompt it got.
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+const PROMPT: &str = "You are terse.\n\nWhat is 2+2?";
+
+/// Logs `save|load|plain` and the prompt (newlines as `~`), saves `STATE`
+/// when asked, and dies without output when the state it loads is not that.
+const RUNNER: &str = r#"#!/bin/sh
+mode=plain
+while [ $# -gt 1 ]; do
+  case "$1" in
+    -state-save) mode=save; printf STATE > "$2"; shift ;;
+    -state-load) mode=load
+      if [ "$(cat "$2")" != STATE ]; then echo rejected >> "$LOG"; exit 1; fi
+      shift ;;
+  esac
+  shift
+done
+printf '%s|%s\n' "$mode" "$(printf '%s' "$1" | tr '\n' '~')" >> "$LOG"
+printf four
+"#;
+
+struct Fake {
+    dir: PathBuf,
+    runner: PathBuf,
+    log: PathBuf,
+}
+
+impl Fake {
+    fn new() -> Self {
+        let dir = scratch();
+        let log = dir.join("calls.log");
+        let runner = dir.join("noxlocal");
+        let script = RUNNER.replacen(
+            "mode=plain",
+            &format!("LOG={}\nmode=plain", log.display()),
+            1,
+        );
+        fs::write(&runner, script).unwrap();
+        fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.join("m.gguf"), b"GGUF\x03\0\0\0").unwrap();
+        Self { dir, runner, log }
+    }
+
+    fn run(&self, prompt: &str) -> String {
+        let out = nox(
+            &[
+                ("NOX_EMULATE_A1000", "0"),
+                ("NOX_RAW", "1"),
+                ("NOX_STATE_AUTO", "1"),
+                ("NOX_LOCAL_RUNNER", self.runner.to_str().unwrap()),
+                ("NOX_MODEL_PATH", self.dir.join("m.gguf").to_str().unwrap()),
+                ("XDG_CACHE_HOME", self.dir.to_str().unwrap()),
+            ],
+            &[prompt],
+            "",
+        );
+        assert!(out.status.success(), "{}", text(&out.stderr));
+        assert_eq!(text(&out.stdout), "four");
+        text(&out.stderr)
+    }
+
+    /// The calls logged since the last look.
+    fn calls(&self) -> Vec<String> {
+        let log = fs::read_to_string(&self.log).unwrap_or_default();
+        let _ = fs::remove_file(&self.log);
+        log.lines().map(str::to_string).collect()
+    }
+
+    fn states(&self) -> Vec<PathBuf> {
+        fs::read_dir(self.dir.join("nox/state"))
+            .map(|dir| dir.flatten().map(|e| e.path()).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Fake {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn the_second_run_loads_the_state_the_first_saved() {
+    let fake = Fake::new();
+    fake.run(PROMPT);
+    assert_eq!(fake.calls(), ["save|You are terse.~~", "load|What is 2+2?"]);
+    assert_eq!(fake.states().len(), 1);
+
+    fake.run(PROMPT);
+    assert_eq!(fake.calls(), ["load|What is 2+2?"]);
+
+    fake.run("You are terse.\n\nAnd 3+3?");
+    assert_eq!(fake.calls(), ["load|And 3+3?"]);
+}
+
+#[test]
+fn a_new_prefix_gets_its_own_state() {
+    let fake = Fake::new();
+    fake.run(PROMPT);
+    fake.calls();
+    fake.run("You are verbose.\n\nWhat is 2+2?");
+    assert_eq!(
+        fake.calls(),
+        ["save|You are verbose.~~", "load|What is 2+2?"]
+    );
+    assert_eq!(fake.states().len(), 2);
+}
+
+#[test]
+fn a_prompt_without_a_prefix_runs_plain() {
+    let fake = Fake::new();
+    fake.run("What is 2+2?");
+    assert_eq!(fake.calls(), ["plain|What is 2+2?"]);
+    assert!(fake.states().is_empty());
+}
+
+#[test]
+fn a_rejected_state_is_deleted_and_the_run_retried_without_it() {
+    let fake = Fake::new();
+    fake.run(PROMPT);
+    fake.calls();
+    let state = fake.states().remove(0);
+    fs::write(&state, "CORRUPT").unwrap();
+
+    let stderr = fake.run(PROMPT);
+    assert!(
+        stderr.contains("deleting it and retrying without it"),
+        "{stderr}"
+    );
+    assert_eq!(
+        fake.calls(),
+        ["rejected", "plain|You are terse.~~What is 2+2?"]
+    );
+    assert!(!state.exists());
+
+    fake.run(PROMPT);
+    assert_eq!(fake.calls(), ["save|You are terse.~~", "load|What is 2+2?"]);
+}
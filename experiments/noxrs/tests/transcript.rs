@@ -0,0 +1,153 @@
+//! `--log FILE` / `NOX_LOG_FILE`: what the transcript holds after two
+//! simulated runs, in both formats, and after a run that fails midway.
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{nox, scratch, text};
+
+const TEXT: (&str, &str) = ("NOX_SIM_TEXT", "one two three");
+const RAW: (&str, &str) = ("NOX_RAW", "1");
+
+/// A log path in a directory of its own, removed on drop.
+struct Log {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Log {
+    fn new(name: &str) -> Self {
+        let dir = scratch();
+        let path = dir.join(name);
+        Self { dir, path }
+    }
+
+    fn path(&self) -> &str {
+        self.path.to_str().unwrap()
+    }
+
+    fn read(&self) -> String {
+        fs::read_to_string(&self.path).unwrap()
+    }
+}
+
+impl Drop for Log {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn is_timestamp(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() == 20
+        && b.iter().enumerate().all(|(i, c)| match i {
+            4 | 7 => *c == b'-',
+            10 => *c == b'T',
+            13 | 16 => *c == b':',
+            19 => *c == b'Z',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+#[test]
+fn two_runs_append_two_framed_turns() {
+    let log = Log::new("nox.log");
+    let first = nox(&[TEXT, RAW], &["--log", log.path(), "hello there"], "");
+    assert!(first.status.success(), "{}", text(&first.stderr));
+    let second = nox(
+        &[TEXT, RAW, ("NOX_LOG_FILE", log.path())],
+        &["second\nline"],
+        "",
+    );
+    assert!(second.status.success(), "{}", text(&second.stderr));
+
+    let transcript = log.read();
+    let turns: Vec<&str> = transcript.split_terminator("\n\n").collect();
+    assert_eq!(turns.len(), 2, "{transcript}");
+    let prompts = [vec![">>> hello there"], vec![">>> second", ">>> line"]];
+    for (turn, prompt) in turns.iter().zip(prompts) {
+        let lines: Vec<&str> = turn.lines().collect();
+        let at = lines[0]
+            .strip_prefix("=== ")
+            .and_then(|l| l.strip_suffix(" ==="))
+            .unwrap();
+        assert!(is_timestamp(at), "{at}");
+        let rest = &lines[1..];
+        assert_eq!(rest[..prompt.len()], prompt[..]);
+        assert_eq!(
+            rest[prompt.len()..rest.len() - 1],
+            ["--- response", "one two three"]
+        );
+        let stats = rest.last().unwrap();
+        assert!(stats.starts_with("--- ttft "), "{stats}");
+        assert!(stats.contains(" tok/s, total "), "{stats}");
+    }
+    assert!(transcript.ends_with(" ms\n\n"), "{transcript:?}");
+}
+
+#[test]
+fn a_jsonl_path_gets_one_record_per_run() {
+    let log = Log::new("nox.jsonl");
+    for prompt in ["first", "second"] {
+        let out = nox(&[TEXT, RAW], &["--log", log.path(), prompt], "");
+        assert!(out.status.success(), "{}", text(&out.stderr));
+    }
+    let transcript = log.read();
+    let records: Vec<&str> = transcript.lines().collect();
+    assert_eq!(records.len(), 2, "{transcript}");
+    for (record, prompt) in records.iter().zip(["first", "second"]) {
+        assert!(record.starts_with("{\"at\":\""), "{record}");
+        assert!(is_timestamp(&record[7..27]), "{record}");
+        for field in [
+            format!("\"prompt\":\"{prompt}\""),
+            "\"response\":\"one two three\"".to_string(),
+            "\"ttft_ms\":0".to_string(),
+            "\"duration_ms\":".to_string(),
+            "\"tokens_per_sec\":".to_string(),
+            "\"error\":null".to_string(),
+        ] {
+            assert!(record.contains(&field), "{field} missing from {record}");
+        }
+    }
+}
+
+#[test]
+fn a_run_that_dies_midway_leaves_its_partial_answer() {
+    let log = Log::new("nox.log");
+    let out = nox(
+        &[
+            ("NOX_SIM_TEXT", "alpha beta gamma"),
+            ("NOX_SIM_FAIL", "midstream:2"),
+            ("NOX_RAW", "1"),
+            ("NOX_LOG_FILE", log.path()),
+        ],
+        &["hi"],
+        "",
+    );
+    assert_eq!(out.status.code(), Some(6), "{}", text(&out.stderr));
+    let transcript = log.read();
+    let lines: Vec<&str> = transcript.lines().collect();
+    assert_eq!(
+        lines[1..],
+        [
+            ">>> hi",
+            "--- response",
+            "alpha beta",
+            "--- error: runner exited with exit status: 1",
+            ""
+        ]
+    );
+}
+
+#[test]
+fn an_unwritable_log_warns_once_and_the_run_goes_on() {
+    let log = Log::new("nox.log");
+    fs::create_dir_all(&log.path).unwrap();
+    let out = nox(&[TEXT, RAW, ("NOX_LOG_FILE", log.path())], &["hi"], "");
+    assert!(out.status.success(), "{}", text(&out.stderr));
+    assert_eq!(text(&out.stdout), "one two three");
+    let stderr = text(&out.stderr);
+    assert_eq!(stderr.matches("logging disabled").count(), 1, "{stderr}");
+}
@@ -0,0 +1,7 @@
+//go:build !unix
+
+package main
+
+// watchCancel is a no-op where there is no SIGUSR1; cancelling means
+// killing the runner.
+func watchCancel() {}
@@ -11,6 +11,7 @@ import (
 	"path/filepath"
 	"strconv"
 	"strings"
+	"sync/atomic"
 	"time"
 
 	"github.com/ollama/ollama/llama"
@@ -26,6 +27,24 @@ type runStats struct {
 
 const metricsPrefix = "NR|"
 
+// controlPrefix marks a -serve request as a command for the runner rather
+// than a prompt. "warmup <text>" prefills <text>, samples one token, discards
+// it all, and answers with an empty response; "ping" just answers, which
+// tells the caller the model is loaded.
+const controlPrefix = "\x05"
+
+// paramsPrefix opens and closes a -serve-params header of sampling settings
+// for one request: "\x06temp=0.7 top-k=40 max-tokens=16\x06<prompt>".
+const paramsPrefix = "\x06"
+
+// rejectPrefix starts a -serve response that refuses the request instead of
+// answering it; the rest of the response says why.
+const rejectPrefix = "\x15"
+
+// cancelRequested stops the running -serve generation early; -cancel-signal
+// sets it on SIGUSR1. The response still ends with the usual marker.
+var cancelRequested atomic.Bool
+
 type triBool struct {
 	value bool
 	set   bool
@@ -112,6 +131,8 @@ func main() {
 		metrics    = flag.Bool("metrics", false, "Emit per-token logit metrics to stderr (NR|token|max|second|margin)")
 		serve      = flag.Bool("serve", false, "Serve prompts from stdin (one per line)")
 		serveRS    = flag.Bool("serve-rs", false, "Use ASCII record separator (0x1e) as prompt delimiter")
+		cancelSig  = flag.Bool("cancel-signal", false, "In -serve mode, SIGUSR1 stops the current generation")
+		reqParams  = flag.Bool("serve-params", false, "In -serve mode, accept per-request temp/top-p/top-k/max-tokens headers")
 		keepCache  = flag.Bool("keep-cache", false, "Reuse KV cache between prompts when prefix matches")
 		appendOnly = flag.Bool("append", false, "Append prompts onto existing cache (no reset)")
 		inputOnly  = flag.Bool("input-only", false, "Keep KV cache aligned to prompt only (do not append generated tokens)")
@@ -221,13 +242,14 @@ func main() {
 		}
 	}
 
-	sampler, err := llama.NewSamplingContext(model, llama.SamplingParams{
+	samplingParams := llama.SamplingParams{
 		TopK:          *topK,
 		TopP:          float32(*topP),
 		Temp:          float32(*temp),
 		RepeatLastN:   *repeatLast,
 		PenaltyRepeat: float32(*repeatPen),
-	})
+	}
+	sampler, err := llama.NewSamplingContext(model, samplingParams)
 	if err != nil {
 		fmt.Fprintf(os.Stderr, "failed to create sampler: %v\n", err)
 		os.Exit(1)
@@ -252,7 +274,10 @@ func main() {
 		if *chatMode || *cotMode || *systemMsg != "" {
 			fmt.Fprintln(os.Stderr, "note: -chat/-cot/-system are not applied in -serve mode")
 		}
-		if err := serveLoop(ctx, model, sampler, batch, streamer, *maxTokens, *rawOut, *serveRS, keepFlag, appendFlag, *inputOnly, *stateSave, loadedTokens, *kvWindow, *metrics); err != nil {
+		if *cancelSig {
+			watchCancel()
+		}
+		if err := serveLoop(ctx, model, sampler, samplingParams, *reqParams, batch, streamer, *maxTokens, *rawOut, *serveRS, keepFlag, appendFlag, *inputOnly, *stateSave, loadedTokens, *kvWindow, *metrics); err != nil {
 			fmt.Fprintf(os.Stderr, "serve loop failed: %v\n", err)
 			os.Exit(1)
 		}
@@ -354,7 +379,7 @@ func min(a, b int) int {
 	return b
 }
 
-func serveLoop(ctx *llama.Context, model *llama.Model, sampler *llama.SamplingContext, batch *llama.Batch, writer *streamWriter, maxTokens int, rawOut bool, useRS bool, keepCache bool, appendOnly bool, inputOnly bool, stateSave string, initialTokens []int, kvWindow int, metrics bool) error {
+func serveLoop(ctx *llama.Context, model *llama.Model, baseSampler *llama.SamplingContext, baseParams llama.SamplingParams, reqParams bool, batch *llama.Batch, writer *streamWriter, maxTokens int, rawOut bool, useRS bool, keepCache bool, appendOnly bool, inputOnly bool, stateSave string, initialTokens []int, kvWindow int, metrics bool) error {
 	reader := bufio.NewReader(os.Stdin)
 	endMarker := "\n<<<NOX_END>>>\n"
 	if useRS {
@@ -372,12 +397,42 @@ func serveLoop(ctx *llama.Context, model *llama.Model, sampler *llama.SamplingCo
 		if err != nil {
 			return err
 		}
+		if strings.HasPrefix(prompt, controlPrefix) {
+			if err := runControl(strings.TrimPrefix(prompt, controlPrefix), ctx, model, baseParams, batch, len(prevTokens)); err != nil {
+				fmt.Fprintf(os.Stderr, "control request failed: %v\n", err)
+			}
+			fmt.Fprint(writer.writer, endMarker)
+			writer.Flush()
+			continue
+		}
+		// Settings from a -serve-params header last for this request only.
+		// A header that cannot be applied gets a refusal, not an answer with
+		// the base settings.
+		sampler, reqMaxTokens := baseSampler, maxTokens
+		if reqParams && strings.HasPrefix(prompt, paramsPrefix) {
+			header, rest, closed := strings.Cut(strings.TrimPrefix(prompt, paramsPrefix), paramsPrefix)
+			prompt = rest
+			params, n, err := parseRequestParams(header, baseParams, maxTokens)
+			if !closed {
+				err = errors.New("unterminated params header")
+			}
+			if err == nil {
+				sampler, err = llama.NewSamplingContext(model, params)
+				reqMaxTokens = n
+			}
+			if err != nil {
+				fmt.Fprintf(writer.writer, "%srequest params: %v%s", rejectPrefix, err, endMarker)
+				writer.Flush()
+				continue
+			}
+		}
 		if strings.TrimSpace(prompt) == "" {
 			continue
 		}
 		if prompt == "exit" || prompt == "quit" {
 			return nil
 		}
+		cancelRequested.Store(false)
 		start := time.Now()
 		toks, err := tokenizePrompt(model, prompt, appendOnly && len(prevTokens) > 0)
 		if err != nil {
@@ -399,7 +454,7 @@ func serveLoop(ctx *llama.Context, model *llama.Model, sampler *llama.SamplingCo
 		}
 		if appendOnly {
 			basePos := len(prevTokens)
-			generated, err = runTokens(toks, 0, basePos, ctx, model, sampler, batch, writer, maxTokens, rawOut, nil, saveFn, kvWindow, metrics)
+			generated, err = runTokens(toks, 0, basePos, ctx, model, sampler, batch, writer, reqMaxTokens, rawOut, nil, saveFn, kvWindow, metrics)
 			prevTokens = append(prevTokens, toks...)
 		} else if keepCache {
 			common := commonPrefixLen(prevTokens, toks)
@@ -408,10 +463,10 @@ func serveLoop(ctx *llama.Context, model *llama.Model, sampler *llama.SamplingCo
 			} else if common < len(prevTokens) {
 				ctx.KvCacheSeqRm(0, common, -1)
 			}
-			generated, err = runTokens(toks, common, 0, ctx, model, sampler, batch, writer, maxTokens, rawOut, nil, saveFn, kvWindow, metrics)
+			generated, err = runTokens(toks, common, 0, ctx, model, sampler, batch, writer, reqMaxTokens, rawOut, nil, saveFn, kvWindow, metrics)
 			prevTokens = toks
 		} else {
-			generated, err = runTokens(toks, 0, 0, ctx, model, sampler, batch, writer, maxTokens, rawOut, nil, saveFn, kvWindow, metrics)
+			generated, err = runTokens(toks, 0, 0, ctx, model, sampler, batch, writer, reqMaxTokens, rawOut, nil, saveFn, kvWindow, metrics)
 			prevTokens = toks
 		}
 		if err != nil {
@@ -441,6 +496,83 @@ func serveLoop(ctx *llama.Context, model *llama.Model, sampler *llama.SamplingCo
 	}
 }
 
+// runControl handles a control request. Warmup runs after the cached tokens
+// (if any) and removes its own tokens again, so the cache is unchanged. It
+// samples through a throwaway context so the shared sampler's penalty
+// history never sees the warmup token.
+func runControl(command string, ctx *llama.Context, model *llama.Model, params llama.SamplingParams, batch *llama.Batch, cached int) error {
+	name, text, _ := strings.Cut(command, " ")
+	if name == "ping" {
+		return nil
+	}
+	if name != "warmup" {
+		return fmt.Errorf("unknown control command %q", name)
+	}
+	if strings.TrimSpace(text) == "" {
+		text = " "
+	}
+	toks, err := tokenizePrompt(model, text, cached > 0)
+	if err != nil {
+		return err
+	}
+	sampler, err := llama.NewSamplingContext(model, params)
+	if err != nil {
+		return err
+	}
+	discard := newStreamWriter(bufio.NewWriter(io.Discard), 0)
+	_, err = runTokens(toks, 0, cached, ctx, model, sampler, batch, discard, 1, true, nil, nil, 0, false)
+	if cached == 0 {
+		ctx.KvCacheClear()
+	} else {
+		ctx.KvCacheSeqRm(0, cached, -1)
+	}
+	return err
+}
+
+// parseRequestParams applies a "temp=0.7 top-k=40 max-tokens=16" header to
+// the process-wide settings, refusing values outside the ranges nox allows.
+func parseRequestParams(header string, params llama.SamplingParams, maxTokens int) (llama.SamplingParams, int, error) {
+	for _, field := range strings.Fields(header) {
+		name, value, ok := strings.Cut(field, "=")
+		if !ok {
+			return params, maxTokens, fmt.Errorf("malformed setting %q", field)
+		}
+		var err error
+		switch name {
+		case "temp", "top-p":
+			var v float64
+			v, err = strconv.ParseFloat(value, 32)
+			if err == nil && name == "temp" && (v < 0 || math.IsInf(v, 0)) {
+				err = fmt.Errorf("temp must be >= 0, got %s", value)
+			}
+			if err == nil && name == "top-p" && !(v >= 0 && v <= 1) {
+				err = fmt.Errorf("top-p must be within [0, 1], got %s", value)
+			}
+			if name == "temp" {
+				params.Temp = float32(v)
+			} else {
+				params.TopP = float32(v)
+			}
+		case "top-k":
+			params.TopK, err = strconv.Atoi(value)
+			if err == nil && params.TopK < 0 {
+				err = fmt.Errorf("top-k must be >= 0, got %s", value)
+			}
+		case "max-tokens":
+			maxTokens, err = strconv.Atoi(value)
+			if err == nil && maxTokens < 1 {
+				err = fmt.Errorf("max-tokens must be >= 1, got %s", value)
+			}
+		default:
+			err = fmt.Errorf("unknown setting %q", name)
+		}
+		if err != nil {
+			return params, maxTokens, err
+		}
+	}
+	return params, maxTokens, nil
+}
+
 func readPrompt(reader *bufio.Reader, useRS bool) (string, error) {
 	if useRS {
 		data, err := reader.ReadBytes(0x1e)
@@ -556,6 +688,9 @@ func runTokens(toks []int, startPos int, posOffset int, ctx *llama.Context, mode
 	generated := make([]int, 0, maxTokens)
 	genStart := time.Now()
 	for i := 0; i < maxTokens; i++ {
+		if cancelRequested.Load() {
+			break
+		}
 		if kvWindow > 0 && curPos >= kvWindow {
 			curPos = shiftKvCache(ctx, curPos, kvWindow)
 		}